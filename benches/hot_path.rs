@@ -0,0 +1,148 @@
+//! 每个数据包都会经过的热点路径：抓包解码、SIP 解析和白名单匹配
+//!
+//! `cargo bench`；只跑某一组：`cargo bench -- sip_parse`
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::net::{IpAddr, Ipv4Addr};
+use uablock_rust::packet_capture::decode_packet;
+use uablock_rust::{SipParser, Whitelist};
+
+const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9));
+
+/// 扫描器的 REGISTER（约 500 字节）
+const SMALL_REGISTER: &[u8] =
+    include_bytes!("../fuzz/corpus/sip_parser/register-friendly-scanner.sip");
+
+/// 带大量 SDP 的 INVITE（约 4 KB，接近单个 UDP 数据报的常见上限）
+fn large_invite() -> Vec<u8> {
+    let mut sdp = String::from(
+        "v=0\r\no=- 3958281937 3958281937 IN IP4 198.51.100.7\r\ns=-\r\nc=IN IP4 198.51.100.7\r\nt=0 0\r\n",
+    );
+    for media in 0..4 {
+        sdp.push_str(&format!(
+            "m=audio {} RTP/AVP 0 8 9 18 101 96 97 98\r\n",
+            10000 + media * 2
+        ));
+        for codec in 0..20 {
+            sdp.push_str(&format!(
+                "a=rtpmap:{} codec-{}/8000\r\na=fmtp:{} mode-set={}\r\n",
+                96 + codec,
+                codec,
+                96 + codec,
+                codec
+            ));
+        }
+    }
+    format!(
+        "INVITE sip:1002@192.0.2.10;transport=UDP SIP/2.0\r\n\
+         Via: SIP/2.0/UDP 198.51.100.7:5060;branch=z9hG4bK-524287-1---2cd5b0b6cdde5d6a;rport\r\n\
+         Max-Forwards: 70\r\n\
+         Contact: <sip:1001@198.51.100.7:5060;transport=UDP>\r\n\
+         To: <sip:1002@192.0.2.10;transport=UDP>\r\n\
+         From: <sip:1001@192.0.2.10;transport=UDP>;tag=a7c6e1d4\r\n\
+         Call-ID: NDg4ZjIxYjJiYTdmNzQ1OWE5ZGE0NGY3MzE2NTg3ZjE\r\n\
+         CSeq: 1 INVITE\r\n\
+         Allow: INVITE, ACK, CANCEL, BYE, NOTIFY, REFER, MESSAGE, OPTIONS, INFO, SUBSCRIBE\r\n\
+         Content-Type: application/sdp\r\n\
+         Supported: replaces, norefersub, extended-refer, timer, outbound, path\r\n\
+         User-Agent: Z 5.5.13 v2.10.18.5\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        sdp.len(),
+        sdp
+    )
+    .into_bytes()
+}
+
+/// 非 SIP 的 UDP 噪声（类似 RTP 的二进制负载）
+fn noise() -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..172)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// 以太网 + IPv4 + UDP 帧
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![
+        0x02, 0x42, 0xac, 0x11, 0x00, 0x02, 0x02, 0x42, 0xac, 0x11, 0x00, 0x03,
+    ];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    let total_len = (20 + 8 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&total_len.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&[203, 0, 113, 9, 192, 0, 2, 10]);
+    frame.extend_from_slice(&5070u16.to_be_bytes());
+    frame.extend_from_slice(&5060u16.to_be_bytes());
+    frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn payloads() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("small_register", SMALL_REGISTER.to_vec()),
+        ("large_invite", large_invite()),
+        ("noise", noise()),
+    ]
+}
+
+fn packet_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet_decode");
+    for (name, payload) in payloads() {
+        let frame = frame(&payload);
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &frame, |b, frame| {
+            b.iter(|| decode_packet(black_box(frame)))
+        });
+    }
+    group.finish();
+}
+
+fn sip_parse(c: &mut Criterion) {
+    let parser = SipParser::new();
+    let mut group = c.benchmark_group("sip_parse");
+    for (name, payload) in payloads() {
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        // 与检测流水线相同：先分类，再解析 REGISTER/INVITE
+        group.bench_with_input(BenchmarkId::from_parameter(name), &payload, |b, payload| {
+            b.iter(|| {
+                parser.classify(black_box(payload));
+                parser.parse_udp_packet(black_box(payload), SOURCE)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn whitelist_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("whitelist_match");
+    for size in [10, 100, 1000] {
+        let mut patterns: Vec<String> = (0..size - 1)
+            .map(|i| format!("vendor-{}-phone", i))
+            .collect();
+        patterns.push("microsip".to_string());
+        let whitelist = Whitelist::new(patterns);
+        // 未命中需要遍历全部模式（扫描器的常见情况）；命中的模式放在最后
+        group.bench_with_input(
+            BenchmarkId::new("miss", size),
+            &whitelist,
+            |b, whitelist| b.iter(|| whitelist.is_allowed(black_box("friendly-scanner"))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("hit_last", size),
+            &whitelist,
+            |b, whitelist| b.iter(|| whitelist.is_allowed(black_box("MicroSIP/3.21.3"))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, packet_decode, sip_parse, whitelist_match);
+criterion_main!(benches);
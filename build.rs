@@ -0,0 +1,12 @@
+fn main() {
+    // gRPC 特性：从 proto/uablock.proto 生成服务端代码（使用内置的 protoc）
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("找不到内置 protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/uablock.proto"], &["proto"])
+            .expect("编译 proto/uablock.proto 失败");
+    }
+}
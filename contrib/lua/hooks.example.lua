@@ -0,0 +1,32 @@
+-- uablock-rust Lua 钩子示例（UABLOCK_LUA_SCRIPT 指向本文件）
+--
+-- 全局变量在调用之间保留，可以在脚本中维护自己的状态。
+
+-- 办公网段的终端即使 UA 不在白名单中也放行
+local trusted_prefix = "192.0.2."
+
+-- 每个来源发出 INVITE 的次数
+local invites = {}
+
+function pre_decision(req, action)
+  if req.source_ip:sub(1, #trusted_prefix) == trusted_prefix then
+    return "allow"
+  end
+
+  -- 同一来源发出大量 INVITE 视为盗打尝试（即使 UA 在白名单中）
+  if req.method == "INVITE" then
+    invites[req.source_ip] = (invites[req.source_ip] or 0) + 1
+    if invites[req.source_ip] > 100 then
+      return "ban", "too many INVITEs"
+    end
+  end
+
+  -- 返回 nil 保持策略的判定
+  return nil
+end
+
+function post_ban(ban)
+  uablock.info("封禁 " .. ban.source_ip .. "，原因 " .. ban.reason)
+  -- 返回 "unban" 可以立即撤销本次封禁
+  return nil
+end
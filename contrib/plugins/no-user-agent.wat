@@ -0,0 +1,61 @@
+;; 示例检测插件：缺少 User-Agent 头的请求直接封禁（解析器记为 "Unknown"，即使它被加入白名单）
+;;
+;; 放到 UABLOCK_PLUGIN_DIR 目录即可使用（.wat 文本格式无需编译；也可用 wat2wasm 转成 .wasm）。
+;; 实际插件通常用 Rust/Go/AssemblyScript 编写并编译到 wasm32，接口见 README「WASM 检测插件」。
+(module
+  (memory (export "memory") 1)
+
+  ;; 输入 JSON 中缺少 UA 时的字段（22 字节）
+  (data (i32.const 0) "\"user_agent\":\"Unknown\"")
+  ;; 返回的判定（48 字节）
+  (data (i32.const 64) "{\"decision\":\"ban\",\"reason\":\"missing User-Agent\"}")
+
+  ;; 输入缓冲区从 1024 开始按需分配
+  (global $heap (mut i32) (i32.const 1024))
+
+  (func (export "uablock_abi_version") (result i32)
+    (i32.const 1))
+
+  (func (export "uablock_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local.set $ptr (global.get $heap))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    ;; 内存不足时按页（64 KiB）增长
+    (if (i32.gt_u (local.get $end) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (drop
+          (memory.grow
+            (i32.sub
+              (i32.div_u (i32.add (local.get $end) (i32.const 65535)) (i32.const 65536))
+              (memory.size))))))
+    (global.set $heap (local.get $end))
+    (local.get $ptr))
+
+  (func (export "uablock_inspect") (param $ptr i32) (param $len i32) (result i64)
+    (local $i i32)
+    (local $j i32)
+    (local $last i32)
+    ;; 每次调用前重置分配器，输入在本次调用结束前不会被覆盖
+    (global.set $heap (i32.const 1024))
+    (if (i32.lt_u (local.get $len) (i32.const 22))
+      (then (return (i64.const 0))))
+    (local.set $i (local.get $ptr))
+    (local.set $last (i32.add (local.get $ptr) (i32.sub (local.get $len) (i32.const 22))))
+    (block $not_found
+      (loop $scan
+        (br_if $not_found (i32.gt_u (local.get $i) (local.get $last)))
+        (local.set $j (i32.const 0))
+        (block $mismatch
+          (loop $compare
+            (br_if $mismatch
+              (i32.ne
+                (i32.load8_u (i32.add (local.get $i) (local.get $j)))
+                (i32.load8_u (local.get $j))))
+            (local.set $j (i32.add (local.get $j) (i32.const 1)))
+            (br_if $compare (i32.lt_u (local.get $j) (i32.const 22))))
+          ;; 匹配：返回 (偏移 << 32) | 长度
+          (return (i64.or (i64.shl (i64.const 64) (i64.const 32)) (i64.const 48))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $scan)))
+    (i64.const 0)))
@@ -0,0 +1,13 @@
+//! 抓包解码：输入为任意链路层帧
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uablock_rust::packet_capture::decode_packet;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(packet) = decode_packet(data) {
+        // 负载总是帧尾部的非空切片
+        assert!(!packet.payload.is_empty());
+        assert!(data.ends_with(&packet.payload));
+    }
+});
@@ -0,0 +1,23 @@
+//! SIP 解析器：输入为任意 UDP 负载
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::OnceLock;
+use uablock_rust::SipParser;
+
+const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9));
+
+fuzz_target!(|data: &[u8]| {
+    // 正则编译开销较大，所有输入共用一个解析器
+    static PARSER: OnceLock<SipParser> = OnceLock::new();
+    let parser = PARSER.get_or_init(SipParser::new);
+
+    parser.classify(data);
+    if let Some(request) = parser.parse_request(data, SOURCE) {
+        assert_eq!(request.source_ip, SOURCE);
+    }
+    if let Some(request) = parser.parse_udp_packet(data, SOURCE) {
+        assert!(request.method == "REGISTER" || request.method == "INVITE");
+    }
+});
@@ -0,0 +1,155 @@
+use crate::packet_capture::{decode_packet, CapturedPacket, PacketSource};
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// 单次读取的缓冲区大小（足够容纳巨帧以外的任何以太网帧）
+const BUFFER_SIZE: usize = 65536;
+
+/// 基于 Linux AF_PACKET 原始套接字的数据包来源，不依赖 libpcap
+///
+/// 适合交叉编译到 musl/ARM 设备。没有 BPF 过滤器，在用户态丢弃出站、非 IPv4/IPv6 UDP 和目标端口不符的
+/// 数据包。
+pub struct AfPacketSource {
+    socket: OwnedFd,
+    ports: Vec<u16>,
+    buffer: Vec<u8>,
+    /// PACKET_STATISTICS 读取后清零，这里累计
+    dropped: u64,
+}
+
+impl AfPacketSource {
+    /// 打开网络接口
+    /// port: 目标端口，只返回目标端口为该端口的入站 UDP 数据包
+    pub fn open(interface: &str, port: u16) -> Result<Self, String> {
+        Self::open_ports(interface, &[port])
+    }
+
+    /// 打开网络接口，只返回目标端口为 `ports` 之一的入站 UDP 数据包
+    pub fn open_ports(interface: &str, ports: &[u16]) -> Result<Self, String> {
+        let name =
+            CString::new(interface).map_err(|_| format!("网络接口名称无效: {}", interface))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(format!(
+                "无法打开网络接口 {}: {}",
+                interface,
+                io::Error::last_os_error()
+            ));
+        }
+
+        // 接收所有协议的帧（IPv4 和 IPv6），非 IP 的帧在解析时丢弃
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, i32::from(protocol)) };
+        if fd < 0 {
+            return Err(format!(
+                "创建 AF_PACKET 套接字失败: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = ifindex as libc::c_int;
+        let bound = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if bound != 0 {
+            return Err(format!(
+                "绑定网络接口 {} 失败: {}",
+                interface,
+                io::Error::last_os_error()
+            ));
+        }
+
+        // 与 pcap 一样 1 秒超时，使读取线程能定期检查通道是否关闭
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if set != 0 {
+            return Err(format!("设置读取超时失败: {}", io::Error::last_os_error()));
+        }
+
+        Ok(Self {
+            socket,
+            ports: ports.to_vec(),
+            buffer: vec![0; BUFFER_SIZE],
+            dropped: 0,
+        })
+    }
+}
+
+impl PacketSource for AfPacketSource {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+        let mut from: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        let mut from_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let received = unsafe {
+            libc::recvfrom(
+                self.socket.as_raw_fd(),
+                self.buffer.as_mut_ptr() as *mut libc::c_void,
+                self.buffer.len(),
+                0,
+                &mut from as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut from_len,
+            )
+        };
+        if received < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                // 超时是正常的，继续等待
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted => Ok(None),
+                _ => Err(format!("抓包错误: {}", error)),
+            };
+        }
+
+        // 只处理入站流量
+        if from.sll_pkttype == libc::PACKET_OUTGOING {
+            return Ok(None);
+        }
+        Ok(decode_packet(&self.buffer[..received as usize])
+            .filter(|packet| self.ports.contains(&packet.dest_port)))
+    }
+
+    /// 获取内核丢弃的数据包总数（自开始抓包以来）
+    fn dropped(&mut self) -> Result<u64, String> {
+        let mut stats: libc::tpacket_stats = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::tpacket_stats>() as libc::socklen_t;
+        let read = unsafe {
+            libc::getsockopt(
+                self.socket.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_STATISTICS,
+                &mut stats as *mut libc::tpacket_stats as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if read != 0 {
+            return Err(format!("获取抓包统计失败: {}", io::Error::last_os_error()));
+        }
+        self.dropped += u64::from(stats.tp_drops);
+        Ok(self.dropped)
+    }
+
+    fn set_ports(&mut self, ports: &[u16]) -> Result<(), String> {
+        self.ports = ports.to_vec();
+        Ok(())
+    }
+}
@@ -0,0 +1,788 @@
+use crate::bans::{parse_duration, BanReason, BanRecord};
+use crate::campaigns::{Campaign, Campaigns};
+use crate::enforcement::Enforcer;
+use crate::ha::HaRole;
+use crate::packet_capture::CapturePorts;
+use crate::pending::{PendingBan, PendingBans, ReviewAction};
+use crate::state_file::unix_now;
+use crate::stats::StatsSnapshot;
+use crate::webhook::{Webhook, WebhookCommand};
+use crate::whitelist::{ListMode, MatchMode, Whitelist};
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// HTTP API 共享状态
+#[derive(Clone)]
+pub struct ApiState {
+    pub token: Arc<String>,
+    /// 封禁列表订阅地址的独立 token（未设置时订阅地址无需认证）
+    pub feed_token: Option<Arc<String>>,
+    pub enforcer: Arc<Enforcer>,
+    pub whitelist: Arc<Mutex<Whitelist>>,
+    /// 外部封禁命令入口（未设置 `UABLOCK_WEBHOOK_SECRET` 时为 None）
+    pub webhook: Option<Arc<Webhook>>,
+    /// 受保护端口（数据包来源不支持在运行中更换端口时为 None）
+    pub ports: Option<Arc<CapturePorts>>,
+    /// 扫描活动（未设置 `UABLOCK_CAMPAIGN_REPORT` 时为 None）
+    pub campaigns: Option<Arc<Campaigns>>,
+    /// 待审核封禁（未设置 `UABLOCK_REVIEW_HOLD` 时为 None）
+    pub review: Option<Arc<PendingBans>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    ip: IpAddr,
+}
+
+/// 批量解封的条件：`all=true` 解封全部，`older_than=7d` 解封封禁时长达到该值的 IP
+#[derive(Debug, Deserialize)]
+struct BatchUnbanQuery {
+    #[serde(default)]
+    all: bool,
+    older_than: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchUnbanResponse {
+    unbanned: Vec<IpAddr>,
+}
+
+#[derive(Debug, Serialize)]
+struct BansResponse {
+    bans: Vec<IpAddr>,
+    /// 与 `bans` 一一对应的封禁原因、命中次数和剩余时长
+    details: Vec<BanDetail>,
+}
+
+#[derive(Debug, Serialize)]
+struct BanDetail {
+    #[serde(flatten)]
+    record: BanRecord,
+    /// 距离到期的剩余秒数，不会自动解封时省略
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct FeedResponse {
+    count: usize,
+    bans: Vec<IpAddr>,
+}
+
+/// 健康检查响应（供 `uablock check` 和监控系统使用）
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    /// 封禁后端状态：`ok`、`degraded`、`standby`、`disabled` 或 `error`
+    backend: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend_error: Option<String>,
+    active_bans: usize,
+    stats: StatsSnapshot,
+}
+
+/// 主备角色（`GET /ha`、`POST /ha/promote`）
+#[derive(Debug, Serialize)]
+struct HaResponse {
+    role: HaRole,
+    /// 升为主机时新下发的封禁规则数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    programmed: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookReply {
+    /// 防火墙规则是否发生变化（已封禁/已解封时为 false）
+    changed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WhitelistBody {
+    patterns: Vec<String>,
+    /// 名单模式（只读，启动时选择）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<ListMode>,
+    /// 没有匹配方式前缀的模式使用的匹配方式（只读，启动时选择）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    match_mode: Option<MatchMode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PortsBody {
+    ports: Vec<u16>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// API 错误响应
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(ErrorBody { error: self.1 })).into_response()
+    }
+}
+
+/// 监听地址并在当前 tokio 运行时中启动 HTTP API
+pub async fn spawn(listen: SocketAddr, state: ApiState) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| format!("HTTP API 无法监听 {}: {}", listen, e))?;
+
+    info!("HTTP API 监听于 http://{}", listen);
+    tokio::spawn(async move {
+        let app = router(state).into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("HTTP API 异常退出: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// 构造路由
+fn router(state: ApiState) -> Router {
+    let feed = Router::new()
+        .route("/feed/plain", get(feed_plain))
+        .route("/feed/json", get(feed_json))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_feed_token,
+        ));
+    Router::new()
+        .route("/bans", get(list_bans).post(add_ban).delete(remove_bans))
+        .route("/bans/{ip}", delete(remove_ban))
+        .route("/whitelist", get(get_whitelist).put(put_whitelist))
+        .route("/ports", get(get_ports).put(put_ports))
+        .route("/stats", get(get_stats))
+        .route("/health", get(get_health))
+        .route("/ha", get(get_ha))
+        .route("/ha/promote", post(promote))
+        .route("/campaigns", get(get_campaigns))
+        .route("/review", get(get_review))
+        .route("/review/{ip}/{action}", post(review_ban))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .merge(feed)
+        .route("/webhook", post(receive_webhook))
+        .with_state(state)
+}
+
+/// 校验 `Authorization: Bearer <token>`
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(provided.as_bytes(), state.token.as_bytes()) {
+        warn!(
+            "【API】拒绝未授权请求: {} {}",
+            request.method(),
+            request.uri()
+        );
+        return ApiError(StatusCode::UNAUTHORIZED, "未授权".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+/// 订阅地址的认证：配置了 feed token 时，接受 `?token=` 参数或 Bearer 头
+async fn require_feed_token(
+    State(state): State<ApiState>,
+    Query(query): Query<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(expected) = &state.feed_token {
+        let provided = query
+            .get("token")
+            .map(String::as_str)
+            .or_else(|| {
+                request
+                    .headers()
+                    .get(header::AUTHORIZATION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "))
+            })
+            .unwrap_or_default();
+        if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            warn!("【API】拒绝未授权的订阅请求: {}", request.uri().path());
+            return ApiError(StatusCode::UNAUTHORIZED, "未授权".to_string()).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// 固定时间比较，避免通过响应时间猜测 token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 在阻塞线程池中执行防火墙操作
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e))
+}
+
+async fn list_bans(State(state): State<ApiState>) -> Result<Json<BansResponse>, ApiError> {
+    let enforcer = state.enforcer.clone();
+    let records = run_blocking(move || enforcer.ban_records()).await?;
+    let now = unix_now();
+    Ok(Json(BansResponse {
+        bans: records.iter().map(|record| record.ip).collect(),
+        details: records
+            .into_iter()
+            .map(|record| BanDetail {
+                remaining_secs: record.remaining_secs(now),
+                record,
+            })
+            .collect(),
+    }))
+}
+
+async fn add_ban(
+    State(state): State<ApiState>,
+    Json(body): Json<BanRequest>,
+) -> Result<StatusCode, ApiError> {
+    let enforcer = state.enforcer.clone();
+    let ip = body.ip;
+    run_blocking(move || enforcer.ban(ip, BanReason::new("MANUAL", "API"))).await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn remove_ban(
+    State(state): State<ApiState>,
+    Path(ip): Path<IpAddr>,
+) -> Result<StatusCode, ApiError> {
+    let enforcer = state.enforcer.clone();
+    run_blocking(move || enforcer.unban(ip, "MANUAL", "API")).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_bans(
+    State(state): State<ApiState>,
+    Query(query): Query<BatchUnbanQuery>,
+) -> Result<Json<BatchUnbanResponse>, ApiError> {
+    let min_age =
+        match (query.all, query.older_than.as_deref()) {
+            (_, Some(text)) => Some(parse_duration(text).ok_or_else(|| {
+                ApiError(StatusCode::BAD_REQUEST, format!("无效的时长: {}", text))
+            })?),
+            (true, None) => None,
+            (false, None) => {
+                return Err(ApiError(
+                    StatusCode::BAD_REQUEST,
+                    "需要指定 all=true 或 older_than".to_string(),
+                ))
+            }
+        };
+    let enforcer = state.enforcer.clone();
+    let unbanned =
+        run_blocking(move || enforcer.unban_older_than(min_age, "MANUAL", "API")).await?;
+    info!("【API】批量解封 {} 个 IP", unbanned.len());
+    Ok(Json(BatchUnbanResponse { unbanned }))
+}
+
+/// 获取排序后的封禁列表，保证相同内容生成相同的 ETag
+async fn sorted_bans(state: &ApiState) -> Result<Vec<IpAddr>, ApiError> {
+    let enforcer = state.enforcer.clone();
+    let mut bans = run_blocking(move || enforcer.list_bans()).await?;
+    bans.sort();
+    bans.dedup();
+    Ok(bans)
+}
+
+/// 带 ETag 的订阅响应；`If-None-Match` 命中时返回 304
+fn feed_response(headers: &HeaderMap, content_type: &'static str, body: String) -> Response {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+    let etag = HeaderValue::from_str(&etag).expect("ETag 只包含十六进制字符");
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// 纯文本订阅：每行一个 IP
+async fn feed_plain(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let bans = sorted_bans(&state).await?;
+    let body: String = bans.iter().map(|ip| format!("{}\n", ip)).collect();
+    Ok(feed_response(&headers, "text/plain; charset=utf-8", body))
+}
+
+/// JSON 订阅：`{"count": n, "bans": [...]}`
+async fn feed_json(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let bans = sorted_bans(&state).await?;
+    let body = serde_json::to_string(&FeedResponse {
+        count: bans.len(),
+        bans,
+    })
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(feed_response(&headers, "application/json", body))
+}
+
+async fn get_whitelist(State(state): State<ApiState>) -> Json<WhitelistBody> {
+    let whitelist = state.whitelist.lock().unwrap();
+    Json(WhitelistBody {
+        patterns: whitelist.get_patterns().to_vec(),
+        mode: Some(whitelist.mode()),
+        match_mode: Some(whitelist.match_mode()),
+    })
+}
+
+async fn put_whitelist(
+    State(state): State<ApiState>,
+    Json(body): Json<WhitelistBody>,
+) -> Result<Json<WhitelistBody>, ApiError> {
+    let patterns: Vec<String> = body
+        .patterns
+        .into_iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if patterns.is_empty() {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "白名单不能为空".to_string(),
+        ));
+    }
+    let mut whitelist = state.whitelist.lock().unwrap();
+    let mode = whitelist.mode();
+    if body.mode.is_some_and(|requested| requested != mode) {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("名单模式只能在启动时选择，当前为 {}", mode.as_str()),
+        ));
+    }
+    info!("【API】更新 UA 名单（{}）: {:?}", mode.as_str(), patterns);
+    whitelist.set_patterns(patterns.clone());
+    Ok(Json(WhitelistBody {
+        patterns,
+        mode: Some(mode),
+        match_mode: Some(whitelist.match_mode()),
+    }))
+}
+
+/// 受保护端口；数据包来源不支持更换端口时返回 404
+fn capture_ports(state: &ApiState) -> Result<&Arc<CapturePorts>, ApiError> {
+    state.ports.as_ref().ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            "当前的数据包来源不支持在运行中更换端口".to_string(),
+        )
+    })
+}
+
+async fn get_ports(State(state): State<ApiState>) -> Result<Json<PortsBody>, ApiError> {
+    let ports = capture_ports(&state)?.ports();
+    Ok(Json(PortsBody { ports }))
+}
+
+/// 更换受保护端口：抓包线程在下一次读取前应用新的过滤器
+async fn put_ports(
+    State(state): State<ApiState>,
+    Json(body): Json<PortsBody>,
+) -> Result<Json<PortsBody>, ApiError> {
+    let ports = capture_ports(&state)?;
+    let changed = ports
+        .set(body.ports)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+    if changed {
+        info!("【API】更换受保护端口: {:?}", ports.ports());
+    }
+    Ok(Json(PortsBody {
+        ports: ports.ports(),
+    }))
+}
+
+async fn get_stats(State(state): State<ApiState>) -> Json<StatsSnapshot> {
+    Json(state.enforcer.stats().snapshot())
+}
+
+/// 健康检查：列出封禁以确认后端可用
+async fn get_health(State(state): State<ApiState>) -> Json<HealthResponse> {
+    let stats = state.enforcer.stats().snapshot();
+    if state.enforcer.is_standby() {
+        return Json(HealthResponse {
+            backend: "standby",
+            backend_error: None,
+            active_bans: state.enforcer.bans().records().len(),
+            stats,
+        });
+    }
+    if state.enforcer.firewall().is_none() {
+        return Json(HealthResponse {
+            backend: "disabled",
+            backend_error: None,
+            active_bans: 0,
+            stats,
+        });
+    }
+    let enforcer = state.enforcer.clone();
+    let response = match run_blocking(move || enforcer.list_bans()).await {
+        // 封禁/解封连续失败时，即使能列出封禁也报告降级
+        Ok(bans) if stats.firewall_degraded > 0 => HealthResponse {
+            backend: "degraded",
+            backend_error: Some(format!(
+                "封禁后端连续执行失败（累计 {} 次）",
+                stats.firewall_failures
+            )),
+            active_bans: bans.len(),
+            stats,
+        },
+        Ok(bans) => HealthResponse {
+            backend: "ok",
+            backend_error: None,
+            active_bans: bans.len(),
+            stats,
+        },
+        Err(ApiError(_, e)) => HealthResponse {
+            backend: "error",
+            backend_error: Some(e),
+            active_bans: 0,
+            stats,
+        },
+    };
+    Json(response)
+}
+
+/// 本机在主备部署中的角色
+async fn get_ha(State(state): State<ApiState>) -> Json<HaResponse> {
+    Json(HaResponse {
+        role: state.enforcer.role(),
+        programmed: None,
+    })
+}
+
+/// 备机升为主机，按封禁表下发防火墙规则
+async fn promote(State(state): State<ApiState>) -> Result<Json<HaResponse>, ApiError> {
+    if !state.enforcer.is_standby() {
+        return Err(ApiError(StatusCode::CONFLICT, "本机已是主机".to_string()));
+    }
+    let enforcer = state.enforcer.clone();
+    let programmed = run_blocking(move || enforcer.promote()).await?;
+    info!("【API】备机已升为主机");
+    Ok(Json(HaResponse {
+        role: HaRole::Active,
+        programmed: Some(programmed),
+    }))
+}
+
+/// 扫描活动列表（未设置 `UABLOCK_CAMPAIGN_REPORT` 时为 404）
+async fn get_campaigns(State(state): State<ApiState>) -> Result<Json<Vec<Campaign>>, ApiError> {
+    let campaigns = state.campaigns.as_ref().ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            "未启用扫描活动归并（UABLOCK_CAMPAIGN_REPORT）".to_string(),
+        )
+    })?;
+    Ok(Json(campaigns.campaigns()))
+}
+
+/// 待审核封禁队列；未启用时返回 404
+fn pending_bans(state: &ApiState) -> Result<&Arc<PendingBans>, ApiError> {
+    state.review.as_ref().ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            "未启用待审核封禁（UABLOCK_REVIEW_HOLD）".to_string(),
+        )
+    })
+}
+
+async fn get_review(State(state): State<ApiState>) -> Result<Json<Vec<PendingBan>>, ApiError> {
+    Ok(Json(pending_bans(&state)?.pending()))
+}
+
+/// 审核一个待审核的封禁：`approve` 立即封禁，`reject` 驳回，`whitelist` 驳回并把 UA 加入白名单
+async fn review_ban(
+    State(state): State<ApiState>,
+    Path((ip, action)): Path<(IpAddr, ReviewAction)>,
+) -> Result<Json<PendingBan>, ApiError> {
+    let pending = pending_bans(&state)?
+        .review(ip, action, &state.whitelist)
+        .map_err(|e| ApiError(StatusCode::CONFLICT, e))?;
+    if action == ReviewAction::Approve {
+        let enforcer = state.enforcer.clone();
+        let detection = pending.detection().clone();
+        run_blocking(move || {
+            enforcer.handle_detection(&detection);
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(Json(pending))
+}
+
+/// 外部系统推送的封禁/解封命令（使用独立的 HMAC 签名认证）
+async fn receive_webhook(
+    State(state): State<ApiState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<WebhookReply>, ApiError> {
+    let webhook = state.webhook.clone().ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            "未启用 webhook（UABLOCK_WEBHOOK_SECRET）".to_string(),
+        )
+    })?;
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Err(e) = webhook.verify(
+        header("x-uablock-timestamp"),
+        header("x-uablock-signature"),
+        &body,
+    ) {
+        warn!("【Webhook】拒绝来自 {} 的请求: {}", remote, e);
+        return Err(ApiError(StatusCode::UNAUTHORIZED, e));
+    }
+    let command: WebhookCommand = serde_json::from_slice(&body)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("请求体无效: {}", e)))?;
+    command
+        .expires_at(unix_now())
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+    let enforcer = state.enforcer.clone();
+    let changed =
+        run_blocking(move || webhook.apply(&enforcer, &command, &remote.to_string())).await?;
+    Ok(Json(WebhookReply { changed }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryFirewall;
+    use crate::{EventBus, Stats};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "admin-token";
+    const SCANNER: &str = "203.0.113.9";
+
+    fn state(feed_token: Option<&str>) -> ApiState {
+        ApiState {
+            token: Arc::new(TOKEN.to_string()),
+            feed_token: feed_token.map(|token| Arc::new(token.to_string())),
+            enforcer: Arc::new(Enforcer::new(
+                Some(Box::new(MemoryFirewall::new())),
+                None,
+                Arc::new(Stats::default()),
+                Arc::new(EventBus::new()),
+            )),
+            whitelist: Arc::new(Mutex::new(Whitelist::default())),
+            webhook: None,
+            ports: None,
+            campaigns: None,
+            review: None,
+        }
+    }
+
+    async fn call(
+        state: &ApiState,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<&str>,
+    ) -> (StatusCode, String) {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = router(state.clone())
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn management_routes_require_the_bearer_token() {
+        let state = state(None);
+        for token in [None, Some("wrong-token"), Some("admin-toke")] {
+            for (method, uri) in [("GET", "/bans"), ("GET", "/whitelist"), ("GET", "/stats")] {
+                let (status, _) = call(&state, method, uri, token, None).await;
+                assert_eq!(
+                    status,
+                    StatusCode::UNAUTHORIZED,
+                    "{} {} {:?}",
+                    method,
+                    uri,
+                    token
+                );
+            }
+        }
+        let (status, body) = call(
+            &state,
+            "POST",
+            "/bans",
+            Some("wrong-token"),
+            Some(r#"{"ip": "203.0.113.9"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("未授权"), "{}", body);
+        assert!(state.enforcer.list_bans().unwrap().is_empty());
+
+        let (status, _) = call(&state, "GET", "/bans", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn feed_is_open_without_a_feed_token() {
+        let state = state(None);
+        state
+            .enforcer
+            .ban(SCANNER.parse().unwrap(), BanReason::new("MANUAL", "API"))
+            .unwrap();
+        let (status, body) = call(&state, "GET", "/feed/plain", None, None).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "203.0.113.9\n"));
+    }
+
+    #[tokio::test]
+    async fn feed_token_is_accepted_as_query_or_bearer_header() {
+        let state = state(Some("feed-token"));
+        let (status, _) = call(&state, "GET", "/feed/json", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&state, "GET", "/feed/json?token=wrong", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // 管理 token 不能代替订阅 token
+        let (status, _) = call(&state, "GET", "/feed/json", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = call(&state, "GET", "/feed/json?token=feed-token", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"count":0,"bans":[]}"#);
+        let (status, _) = call(&state, "GET", "/feed/plain", Some("feed-token"), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn bans_are_added_listed_and_removed() {
+        let state = state(None);
+        let (status, _) = call(
+            &state,
+            "POST",
+            "/bans",
+            Some(TOKEN),
+            Some(r#"{"ip": "203.0.113.9"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, body) =
+            call(&state, "POST", "/bans", Some(TOKEN), Some(r#"{"ip": "x"}"#)).await;
+        assert!(status.is_client_error(), "{} {}", status, body);
+
+        let (status, body) = call(&state, "GET", "/bans", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(listed["bans"], serde_json::json!([SCANNER]));
+        assert_eq!(listed["details"][0]["reason"], "MANUAL");
+
+        let (status, _) = call(&state, "DELETE", "/bans/203.0.113.9", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.enforcer.list_bans().unwrap().is_empty());
+
+        // 批量解封需要指定条件
+        let (status, _) = call(&state, "DELETE", "/bans", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(&state, "DELETE", "/bans?older_than=soon", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        state
+            .enforcer
+            .ban(SCANNER.parse().unwrap(), BanReason::new("MANUAL", "API"))
+            .unwrap();
+        let (status, body) = call(&state, "DELETE", "/bans?all=true", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"unbanned":["203.0.113.9"]}"#);
+    }
+
+    #[tokio::test]
+    async fn whitelist_can_be_read_and_replaced() {
+        let state = state(None);
+        let (status, body) = call(&state, "GET", "/whitelist", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("microsip"), "{}", body);
+
+        let (status, _) = call(
+            &state,
+            "PUT",
+            "/whitelist",
+            Some(TOKEN),
+            Some(r#"{"patterns": [" ", ""]}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(
+            &state,
+            "PUT",
+            "/whitelist",
+            Some(TOKEN),
+            Some(r#"{"patterns": ["Yealink"], "mode": "deny"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = call(
+            &state,
+            "PUT",
+            "/whitelist",
+            Some(TOKEN),
+            Some(r#"{"patterns": [" Yealink ", "Grandstream"]}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let replaced: WhitelistBody = serde_json::from_str(&body).unwrap();
+        assert_eq!(replaced.patterns, ["Yealink", "Grandstream"]);
+        assert_eq!(
+            state.whitelist.lock().unwrap().get_patterns(),
+            ["Yealink", "Grandstream"]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::ingest::{ExternalSignal, SignalSender};
+use crate::verification::AUTH_SUCCESS;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 日志轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// AMI 断线后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// AMI 读超时，超时后发送 Ping 保活
+const AMI_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 把 Asterisk 安全事件映射为原因代码和权重系数
+///
+/// ChallengeSent 在正常注册流程中也会出现，只计入很小的权重，
+/// 用于发现只发起挑战却从不完成认证的扫描器。SuccessfulAuth 不计入惩罚分，
+/// 只用于白名单 UA 的二次验证。
+fn classify_event(name: &str) -> Option<(&'static str, f64)> {
+    match name {
+        "InvalidPassword" | "InvalidAccountID" | "ChallengeResponseFailed" => {
+            Some(("AUTH_FAILURE", 1.0))
+        }
+        "FailedACL" => Some(("ACL_DENIED", 1.5)),
+        "ChallengeSent" => Some(("AUTH_CHALLENGE", 0.1)),
+        "SuccessfulAuth" => Some((AUTH_SUCCESS, 0.0)),
+        _ => None,
+    }
+}
+
+/// 解析 `IPV4/UDP/1.2.3.4/5060` 形式的 RemoteAddress
+fn parse_remote_address(address: &str) -> Option<IpAddr> {
+    let ip = address.split('/').nth(2)?;
+    ip.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// 由事件名和 RemoteAddress 生成信号
+fn to_signal(event: &str, remote_address: &str, weight: f64) -> Option<ExternalSignal> {
+    let (reason, factor) = classify_event(event)?;
+    Some(ExternalSignal {
+        source_ip: parse_remote_address(remote_address)?,
+        user_agent: String::new(),
+        reason: reason.to_string(),
+        weight: weight * factor,
+        origin: "asterisk".to_string(),
+    })
+}
+
+/// 从环境变量读取基础权重 `UABLOCK_ASTERISK_WEIGHT`（默认 2.0）
+fn weight_from_env() -> f64 {
+    std::env::var("UABLOCK_ASTERISK_WEIGHT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2.0)
+}
+
+/// 跟踪 Asterisk 安全日志（res_security_log 输出）
+pub struct AsteriskSecurityLog {
+    path: PathBuf,
+    weight: f64,
+    sender: SignalSender,
+}
+
+impl AsteriskSecurityLog {
+    /// 从环境变量创建，未设置 `UABLOCK_ASTERISK_SECURITY_LOG` 时返回 None
+    pub fn from_env(sender: SignalSender) -> Option<Self> {
+        let path = std::env::var("UABLOCK_ASTERISK_SECURITY_LOG")
+            .ok()
+            .filter(|p| !p.is_empty())?;
+        Some(Self {
+            path: PathBuf::from(path),
+            weight: weight_from_env(),
+            sender,
+        })
+    }
+
+    /// 在后台线程中跟踪日志（从文件末尾开始，支持 logrotate）
+    pub fn start(self) -> Result<(), String> {
+        info!("【Asterisk】跟踪安全日志 {}", self.path.display());
+        std::thread::Builder::new()
+            .name("asterisk-log".to_string())
+            .spawn(move || self.tail())
+            .map_err(|e| format!("启动 Asterisk 日志线程失败: {}", e))?;
+        Ok(())
+    }
+
+    fn tail(self) {
+        let mut reader: Option<BufReader<File>> = None;
+        let mut position = 0u64;
+        let mut line = String::new();
+        loop {
+            // 文件被轮转（变小或被删除）时重新打开
+            let length = std::fs::metadata(&self.path).map(|m| m.len()).ok();
+            if length.is_none_or(|length| length < position) {
+                reader = None;
+            }
+            if reader.is_none() {
+                match File::open(&self.path) {
+                    Ok(mut file) => {
+                        // 首次打开从末尾开始，轮转后的新文件从头开始
+                        position = if position == 0 {
+                            file.seek(SeekFrom::End(0)).unwrap_or(0)
+                        } else {
+                            0
+                        };
+                        reader = Some(BufReader::new(file));
+                    }
+                    Err(e) => debug!("【Asterisk】无法打开 {}: {}", self.path.display(), e),
+                }
+            }
+
+            if let Some(reader) = reader.as_mut() {
+                loop {
+                    match reader.read_line(&mut line) {
+                        Ok(0) => break,
+                        // 不完整的行留到下次继续读取
+                        Ok(n) if !line.ends_with('\n') => position += n as u64,
+                        Ok(n) => {
+                            position += n as u64;
+                            if let Some(signal) = self.parse_line(&line) {
+                                if self.sender.send(signal).is_err() {
+                                    return;
+                                }
+                            }
+                            line.clear();
+                        }
+                        Err(e) => {
+                            warn!("【Asterisk】读取安全日志失败: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// 解析一行：`... SecurityEvent="InvalidPassword",...,RemoteAddress="IPV4/UDP/1.2.3.4/5060",...`
+    fn parse_line(&self, line: &str) -> Option<ExternalSignal> {
+        let fields: HashMap<&str, &str> = line
+            .split(',')
+            .filter_map(|field| {
+                let (key, value) = field.split_once('=')?;
+                let key = key.rsplit(' ').next()?;
+                Some((key, value.trim().trim_matches('"')))
+            })
+            .collect();
+        to_signal(
+            fields.get("SecurityEvent")?,
+            fields.get("RemoteAddress")?,
+            self.weight,
+        )
+    }
+}
+
+/// 通过 AMI 订阅 security 类事件
+pub struct AsteriskAmi {
+    address: String,
+    username: String,
+    secret: String,
+    weight: f64,
+    sender: SignalSender,
+}
+
+impl AsteriskAmi {
+    /// 从环境变量创建，未设置 `UABLOCK_ASTERISK_AMI` 时返回 None
+    ///
+    /// - `UABLOCK_ASTERISK_AMI`：AMI 地址，例如 `127.0.0.1:5038`
+    /// - `UABLOCK_ASTERISK_AMI_USER` / `UABLOCK_ASTERISK_AMI_SECRET`：manager.conf 中的账号
+    pub fn from_env(sender: SignalSender) -> Option<Self> {
+        let address = std::env::var("UABLOCK_ASTERISK_AMI")
+            .ok()
+            .filter(|a| !a.is_empty())?;
+        Some(Self {
+            address,
+            username: std::env::var("UABLOCK_ASTERISK_AMI_USER").unwrap_or_default(),
+            secret: std::env::var("UABLOCK_ASTERISK_AMI_SECRET").unwrap_or_default(),
+            weight: weight_from_env(),
+            sender,
+        })
+    }
+
+    /// 在后台线程中运行，断线后自动重连
+    pub fn start(self) -> Result<(), String> {
+        info!("【Asterisk】订阅 AMI {} 的安全事件", self.address);
+        std::thread::Builder::new()
+            .name("asterisk-ami".to_string())
+            .spawn(move || loop {
+                match self.session() {
+                    Ok(()) => return,
+                    Err(e) => warn!(
+                        "【Asterisk】AMI 连接中断: {}，{} 秒后重连",
+                        e,
+                        RECONNECT_DELAY.as_secs()
+                    ),
+                }
+                std::thread::sleep(RECONNECT_DELAY);
+            })
+            .map_err(|e| format!("启动 Asterisk AMI 线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 一次连接会话；主循环退出（通道关闭）时返回 Ok
+    fn session(&self) -> Result<(), String> {
+        let addr = self
+            .address
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("无法解析地址")?;
+        let mut stream =
+            TcpStream::connect_timeout(&addr, RECONNECT_DELAY).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(AMI_READ_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let mut reader = AmiReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+
+        // 欢迎行：Asterisk Call Manager/x.y.z
+        let banner = reader.read_line()?;
+        debug!("【Asterisk】{}", banner.trim());
+
+        let login = format!(
+            "Action: Login\r\nUsername: {}\r\nSecret: {}\r\nEvents: security\r\n\r\n",
+            self.username, self.secret
+        );
+        stream
+            .write_all(login.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let response = reader.read_block(&mut stream)?;
+        if response.get("Response").map(String::as_str) != Some("Success") {
+            return Err(format!(
+                "登录失败: {}",
+                response.get("Message").cloned().unwrap_or_default()
+            ));
+        }
+        info!("【Asterisk】已登录 AMI {}", self.address);
+
+        loop {
+            let block = reader.read_block(&mut stream)?;
+            let (Some(event), Some(remote)) = (block.get("Event"), block.get("RemoteAddress"))
+            else {
+                continue;
+            };
+            if let Some(signal) = to_signal(event, remote, self.weight) {
+                debug!("【Asterisk】安全事件 {} IP: {}", event, signal.source_ip);
+                if self.sender.send(signal).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// AMI 消息读取器：读超时时发送 Ping 保活，并保留未读完的行
+struct AmiReader {
+    reader: BufReader<TcpStream>,
+    line: String,
+}
+
+impl AmiReader {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            line: String::new(),
+        }
+    }
+
+    /// 读取一整行（超时返回错误）
+    fn read_line(&mut self) -> Result<String, String> {
+        self.line.clear();
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => Err("连接已关闭".to_string()),
+            Ok(_) => Ok(std::mem::take(&mut self.line)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// 读取一个以空行结束的消息块，空闲超时时发送 Ping
+    fn read_block(&mut self, stream: &mut TcpStream) -> Result<HashMap<String, String>, String> {
+        let mut block = HashMap::new();
+        loop {
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return Err("连接已关闭".to_string()),
+                Ok(_) if !self.line.ends_with('\n') => continue,
+                Ok(_) => {
+                    let line = std::mem::take(&mut self.line);
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        if block.is_empty() {
+                            continue;
+                        }
+                        return Ok(block);
+                    }
+                    if let Some((key, value)) = line.split_once(": ") {
+                        block.insert(key.to_string(), value.to_string());
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    stream
+                        .write_all(b"Action: Ping\r\n\r\n")
+                        .map_err(|e| e.to_string())?;
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::enforcement::Enforcer;
+use crate::reason::ReasonCode;
+use crate::registered::RegisteredEndpoints;
+use crate::whitelist::Whitelist;
+use log::{debug, info, warn};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+/// 查询的最大长度（IP、空格和 User-Agent）
+const MAX_QUERY: usize = 2048;
+
+/// 一次授权查询的结论
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// 拒绝，附带原因代码和分类
+    Deny {
+        reason: String,
+        code: ReasonCode,
+    },
+}
+
+impl fmt::Display for Decision {
+    /// 应答格式：`ALLOW` 或 `DENY <原因代码> <原因分类>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => f.write_str("ALLOW"),
+            Self::Deny { reason, code } => write!(f, "DENY {} {}", reason, code),
+        }
+    }
+}
+
+/// 供 PBX 调用的本地授权查询：这个（IP, UA）现在是否放行
+///
+/// 只读内存中的封禁表和白名单，不执行任何防火墙命令，适合在 Kamailio 路由脚本中对每个 REGISTER
+/// 同步查询并当场拒绝；防火墙规则照常由检测流程下发，兜住不经过查询的流量。
+/// 查询不计入检测，也不会触发封禁。
+pub struct Authz {
+    enforcer: Arc<Enforcer>,
+    whitelist: Arc<Mutex<Whitelist>>,
+    registered: Option<Arc<RegisteredEndpoints>>,
+}
+
+impl Authz {
+    pub fn new(enforcer: Arc<Enforcer>, whitelist: Arc<Mutex<Whitelist>>) -> Self {
+        Self {
+            enforcer,
+            whitelist,
+            registered: None,
+        }
+    }
+
+    /// 已注册终端不因 UA 不在白名单中被拒绝（与检测流程一致）
+    pub fn with_registered(mut self, registered: Arc<RegisteredEndpoints>) -> Self {
+        self.registered = Some(registered);
+        self
+    }
+
+    /// 判定：本机地址和可信来源放行；封禁表中的来源（含灰名单临时丢弃）拒绝；UA 不在白名单中（或命中黑名单）拒绝
+    pub fn decide(&self, ip: IpAddr, user_agent: &str) -> Decision {
+        if self.enforcer.is_host_address(&ip) || self.enforcer.is_trusted(&ip) {
+            return Decision::Allow;
+        }
+        if let Some(record) = self.enforcer.bans().get(&ip) {
+            return Decision::Deny {
+                reason: record.reason.reason,
+                code: record.reason.code,
+            };
+        }
+        if self
+            .registered
+            .as_ref()
+            .is_some_and(|registered| registered.is_registered(&ip))
+        {
+            return Decision::Allow;
+        }
+        let whitelist = self.whitelist.lock().unwrap();
+        if !whitelist.is_allowed(user_agent) {
+            return Decision::Deny {
+                reason: whitelist.mode().reason().to_string(),
+                code: ReasonCode::UaNotAllowed,
+            };
+        }
+        Decision::Allow
+    }
+
+    /// 应答一条查询 `<IP> <User-Agent>`（UA 为行内其余部分，可以为空）
+    ///
+    /// 无法解析时返回 `ERROR <说明>`。
+    pub fn answer(&self, query: &[u8]) -> String {
+        let query = String::from_utf8_lossy(query);
+        let query = query.trim_end_matches(['\r', '\n']);
+        let (ip, user_agent) = query.split_once(' ').unwrap_or((query, ""));
+        match ip.parse::<IpAddr>() {
+            Ok(ip) => self.decide(ip, user_agent.trim()).to_string(),
+            Err(_) => format!("ERROR invalid ip: {}", ip),
+        }
+    }
+
+    /// 在 UDP 地址上应答查询（每个数据报一条查询，应答发回对端）
+    pub fn start(self: Arc<Self>, listen: SocketAddr) -> Result<(), String> {
+        let socket = UdpSocket::bind(listen).map_err(|e| format!("无法监听 {}: {}", listen, e))?;
+        if !listen.ip().is_loopback() {
+            warn!(
+                "授权查询监听在非回环地址 {}，任何能访问该地址的人都能查询封禁状态",
+                listen
+            );
+        }
+        std::thread::Builder::new()
+            .name("authz".to_string())
+            .spawn(move || {
+                let mut buffer = [0u8; MAX_QUERY];
+                loop {
+                    let (len, peer) = match socket.recv_from(&mut buffer) {
+                        Ok(received) => received,
+                        Err(e) => {
+                            debug!("【授权查询】接收失败: {}", e);
+                            continue;
+                        }
+                    };
+                    let reply = self.answer(&buffer[..len]);
+                    debug!(
+                        "【授权查询】{} 查询 {} → {}",
+                        peer,
+                        String::from_utf8_lossy(&buffer[..len]).trim_end(),
+                        reply
+                    );
+                    if let Err(e) = socket.send_to(reply.as_bytes(), peer) {
+                        debug!("【授权查询】应答 {} 失败: {}", peer, e);
+                    }
+                }
+            })
+            .map_err(|e| format!("启动授权查询线程失败: {}", e))?;
+        info!("授权查询已启用，监听 UDP {}", listen);
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::events::{Event, EventBus, EventKind};
+use crate::stats::Stats;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 封禁无效告警的原因代码
+pub const BAN_INEFFECTIVE: &str = "BAN_INEFFECTIVE";
+
+/// 一个正在验证的封禁
+struct Watch {
+    since: Instant,
+    /// 封禁以来本机发给该来源的 SIP 数据包数
+    responses: u64,
+}
+
+/// 封禁效果验证：封禁后本机是否仍在应答该来源
+///
+/// 抓包发生在防火墙之前，封禁后扫描器的数据包照样能抓到，无法据此判断规则是否生效；
+/// 但规则生效时这些数据包到不了 PBX，PBX 也就不会再应答。封禁后的验证窗口内，
+/// 本机从 SIP 端口发给被封禁来源的数据包达到阈值时，说明 DROP 规则没有拦住流量
+/// （常见原因是规则所在的链不对，或排在了放行规则之后），此时发布 `alert` 事件并输出错误日志。
+pub struct BanCheck {
+    window: Duration,
+    threshold: u64,
+    watches: Mutex<HashMap<IpAddr, Watch>>,
+}
+
+impl BanCheck {
+    /// `window` 为封禁后的验证窗口，`threshold` 为窗口内判定封禁无效的应答数
+    pub fn new(window: Duration, threshold: u64) -> Self {
+        Self {
+            window,
+            threshold: threshold.max(1),
+            watches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 从环境变量创建，未设置 UABLOCK_BAN_CHECK=1 时返回 None
+    ///
+    /// - UABLOCK_BAN_CHECK_WINDOW：验证窗口（秒，默认 60）
+    /// - UABLOCK_BAN_CHECK_THRESHOLD：窗口内判定封禁无效的应答数（默认 3）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        if std::env::var("UABLOCK_BAN_CHECK").as_deref() != Ok("1") {
+            return Ok(None);
+        }
+        let env_u64 = |name: &str, default: u64| match std::env::var(name) {
+            Ok(value) if !value.is_empty() => value
+                .parse()
+                .map_err(|_| format!("{} 无效: {}", name, value)),
+            _ => Ok(default),
+        };
+        let check = Self::new(
+            Duration::from_secs(env_u64("UABLOCK_BAN_CHECK_WINDOW", 60)?),
+            env_u64("UABLOCK_BAN_CHECK_THRESHOLD", 3)?,
+        );
+        info!(
+            "封禁效果验证已启用: 封禁后 {} 秒内本机应答 {} 次即告警",
+            check.window.as_secs(),
+            check.threshold
+        );
+        Ok(Some(check))
+    }
+
+    /// 开始验证一个新封禁（重复封禁时重新计时）
+    pub fn watch(&self, ip: IpAddr) {
+        self.watches.lock().unwrap().insert(
+            ip,
+            Watch {
+                since: Instant::now(),
+                responses: 0,
+            },
+        );
+    }
+
+    /// 停止验证（已解封）
+    pub fn forget(&self, ip: &IpAddr) {
+        self.watches.lock().unwrap().remove(ip);
+    }
+
+    /// 本机向 `ip` 发出了一个数据包；验证窗口内达到阈值时返回应答数，此后不再验证该封禁
+    pub fn observe(&self, ip: &IpAddr) -> Option<u64> {
+        let mut watches = self.watches.lock().unwrap();
+        let watch = watches.get_mut(ip)?;
+        if watch.since.elapsed() >= self.window {
+            return None;
+        }
+        watch.responses += 1;
+        if watch.responses < self.threshold {
+            return None;
+        }
+        let responses = watch.responses;
+        watches.remove(ip);
+        Some(responses)
+    }
+
+    /// 清理验证窗口已结束的封禁
+    pub fn expire(&self) {
+        let window = self.window;
+        self.watches.lock().unwrap().retain(|ip, watch| {
+            let active = watch.since.elapsed() < window;
+            if !active {
+                debug!(
+                    "【封禁验证】IP: {} 封禁后 {} 秒内应答 {} 次，封禁有效",
+                    ip,
+                    window.as_secs(),
+                    watch.responses
+                );
+            }
+            active
+        });
+    }
+
+    /// 正在验证的封禁数
+    pub fn len(&self) -> usize {
+        self.watches.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 订阅封禁/解封事件，自动验证每个新封禁
+    pub fn subscribe(self: &Arc<Self>, events: &EventBus) {
+        let check = Arc::downgrade(self);
+        events.subscribe(move |event| {
+            let Some(check) = check.upgrade() else {
+                return false;
+            };
+            match event.kind {
+                EventKind::Ban => check.watch(event.ip),
+                EventKind::Unban => check.forget(&event.ip),
+                EventKind::Detection | EventKind::Alert => {}
+            }
+            true
+        });
+    }
+
+    /// 封禁无效：计数、输出错误日志并发布告警事件
+    pub fn report(&self, ip: IpAddr, responses: u64, stats: &Stats, events: &EventBus) {
+        Stats::incr(&stats.ineffective_bans);
+        error!(
+            "【封禁无效】IP: {} 已封禁，但 {} 秒内本机仍向其发出 {} 个 SIP 数据包，请检查 DROP 规则所在的链和顺序",
+            ip,
+            self.window.as_secs(),
+            responses
+        );
+        events.publish(Event::new(
+            EventKind::Alert,
+            ip,
+            "",
+            BAN_INEFFECTIVE,
+            "ban-check",
+        ));
+    }
+
+    /// 在抓包接口上观察本机从 SIP 端口发出的数据包，在后台线程中验证封禁
+    #[cfg(feature = "pcap")]
+    pub fn start(
+        self: Arc<Self>,
+        interface: &str,
+        port: u16,
+        stats: Arc<Stats>,
+        events: Arc<EventBus>,
+    ) -> Result<(), String> {
+        use crate::packet_capture::{decode_frame, LinkLayer};
+
+        let mut capture = pcap::Capture::from_device(interface)
+            .map_err(|e| format!("无法打开网络接口 {}: {}", interface, e))?
+            .promisc(false)
+            .snaplen(128)
+            .timeout(1000)
+            .open()
+            .map_err(|e| format!("无法开始抓包: {}", e))?;
+        let link = LinkLayer::of(&capture);
+        capture
+            .filter(&format!("udp and src port {}", port), true)
+            .map_err(|e| format!("设置过滤器失败: {}", e))?;
+        self.subscribe(&events);
+
+        std::thread::Builder::new()
+            .name("ban-check".to_string())
+            .spawn(move || {
+                let mut last_expire = Instant::now();
+                loop {
+                    if last_expire.elapsed() >= Duration::from_secs(1) {
+                        self.expire();
+                        last_expire = Instant::now();
+                    }
+                    let packet = match capture.next_packet() {
+                        Ok(packet) => decode_frame(packet.data, link),
+                        Err(pcap::Error::TimeoutExpired) => None,
+                        Err(e) => {
+                            error!("【封禁验证】抓包错误: {}", e);
+                            return;
+                        }
+                    };
+                    let Some(packet) = packet else {
+                        continue;
+                    };
+                    // 发出的数据包：目标是被封禁的来源
+                    if let Some(responses) = self.observe(&packet.dest_ip) {
+                        self.report(packet.dest_ip, responses, &stats, &events);
+                    }
+                }
+            })
+            .map_err(|e| format!("启动封禁验证线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 未启用 `pcap` 特性时无法观察本机发出的数据包
+    #[cfg(not(feature = "pcap"))]
+    pub fn start(
+        self: Arc<Self>,
+        _interface: &str,
+        _port: u16,
+        _stats: Arc<Stats>,
+        _events: Arc<EventBus>,
+    ) -> Result<(), String> {
+        Err("封禁效果验证（UABLOCK_BAN_CHECK）需要 pcap 特性".to_string())
+    }
+}
@@ -0,0 +1,194 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 延迟告警的原因代码
+pub const BAN_LATENCY_HIGH: &str = "BAN_LATENCY_HIGH";
+
+/// 直方图各桶的上界（毫秒），最后还有一个不设上界的桶
+const BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// 计算 P99 时使用的最近封禁数
+const ALERT_WINDOW: usize = 100;
+
+/// 最近封禁数达到该值后才开始判断 P99，避免刚启动时单次慢封禁触发告警
+const ALERT_MIN_SAMPLES: usize = 10;
+
+/// 延迟直方图（可在多个线程间共享）
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    /// 各桶的计数（非累计），最后一个为超过最大上界的计数
+    buckets: [AtomicU64; BUCKETS_MS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let ms = latency.as_millis();
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= u128::from(bound))
+            .unwrap_or(BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                LatencyBucket {
+                    le_ms: BUCKETS_MS.get(i).copied(),
+                    count: cumulative,
+                }
+            })
+            .collect();
+        LatencySnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum_ms: self.sum_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            buckets,
+        }
+    }
+}
+
+/// 直方图的一个桶（与 Prometheus 一样为累计计数）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    /// 桶的上界（毫秒），None 表示不设上界
+    pub le_ms: Option<u64>,
+    /// 延迟不超过上界的次数
+    pub count: u64,
+}
+
+/// 延迟直方图快照
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub sum_ms: f64,
+    pub buckets: Vec<LatencyBucket>,
+}
+
+impl LatencySnapshot {
+    /// 按桶估计的分位数（所在桶的上界，毫秒）；没有样本或落在最后一个桶时为 None
+    pub fn quantile_ms(&self, q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (self.count as f64 * q).ceil() as u64;
+        self.buckets
+            .iter()
+            .find(|bucket| bucket.count >= rank)
+            .and_then(|bucket| bucket.le_ms)
+    }
+}
+
+/// 封禁生效延迟的分段直方图
+///
+/// 每个由抓包触发的封禁记录三个时刻：读取线程收到数据包、流水线判定封禁、防火墙命令返回成功。
+/// 外部信号触发的封禁没有抓包时刻，只计入判定到生效一段。
+#[derive(Debug, Default)]
+pub struct BanLatency {
+    /// 抓包 → 判定（含数据包在队列中的等待）
+    pub capture_to_detect: LatencyHistogram,
+    /// 判定 → 规则生效（含处置队列等待和防火墙命令）
+    pub detect_to_block: LatencyHistogram,
+    /// 抓包 → 规则生效，即攻击者不受限制的时间窗口
+    pub capture_to_block: LatencyHistogram,
+}
+
+impl BanLatency {
+    pub fn snapshot(&self) -> BanLatencySnapshot {
+        BanLatencySnapshot {
+            capture_to_detect: self.capture_to_detect.snapshot(),
+            detect_to_block: self.detect_to_block.snapshot(),
+            capture_to_block: self.capture_to_block.snapshot(),
+        }
+    }
+}
+
+/// 封禁生效延迟快照
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BanLatencySnapshot {
+    pub capture_to_detect: LatencySnapshot,
+    pub detect_to_block: LatencySnapshot,
+    pub capture_to_block: LatencySnapshot,
+}
+
+/// 最近封禁的 P99 延迟超过上限时告警
+///
+/// 按最近 [`ALERT_WINDOW`] 次封禁计算（而不是整个运行期间），延迟恢复正常后可以再次告警。
+pub struct LatencyAlert {
+    bound: Duration,
+    recent: Mutex<RecentLatencies>,
+}
+
+#[derive(Default)]
+struct RecentLatencies {
+    samples: VecDeque<Duration>,
+    /// 当前是否处于超限状态（只在进入超限时告警一次）
+    exceeded: bool,
+}
+
+impl LatencyAlert {
+    pub fn new(bound: Duration) -> Self {
+        Self {
+            bound,
+            recent: Mutex::new(RecentLatencies::default()),
+        }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_BAN_LATENCY_P99_MS` 时返回 None
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_BAN_LATENCY_P99_MS") {
+            Ok(value) if !value.is_empty() => {
+                let ms = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or_else(|| format!("UABLOCK_BAN_LATENCY_P99_MS 无效: {}", value))?;
+                info!("封禁延迟告警已启用: 最近封禁的 P99 超过 {} 毫秒时告警", ms);
+                Ok(Some(Self::new(Duration::from_millis(ms))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn bound(&self) -> Duration {
+        self.bound
+    }
+
+    /// 记录一次封禁的延迟，刚超过上限时返回最近封禁的 P99
+    pub fn observe(&self, latency: Duration) -> Option<Duration> {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.samples.len() == ALERT_WINDOW {
+            recent.samples.pop_front();
+        }
+        recent.samples.push_back(latency);
+        if recent.samples.len() < ALERT_MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = recent.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let p99 = sorted[(sorted.len() * 99).div_ceil(100) - 1];
+        let exceeded = p99 > self.bound;
+        let newly = exceeded && !recent.exceeded;
+        if recent.exceeded && !exceeded {
+            info!(
+                "【封禁延迟】最近封禁的 P99 延迟 {} 毫秒，已恢复到上限以内",
+                p99.as_millis()
+            );
+        }
+        recent.exceeded = exceeded;
+        newly.then_some(p99)
+    }
+}
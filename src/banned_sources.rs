@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 一个来源自上次输出日志以来被跳过的数据包
+struct Suppressed {
+    since: Instant,
+    hits: u64,
+}
+
+/// 已封禁来源的日志限流
+///
+/// 封禁后扫描器的数据包仍会被抓到（抓包发生在防火墙之前，规则顺序有误时还会漏到 PBX），
+/// 每个数据包都重新判定并输出"已被封禁"的日志会淹没有用的信息。流水线对封禁表中的来源
+/// 只计数、不判定，每个来源每个间隔最多输出一行日志，并借此复核一次防火墙规则仍然存在。
+pub struct BannedSources {
+    interval: Duration,
+    entries: HashMap<IpAddr, Suppressed>,
+}
+
+impl BannedSources {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// 记录一次跳过；需要输出日志时返回本间隔内跳过的数据包数（含本次）并开始新的间隔
+    pub fn hit(&mut self, ip: IpAddr) -> Option<u64> {
+        let now = Instant::now();
+        let entry = self.entries.entry(ip).or_insert(Suppressed {
+            since: now,
+            hits: 0,
+        });
+        entry.hits += 1;
+        if entry.hits > 1 && now.duration_since(entry.since) < self.interval {
+            return None;
+        }
+        let hits = entry.hits;
+        entry.since = now;
+        entry.hits = 0;
+        Some(hits)
+    }
+
+    /// 来源已不在封禁中
+    pub fn forget(&mut self, ip: &IpAddr) {
+        self.entries.remove(ip);
+    }
+
+    /// 清理间隔已结束的来源（之后再有数据包时重新开始计数并输出日志）
+    pub fn cleanup(&mut self) {
+        let interval = self.interval;
+        self.entries
+            .retain(|_, entry| entry.since.elapsed() < interval);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
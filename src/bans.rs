@@ -0,0 +1,271 @@
+use crate::detection::Detection;
+use crate::geoip::GeoInfo;
+use crate::reason::ReasonCode;
+use crate::state_file::unix_now;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// 封禁的原因：由哪里、因为什么封禁
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanReason {
+    /// 机器可读的原因代码，如 `UA_NOT_ALLOWED`、`MANUAL`、`CROWDSEC`
+    pub reason: String,
+    /// 原因分类（旧版本保存的记录没有该字段，恢复时按原因代码推断）
+    #[serde(default)]
+    pub code: ReasonCode,
+    /// 发起封禁的一方：`engine`、`API`、`gRPC`、集群节点等
+    pub origin: String,
+    /// 触发封禁的 User-Agent（本地检测时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// 触发封禁的 SIP 方法（本地检测时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// 命中的检测规则、插件、脚本说明、CrowdSec 场景或外部调用方的备注
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// 到期时间（Unix 秒），None 表示不会自动解封
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// 触发封禁的请求所访问的租户
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl BanReason {
+    pub fn new(reason: &str, origin: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+            code: ReasonCode::classify(reason),
+            origin: origin.to_string(),
+            user_agent: None,
+            method: None,
+            rule: None,
+            expires_at: None,
+            tenant: None,
+        }
+    }
+
+    /// 本地检测触发的封禁
+    pub fn from_detection(detection: &Detection) -> Self {
+        Self {
+            reason: detection.reason.clone(),
+            code: detection.code,
+            origin: "engine".to_string(),
+            user_agent: Some(detection.user_agent.clone()).filter(|ua| !ua.is_empty()),
+            method: Some(detection.method.clone()).filter(|m| m != "-"),
+            rule: detection.rule.clone(),
+            expires_at: None,
+            tenant: detection.tenant.clone(),
+        }
+    }
+
+    pub fn with_rule(mut self, rule: &str) -> Self {
+        self.rule = Some(rule.to_string()).filter(|r| !r.is_empty());
+        self
+    }
+
+    /// 设置到期时间（Unix 秒），0 表示不会自动解封
+    pub fn expires_at(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at).filter(|&t| t != 0);
+        self
+    }
+}
+
+/// 一条当前生效的封禁
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    pub ip: IpAddr,
+    #[serde(flatten)]
+    pub reason: BanReason,
+    /// 封禁时间（Unix 秒）；本次运行之前就存在的防火墙规则为 None
+    pub banned_at: Option<u64>,
+    /// 封禁以来该来源继续发来的请求/检测次数
+    pub hits: u64,
+    /// 最近一次命中的时间（Unix 秒）
+    pub last_hit: Option<u64>,
+    /// 来源的国家、城市和 ASN（启用 GeoIP 时在列出封禁时补充）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoInfo>,
+}
+
+impl BanRecord {
+    /// 本次运行之前就存在、原因未知的封禁
+    pub fn unknown(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            reason: BanReason::new("UNKNOWN", "firewall"),
+            banned_at: None,
+            hits: 0,
+            last_hit: None,
+            geo: None,
+        }
+    }
+
+    /// 距离到期的剩余秒数，None 表示不会自动解封
+    pub fn remaining_secs(&self, now: u64) -> Option<u64> {
+        self.reason
+            .expires_at
+            .map(|expires_at| expires_at.saturating_sub(now))
+    }
+}
+
+/// 封禁记录的持久化存储（如 SQLite），封禁表每次增删记录时同步写入
+pub trait BanStore: Send + Sync {
+    /// 写入（或覆盖）一条记录
+    fn upsert(&self, record: &BanRecord) -> Result<(), String>;
+    /// 删除一条记录
+    fn remove(&self, ip: &IpAddr) -> Result<(), String>;
+    /// 用当前全部记录替换存储中的内容（同步命中次数，并修正之前写入失败的记录）
+    fn replace_all(&self, records: &[BanRecord]) -> Result<(), String>;
+}
+
+/// 封禁原因表：记录每个封禁 IP 的原因和命中次数
+///
+/// 防火墙只保存 IP，原因等信息保存在内存中（配置状态文件时随检测状态一起保存，配置
+/// [`BanStore`] 时每次增删同步写入）；没有记录的已有规则显示为 `UNKNOWN`。
+#[derive(Default)]
+pub struct BanTable {
+    records: Mutex<HashMap<IpAddr, BanRecord>>,
+    store: OnceLock<Arc<dyn BanStore>>,
+}
+
+impl BanTable {
+    /// 设置持久化存储，之后的增删同步写入；只能设置一次
+    pub fn set_store(&self, store: Arc<dyn BanStore>) -> Result<(), String> {
+        self.store
+            .set(store)
+            .map_err(|_| "封禁存储已设置".to_string())
+    }
+
+    /// 是否设置了持久化存储
+    pub fn has_store(&self) -> bool {
+        self.store.get().is_some()
+    }
+
+    /// 把全部记录写入持久化存储（未设置存储时不做任何事）
+    pub fn sync_store(&self) {
+        self.persist(|store| store.replace_all(&self.records()));
+    }
+
+    fn persist(&self, write: impl FnOnce(&dyn BanStore) -> Result<(), String>) {
+        if let Some(store) = self.store.get() {
+            if let Err(e) = write(store.as_ref()) {
+                error!("【封禁存储】{}", e);
+            }
+        }
+    }
+
+    /// 记录一次封禁（覆盖该 IP 之前的记录）
+    pub fn insert(&self, ip: IpAddr, reason: BanReason) {
+        let record = BanRecord {
+            ip,
+            reason,
+            banned_at: Some(unix_now()),
+            hits: 0,
+            last_hit: None,
+            geo: None,
+        };
+        self.persist(|store| store.upsert(&record));
+        self.records.lock().unwrap().insert(ip, record);
+    }
+
+    pub fn remove(&self, ip: &IpAddr) -> Option<BanRecord> {
+        let removed = self.records.lock().unwrap().remove(ip);
+        if removed.is_some() {
+            self.persist(|store| store.remove(ip));
+        }
+        removed
+    }
+
+    /// 已封禁的来源又发来了请求
+    pub fn hit(&self, ip: &IpAddr) {
+        if let Some(record) = self.records.lock().unwrap().get_mut(ip) {
+            record.hits += 1;
+            record.last_hit = Some(unix_now());
+        }
+    }
+
+    pub fn get(&self, ip: &IpAddr) -> Option<BanRecord> {
+        self.records.lock().unwrap().get(ip).cloned()
+    }
+
+    /// 该 IP 有封禁记录且满足条件（不复制记录）
+    pub fn matches(&self, ip: &IpAddr, predicate: impl FnOnce(&BanRecord) -> bool) -> bool {
+        self.records.lock().unwrap().get(ip).is_some_and(predicate)
+    }
+
+    /// 当前全部记录（保存到状态文件，不核对防火墙）
+    pub fn records(&self) -> Vec<BanRecord> {
+        let mut records: Vec<BanRecord> = self.records.lock().unwrap().values().cloned().collect();
+        records.sort_by_key(|record| record.ip);
+        records
+    }
+
+    /// 恢复状态文件中保存的记录（不覆盖本次运行中已有的记录）
+    pub fn restore(&self, records: &[BanRecord]) {
+        let mut table = self.records.lock().unwrap();
+        for record in records {
+            table.entry(record.ip).or_insert_with(|| {
+                let mut record = record.clone();
+                if record.reason.code == ReasonCode::Unknown {
+                    record.reason.code = ReasonCode::classify(&record.reason.reason);
+                }
+                record
+            });
+        }
+    }
+
+    /// 按防火墙中实际存在的封禁生成列表，并丢弃已不存在的记录
+    pub fn reconcile(&self, blocked: &[IpAddr]) -> Vec<BanRecord> {
+        let present: HashSet<&IpAddr> = blocked.iter().collect();
+        let mut records = self.records.lock().unwrap();
+        let stale: Vec<IpAddr> = records
+            .keys()
+            .filter(|ip| !present.contains(ip))
+            .copied()
+            .collect();
+        for ip in &stale {
+            records.remove(ip);
+            self.persist(|store| store.remove(ip));
+        }
+        blocked
+            .iter()
+            .map(|ip| {
+                records
+                    .get(ip)
+                    .cloned()
+                    .unwrap_or_else(|| BanRecord::unknown(*ip))
+            })
+            .collect()
+    }
+}
+
+/// 解析时长（如 `7d`、`4h`、`30m`、`1h30m`），支持 d/h/m/s 单位
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: u64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'd' => n * 86400,
+            'h' => n * 3600,
+            'm' => n * 60,
+            's' => n,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
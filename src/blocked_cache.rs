@@ -0,0 +1,186 @@
+use crate::bans::parse_duration;
+use crate::firewall::FirewallBackend;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 默认按实际规则刷新缓存的间隔
+const DEFAULT_REFRESH: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct State {
+    /// 已封禁的 IP，第一次列出规则之前为 None
+    blocked: Option<HashSet<IpAddr>>,
+    /// 正在列出规则
+    refreshing: bool,
+    /// 列出规则期间本进程的封禁（true）和解封（false），列出完成后重新应用
+    changes: Vec<(IpAddr, bool)>,
+}
+
+/// 封禁 IP 的内存缓存
+///
+/// 检查 IP 是否已封禁只查内存中的集合，数据包处理路径上不再执行任何防火墙命令。集合在创建时
+/// 按防火墙的实际规则装载，由本工具的封禁/解封同步更新，并定期（以及每次列出规则，如启动核对和
+/// 规则核查时）按实际规则刷新，其他工具对规则的改动在下次刷新时生效。尚未成功列出过规则时
+/// 直接查询防火墙。
+#[derive(Clone)]
+pub struct BlockedCache {
+    inner: Arc<dyn FirewallBackend>,
+    state: Arc<Mutex<State>>,
+}
+
+impl BlockedCache {
+    /// 创建缓存并按实际规则装载，列出失败时只告警（之后的检查直接查询防火墙，直到刷新成功）
+    pub fn new(inner: Box<dyn FirewallBackend>) -> Self {
+        let cache = Self {
+            inner: Arc::from(inner),
+            state: Arc::new(Mutex::new(State::default())),
+        };
+        if let Err(e) = cache.refresh() {
+            warn!("【封禁缓存】装载失败，暂时直接查询防火墙: {}", e);
+        }
+        cache
+    }
+
+    /// 按 `UABLOCK_BLOCKED_CACHE_REFRESH` 为后端加上缓存并启动定期刷新，设为 0 时原样返回
+    pub fn wrap_from_env(
+        inner: Box<dyn FirewallBackend>,
+    ) -> Result<Box<dyn FirewallBackend>, String> {
+        let interval = match std::env::var("UABLOCK_BLOCKED_CACHE_REFRESH") {
+            Ok(value) if value == "0" => return Ok(inner),
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|interval| !interval.is_zero())
+                .ok_or_else(|| format!("UABLOCK_BLOCKED_CACHE_REFRESH 无效: {}", value))?,
+            _ => DEFAULT_REFRESH,
+        };
+        let cache = Self::new(inner);
+        cache.start(interval)?;
+        info!("封禁缓存已启用: 每 {} 秒按实际规则刷新", interval.as_secs());
+        Ok(Box::new(cache))
+    }
+
+    /// 启动定期刷新线程
+    pub fn start(&self, interval: Duration) -> Result<(), String> {
+        let cache = self.clone();
+        std::thread::Builder::new()
+            .name("blocked-cache".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                if let Err(e) = cache.refresh() {
+                    warn!("【封禁缓存】刷新失败: {}", e);
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("无法启动封禁缓存刷新线程: {}", e))
+    }
+
+    /// 按防火墙的实际规则刷新，返回封禁数
+    pub fn refresh(&self) -> Result<usize, String> {
+        self.list().map(|ips| ips.len())
+    }
+
+    /// 缓存是否已装载
+    pub fn is_loaded(&self) -> bool {
+        self.state.lock().unwrap().blocked.is_some()
+    }
+
+    /// 列出实际规则并替换缓存，列出期间的封禁/解封在替换后重新应用
+    fn list(&self) -> Result<Vec<IpAddr>, String> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.refreshing = true;
+            state.changes.clear();
+        }
+        let listed = self.inner.list_blocked();
+        let mut state = self.state.lock().unwrap();
+        state.refreshing = false;
+        let changes = std::mem::take(&mut state.changes);
+        let ips = listed?;
+        let mut blocked: HashSet<IpAddr> = ips.iter().copied().collect();
+        for (ip, banned) in changes {
+            if banned {
+                blocked.insert(ip);
+            } else {
+                blocked.remove(&ip);
+            }
+        }
+        debug!("【封禁缓存】已按实际规则刷新，{} 个封禁", blocked.len());
+        state.blocked = Some(blocked);
+        Ok(ips)
+    }
+
+    fn remember(&self, ip: &IpAddr, banned: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.refreshing {
+            state.changes.push((*ip, banned));
+        }
+        if let Some(blocked) = state.blocked.as_mut() {
+            if banned {
+                blocked.insert(*ip);
+            } else {
+                blocked.remove(ip);
+            }
+        }
+    }
+}
+
+impl FirewallBackend for BlockedCache {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.inner.block_ip(ip)?;
+        self.remember(ip, true);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.inner.unblock_ip(ip)?;
+        self.remember(ip, false);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        if let Some(blocked) = self.state.lock().unwrap().blocked.as_ref() {
+            return blocked.contains(ip);
+        }
+        self.inner.is_blocked(ip)
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        self.list()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryFirewall;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn blocked_cache_answers_from_memory_until_refreshed() {
+        let firewall = MemoryFirewall::new();
+        firewall.block_ip(&ip("198.51.100.7")).unwrap();
+        let cache = BlockedCache::new(Box::new(firewall.clone()));
+        assert!(cache.is_loaded());
+        assert!(cache.is_blocked(&ip("198.51.100.7")));
+
+        cache.block_ip(&ip(SCANNER)).unwrap();
+        assert!(cache.is_blocked(&ip(SCANNER)));
+        // 绕过本工具删除的规则在刷新后才反映到缓存中
+        firewall.unblock_ip(&ip(SCANNER)).unwrap();
+        assert!(cache.is_blocked(&ip(SCANNER)));
+        assert_eq!(cache.refresh(), Ok(1));
+        assert!(!cache.is_blocked(&ip(SCANNER)));
+
+        // 封禁失败时不记入缓存
+        firewall.fail_with(Some("iptables: Resource temporarily unavailable"));
+        assert!(cache.block_ip(&ip("192.0.2.44")).is_err());
+        assert!(!cache.is_blocked(&ip("192.0.2.44")));
+    }
+}
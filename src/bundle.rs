@@ -0,0 +1,181 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// 签名文件的后缀：`rules.toml` 的签名为 `rules.toml.sig`
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// 规则包的签名公钥
+///
+/// 总部用私钥为规则文件（和配置文件）生成分离的 ed25519 签名（base64，写在同名的 `.sig` 文件中），
+/// 各 SBC 配置总部的公钥后只应用签名有效的文件：没有签名或内容被改动过的文件被拒绝，
+/// 不会回退到未签名的内容。
+pub struct BundleVerifier {
+    key: VerifyingKey,
+}
+
+/// 通过校验的规则包
+#[derive(Debug, Clone)]
+pub struct VerifiedBundle {
+    /// 文件内容（即被签名的字节，之后只使用这份内容，不再重新读取文件）
+    pub content: String,
+    /// 内容的 SHA-256（十六进制）
+    pub sha256: String,
+}
+
+impl BundleVerifier {
+    pub fn new(key: VerifyingKey) -> Self {
+        Self { key }
+    }
+
+    /// 解析 base64 编码的 32 字节公钥
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bytes: [u8; 32] = decode(text)?
+            .try_into()
+            .map_err(|_| "公钥应为 32 字节".to_string())?;
+        VerifyingKey::from_bytes(&bytes)
+            .map(Self::new)
+            .map_err(|e| format!("公钥无效: {}", e))
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_BUNDLE_PUBKEY` 时返回 None
+    ///
+    /// `UABLOCK_BUNDLE_PUBKEY` 为公钥文件的路径，或直接为 base64 编码的公钥。
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let value = match std::env::var("UABLOCK_BUNDLE_PUBKEY") {
+            Ok(value) if !value.is_empty() => value,
+            _ => return Ok(None),
+        };
+        let text = if Path::new(&value).is_file() {
+            std::fs::read_to_string(&value)
+                .map_err(|e| format!("无法读取公钥文件 {}: {}", value, e))?
+        } else {
+            value
+        };
+        Self::parse(&text)
+            .map(Some)
+            .map_err(|e| format!("UABLOCK_BUNDLE_PUBKEY {}", e))
+    }
+
+    /// 校验内容和 base64 编码的签名
+    pub fn verify(&self, content: &[u8], signature: &str) -> Result<(), String> {
+        let bytes: [u8; 64] = decode(signature)?
+            .try_into()
+            .map_err(|_| "签名应为 64 字节".to_string())?;
+        self.key
+            .verify(content, &Signature::from_bytes(&bytes))
+            .map_err(|_| "签名与内容不符（文件被改动过，或不是用对应的私钥签名的）".to_string())
+    }
+
+    /// 读取文件及其 `.sig` 签名并校验，返回通过校验的内容
+    pub fn verify_file(&self, path: &Path) -> Result<VerifiedBundle, String> {
+        let content =
+            std::fs::read(path).map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+        let signature_path = signature_path(path);
+        let signature = std::fs::read_to_string(&signature_path).map_err(|e| {
+            format!(
+                "{} 没有签名（无法读取 {}: {}），拒绝应用",
+                path.display(),
+                signature_path.display(),
+                e
+            )
+        })?;
+        self.verify(&content, &signature)
+            .map_err(|e| format!("{} 签名校验失败: {}，拒绝应用", path.display(), e))?;
+        let content = String::from_utf8(content)
+            .map_err(|_| format!("{} 不是 UTF-8 文本", path.display()))?;
+        let sha256 = sha256_hex(content.as_bytes());
+        info!(
+            "【规则包】{} 签名有效（SHA-256 {}）",
+            path.display(),
+            &sha256[..16]
+        );
+        Ok(VerifiedBundle { content, sha256 })
+    }
+}
+
+/// 文件的签名路径：原路径加 `.sig` 后缀
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(SIGNATURE_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// 由 32 字节种子生成签名密钥，返回 base64 编码的（私钥, 公钥）
+pub fn keypair(seed: [u8; 32]) -> (String, String) {
+    let key = SigningKey::from_bytes(&seed);
+    (
+        STANDARD.encode(key.to_bytes()),
+        STANDARD.encode(key.verifying_key().to_bytes()),
+    )
+}
+
+/// 用 base64 编码的私钥为内容签名，返回 base64 编码的签名
+pub fn sign(private_key: &str, content: &[u8]) -> Result<String, String> {
+    let bytes: [u8; 32] = decode(private_key)?
+        .try_into()
+        .map_err(|_| "私钥应为 32 字节".to_string())?;
+    let signature = SigningKey::from_bytes(&bytes).sign(content);
+    Ok(STANDARD.encode(signature.to_bytes()))
+}
+
+fn decode(text: &str) -> Result<Vec<u8>, String> {
+    STANDARD
+        .decode(text.trim())
+        .map_err(|e| format!("不是有效的 base64: {}", e))
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RulesEngine;
+    use std::path::Path;
+
+    #[test]
+    fn signed_rule_bundles_are_verified_before_loading() {
+        let (private_key, public_key) = keypair([7; 32]);
+        let verifier = BundleVerifier::parse(&public_key).unwrap();
+        let path = std::env::temp_dir().join(format!("uablock-bundle-{}.toml", std::process::id()));
+        let rules = "version = \"2026.10.1\"\n\n[[rule]]\nname = \"known-scanner\"\nuser_agent = \"sipvicious\"\naction = \"block\"\n";
+        std::fs::write(&path, rules).unwrap();
+
+        // 没有签名的文件被拒绝
+        assert!(verifier
+            .verify_file(&path)
+            .is_err_and(|e| e.contains("没有签名")));
+
+        std::fs::write(
+            signature_path(&path),
+            sign(&private_key, rules.as_bytes()).unwrap(),
+        )
+        .unwrap();
+        let bundle = verifier.verify_file(&path).unwrap();
+        let engine = RulesEngine::load_content(Path::new("rules.toml"), &bundle.content).unwrap();
+        assert_eq!(engine.version(), Some("2026.10.1"));
+
+        // 签名后被改动的文件、其他私钥签名的文件都被拒绝
+        std::fs::write(&path, rules.replace("sipvicious", "sipviciousX")).unwrap();
+        assert!(verifier
+            .verify_file(&path)
+            .is_err_and(|e| e.contains("签名校验失败")));
+        let (_, other_key) = keypair([8; 32]);
+        std::fs::write(&path, rules).unwrap();
+        assert!(BundleVerifier::parse(&other_key)
+            .unwrap()
+            .verify_file(&path)
+            .is_err());
+
+        std::fs::remove_file(signature_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
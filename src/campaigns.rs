@@ -0,0 +1,286 @@
+use crate::bans::parse_duration;
+use crate::detection::Detection;
+use crate::local_net::Subnet;
+use crate::state_file::unix_now;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 默认的归并时间窗口
+const DEFAULT_WINDOW: Duration = Duration::from_secs(3600);
+
+/// 每个活动最多记录的目标分机数
+const MAX_TARGETS: usize = 1000;
+
+/// 报告中最多列出的 User-Agent / 目标数
+const LIST_LIMIT: usize = 3;
+
+/// 一次扫描活动：时间上相邻、特征相同的一组检测
+#[derive(Debug, Clone, Serialize)]
+pub struct Campaign {
+    pub id: u64,
+    /// 首次和最近一次检测的时间（Unix 秒）
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub user_agents: BTreeSet<String>,
+    pub ips: BTreeSet<IpAddr>,
+    /// To 头中的分机号（最多记录 1000 个）
+    pub targets: BTreeSet<String>,
+    pub methods: BTreeSet<String>,
+    pub reasons: BTreeSet<String>,
+    pub detections: u64,
+    /// 来源所在的网段（IPv4 /24、IPv6 /64）
+    #[serde(skip)]
+    networks: Vec<Subnet>,
+}
+
+impl Campaign {
+    fn new(id: u64, now: u64) -> Self {
+        Self {
+            id,
+            first_seen: now,
+            last_seen: now,
+            user_agents: BTreeSet::new(),
+            ips: BTreeSet::new(),
+            targets: BTreeSet::new(),
+            methods: BTreeSet::new(),
+            reasons: BTreeSet::new(),
+            detections: 0,
+            networks: Vec::new(),
+        }
+    }
+
+    /// 检测是否属于本活动：同一 UA、相邻 IP（同一网段），或双方都没有 UA 时目标分机号形态相同
+    fn matches(&self, detection: &Detection, target: Option<&str>) -> bool {
+        let network = network(detection.source_ip);
+        if self.networks.contains(&network) {
+            return true;
+        }
+        match user_agent(detection) {
+            Some(ua) => self.user_agents.contains(ua),
+            None => {
+                self.user_agents.is_empty()
+                    && target.is_some_and(|target| {
+                        let wanted = shape(target);
+                        self.targets.iter().any(|known| shape(known) == wanted)
+                    })
+            }
+        }
+    }
+
+    fn add(&mut self, detection: &Detection, target: Option<&str>, now: u64) {
+        self.last_seen = now;
+        self.detections += 1;
+        if let Some(ua) = user_agent(detection) {
+            self.user_agents.insert(ua.to_string());
+        }
+        if self.ips.insert(detection.source_ip) {
+            let network = network(detection.source_ip);
+            if !self.networks.contains(&network) {
+                self.networks.push(network);
+            }
+        }
+        if let Some(target) = target {
+            if self.targets.len() < MAX_TARGETS {
+                self.targets.insert(target.to_string());
+            }
+        }
+        self.methods.insert(detection.method.clone());
+        self.reasons.insert(detection.reason.clone());
+    }
+
+    /// 目标分机的概括：全部为数字时给出范围（如 `100-199`），否则列出前几个
+    pub fn target_range(&self) -> Option<String> {
+        let numbers: Option<Vec<u64>> = self
+            .targets
+            .iter()
+            .map(|target| {
+                target
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+                    .then(|| target.parse().ok())
+                    .flatten()
+            })
+            .collect();
+        match numbers {
+            _ if self.targets.is_empty() => None,
+            Some(numbers) if numbers.len() > 1 => Some(format!(
+                "{}-{}",
+                numbers.iter().min().unwrap(),
+                numbers.iter().max().unwrap()
+            )),
+            _ => Some(list(&self.targets)),
+        }
+    }
+
+    /// 报告中的一行，如 `活动 #42: 37 个 IP, UA sipcli, 目标分机 100-199, 512 次检测`
+    pub fn summary(&self) -> String {
+        let mut line = format!("活动 #{}: {} 个 IP", self.id, self.ips.len());
+        if !self.user_agents.is_empty() {
+            line.push_str(&format!(", UA {}", list(&self.user_agents)));
+        }
+        if let Some(range) = self.target_range() {
+            line.push_str(&format!(", 目标分机 {}", range));
+        }
+        line.push_str(&format!(
+            ", {} 次检测（{}）",
+            self.detections,
+            list(&self.reasons)
+        ));
+        line
+    }
+}
+
+struct State {
+    next_id: u64,
+    campaigns: Vec<Campaign>,
+    last_report: Instant,
+}
+
+/// 扫描活动归并
+///
+/// 把检测按特征（同一 UA、相邻 IP、相同的分机号形态）和时间（最近一次检测在窗口内）归并为带编号的
+/// 活动，定期报告涉及多个 IP 的活动，代替成百上千行互不相关的封禁日志。只影响报告，不影响封禁。
+pub struct Campaigns {
+    window: Duration,
+    report_interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Campaigns {
+    pub fn new(window: Duration, report_interval: Duration) -> Self {
+        Self {
+            window,
+            report_interval,
+            state: Mutex::new(State {
+                next_id: 1,
+                campaigns: Vec::new(),
+                last_report: Instant::now(),
+            }),
+        }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_CAMPAIGN_REPORT` 时返回 None
+    ///
+    /// - `UABLOCK_CAMPAIGN_REPORT`：报告间隔（支持 `d`/`h`/`m`/`s`，如 `24h`）
+    /// - `UABLOCK_CAMPAIGN_WINDOW`：同一活动相邻两次检测的最大间隔（默认 1h）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let report_interval = match std::env::var("UABLOCK_CAMPAIGN_REPORT") {
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|interval| !interval.is_zero())
+                .ok_or_else(|| format!("UABLOCK_CAMPAIGN_REPORT 无效: {}", value))?,
+            _ => return Ok(None),
+        };
+        let window = match std::env::var("UABLOCK_CAMPAIGN_WINDOW") {
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|window| !window.is_zero())
+                .ok_or_else(|| format!("UABLOCK_CAMPAIGN_WINDOW 无效: {}", value))?,
+            _ => DEFAULT_WINDOW,
+        };
+        info!(
+            "扫描活动归并已启用: 窗口 {} 秒，每 {} 秒报告一次",
+            window.as_secs(),
+            report_interval.as_secs()
+        );
+        Ok(Some(Self::new(window, report_interval)))
+    }
+
+    /// 记录一次检测，返回所属活动的编号
+    ///
+    /// `target` 为 To 头中的分机号（来自已解析的 SIP 请求）。
+    pub fn observe(&self, detection: &Detection, target: Option<&str>) -> u64 {
+        let now = unix_now();
+        let mut state = self.state.lock().unwrap();
+        let window = self.window.as_secs();
+        let index = state.campaigns.iter().rposition(|campaign| {
+            now.saturating_sub(campaign.last_seen) <= window && campaign.matches(detection, target)
+        });
+        let index = index.unwrap_or_else(|| {
+            let id = state.next_id;
+            state.next_id += 1;
+            state.campaigns.push(Campaign::new(id, now));
+            state.campaigns.len() - 1
+        });
+        let campaign = &mut state.campaigns[index];
+        campaign.add(detection, target, now);
+        campaign.id
+    }
+
+    /// 全部已记录的活动（按编号）
+    pub fn campaigns(&self) -> Vec<Campaign> {
+        self.state.lock().unwrap().campaigns.clone()
+    }
+
+    /// 到报告时间时输出报告，返回报告的活动
+    ///
+    /// 只报告本周期内有检测、涉及多个 IP 的活动；报告后丢弃已结束（超出窗口）的活动。
+    pub fn report_if_due(&self) -> Option<Vec<Campaign>> {
+        let mut state = self.state.lock().unwrap();
+        if state.last_report.elapsed() < self.report_interval {
+            return None;
+        }
+        let now = unix_now();
+        let since = now.saturating_sub(state.last_report.elapsed().as_secs());
+        state.last_report = Instant::now();
+        let mut reported: Vec<Campaign> = state
+            .campaigns
+            .iter()
+            .filter(|campaign| campaign.last_seen >= since && campaign.ips.len() > 1)
+            .cloned()
+            .collect();
+        reported.sort_by_key(|campaign| std::cmp::Reverse(campaign.ips.len()));
+        if reported.is_empty() {
+            info!("【攻击活动】本周期没有涉及多个 IP 的扫描活动");
+        } else {
+            info!("【攻击活动】本周期共 {} 个扫描活动", reported.len());
+            for campaign in &reported {
+                info!("【攻击活动】{}", campaign.summary());
+            }
+        }
+        let window = self.window.as_secs();
+        state
+            .campaigns
+            .retain(|campaign| now.saturating_sub(campaign.last_seen) <= window);
+        Some(reported)
+    }
+}
+
+/// 有意义的 User-Agent（空值和解析器的 `Unknown` 占位不算）
+fn user_agent(detection: &Detection) -> Option<&str> {
+    let ua = detection.user_agent.trim();
+    (!ua.is_empty() && ua != "Unknown").then_some(ua)
+}
+
+/// 来源所在的网段：IPv4 /24、IPv6 /64
+fn network(ip: IpAddr) -> Subnet {
+    match ip {
+        IpAddr::V4(_) => Subnet::new(ip, 24),
+        IpAddr::V6(_) => Subnet::new(ip, 64),
+    }
+}
+
+/// 分机号的形态：数字替换为 `9`，字母统一小写，如 `1001` → `9999`、`Admin1` → `admin9`
+fn shape(target: &str) -> String {
+    target
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                '9'
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+fn list(items: &BTreeSet<String>) -> String {
+    let listed: Vec<&str> = items.iter().take(LIST_LIMIT).map(String::as_str).collect();
+    if items.len() > LIST_LIMIT {
+        format!("{} 等 {} 个", listed.join("/"), items.len())
+    } else {
+        listed.join("/")
+    }
+}
@@ -0,0 +1,511 @@
+use crate::bans::BanReason;
+use crate::enforcement::Enforcer;
+use crate::events::{EventKind, QueueReceiver, QueueSender};
+use crate::node::node_id;
+use crate::stats::Stats;
+use crate::wire;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 心跳间隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 超过该时间未收到任何帧则认为连接已断开
+const READ_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// 写入和建立连接的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 代理断线后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 中央服务器自身下发的封禁使用的事件来源，避免重复广播
+const CENTRAL_ORIGIN: &str = "central";
+
+/// 代理发往中央服务器的消息
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AgentMessage {
+    /// 连接后的第一条消息，声明代理 ID
+    Hello {
+        agent: String,
+    },
+    /// 代理本地检测到的可疑来源
+    Detection {
+        ip: IpAddr,
+        user_agent: String,
+        reason: String,
+    },
+    Heartbeat,
+}
+
+/// 中央服务器下发给代理的命令
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerCommand {
+    Ban { ip: IpAddr, reason: String },
+    Unban { ip: IpAddr, reason: String },
+    Heartbeat,
+}
+
+/// 从环境变量读取共享密钥
+fn read_key() -> Result<Arc<Vec<u8>>, String> {
+    match std::env::var("UABLOCK_CENTRAL_KEY") {
+        Ok(key) if !key.is_empty() => Ok(Arc::new(key.into_bytes())),
+        _ => Err("启用中央服务器/代理模式时必须设置 UABLOCK_CENTRAL_KEY".to_string()),
+    }
+}
+
+/// 代理模式：只负责捕获和解析，把检测结果转发给中央服务器，并执行其下发的封禁
+pub struct CentralAgent {
+    server: String,
+    key: Arc<Vec<u8>>,
+    agent: String,
+}
+
+impl CentralAgent {
+    /// 从环境变量创建，未设置 `UABLOCK_CENTRAL_SERVER` 时返回 None
+    ///
+    /// - `UABLOCK_CENTRAL_SERVER`：中央服务器地址（`host:port`）
+    /// - `UABLOCK_CENTRAL_KEY`：共享密钥（必填）
+    /// - `UABLOCK_NODE_ID`：代理 ID，默认使用主机名
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let server = match std::env::var("UABLOCK_CENTRAL_SERVER") {
+            Ok(server) if !server.is_empty() => server,
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            server,
+            key: read_key()?,
+            agent: node_id(),
+        }))
+    }
+
+    /// 订阅本地检测事件并启动与中央服务器的连接线程
+    pub fn start(self, enforcer: Arc<Enforcer>) -> Result<(), String> {
+        let (tx, rx) = enforcer.events().queue();
+        enforcer.events().subscribe(move |event| {
+            if event.kind != EventKind::Detection {
+                return true;
+            }
+            tx.send(AgentMessage::Detection {
+                ip: event.ip,
+                user_agent: event.user_agent.clone(),
+                reason: event.reason.clone(),
+            })
+        });
+
+        info!(
+            "【中央代理】代理 {} 将检测结果转发至中央服务器 {}",
+            self.agent, self.server
+        );
+        std::thread::Builder::new()
+            .name("central-agent".to_string())
+            .spawn(move || self.run(&enforcer, rx))
+            .map_err(|e| format!("启动中央代理线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 保持与中央服务器的连接，断线后自动重连
+    fn run(self, enforcer: &Arc<Enforcer>, rx: QueueReceiver<AgentMessage>) {
+        loop {
+            match self.session(enforcer, &rx) {
+                Ok(()) => return,
+                Err(e) => warn!(
+                    "【中央代理】与中央服务器的连接中断: {}，{} 秒后重连",
+                    e,
+                    RECONNECT_DELAY.as_secs()
+                ),
+            }
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    /// 一次连接会话：读线程执行下发的命令，当前线程发送检测结果和心跳
+    fn session(
+        &self,
+        enforcer: &Arc<Enforcer>,
+        rx: &QueueReceiver<AgentMessage>,
+    ) -> Result<(), String> {
+        let mut stream = wire::connect(&self.server, IO_TIMEOUT)?;
+        let hello = AgentMessage::Hello {
+            agent: self.agent.clone(),
+        };
+        stream
+            .write_all(&wire::encode(&hello, &self.key)?)
+            .map_err(|e| e.to_string())?;
+        info!("【中央代理】已连接中央服务器 {}", self.server);
+
+        let alive = Arc::new(AtomicBool::new(true));
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+        {
+            let alive = alive.clone();
+            let key = self.key.clone();
+            let enforcer = enforcer.clone();
+            std::thread::Builder::new()
+                .name("central-agent-recv".to_string())
+                .spawn(move || {
+                    if let Err(e) = receive_commands(reader, &key, &enforcer) {
+                        debug!("【中央代理】接收命令结束: {}", e);
+                    }
+                    alive.store(false, Ordering::Relaxed);
+                })
+                .map_err(|e| e.to_string())?;
+        }
+
+        let mut last_sent = Instant::now();
+        while alive.load(Ordering::Relaxed) {
+            let message = match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) if last_sent.elapsed() >= HEARTBEAT_INTERVAL => {
+                    AgentMessage::Heartbeat
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+            let encoded = wire::encode(&message, &self.key)?;
+            if let Err(e) = stream.write_all(&encoded) {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Err(e.to_string());
+            }
+            last_sent = Instant::now();
+        }
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        Err("中央服务器关闭了连接".to_string())
+    }
+}
+
+/// 读取并执行中央服务器下发的命令
+fn receive_commands(mut stream: TcpStream, key: &[u8], enforcer: &Enforcer) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    loop {
+        let result = match wire::read::<ServerCommand>(&mut stream, key)? {
+            ServerCommand::Ban { ip, reason } => {
+                enforcer.ban(ip, BanReason::new(&reason, CENTRAL_ORIGIN))
+            }
+            ServerCommand::Unban { ip, reason } => enforcer.unban(ip, &reason, CENTRAL_ORIGIN),
+            ServerCommand::Heartbeat => continue,
+        };
+        if let Err(e) = result {
+            error!("【中央代理】执行中央服务器命令失败: {}", e);
+        }
+    }
+}
+
+/// 已连接的代理
+struct AgentConnection {
+    /// 连接序号，用于区分同一代理的新旧连接
+    id: u64,
+    stream: TcpStream,
+}
+
+/// 中央服务器模式：接收各代理的检测结果，按跨站点策略决定封禁并下发给所有代理
+pub struct CentralServer {
+    listen: SocketAddr,
+    key: Arc<Vec<u8>>,
+    /// 封禁前至少需要多少个不同代理报告同一 IP
+    min_sites: usize,
+    /// 统计报告的时间窗口
+    window: Duration,
+    agents: Mutex<HashMap<String, AgentConnection>>,
+    /// 已下发的封禁：IP -> 原因
+    bans: Mutex<HashMap<IpAddr, String>>,
+    /// 各 IP 被哪些代理报告过：IP -> (代理 ID -> 最近报告时间)
+    reports: Mutex<HashMap<IpAddr, HashMap<String, Instant>>>,
+    next_connection: AtomicU64,
+}
+
+impl CentralServer {
+    /// 从环境变量创建，未设置 `UABLOCK_CENTRAL_LISTEN` 时返回 None
+    ///
+    /// - `UABLOCK_CENTRAL_LISTEN`：监听地址，例如 `0.0.0.0:7947`
+    /// - `UABLOCK_CENTRAL_KEY`：共享密钥（必填）
+    /// - `UABLOCK_CENTRAL_MIN_SITES`：封禁前需要的报告代理数（默认 1）
+    /// - `UABLOCK_CENTRAL_WINDOW`：报告统计窗口（秒，默认 3600）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let listen = match std::env::var("UABLOCK_CENTRAL_LISTEN") {
+            Ok(listen) if !listen.is_empty() => listen,
+            _ => return Ok(None),
+        };
+        let listen = listen
+            .parse()
+            .map_err(|e| format!("UABLOCK_CENTRAL_LISTEN 地址无效 {}: {}", listen, e))?;
+        let min_sites = std::env::var("UABLOCK_CENTRAL_MIN_SITES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1usize)
+            .max(1);
+        let window = std::env::var("UABLOCK_CENTRAL_WINDOW")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        Ok(Some(Self {
+            listen,
+            key: read_key()?,
+            min_sites,
+            window: Duration::from_secs(window),
+            agents: Mutex::new(HashMap::new()),
+            bans: Mutex::new(HashMap::new()),
+            reports: Mutex::new(HashMap::new()),
+            next_connection: AtomicU64::new(1),
+        }))
+    }
+
+    /// 启动监听线程和广播线程
+    ///
+    /// 本机（API、gRPC、本地检测）产生的封禁/解封同样会下发给所有代理。
+    pub fn start(self, enforcer: Arc<Enforcer>) -> Result<(), String> {
+        let listener = TcpListener::bind(self.listen)
+            .map_err(|e| format!("中央服务器无法监听 {}: {}", self.listen, e))?;
+        let server = Arc::new(self);
+
+        let (tx, rx) = enforcer.events().queue();
+        {
+            let server = server.clone();
+            let tx = tx.clone();
+            enforcer.events().subscribe(move |event| {
+                if event.origin == CENTRAL_ORIGIN {
+                    return true;
+                }
+                let command = match event.kind {
+                    EventKind::Ban => ServerCommand::Ban {
+                        ip: event.ip,
+                        reason: event.reason.clone(),
+                    },
+                    EventKind::Unban => ServerCommand::Unban {
+                        ip: event.ip,
+                        reason: event.reason.clone(),
+                    },
+                    EventKind::Detection | EventKind::Alert => return true,
+                };
+                server.record(&command);
+                tx.send(command)
+            });
+        }
+
+        info!(
+            "【中央服务器】监听于 {}（至少 {} 个站点报告后封禁）",
+            server.listen, server.min_sites
+        );
+        {
+            let server = server.clone();
+            std::thread::Builder::new()
+                .name("central-broadcast".to_string())
+                .spawn(move || server.broadcast_loop(rx))
+                .map_err(|e| format!("启动中央服务器广播线程失败: {}", e))?;
+        }
+        std::thread::Builder::new()
+            .name("central-accept".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("【中央服务器】接受连接失败: {}", e);
+                            continue;
+                        }
+                    };
+                    let server = server.clone();
+                    let enforcer = enforcer.clone();
+                    let tx = tx.clone();
+                    let spawned = std::thread::Builder::new()
+                        .name("central-agent-conn".to_string())
+                        .spawn(move || server.handle_agent(stream, &enforcer, &tx));
+                    if let Err(e) = spawned {
+                        error!("【中央服务器】启动代理连接线程失败: {}", e);
+                    }
+                }
+            })
+            .map_err(|e| format!("启动中央服务器监听线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 处理一个代理连接
+    fn handle_agent(
+        &self,
+        mut stream: TcpStream,
+        enforcer: &Enforcer,
+        tx: &QueueSender<ServerCommand>,
+    ) {
+        let peer = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            warn!("【中央服务器】设置读超时失败: {}", e);
+            return;
+        }
+        let agent = match wire::read::<AgentMessage>(&mut stream, &self.key) {
+            Ok(AgentMessage::Hello { agent }) => agent,
+            Ok(_) => {
+                warn!("【中央服务器】{} 未先发送 hello，断开连接", peer);
+                return;
+            }
+            Err(e) => {
+                warn!("【中央服务器】拒绝来自 {} 的连接: {}", peer, e);
+                return;
+            }
+        };
+        let connection_id = match self.register(&agent, &stream) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("【中央服务器】注册代理 {} 失败: {}", agent, e);
+                return;
+            }
+        };
+        info!("【中央服务器】代理 {} 已连接（{}）", agent, peer);
+
+        loop {
+            match wire::read::<AgentMessage>(&mut stream, &self.key) {
+                Ok(AgentMessage::Detection {
+                    ip,
+                    user_agent,
+                    reason,
+                }) => self.report(&agent, ip, &user_agent, &reason, enforcer, tx),
+                Ok(AgentMessage::Heartbeat) => {}
+                Ok(AgentMessage::Hello { .. }) => {
+                    warn!("【中央服务器】代理 {} 重复发送 hello", agent)
+                }
+                Err(e) => {
+                    info!("【中央服务器】代理 {} 断开连接: {}", agent, e);
+                    break;
+                }
+            }
+        }
+
+        let mut agents = self.agents.lock().unwrap();
+        if agents.get(&agent).map(|c| c.id) == Some(connection_id) {
+            agents.remove(&agent);
+        }
+    }
+
+    /// 登记代理连接，并把当前所有封禁同步给它
+    fn register(&self, agent: &str, stream: &TcpStream) -> Result<u64, String> {
+        let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+        writer
+            .set_write_timeout(Some(IO_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let bans: Vec<ServerCommand> = self
+            .bans
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(ip, reason)| ServerCommand::Ban {
+                ip: *ip,
+                reason: reason.clone(),
+            })
+            .collect();
+        for command in &bans {
+            writer
+                .write_all(&wire::encode(command, &self.key)?)
+                .map_err(|e| e.to_string())?;
+        }
+
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        self.agents
+            .lock()
+            .unwrap()
+            .insert(agent.to_string(), AgentConnection { id, stream: writer });
+        Ok(id)
+    }
+
+    /// 汇总代理的检测报告，达到跨站点阈值时封禁并下发
+    fn report(
+        &self,
+        agent: &str,
+        ip: IpAddr,
+        user_agent: &str,
+        reason: &str,
+        enforcer: &Enforcer,
+        tx: &QueueSender<ServerCommand>,
+    ) {
+        Stats::incr(&enforcer.stats().detections);
+        let sites = {
+            let mut reports = self.reports.lock().unwrap();
+            let sites = reports.entry(ip).or_default();
+            sites.insert(agent.to_string(), Instant::now());
+            sites.retain(|_, at| at.elapsed() < self.window);
+            sites.len()
+        };
+        info!(
+            "【中央服务器】代理 {} 报告 IP: {}, User-Agent: '{}', 原因: {}（{} 个站点报告）",
+            agent, ip, user_agent, reason, sites
+        );
+        if sites < self.min_sites || self.bans.lock().unwrap().contains_key(&ip) {
+            return;
+        }
+
+        let command = ServerCommand::Ban {
+            ip,
+            reason: reason.to_string(),
+        };
+        self.record(&command);
+        tx.send(command);
+        if enforcer.firewall().is_some() {
+            if let Err(e) = enforcer.ban(ip, BanReason::new(reason, CENTRAL_ORIGIN)) {
+                error!("【中央服务器】本机封禁 IP {} 失败: {}", ip, e);
+            }
+        }
+    }
+
+    /// 记录已下发的封禁状态
+    fn record(&self, command: &ServerCommand) {
+        let mut bans = self.bans.lock().unwrap();
+        match command {
+            ServerCommand::Ban { ip, reason } => {
+                bans.insert(*ip, reason.clone());
+            }
+            ServerCommand::Unban { ip, .. } => {
+                bans.remove(ip);
+                self.reports.lock().unwrap().remove(ip);
+            }
+            ServerCommand::Heartbeat => {}
+        }
+    }
+
+    /// 把命令广播给所有代理，空闲时发送心跳并清理过期报告
+    fn broadcast_loop(&self, rx: QueueReceiver<ServerCommand>) {
+        loop {
+            let command = match rx.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.reports.lock().unwrap().retain(|_, sites| {
+                        sites.retain(|_, at| at.elapsed() < self.window);
+                        !sites.is_empty()
+                    });
+                    ServerCommand::Heartbeat
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            let encoded = match wire::encode(&command, &self.key) {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    error!("【中央服务器】{}", e);
+                    continue;
+                }
+            };
+            self.agents.lock().unwrap().retain(|agent, connection| {
+                match connection.stream.write_all(&encoded) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("【中央服务器】向代理 {} 下发命令失败: {}", agent, e);
+                        let _ = connection.stream.shutdown(std::net::Shutdown::Both);
+                        false
+                    }
+                }
+            });
+            if !matches!(command, ServerCommand::Heartbeat) {
+                debug!("【中央服务器】已下发: {:?}", command);
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use serde::Deserialize;
+use std::time::Duration;
+use uablock_rust::stats::StatsSnapshot;
+
+/// Nagios 插件退出码
+const OK: i32 = 0;
+const WARNING: i32 = 1;
+const CRITICAL: i32 = 2;
+const UNKNOWN: i32 = 3;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时查询的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+/// `/health` 响应
+#[derive(Debug, Deserialize)]
+struct HealthReport {
+    backend: String,
+    backend_error: Option<String>,
+    active_bans: u64,
+    stats: StatsSnapshot,
+}
+
+/// 告警/严重阈值（未设置时不检查）
+#[derive(Debug, Default)]
+struct Thresholds {
+    warn: Option<u64>,
+    crit: Option<u64>,
+}
+
+impl Thresholds {
+    fn status(&self, value: u64) -> i32 {
+        if self.crit.is_some_and(|c| value >= c) {
+            CRITICAL
+        } else if self.warn.is_some_and(|w| value >= w) {
+            WARNING
+        } else {
+            OK
+        }
+    }
+
+    /// perfdata 中的 `;warn;crit` 部分
+    fn perfdata(&self) -> String {
+        let fmt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        format!(";{};{}", fmt(self.warn), fmt(self.crit))
+    }
+}
+
+/// 检查参数
+#[derive(Debug)]
+struct CheckOptions {
+    url: String,
+    token: String,
+    timeout: Duration,
+    bans: Thresholds,
+    drops: Thresholds,
+}
+
+impl CheckOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            timeout: Duration::from_secs(10),
+            bans: Thresholds::default(),
+            drops: Thresholds::default(),
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))
+            };
+            match flag.as_str() {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--timeout" => options.timeout = Duration::from_secs(number()?),
+                "--warn-bans" => options.bans.warn = Some(number()?),
+                "--crit-bans" => options.bans.crit = Some(number()?),
+                "--warn-drops" => options.drops.warn = Some(number()?),
+                "--crit-drops" => options.drops.crit = Some(number()?),
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock check`：查询运行中的守护进程，按 Nagios 插件规范输出并返回退出码
+///
+/// 封禁后端异常或无法连接守护进程时为 CRITICAL；活动封禁数和抓包丢包数（自启动以来的累计值）
+/// 分别按 `--warn-*`/`--crit-*` 阈值判断。
+pub fn run(args: &[String]) -> i32 {
+    let options = match CheckOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("UABLOCK UNKNOWN - {}", e);
+            return UNKNOWN;
+        }
+    };
+    let report = match fetch(&options) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("UABLOCK CRITICAL - {}", e);
+            return CRITICAL;
+        }
+    };
+
+    let mut status = options
+        .bans
+        .status(report.active_bans)
+        .max(options.drops.status(report.stats.capture_drops));
+    let backend = match (report.backend.as_str(), &report.backend_error) {
+        ("ok" | "disabled" | "standby", _) => report.backend.clone(),
+        (_, error) => {
+            status = CRITICAL;
+            format!("error ({})", error.as_deref().unwrap_or("unknown"))
+        }
+    };
+    let label = match status {
+        OK => "OK",
+        WARNING => "WARNING",
+        _ => "CRITICAL",
+    };
+    println!(
+        "UABLOCK {} - {} active bans, {} capture drops, backend {} | active_bans={}{};0 capture_drops={}c{};0 packets={}c detections={}c bans={}c unbans={}c",
+        label,
+        report.active_bans,
+        report.stats.capture_drops,
+        backend,
+        report.active_bans,
+        options.bans.perfdata(),
+        report.stats.capture_drops,
+        options.drops.perfdata(),
+        report.stats.packets,
+        report.stats.detections,
+        report.stats.bans,
+        report.stats.unbans,
+    );
+    status
+}
+
+fn fetch(options: &CheckOptions) -> Result<HealthReport, String> {
+    let url = format!("{}/health", options.url);
+    ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .build()
+        .get(&url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| format!("无法查询 {}: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))
+}
@@ -0,0 +1,244 @@
+use crate::bans::BanReason;
+use crate::enforcement::Enforcer;
+use crate::events::{Event, EventKind};
+use crate::node::node_id;
+use crate::state_file::unix_now;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// 集群同步事件来源前缀，带此前缀的事件不会再次广播（防止回环）
+const CLUSTER_ORIGIN_PREFIX: &str = "cluster:";
+
+/// 集群消息动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterAction {
+    Ban,
+    Unban,
+}
+
+/// 节点之间交换的封禁/解封消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterMessage {
+    /// 发送方节点 ID
+    pub node: String,
+    pub action: ClusterAction,
+    pub ip: IpAddr,
+    /// 机器可读的原因代码
+    pub reason: String,
+    /// 封禁到期时间（Unix 秒），0 表示永久
+    pub expires_at: u64,
+}
+
+/// 节点全量封禁摘要，用于定期反熵（修复丢失的消息）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterDigest {
+    pub node: String,
+    /// 该节点发起且仍然有效的封禁
+    pub bans: Vec<ClusterMessage>,
+}
+
+/// 从对端同步来的封禁
+struct RemoteBan {
+    /// 发起封禁的节点
+    node: String,
+    /// 到期时间（Unix 秒），0 表示永久
+    expires_at: u64,
+}
+
+/// 集群同步状态：把本地事件转换为消息，并应用对端发来的消息
+///
+/// 对端同步来的封禁带有到期时间，同一 IP 收到多次封禁时以到期时间最晚的为准
+/// （最长 TTL 优先）；本地自己产生的封禁不受对端到期时间影响。
+pub struct ClusterSync {
+    node: String,
+    /// 广播给对端的封禁 TTL（秒），0 表示永久
+    ban_ttl: u64,
+    enforcer: Arc<Enforcer>,
+    /// 从对端同步来的封禁
+    remote_bans: Mutex<HashMap<IpAddr, RemoteBan>>,
+    /// 本节点发起的封禁，用于生成摘要：IP -> 广播出去的消息
+    local_bans: Mutex<HashMap<IpAddr, ClusterMessage>>,
+}
+
+impl ClusterSync {
+    pub fn new(node: String, ban_ttl: u64, enforcer: Arc<Enforcer>) -> Self {
+        Self {
+            node,
+            ban_ttl,
+            enforcer,
+            remote_bans: Mutex::new(HashMap::new()),
+            local_bans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 从环境变量读取节点 ID 和 TTL
+    ///
+    /// - `UABLOCK_NODE_ID`：节点 ID，默认使用主机名
+    /// - `UABLOCK_SYNC_BAN_TTL`：同步给对端的封禁时长（秒，默认 86400，0 表示永久）
+    pub fn from_env(enforcer: Arc<Enforcer>) -> Self {
+        let ban_ttl = std::env::var("UABLOCK_SYNC_BAN_TTL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400);
+        Self::new(node_id(), ban_ttl, enforcer)
+    }
+
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// 把本地事件转换为要广播的消息；检测事件和来自集群的事件不广播
+    pub fn outgoing(&self, event: &Event) -> Option<ClusterMessage> {
+        if event.origin.starts_with(CLUSTER_ORIGIN_PREFIX) {
+            return None;
+        }
+        let (action, expires_at) = match event.kind {
+            EventKind::Ban if self.ban_ttl == 0 => (ClusterAction::Ban, 0),
+            EventKind::Ban => (ClusterAction::Ban, event.timestamp + self.ban_ttl),
+            EventKind::Unban => (ClusterAction::Unban, 0),
+            EventKind::Detection | EventKind::Alert => return None,
+        };
+        let message = ClusterMessage {
+            node: self.node.clone(),
+            action,
+            ip: event.ip,
+            reason: event.reason.clone(),
+            expires_at,
+        };
+        let mut local_bans = self.local_bans.lock().unwrap();
+        match action {
+            ClusterAction::Ban => local_bans.insert(event.ip, message.clone()),
+            ClusterAction::Unban => local_bans.remove(&event.ip),
+        };
+        Some(message)
+    }
+
+    /// 生成本节点的封禁摘要（顺带清理已到期的记录）
+    #[allow(dead_code)]
+    pub fn digest(&self) -> ClusterDigest {
+        let now = unix_now();
+        let mut local_bans = self.local_bans.lock().unwrap();
+        local_bans.retain(|_, message| message.expires_at == 0 || message.expires_at > now);
+        ClusterDigest {
+            node: self.node.clone(),
+            bans: local_bans.values().cloned().collect(),
+        }
+    }
+
+    /// 应用对端摘要：补齐缺失的封禁，并解封该节点已不再持有的封禁
+    #[allow(dead_code)]
+    pub fn apply_digest(&self, digest: &ClusterDigest) {
+        if digest.node == self.node {
+            return;
+        }
+        for message in digest.bans.iter().filter(|m| m.node == digest.node) {
+            self.apply(message);
+        }
+
+        let stale: Vec<IpAddr> = {
+            let remote_bans = self.remote_bans.lock().unwrap();
+            remote_bans
+                .iter()
+                .filter(|(ip, ban)| {
+                    ban.node == digest.node && !digest.bans.iter().any(|m| m.ip == **ip)
+                })
+                .map(|(ip, _)| *ip)
+                .collect()
+        };
+        for ip in stale {
+            info!(
+                "【集群同步】节点 {} 的摘要中已无 IP {}，补做解封",
+                digest.node, ip
+            );
+            self.apply(&ClusterMessage {
+                node: digest.node.clone(),
+                action: ClusterAction::Unban,
+                ip,
+                reason: "DIGEST".to_string(),
+                expires_at: 0,
+            });
+        }
+    }
+
+    /// 应用对端发来的消息（忽略自己发出的消息）
+    pub fn apply(&self, message: &ClusterMessage) {
+        if message.node == self.node {
+            return;
+        }
+        let origin = format!("{}{}", CLUSTER_ORIGIN_PREFIX, message.node);
+        let mut remote_bans = self.remote_bans.lock().unwrap();
+        match message.action {
+            ClusterAction::Ban => {
+                if message.expires_at != 0 && message.expires_at <= unix_now() {
+                    return;
+                }
+                if let Some(ban) = remote_bans.get_mut(&message.ip) {
+                    // 已由对端封禁：最长 TTL 优先
+                    if ban.expires_at != 0
+                        && (message.expires_at == 0 || message.expires_at > ban.expires_at)
+                    {
+                        ban.expires_at = message.expires_at;
+                        ban.node = message.node.clone();
+                        debug!(
+                            "【集群同步】延长 IP {} 的封禁至 {}（来自 {}）",
+                            message.ip, message.expires_at, message.node
+                        );
+                    }
+                    return;
+                }
+                match self.enforcer.ban(
+                    message.ip,
+                    BanReason::new(&message.reason, &origin).expires_at(message.expires_at),
+                ) {
+                    Ok(true) => {
+                        remote_bans.insert(
+                            message.ip,
+                            RemoteBan {
+                                node: message.node.clone(),
+                                expires_at: message.expires_at,
+                            },
+                        );
+                    }
+                    // 本地已封禁（本地封禁永久有效），无需记录
+                    Ok(false) => {}
+                    Err(e) => error!(
+                        "【集群同步】应用来自 {} 的封禁失败 IP: {}, 错误: {}",
+                        message.node, message.ip, e
+                    ),
+                }
+            }
+            ClusterAction::Unban => {
+                remote_bans.remove(&message.ip);
+                if let Err(e) = self.enforcer.unban(message.ip, &message.reason, &origin) {
+                    error!(
+                        "【集群同步】应用来自 {} 的解封失败 IP: {}, 错误: {}",
+                        message.node, message.ip, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// 解封已到期的对端封禁
+    pub fn expire(&self) {
+        let now = unix_now();
+        let mut remote_bans = self.remote_bans.lock().unwrap();
+        let expired: Vec<IpAddr> = remote_bans
+            .iter()
+            .filter(|(_, ban)| ban.expires_at != 0 && ban.expires_at <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in expired {
+            remote_bans.remove(&ip);
+            info!("【集群同步】对端封禁已到期，解封 IP: {}", ip);
+            let origin = format!("{}{}", CLUSTER_ORIGIN_PREFIX, self.node);
+            if let Err(e) = self.enforcer.unban(ip, "EXPIRED", &origin) {
+                error!("【集群同步】解封到期 IP {} 失败: {}", ip, e);
+            }
+        }
+    }
+}
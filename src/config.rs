@@ -0,0 +1,506 @@
+use crate::bans::parse_duration;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// `backend` 的可选值
+const BACKENDS: [&str; 7] = ["iptables", "ipset", "nft", "firewalld", "pf", "ssh", "none"];
+
+/// `[env]` 中可以设置的选项及其取值类型，按名称排序
+const ENV_OPTIONS: [(&str, EnvKind); 149] = [
+    ("SIP_UA_BLACKLIST", EnvKind::Text),
+    ("SIP_UA_WHITELIST", EnvKind::Text),
+    ("UABLOCK_AMNESTY_AGE", EnvKind::Duration),
+    ("UABLOCK_AMNESTY_INTERVAL", EnvKind::Duration),
+    ("UABLOCK_API_LISTEN", EnvKind::Address),
+    ("UABLOCK_API_TOKEN", EnvKind::Text),
+    ("UABLOCK_ASTERISK_AMI", EnvKind::Text),
+    ("UABLOCK_ASTERISK_AMI_SECRET", EnvKind::Text),
+    ("UABLOCK_ASTERISK_AMI_USER", EnvKind::Text),
+    ("UABLOCK_ASTERISK_SECURITY_LOG", EnvKind::Text),
+    ("UABLOCK_ASTERISK_WEIGHT", EnvKind::Number),
+    ("UABLOCK_AUTHZ_LISTEN", EnvKind::Address),
+    ("UABLOCK_BACKEND", EnvKind::Choice(&BACKENDS)),
+    ("UABLOCK_BACKEND_FAILURE_THRESHOLD", EnvKind::Integer),
+    ("UABLOCK_BACKEND_FALLBACK", EnvKind::Choice(&BACKENDS)),
+    (
+        "UABLOCK_BACKEND_ON_FAILURE",
+        EnvKind::Choice(&["alert", "fallback", "exit"]),
+    ),
+    ("UABLOCK_BANNED_LOG_INTERVAL", EnvKind::Integer),
+    ("UABLOCK_BAN_CHECK", EnvKind::Flag),
+    ("UABLOCK_BAN_CHECK_THRESHOLD", EnvKind::Integer),
+    ("UABLOCK_BAN_CHECK_WINDOW", EnvKind::Integer),
+    ("UABLOCK_BAN_DB", EnvKind::Text),
+    ("UABLOCK_BAN_ESCALATION", EnvKind::Text),
+    ("UABLOCK_BAN_ESCALATION_WINDOW", EnvKind::Duration),
+    ("UABLOCK_BAN_LATENCY_P99_MS", EnvKind::Integer),
+    ("UABLOCK_BAN_TTL", EnvKind::Duration),
+    ("UABLOCK_BLOCKED_CACHE_REFRESH", EnvKind::DurationOrOff),
+    ("UABLOCK_BUNDLE_PUBKEY", EnvKind::Text),
+    ("UABLOCK_CAMPAIGN_REPORT", EnvKind::Duration),
+    ("UABLOCK_CAMPAIGN_WINDOW", EnvKind::Duration),
+    (
+        "UABLOCK_CAPTURE",
+        EnvKind::Choice(&["pcap", "af_packet", "udp", "none"]),
+    ),
+    ("UABLOCK_CAPTURE_FILTER", EnvKind::Text),
+    ("UABLOCK_CENTRAL_KEY", EnvKind::Text),
+    ("UABLOCK_CENTRAL_LISTEN", EnvKind::Address),
+    ("UABLOCK_CENTRAL_MIN_SITES", EnvKind::Integer),
+    ("UABLOCK_CENTRAL_SERVER", EnvKind::Text),
+    ("UABLOCK_CENTRAL_WINDOW", EnvKind::Integer),
+    ("UABLOCK_CONNTRACK_FLUSH", EnvKind::Flag),
+    ("UABLOCK_CONSUL_TOKEN", EnvKind::Text),
+    ("UABLOCK_CONSUL_URL", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_BAN_DURATION", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_BOUNCER_KEY", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_MACHINE_ID", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_PASSWORD", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_POLL", EnvKind::Integer),
+    ("UABLOCK_CROWDSEC_SCENARIOS", EnvKind::Text),
+    ("UABLOCK_CROWDSEC_URL", EnvKind::Text),
+    ("UABLOCK_DECISION_CACHE_TTL", EnvKind::Integer),
+    ("UABLOCK_ENFORCEMENT_QUEUE", EnvKind::Integer),
+    ("UABLOCK_ETCD_URL", EnvKind::Text),
+    ("UABLOCK_EVENT_QUEUE", EnvKind::Integer),
+    ("UABLOCK_FAIL2BAN_LOG", EnvKind::Text),
+    ("UABLOCK_FEED_TOKEN", EnvKind::Text),
+    ("UABLOCK_FIREWALLD_ZONE", EnvKind::Text),
+    ("UABLOCK_FREESWITCH_ESL", EnvKind::Text),
+    ("UABLOCK_FREESWITCH_PASSWORD", EnvKind::Text),
+    ("UABLOCK_FREESWITCH_WEIGHT", EnvKind::Number),
+    ("UABLOCK_GEOIP_DB", EnvKind::Text),
+    ("UABLOCK_GEOIP_RELOAD", EnvKind::DurationOrOff),
+    ("UABLOCK_GOSSIP_DIGEST_INTERVAL", EnvKind::Integer),
+    ("UABLOCK_GOSSIP_KEY", EnvKind::Text),
+    ("UABLOCK_GOSSIP_LISTEN", EnvKind::Address),
+    ("UABLOCK_GOSSIP_PEERS", EnvKind::Text),
+    ("UABLOCK_GREYLIST", EnvKind::Flag),
+    ("UABLOCK_GREYLIST_ALLOW_TTL", EnvKind::Integer),
+    ("UABLOCK_GREYLIST_HOLD", EnvKind::Integer),
+    ("UABLOCK_GREYLIST_MAX_REQUESTS", EnvKind::Integer),
+    ("UABLOCK_GREYLIST_PROBATION", EnvKind::Integer),
+    ("UABLOCK_GRPC_LISTEN", EnvKind::Address),
+    (
+        "UABLOCK_HA_ROLE",
+        EnvKind::ChoiceAnyCase(&["active", "standby"]),
+    ),
+    ("UABLOCK_HEP_RELAYS", EnvKind::Text),
+    ("UABLOCK_HONEYPOT", EnvKind::Flag),
+    ("UABLOCK_HONEYPOT_BANNER", EnvKind::Text),
+    ("UABLOCK_IPSET_MAXELEM", EnvKind::Integer),
+    ("UABLOCK_IPSET_NAME", EnvKind::Text),
+    ("UABLOCK_IPTABLES_CHAIN", EnvKind::Text),
+    ("UABLOCK_IPTABLES_PARENT", EnvKind::Text),
+    ("UABLOCK_IPTABLES_RETRIES", EnvKind::Integer),
+    ("UABLOCK_IPTABLES_TARGET", EnvKind::Text),
+    ("UABLOCK_IPTABLES_WAIT", EnvKind::Integer),
+    ("UABLOCK_KAMAILIO_RPC", EnvKind::Text),
+    ("UABLOCK_KV_PREFIX", EnvKind::Text),
+    ("UABLOCK_LAN_ALERT_ONLY", EnvKind::Flag),
+    ("UABLOCK_LAN_MACS", EnvKind::Text),
+    ("UABLOCK_LAN_SUBNETS", EnvKind::Text),
+    ("UABLOCK_LUA_SCRIPT", EnvKind::Text),
+    ("UABLOCK_MAX_SOURCES", EnvKind::Integer),
+    ("UABLOCK_MAX_TRANSACTIONS", EnvKind::Integer),
+    ("UABLOCK_MESSAGE_LIMIT", EnvKind::Integer),
+    ("UABLOCK_METHOD_WINDOW", EnvKind::Integer),
+    ("UABLOCK_NODE_ID", EnvKind::Text),
+    ("UABLOCK_NOISE_WEIGHT", EnvKind::Number),
+    ("UABLOCK_NOTIFY", EnvKind::Text),
+    ("UABLOCK_NOTIFY_LIMIT", EnvKind::Integer),
+    ("UABLOCK_PARSE_WORKERS", EnvKind::Integer),
+    ("UABLOCK_PF_ANCHOR", EnvKind::Text),
+    ("UABLOCK_PF_TABLE", EnvKind::Text),
+    ("UABLOCK_PLUGIN_DIR", EnvKind::Text),
+    ("UABLOCK_PORTS_FILE", EnvKind::Text),
+    ("UABLOCK_RECONCILE_REPAIR", EnvKind::Flag),
+    ("UABLOCK_REDIS_CHANNEL", EnvKind::Text),
+    ("UABLOCK_REDIS_URL", EnvKind::Text),
+    ("UABLOCK_REGISTERED_TTL", EnvKind::Duration),
+    ("UABLOCK_REVIEW_HOLD", EnvKind::Duration),
+    ("UABLOCK_REVIEW_IMMEDIATE", EnvKind::Text),
+    ("UABLOCK_RULES_FILE", EnvKind::Text),
+    ("UABLOCK_RULE_AUDIT_BATCH", EnvKind::Integer),
+    ("UABLOCK_RULE_AUDIT_INTERVAL", EnvKind::DurationOrOff),
+    ("UABLOCK_SELF_PROTECT", EnvKind::Flag),
+    ("UABLOCK_SENDMAIL", EnvKind::Text),
+    ("UABLOCK_SNAPSHOT_FILE", EnvKind::Text),
+    ("UABLOCK_SNMP_AGENTX", EnvKind::Text),
+    ("UABLOCK_SNMP_ROOT_OID", EnvKind::Text),
+    ("UABLOCK_SNMP_TRAP_SEVERITY", EnvKind::Integer),
+    ("UABLOCK_SSH_BLOCK_CMD", EnvKind::Text),
+    ("UABLOCK_SSH_CHECK_CMD", EnvKind::Text),
+    ("UABLOCK_SSH_HOST", EnvKind::Text),
+    ("UABLOCK_SSH_KEY", EnvKind::Text),
+    ("UABLOCK_SSH_LIST_CMD", EnvKind::Text),
+    ("UABLOCK_SSH_PORT", EnvKind::Integer),
+    ("UABLOCK_SSH_UNBLOCK_CMD", EnvKind::Text),
+    ("UABLOCK_STATE_FILE", EnvKind::Text),
+    ("UABLOCK_STRIKE_HALF_LIFE", EnvKind::Integer),
+    ("UABLOCK_STRIKE_THRESHOLD", EnvKind::Number),
+    ("UABLOCK_SUBSCRIBE_LIMIT", EnvKind::Integer),
+    ("UABLOCK_SYNC_BAN_TTL", EnvKind::Integer),
+    ("UABLOCK_TENANTS", EnvKind::Text),
+    ("UABLOCK_TLS_JA3_BLOCKLIST", EnvKind::Text),
+    ("UABLOCK_TLS_PORT", EnvKind::Integer),
+    ("UABLOCK_TLS_SYN_LIMIT", EnvKind::Integer),
+    ("UABLOCK_TLS_WEIGHT", EnvKind::Number),
+    ("UABLOCK_TRACE_FILE", EnvKind::Text),
+    ("UABLOCK_TRACE_SOURCES", EnvKind::Text),
+    ("UABLOCK_TRUSTED_SOURCES", EnvKind::Text),
+    ("UABLOCK_UA_DENY_DURATION", EnvKind::Integer),
+    (
+        "UABLOCK_UA_MATCH",
+        EnvKind::Choice(&["fuzzy", "substring", "prefix", "exact", "glob"]),
+    ),
+    ("UABLOCK_UA_RATE_MIN_SOURCES", EnvKind::Integer),
+    ("UABLOCK_UA_RATE_THRESHOLD", EnvKind::Integer),
+    ("UABLOCK_UA_RATE_WINDOW", EnvKind::Integer),
+    ("UABLOCK_UDP_LISTEN", EnvKind::Address),
+    ("UABLOCK_VERIFY", EnvKind::Text),
+    ("UABLOCK_VERIFY_FINGERPRINTS", EnvKind::Text),
+    ("UABLOCK_VERIFY_LIMIT", EnvKind::Integer),
+    ("UABLOCK_VERIFY_RDNS", EnvKind::Text),
+    ("UABLOCK_VERIFY_TTL", EnvKind::Integer),
+    ("UABLOCK_WEBHOOK_AUDIT_LOG", EnvKind::Text),
+    ("UABLOCK_WEBHOOK_SECRET", EnvKind::Text),
+    ("UABLOCK_WEBHOOK_STATE", EnvKind::Text),
+    ("UABLOCK_WHITELIST_FILE", EnvKind::Text),
+    ("UABLOCK_WHITELIST_RELOAD", EnvKind::DurationOrOff),
+];
+
+/// `[env]` 选项的取值类型，加载配置文件时校验，避免写错的取值到启动后才被忽略或报错
+#[derive(Debug, Clone, Copy)]
+enum EnvKind {
+    /// 任意文本（路径、地址列表、命令模板等）
+    Text,
+    /// 非负整数
+    Integer,
+    /// 数字（权重、阈值）
+    Number,
+    /// 时长，如 `30s`、`10m`、`1d12h`
+    Duration,
+    /// 时长，`0` 表示关闭
+    DurationOrOff,
+    /// `0` 或 `1`
+    Flag,
+    /// 监听地址，如 `127.0.0.1:8080`
+    Address,
+    /// 固定的可选值之一，区分大小写（与运行时的比较一致）
+    Choice(&'static [&'static str]),
+    /// 固定的可选值之一，不区分大小写（运行时同样先转为小写，如 `UABLOCK_HA_ROLE`）
+    ChoiceAnyCase(&'static [&'static str]),
+}
+
+impl EnvKind {
+    /// 校验取值，无效时返回取值要求
+    fn check(self, value: &str) -> Result<(), String> {
+        let require = |valid: bool, requirement: &str| match valid {
+            true => Ok(()),
+            false => Err(requirement.to_string()),
+        };
+        let duration = parse_duration(value).is_some_and(|duration| !duration.is_zero());
+        match self {
+            Self::Text => Ok(()),
+            Self::Integer => require(value.parse::<u64>().is_ok(), "应为非负整数"),
+            Self::Number => require(value.parse::<f64>().is_ok_and(f64::is_finite), "应为数字"),
+            Self::Duration => require(duration, "应为时长，如 30s、10m、1d12h"),
+            Self::DurationOrOff => require(duration || value == "0", "应为时长（如 30s、10m）或 0"),
+            Self::Flag => require(value == "0" || value == "1", "应为 0 或 1"),
+            Self::Address => require(
+                value.parse::<SocketAddr>().is_ok(),
+                "应为地址:端口，如 127.0.0.1:8080",
+            ),
+            Self::Choice(choices) => require(
+                choices.contains(&value),
+                &format!("可选 {}", choices.join("、")),
+            ),
+            Self::ChoiceAnyCase(choices) => require(
+                choices.contains(&value.to_ascii_lowercase().as_str()),
+                &format!("可选 {}", choices.join("、")),
+            ),
+        }
+    }
+}
+
+/// 配置文件（`--config /etc/uablock/config.toml`）
+///
+/// 所有字段都可以省略，省略时与不使用配置文件相同。优先级：命令行参数 > 环境变量 > 配置文件 > 默认值。
+///
+/// ```toml
+/// interface = "eth1"
+/// port = 5080
+/// whitelist = ["Yealink", "Grandstream", "MicroSIP"]   # 或 blacklist = ["friendly-scanner", "sipvicious"]
+/// chain = "UABLOCK"
+/// backend = "ipset"
+/// capture_filter = "dst host 203.0.113.5"
+/// log_level = "info"
+///
+/// # 其他选项使用与环境变量相同的名称，已设置的环境变量优先
+/// [env]
+/// UABLOCK_API_LISTEN = "127.0.0.1:8080"
+/// UABLOCK_RULE_AUDIT_INTERVAL = "10m"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 抓包网络接口，多个接口以逗号分隔
+    pub interface: String,
+    /// 封禁端口
+    pub port: u16,
+    /// UA 白名单模式；省略时使用 `SIP_UA_WHITELIST` 或内置白名单
+    pub whitelist: Option<Vec<String>>,
+    /// UA 黑名单模式：设置后改为只封禁匹配的 UA，不能与 `whitelist` 同时设置
+    pub blacklist: Option<Vec<String>>,
+    /// iptables 规则所在的链（对应 `UABLOCK_IPTABLES_CHAIN`），默认专用链 UABLOCK
+    pub chain: Option<String>,
+    /// 封禁后端（对应 `UABLOCK_BACKEND`）：iptables、ipset、nft、firewalld、pf、ssh 或 none
+    pub backend: Option<String>,
+    /// 附加的 BPF 抓包过滤表达式（对应 `UABLOCK_CAPTURE_FILTER`），与受保护端口的过滤同时满足
+    pub capture_filter: Option<String>,
+    /// 日志级别：off、error、warn、info、debug、trace
+    pub log_level: String,
+    /// 其他选项：环境变量名 → 取值，只在该环境变量未设置时生效
+    pub env: BTreeMap<String, EnvValue>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interface: "eth0".to_string(),
+            port: 5060,
+            whitelist: None,
+            blacklist: None,
+            chain: None,
+            backend: None,
+            capture_filter: None,
+            log_level: "debug".to_string(),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+/// `[env]` 中的取值，字符串和数字均可
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum EnvValue {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl EnvValue {
+    fn to_env(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Integer(value) => value.to_string(),
+            Self::Float(value) => value.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取并校验配置文件
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("无法读取配置文件 {}: {}", path.display(), e))?;
+        Self::load_content(path, &content)
+    }
+
+    /// 解析并校验已读取（例如已校验签名）的配置文件内容，`path` 只用于错误信息
+    pub fn load_content(path: &Path, content: &str) -> Result<Self, String> {
+        Self::parse(content).map_err(|e| format!("配置文件 {} 无效: {}", path.display(), e))
+    }
+
+    /// 解析并校验配置内容
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.interface.trim().is_empty() {
+            return Err("interface 不能为空".to_string());
+        }
+        if self.port == 0 {
+            return Err("port 必须在 1-65535 之间".to_string());
+        }
+        if let Some(patterns) = &self.whitelist {
+            if patterns.iter().any(|pattern| pattern.trim().is_empty()) {
+                return Err("whitelist 不能包含空模式（空模式会放行所有 UA）".to_string());
+            }
+        }
+        if let Some(patterns) = &self.blacklist {
+            if self.whitelist.is_some() {
+                return Err("whitelist 和 blacklist 不能同时设置".to_string());
+            }
+            if patterns.is_empty() || patterns.iter().any(|pattern| pattern.trim().is_empty()) {
+                return Err(
+                    "blacklist 不能为空，也不能包含空模式（空模式会封禁所有 UA）".to_string(),
+                );
+            }
+        }
+        if self.chain.as_ref().is_some_and(|chain| {
+            chain.is_empty() || chain.contains(char::is_whitespace) || chain.len() > 28
+        }) {
+            return Err(format!(
+                "chain 无效: {:?}（不能为空或包含空白，最长 28 个字符）",
+                self.chain.as_deref().unwrap_or_default()
+            ));
+        }
+        if let Some(backend) = self
+            .backend
+            .as_ref()
+            .filter(|backend| !BACKENDS.contains(&backend.as_str()))
+        {
+            return Err(format!(
+                "backend 无效: {}（可选 {}）",
+                backend,
+                BACKENDS.join("、")
+            ));
+        }
+        if self
+            .capture_filter
+            .as_ref()
+            .is_some_and(|filter| filter.trim().is_empty())
+        {
+            return Err("capture_filter 不能为空".to_string());
+        }
+        self.level()?;
+        for (name, value) in &self.env {
+            let Ok(index) = ENV_OPTIONS.binary_search_by(|(option, _)| option.cmp(&name.as_str()))
+            else {
+                return Err(format!("env 中的 {} 不是本工具的选项", name));
+            };
+            let value = value.to_env();
+            ENV_OPTIONS[index]
+                .1
+                .check(&value)
+                .map_err(|e| format!("env 中的 {} 无效: {}（{}）", name, value, e))?;
+        }
+        Ok(())
+    }
+
+    /// 日志级别
+    pub fn level(&self) -> Result<LevelFilter, String> {
+        LevelFilter::from_str(&self.log_level).map_err(|_| {
+            format!(
+                "log_level 无效: {}（可选 off、error、warn、info、debug、trace）",
+                self.log_level
+            )
+        })
+    }
+
+    /// 配置文件中设置、但环境中未设置的变量
+    ///
+    /// `chain` 对应 `UABLOCK_IPTABLES_CHAIN`，`backend` 对应 `UABLOCK_BACKEND`，`capture_filter` 对应
+    /// `UABLOCK_CAPTURE_FILTER`，`[env]` 中的条目原样对应。
+    pub fn env_defaults(&self) -> Vec<(String, String)> {
+        let chain = self
+            .chain
+            .as_ref()
+            .map(|chain| ("UABLOCK_IPTABLES_CHAIN".to_string(), chain.clone()));
+        let backend = self
+            .backend
+            .as_ref()
+            .map(|backend| ("UABLOCK_BACKEND".to_string(), backend.clone()));
+        let capture_filter = self
+            .capture_filter
+            .as_ref()
+            .map(|filter| ("UABLOCK_CAPTURE_FILTER".to_string(), filter.clone()));
+        chain
+            .into_iter()
+            .chain(backend)
+            .chain(capture_filter)
+            .chain(
+                self.env
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.to_env())),
+            )
+            .filter(|(name, _)| std::env::var_os(name).is_none())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_sets_defaults_and_rejects_invalid_values() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!((config.interface.as_str(), config.port), ("eth0", 5060));
+
+        let config = Config::parse(
+            r#"
+        interface = "eth1"
+        port = 5080
+        whitelist = ["Yealink", "MicroSIP"]
+        chain = "UABLOCK"
+        log_level = "warn"
+
+        [env]
+        UABLOCK_RULE_AUDIT_INTERVAL = "10m"
+        UABLOCK_RULE_AUDIT_BATCH = 50
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.interface, "eth1");
+        assert_eq!(config.port, 5080);
+        assert_eq!(
+            config.whitelist.as_deref(),
+            Some(&["Yealink".to_string(), "MicroSIP".to_string()][..])
+        );
+        assert_eq!(config.level().unwrap(), log::LevelFilter::Warn);
+        let mut defaults = config.env_defaults();
+        defaults.sort();
+        assert_eq!(
+            defaults,
+            vec![
+                ("UABLOCK_IPTABLES_CHAIN".to_string(), "UABLOCK".to_string()),
+                ("UABLOCK_RULE_AUDIT_BATCH".to_string(), "50".to_string()),
+                ("UABLOCK_RULE_AUDIT_INTERVAL".to_string(), "10m".to_string()),
+            ]
+        );
+
+        // 无效取值和拼错的字段名都报错并指出字段
+        for (content, field) in [
+            ("port = 0", "port"),
+            ("port = 70000", "port"),
+            ("log_level = \"loud\"", "log_level"),
+            ("whitelist = [\"\"]", "whitelist"),
+            ("chain = \"MY CHAIN\"", "chain"),
+            ("interfaces = \"eth1\"", "interfaces"),
+            ("[env]\nPATH = \"/tmp\"", "PATH"),
+            ("[env]\nUABLOCK_PARSE_WORKER = 4", "UABLOCK_PARSE_WORKER"),
+            (
+                "[env]\nUABLOCK_PARSE_WORKERS = \"abc\"",
+                "UABLOCK_PARSE_WORKERS",
+            ),
+            ("[env]\nUABLOCK_BAN_TTL = \"1 hour\"", "UABLOCK_BAN_TTL"),
+            ("[env]\nUABLOCK_GREYLIST = \"yes\"", "UABLOCK_GREYLIST"),
+            (
+                "[env]\nUABLOCK_API_LISTEN = \"localhost\"",
+                "UABLOCK_API_LISTEN",
+            ),
+            ("[env]\nUABLOCK_HA_ROLE = \"backup\"", "UABLOCK_HA_ROLE"),
+            // 运行时按原样比较的可选值不接受其他大小写
+            ("[env]\nUABLOCK_BACKEND = \"IPTABLES\"", "UABLOCK_BACKEND"),
+            ("[env]\nUABLOCK_UA_MATCH = \"Exact\"", "UABLOCK_UA_MATCH"),
+        ] {
+            let error = Config::parse(content).unwrap_err();
+            assert!(error.contains(field), "{}: {}", content, error);
+        }
+        // UABLOCK_HA_ROLE 在运行时不区分大小写，校验也一样
+        Config::parse("[env]\nUABLOCK_HA_ROLE = \"Standby\"").unwrap();
+        // 示例配置文件可以直接使用
+        Config::load(std::path::Path::new("contrib/config.example.toml")).unwrap();
+    }
+}
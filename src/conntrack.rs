@@ -0,0 +1,113 @@
+use crate::firewall::FirewallBackend;
+use log::{debug, info, warn};
+use std::net::IpAddr;
+use std::process::Command;
+
+/// 封禁后清除来源的连接跟踪条目
+///
+/// 封禁规则位于 `ESTABLISHED,RELATED` 放行规则之后时（如 firewalld 的富规则，或跳转被其他工具
+/// 挪到了后面），已经被 conntrack 跟踪的 UDP 流（扫描器持续向同一端口发包）在封禁后仍被放行，
+/// 直到条目超时；转发场景下已建立的 NAT 映射也会保留。封禁成功后执行 `conntrack -D -s <ip>`
+/// 删除该来源的全部条目，后续数据包按新连接重新匹配规则。清除失败（如没有安装 conntrack 工具）
+/// 只记录警告，不影响封禁结果。
+pub struct ConntrackFlush {
+    inner: Box<dyn FirewallBackend>,
+}
+
+impl ConntrackFlush {
+    pub fn new(inner: Box<dyn FirewallBackend>) -> Self {
+        Self { inner }
+    }
+
+    /// `UABLOCK_CONNTRACK_FLUSH=1` 时为后端加上清除，否则原样返回
+    pub fn wrap_from_env(inner: Box<dyn FirewallBackend>) -> Box<dyn FirewallBackend> {
+        if std::env::var("UABLOCK_CONNTRACK_FLUSH").as_deref() == Ok("1") {
+            info!("封禁后清除来源的连接跟踪条目");
+            Box::new(Self::new(inner))
+        } else {
+            inner
+        }
+    }
+
+    /// 删除来源的连接跟踪条目，返回删除的条目数
+    pub fn flush(ip: &IpAddr) -> Result<u64, String> {
+        let family = match ip {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        };
+        let output = Command::new("conntrack")
+            .args(["-D", "-f", family, "-s", &ip.to_string()])
+            .output()
+            .map_err(|e| format!("执行 conntrack 命令失败: {}", e))?;
+        // 输出形如 "conntrack v1.4.6 (conntrack-tools): 2 flow entries have been deleted."，
+        // 没有匹配的条目时退出码为 1
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match parse_deleted(&stderr) {
+            Some(deleted) => Ok(deleted),
+            None if output.status.success() => Ok(0),
+            None => Err(format!("清除 {} 的连接跟踪条目失败: {}", ip, stderr.trim())),
+        }
+    }
+}
+
+/// 从 conntrack 的输出中取出删除的条目数
+pub fn parse_deleted(output: &str) -> Option<u64> {
+    let (before, _) = output.split_once(" flow entries have been deleted")?;
+    before.rsplit(' ').next()?.parse().ok()
+}
+
+impl FirewallBackend for ConntrackFlush {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.inner.block_ip(ip)?;
+        match Self::flush(ip) {
+            Ok(0) => debug!("{} 没有连接跟踪条目", ip),
+            Ok(deleted) => info!("已清除 {} 的 {} 个连接跟踪条目", ip, deleted),
+            Err(e) => warn!("{}", e),
+        }
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.inner.unblock_ip(ip)
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        self.inner.is_blocked(ip)
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        self.inner.list_blocked()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryFirewall;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn conntrack_flush_never_fails_a_successful_ban() {
+        assert_eq!(
+            parse_deleted(
+                "conntrack v1.4.6 (conntrack-tools): 2 flow entries have been deleted.\n"
+            ),
+            Some(2)
+        );
+        assert_eq!(parse_deleted("conntrack: invalid option"), None);
+
+        // conntrack 不可用或没有条目时封禁照常成功，其余操作直接交给原后端
+        let firewall = MemoryFirewall::new();
+        let flushing = ConntrackFlush::new(Box::new(firewall.clone()));
+        flushing.block_ip(&ip(SCANNER)).unwrap();
+        assert!(flushing.is_blocked(&ip(SCANNER)));
+        assert_eq!(flushing.list_blocked().unwrap(), vec![ip(SCANNER)]);
+        flushing.unblock_ip(&ip(SCANNER)).unwrap();
+        assert!(firewall.blocked().is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::bans::{parse_duration, BanReason};
+use crate::enforcement::Enforcer;
+use crate::events::{Event, EventKind, QueueReceiver};
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// HTTP 请求超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 上报告警时使用的场景名
+const SCENARIO: &str = "uablock/sip-scanner";
+
+/// 从 CrowdSec 同步的封禁使用的事件来源
+const CROWDSEC_ORIGIN: &str = "crowdsec";
+
+/// CrowdSec 本地 API（LAPI）集成
+///
+/// - 作为 watcher：把检测结果作为告警（附带封禁决策）上报 LAPI，供其他 bouncer 使用
+/// - 作为 bouncer：轮询决策流，预先封禁社区/其他来源判定的 SIP 攻击者
+pub struct CrowdSec {
+    url: String,
+    agent: ureq::Agent,
+    /// watcher 账号（machine_id, password），未设置时不上报告警
+    machine: Option<(String, String)>,
+    /// bouncer API key，未设置时不消费决策
+    bouncer_key: Option<String>,
+    /// 只应用场景名包含这些关键字的决策（为空时应用全部）
+    scenarios: Vec<String>,
+    poll_interval: Duration,
+    /// 上报告警中的封禁时长（CrowdSec 格式，如 `4h`）
+    ban_duration: String,
+}
+
+/// 决策流响应
+#[derive(Debug, Deserialize)]
+struct DecisionStream {
+    new: Option<Vec<Decision>>,
+    deleted: Option<Vec<Decision>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Decision {
+    scope: String,
+    value: String,
+    #[serde(rename = "type")]
+    kind: String,
+    scenario: String,
+}
+
+impl CrowdSec {
+    /// 从环境变量创建，未设置 `UABLOCK_CROWDSEC_URL` 时返回 None
+    ///
+    /// - `UABLOCK_CROWDSEC_URL`：LAPI 地址，例如 `http://127.0.0.1:8080`
+    /// - `UABLOCK_CROWDSEC_MACHINE_ID` / `UABLOCK_CROWDSEC_PASSWORD`：watcher 账号（上报告警）
+    /// - `UABLOCK_CROWDSEC_BOUNCER_KEY`：bouncer API key（消费决策）
+    /// - `UABLOCK_CROWDSEC_SCENARIOS`：决策场景过滤关键字（逗号分隔，默认 `sip`）
+    /// - `UABLOCK_CROWDSEC_POLL`：决策轮询间隔（秒，默认 60）
+    /// - `UABLOCK_CROWDSEC_BAN_DURATION`：告警附带的封禁时长（默认 `4h`）
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("UABLOCK_CROWDSEC_URL")
+            .ok()
+            .filter(|u| !u.is_empty())?;
+        let machine = match (
+            std::env::var("UABLOCK_CROWDSEC_MACHINE_ID"),
+            std::env::var("UABLOCK_CROWDSEC_PASSWORD"),
+        ) {
+            (Ok(id), Ok(password)) if !id.is_empty() => Some((id, password)),
+            _ => None,
+        };
+        let bouncer_key = std::env::var("UABLOCK_CROWDSEC_BOUNCER_KEY")
+            .ok()
+            .filter(|k| !k.is_empty());
+        let scenarios = std::env::var("UABLOCK_CROWDSEC_SCENARIOS")
+            .unwrap_or_else(|_| "sip".to_string())
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let poll_interval = std::env::var("UABLOCK_CROWDSEC_POLL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        Some(Self {
+            url: url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            machine,
+            bouncer_key,
+            scenarios,
+            poll_interval: Duration::from_secs(poll_interval),
+            ban_duration: std::env::var("UABLOCK_CROWDSEC_BAN_DURATION")
+                .unwrap_or_else(|_| "4h".to_string()),
+        })
+    }
+
+    /// 启动告警上报和决策轮询线程
+    pub fn start(self, enforcer: Arc<Enforcer>) -> Result<(), String> {
+        if self.machine.is_none() && self.bouncer_key.is_none() {
+            return Err(
+                "启用 CrowdSec 时需设置 watcher 账号或 UABLOCK_CROWDSEC_BOUNCER_KEY".to_string(),
+            );
+        }
+        let this = Arc::new(self);
+
+        if this.machine.is_some() {
+            let (tx, rx) = enforcer.events().queue();
+            enforcer.events().subscribe(move |event| {
+                if event.kind != EventKind::Detection || event.origin == CROWDSEC_ORIGIN {
+                    return true;
+                }
+                tx.send(event.clone())
+            });
+            info!("【CrowdSec】检测结果将作为告警上报 {}", this.url);
+            let this = this.clone();
+            std::thread::Builder::new()
+                .name("crowdsec-alerts".to_string())
+                .spawn(move || this.alert_loop(rx))
+                .map_err(|e| format!("启动 CrowdSec 告警线程失败: {}", e))?;
+        }
+
+        if this.bouncer_key.is_some() {
+            info!(
+                "【CrowdSec】每 {} 秒从 {} 同步决策（场景过滤: {:?}）",
+                this.poll_interval.as_secs(),
+                this.url,
+                this.scenarios
+            );
+            std::thread::Builder::new()
+                .name("crowdsec-decisions".to_string())
+                .spawn(move || this.decision_loop(&enforcer))
+                .map_err(|e| format!("启动 CrowdSec 决策线程失败: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// 上报告警；同一 IP 在封禁时长内只上报一次
+    fn alert_loop(&self, rx: QueueReceiver<Event>) {
+        let mut token: Option<String> = None;
+        let mut reported: HashMap<IpAddr, Instant> = HashMap::new();
+        let dedup_window = parse_duration(&self.ban_duration).unwrap_or(Duration::from_secs(3600));
+        for event in rx {
+            reported.retain(|_, at| at.elapsed() < dedup_window);
+            if reported.contains_key(&event.ip) {
+                continue;
+            }
+            // token 过期时重新登录一次
+            for _ in 0..2 {
+                if token.is_none() {
+                    token = match self.login() {
+                        Ok(token) => Some(token),
+                        Err(e) => {
+                            error!("【CrowdSec】watcher 登录失败: {}", e);
+                            break;
+                        }
+                    };
+                }
+                match self.push_alert(token.as_deref().unwrap_or_default(), &event) {
+                    Ok(()) => {
+                        debug!("【CrowdSec】已上报告警 IP: {}", event.ip);
+                        reported.insert(event.ip, Instant::now());
+                        break;
+                    }
+                    Err(e) if matches!(*e, ureq::Error::Status(401, _)) => token = None,
+                    Err(e) => {
+                        error!("【CrowdSec】上报告警失败 IP {}: {}", event.ip, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// watcher 登录，返回 JWT
+    fn login(&self) -> Result<String, String> {
+        let (machine_id, password) = self.machine.as_ref().ok_or("未配置 watcher 账号")?;
+        let response: Value = self
+            .agent
+            .post(&format!("{}/v1/watchers/login", self.url))
+            .send_json(json!({
+                "machine_id": machine_id,
+                "password": password,
+                "scenarios": [SCENARIO],
+            }))
+            .map_err(|e| e.to_string())?
+            .into_json()
+            .map_err(|e| e.to_string())?;
+        response
+            .get("token")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| "登录响应中没有 token".to_string())
+    }
+
+    fn push_alert(&self, token: &str, event: &Event) -> Result<(), Box<ureq::Error>> {
+        let now = Utc::now().to_rfc3339();
+        let ip = event.ip.to_string();
+        let alert = json!([{
+            "scenario": SCENARIO,
+            "scenario_hash": "",
+            "scenario_version": env!("CARGO_PKG_VERSION"),
+            "message": format!("uablock: {} ({}) ua=\"{}\"", ip, event.reason, event.user_agent),
+            "events_count": 1,
+            "start_at": now,
+            "stop_at": now,
+            "capacity": 0,
+            "leakspeed": "0",
+            "simulated": false,
+            "events": [{
+                "timestamp": now,
+                "meta": [
+                    {"key": "source_ip", "value": ip},
+                    {"key": "user_agent", "value": event.user_agent},
+                    {"key": "reason", "value": event.reason},
+                ],
+            }],
+            "source": {"scope": "Ip", "value": ip, "ip": ip},
+            "decisions": [{
+                "duration": self.ban_duration,
+                "type": "ban",
+                "scope": "Ip",
+                "value": ip,
+                "origin": "uablock",
+                "scenario": SCENARIO,
+            }],
+        }]);
+        self.agent
+            .post(&format!("{}/v1/alerts", self.url))
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(alert)
+            .map_err(Box::new)?;
+        Ok(())
+    }
+
+    /// 轮询决策流：首次拉取全量，之后只拉取增量
+    fn decision_loop(&self, enforcer: &Enforcer) {
+        // 只解封由 CrowdSec 决策封禁的 IP，不影响本地检测产生的封禁
+        let mut applied: HashSet<IpAddr> = HashSet::new();
+        let mut startup = true;
+        loop {
+            match self.fetch_decisions(startup) {
+                Ok(stream) => {
+                    startup = false;
+                    for decision in stream.new.unwrap_or_default() {
+                        let Some(ip) = self.accept(&decision) else {
+                            continue;
+                        };
+                        match enforcer.ban(
+                            ip,
+                            BanReason::new("CROWDSEC", CROWDSEC_ORIGIN)
+                                .with_rule(&decision.scenario),
+                        ) {
+                            Ok(true) => {
+                                applied.insert(ip);
+                                debug!(
+                                    "【CrowdSec】按决策封禁 IP: {}（场景: {}）",
+                                    ip, decision.scenario
+                                );
+                            }
+                            Ok(false) => {}
+                            Err(e) => error!("【CrowdSec】封禁 IP {} 失败: {}", ip, e),
+                        }
+                    }
+                    for decision in stream.deleted.unwrap_or_default() {
+                        let Some(ip) = self.accept(&decision) else {
+                            continue;
+                        };
+                        if applied.remove(&ip) {
+                            if let Err(e) = enforcer.unban(ip, "CROWDSEC", CROWDSEC_ORIGIN) {
+                                error!("【CrowdSec】解封 IP {} 失败: {}", ip, e);
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("【CrowdSec】拉取决策失败: {}", e),
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+
+    fn fetch_decisions(&self, startup: bool) -> Result<DecisionStream, String> {
+        let key = self.bouncer_key.as_deref().unwrap_or_default();
+        self.agent
+            .get(&format!("{}/v1/decisions/stream", self.url))
+            .query("startup", if startup { "true" } else { "false" })
+            .set("X-Api-Key", key)
+            .call()
+            .map_err(|e| e.to_string())?
+            .into_json()
+            .map_err(|e| e.to_string())
+    }
+
+    /// 只接受 IP 范围的封禁决策，并按场景关键字过滤
+    fn accept(&self, decision: &Decision) -> Option<IpAddr> {
+        if !decision.scope.eq_ignore_ascii_case("ip") || decision.kind != "ban" {
+            return None;
+        }
+        let scenario = decision.scenario.to_lowercase();
+        if !self.scenarios.is_empty() && !self.scenarios.iter().any(|s| scenario.contains(s)) {
+            return None;
+        }
+        decision.value.parse().ok()
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 缓存的条目上限，防止伪造源地址的洪泛撑大内存
+const MAX_ENTRIES: usize = 65536;
+
+/// 最近的判定
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Decision {
+    /// 已封禁（或已排队封禁）
+    Banned,
+    /// 白名单 UA 已确认无需解封
+    Allowed { user_agent: String },
+}
+
+/// 按来源 IP 缓存最近的判定
+///
+/// 已封禁的洪泛来源在有效期内的数据包只计数，不再解析、判定和查询防火墙；
+/// 白名单 UA 在有效期内不再重复检查是否需要解封（仍然经过策略判定，限速等计数不受影响）。
+pub struct DecisionCache {
+    ttl: Duration,
+    entries: HashMap<IpAddr, (Decision, Instant)>,
+}
+
+impl DecisionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// 来源在有效期内已被判定封禁
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        matches!(self.get(ip), Some(Decision::Banned))
+    }
+
+    /// 来源在有效期内已以相同 UA 确认放行
+    pub fn is_allowed(&self, ip: &IpAddr, user_agent: &str) -> bool {
+        matches!(self.get(ip), Some(Decision::Allowed { user_agent: ua }) if ua == user_agent)
+    }
+
+    pub fn ban(&mut self, ip: IpAddr) {
+        self.insert(ip, Decision::Banned);
+    }
+
+    pub fn allow(&mut self, ip: IpAddr, user_agent: &str) {
+        self.insert(
+            ip,
+            Decision::Allowed {
+                user_agent: user_agent.to_string(),
+            },
+        );
+    }
+
+    /// 移除来源的缓存判定（例如封禁被撤销）
+    pub fn forget(&mut self, ip: &IpAddr) {
+        self.entries.remove(ip);
+    }
+
+    /// 清理过期条目
+    pub fn cleanup(&mut self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (_, at)| at.elapsed() < ttl);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn get(&self, ip: &IpAddr) -> Option<&Decision> {
+        self.entries
+            .get(ip)
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(decision, _)| decision)
+    }
+
+    fn insert(&mut self, ip: IpAddr, decision: Decision) {
+        // 已满时不再缓存新来源（过期条目由定时任务清理），只是少了缓存的收益
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&ip) {
+            return;
+        }
+        self.entries.insert(ip, (decision, Instant::now()));
+    }
+}
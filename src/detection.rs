@@ -0,0 +1,140 @@
+use crate::ingest::ExternalSignal;
+use crate::reason::ReasonCode;
+use crate::sip_parser::SipRequest;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// 一次需要处置的检测结果
+#[derive(Debug, Clone)]
+pub struct Detection {
+    /// 网络层真实源 IP
+    pub source_ip: IpAddr,
+    /// 触发检测的 SIP 方法（无法解析时为 `-`）
+    pub method: String,
+    /// 触发检测的 User-Agent（无法解析时为空）
+    pub user_agent: String,
+    /// 机器可读的原因代码，如 `UA_NOT_ALLOWED`
+    pub reason: String,
+    /// 原因分类
+    pub code: ReasonCode,
+    /// 触发检测的规则名称（原因代码为 `RULE_MATCH` 时）、插件名称（`PLUGIN` 时）或脚本给出的说明（`SCRIPT` 时）
+    pub rule: Option<String>,
+    /// 被访问的目标地址所属的租户
+    pub tenant: Option<String>,
+    /// 被访问的本机 UDP 端口
+    pub dest_port: Option<u16>,
+    /// 读取线程收到触发检测的数据包的时刻（由抓包触发时）
+    pub captured_at: Option<Instant>,
+    /// 流水线判定封禁的时刻
+    pub detected_at: Option<Instant>,
+}
+
+impl Detection {
+    /// 由已解析的 SIP 请求生成检测结果
+    pub fn from_request(request: &SipRequest, reason: &str) -> Self {
+        Self {
+            source_ip: request.source_ip,
+            method: request.method.clone(),
+            user_agent: request.user_agent.clone(),
+            reason: reason.to_string(),
+            code: ReasonCode::classify(reason),
+            rule: None,
+            tenant: None,
+            dest_port: request.dest_port,
+            captured_at: None,
+            detected_at: None,
+        }
+    }
+
+    /// 无法解析出 SIP 请求时（如畸形报文）生成检测结果
+    pub fn from_source(source_ip: IpAddr, reason: &str) -> Self {
+        Self {
+            source_ip,
+            method: "-".to_string(),
+            user_agent: String::new(),
+            reason: reason.to_string(),
+            code: ReasonCode::classify(reason),
+            rule: None,
+            tenant: None,
+            dest_port: None,
+            captured_at: None,
+            detected_at: None,
+        }
+    }
+
+    /// 由外部系统上报的信号生成检测结果
+    pub fn from_signal(signal: &ExternalSignal) -> Self {
+        Self {
+            source_ip: signal.source_ip,
+            method: "-".to_string(),
+            user_agent: signal.user_agent.clone(),
+            reason: signal.reason.clone(),
+            code: ReasonCode::classify(&signal.reason),
+            rule: None,
+            tenant: None,
+            dest_port: None,
+            captured_at: None,
+            detected_at: None,
+        }
+    }
+
+    /// 记录触发检测的规则名称
+    pub fn with_rule(mut self, rule: &str) -> Self {
+        self.rule = Some(rule.to_string());
+        self
+    }
+
+    /// 覆盖按原因代码推断的分类（例如检测规则自己声明的分类）
+    pub fn with_code(mut self, code: ReasonCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// 记录被访问的租户
+    pub fn with_tenant(mut self, tenant: Option<&str>) -> Self {
+        self.tenant = tenant.map(str::to_string);
+        self
+    }
+
+    /// 记录被访问的本机 UDP 端口
+    pub fn with_dest_port(mut self, dest_port: u16) -> Self {
+        self.dest_port = Some(dest_port);
+        self
+    }
+
+    /// 记录抓包时刻（未记录过时）和判定时刻，用于统计封禁生效延迟
+    pub fn stamped(mut self, captured_at: Option<Instant>) -> Self {
+        self.captured_at = self.captured_at.or(captured_at);
+        self.detected_at.get_or_insert_with(Instant::now);
+        self
+    }
+
+    /// 原因代码对应的中文描述，用于日志
+    pub fn description(&self) -> String {
+        match self.reason.as_str() {
+            "UA_NOT_ALLOWED" => "UA 不在白名单中".to_string(),
+            "UA_DENIED" => "UA 命中黑名单".to_string(),
+            "MALFORMED_PACKET" => "持续发送畸形 SIP 报文".to_string(),
+            "BINARY_JUNK" => "持续向 SIP 端口发送二进制垃圾数据".to_string(),
+            "UA_RATE_EXCEEDED" => "UA 全局请求速率超限".to_string(),
+            "GREYLIST_VIOLATION" => "灰名单观察期内请求过多".to_string(),
+            "GREYLIST_HOLD" => "灰名单首次出现，临时丢弃".to_string(),
+            "UA_UNVERIFIED" => "白名单 UA 未通过二次验证且请求过多".to_string(),
+            "AUTH_FAILURE" => "PBX 报告多次认证失败".to_string(),
+            "ACL_DENIED" => "PBX 报告多次被 ACL 拒绝".to_string(),
+            "TLS_SYN_RATE" => "SIPS 端口新建连接过于频繁".to_string(),
+            "TLS_FINGERPRINT" => "TLS ClientHello 指纹在黑名单中".to_string(),
+            "AUTH_CHALLENGE" => "大量发起认证挑战却未完成认证".to_string(),
+            "MESSAGE_FLOOD" => "MESSAGE 请求过多（垃圾短信）".to_string(),
+            "SUBSCRIBE_FLOOD" => "SUBSCRIBE 请求过多".to_string(),
+            "NOTIFY_FLOOD" => "NOTIFY 请求过多".to_string(),
+            "RULE_MATCH" => format!("命中检测规则 {}", self.rule.as_deref().unwrap_or("-")),
+            "SCRIPT" => match &self.rule {
+                Some(note) => format!("Lua 脚本判定封禁（{}）", note),
+                None => "Lua 脚本判定封禁".to_string(),
+            },
+            "PLUGIN" => format!("检测插件 {} 判定封禁", self.rule.as_deref().unwrap_or("-")),
+            other => other.to_string(),
+        }
+    }
+}
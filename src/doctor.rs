@@ -0,0 +1,191 @@
+use crate::{default_backend, initialize_whitelist, open_firewall};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uablock_rust::config::Config;
+use uablock_rust::local_net::{LocalNetworks, TrustedSources};
+use uablock_rust::reconcile::{examine, ReconcileReport};
+#[cfg(feature = "sqlite")]
+use uablock_rust::sqlite_store::SqliteBanStore;
+use uablock_rust::state_file::StateFile;
+use uablock_rust::{Enforcer, EventBus, Stats};
+
+/// 核对参数
+#[derive(Debug)]
+struct DoctorOptions {
+    interface: String,
+    port: u16,
+    state_file: Option<PathBuf>,
+    repair: bool,
+    json: bool,
+}
+
+impl DoctorOptions {
+    fn parse(args: &[String], config: &Config) -> Result<Self, String> {
+        let mut options = Self {
+            interface: config
+                .interface
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            port: config.port,
+            state_file: std::env::var("UABLOCK_STATE_FILE")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            repair: false,
+            json: false,
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            match flag.as_str() {
+                "--repair" => {
+                    options.repair = true;
+                    continue;
+                }
+                "--json" => {
+                    options.json = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            match flag.as_str() {
+                "--interface" => options.interface = value.clone(),
+                "--port" => {
+                    options.port = value
+                        .parse()
+                        .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))?
+                }
+                "--state-file" => options.state_file = Some(PathBuf::from(value)),
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock doctor`：核对状态文件中的封禁记录、防火墙规则和当前配置
+///
+/// 直接读取状态文件（`--state-file`，默认 `UABLOCK_STATE_FILE`）和防火墙（`UABLOCK_BACKEND`，
+/// `--port` 默认取配置文件，其次为 5060），守护进程未运行时同样可用。报告四类差异：有记录但防火墙中
+/// 没有规则的封禁、防火墙中没有记录的外来规则、按当前白名单/本机地址/局域网配置（`--interface`
+/// 默认为配置文件中的第一个接口，其次为 eth0）
+/// 不应封禁的 IP、已到期但仍有规则的封禁。`--repair` 补上缺失的封禁、解封不应封禁的 IP 并解除
+/// 到期的封禁（外来规则只报告），`--json` 输出原始报告。存在未修复的差异时退出码为 1。
+pub fn run(args: &[String], config: &Config) -> i32 {
+    let options = match DoctorOptions::parse(args, config) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    match diagnose(&options, config) {
+        Ok((report, repaired)) => {
+            if options.json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return 1;
+                    }
+                }
+            } else {
+                print_report(&report);
+                if let Some(repaired) = repaired {
+                    println!("已修复 {} 处差异", repaired);
+                }
+            }
+            i32::from(!report.is_clean() && repaired.is_none())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// 执行核对，`--repair` 时返回修复的条数
+fn diagnose(
+    options: &DoctorOptions,
+    config: &Config,
+) -> Result<(ReconcileReport, Option<usize>), String> {
+    let backend =
+        std::env::var("UABLOCK_BACKEND").unwrap_or_else(|_| default_backend().to_string());
+    let stats = Arc::new(Stats::default());
+    let firewall = open_firewall(&backend, options.port, &stats)?
+        .ok_or("未启用内置封禁（UABLOCK_BACKEND=none），没有可核对的规则")?;
+    let mut enforcer = Enforcer::new(Some(firewall), None, stats, Arc::new(EventBus::new()));
+    if let Some(trusted) = TrustedSources::from_env()? {
+        enforcer.set_trusted_sources(trusted);
+    }
+    // 封禁数据库（UABLOCK_BAN_DB）中的记录优先于状态文件
+    #[cfg(feature = "sqlite")]
+    let has_store = match SqliteBanStore::from_env()? {
+        Some(store) => {
+            enforcer.bans().restore(&store.load()?);
+            true
+        }
+        None => false,
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let has_store = false;
+    if let Some(path) = &options.state_file {
+        if let Some(state) = StateFile::new(path).load()? {
+            enforcer.bans().restore(&state.bans);
+        }
+    }
+    let networks = LocalNetworks::from_env(&options.interface)?;
+    let mut report = examine(&enforcer, &initialize_whitelist(config)?, networks.as_ref())?;
+    if options.state_file.is_none() && !has_store {
+        eprintln!("未指定状态文件（--state-file 或 UABLOCK_STATE_FILE），只检查不应封禁的 IP");
+        report.foreign.clear();
+    }
+    let repaired = options.repair.then(|| report.repair(&enforcer));
+    Ok((report, repaired))
+}
+
+fn print_report(report: &ReconcileReport) {
+    if report.is_clean() {
+        println!("状态文件、防火墙规则和当前配置一致");
+        return;
+    }
+    if !report.missing.is_empty() {
+        println!("有记录但防火墙中没有规则（{} 个）:", report.missing.len());
+        for record in &report.missing {
+            println!(
+                "  {:<40} {} ({})",
+                record.ip.to_string(),
+                record.reason.reason,
+                record.reason.origin
+            );
+        }
+    }
+    if !report.foreign.is_empty() {
+        println!("防火墙中没有记录的规则（{} 个）:", report.foreign.len());
+        for ip in &report.foreign {
+            println!("  {}", ip);
+        }
+    }
+    if !report.exempt.is_empty() {
+        println!("按当前配置不应封禁（{} 个）:", report.exempt.len());
+        for ban in &report.exempt {
+            println!("  {:<40} {}", ban.ip.to_string(), ban.note);
+        }
+    }
+    if !report.expired.is_empty() {
+        println!("已到期但仍有规则（{} 个）:", report.expired.len());
+        for record in &report.expired {
+            println!(
+                "  {:<40} {} ({})",
+                record.ip.to_string(),
+                record.reason.reason,
+                record.reason.origin
+            );
+        }
+    }
+}
@@ -0,0 +1,852 @@
+use crate::ban_latency::{LatencyAlert, BAN_LATENCY_HIGH};
+use crate::bans::{BanReason, BanRecord, BanTable};
+use crate::detection::Detection;
+use crate::enforcement_queue::{Action, EnforcementQueue};
+use crate::events::{Event, EventBus, EventKind};
+use crate::fail2ban::Fail2banLogger;
+use crate::firewall::FirewallBackend;
+use crate::ha::HaRole;
+use crate::local_net::{HostAddresses, Subnet, TrustedSources};
+use crate::offences::Escalation;
+use crate::reason::ReasonCode;
+use crate::rules::{MethodAction, MethodPolicy};
+use crate::state_file::unix_now;
+use crate::stats::Stats;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// 处置执行器：统一执行封禁/解封、写 fail2ban 日志、更新统计并发布事件
+///
+/// 主循环和各管理接口（HTTP API、gRPC）共用同一个执行器，保证
+/// 无论从哪里触发封禁，统计和事件都是一致的。
+pub struct Enforcer {
+    firewall: Option<Box<dyn FirewallBackend>>,
+    fail2ban: Option<Fail2banLogger>,
+    stats: Arc<Stats>,
+    events: Arc<EventBus>,
+    /// 是否根据本地检测结果直接封禁/解封（代理模式下由中央服务器决定）
+    local_enforcement: bool,
+    /// 处置队列（启用后检测触发的封禁和白名单解封由执行线程完成）
+    queue: OnceLock<Arc<EnforcementQueue>>,
+    /// 每个封禁 IP 的原因和命中次数
+    bans: BanTable,
+    /// 启动时间（Unix 秒），本次运行之前就存在的封禁按此时开始计算封禁时长
+    started_at: u64,
+    /// 本机地址，不会被封禁
+    host_addresses: Option<Arc<HostAddresses>>,
+    /// 可信来源，不会被封禁
+    trusted: Option<TrustedSources>,
+    /// 封禁生效延迟的 P99 告警
+    latency_alert: Option<LatencyAlert>,
+    /// SIP 方法 → 该方法触发的检测的处置方式
+    method_policies: HashMap<String, MethodPolicy>,
+    /// 检测触发的封禁的默认时长，None 表示永久（触发方法设置的时长优先）
+    ban_ttl: Option<Duration>,
+    /// 重复违规的封禁时长升级（优先于默认封禁时长）
+    escalation: Option<Escalation>,
+    /// 是否为备机：只维护封禁表，不下发防火墙规则
+    standby: AtomicBool,
+}
+
+impl Enforcer {
+    pub fn new(
+        firewall: Option<Box<dyn FirewallBackend>>,
+        fail2ban: Option<Fail2banLogger>,
+        stats: Arc<Stats>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Self {
+            firewall,
+            fail2ban,
+            stats,
+            events,
+            local_enforcement: true,
+            queue: OnceLock::new(),
+            bans: BanTable::default(),
+            started_at: unix_now(),
+            host_addresses: None,
+            trusted: None,
+            latency_alert: None,
+            method_policies: HashMap::new(),
+            ban_ttl: None,
+            escalation: None,
+            standby: AtomicBool::new(false),
+        }
+    }
+
+    /// 启用处置队列，并启动执行线程
+    ///
+    /// 之后检测触发的封禁、白名单解封和灰名单的临时丢弃/解除只放入队列（见 [`EnforcementQueue`]），检测任务不再等待
+    /// 防火墙命令；管理接口发起的封禁/解封仍然同步执行，并撤销该 IP 待执行的处置。
+    /// 执行线程在执行器被释放后退出。
+    pub fn start_queue(self: &Arc<Self>, capacity: usize) -> Result<(), String> {
+        let queue = Arc::new(EnforcementQueue::new(capacity, self.stats.clone()));
+        self.queue
+            .set(queue.clone())
+            .map_err(|_| "处置队列已启动".to_string())?;
+        let enforcer = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("enforcement".to_string())
+            .spawn(move || loop {
+                let action = queue.pop(Duration::from_secs(1));
+                let Some(enforcer) = enforcer.upgrade() else {
+                    break;
+                };
+                if let Some(action) = action {
+                    enforcer.execute(action);
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动处置线程失败: {}", e))
+    }
+
+    /// 执行一个排队的处置
+    fn execute(&self, action: Action) {
+        let Some(firewall) = self.firewall() else {
+            return;
+        };
+        match action {
+            Action::Ban(detection) => {
+                self.block_if_needed(firewall, &detection);
+            }
+            Action::Unban { ip, user_agent } => self.unblock_now(firewall, ip, &user_agent),
+            Action::Hold(detection) => self.hold_now(firewall, &detection),
+            Action::Release(ip) => self.release_now(firewall, ip),
+            Action::Verify(ip) => {
+                self.verify_now(firewall, ip);
+            }
+        }
+    }
+
+    /// 复核已封禁来源的防火墙规则：规则已被外部删除时清除封禁记录并返回 false
+    ///
+    /// 启用处置队列时只放入队列由执行线程复核（返回 true），检测任务不等待防火墙命令。
+    pub fn verify_rule(&self, ip: IpAddr) -> bool {
+        let Some(firewall) = self.firewall() else {
+            return true;
+        };
+        match self.queue.get() {
+            Some(queue) => {
+                queue.push(Action::Verify(ip));
+                true
+            }
+            None => self.verify_now(firewall, ip),
+        }
+    }
+
+    fn verify_now(&self, firewall: &dyn FirewallBackend, ip: IpAddr) -> bool {
+        if self.bans.get(&ip).is_none() || firewall.is_blocked(&ip) {
+            return true;
+        }
+        debug!("【已封禁】IP: {} 的封禁规则已不存在，恢复正常处理", ip);
+        self.bans.remove(&ip);
+        false
+    }
+
+    /// 灰名单临时丢弃首次出现的来源：封禁并记入封禁表（不计入封禁次数、不发布事件），
+    /// 启用处置队列时只放入队列
+    pub fn hold(&self, detection: Detection) {
+        let Some(firewall) = self.firewall() else {
+            return;
+        };
+        match self.queue.get() {
+            Some(queue) => {
+                queue.push(Action::Hold(detection));
+            }
+            None => self.hold_now(firewall, &detection),
+        }
+    }
+
+    fn hold_now(&self, firewall: &dyn FirewallBackend, detection: &Detection) {
+        if firewall.is_blocked(&detection.source_ip) {
+            return;
+        }
+        match firewall.block_ip(&detection.source_ip) {
+            Ok(()) => self
+                .bans
+                .insert(detection.source_ip, BanReason::from_detection(detection)),
+            Err(e) => error!("【灰名单】临时丢弃失败: {}", e),
+        }
+    }
+
+    /// 解除到期的灰名单临时丢弃，启用处置队列时只放入队列
+    pub fn release(&self, ip: IpAddr) {
+        let Some(firewall) = self.firewall() else {
+            return;
+        };
+        match self.queue.get() {
+            Some(queue) => {
+                queue.push(Action::Release(ip));
+            }
+            None => self.release_now(firewall, ip),
+        }
+    }
+
+    fn release_now(&self, firewall: &dyn FirewallBackend, ip: IpAddr) {
+        match firewall.unblock_ip(&ip) {
+            Ok(_) => {
+                self.bans.remove(&ip);
+                info!("【灰名单】IP: {} 临时丢弃到期，已解除", ip);
+            }
+            Err(e) => error!("【灰名单】解除 IP {} 临时丢弃失败: {}", ip, e),
+        }
+    }
+
+    /// 关闭本地处置：检测结果只记录和发布事件，由外部（中央服务器）下发封禁
+    #[allow(dead_code)]
+    pub fn set_local_enforcement(&mut self, enabled: bool) {
+        self.local_enforcement = enabled;
+    }
+
+    /// 不封禁本机地址，无论封禁来自检测、管理接口还是集群同步
+    pub fn set_host_addresses(&mut self, addresses: Arc<HostAddresses>) {
+        self.host_addresses = Some(addresses);
+    }
+
+    /// 不封禁可信来源，无论封禁来自检测、管理接口还是集群同步
+    pub fn set_trusted_sources(&mut self, trusted: TrustedSources) {
+        self.trusted = Some(trusted);
+    }
+
+    /// 最近封禁的 P99 生效延迟超过上限时告警
+    pub fn set_latency_alert(&mut self, alert: LatencyAlert) {
+        self.latency_alert = Some(alert);
+    }
+
+    /// 按触发检测的 SIP 方法设置处置方式（只告警或限时封禁）
+    pub fn set_method_policies(&mut self, policies: HashMap<String, MethodPolicy>) {
+        self.method_policies = policies;
+    }
+
+    /// 检测触发的封禁在 `ttl` 后到期解封（触发方法设置了封禁时长的以方法为准）
+    pub fn set_ban_ttl(&mut self, ttl: Duration) {
+        self.ban_ttl = Some(ttl);
+    }
+
+    /// 检测触发的封禁的默认时长，None 表示永久
+    pub fn ban_ttl(&self) -> Option<Duration> {
+        self.ban_ttl
+    }
+
+    /// 按违规次数升级检测触发的封禁时长
+    pub fn set_escalation(&mut self, escalation: Escalation) {
+        self.escalation = Some(escalation);
+    }
+
+    pub fn escalation(&self) -> Option<&Escalation> {
+        self.escalation.as_ref()
+    }
+
+    /// 设置主备角色，备机在升为主机前不下发防火墙规则
+    pub fn set_role(&mut self, role: HaRole) {
+        *self.standby.get_mut() = role == HaRole::Standby;
+    }
+
+    /// 当前的主备角色
+    pub fn role(&self) -> HaRole {
+        if self.is_standby() {
+            HaRole::Standby
+        } else {
+            HaRole::Active
+        }
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Acquire)
+    }
+
+    /// 备机升为主机：为封禁表中尚未到期的封禁下发防火墙规则，返回新下发的条数
+    ///
+    /// 本机地址和已到期的记录直接清除；单个 IP 封禁失败时记录日志并继续，记录保留，
+    /// 之后的核对或再次触发检测时补上。
+    pub fn promote(&self) -> Result<usize, String> {
+        let firewall = self.firewall.as_deref().ok_or("未启用内置封禁")?;
+        // 先切换角色，之后的检测直接封禁，与补齐规则并行也不会遗漏
+        if !self.standby.swap(false, Ordering::AcqRel) {
+            return Err("本机已是主机".to_string());
+        }
+        let now = unix_now();
+        let mut programmed = 0;
+        for record in self.bans.records() {
+            if self.is_host_address(&record.ip)
+                || self.is_trusted(&record.ip)
+                || record.reason.expires_at.is_some_and(|t| t <= now)
+            {
+                self.bans.remove(&record.ip);
+                continue;
+            }
+            if firewall.is_blocked(&record.ip) {
+                continue;
+            }
+            match firewall.block_ip(&record.ip) {
+                Ok(()) => {
+                    Stats::incr(&self.stats.bans);
+                    programmed += 1;
+                }
+                Err(e) => error!("【主备切换】封禁 IP: {} 失败: {}", record.ip, e),
+            }
+        }
+        warn!(
+            "【主备切换】本机已升为主机，按封禁表下发 {} 条封禁规则",
+            programmed
+        );
+        Ok(programmed)
+    }
+
+    /// 检测的处置方式；检测规则的 `ban` 是明确的判定，不受影响
+    fn method_policy(&self, detection: &Detection) -> Option<MethodPolicy> {
+        if detection.reason == "RULE_MATCH" {
+            return None;
+        }
+        self.method_policies.get(&detection.method).copied()
+    }
+
+    /// IP 是否为本机地址
+    pub fn is_host_address(&self, ip: &IpAddr) -> bool {
+        self.host_addresses
+            .as_ref()
+            .is_some_and(|addresses| addresses.contains(ip))
+    }
+
+    /// IP 所在的可信子网
+    pub fn trusted_source(&self, ip: &IpAddr) -> Option<Subnet> {
+        self.trusted
+            .as_ref()
+            .and_then(|trusted| trusted.matches(ip))
+    }
+
+    /// IP 是否为可信来源
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_source(ip).is_some()
+    }
+
+    /// 防火墙后端（未启用内置封禁或本机为备机时为 None）
+    pub fn firewall(&self) -> Option<&dyn FirewallBackend> {
+        if self.is_standby() {
+            return None;
+        }
+        self.firewall.as_deref()
+    }
+
+    /// 封禁原因表
+    pub fn bans(&self) -> &BanTable {
+        &self.bans
+    }
+
+    #[allow(dead_code)]
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.stats
+    }
+
+    #[allow(dead_code)]
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    /// 处置一次检测：写入 fail2ban 日志，并在启用内置封禁时封禁来源
+    ///
+    /// 返回 true 表示本次新封禁了来源；启用处置队列时表示新排队了封禁。
+    pub fn handle_detection(&self, detection: &Detection) -> bool {
+        if self
+            .method_policy(detection)
+            .is_some_and(|policy| policy.action == MethodAction::Alert)
+        {
+            self.alert(detection, &format!("{} 请求按策略只告警", detection.method));
+            return false;
+        }
+        Stats::incr(&self.stats.detections);
+        if let Some(port) = detection.dest_port {
+            self.stats.port(port, |port| port.detections += 1);
+        }
+        self.events
+            .publish(Event::from_detection(EventKind::Detection, detection));
+        if let Some(logger) = &self.fail2ban {
+            logger.log_detection(detection);
+        }
+        if !self.local_enforcement {
+            info!(
+                "【检测】User-Agent: '{}', IP: {}, 原因: [{}] {}（由中央服务器决定处置）",
+                detection.user_agent,
+                detection.source_ip,
+                detection.code,
+                detection.description()
+            );
+            return false;
+        }
+        if self.is_standby() {
+            return self.learn(detection);
+        }
+        match (self.firewall(), self.queue.get()) {
+            (Some(_), Some(queue)) => queue.push(Action::Ban(detection.clone())),
+            (Some(firewall), None) => self.block_if_needed(firewall, detection),
+            (None, _) => {
+                warn!(
+                    "【检测】User-Agent: '{}', IP: {}, 原因: [{}] {}（未启用内置封禁）",
+                    detection.user_agent,
+                    detection.source_ip,
+                    detection.code,
+                    detection.description()
+                );
+                false
+            }
+        }
+    }
+
+    /// 备机处置检测：只记入封禁表，升为主机时再下发规则
+    fn learn(&self, detection: &Detection) -> bool {
+        if self.is_host_address(&detection.source_ip) || self.is_trusted(&detection.source_ip) {
+            return false;
+        }
+        if self.bans.get(&detection.source_ip).is_some() {
+            self.bans.hit(&detection.source_ip);
+            return false;
+        }
+        self.bans
+            .insert(detection.source_ip, self.ban_reason(detection));
+        info!(
+            "【备机】User-Agent: '{}', IP: {}, 原因: [{}] {}（记入封禁表，升为主机后生效）",
+            detection.user_agent,
+            detection.source_ip,
+            detection.code,
+            detection.description()
+        );
+        false
+    }
+
+    /// 检测触发的新封禁的记录，启用封禁升级时同时记一次违规
+    ///
+    /// 封禁时长依次取触发方法设置的时长、按违规次数升级的时长、默认封禁时长，都没有时永久封禁。
+    fn ban_reason(&self, detection: &Detection) -> BanReason {
+        let reason = BanReason::from_detection(detection);
+        let method_duration = self
+            .method_policy(detection)
+            .and_then(|policy| policy.ban_duration);
+        let duration = match &self.escalation {
+            Some(escalation) => {
+                let (count, step) = escalation.record(detection.source_ip);
+                info!(
+                    "【封禁升级】IP: {} 第 {} 次违规，封禁{}",
+                    detection.source_ip,
+                    count,
+                    step.map_or("永久".to_string(), |d| format!(" {} 秒", d.as_secs()))
+                );
+                method_duration.or(step)
+            }
+            None => method_duration.or(self.ban_ttl),
+        };
+        match duration {
+            Some(duration) => reason.expires_at(unix_now() + duration.as_secs()),
+            None => reason,
+        }
+    }
+
+    /// 只告警的检测：记录并发布 Alert 事件，不写 fail2ban 日志，也不封禁
+    pub fn alert(&self, detection: &Detection, note: &str) {
+        Stats::incr(&self.stats.detections);
+        Stats::incr(&self.stats.alerts);
+        if let Some(port) = detection.dest_port {
+            self.stats.port(port, |port| port.detections += 1);
+        }
+        self.events
+            .publish(Event::from_detection(EventKind::Alert, detection));
+        warn!(
+            "【仅告警】User-Agent: '{}', IP: {}, 原因: [{}] {}（{}）",
+            detection.user_agent,
+            detection.source_ip,
+            detection.code,
+            detection.description(),
+            note
+        );
+    }
+
+    /// 如果 IP 尚未被封禁则封禁，返回是否新封禁
+    fn block_if_needed(&self, firewall: &dyn FirewallBackend, detection: &Detection) -> bool {
+        if self.is_host_address(&detection.source_ip) {
+            warn!(
+                "【本机地址】User-Agent: '{}', IP: {} 触发检测（{}），本机地址不封禁",
+                detection.user_agent,
+                detection.source_ip,
+                detection.description()
+            );
+            return false;
+        }
+        if let Some(subnet) = self.trusted_source(&detection.source_ip) {
+            warn!(
+                "【可信来源】User-Agent: '{}', IP: {} 触发检测（{}），在可信来源 {} 中，不封禁",
+                detection.user_agent,
+                detection.source_ip,
+                detection.description(),
+                subnet
+            );
+            return false;
+        }
+        if firewall.is_blocked(&detection.source_ip) {
+            self.bans.hit(&detection.source_ip);
+            debug!(
+                "User-Agent '{}' 触发检测（{}），IP {} 已被封禁，无需重复封禁",
+                detection.user_agent,
+                detection.description(),
+                detection.source_ip
+            );
+            return false;
+        }
+
+        warn!(
+            "【封禁】User-Agent: '{}', IP: {}, 原因: [{}] {}",
+            detection.user_agent,
+            detection.source_ip,
+            detection.code,
+            detection.description()
+        );
+        match firewall.block_ip(&detection.source_ip) {
+            Ok(_) => {
+                Stats::incr(&self.stats.bans);
+                if let Some(port) = detection.dest_port {
+                    self.stats.port(port, |port| port.bans += 1);
+                }
+                self.bans
+                    .insert(detection.source_ip, self.ban_reason(detection));
+                self.events
+                    .publish(Event::from_detection(EventKind::Ban, detection));
+                info!(
+                    "【封禁成功】User-Agent: '{}', IP: {}",
+                    detection.user_agent, detection.source_ip
+                );
+                // 规则是否确实生效由定期核查（见 `rule_audit`）批量确认
+                self.record_latency(detection);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "【封禁失败】User-Agent: '{}', IP: {}, 错误: {}",
+                    detection.user_agent, detection.source_ip, e
+                );
+                false
+            }
+        }
+    }
+
+    /// UA 在白名单中时，如果 IP 已被封禁则解封
+    pub fn unblock_if_needed(&self, ip: IpAddr, user_agent: &str) {
+        if self.is_standby() && self.local_enforcement {
+            if self.bans.remove(&ip).is_some() {
+                info!(
+                    "【备机】User-Agent: '{}' 在白名单中，IP: {} 移出封禁表",
+                    user_agent, ip
+                );
+            }
+            return;
+        }
+        let firewall = match self.firewall() {
+            Some(firewall) if self.local_enforcement => firewall,
+            _ => return,
+        };
+        match self.queue.get() {
+            Some(queue) => {
+                queue.push(Action::Unban {
+                    ip,
+                    user_agent: user_agent.to_string(),
+                });
+            }
+            None => self.unblock_now(firewall, ip, user_agent),
+        }
+    }
+
+    fn unblock_now(&self, firewall: &dyn FirewallBackend, ip: IpAddr, user_agent: &str) {
+        if !firewall.is_blocked(&ip) {
+            debug!(
+                "User-Agent '{}' 在白名单中，IP {} 未被封禁，无需操作",
+                user_agent, ip
+            );
+            return;
+        }
+
+        info!(
+            "【解封】User-Agent: '{}', IP: {}, 原因: [{}] UA 在白名单中",
+            user_agent,
+            ip,
+            ReasonCode::Allowed
+        );
+        match firewall.unblock_ip(&ip) {
+            Ok(_) => {
+                Stats::incr(&self.stats.unbans);
+                self.bans.remove(&ip);
+                self.events.publish(Event::new(
+                    EventKind::Unban,
+                    ip,
+                    user_agent,
+                    "UA_ALLOWED",
+                    "engine",
+                ));
+                info!("【解封成功】User-Agent: '{}', IP: {}", user_agent, ip);
+            }
+            Err(e) => {
+                error!(
+                    "【解封失败】User-Agent: '{}', IP: {}, 错误: {}",
+                    user_agent, ip, e
+                );
+            }
+        }
+    }
+
+    /// 由管理接口或集群同步发起的封禁，IP 已被封禁时返回 false
+    #[allow(dead_code)]
+    pub fn ban(&self, ip: IpAddr, reason: BanReason) -> Result<bool, String> {
+        if self.is_host_address(&ip) {
+            return Err(format!("{} 是本机地址，不封禁", ip));
+        }
+        if let Some(subnet) = self.trusted_source(&ip) {
+            return Err(format!("{} 在可信来源 {} 中，不封禁", ip, subnet));
+        }
+        if self.is_standby() {
+            if self.bans.get(&ip).is_some() {
+                return Ok(false);
+            }
+            info!(
+                "【备机】{} 封禁 IP: {}, 原因: [{}] {}（记入封禁表，升为主机后生效）",
+                reason.origin, ip, reason.code, reason.reason
+            );
+            self.bans.insert(ip, reason);
+            return Ok(true);
+        }
+        let firewall = self.firewall().ok_or("未启用内置封禁")?;
+        self.cancel_queued(&ip);
+        if firewall.is_blocked(&ip) {
+            // 原因未知的已有规则（例如重启前的封禁）补上原因
+            if self.bans.get(&ip).is_none() {
+                self.bans.insert(ip, reason);
+            }
+            return Ok(false);
+        }
+        firewall.block_ip(&ip)?;
+        Stats::incr(&self.stats.bans);
+        self.events.publish(Event {
+            tenant: reason.tenant.clone(),
+            code: reason.code,
+            ..Event::new(
+                EventKind::Ban,
+                ip,
+                reason.user_agent.as_deref().unwrap_or(""),
+                &reason.reason,
+                &reason.origin,
+            )
+        });
+        info!(
+            "【{}】封禁 IP: {}, 原因: [{}] {}",
+            reason.origin, ip, reason.code, reason.reason
+        );
+        self.bans.insert(ip, reason);
+        Ok(true)
+    }
+
+    /// 由管理接口或集群同步发起的解封，IP 未被封禁时返回 false
+    #[allow(dead_code)]
+    pub fn unban(&self, ip: IpAddr, reason: &str, origin: &str) -> Result<bool, String> {
+        if self.is_standby() {
+            let removed = self.bans.remove(&ip).is_some();
+            if removed {
+                info!("【备机】{} 解封 IP: {}, 原因: {}", origin, ip, reason);
+            }
+            return Ok(removed);
+        }
+        let firewall = self.firewall().ok_or("未启用内置封禁")?;
+        // 撤销尚未执行的封禁，否则稍后仍会生效
+        if let Some(Action::Ban(_)) = self.cancel_queued(&ip) {
+            info!(
+                "【{}】撤销待执行的封禁 IP: {}, 原因: {}",
+                origin, ip, reason
+            );
+        }
+        if !firewall.is_blocked(&ip) {
+            return Ok(false);
+        }
+        firewall.unblock_ip(&ip)?;
+        Stats::incr(&self.stats.unbans);
+        self.bans.remove(&ip);
+        self.events
+            .publish(Event::new(EventKind::Unban, ip, "", reason, origin));
+        info!(
+            "【{}】解封 IP: {}, 原因: [{}] {}",
+            origin,
+            ip,
+            ReasonCode::classify(reason),
+            reason
+        );
+        Ok(true)
+    }
+
+    /// 列出当前封禁的 IP
+    #[allow(dead_code)]
+    pub fn list_bans(&self) -> Result<Vec<IpAddr>, String> {
+        // 备机没有规则，以封禁表为准
+        if self.is_standby() {
+            return Ok(self.bans.records().iter().map(|record| record.ip).collect());
+        }
+        let firewall = self.firewall().ok_or("未启用内置封禁")?;
+        firewall.list_blocked()
+    }
+
+    /// 列出当前封禁及其原因、命中次数和剩余时长
+    #[allow(dead_code)]
+    pub fn ban_records(&self) -> Result<Vec<BanRecord>, String> {
+        let blocked = self.list_bans()?;
+        let mut records = self.bans.reconcile(&blocked);
+        if let Some(geoip) = self.events.geoip() {
+            for record in &mut records {
+                record.geo = geoip.lookup(&record.ip);
+            }
+        }
+        Ok(records)
+    }
+
+    /// 批量解封封禁时长达到 `min_age` 的 IP（None 表示全部解封），返回已解封的 IP
+    ///
+    /// 本次运行之前就存在的封禁不知道封禁时间，按启动时间计算。单个 IP 解封失败时
+    /// 记录日志并继续处理其余 IP。
+    pub fn unban_older_than(
+        &self,
+        min_age: Option<Duration>,
+        reason: &str,
+        origin: &str,
+    ) -> Result<Vec<IpAddr>, String> {
+        let now = unix_now();
+        let mut unbanned = Vec::new();
+        for record in self.ban_records()? {
+            let banned_at = record.banned_at.unwrap_or(self.started_at);
+            if min_age.is_some_and(|age| now.saturating_sub(banned_at) < age.as_secs()) {
+                continue;
+            }
+            match self.unban(record.ip, reason, origin) {
+                Ok(true) => unbanned.push(record.ip),
+                Ok(false) => {}
+                Err(e) => error!("【{}】解封 IP: {} 失败: {}", origin, record.ip, e),
+            }
+        }
+        Ok(unbanned)
+    }
+
+    /// 解封到期的限时封禁（检测按默认封禁时长或触发方法设置了封禁时长的），返回已解封的 IP
+    ///
+    /// API、webhook、集群同步等其他来源的限时封禁由各自的模块负责到期解封。
+    pub fn expire_bans(&self) -> Vec<IpAddr> {
+        let now = unix_now();
+        let due: Vec<IpAddr> = self
+            .bans
+            .records()
+            .into_iter()
+            .filter(|record| {
+                record.reason.origin == "engine"
+                    && record.reason.expires_at.is_some_and(|t| t <= now)
+            })
+            .map(|record| record.ip)
+            .collect();
+        let mut unbanned = Vec::new();
+        for ip in due {
+            match self.unban(ip, "EXPIRED", "expiry") {
+                Ok(true) => unbanned.push(ip),
+                // 规则已不存在（例如被手动删除），只清理记录
+                Ok(false) => {
+                    self.bans.remove(&ip);
+                }
+                Err(e) => error!("【到期解封】解封 IP: {} 失败: {}", ip, e),
+            }
+        }
+        unbanned
+    }
+
+    /// 到期解封：每隔 `interval` 解封到期的检测封禁（见 [`Enforcer::expire_bans`]）
+    ///
+    /// 在后台线程中执行，执行器被释放后退出。
+    pub fn start_expiry(self: &Arc<Self>, interval: Duration) -> Result<(), String> {
+        let enforcer = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("ban-expiry".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(enforcer) = enforcer.upgrade() else {
+                    break;
+                };
+                let unbanned = enforcer.expire_bans();
+                if !unbanned.is_empty() {
+                    info!("【到期解封】解封 {} 个封禁到期的 IP", unbanned.len());
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动到期解封线程失败: {}", e))
+    }
+
+    /// 定期大赦：每隔 `interval` 解封封禁时长超过 `max_age` 的 IP，避免长期运行后规则表无限增长
+    ///
+    /// 在后台线程中执行，执行器被释放后退出。
+    pub fn start_amnesty(
+        self: &Arc<Self>,
+        max_age: Duration,
+        interval: Duration,
+    ) -> Result<(), String> {
+        let enforcer = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("amnesty".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(enforcer) = enforcer.upgrade() else {
+                    break;
+                };
+                match enforcer.unban_older_than(Some(max_age), "AMNESTY", "amnesty") {
+                    Ok(unbanned) if !unbanned.is_empty() => info!(
+                        "【大赦】解封 {} 个封禁超过 {} 秒的 IP",
+                        unbanned.len(),
+                        max_age.as_secs()
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!("【大赦】无法列出封禁: {}", e),
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动大赦线程失败: {}", e))
+    }
+
+    /// 统计检测触发的封禁从抓包、判定到规则生效的延迟，P99 超限时告警
+    fn record_latency(&self, detection: &Detection) {
+        let Some(detected_at) = detection.detected_at else {
+            return;
+        };
+        let now = Instant::now();
+        let latency = &self.stats.ban_latency;
+        let mut exposure = now.saturating_duration_since(detected_at);
+        latency.detect_to_block.record(exposure);
+        if let Some(captured_at) = detection.captured_at {
+            latency
+                .capture_to_detect
+                .record(detected_at.saturating_duration_since(captured_at));
+            exposure = now.saturating_duration_since(captured_at);
+            latency.capture_to_block.record(exposure);
+        }
+        let Some(alert) = &self.latency_alert else {
+            return;
+        };
+        if let Some(p99) = alert.observe(exposure) {
+            error!(
+                "【封禁延迟】最近封禁的 P99 生效延迟 {} 毫秒，超过上限 {} 毫秒（本次 IP: {}，{} 毫秒）",
+                p99.as_millis(),
+                alert.bound().as_millis(),
+                detection.source_ip,
+                exposure.as_millis()
+            );
+            self.events.publish(Event::new(
+                EventKind::Alert,
+                detection.source_ip,
+                &detection.user_agent,
+                BAN_LATENCY_HIGH,
+                "ban-latency",
+            ));
+        }
+    }
+
+    fn cancel_queued(&self, ip: &IpAddr) -> Option<Action> {
+        self.queue.get().and_then(|queue| queue.cancel(ip))
+    }
+}
@@ -0,0 +1,245 @@
+use crate::detection::Detection;
+use crate::stats::Stats;
+use log::debug;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// 待执行的处置
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// 封禁检测到的来源
+    Ban(Detection),
+    /// UA 在白名单中，解封该来源
+    Unban { ip: IpAddr, user_agent: String },
+    /// 灰名单：首次出现的来源临时丢弃
+    Hold(Detection),
+    /// 灰名单：临时丢弃到期，解除
+    Release(IpAddr),
+    /// 复核已封禁来源的规则，规则已被外部删除时清除封禁记录
+    Verify(IpAddr),
+}
+
+impl Action {
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Action::Ban(detection) | Action::Hold(detection) => detection.source_ip,
+            Action::Unban { ip, .. } | Action::Release(ip) | Action::Verify(ip) => *ip,
+        }
+    }
+
+    fn is_unban(&self) -> bool {
+        matches!(self, Action::Unban { .. } | Action::Release(_))
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    /// 每个 IP 最多一个待执行的处置
+    actions: HashMap<IpAddr, Action>,
+    /// 解封优先执行
+    unbans: VecDeque<IpAddr>,
+    bans: VecDeque<IpAddr>,
+}
+
+impl Pending {
+    fn remove(&mut self, ip: &IpAddr) -> Option<Action> {
+        let action = self.actions.remove(ip)?;
+        let order = if action.is_unban() {
+            &mut self.unbans
+        } else {
+            &mut self.bans
+        };
+        order.retain(|queued| queued != ip);
+        Some(action)
+    }
+
+    fn insert(&mut self, action: Action) {
+        let ip = action.ip();
+        if action.is_unban() {
+            self.unbans.push_back(ip);
+        } else {
+            self.bans.push_back(ip);
+        }
+        self.actions.insert(ip, action);
+    }
+}
+
+/// 有界的处置队列：同一 IP 的处置合并为一个，解封优先，队列满时丢弃并计数
+///
+/// 一次扫描可能在一秒内产生上百个封禁判定，每个都要调用防火墙命令。检测任务只把处置放进
+/// 队列，由执行线程依次完成：
+///
+/// - 同一 IP 已有待执行的同类处置时直接合并（`stats.enforcement_coalesced`）
+/// - 同一 IP 的封禁和解封以最后一次为准；灰名单到期解除只取代临时丢弃，不取代封禁；
+///   规则复核让位于该 IP 的其他处置
+/// - 队列满时丢弃新的封禁（`stats.enforcement_drops`）；新的解封挤掉最近排队的封禁，
+///   合法来源不会因为扫描洪泛而迟迟不能解封
+pub struct EnforcementQueue {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+    capacity: usize,
+    stats: Arc<Stats>,
+}
+
+impl EnforcementQueue {
+    pub fn new(capacity: usize, stats: Arc<Stats>) -> Self {
+        Self {
+            pending: Mutex::new(Pending::default()),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+            stats,
+        }
+    }
+
+    /// 放入一个处置，返回 true 表示新排队了该处置（与待执行的同类处置合并或被丢弃时为 false）
+    pub fn push(&self, action: Action) -> bool {
+        let ip = action.ip();
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(queued) = pending.actions.get(&ip) {
+            Stats::incr(&self.stats.enforcement_coalesced);
+            // 排队中的处置执行后规则自然与记录一致，不必再复核；任何处置都取代排队中的复核
+            if matches!(action, Action::Verify(_)) {
+                return false;
+            }
+            // 检测触发的封禁取代排队中的灰名单临时丢弃，其余同类处置合并
+            let upgrade = matches!(
+                (queued, &action),
+                (Action::Hold(_), Action::Ban(_)) | (Action::Verify(_), _)
+            );
+            // 灰名单到期解除只撤销临时丢弃，不能取代排队中的封禁
+            let stale_release = matches!((queued, &action), (Action::Ban(_), Action::Release(_)));
+            if stale_release || (queued.is_unban() == action.is_unban() && !upgrade) {
+                return false;
+            }
+            pending.remove(&ip);
+            pending.insert(action);
+            return true;
+        }
+
+        if pending.actions.len() >= self.capacity {
+            let evicted = if action.is_unban() {
+                pending.bans.pop_back()
+            } else {
+                None
+            };
+            Stats::incr(&self.stats.enforcement_drops);
+            match evicted {
+                Some(evicted) => {
+                    debug!("处置队列已满，丢弃 IP {} 的封禁，优先解封 {}", evicted, ip);
+                    pending.actions.remove(&evicted);
+                }
+                None => {
+                    debug!("处置队列已满，丢弃 IP {} 的处置", ip);
+                    return false;
+                }
+            }
+        }
+        pending.insert(action);
+        Stats::set(
+            &self.stats.enforcement_pending,
+            pending.actions.len() as u64,
+        );
+        self.ready.notify_one();
+        true
+    }
+
+    /// 撤销某个 IP 待执行的处置（管理接口直接封禁/解封时使用），返回被撤销的处置
+    pub fn cancel(&self, ip: &IpAddr) -> Option<Action> {
+        let mut pending = self.pending.lock().unwrap();
+        let action = pending.remove(ip);
+        Stats::set(
+            &self.stats.enforcement_pending,
+            pending.actions.len() as u64,
+        );
+        action
+    }
+
+    /// 取出下一个处置（解封优先），最多等待 `timeout`
+    pub fn pop(&self, timeout: Duration) -> Option<Action> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.actions.is_empty() {
+            pending = self.ready.wait_timeout(pending, timeout).unwrap().0;
+        }
+        let ip = pending
+            .unbans
+            .pop_front()
+            .or_else(|| pending.bans.pop_front())?;
+        let action = pending.actions.remove(&ip);
+        Stats::set(
+            &self.stats.enforcement_pending,
+            pending.actions.len() as u64,
+        );
+        action
+    }
+
+    /// 待执行的处置数
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANNER: &str = "203.0.113.9";
+    const PHONE: &str = "198.51.100.7";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn rule_checks_yield_to_other_actions_for_the_same_ip() {
+        let stats = Arc::new(Stats::default());
+        let queue = EnforcementQueue::new(4, stats.clone());
+        let scanner: IpAddr = "203.0.113.9".parse().unwrap();
+        let ban = Action::Ban(Detection::from_source(scanner, "UA_NOT_ALLOWED"));
+
+        // 已有排队的封禁时不再复核
+        assert!(queue.push(ban.clone()));
+        assert!(!queue.push(Action::Verify(scanner)));
+        assert!(matches!(queue.pop(Duration::ZERO), Some(Action::Ban(_))));
+
+        // 排队中的复核被之后的处置取代
+        assert!(queue.push(Action::Verify(scanner)));
+        assert!(!queue.push(Action::Verify(scanner)));
+        assert!(queue.push(ban));
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(queue.pop(Duration::ZERO), Some(Action::Ban(_))));
+        assert_eq!(stats.enforcement_coalesced.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn enforcement_queue_coalesces_and_runs_unbans_first() {
+        let stats = Arc::new(Stats::default());
+        let queue = EnforcementQueue::new(2, stats.clone());
+        let ban = |source: &str| Action::Ban(Detection::from_source(ip(source), "UA_NOT_ALLOWED"));
+        let unban = |source: &str| Action::Unban {
+            ip: ip(source),
+            user_agent: "MicroSIP/3.21.3".to_string(),
+        };
+
+        assert!(queue.push(ban(SCANNER)));
+        assert!(!queue.push(ban(SCANNER)));
+        assert!(queue.push(ban("203.0.113.10")));
+        // 队列已满：新的封禁被丢弃，解封挤掉最近排队的封禁
+        assert!(!queue.push(ban("203.0.113.11")));
+        assert!(queue.push(unban(PHONE)));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(stats.enforcement_coalesced.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.enforcement_drops.load(Ordering::Relaxed), 2);
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop(Duration::ZERO))
+            .map(|action| (matches!(action, Action::Unban { .. }), action.ip()))
+            .collect();
+        assert_eq!(order, vec![(true, ip(PHONE)), (false, ip(SCANNER))]);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::ingest::ExternalSignal;
+use crate::packet_capture::CapturedPacket;
+use crate::pipeline::{PacketOutcome, Pipeline};
+use crate::state_file::{DetectionState, SourceRecord, StateFile};
+use log::{debug, error, info, warn};
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+/// 执行定时工作（灰名单到期解除、周期性维护）的间隔
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 来源超过该时间没有请求即不再跟踪
+const SOURCE_IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// 升级交接：触发后把完整状态写入快照文件并退出
+struct Handover {
+    path: PathBuf,
+    trigger: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+/// 检测任务的运行状态：流水线和定时器
+///
+/// 所有状态都归引擎所有，没有全局变量；来源最近活动时间记录在策略的
+/// [`SourceTable`](crate::sources::SourceTable) 中，与惩罚分等按来源的状态放在一起。守护进程通过 [`Engine::run`] 驱动；测试可以直接调用
+/// [`Engine::handle_packet`]、[`Engine::handle_signal`]、[`Engine::tick`] 和 [`Engine::prune`]。
+pub struct Engine {
+    pipeline: Pipeline,
+    handover: Option<Handover>,
+}
+
+impl Engine {
+    pub fn new(pipeline: Pipeline) -> Self {
+        Self {
+            pipeline,
+            handover: None,
+        }
+    }
+
+    /// `trigger` 完成时把完整状态写入 `path` 并停止，新版本以该快照启动即可无损接续
+    pub fn handover_on(
+        mut self,
+        path: &Path,
+        trigger: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        self.handover = Some(Handover {
+            path: path.to_path_buf(),
+            trigger: Box::pin(trigger),
+        });
+        self
+    }
+
+    /// 导出完整的检测状态（含跟踪的来源）
+    pub fn snapshot(&self) -> DetectionState {
+        let now = Instant::now();
+        DetectionState {
+            sources: self.pipeline.policy().sources().collect(|ip, state| {
+                state.last_seen.map(|seen| SourceRecord {
+                    ip: *ip,
+                    idle_secs: now.saturating_duration_since(seen).as_secs_f64(),
+                })
+            }),
+            ..self.pipeline.snapshot()
+        }
+    }
+
+    /// 从快照恢复完整的检测状态
+    pub fn restore(&mut self, state: &DetectionState) {
+        self.pipeline.restore(state);
+        let now = Instant::now();
+        let elapsed = state.elapsed_secs();
+        let sources = self.pipeline.policy().sources();
+        for record in &state.sources {
+            let Ok(idle) = Duration::try_from_secs_f64((record.idle_secs + elapsed).max(0.0))
+            else {
+                warn!(
+                    "忽略无效的来源记录: {}（idle_secs={}）",
+                    record.ip, record.idle_secs
+                );
+                continue;
+            };
+            if idle < SOURCE_IDLE_TIMEOUT {
+                let seen = now.checked_sub(idle).unwrap_or(now);
+                sources.update(record.ip, |source| source.last_seen = Some(seen));
+            }
+        }
+    }
+
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// 处理一个捕获到的数据包
+    pub fn handle_packet(&mut self, packet: &CapturedPacket) -> PacketOutcome {
+        let outcome = self.pipeline.process(packet);
+        if let PacketOutcome::Request { source_ip, .. } = &outcome {
+            self.pipeline
+                .policy()
+                .sources()
+                .update(*source_ip, |source| {
+                    source.last_seen = Some(Instant::now());
+                    source.requests += 1;
+                });
+        }
+        outcome
+    }
+
+    /// 处理外部系统上报的信号（计入惩罚分）
+    pub fn handle_signal(&mut self, signal: &ExternalSignal) {
+        self.pipeline.process_signal(signal);
+    }
+
+    /// 定时工作：交给流水线执行，周期性维护时同时清理长时间不活动的来源
+    ///
+    /// 返回 true 表示本次执行了周期性维护。
+    pub fn tick(&mut self) -> bool {
+        if !self.pipeline.tick() {
+            return false;
+        }
+        self.prune(Instant::now());
+        debug!(
+            "【运行统计】{:?}",
+            self.pipeline.enforcer().stats().snapshot()
+        );
+        true
+    }
+
+    /// 清理在 `now` 之前已超过一小时没有请求的来源
+    pub fn prune(&mut self, now: Instant) {
+        self.pipeline.policy().sources().retain(|_, source| {
+            if source
+                .last_seen
+                .is_some_and(|seen| now.saturating_duration_since(seen) >= SOURCE_IDLE_TIMEOUT)
+            {
+                source.last_seen = None;
+            }
+        });
+    }
+
+    /// 来源最近一次发出 SIP 请求的时间
+    pub fn last_seen(&self, ip: &IpAddr) -> Option<Instant> {
+        self.pipeline.policy().sources().get(ip)?.last_seen
+    }
+
+    /// 当前跟踪的来源数
+    pub fn tracked_sources(&self) -> usize {
+        self.pipeline
+            .policy()
+            .sources()
+            .collect(|_, source| source.last_seen)
+            .len()
+    }
+
+    /// 运行检测任务，直到 `shutdown` 完成、升级交接触发或数据包通道关闭；退出前保存检测状态
+    pub async fn run(
+        mut self,
+        mut packets: mpsc::Receiver<CapturedPacket>,
+        mut signals: mpsc::UnboundedReceiver<ExternalSignal>,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        tokio::pin!(shutdown);
+        let mut ticker = tokio::time::interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let (handover_path, mut handover) = match self.handover.take() {
+            Some(handover) => (Some(handover.path), handover.trigger),
+            None => (None, Box::pin(std::future::pending()) as Pin<Box<_>>),
+        };
+        let mut handing_over = false;
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("收到退出信号，停止监控");
+                    break;
+                }
+                _ = &mut handover => {
+                    info!("收到升级交接信号，保存快照后停止监控");
+                    handing_over = true;
+                    break;
+                }
+                _ = ticker.tick() => {
+                    self.tick();
+                }
+                Some(signal) = signals.recv() => self.handle_signal(&signal),
+                packet = packets.recv() => {
+                    let Some(packet) = packet else {
+                        error!("抓包线程已退出，停止监控");
+                        break;
+                    };
+                    self.handle_packet(&packet);
+                }
+            }
+        }
+        self.pipeline.save_state();
+        if let (true, Some(path)) = (handing_over, handover_path) {
+            match StateFile::new(&path).save(self.snapshot()) {
+                Ok(()) => info!(
+                    "升级快照已写入 {}，新版本可使用 --restore {} 启动",
+                    path.display(),
+                    path.display()
+                ),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+}
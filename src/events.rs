@@ -0,0 +1,264 @@
+use crate::detection::Detection;
+use crate::geoip::{GeoInfo, GeoIp};
+use crate::reason::ReasonCode;
+use crate::state_file::unix_now;
+use log::warn;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 默认的订阅者队列长度
+const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+/// 事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// 检测到需要处置的请求
+    Detection,
+    /// IP 被封禁
+    Ban,
+    /// IP 被解封
+    Unban,
+    /// 检测到但只告警、不处置（例如局域网设备）
+    Alert,
+}
+
+/// 封禁流程中产生的事件
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Unix 时间戳（秒）
+    pub timestamp: u64,
+    pub kind: EventKind,
+    pub ip: IpAddr,
+    pub user_agent: String,
+    /// 机器可读的原因代码
+    pub reason: String,
+    /// 原因分类，供下游按类别分支
+    pub code: ReasonCode,
+    /// 事件来源：engine（自动检测）、api、grpc 等
+    pub origin: String,
+    /// 被访问的目标地址所属的租户（配置了多租户时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// 被访问的本机 UDP 端口（由检测触发时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dest_port: Option<u16>,
+    /// 来源的国家、城市和 ASN（启用 GeoIP 时由事件总线补充）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoInfo>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, ip: IpAddr, user_agent: &str, reason: &str, origin: &str) -> Self {
+        Self {
+            timestamp: unix_now(),
+            kind,
+            ip,
+            user_agent: user_agent.to_string(),
+            reason: reason.to_string(),
+            code: ReasonCode::classify(reason),
+            origin: origin.to_string(),
+            tenant: None,
+            dest_port: None,
+            geo: None,
+        }
+    }
+
+    /// 本地检测产生的事件
+    pub fn from_detection(kind: EventKind, detection: &Detection) -> Self {
+        Self {
+            tenant: detection.tenant.clone(),
+            dest_port: detection.dest_port,
+            code: detection.code,
+            ..Self::new(
+                kind,
+                detection.source_ip,
+                &detection.user_agent,
+                &detection.reason,
+                "engine",
+            )
+        }
+    }
+}
+
+/// 订阅回调，返回 false 表示取消订阅
+type Subscriber = Box<dyn Fn(&Event) -> bool + Send>;
+
+/// 进程内事件总线
+///
+/// 发布是同步的，订阅者应尽快返回（例如只把事件放入 [`EventBus::queue`] 创建的队列），
+/// 不能在回调中执行阻塞操作。
+pub struct EventBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+    geoip: Option<Arc<GeoIp>>,
+    queue_capacity: usize,
+    usage: Arc<QueueUsage>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            subscribers: Mutex::default(),
+            geoip: None,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            usage: Arc::default(),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 发布前为事件补充来源的地理位置
+    pub fn with_geoip(mut self, geoip: Arc<GeoIp>) -> Self {
+        self.geoip = Some(geoip);
+        self
+    }
+
+    /// 订阅者队列的长度（默认 10000）
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn geoip(&self) -> Option<&Arc<GeoIp>> {
+        self.geoip.as_ref()
+    }
+
+    /// 注册订阅者
+    #[allow(dead_code)]
+    pub fn subscribe(&self, subscriber: impl Fn(&Event) -> bool + Send + 'static) {
+        self.subscribers.lock().unwrap().push(Box::new(subscriber));
+    }
+
+    /// 为订阅者的后台线程创建一个有界队列
+    ///
+    /// 队列已满（后台线程处理不过来）时丢弃新放入的条目，所有队列的积压数和丢弃数见
+    /// [`EventBus::queued`] 和 [`EventBus::dropped`]。
+    pub fn queue<T>(&self) -> (QueueSender<T>, QueueReceiver<T>) {
+        let (tx, rx) = mpsc::sync_channel(self.queue_capacity);
+        (
+            QueueSender {
+                tx,
+                usage: self.usage.clone(),
+            },
+            QueueReceiver {
+                rx,
+                usage: self.usage.clone(),
+            },
+        )
+    }
+
+    /// 各订阅者队列中尚未处理的条目数之和
+    pub fn queued(&self) -> usize {
+        self.usage.pending.load(Ordering::Relaxed)
+    }
+
+    /// 因队列已满被丢弃的条目数（累计）
+    pub fn dropped(&self) -> u64 {
+        self.usage.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 发布事件，自动移除已取消订阅的订阅者
+    pub fn publish(&self, mut event: Event) {
+        if let Some(geoip) = self.geoip.as_ref().filter(|_| event.geo.is_none()) {
+            event.geo = geoip.lookup(&event.ip);
+        }
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber(&event));
+    }
+}
+
+/// 同一事件总线上所有订阅者队列的用量
+#[derive(Default)]
+struct QueueUsage {
+    pending: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+/// 订阅者队列的发送端，见 [`EventBus::queue`]
+pub struct QueueSender<T> {
+    tx: SyncSender<T>,
+    usage: Arc<QueueUsage>,
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            usage: self.usage.clone(),
+        }
+    }
+}
+
+impl<T> QueueSender<T> {
+    /// 放入队列，队列已满时丢弃；返回 false 表示接收端已退出（应取消订阅）
+    pub fn send(&self, item: T) -> bool {
+        // 先计入积压，避免接收端在计数前取走条目
+        self.usage.pending.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(item) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.usage.pending.fetch_sub(1, Ordering::Relaxed);
+                let dropped = self.usage.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped == 1 || dropped.is_multiple_of(1000) {
+                    warn!(
+                        "【事件队列】订阅者处理不过来，队列已满，累计丢弃 {} 个条目",
+                        dropped
+                    );
+                }
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.usage.pending.fetch_sub(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+}
+
+/// 订阅者队列的接收端，见 [`EventBus::queue`]
+pub struct QueueReceiver<T> {
+    rx: Receiver<T>,
+    usage: Arc<QueueUsage>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// 等待下一个条目，所有发送端都已释放时返回 None
+    pub fn recv(&self) -> Option<T> {
+        let item = self.rx.recv().ok()?;
+        self.usage.pending.fetch_sub(1, Ordering::Relaxed);
+        Some(item)
+    }
+
+    /// 最多等待 `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let item = self.rx.recv_timeout(timeout)?;
+        self.usage.pending.fetch_sub(1, Ordering::Relaxed);
+        Ok(item)
+    }
+}
+
+impl<T> Iterator for QueueReceiver<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.recv()
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    /// 接收端退出时队列中剩余的条目不再计入积压
+    fn drop(&mut self) {
+        let remaining = self.rx.try_iter().count();
+        self.usage.pending.fetch_sub(remaining, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::detection::Detection;
+use log::{error, info};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// fail2ban 兼容的检测日志输出
+///
+/// 每次检测写入一行，格式固定（供 fail2ban filter 匹配，修改需保持兼容）：
+///
+/// ```text
+/// 2025-01-01 12:00:00 uablock[1234]: DETECTION src=1.2.3.4 method=REGISTER reason=UA_NOT_ALLOWED ua="friendly-scanner"
+/// ```
+///
+/// - 时间为本地时间，格式 `%Y-%m-%d %H:%M:%S`
+/// - `src` 为网络层真实源 IP
+/// - `method` 无法解析时为 `-`（如畸形报文）
+/// - `ua` 始终位于行尾，其中的双引号和控制字符会被替换为 `_`
+pub struct Fail2banLogger {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Fail2banLogger {
+    /// 以追加模式打开日志文件（不存在时自动创建）
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = Self::open_file(path)?;
+        info!("fail2ban 检测日志输出到: {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    fn open_file(path: &Path) -> Result<File, String> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("无法打开 fail2ban 日志文件 {}: {}", path.display(), e))
+    }
+
+    /// 写入一条检测记录
+    pub fn log_detection(&self, detection: &Detection) {
+        let line = format_line(
+            &chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            detection,
+        );
+
+        let mut file = self.file.lock().unwrap();
+        // logrotate 可能已经移走文件，写入前确认路径仍然存在，否则重新打开
+        if !self.path.exists() {
+            match Self::open_file(&self.path) {
+                Ok(new_file) => *file = new_file,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+        }
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("写入 fail2ban 日志失败: {}", e);
+        }
+    }
+}
+
+/// 生成单行检测记录
+fn format_line(timestamp: &str, detection: &Detection) -> String {
+    format!(
+        "{} uablock[{}]: DETECTION src={} method={} reason={} ua=\"{}\"",
+        timestamp,
+        std::process::id(),
+        detection.source_ip,
+        detection.method,
+        detection.reason,
+        sanitize(&detection.user_agent)
+    )
+}
+
+/// 替换双引号和控制字符，保证一条记录只占一行
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c == '"' || c.is_control() { '_' } else { c })
+        .collect()
+}
@@ -0,0 +1,192 @@
+use crate::firewall::FirewallBackend;
+use crate::stats::Stats;
+use log::{error, info, warn};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// 封禁后端连续失败多少次后视为不可用
+const DEFAULT_THRESHOLD: u32 = 3;
+
+/// 封禁后端不可用时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureAction {
+    /// 输出错误日志并在 `/health` 中报告降级，继续尝试原后端
+    Alert,
+    /// 切换到备用后端（直到重启）
+    Fallback,
+    /// 以非零状态退出，交给 systemd 等重启或升级处理
+    Exit,
+}
+
+impl FailureAction {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "alert" => Some(Self::Alert),
+            "fallback" => Some(Self::Fallback),
+            "exit" => Some(Self::Exit),
+            _ => None,
+        }
+    }
+}
+
+/// 带降级策略的封禁后端
+///
+/// 封禁后端可能在运行中失效（命令被删除、文件系统只读、锁一直被占用），此时只在日志里
+/// 报错而攻击仍在继续。封禁/解封连续失败达到阈值后按 [`FailureAction`] 处理；
+/// 任意一次成功都会清零失败计数并解除降级告警。`stats.firewall_degraded` 为 1 时
+/// `/health` 报告后端降级。
+pub struct GuardedFirewall {
+    primary: Box<dyn FirewallBackend>,
+    fallback: Option<Box<dyn FirewallBackend>>,
+    action: FailureAction,
+    threshold: u32,
+    stats: Arc<Stats>,
+    /// 原后端连续失败的次数
+    failures: AtomicU32,
+    /// 已切换到备用后端
+    on_fallback: AtomicBool,
+}
+
+impl GuardedFirewall {
+    /// 默认连续失败 3 次后告警
+    pub fn new(primary: Box<dyn FirewallBackend>, stats: Arc<Stats>) -> Self {
+        Self {
+            primary,
+            fallback: None,
+            action: FailureAction::Alert,
+            threshold: DEFAULT_THRESHOLD,
+            stats,
+            failures: AtomicU32::new(0),
+            on_fallback: AtomicBool::new(false),
+        }
+    }
+
+    /// 后端不可用时的处理方式；`Fallback` 需要同时设置备用后端
+    pub fn with_action(mut self, action: FailureAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// 备用后端
+    pub fn with_fallback(mut self, fallback: Box<dyn FirewallBackend>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// 连续失败多少次后视为不可用（至少 1 次）
+    pub fn with_threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold.max(1);
+        self
+    }
+
+    /// 是否已切换到备用后端
+    pub fn is_on_fallback(&self) -> bool {
+        self.on_fallback.load(Ordering::Relaxed)
+    }
+
+    fn active_fallback(&self) -> Option<&dyn FirewallBackend> {
+        self.fallback.as_deref().filter(|_| self.is_on_fallback())
+    }
+
+    /// 在原后端上执行封禁/解封；失败达到阈值时按降级策略处理
+    fn mutate(
+        &self,
+        ip: &IpAddr,
+        apply: impl Fn(&dyn FirewallBackend) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if let Some(fallback) = self.active_fallback() {
+            return apply(fallback);
+        }
+        let error = match apply(self.primary.as_ref()) {
+            Ok(()) => {
+                self.recovered();
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        Stats::incr(&self.stats.firewall_failures);
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < self.threshold {
+            return Err(error);
+        }
+        if failures == self.threshold {
+            Stats::set(&self.stats.firewall_degraded, 1);
+        }
+        match (self.action, self.fallback.as_deref()) {
+            (FailureAction::Fallback, Some(fallback)) => {
+                if !self.on_fallback.swap(true, Ordering::Relaxed) {
+                    error!(
+                        "【后端故障】封禁后端连续失败 {} 次（{}），切换到备用后端",
+                        failures, error
+                    );
+                }
+                apply(fallback).map_err(|e| format!("{}；备用后端也失败: {}", error, e))
+            }
+            (FailureAction::Exit, _) => {
+                error!(
+                    "【后端故障】处置 IP {} 时封禁后端连续失败 {} 次（{}），按配置退出",
+                    ip, failures, error
+                );
+                std::process::exit(1);
+            }
+            _ => {
+                if failures == self.threshold {
+                    error!(
+                        "【后端故障】封禁后端连续失败 {} 次（{}），封禁已失效，请尽快检查防火墙",
+                        failures, error
+                    );
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// 原后端恢复：清零失败计数并解除降级
+    fn recovered(&self) {
+        if self.failures.swap(0, Ordering::Relaxed) >= self.threshold {
+            Stats::set(&self.stats.firewall_degraded, 0);
+            info!("【后端恢复】封禁后端已恢复正常");
+        }
+    }
+}
+
+impl FirewallBackend for GuardedFirewall {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.mutate(ip, |backend| backend.block_ip(ip))
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        // 切换之前在原后端上的封禁，仍然从原后端解除
+        if let Some(fallback) = self.active_fallback() {
+            if !fallback.is_blocked(ip) && self.primary.is_blocked(ip) {
+                return self.primary.unblock_ip(ip);
+            }
+        }
+        self.mutate(ip, |backend| backend.unblock_ip(ip))
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        self.active_fallback()
+            .is_some_and(|fallback| fallback.is_blocked(ip))
+            || self.primary.is_blocked(ip)
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let Some(fallback) = self.active_fallback() else {
+            return self.primary.list_blocked();
+        };
+        let mut blocked = fallback.list_blocked()?;
+        match self.primary.list_blocked() {
+            Ok(primary) => {
+                for ip in primary {
+                    if !blocked.contains(&ip) {
+                        blocked.push(ip);
+                    }
+                }
+            }
+            Err(e) => warn!("无法列出原后端的封禁: {}", e),
+        }
+        Ok(blocked)
+    }
+}
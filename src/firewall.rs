@@ -0,0 +1,21 @@
+use std::net::IpAddr;
+
+/// 防火墙后端：按来源 IP 封禁/解封
+///
+/// 内置实现为 `IptablesManager`（`iptables` 特性）、[`IpsetManager`](crate::ipset::IpsetManager)、
+/// [`NftManager`](crate::nft::NftManager)、[`FirewalldManager`](crate::firewalld::FirewalldManager)、
+/// [`PfManager`](crate::pf::PfManager) 和经 SSH 在远端执行命令的 [`SshFirewall`](crate::ssh::SshFirewall)。
+/// 嵌入方可以实现该 trait，把处置交给自己的防火墙（云安全组、SBC 的黑名单等）。
+pub trait FirewallBackend: Send + Sync {
+    /// 封禁 IP
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String>;
+
+    /// 解封 IP
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String>;
+
+    /// IP 当前是否被封禁
+    fn is_blocked(&self, ip: &IpAddr) -> bool;
+
+    /// 列出当前封禁的 IP
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String>;
+}
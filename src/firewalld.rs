@@ -0,0 +1,242 @@
+use crate::firewall::FirewallBackend;
+use crate::ipset::{DEFAULT_MAXELEM, DEFAULT_SET};
+use log::{debug, info};
+use std::net::IpAddr;
+use std::process::{Command, Output};
+
+/// firewalld 后端：封禁的 IP 放在 firewalld 管理的 ipset 中，由区域中的一条富规则统一丢弃
+///
+/// firewalld 重新加载（`firewall-cmd --reload`）时会清掉直接用 iptables/nft 添加的规则，
+/// 也会丢弃只在运行时配置中的改动。本后端的集合、富规则和每个封禁都同时写入运行时和永久配置，
+/// 重新加载和重启 firewalld 后封禁仍然有效。[`FirewalldManager::setup`] 创建集合和富规则
+/// （已存在时不重复创建），有新建时执行一次重新加载使其生效。
+///
+/// 每次封禁执行两次 `firewall-cmd`（运行时和永久配置各一次），比 iptables/nft 后端慢，
+/// 由处置队列在专用线程中执行，不影响检测。
+pub struct FirewalldManager {
+    set_v4: String,
+    set_v6: String,
+    /// 富规则所在的区域，None 表示默认区域
+    zone: Option<String>,
+    block_port: Option<u16>,
+    maxelem: u32,
+}
+
+impl FirewalldManager {
+    pub fn new(block_port: Option<u16>) -> Self {
+        Self {
+            set_v4: DEFAULT_SET.to_string(),
+            set_v6: format!("{}6", DEFAULT_SET),
+            zone: None,
+            block_port,
+            maxelem: DEFAULT_MAXELEM,
+        }
+    }
+
+    /// 集合名（默认 uablock），IPv6 集合在其后加 `6`
+    pub fn with_set_name(mut self, name: &str) -> Self {
+        self.set_v4 = name.to_string();
+        self.set_v6 = format!("{}6", name);
+        self
+    }
+
+    /// 富规则所在的区域（默认为 firewalld 的默认区域）
+    pub fn with_zone(mut self, zone: &str) -> Self {
+        self.zone = Some(zone.to_string());
+        self
+    }
+
+    /// 新建集合的容量（默认 1048576；已存在的集合不受影响）
+    pub fn with_maxelem(mut self, maxelem: u32) -> Self {
+        self.maxelem = maxelem;
+        self
+    }
+
+    pub fn set_name(&self) -> &str {
+        &self.set_v4
+    }
+
+    /// 匹配集合的富规则
+    pub fn rich_rule(&self, set: &str) -> String {
+        let port = self
+            .block_port
+            .map(|port| format!(" port port=\"{}\" protocol=\"udp\"", port))
+            .unwrap_or_default();
+        format!("rule source ipset=\"{}\"{} drop", set, port)
+    }
+
+    /// 确认 firewalld 正在运行，创建集合和富规则（写入永久配置），有新建时重新加载
+    pub fn setup(&self) -> Result<(), String> {
+        self.check(&["--state"], "检查 firewalld 状态")?;
+        let existing = self.check(&["--permanent", "--get-ipsets"], "获取 firewalld 集合")?;
+        let existing: Vec<&str> = existing.split_whitespace().collect();
+        let mut changed = false;
+        for (set, family) in [(&self.set_v4, "inet"), (&self.set_v6, "inet6")] {
+            if existing.contains(&set.as_str()) {
+                continue;
+            }
+            self.check(
+                &[
+                    "--permanent",
+                    &format!("--new-ipset={}", set),
+                    "--type=hash:ip",
+                    &format!("--option=family={}", family),
+                    &format!("--option=maxelem={}", self.maxelem),
+                ],
+                &format!("创建 firewalld 集合 {}", set),
+            )?;
+            info!("已创建 firewalld 集合 {}（{}）", set, family);
+            changed = true;
+        }
+        for set in [&self.set_v4, &self.set_v6] {
+            let rule = self.rich_rule(set);
+            let query = format!("--query-rich-rule={}", rule);
+            if self.zoned(&["--permanent", &query])?.status.success() {
+                continue;
+            }
+            self.check_zoned(
+                &["--permanent", &format!("--add-rich-rule={}", rule)],
+                &format!("添加富规则 {}", rule),
+            )?;
+            info!("已添加 firewalld 富规则: {}", rule);
+            changed = true;
+        }
+        if changed {
+            self.check(&["--reload"], "重新加载 firewalld")?;
+            info!("firewalld 已重新加载");
+        }
+        info!(
+            "firewalld 后端已就绪（集合 {}/{}，区域 {}，端口 {}）",
+            self.set_v4,
+            self.set_v6,
+            self.zone.as_deref().unwrap_or("默认"),
+            self.block_port
+                .map_or("全部".to_string(), |p| p.to_string())
+        );
+        Ok(())
+    }
+
+    fn set_for(&self, ip: &IpAddr) -> &str {
+        match ip {
+            IpAddr::V4(_) => &self.set_v4,
+            IpAddr::V6(_) => &self.set_v6,
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<Output, String> {
+        debug!("执行 firewall-cmd 命令: firewall-cmd {}", args.join(" "));
+        Command::new("firewall-cmd")
+            .args(args)
+            .output()
+            .map_err(|e| format!("执行 firewall-cmd 命令失败: {}", e))
+    }
+
+    /// 在配置的区域中执行（未配置时不带 `--zone`，即默认区域）
+    fn zoned(&self, args: &[&str]) -> Result<Output, String> {
+        let zone = self.zone.as_ref().map(|zone| format!("--zone={}", zone));
+        let mut all: Vec<&str> = zone.iter().map(String::as_str).collect();
+        all.extend_from_slice(args);
+        self.run(&all)
+    }
+
+    /// 执行命令，成功时返回标准输出，失败时返回带说明的错误
+    fn check(&self, args: &[&str], action: &str) -> Result<String, String> {
+        output_text(self.run(args)?, action)
+    }
+
+    fn check_zoned(&self, args: &[&str], action: &str) -> Result<String, String> {
+        output_text(self.zoned(args)?, action)
+    }
+
+    /// 对集合条目同时修改运行时和永久配置
+    fn entry(&self, option: &str, ip: &IpAddr, action: &str) -> Result<(), String> {
+        let set = format!("--ipset={}", self.set_for(ip));
+        let entry = format!("--{}={}", option, ip);
+        self.check(&[&set, &entry], action)?;
+        self.check(&["--permanent", &set, &entry], action)?;
+        Ok(())
+    }
+
+    /// 解析 `firewall-cmd --ipset=<集合> --get-entries` 的输出（每行一个条目）
+    pub fn parse_entries(text: &str) -> Vec<IpAddr> {
+        text.split_whitespace()
+            .filter_map(|entry| entry.parse().ok())
+            .collect()
+    }
+}
+
+fn output_text(output: Output, action: &str) -> Result<String, String> {
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!(
+            "{}失败: {}",
+            action,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+impl FirewallBackend for FirewalldManager {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.entry("add-entry", ip, "firewalld 添加封禁")?;
+        info!("成功封禁 IP: {}", ip);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        if !self.is_blocked(ip) {
+            return Ok(());
+        }
+        self.entry("remove-entry", ip, "firewalld 解除封禁")?;
+        info!("成功解封 IP: {}", ip);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        let set = format!("--ipset={}", self.set_for(ip));
+        let entry = format!("--query-entry={}", ip);
+        matches!(self.run(&[&set, &entry]), Ok(output) if output.status.success())
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let mut ips = Vec::new();
+        for set in [&self.set_v4, &self.set_v6] {
+            let text = self.check(
+                &[&format!("--ipset={}", set), "--get-entries"],
+                &format!("获取 firewalld 集合 {}", set),
+            )?;
+            ips.extend(Self::parse_entries(&text));
+        }
+        Ok(ips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn firewalld_backend_matches_its_ipset_with_one_rich_rule() {
+        let manager = FirewalldManager::new(Some(5060));
+        assert_eq!(manager.set_name(), "uablock");
+        assert_eq!(
+            manager.rich_rule("uablock"),
+            r#"rule source ipset="uablock" port port="5060" protocol="udp" drop"#
+        );
+        assert_eq!(
+            FirewalldManager::new(None).rich_rule("uablock6"),
+            r#"rule source ipset="uablock6" drop"#
+        );
+        assert_eq!(
+            FirewalldManager::parse_entries("203.0.113.5\n2001:db8::7\n\n"),
+            vec![ip("203.0.113.5"), ip("2001:db8::7")]
+        );
+        Config::parse("backend = \"firewalld\"").unwrap();
+    }
+}
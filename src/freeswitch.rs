@@ -0,0 +1,249 @@
+use crate::ingest::{ExternalSignal, SignalSender};
+use crate::verification::AUTH_SUCCESS;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// 断线后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 读超时：订阅了 HEARTBEAT（默认 20 秒一次），超过该时间无数据视为连接失效
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 认证失败事件的子类
+const REGISTER_FAILURE: &str = "sofia::register_failure";
+
+/// 注册成功事件的子类
+const REGISTER_SUCCESS: &str = "sofia::register";
+
+/// FreeSWITCH Event Socket 客户端
+///
+/// 订阅 `sofia::register_failure` 事件，把 FreeSWITCH 判定的认证失败
+/// 作为高权重信号送入检测引擎；`sofia::register`（注册成功）作为认证成功信号，
+/// 用于白名单 UA 的二次验证。
+pub struct FreeswitchEsl {
+    address: String,
+    password: String,
+    weight: f64,
+    sender: SignalSender,
+}
+
+impl FreeswitchEsl {
+    /// 从环境变量创建，未设置 `UABLOCK_FREESWITCH_ESL` 时返回 None
+    ///
+    /// - `UABLOCK_FREESWITCH_ESL`：ESL 地址，例如 `127.0.0.1:8021`
+    /// - `UABLOCK_FREESWITCH_PASSWORD`：ESL 密码（默认 `ClueCon`）
+    /// - `UABLOCK_FREESWITCH_WEIGHT`：每次认证失败计入的惩罚分（默认 2.0）
+    pub fn from_env(sender: SignalSender) -> Option<Self> {
+        let address = std::env::var("UABLOCK_FREESWITCH_ESL")
+            .ok()
+            .filter(|a| !a.is_empty())?;
+        let password =
+            std::env::var("UABLOCK_FREESWITCH_PASSWORD").unwrap_or_else(|_| "ClueCon".to_string());
+        let weight = std::env::var("UABLOCK_FREESWITCH_WEIGHT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2.0);
+        Some(Self {
+            address,
+            password,
+            weight,
+            sender,
+        })
+    }
+
+    /// 在后台线程中运行，断线后自动重连
+    pub fn start(self) -> Result<(), String> {
+        info!("【FreeSWITCH】订阅 {} 的认证失败事件", self.address);
+        std::thread::Builder::new()
+            .name("freeswitch-esl".to_string())
+            .spawn(move || loop {
+                match self.session() {
+                    Ok(()) => return,
+                    Err(e) => warn!(
+                        "【FreeSWITCH】ESL 连接中断: {}，{} 秒后重连",
+                        e,
+                        RECONNECT_DELAY.as_secs()
+                    ),
+                }
+                std::thread::sleep(RECONNECT_DELAY);
+            })
+            .map_err(|e| format!("启动 FreeSWITCH ESL 线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 一次连接会话；主循环退出（通道关闭）时返回 Ok
+    fn session(&self) -> Result<(), String> {
+        let (mut stream, mut reader) = connect(&self.address, &self.password)?;
+
+        // 订阅认证失败、注册成功事件和心跳
+        send_command(
+            &mut stream,
+            &format!(
+                "event plain HEARTBEAT CUSTOM {} {}",
+                REGISTER_FAILURE, REGISTER_SUCCESS
+            ),
+        )?;
+        expect_ok(&mut reader, "订阅事件")?;
+        info!("【FreeSWITCH】已连接 ESL {}", self.address);
+
+        loop {
+            let (headers, body) = read_message(&mut reader)?;
+            match headers.get("Content-Type").map(String::as_str) {
+                Some("text/event-plain") => {}
+                Some("text/disconnect-notice") => return Err("FreeSWITCH 断开连接".to_string()),
+                _ => continue,
+            }
+            let event = parse_event(&body);
+            let (reason, weight) = match event.get("Event-Subclass").map(String::as_str) {
+                Some(REGISTER_FAILURE) => ("AUTH_FAILURE", self.weight),
+                Some(REGISTER_SUCCESS) => (AUTH_SUCCESS, 0.0),
+                _ => continue,
+            };
+            if let Some(signal) = Self::to_signal(&event, reason, weight) {
+                debug!(
+                    "【FreeSWITCH】{} IP: {}, User-Agent: '{}'",
+                    reason, signal.source_ip, signal.user_agent
+                );
+                if self.sender.send(signal).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// 把 register_failure / register 事件转换为信号
+    fn to_signal(
+        event: &HashMap<String, String>,
+        reason: &str,
+        weight: f64,
+    ) -> Option<ExternalSignal> {
+        let source_ip: IpAddr = event.get("network-ip")?.parse().ok()?;
+        Some(ExternalSignal {
+            source_ip,
+            user_agent: event.get("user-agent").cloned().unwrap_or_default(),
+            reason: reason.to_string(),
+            weight,
+            origin: "freeswitch".to_string(),
+        })
+    }
+}
+
+/// 连接 ESL 并认证
+fn connect(address: &str, password: &str) -> Result<(TcpStream, BufReader<TcpStream>), String> {
+    let addr = address
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("无法解析地址")?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, RECONNECT_DELAY).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+
+    // 认证
+    let (headers, _) = read_message(&mut reader)?;
+    if headers.get("Content-Type").map(String::as_str) != Some("auth/request") {
+        return Err("未收到 auth/request".to_string());
+    }
+    send_command(&mut stream, &format!("auth {}", password))?;
+    expect_ok(&mut reader, "认证")?;
+    Ok((stream, reader))
+}
+
+/// 连接 ESL 执行一条 `api` 命令，返回命令输出
+pub fn api(address: &str, password: &str, command: &str) -> Result<String, String> {
+    let (mut stream, mut reader) = connect(address, password)
+        .map_err(|e| format!("无法连接 FreeSWITCH ESL {}: {}", address, e))?;
+    send_command(&mut stream, &format!("api {}", command))?;
+    loop {
+        let (headers, body) = read_message(&mut reader)?;
+        if headers.get("Content-Type").map(String::as_str) == Some("api/response") {
+            return match body.strip_prefix("-ERR") {
+                Some(error) => Err(format!("命令 {} 失败:{}", command, error.trim_end())),
+                None => Ok(body),
+            };
+        }
+    }
+}
+
+/// 发送一条 ESL 命令（以空行结束）
+fn send_command(stream: &mut TcpStream, command: &str) -> Result<(), String> {
+    stream
+        .write_all(format!("{}\n\n", command).as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+/// 读取命令回复，要求 `Reply-Text` 以 `+OK` 开头
+fn expect_ok(reader: &mut impl BufRead, action: &str) -> Result<(), String> {
+    let (headers, _) = read_message(reader)?;
+    match headers.get("Reply-Text") {
+        Some(reply) if reply.starts_with("+OK") => Ok(()),
+        Some(reply) => Err(format!("{}失败: {}", action, reply)),
+        None => Err(format!("{}失败: 无回复", action)),
+    }
+}
+
+/// 读取一条 ESL 消息：头部（空行结束）和可选的 Content-Length 正文
+fn read_message(reader: &mut impl BufRead) -> Result<(HashMap<String, String>, String), String> {
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err("连接已关闭".to_string());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if headers.is_empty() {
+                continue;
+            }
+            break;
+        }
+        if let Some((key, value)) = line.split_once(": ") {
+            headers.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    let length: usize = headers
+        .get("Content-Length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok((headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// 解析 plain 格式的事件正文（每行 `Key: url 编码的值`）
+fn parse_event(body: &str) -> HashMap<String, String> {
+    body.lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value)| (key.to_string(), url_decode(value)))
+        .collect()
+}
+
+/// 百分号解码
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            if let Some(byte) = hex {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,565 @@
+use crate::bans::parse_duration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// 元数据段的起始标记
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// 搜索树与数据段之间的 16 个零字节
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// 数据解码的最大嵌套深度，防止损坏的文件造成无限递归
+const MAX_DEPTH: usize = 32;
+/// 默认的重新加载检查间隔
+const DEFAULT_RELOAD: &str = "1d";
+
+/// 来源 IP 的地理位置和所属网络
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoInfo {
+    /// 国家代码（ISO 3166-1）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// 城市（英文名称）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// 自治系统号
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// 自治系统所属组织
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+}
+
+impl GeoInfo {
+    /// 从 MaxMind 记录中提取（City/Country 库和 ASN 库的字段）
+    fn from_record(record: &Value) -> Self {
+        let text = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+        Self {
+            country: text(record.pointer("/country/iso_code"))
+                .or_else(|| text(record.pointer("/registered_country/iso_code"))),
+            city: text(record.pointer("/city/names/en")),
+            asn: record
+                .get("autonomous_system_number")
+                .and_then(Value::as_u64)
+                .and_then(|asn| u32::try_from(asn).ok()),
+            as_org: text(record.get("autonomous_system_organization")),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.country = self.country.take().or(other.country);
+        self.city = self.city.take().or(other.city);
+        self.asn = self.asn.or(other.asn);
+        self.as_org = self.as_org.take().or(other.as_org);
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 一行摘要，例如 `CN AS4134 Beijing`
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = [
+            self.country.clone(),
+            self.asn.map(|asn| format!("AS{}", asn)),
+            self.city.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        parts.join(" ")
+    }
+}
+
+/// 本地 MaxMind DB（GeoLite2/GeoIP2 的 `.mmdb` 文件）查询
+///
+/// 只用于给事件和封禁记录补充国家、城市和 ASN，便于排查，不参与判定。可以同时加载
+/// City（或 Country）库和 ASN 库，各库的字段合并。后台线程按间隔检查文件修改时间，
+/// 文件更新后重新加载；加载失败时继续使用旧数据。
+pub struct GeoIp {
+    databases: RwLock<Vec<Database>>,
+}
+
+struct Database {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    reader: Mmdb,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self, String> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let data = std::fs::read(path)
+            .map_err(|e| format!("无法读取 GeoIP 数据库 {}: {}", path.display(), e))?;
+        let reader = Mmdb::parse(data)
+            .map_err(|e| format!("GeoIP 数据库 {} 无效: {}", path.display(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified,
+            reader,
+        })
+    }
+}
+
+impl GeoIp {
+    pub fn open(paths: &[PathBuf]) -> Result<Self, String> {
+        let databases = paths
+            .iter()
+            .map(|path| Database::open(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            databases: RwLock::new(databases),
+        })
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_GEOIP_DB` 时返回 None
+    ///
+    /// - `UABLOCK_GEOIP_DB`：数据库文件路径，逗号分隔（如 City 库和 ASN 库）
+    /// - `UABLOCK_GEOIP_RELOAD`：检查文件更新的间隔（支持 `d`/`h`/`m`/`s`，默认 `1d`，`0` 表示不检查）
+    pub fn from_env() -> Result<Option<(Self, Option<Duration>)>, String> {
+        let paths: Vec<PathBuf> = std::env::var("UABLOCK_GEOIP_DB")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        if paths.is_empty() {
+            return Ok(None);
+        }
+        let reload =
+            std::env::var("UABLOCK_GEOIP_RELOAD").unwrap_or_else(|_| DEFAULT_RELOAD.to_string());
+        let reload = match reload.trim() {
+            "0" => None,
+            value => Some(
+                parse_duration(value)
+                    .ok_or_else(|| format!("UABLOCK_GEOIP_RELOAD 无效: {}", value))?,
+            ),
+        };
+        let geoip = Self::open(&paths)?;
+        info!("GeoIP 已启用: {:?}", paths);
+        Ok(Some((geoip, reload)))
+    }
+
+    /// 查询 IP，各数据库都没有记录时返回 None
+    pub fn lookup(&self, ip: &IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo::default();
+        for database in self.databases.read().unwrap().iter() {
+            match database.reader.lookup(ip) {
+                Ok(Some(record)) => info.merge(GeoInfo::from_record(&record)),
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "GeoIP 查询 {} 失败（{}）: {}",
+                    ip,
+                    database.path.display(),
+                    e
+                ),
+            }
+        }
+        Some(info).filter(|info| !info.is_empty())
+    }
+
+    /// 重新加载修改时间发生变化的数据库，返回重新加载的数量
+    pub fn reload(&self) -> usize {
+        let changed: Vec<(usize, PathBuf)> = self
+            .databases
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, database)| {
+                std::fs::metadata(&database.path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    != database.modified
+            })
+            .map(|(index, database)| (index, database.path.clone()))
+            .collect();
+        let mut reloaded = 0;
+        for (index, path) in changed {
+            match Database::open(&path) {
+                Ok(database) => {
+                    self.databases.write().unwrap()[index] = database;
+                    reloaded += 1;
+                    info!("GeoIP 数据库 {} 已重新加载", path.display());
+                }
+                Err(e) => warn!("{}，继续使用旧数据", e),
+            }
+        }
+        reloaded
+    }
+
+    /// 启动按间隔检查数据库更新的线程
+    pub fn start_reload(self: &Arc<Self>, interval: Duration) -> Result<(), String> {
+        let geoip = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("geoip-reload".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(geoip) = geoip.upgrade() else {
+                    break;
+                };
+                geoip.reload();
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动 GeoIP 重新加载线程失败: {}", e))
+    }
+}
+
+/// MaxMind DB 文件格式的最小实现：二叉搜索树 + 数据段解码
+struct Mmdb {
+    data: Vec<u8>,
+    node_count: usize,
+    /// 每条记录的位数（24、28 或 32）
+    record_size: usize,
+    ip_version: u64,
+    /// 搜索树的字节数
+    tree_size: usize,
+    /// IPv6 库中 IPv4 地址（::/96）所在的节点
+    ipv4_start: usize,
+}
+
+impl Mmdb {
+    fn parse(data: Vec<u8>) -> Result<Self, String> {
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or("找不到元数据")?;
+        let metadata = Decoder(&data[marker + METADATA_MARKER.len()..])
+            .decode(0, 0)?
+            .0;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| format!("元数据缺少 {}", name))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        if !matches!(record_size, 24 | 28 | 32) {
+            return Err(format!("不支持的记录长度: {}", record_size));
+        }
+        let tree_size = node_count
+            .checked_mul(record_size / 4)
+            .filter(|size| size + DATA_SECTION_SEPARATOR <= marker)
+            .ok_or("搜索树超出文件长度")?;
+        let mut mmdb = Self {
+            data,
+            node_count,
+            record_size,
+            ip_version: field("ip_version")?,
+            tree_size,
+            ipv4_start: 0,
+        };
+        if mmdb.ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = mmdb.record(node, 0)?;
+            }
+            mmdb.ipv4_start = node;
+        }
+        Ok(mmdb)
+    }
+
+    /// 节点的左（bit = 0）或右（bit = 1）记录
+    fn record(&self, node: usize, bit: u8) -> Result<usize, String> {
+        let width = self.record_size / 4;
+        let bytes = self
+            .data
+            .get(node * width..node * width + width)
+            .ok_or("搜索树节点越界")?;
+        Ok(match (self.record_size, bit) {
+            (24, 0) => be(&bytes[0..3]),
+            (24, _) => be(&bytes[3..6]),
+            (28, 0) => (usize::from(bytes[3]) & 0xf0) << 20 | be(&bytes[0..3]),
+            (28, _) => (usize::from(bytes[3]) & 0x0f) << 24 | be(&bytes[4..7]),
+            (_, 0) => be(&bytes[0..4]),
+            (_, _) => be(&bytes[4..8]),
+        })
+    }
+
+    fn lookup(&self, ip: &IpAddr) -> Result<Option<Value>, String> {
+        let (address, mut node) = match ip {
+            IpAddr::V4(v4) => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(v6) => (v6.octets().to_vec(), 0),
+        };
+        for index in 0..address.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (address[index / 8] >> (7 - index % 8)) & 1;
+            node = self.record(node, bit)?;
+        }
+        if node <= self.node_count {
+            return Ok(None);
+        }
+        let offset = (node - self.node_count)
+            .checked_sub(DATA_SECTION_SEPARATOR)
+            .ok_or("数据指针无效")?;
+        let section = &self.data[self.tree_size + DATA_SECTION_SEPARATOR..];
+        Decoder(section)
+            .decode(offset, 0)
+            .map(|(value, _)| Some(value))
+    }
+}
+
+/// 大端无符号整数
+fn be(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| value << 8 | usize::from(byte))
+}
+
+/// 数据段解码器；指针是相对于所在段起始的偏移
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], String> {
+        self.0
+            .get(offset..offset + len)
+            .ok_or_else(|| "数据越界".to_string())
+    }
+
+    /// 解码一个值，返回值和其后的偏移
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize), String> {
+        if depth > MAX_DEPTH {
+            return Err("数据嵌套过深".to_string());
+        }
+        let control = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            // 指针：解码指向的值，之后从指针后面继续
+            let size = usize::from((control >> 3) & 0x3) + 1;
+            let high = usize::from(control & 0x7);
+            let low = be(self.bytes(offset, size)?);
+            let pointer = match size {
+                1 => high << 8 | low,
+                2 => (high << 16 | low) + 2048,
+                3 => (high << 24 | low) + 526_336,
+                _ => low,
+            };
+            let (value, _) = self.decode(pointer, depth + 1)?;
+            return Ok((value, offset + size));
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+        let mut size = usize::from(control & 0x1f);
+        if size >= 29 {
+            let extra = size - 28;
+            let value = be(self.bytes(offset, extra)?);
+            size = match extra {
+                1 => 29 + value,
+                2 => 285 + value,
+                _ => 65_821 + value,
+            };
+            offset += extra;
+        }
+        match kind {
+            // UTF-8 字符串
+            2 => {
+                let text = String::from_utf8_lossy(self.bytes(offset, size)?).into_owned();
+                Ok((Value::String(text), offset + size))
+            }
+            // double
+            3 => {
+                let bytes: [u8; 8] = self
+                    .bytes(offset, 8)?
+                    .try_into()
+                    .map_err(|_| "double 长度无效")?;
+                Ok((Value::from(f64::from_be_bytes(bytes)), offset + 8))
+            }
+            // 原始字节，不需要
+            4 => Ok((Value::Null, offset + size)),
+            // uint16/uint32/uint64
+            5 | 6 | 9 if size <= 8 => {
+                let value = self
+                    .bytes(offset, size)?
+                    .iter()
+                    .fold(0u64, |value, &byte| value << 8 | u64::from(byte));
+                Ok((Value::from(value), offset + size))
+            }
+            // uint128
+            10 if size <= 16 => {
+                let value = self
+                    .bytes(offset, size)?
+                    .iter()
+                    .fold(0u128, |value, &byte| value << 8 | u128::from(byte));
+                Ok((Value::String(value.to_string()), offset + size))
+            }
+            // int32
+            8 if size <= 4 => {
+                let value = self
+                    .bytes(offset, size)?
+                    .iter()
+                    .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+                let value = if size == 4 {
+                    i64::from(value as i32)
+                } else {
+                    i64::from(value)
+                };
+                Ok((Value::from(value), offset + size))
+            }
+            // map
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset, depth + 1)?;
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    let Value::String(key) = key else {
+                        return Err("map 的键不是字符串".to_string());
+                    };
+                    map.insert(key, value);
+                    offset = next;
+                }
+                Ok((Value::Object(map), offset))
+            }
+            // array
+            11 => {
+                let mut items = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let (value, next) = self.decode(offset, depth + 1)?;
+                    items.push(value);
+                    offset = next;
+                }
+                Ok((Value::Array(items), offset))
+            }
+            // boolean：值在 size 中
+            14 => Ok((Value::Bool(size != 0), offset)),
+            // float
+            15 => {
+                let bytes: [u8; 4] = self
+                    .bytes(offset, 4)?
+                    .try_into()
+                    .map_err(|_| "float 长度无效")?;
+                Ok((Value::from(f32::from_be_bytes(bytes)), offset + 4))
+            }
+            _ => Err(format!("不支持的数据类型 {}", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::BanReason;
+    use crate::enforcement::Enforcer;
+    use crate::events::EventBus;
+    use crate::stats::Stats;
+    use crate::testing::MemoryFirewall;
+    use std::sync::Mutex;
+
+    const SCANNER: &str = "203.0.113.9";
+    const PHONE: &str = "198.51.100.7";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    /// 构造只包含 203.0.113.0/24 一条记录的 IPv4 MaxMind DB（24 位记录）
+    fn tiny_mmdb() -> Vec<u8> {
+        fn string(text: &str) -> Vec<u8> {
+            let mut out = vec![0x40 | text.len() as u8];
+            out.extend(text.as_bytes());
+            out
+        }
+        fn uint32(value: u32) -> Vec<u8> {
+            let mut out = vec![0xc4];
+            out.extend(value.to_be_bytes());
+            out
+        }
+        fn map(pairs: &[(&str, Vec<u8>)]) -> Vec<u8> {
+            let mut out = vec![0xe0 | pairs.len() as u8];
+            for (key, value) in pairs {
+                out.extend(string(key));
+                out.extend(value);
+            }
+            out
+        }
+
+        let node_count = 24u32;
+        let data_pointer = node_count + 16;
+        let mut db = Vec::new();
+        for (index, bit) in (0..24).map(|i| (i, (0xcb_00_71u32 >> (23 - i)) & 1)) {
+            let next = if index == 23 { data_pointer } else { index + 1 };
+            let (left, right) = if bit == 0 {
+                (next, node_count)
+            } else {
+                (node_count, next)
+            };
+            db.extend(&left.to_be_bytes()[1..]);
+            db.extend(&right.to_be_bytes()[1..]);
+        }
+        db.extend([0; 16]);
+        db.extend(map(&[
+            ("country", map(&[("iso_code", string("CN"))])),
+            ("city", map(&[("names", map(&[("en", string("Beijing"))]))])),
+            ("autonomous_system_number", uint32(4134)),
+        ]));
+        db.extend(b"\xab\xcd\xefMaxMind.com");
+        db.extend(map(&[
+            ("node_count", uint32(node_count)),
+            ("record_size", uint32(24)),
+            ("ip_version", uint32(4)),
+        ]));
+        db
+    }
+
+    #[test]
+    fn geoip_enriches_events_and_ban_records() {
+        let path = std::env::temp_dir().join(format!("uablock-geoip-{}.mmdb", std::process::id()));
+        std::fs::write(&path, tiny_mmdb()).unwrap();
+        let geoip = Arc::new(GeoIp::open(std::slice::from_ref(&path)).unwrap());
+        let expected = GeoInfo {
+            country: Some("CN".to_string()),
+            city: Some("Beijing".to_string()),
+            asn: Some(4134),
+            as_org: None,
+        };
+        assert_eq!(geoip.lookup(&ip(SCANNER)), Some(expected.clone()));
+        assert_eq!(geoip.lookup(&ip(PHONE)), None);
+        assert_eq!(geoip.lookup(&ip("2001:db8::1")), None);
+        assert_eq!(expected.summary(), "CN AS4134 Beijing");
+
+        let firewall = MemoryFirewall::new();
+        let bus = EventBus::new().with_geoip(geoip.clone());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        bus.subscribe(move |event| {
+            sink.lock().unwrap().push(event.clone());
+            true
+        });
+        let enforcer = Arc::new(Enforcer::new(
+            Some(Box::new(firewall)),
+            None,
+            Arc::new(Stats::default()),
+            Arc::new(bus),
+        ));
+        enforcer
+            .ban(ip(SCANNER), BanReason::new("MANUAL", "API"))
+            .unwrap();
+        enforcer
+            .ban(ip(PHONE), BanReason::new("MANUAL", "API"))
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0].geo, Some(expected.clone()));
+        assert_eq!(events[1].geo, None);
+        let records = enforcer.ban_records().unwrap();
+        let scanner = records.iter().find(|r| r.ip == ip(SCANNER)).unwrap();
+        assert_eq!(scanner.geo, Some(expected));
+
+        // 文件未变化时不重新加载
+        assert_eq!(geoip.reload(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
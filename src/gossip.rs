@@ -0,0 +1,310 @@
+use crate::cluster::{ClusterDigest, ClusterMessage, ClusterSync};
+use crate::events::{EventBus, QueueReceiver};
+use crate::wire;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 连接对端和读取帧的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 发送线程的轮询周期，同时也是检查对端封禁到期的周期
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 节点之间传输的内容
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// 单条封禁/解封
+    Message(ClusterMessage),
+    /// 全量封禁摘要
+    Digest(ClusterDigest),
+}
+
+/// 无外部依赖的点对点封禁同步
+///
+/// 节点之间通过 TCP 交换带 HMAC-SHA256 认证的帧（见 `wire` 模块）。每个封禁/解封事件实时推送给所有对端，并定期发送
+/// 全量摘要用于反熵，修复节点离线期间丢失的消息。
+pub struct Gossip {
+    listen: SocketAddr,
+    peers: Vec<String>,
+    key: Arc<Vec<u8>>,
+    digest_interval: Duration,
+    cluster: Arc<ClusterSync>,
+}
+
+impl Gossip {
+    /// 从环境变量创建，未设置 `UABLOCK_GOSSIP_LISTEN` 时返回 None
+    ///
+    /// - `UABLOCK_GOSSIP_LISTEN`：监听地址，例如 `0.0.0.0:7946`
+    /// - `UABLOCK_GOSSIP_PEERS`：对端地址列表（逗号分隔，支持主机名）
+    /// - `UABLOCK_GOSSIP_KEY`：共享密钥（必填）
+    /// - `UABLOCK_GOSSIP_DIGEST_INTERVAL`：摘要发送间隔（秒，默认 60）
+    pub fn from_env(cluster: Arc<ClusterSync>) -> Result<Option<Self>, String> {
+        let listen = match std::env::var("UABLOCK_GOSSIP_LISTEN") {
+            Ok(listen) if !listen.is_empty() => listen,
+            _ => return Ok(None),
+        };
+        let listen = listen
+            .parse()
+            .map_err(|e| format!("UABLOCK_GOSSIP_LISTEN 地址无效 {}: {}", listen, e))?;
+        let key = std::env::var("UABLOCK_GOSSIP_KEY").unwrap_or_default();
+        if key.is_empty() {
+            return Err("启用点对点同步时必须设置 UABLOCK_GOSSIP_KEY".to_string());
+        }
+        let peers = std::env::var("UABLOCK_GOSSIP_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        let digest_interval = std::env::var("UABLOCK_GOSSIP_DIGEST_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+        Ok(Some(Self {
+            listen,
+            peers,
+            key: Arc::new(key.into_bytes()),
+            digest_interval: Duration::from_secs(digest_interval),
+            cluster,
+        }))
+    }
+
+    /// 订阅本地事件并启动监听/发送线程
+    pub fn start(self, events: &EventBus) -> Result<(), String> {
+        let listener = TcpListener::bind(self.listen)
+            .map_err(|e| format!("点对点同步无法监听 {}: {}", self.listen, e))?;
+
+        let (tx, rx) = events.queue();
+        let cluster = self.cluster.clone();
+        events.subscribe(move |event| match cluster.outgoing(event) {
+            Some(message) => tx.send(message),
+            None => true,
+        });
+
+        info!(
+            "【点对点同步】节点 {} 监听于 {}，对端: {:?}",
+            self.cluster.node(),
+            self.listen,
+            self.peers
+        );
+
+        let cluster = self.cluster.clone();
+        let key = self.key.clone();
+        std::thread::Builder::new()
+            .name("gossip-recv".to_string())
+            .spawn(move || receive_loop(listener, &key, &cluster))
+            .map_err(|e| format!("启动点对点同步监听线程失败: {}", e))?;
+        std::thread::Builder::new()
+            .name("gossip-send".to_string())
+            .spawn(move || self.send_loop(rx))
+            .map_err(|e| format!("启动点对点同步发送线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 推送本地事件，定期发送摘要并清理到期的对端封禁
+    fn send_loop(self, rx: QueueReceiver<ClusterMessage>) {
+        let mut last_digest = Instant::now();
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(message) => self.broadcast(Payload::Message(message)),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if last_digest.elapsed() >= self.digest_interval {
+                last_digest = Instant::now();
+                self.broadcast(Payload::Digest(self.cluster.digest()));
+            }
+            self.cluster.expire();
+        }
+    }
+
+    /// 把一帧发送给所有对端
+    fn broadcast(&self, payload: Payload) {
+        let encoded = match wire::encode(&payload, &self.key) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                error!("【点对点同步】{}", e);
+                return;
+            }
+        };
+        for peer in &self.peers {
+            match send_to(peer, &encoded) {
+                Ok(()) => debug!("【点对点同步】已发送到 {}", peer),
+                Err(e) => warn!("【点对点同步】发送到 {} 失败: {}", peer, e),
+            }
+        }
+    }
+}
+
+/// 接收对端连接并应用其中的帧
+fn receive_loop(listener: TcpListener, key: &[u8], cluster: &ClusterSync) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("【点对点同步】接受连接失败: {}", e);
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        if let Err(e) = stream.set_read_timeout(Some(IO_TIMEOUT)) {
+            warn!("【点对点同步】设置读超时失败: {}", e);
+            continue;
+        }
+        if let Err(e) = receive(&mut stream, key, cluster) {
+            warn!("【点对点同步】丢弃来自 {} 的帧: {}", peer, e);
+        }
+    }
+}
+
+/// 读取一帧，通过认证后应用其中的封禁/解封或摘要
+fn receive(reader: &mut impl Read, key: &[u8], cluster: &ClusterSync) -> Result<(), String> {
+    match wire::read::<Payload>(reader, key)? {
+        Payload::Message(message) => cluster.apply(&message),
+        Payload::Digest(digest) => {
+            debug!(
+                "【点对点同步】收到节点 {} 的摘要（{} 条封禁）",
+                digest.node,
+                digest.bans.len()
+            );
+            cluster.apply_digest(&digest);
+        }
+    }
+    Ok(())
+}
+
+/// 连接对端并发送一帧
+fn send_to(peer: &str, encoded: &[u8]) -> Result<(), String> {
+    let mut stream = wire::connect(peer, IO_TIMEOUT)?;
+    stream.write_all(encoded).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterAction;
+    use crate::state_file::unix_now;
+    use crate::testing::MemoryFirewall;
+    use crate::{Enforcer, Stats};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::net::IpAddr;
+
+    const KEY: &[u8] = b"cluster-key";
+
+    fn cluster() -> (ClusterSync, Arc<Enforcer>) {
+        let enforcer = Arc::new(Enforcer::new(
+            Some(Box::new(MemoryFirewall::new())),
+            None,
+            Arc::new(Stats::default()),
+            Arc::new(EventBus::new()),
+        ));
+        (
+            ClusterSync::new("node-a".to_string(), 0, enforcer.clone()),
+            enforcer,
+        )
+    }
+
+    fn ban(ip: &str) -> Payload {
+        Payload::Message(ClusterMessage {
+            node: "node-b".to_string(),
+            action: ClusterAction::Ban,
+            ip: ip.parse().unwrap(),
+            reason: "UA_NOT_ALLOWED".to_string(),
+            expires_at: 0,
+        })
+    }
+
+    fn bans(enforcer: &Enforcer) -> Vec<IpAddr> {
+        let mut bans = enforcer.list_bans().unwrap();
+        bans.sort();
+        bans
+    }
+
+    #[test]
+    fn applies_authenticated_messages_and_digests() {
+        let (cluster, enforcer) = cluster();
+        let frame = wire::encode(&ban("203.0.113.9"), KEY).unwrap();
+        receive(&mut frame.as_slice(), KEY, &cluster).unwrap();
+        assert_eq!(
+            bans(&enforcer),
+            vec!["203.0.113.9".parse::<IpAddr>().unwrap()]
+        );
+
+        let Payload::Message(message) = ban("203.0.113.10") else {
+            unreachable!()
+        };
+        let digest = Payload::Digest(ClusterDigest {
+            node: "node-b".to_string(),
+            bans: vec![message],
+        });
+        let frame = wire::encode(&digest, KEY).unwrap();
+        receive(&mut frame.as_slice(), KEY, &cluster).unwrap();
+        // 摘要是对端仍持有的全部封禁：补齐缺失的，解除摘要中已没有的
+        assert_eq!(
+            bans(&enforcer),
+            vec!["203.0.113.10".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_frames_with_the_wrong_key() {
+        let (cluster, enforcer) = cluster();
+        let frame = wire::encode(&ban("203.0.113.9"), b"other-key").unwrap();
+        let error = receive(&mut frame.as_slice(), KEY, &cluster).unwrap_err();
+        assert!(error.contains("认证失败"), "{}", error);
+        assert!(bans(&enforcer).is_empty());
+    }
+
+    #[test]
+    fn rejects_tampered_or_unsigned_frames() {
+        let (cluster, enforcer) = cluster();
+        let frame = wire::encode(&ban("203.0.113.9"), KEY).unwrap();
+
+        // 改写负载中的 IP：长度不变，标签不再匹配
+        let text = String::from_utf8_lossy(&frame[4..frame.len() - 32]).into_owned();
+        let forged = text.replace("203.0.113.9", "198.51.100.7");
+        let mut tampered = frame[..4].to_vec();
+        tampered.extend_from_slice(forged.as_bytes());
+        tampered.extend_from_slice(&frame[frame.len() - 32..]);
+        assert!(receive(&mut tampered.as_slice(), KEY, &cluster).is_err());
+
+        // 标签被改动
+        let mut bad_tag = frame.clone();
+        *bad_tag.last_mut().unwrap() ^= 1;
+        assert!(receive(&mut bad_tag.as_slice(), KEY, &cluster).is_err());
+
+        // 没有标签的帧
+        let unsigned = &frame[..frame.len() - 32];
+        assert!(receive(&mut &unsigned[..], KEY, &cluster).is_err());
+        assert!(bans(&enforcer).is_empty());
+    }
+
+    #[test]
+    fn rejects_correctly_signed_but_stale_frames() {
+        let (cluster, enforcer) = cluster();
+        let body = serde_json::to_vec(&serde_json::json!({
+            "sent_at": unix_now() - 3600,
+            "payload": ban("203.0.113.9"),
+        }))
+        .unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(KEY).unwrap();
+        mac.update(&body);
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        frame.extend_from_slice(&mac.finalize().into_bytes());
+
+        let error = receive(&mut frame.as_slice(), KEY, &cluster).unwrap_err();
+        assert!(error.contains("重放"), "{}", error);
+        assert!(bans(&enforcer).is_empty());
+    }
+}
@@ -0,0 +1,411 @@
+use crate::state_file::{GreylistRecord, ReleaseRecord};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// (来源 IP, 小写 UA) 的灰名单状态
+#[derive(Debug, Clone, Copy)]
+enum PairState {
+    /// 观察期：hold_until 之前的请求被临时规则丢弃
+    Probation {
+        since: Instant,
+        hold_until: Instant,
+        requests: u32,
+    },
+    /// 已通过观察期
+    Allowed { since: Instant },
+}
+
+/// 灰名单判定结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GreylistDecision {
+    /// 已放行的来源，按正常流程处理
+    Allowed,
+    /// 首次出现，应添加临时丢弃规则
+    Hold,
+    /// 观察期内，请求不应触发解封
+    Probation,
+    /// 观察期内请求过多，应封禁
+    Violation,
+}
+
+/// 首次来源灰名单
+///
+/// 未见过的 (IP, UA) 发来的第一个 REGISTER 会被短时间丢弃，合法终端
+/// 会在稍后重试；观察期内行为正常（请求数不超过上限）的来源升级为已放行。
+pub struct Greylist {
+    hold: Duration,
+    probation: Duration,
+    max_requests: u32,
+    allow_ttl: Duration,
+    pairs: HashMap<(IpAddr, String), PairState>,
+    /// 待解除的临时规则：IP -> 解除时间
+    releases: HashMap<IpAddr, Instant>,
+}
+
+impl Greylist {
+    pub fn new(
+        hold: Duration,
+        probation: Duration,
+        max_requests: u32,
+        allow_ttl: Duration,
+    ) -> Self {
+        Self {
+            hold,
+            probation,
+            max_requests,
+            allow_ttl,
+            pairs: HashMap::new(),
+            releases: HashMap::new(),
+        }
+    }
+
+    /// 从环境变量创建，未设置 UABLOCK_GREYLIST=1 时返回 None
+    ///
+    /// - UABLOCK_GREYLIST_HOLD：首次请求的丢弃时长（秒，默认 30）
+    /// - UABLOCK_GREYLIST_PROBATION：观察期（秒，默认 600）
+    /// - UABLOCK_GREYLIST_MAX_REQUESTS：观察期内允许的请求数（默认 30）
+    /// - UABLOCK_GREYLIST_ALLOW_TTL：已放行来源的有效期（秒，默认 604800）
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("UABLOCK_GREYLIST").as_deref() != Ok("1") {
+            return None;
+        }
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let greylist = Self::new(
+            Duration::from_secs(env_u64("UABLOCK_GREYLIST_HOLD", 30)),
+            Duration::from_secs(env_u64("UABLOCK_GREYLIST_PROBATION", 600)),
+            env_u64("UABLOCK_GREYLIST_MAX_REQUESTS", 30) as u32,
+            Duration::from_secs(env_u64("UABLOCK_GREYLIST_ALLOW_TTL", 604800)),
+        );
+        info!(
+            "灰名单模式已启用: 首次请求丢弃 {} 秒，观察期 {} 秒，观察期内最多 {} 次请求",
+            greylist.hold.as_secs(),
+            greylist.probation.as_secs(),
+            greylist.max_requests
+        );
+        Some(greylist)
+    }
+
+    /// 对一个（已通过白名单的）请求进行灰名单判定
+    pub fn check(&mut self, ip: IpAddr, user_agent: &str, method: &str) -> GreylistDecision {
+        self.check_at(ip, user_agent, method, Instant::now())
+    }
+
+    /// 同 [`check`](Self::check)，以 `now` 为当前时间
+    fn check_at(
+        &mut self,
+        ip: IpAddr,
+        user_agent: &str,
+        method: &str,
+        now: Instant,
+    ) -> GreylistDecision {
+        let key = (ip, user_agent.to_lowercase());
+        let state = match self.pairs.get_mut(&key) {
+            Some(state) => state,
+            None => {
+                // 只有 REGISTER 会开启观察期，其他方法按正常流程处理
+                if method != "REGISTER" {
+                    return GreylistDecision::Allowed;
+                }
+                let hold_until = now + self.hold;
+                self.pairs.insert(
+                    key,
+                    PairState::Probation {
+                        since: now,
+                        hold_until,
+                        requests: 1,
+                    },
+                );
+                self.releases.insert(ip, hold_until);
+                return GreylistDecision::Hold;
+            }
+        };
+
+        match state {
+            PairState::Allowed { .. } => GreylistDecision::Allowed,
+            PairState::Probation {
+                since,
+                hold_until,
+                requests,
+            } => {
+                // 临时丢弃期间的重试是合法终端的正常行为，不计入请求数
+                if now < *hold_until {
+                    return GreylistDecision::Probation;
+                }
+                *requests += 1;
+                if *requests > self.max_requests {
+                    self.pairs.remove(&key);
+                    return GreylistDecision::Violation;
+                }
+                if now.duration_since(*since) >= self.probation {
+                    info!(
+                        "【灰名单】IP: {}, User-Agent: '{}' 观察期内行为正常，升级为已放行",
+                        ip, user_agent
+                    );
+                    *state = PairState::Allowed { since: now };
+                    return GreylistDecision::Allowed;
+                }
+                GreylistDecision::Probation
+            }
+        }
+    }
+
+    /// 取出到期需要解除的临时规则
+    pub fn due_releases(&mut self) -> Vec<IpAddr> {
+        self.due_releases_at(Instant::now())
+    }
+
+    fn due_releases_at(&mut self, now: Instant) -> Vec<IpAddr> {
+        let due: Vec<IpAddr> = self
+            .releases
+            .iter()
+            .filter(|(_, at)| now >= **at)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in &due {
+            self.releases.remove(ip);
+        }
+        due
+    }
+
+    /// 来源因其他原因被封禁时调用，取消待解除的临时规则
+    pub fn cancel_release(&mut self, ip: &IpAddr) {
+        self.releases.remove(ip);
+    }
+
+    /// 导出 (IP, UA) 状态和待解除的临时规则，用于持久化
+    pub fn snapshot(&self) -> (Vec<GreylistRecord>, Vec<ReleaseRecord>) {
+        let now = Instant::now();
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|((ip, user_agent), state)| match *state {
+                PairState::Probation {
+                    since,
+                    hold_until,
+                    requests,
+                } => GreylistRecord {
+                    ip: *ip,
+                    user_agent: user_agent.clone(),
+                    allowed: false,
+                    age_secs: now.duration_since(since).as_secs_f64(),
+                    hold_remaining_secs: hold_until.saturating_duration_since(now).as_secs_f64(),
+                    requests,
+                },
+                PairState::Allowed { since } => GreylistRecord {
+                    ip: *ip,
+                    user_agent: user_agent.clone(),
+                    allowed: true,
+                    age_secs: now.duration_since(since).as_secs_f64(),
+                    hold_remaining_secs: 0.0,
+                    requests: 0,
+                },
+            })
+            .collect();
+        let releases = self
+            .releases
+            .iter()
+            .map(|(ip, at)| ReleaseRecord {
+                ip: *ip,
+                remaining_secs: at.saturating_duration_since(now).as_secs_f64(),
+            })
+            .collect();
+        (pairs, releases)
+    }
+
+    /// 恢复状态，扣除停机时长（elapsed_secs）
+    ///
+    /// 已到期的临时规则仍会恢复为立即到期，确保下一次定时任务把它解除。
+    pub fn restore(
+        &mut self,
+        pairs: &[GreylistRecord],
+        releases: &[ReleaseRecord],
+        elapsed_secs: f64,
+    ) {
+        let now = Instant::now();
+        let at = |secs: f64| Duration::try_from_secs_f64(secs.max(0.0)).ok();
+        let until = |secs: f64| at(secs).and_then(|remaining| now.checked_add(remaining));
+        for record in pairs {
+            let Some(age) = at(record.age_secs + elapsed_secs) else {
+                warn!(
+                    "忽略无效的灰名单记录: {}（age_secs={}）",
+                    record.ip, record.age_secs
+                );
+                continue;
+            };
+            let since = now.checked_sub(age).unwrap_or(now);
+            let state = if record.allowed {
+                PairState::Allowed { since }
+            } else {
+                let Some(hold_until) = until(record.hold_remaining_secs - elapsed_secs) else {
+                    warn!(
+                        "忽略无效的灰名单记录: {}（hold_remaining_secs={}）",
+                        record.ip, record.hold_remaining_secs
+                    );
+                    continue;
+                };
+                PairState::Probation {
+                    since,
+                    hold_until,
+                    requests: record.requests,
+                }
+            };
+            self.pairs
+                .insert((record.ip, record.user_agent.to_lowercase()), state);
+        }
+        for record in releases {
+            match until(record.remaining_secs - elapsed_secs) {
+                Some(expires) => {
+                    self.releases.insert(record.ip, expires);
+                }
+                None => warn!(
+                    "忽略无效的灰名单放行记录: {}（remaining_secs={}）",
+                    record.ip, record.remaining_secs
+                ),
+            }
+        }
+        // 过期的记录交给 cleanup 按当前配置清理
+        self.cleanup();
+    }
+
+    /// 清理过期的观察期和放行记录
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        let (probation, allow_ttl) = (self.probation, self.allow_ttl);
+        self.pairs.retain(|_, state| match state {
+            // 观察期结束后再无请求的来源不做升级，直接遗忘
+            PairState::Probation { since, .. } => now.duration_since(*since) < probation * 2,
+            PairState::Allowed { since } => now.duration_since(*since) < allow_ttl,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLD: Duration = Duration::from_secs(30);
+    const PROBATION: Duration = Duration::from_secs(600);
+    const PHONE: &str = "Yealink SIP-T46S 66.86.0.15";
+
+    fn greylist(max_requests: u32) -> Greylist {
+        Greylist::new(HOLD, PROBATION, max_requests, Duration::from_secs(86400))
+    }
+
+    fn ip() -> IpAddr {
+        IpAddr::from([198, 51, 100, 7])
+    }
+
+    #[test]
+    fn first_register_is_held_and_promoted_after_probation() {
+        let mut greylist = greylist(30);
+        let start = Instant::now();
+        // 首次出现的其他方法不开启观察期
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "OPTIONS", start),
+            GreylistDecision::Allowed
+        );
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "REGISTER", start),
+            GreylistDecision::Hold
+        );
+        // 同一 IP 和 UA（不区分大小写）在观察期内
+        assert_eq!(
+            greylist.check_at(ip(), &PHONE.to_uppercase(), "REGISTER", start + HOLD),
+            GreylistDecision::Probation
+        );
+        assert_eq!(
+            greylist.check_at(
+                ip(),
+                PHONE,
+                "REGISTER",
+                start + PROBATION - Duration::from_nanos(1)
+            ),
+            GreylistDecision::Probation
+        );
+        // 观察期满后的第一个请求升级为已放行
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "REGISTER", start + PROBATION),
+            GreylistDecision::Allowed
+        );
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "INVITE", start + PROBATION),
+            GreylistDecision::Allowed
+        );
+        // 另一个 UA 重新开始观察
+        assert_eq!(
+            greylist.check_at(ip(), "MicroSIP/3.21.3", "REGISTER", start + PROBATION),
+            GreylistDecision::Hold
+        );
+    }
+
+    #[test]
+    fn retries_during_the_hold_are_not_counted() {
+        let mut greylist = greylist(2);
+        let start = Instant::now();
+        greylist.check_at(ip(), PHONE, "REGISTER", start);
+        for _ in 0..10 {
+            assert_eq!(
+                greylist.check_at(ip(), PHONE, "REGISTER", start + HOLD / 2),
+                GreylistDecision::Probation
+            );
+        }
+        // 丢弃期结束后计数：第 2 个请求仍在上限内，第 3 个违规
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "REGISTER", start + HOLD),
+            GreylistDecision::Probation
+        );
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "REGISTER", start + HOLD),
+            GreylistDecision::Violation
+        );
+        // 违规后遗忘该来源，再次出现时重新开始
+        assert_eq!(
+            greylist.check_at(ip(), PHONE, "REGISTER", start + HOLD),
+            GreylistDecision::Hold
+        );
+    }
+
+    #[test]
+    fn holds_are_released_exactly_when_they_end() {
+        let mut greylist = greylist(30);
+        let start = Instant::now();
+        greylist.check_at(ip(), PHONE, "REGISTER", start);
+        assert!(greylist
+            .due_releases_at(start + HOLD - Duration::from_nanos(1))
+            .is_empty());
+        assert_eq!(greylist.due_releases_at(start + HOLD), vec![ip()]);
+        // 每条临时规则只解除一次
+        assert!(greylist.due_releases_at(start + HOLD * 2).is_empty());
+
+        // 被正式封禁的来源不再按期解除
+        let scanner = IpAddr::from([203, 0, 113, 9]);
+        greylist.check_at(scanner, "friendly-scanner", "REGISTER", start);
+        greylist.cancel_release(&scanner);
+        assert!(greylist.due_releases_at(start + HOLD).is_empty());
+    }
+
+    #[test]
+    fn restore_keeps_pending_releases_due_after_a_restart() {
+        let mut original = greylist(30);
+        original.check(ip(), PHONE, "REGISTER");
+        let (pairs, releases) = original.snapshot();
+        assert_eq!(pairs.len(), 1);
+        assert!(!pairs[0].allowed);
+
+        // 停机时间超过丢弃期：临时规则恢复为立即到期
+        let mut restored = greylist(30);
+        restored.restore(&pairs, &releases, HOLD.as_secs_f64() + 1.0);
+        assert_eq!(restored.due_releases(), vec![ip()]);
+        assert_eq!(
+            restored.check(ip(), PHONE, "REGISTER"),
+            GreylistDecision::Probation
+        );
+    }
+}
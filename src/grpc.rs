@@ -0,0 +1,167 @@
+use crate::bans::BanReason;
+use crate::enforcement::Enforcer;
+use crate::events::{Event, EventKind};
+use log::{error, info};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+/// 由 proto/uablock.proto 生成的代码
+pub mod proto {
+    tonic::include_proto!("uablock.v1");
+}
+
+use proto::uablock_server::{Uablock, UablockServer};
+
+/// 每个事件订阅者的缓冲区大小，订阅者消费过慢时丢弃新事件
+const EVENT_BUFFER: usize = 1024;
+
+/// gRPC 控制服务
+struct UablockService {
+    enforcer: Arc<Enforcer>,
+    started: Instant,
+}
+
+/// 在当前 tokio 运行时中启动 gRPC 服务
+pub fn spawn(listen: SocketAddr, enforcer: Arc<Enforcer>) {
+    let service = UablockService {
+        enforcer,
+        started: Instant::now(),
+    };
+
+    info!("gRPC 服务监听于 {}", listen);
+    tokio::spawn(async move {
+        let result = tonic::transport::Server::builder()
+            .add_service(UablockServer::new(service))
+            .serve(listen)
+            .await;
+        if let Err(e) = result {
+            error!("gRPC 服务异常退出: {}", e);
+        }
+    });
+}
+
+fn parse_ip(ip: &str) -> Result<IpAddr, Status> {
+    ip.parse()
+        .map_err(|_| Status::invalid_argument(format!("无效的 IP 地址: {}", ip)))
+}
+
+/// 在阻塞线程池中执行防火墙操作
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(Status::failed_precondition)
+}
+
+fn to_proto_event(event: &Event) -> proto::Event {
+    let kind = match event.kind {
+        EventKind::Detection => proto::EventKind::Detection,
+        EventKind::Ban => proto::EventKind::Ban,
+        EventKind::Unban => proto::EventKind::Unban,
+        EventKind::Alert => proto::EventKind::Alert,
+    };
+    proto::Event {
+        timestamp: event.timestamp,
+        kind: kind as i32,
+        ip: event.ip.to_string(),
+        user_agent: event.user_agent.clone(),
+        reason: event.reason.clone(),
+        code: event.code.to_string(),
+        origin: event.origin.clone(),
+        tenant: event.tenant.clone().unwrap_or_default(),
+        dest_port: event.dest_port.map_or(0, u32::from),
+    }
+}
+
+#[tonic::async_trait]
+impl Uablock for UablockService {
+    async fn ban(
+        &self,
+        request: Request<proto::BanRequest>,
+    ) -> Result<Response<proto::BanReply>, Status> {
+        let ip = parse_ip(&request.into_inner().ip)?;
+        let enforcer = self.enforcer.clone();
+        run_blocking(move || enforcer.ban(ip, BanReason::new("MANUAL", "gRPC"))).await?;
+        Ok(Response::new(proto::BanReply {}))
+    }
+
+    async fn unban(
+        &self,
+        request: Request<proto::UnbanRequest>,
+    ) -> Result<Response<proto::UnbanReply>, Status> {
+        let ip = parse_ip(&request.into_inner().ip)?;
+        let enforcer = self.enforcer.clone();
+        run_blocking(move || enforcer.unban(ip, "MANUAL", "gRPC")).await?;
+        Ok(Response::new(proto::UnbanReply {}))
+    }
+
+    async fn list_bans(
+        &self,
+        _request: Request<proto::ListBansRequest>,
+    ) -> Result<Response<proto::ListBansReply>, Status> {
+        let enforcer = self.enforcer.clone();
+        let records = run_blocking(move || enforcer.ban_records()).await?;
+        Ok(Response::new(proto::ListBansReply {
+            ips: records.iter().map(|record| record.ip.to_string()).collect(),
+            bans: records
+                .into_iter()
+                .map(|record| proto::BanDetail {
+                    ip: record.ip.to_string(),
+                    reason: record.reason.reason,
+                    origin: record.reason.origin,
+                    user_agent: record.reason.user_agent.unwrap_or_default(),
+                    method: record.reason.method.unwrap_or_default(),
+                    rule: record.reason.rule.unwrap_or_default(),
+                    banned_at: record.banned_at.unwrap_or(0),
+                    hits: record.hits,
+                    last_hit: record.last_hit.unwrap_or(0),
+                    expires_at: record.reason.expires_at.unwrap_or(0),
+                    code: record.reason.code.to_string(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn status(
+        &self,
+        _request: Request<proto::StatusRequest>,
+    ) -> Result<Response<proto::StatusReply>, Status> {
+        let stats = self.enforcer.stats().snapshot();
+        Ok(Response::new(proto::StatusReply {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            packets: stats.packets,
+            sip_requests: stats.sip_requests,
+            retransmissions: stats.retransmissions,
+            malformed: stats.malformed,
+            detections: stats.detections,
+            bans: stats.bans,
+            unbans: stats.unbans,
+            capture_drops: stats.capture_drops,
+        }))
+    }
+
+    type EventsStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+    async fn events(
+        &self,
+        _request: Request<proto::EventsRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let (tx, rx) = tokio::sync::mpsc::channel(EVENT_BUFFER);
+        // 客户端断开后 try_send 返回 Closed，订阅随之被移除
+        self.enforcer.events().subscribe(move |event| {
+            !matches!(
+                tx.try_send(Ok(to_proto_event(event))),
+                Err(tokio::sync::mpsc::error::TrySendError::Closed(_))
+            )
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
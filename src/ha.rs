@@ -0,0 +1,48 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 主备部署中本机的角色
+///
+/// 备机照常抓包检测，并通过集群同步接收主机的封禁，维护同样的封禁表和状态，但不下发防火墙规则；
+/// 升为主机（`POST /ha/promote` 或 `uablock promote`）时按封禁表一次性补齐规则。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HaRole {
+    #[default]
+    Active,
+    Standby,
+}
+
+impl HaRole {
+    /// 从环境变量 `UABLOCK_HA_ROLE`（`active`/`standby`）读取，未设置时为主机
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("UABLOCK_HA_ROLE") {
+            Ok(value) if !value.is_empty() => {
+                let role = match value.to_ascii_lowercase().as_str() {
+                    "active" => Self::Active,
+                    "standby" => Self::Standby,
+                    _ => return Err(format!("UABLOCK_HA_ROLE 无效: {}", value)),
+                };
+                if role == Self::Standby {
+                    info!("以备机角色运行: 只维护封禁表，升为主机前不下发防火墙规则");
+                }
+                Ok(role)
+            }
+            _ => Ok(Self::Active),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Standby => "standby",
+        }
+    }
+}
+
+impl fmt::Display for HaRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
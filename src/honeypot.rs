@@ -0,0 +1,309 @@
+use crate::packet_capture::CapturedPacket;
+use crate::sip_parser::SipRequest;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+/// 默认伪装的 Server 头
+const DEFAULT_BANNER: &str = "Asterisk PBX 16.2.1";
+/// 每个来源最多记录的后续请求数
+const MAX_FOLLOW_UPS: usize = 20;
+/// 蜜罐会话保留时间
+const SESSION_TTL: Duration = Duration::from_secs(3600);
+
+/// 蜜罐会话：记录已应答来源的后续行为
+struct HoneypotSession {
+    last_seen: Instant,
+    responses: u32,
+    follow_ups: Vec<String>,
+}
+
+/// 扫描器蜜罐应答（可选，默认关闭）
+///
+/// 对非白名单来源的 OPTIONS/REGISTER 通过原始套接字伪造 `200 OK` 应答，
+/// 让扫描器继续后续探测以便记录其行为特征。封禁流程不受影响。
+pub struct Honeypot {
+    banner: String,
+    sender: RawSender,
+    sessions: HashMap<IpAddr, HoneypotSession>,
+}
+
+impl Honeypot {
+    pub fn new(banner: Option<String>) -> Result<Self, String> {
+        let banner = banner.unwrap_or_else(|| DEFAULT_BANNER.to_string());
+        let sender = RawSender::open()?;
+        info!("蜜罐应答模式已启用，伪装 Server: {}", banner);
+        Ok(Self {
+            banner,
+            sender,
+            sessions: HashMap::new(),
+        })
+    }
+
+    /// 处理来自扫描器的请求：记录后续行为，并对 OPTIONS/REGISTER 发送伪造应答
+    pub fn handle(&mut self, packet: &CapturedPacket, request: &SipRequest) {
+        let now = Instant::now();
+        if let Some(session) = self.sessions.get_mut(&packet.source_ip) {
+            let fingerprint = format!(
+                "{} -> {} UA='{}'",
+                request.method, packet.dest_port, request.user_agent
+            );
+            info!(
+                "【蜜罐】IP: {} 后续请求: {}（已应答 {} 次）",
+                packet.source_ip, fingerprint, session.responses
+            );
+            if session.follow_ups.len() < MAX_FOLLOW_UPS {
+                session.follow_ups.push(fingerprint);
+            }
+            session.last_seen = now;
+        }
+
+        if request.method != "OPTIONS" && request.method != "REGISTER" {
+            return;
+        }
+
+        let text = String::from_utf8_lossy(&packet.payload);
+        let response = match build_response(&text, &request.method, &self.banner) {
+            Some(response) => response,
+            None => {
+                debug!("蜜罐: 请求缺少必要头部，不应答: IP {}", packet.source_ip);
+                return;
+            }
+        };
+
+        match self.sender.send_udp(packet, response.as_bytes()) {
+            Ok(()) => {
+                let session =
+                    self.sessions
+                        .entry(packet.source_ip)
+                        .or_insert_with(|| HoneypotSession {
+                            last_seen: now,
+                            responses: 0,
+                            follow_ups: Vec::new(),
+                        });
+                session.responses += 1;
+                session.last_seen = now;
+                debug!(
+                    "【蜜罐】已向 IP: {}:{} 发送 {} 伪造应答",
+                    packet.source_ip, packet.source_port, request.method
+                );
+            }
+            Err(e) => error!("蜜罐应答发送失败: {}", e),
+        }
+    }
+
+    /// 清理过期会话，并输出每个会话的行为摘要
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|ip, session| {
+            let keep = now.duration_since(session.last_seen) < SESSION_TTL;
+            if !keep {
+                info!(
+                    "【蜜罐摘要】IP: {}, 应答 {} 次, 后续请求: {:?}",
+                    ip, session.responses, session.follow_ups
+                );
+            }
+            keep
+        });
+    }
+}
+
+/// 根据请求构造伪造的 200 OK 应答，缺少必要头部时返回 None
+fn build_response(request_text: &str, method: &str, banner: &str) -> Option<String> {
+    let mut vias = Vec::new();
+    let mut from = None;
+    let mut to = None;
+    let mut call_id = None;
+    let mut cseq = None;
+    let mut contact = None;
+
+    for line in request_text.split("\r\n").skip(1) {
+        if line.is_empty() {
+            break;
+        }
+        let (name, _) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "via" | "v" => vias.push(line),
+            "from" | "f" => from = Some(line),
+            "to" | "t" => to = Some(line),
+            "call-id" | "i" => call_id = Some(line),
+            "cseq" => cseq = Some(line),
+            "contact" | "m" => contact = Some(line),
+            _ => {}
+        }
+    }
+
+    if vias.is_empty() {
+        return None;
+    }
+    let (from, to, call_id, cseq) = (from?, to?, call_id?, cseq?);
+    let to = if to.contains(";tag=") {
+        to.to_string()
+    } else {
+        format!("{};tag={:08x}", to, rand_tag())
+    };
+
+    let mut response = String::from("SIP/2.0 200 OK\r\n");
+    for via in vias {
+        response.push_str(via);
+        response.push_str("\r\n");
+    }
+    for line in [from, to.as_str(), call_id, cseq] {
+        response.push_str(line);
+        response.push_str("\r\n");
+    }
+    if method == "REGISTER" {
+        if let Some(contact) = contact {
+            response.push_str(contact);
+            response.push_str("\r\nExpires: 3600\r\n");
+        }
+    } else {
+        response.push_str("Allow: INVITE, ACK, CANCEL, OPTIONS, BYE, REGISTER\r\n");
+    }
+    response.push_str(&format!("Server: {}\r\nContent-Length: 0\r\n\r\n", banner));
+    Some(response)
+}
+
+/// 生成 To tag 用的伪随机数
+fn rand_tag() -> u32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+/// 基于 IPPROTO_RAW 的原始套接字发送器（自行构造 IP 与 UDP 头）
+struct RawSender {
+    #[cfg(unix)]
+    fd: libc::c_int,
+}
+
+impl RawSender {
+    #[cfg(unix)]
+    fn open() -> Result<Self, String> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW) };
+        if fd < 0 {
+            return Err(format!(
+                "无法创建原始套接字: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(Self { fd })
+    }
+
+    #[cfg(not(unix))]
+    fn open() -> Result<Self, String> {
+        Err("当前平台不支持原始套接字".to_string())
+    }
+
+    /// 以原请求的目标地址为源，向请求来源发送 UDP 数据
+    #[cfg(unix)]
+    fn send_udp(&self, packet: &CapturedPacket, payload: &[u8]) -> Result<(), String> {
+        let (src, dst) = match (packet.dest_ip, packet.source_ip) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => (src, dst),
+            _ => return Err("蜜罐应答仅支持 IPv4".to_string()),
+        };
+        let datagram = build_ipv4_udp(src, dst, packet.dest_port, packet.source_port, payload);
+
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from(dst).to_be(),
+            },
+            sin_zero: [0; 8],
+        };
+        let sent = unsafe {
+            libc::sendto(
+                self.fd,
+                datagram.as_ptr() as *const libc::c_void,
+                datagram.len(),
+                0,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn send_udp(&self, _packet: &CapturedPacket, _payload: &[u8]) -> Result<(), String> {
+        Err("当前平台不支持原始套接字".to_string())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawSender {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// 构造完整的 IPv4 + UDP 数据报
+fn build_ipv4_udp(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let total_len = 20 + udp_len;
+
+    let mut packet = Vec::with_capacity(total_len as usize);
+    // IPv4 头：版本 4，IHL 5，DF，TTL 64，协议 UDP
+    packet.extend_from_slice(&[0x45, 0x00]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 64, 17, 0x00, 0x00]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let ip_checksum = checksum(&packet[..20], 0);
+    packet[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    // UDP 头
+    packet.extend_from_slice(&src_port.to_be_bytes());
+    packet.extend_from_slice(&dst_port.to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0x00, 0x00]);
+    packet.extend_from_slice(payload);
+
+    // UDP 校验和包含伪首部：源/目标地址、协议号、UDP 长度
+    let mut pseudo = 0u32;
+    for chunk in src.octets().chunks(2).chain(dst.octets().chunks(2)) {
+        pseudo += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    pseudo += 17 + udp_len as u32;
+    let udp_checksum = match checksum(&packet[20..], pseudo) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    packet[26..28].copy_from_slice(&udp_checksum.to_be_bytes());
+    packet
+}
+
+/// 互联网校验和（RFC 1071）
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
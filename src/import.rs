@@ -0,0 +1,149 @@
+use crate::initialize_whitelist;
+use std::io::Read;
+use std::net::IpAddr;
+use uablock_rust::config::Config;
+use uablock_rust::freeswitch;
+use uablock_rust::registrations::{parse_astdb, parse_sofia_status, ImportedEntries, Registration};
+use uablock_rust::whitelist::ListMode;
+
+/// 导入参数
+#[derive(Debug, Default)]
+struct ImportOptions {
+    freeswitch_esl: Option<String>,
+    freeswitch_password: String,
+    freeswitch_profile: String,
+    asterisk_db: Option<String>,
+    json: bool,
+}
+
+impl ImportOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            freeswitch_password: std::env::var("UABLOCK_FREESWITCH_PASSWORD")
+                .unwrap_or_else(|_| "ClueCon".to_string()),
+            freeswitch_profile: "internal".to_string(),
+            ..Self::default()
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--json" {
+                options.json = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?
+                .clone();
+            match flag.as_str() {
+                "--freeswitch-esl" => options.freeswitch_esl = Some(value),
+                "--freeswitch-password" => options.freeswitch_password = value,
+                "--freeswitch-profile" => options.freeswitch_profile = value,
+                "--asterisk-db" => options.asterisk_db = Some(value),
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        if options.freeswitch_esl.is_none() && options.asterisk_db.is_none() {
+            return Err(
+                "用法: uablock import-registrations --freeswitch-esl <地址> | --asterisk-db <文件|->"
+                    .to_string(),
+            );
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock import-registrations`：由 PBX 当前的注册表生成白名单条目
+///
+/// - `--freeswitch-esl 127.0.0.1:8021`：经 ESL 执行 `sofia status profile <profile> reg`
+///   （`--freeswitch-profile`，默认 `internal`；密码取 `--freeswitch-password` 或
+///   `UABLOCK_FREESWITCH_PASSWORD`）
+/// - `--asterisk-db <文件>`：`asterisk -rx "database show registrar"` 的输出，`-` 表示从标准输入读取
+///
+/// 输出可直接写入环境文件的 `SIP_UA_WHITELIST`（现有白名单加上尚未覆盖的 UA）和
+/// `UABLOCK_LAN_SUBNETS`（已注册终端的 IP，配合 `UABLOCK_LAN_ALERT_ONLY=1` 只告警不封禁）；
+/// `--json` 输出新增的条目。
+pub fn run(args: &[String], config: &Config) -> i32 {
+    let options = match ImportOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let whitelist = match initialize_whitelist(config) {
+        Ok(whitelist) if whitelist.mode() == ListMode::Deny => {
+            eprintln!("当前为黑名单模式（SIP_UA_BLACKLIST），不需要导入白名单条目");
+            return 1;
+        }
+        Ok(whitelist) => whitelist,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let registrations = match collect(&options) {
+        Ok(registrations) => registrations,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let entries = ImportedEntries::collect(&registrations, &whitelist);
+
+    if options.json {
+        match serde_json::to_string_pretty(&entries) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+        return 0;
+    }
+
+    println!(
+        "# 由 uablock import-registrations 生成：{} 个注册，{} 个 IP，新增 {} 个 UA 模式",
+        registrations.len(),
+        entries.ips.len(),
+        entries.user_agents.len()
+    );
+    for pattern in &entries.skipped {
+        println!("# 模式 '{}' 过短，未加入白名单", pattern);
+    }
+    let patterns: Vec<&str> = whitelist
+        .get_patterns()
+        .iter()
+        .map(String::as_str)
+        .chain(entries.user_agents.iter().map(String::as_str))
+        .collect();
+    println!("SIP_UA_WHITELIST={}", patterns.join(","));
+    let ips: Vec<String> = entries.ips.iter().map(IpAddr::to_string).collect();
+    println!("UABLOCK_LAN_SUBNETS={}", ips.join(","));
+    0
+}
+
+/// 从各个 PBX 读取注册表
+fn collect(options: &ImportOptions) -> Result<Vec<Registration>, String> {
+    let mut registrations = Vec::new();
+    if let Some(address) = &options.freeswitch_esl {
+        let output = freeswitch::api(
+            address,
+            &options.freeswitch_password,
+            &format!("sofia status profile {} reg", options.freeswitch_profile),
+        )?;
+        registrations.extend(parse_sofia_status(&output));
+    }
+    if let Some(path) = &options.asterisk_db {
+        let mut text = String::new();
+        if path == "-" {
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| format!("读取标准输入失败: {}", e))?;
+        } else {
+            text =
+                std::fs::read_to_string(path).map_err(|e| format!("无法读取 {}: {}", path, e))?;
+        }
+        registrations.extend(parse_astdb(&text));
+    }
+    Ok(registrations)
+}
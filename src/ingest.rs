@@ -0,0 +1,23 @@
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+/// 外部系统（如 PBX）上报的安全信号
+///
+/// 由各输入模块在后台线程中产生，检测任务统一计入惩罚分，
+/// 与数据包捕获得到的检测结果合并处置。
+#[derive(Debug, Clone)]
+pub struct ExternalSignal {
+    /// 来源 IP
+    pub source_ip: IpAddr,
+    /// User-Agent（外部系统未提供时为空）
+    pub user_agent: String,
+    /// 机器可读的原因代码，如 `AUTH_FAILURE`
+    pub reason: String,
+    /// 计入惩罚分的权重
+    pub weight: f64,
+    /// 信号来源，如 `freeswitch`
+    pub origin: String,
+}
+
+/// 输入模块向检测任务发送信号的通道（无界，可在普通线程中直接发送）
+pub type SignalSender = mpsc::UnboundedSender<ExternalSignal>;
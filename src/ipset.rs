@@ -0,0 +1,250 @@
+use crate::firewall::FirewallBackend;
+use log::{debug, info, warn};
+use std::net::IpAddr;
+use std::process::{Command, Output};
+
+/// 默认的集合名，IPv6 集合在其后加 `6`
+pub const DEFAULT_SET: &str = "uablock";
+
+/// 默认的集合容量（ipset 自身的默认值 65536 对大规模扫描偏小）
+pub const DEFAULT_MAXELEM: u32 = 1_048_576;
+
+/// 匹配规则所在的默认内置链
+const DEFAULT_CHAIN: &str = "INPUT";
+
+/// 执行 iptables 命令时等待 xtables 锁的秒数
+const LOCK_WAIT_SECS: &str = "5";
+
+/// ipset 后端：封禁的 IP 放在 `hash:ip` 集合中，由一条 `iptables -m set` 规则统一丢弃
+///
+/// 封禁和解封只执行 `ipset add/del`，不再每个 IP 一条 iptables 规则，封禁数万个 IP 时
+/// 匹配开销和规则操作的耗时都不随数量增长。[`IpsetManager::setup`] 创建集合（已存在时保留
+/// 内容）并在链的开头插入匹配规则（已存在时不重复插入）；IPv6 集合由 ip6tables 规则匹配，
+/// 系统不支持 ip6tables 时只告警，IPv6 封禁不会生效。
+pub struct IpsetManager {
+    set_v4: String,
+    set_v6: String,
+    chain: String,
+    block_port: Option<u16>,
+    maxelem: u32,
+}
+
+impl IpsetManager {
+    pub fn new(block_port: Option<u16>) -> Self {
+        Self {
+            set_v4: DEFAULT_SET.to_string(),
+            set_v6: format!("{}6", DEFAULT_SET),
+            chain: DEFAULT_CHAIN.to_string(),
+            block_port,
+            maxelem: DEFAULT_MAXELEM,
+        }
+    }
+
+    /// 集合名（默认 uablock），IPv6 集合在其后加 `6`
+    pub fn with_set_name(mut self, name: &str) -> Self {
+        self.set_v4 = name.to_string();
+        self.set_v6 = format!("{}6", name);
+        self
+    }
+
+    /// 匹配规则所在的链（默认 INPUT）
+    pub fn with_chain(mut self, chain: &str) -> Self {
+        self.chain = chain.to_string();
+        self
+    }
+
+    /// 新建集合的容量（默认 1048576；已存在的集合不受影响）
+    pub fn with_maxelem(mut self, maxelem: u32) -> Self {
+        self.maxelem = maxelem;
+        self
+    }
+
+    pub fn set_name(&self) -> &str {
+        &self.set_v4
+    }
+
+    /// 创建集合和匹配规则，重复执行不会产生重复的集合或规则
+    pub fn setup(&self) -> Result<(), String> {
+        for (set, family) in [(&self.set_v4, "inet"), (&self.set_v6, "inet6")] {
+            if self.ipset(&["list", "-n", set])?.status.success() {
+                continue;
+            }
+            let maxelem = self.maxelem.to_string();
+            let output = self.ipset(&[
+                "create", set, "hash:ip", "family", family, "maxelem", &maxelem,
+            ])?;
+            if !output.status.success() {
+                return Err(format!(
+                    "创建 ipset 集合 {} 失败: {}",
+                    set,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            info!("已创建 ipset 集合 {}（{}，容量 {}）", set, family, maxelem);
+        }
+        self.insert_rule("iptables", &self.set_v4)?;
+        if let Err(e) = self.insert_rule("ip6tables", &self.set_v6) {
+            warn!("{}，IPv6 封禁不会生效", e);
+        }
+        info!(
+            "ipset 后端已就绪（集合 {}/{}，链 {}，端口 {}）",
+            self.set_v4,
+            self.set_v6,
+            self.chain,
+            self.block_port
+                .map_or("全部".to_string(), |p| p.to_string())
+        );
+        Ok(())
+    }
+
+    /// 在链的开头插入匹配集合的 DROP 规则（已存在时不做任何事）
+    fn insert_rule(&self, command: &str, set: &str) -> Result<(), String> {
+        let mut rule = Vec::new();
+        if let Some(port) = self.block_port {
+            rule.extend(["-p".to_string(), "udp".to_string()]);
+            rule.extend(["--dport".to_string(), port.to_string()]);
+        }
+        rule.extend(["-m", "set", "--match-set", set, "src", "-j", "DROP"].map(str::to_string));
+        let run = |position: &[&str]| -> Result<Output, String> {
+            Command::new(command)
+                .args(["-w", LOCK_WAIT_SECS])
+                .args(position)
+                .args(&rule)
+                .output()
+                .map_err(|e| format!("执行 {} 命令失败: {}", command, e))
+        };
+        if run(&["-C", &self.chain])?.status.success() {
+            return Ok(());
+        }
+        let output = run(&["-I", &self.chain, "1"])?;
+        if !output.status.success() {
+            return Err(format!(
+                "在 {} 中插入匹配集合 {} 的规则失败: {}",
+                self.chain,
+                set,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("已在 {} 开头插入匹配集合 {} 的规则", self.chain, set);
+        Ok(())
+    }
+
+    fn set_for(&self, ip: &IpAddr) -> &str {
+        match ip {
+            IpAddr::V4(_) => &self.set_v4,
+            IpAddr::V6(_) => &self.set_v6,
+        }
+    }
+
+    fn ipset(&self, args: &[&str]) -> Result<Output, String> {
+        debug!("执行 ipset 命令: ipset {}", args.join(" "));
+        Command::new("ipset")
+            .args(args)
+            .output()
+            .map_err(|e| format!("执行 ipset 命令失败: {}", e))
+    }
+
+    /// 解析 `ipset save <集合>` 输出中的成员（`add <集合> <IP> [选项]`）
+    pub fn parse_members(save: &str) -> Vec<IpAddr> {
+        save.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some("add"), Some(_), Some(ip)) => ip.parse().ok(),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+impl FirewallBackend for IpsetManager {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        let output = self.ipset(&["add", self.set_for(ip), &ip.to_string(), "-exist"])?;
+        if !output.status.success() {
+            return Err(format!(
+                "ipset 添加封禁失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("成功封禁 IP: {}", ip);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        if !self.is_blocked(ip) {
+            return Ok(());
+        }
+        let output = self.ipset(&["del", self.set_for(ip), &ip.to_string(), "-exist"])?;
+        if !output.status.success() {
+            return Err(format!(
+                "ipset 解除封禁失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("成功解封 IP: {}", ip);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        matches!(
+            self.ipset(&["test", self.set_for(ip), &ip.to_string()]),
+            Ok(output) if output.status.success()
+        )
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let mut ips = Vec::new();
+        for set in [&self.set_v4, &self.set_v6] {
+            let output = self.ipset(&["save", set])?;
+            if !output.status.success() {
+                return Err(format!(
+                    "获取 ipset 集合 {} 失败: {}",
+                    set,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            ips.extend(Self::parse_members(&String::from_utf8_lossy(
+                &output.stdout,
+            )));
+        }
+        Ok(ips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipset_backend_reads_members_and_is_selectable_in_config() {
+        let save = "create uablock hash:ip family inet hashsize 1024 maxelem 1048576\n\
+                add uablock 203.0.113.5\n\
+                add uablock 198.51.100.7 timeout 600\n\
+                add uablock not-an-ip\n";
+        assert_eq!(
+            IpsetManager::parse_members(save),
+            vec![ip("203.0.113.5"), ip("198.51.100.7")]
+        );
+        assert_eq!(IpsetManager::new(Some(5060)).set_name(), "uablock");
+        assert_eq!(
+            IpsetManager::new(None).with_set_name("sipblock").set_name(),
+            "sipblock"
+        );
+
+        let config = Config::parse("backend = \"ipset\"").unwrap();
+        assert_eq!(config.backend.as_deref(), Some("ipset"));
+        if std::env::var_os("UABLOCK_BACKEND").is_none() {
+            assert!(config
+                .env_defaults()
+                .contains(&("UABLOCK_BACKEND".to_string(), "ipset".to_string())));
+        }
+        let error = Config::parse("backend = \"ebtables\"").unwrap_err();
+        assert!(error.contains("backend"), "{}", error);
+    }
+}
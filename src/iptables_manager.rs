@@ -1,11 +1,105 @@
+use crate::firewall::FirewallBackend;
+use crate::stats::Stats;
 use log::{debug, error, info, warn};
+use std::collections::HashSet;
 use std::net::IpAddr;
-use std::process::Command;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 等待 xtables 锁的默认秒数（`iptables -w`）
+const DEFAULT_LOCK_WAIT_SECS: u32 = 5;
+
+/// 临时错误的默认重试次数
+const DEFAULT_RETRIES: u32 = 3;
+
+/// 第一次重试前的等待，之后每次翻倍
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 默认的专用链
+pub const DEFAULT_CHAIN: &str = "UABLOCK";
+
+/// 默认从哪条内置链跳转到专用链
+pub const DEFAULT_PARENT_CHAIN: &str = "INPUT";
+
+/// 默认的封禁动作
+pub const DEFAULT_TARGET: &str = "DROP";
+
+/// 本工具添加的每条封禁规则都带的注释（`-m comment --comment uablock`），检查、列出和删除规则时按它匹配
+pub const RULE_COMMENT: &str = "uablock";
+
+/// REJECT 的 ICMP 类型在 ip6tables 中的对应名称
+const ICMP6_REJECT_TYPES: [(&str, &str); 5] = [
+    ("icmp-port-unreachable", "icmp6-port-unreachable"),
+    ("icmp-host-unreachable", "icmp6-addr-unreachable"),
+    ("icmp-net-unreachable", "icmp6-no-route"),
+    ("icmp-admin-prohibited", "icmp6-adm-prohibited"),
+    ("icmp-net-prohibited", "icmp6-adm-prohibited"),
+];
+
+/// 处理 IPv4 和 IPv6 规则的命令
+const IPTABLES: &str = "iptables";
+const IP6TABLES: &str = "ip6tables";
+
+/// 执行 iptables/ip6tables 命令的方式
+trait CommandRunner: Send + Sync {
+    /// 执行命令并等待它结束
+    fn output(&self, command: &str, args: &[String]) -> std::io::Result<Output>;
+    /// 重试前的等待
+    fn sleep(&self, delay: Duration);
+}
+
+/// 执行系统中的命令
+struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, command: &str, args: &[String]) -> std::io::Result<Output> {
+        Command::new(command).args(args).output()
+    }
+
+    fn sleep(&self, delay: Duration) {
+        std::thread::sleep(delay);
+    }
+}
+
+/// 内置链：配置为内置链时直接把规则写在其中，不创建专用链
+const BUILTIN_CHAINS: [&str; 5] = ["INPUT", "FORWARD", "OUTPUT", "PREROUTING", "POSTROUTING"];
 
 /// iptables 管理器，用于封禁和解封 IP
+///
+/// 所有命令都带 `-w` 等待 xtables 锁；仍然因锁或资源暂时不可用失败（退出码 4）时按指数退避重试，
+/// 重试次数计入 `stats.firewall_retries`。
+///
+/// 规则默认放在专用链 `UABLOCK` 中，由 [`IptablesManager::setup`] 创建该链并在 `INPUT` 开头插入
+/// 一条跳转，与其他防火墙工具的规则互不干扰，清空专用链（`iptables -F UABLOCK`）也只会解除本工具的
+/// 封禁。链配置为 `INPUT` 等内置链时直接把规则写在其中（旧版本的行为）。
+///
+/// IPv4 地址的规则由 `iptables` 管理，IPv6 地址的规则由 `ip6tables` 管理，两边使用同名的链；
+/// 系统不支持 ip6tables 时（[`IptablesManager::setup`] 失败）只告警，IPv6 地址的封禁返回错误。
+///
+/// 封禁规则默认 `-j DROP`，可以改为 `REJECT --reject-with icmp-port-unreachable`（配置有误的合法
+/// 终端立即收到错误，而不是一直重试到超时）或自定义链；检查和列出规则时按配置的动作匹配。
+///
+/// 每条封禁规则都带注释 [`RULE_COMMENT`]，检查（`-C`）、删除（`-D`）和列出（`-S`）都只认带注释的
+/// 规则，不再在 `iptables -L` 的输出中按子串查找（端口会被显示为 `dpt:sip` 等服务名，IP 也可能是
+/// 其他地址的子串）。旧版本添加的无注释规则在 [`IptablesManager::setup`] 时换成带注释的规则。
+///
+/// 检查是否已封禁每次执行一条 `iptables -C`；守护进程中由
+/// [`BlockedCache`](crate::blocked_cache::BlockedCache) 在内存中缓存封禁的 IP。
 pub struct IptablesManager {
     chain_name: String,
+    /// 跳转到专用链的内置链
+    parent_chain: String,
     block_port: Option<u16>,
+    /// 封禁规则的动作：`-j` 之后的参数
+    target: Vec<String>,
+    lock_wait_secs: u32,
+    retries: u32,
+    stats: Option<Arc<Stats>>,
+    /// ip6tables 是否可用
+    ipv6: AtomicBool,
+    runner: Box<dyn CommandRunner>,
 }
 
 impl IptablesManager {
@@ -15,113 +109,417 @@ impl IptablesManager {
 
     pub fn new_with_port(chain_name: Option<String>, block_port: Option<u16>) -> Self {
         Self {
-            chain_name: chain_name.unwrap_or_else(|| "INPUT".to_string()),
+            chain_name: chain_name.unwrap_or_else(|| DEFAULT_CHAIN.to_string()),
+            parent_chain: DEFAULT_PARENT_CHAIN.to_string(),
             block_port,
+            target: vec![DEFAULT_TARGET.to_string()],
+            lock_wait_secs: DEFAULT_LOCK_WAIT_SECS,
+            retries: DEFAULT_RETRIES,
+            stats: None,
+            ipv6: AtomicBool::new(true),
+            runner: Box::new(SystemRunner),
         }
     }
 
-    /// 检查 IP 是否已被封禁
-    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
-        // 先尝试使用 -C 检查（更快速）
-        let ip_str = ip.to_string();
-        let mut args: Vec<String> = vec![
-            "-C".to_string(),
-            self.chain_name.clone(),
+    /// 跳转到专用链的内置链（默认 INPUT）
+    pub fn with_parent_chain(mut self, parent: &str) -> Self {
+        self.parent_chain = parent.to_string();
+        self
+    }
+
+    pub fn chain(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// 规则是否放在专用链中（而不是直接写在内置链中）
+    pub fn is_dedicated_chain(&self) -> bool {
+        !BUILTIN_CHAINS.contains(&self.chain_name.as_str())
+    }
+
+    /// 封禁规则的动作（`-j` 之后的部分，默认 DROP），如 `REJECT --reject-with icmp-port-unreachable`
+    /// 或自定义链的名称；IPv6 规则中 REJECT 的 ICMP 类型换成对应的 ICMPv6 类型
+    pub fn with_target(mut self, target: &str) -> Self {
+        let target: Vec<String> = target.split_whitespace().map(str::to_string).collect();
+        if !target.is_empty() {
+            self.target = target;
+        }
+        self
+    }
+
+    /// 封禁规则跳转的目标（DROP、REJECT 或自定义链）
+    pub fn target(&self) -> &str {
+        &self.target[0]
+    }
+
+    /// 该地址的规则中 `-j` 之后的参数
+    pub fn target_args(&self, ip: &IpAddr) -> Vec<String> {
+        self.target
+            .iter()
+            .map(|arg| match ip {
+                IpAddr::V6(_) => ICMP6_REJECT_TYPES
+                    .iter()
+                    .find(|(icmp, _)| icmp == arg)
+                    .map_or(arg.clone(), |(_, icmp6)| icmp6.to_string()),
+                IpAddr::V4(_) => arg.clone(),
+            })
+            .collect()
+    }
+
+    /// 每条命令等待 xtables 锁的秒数（默认 5）
+    pub fn with_lock_wait(mut self, secs: u32) -> Self {
+        self.lock_wait_secs = secs;
+        self
+    }
+
+    /// 临时错误的重试次数（默认 3，0 表示不重试）
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 把重试次数计入运行统计
+    pub fn with_stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// 执行 iptables（或 ip6tables）命令：带 `-w` 等待锁，临时错误按指数退避重试
+    fn run<S: AsRef<str>>(&self, command: &str, args: &[S]) -> std::io::Result<Output> {
+        let args: Vec<String> = ["-w".to_string(), self.lock_wait_secs.to_string()]
+            .into_iter()
+            .chain(args.iter().map(|arg| arg.as_ref().to_string()))
+            .collect();
+        let mut delay = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let output = self.runner.output(command, &args)?;
+            if output.status.success() || attempt >= self.retries || !is_transient(&output) {
+                return Ok(output);
+            }
+            attempt += 1;
+            if let Some(stats) = &self.stats {
+                Stats::incr(&stats.firewall_retries);
+            }
+            debug!(
+                "{} 暂时不可用（{}），{} 毫秒后第 {} 次重试",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim(),
+                delay.as_millis(),
+                attempt
+            );
+            self.runner.sleep(delay);
+            delay *= 2;
+        }
+    }
+
+    /// 准备专用链：不存在时创建，确保父链中有跳转，并把旧版本写在父链中的封禁规则移入专用链
+    ///
+    /// iptables 和 ip6tables 中各准备一次；ip6tables 失败时只告警，之后不再处理 IPv6 地址。
+    /// 链配置为内置链时只检查 ip6tables 是否可用。最后给链中无注释的旧规则加上注释。
+    /// 重复执行不会产生重复的链、跳转或规则。
+    pub fn setup(&self) -> Result<(), String> {
+        if self.is_dedicated_chain() {
+            self.setup_chain(IPTABLES)?;
+            self.migrate()?;
+            if let Err(e) = self.setup_chain(IP6TABLES) {
+                warn!("{}，IPv6 地址的封禁不会生效", e);
+                self.ipv6.store(false, Ordering::Relaxed);
+            }
+        } else {
+            let available = self
+                .run(IP6TABLES, &["-S", &self.chain_name])
+                .is_ok_and(|output| output.status.success());
+            if !available {
+                warn!("ip6tables 不可用，IPv6 地址的封禁不会生效");
+            }
+            self.ipv6.store(available, Ordering::Relaxed);
+        }
+        self.retag(IPTABLES)?;
+        if self.ipv6.load(Ordering::Relaxed) {
+            if let Err(e) = self.retag(IP6TABLES) {
+                warn!("{}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 在 iptables 或 ip6tables 中创建专用链和父链中的跳转（已存在时不做任何事）
+    fn setup_chain(&self, command: &str) -> Result<(), String> {
+        let chain = self.chain_name.as_str();
+        let parent = self.parent_chain.as_str();
+        let exists = self
+            .run(command, &["-S", chain])
+            .map_err(|e| format!("执行 {} 命令失败: {}", command, e))?
+            .status
+            .success();
+        if !exists {
+            self.check(
+                command,
+                &["-N", chain],
+                &format!("创建 {} 链 {}", command, chain),
+            )?;
+            info!("已创建 {} 链 {}", command, chain);
+        }
+        let jumps = self
+            .run(command, &["-C", parent, "-j", chain])
+            .map_err(|e| format!("执行 {} 命令失败: {}", command, e))?
+            .status
+            .success();
+        if !jumps {
+            self.check(
+                command,
+                &["-I", parent, "1", "-j", chain],
+                &format!("在 {} 的 {} 中插入到 {} 的跳转", command, parent, chain),
+            )?;
+            info!("已在 {} 的 {} 开头插入到 {} 的跳转", command, parent, chain);
+        }
+        Ok(())
+    }
+
+    /// 把父链中本工具旧版本添加的封禁规则（同一端口的 DROP）移入专用链
+    ///
+    /// 旧版本只添加过 IPv4 的 DROP 规则，只迁移 iptables 中的规则，移入后使用配置的动作。
+    fn migrate(&self) -> Result<(), String> {
+        // 没有端口限制时无法区分其他工具的 DROP 规则，不迁移
+        if self.block_port.is_none() {
+            return Ok(());
+        }
+        let legacy: Vec<IpAddr> = self
+            .list_chain(IPTABLES, &self.parent_chain, DEFAULT_TARGET)?
+            .into_iter()
+            .filter(|(_, tagged)| !tagged)
+            .map(|(ip, _)| ip)
+            .collect();
+        let present: HashSet<IpAddr> = self
+            .list_chain(IPTABLES, &self.chain_name, self.target())?
+            .into_iter()
+            .map(|(ip, _)| ip)
+            .collect();
+        for ip in &legacy {
+            if !present.contains(ip) {
+                self.check(
+                    IPTABLES,
+                    &self.rule_args("-A", &self.chain_name, ip),
+                    &format!("把 {} 的封禁移入 {}", ip, self.chain_name),
+                )?;
+            }
+            self.check(
+                IPTABLES,
+                &self.rule(
+                    "-D",
+                    &self.parent_chain,
+                    ip,
+                    &[DEFAULT_TARGET.to_string()],
+                    false,
+                ),
+                &format!("删除 {} 中 {} 的旧封禁规则", self.parent_chain, ip),
+            )?;
+        }
+        if !legacy.is_empty() {
+            info!(
+                "已把 {} 中的 {} 条封禁规则移入 {}",
+                self.parent_chain,
+                legacy.len(),
+                self.chain_name
+            );
+        }
+        Ok(())
+    }
+
+    /// 把链中旧版本添加的无注释封禁规则换成带注释的规则（先添加，再删除旧规则）
+    fn retag(&self, command: &str) -> Result<(), String> {
+        // 内置链中没有端口限制时无法区分其他工具的规则，不处理
+        if !self.is_dedicated_chain() && self.block_port.is_none() {
+            return Ok(());
+        }
+        let rules = self.list_chain(command, &self.chain_name, self.target())?;
+        let tagged: HashSet<IpAddr> = rules
+            .iter()
+            .filter(|(_, tagged)| *tagged)
+            .map(|(ip, _)| *ip)
+            .collect();
+        let mut retagged = 0;
+        for (ip, _) in rules.iter().filter(|(_, tagged)| !tagged) {
+            if !tagged.contains(ip) {
+                self.check(
+                    command,
+                    &self.rule_args("-A", &self.chain_name, ip),
+                    &format!("为 {} 添加带注释的封禁规则", ip),
+                )?;
+            }
+            self.check(
+                command,
+                &self.rule("-D", &self.chain_name, ip, &self.target_args(ip), false),
+                &format!("删除 {} 的无注释封禁规则", ip),
+            )?;
+            retagged += 1;
+        }
+        if retagged > 0 {
+            info!(
+                "已为 {} 中的 {} 条封禁规则加上注释 {}",
+                self.chain_name, retagged, RULE_COMMENT
+            );
+        }
+        Ok(())
+    }
+
+    /// 执行命令，失败时返回带说明的错误
+    fn check<S: AsRef<str>>(&self, command: &str, args: &[S], action: &str) -> Result<(), String> {
+        let output = self
+            .run(command, args)
+            .map_err(|e| format!("执行 {} 命令失败: {}", command, e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{}失败: {}",
+                action,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// 封禁规则的参数：`<command> <chain> -s <ip> [-p udp --dport <port>] -m comment --comment uablock -j <动作>`
+    fn rule_args(&self, command: &str, chain: &str, ip: &IpAddr) -> Vec<String> {
+        self.rule(command, chain, ip, &self.target_args(ip), true)
+    }
+
+    /// 指定动作的封禁规则参数，`tagged` 为 false 时不带注释（旧版本的规则）
+    fn rule(
+        &self,
+        command: &str,
+        chain: &str,
+        ip: &IpAddr,
+        target: &[String],
+        tagged: bool,
+    ) -> Vec<String> {
+        let mut args = vec![
+            command.to_string(),
+            chain.to_string(),
             "-s".to_string(),
-            ip_str.clone(),
+            ip.to_string(),
         ];
-
-        // 如果指定了端口，添加端口限制
         if let Some(port) = self.block_port {
-            let port_str = port.to_string();
-            args.extend_from_slice(&[
+            args.extend([
                 "-p".to_string(),
                 "udp".to_string(),
                 "--dport".to_string(),
-                port_str,
+                port.to_string(),
             ]);
         }
+        if tagged {
+            args.extend(["-m", "comment", "--comment", RULE_COMMENT].map(str::to_string));
+        }
+        args.push("-j".to_string());
+        args.extend_from_slice(target);
+        args
+    }
 
-        args.extend_from_slice(&["-j".to_string(), "DROP".to_string()]);
-
-        let output = Command::new("iptables").args(&args).output();
+    /// 处理该地址规则的命令：IPv4 为 iptables，IPv6 为 ip6tables
+    fn command_for(ip: &IpAddr) -> &'static str {
+        match ip {
+            IpAddr::V4(_) => IPTABLES,
+            IpAddr::V6(_) => IP6TABLES,
+        }
+    }
 
-        match output {
-            Ok(result) if result.status.success() => return true,
-            _ => {}
+    /// 系统不支持 ip6tables 时，IPv6 地址返回错误
+    fn ensure_supported(&self, ip: &IpAddr) -> Result<(), String> {
+        if ip.is_ipv6() && !self.ipv6.load(Ordering::Relaxed) {
+            return Err(format!("ip6tables 不可用，无法处理 IPv6 地址 {}", ip));
         }
+        Ok(())
+    }
 
-        // 如果 -C 检查失败，尝试列出规则并手动检查（更可靠）
-        let list_output = Command::new("iptables")
-            .args(["-L", &self.chain_name, "-n", "--line-numbers"])
-            .output();
-
-        match list_output {
-            Ok(result) if result.status.success() => {
-                let output_str = String::from_utf8_lossy(&result.stdout);
-                let ip_str = ip.to_string();
-
-                for line in output_str.lines() {
-                    if line.contains(&ip_str) && line.contains("DROP") {
-                        // 如果指定了端口，检查端口是否匹配
-                        if let Some(port) = self.block_port {
-                            // 检查端口号（数字格式：dpt:5060）
-                            // 或者服务名称（sip 对应 5060）
-                            let port_match = line.contains(&format!("dpt:{}", port))
-                                || line.contains(&port.to_string())
-                                || (port == 5060
-                                    && (line.contains("dpt:sip") || line.contains("sip")));
-
-                            if port_match {
-                                debug!("在规则中找到匹配的封禁规则: {}", line);
-                                return true;
-                            }
-                        } else {
-                            // 没有指定端口，只要包含 IP 和 DROP 就认为被封禁
-                            debug!("在规则中找到匹配的封禁规则: {}", line);
-                            return true;
-                        }
-                    }
-                }
-                false
-            }
+    /// 检查 IP 是否已被封禁
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        if self.ensure_supported(ip).is_err() {
+            return false;
+        }
+        let command = Self::command_for(ip);
+        match self.run(command, &self.rule_args("-C", &self.chain_name, ip)) {
+            Ok(result) => result.status.success(),
             Err(e) => {
                 debug!("检查 IP {} 封禁状态失败: {}", ip, e);
                 false
             }
-            _ => false,
         }
     }
 
-    /// 封禁 IP
-    pub fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
-        if self.is_blocked(ip) {
-            debug!("IP {} 已经被封禁", ip);
-            return Ok(());
+    /// 列出本工具管理的所有已封禁 IP（解析 `iptables -S` 和 `ip6tables -S` 输出）
+    pub fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let mut rules = self.list_chain(IPTABLES, &self.chain_name, self.target())?;
+        if self.ipv6.load(Ordering::Relaxed) {
+            rules.extend(self.list_chain(IP6TABLES, &self.chain_name, self.target())?);
         }
-        let ip_str = ip.to_string();
-        let mut args: Vec<String> = vec![
-            "-A".to_string(),
-            self.chain_name.clone(),
-            "-s".to_string(),
-            ip_str.clone(),
-        ];
+        Ok(rules
+            .into_iter()
+            .filter(|(_, tagged)| *tagged)
+            .map(|(ip, _)| ip)
+            .collect())
+    }
+
+    /// 列出链中跳转到 `target` 的封禁规则的来源 IP，以及规则是否带本工具的注释
+    fn list_chain(
+        &self,
+        command: &str,
+        chain: &str,
+        target: &str,
+    ) -> Result<Vec<(IpAddr, bool)>, String> {
+        let output = self
+            .run(command, &["-S", chain])
+            .map_err(|e| format!("执行 {} 命令失败: {}", command, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "获取 {} 规则列表失败: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| self.parse_rule(line, target))
+            .collect())
+    }
 
-        // 如果指定了端口，添加端口限制
+    /// 解析 `iptables -S` 的一行：跳转到 `target`（且端口匹配）的封禁规则返回来源 IP
+    /// 和规则是否带注释 [`RULE_COMMENT`]，其他规则返回 None
+    pub fn parse_rule(&self, line: &str, target: &str) -> Option<(IpAddr, bool)> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.first() != Some(&"-A") || !args.windows(2).any(|w| w == ["-j", target]) {
+            return None;
+        }
         if let Some(port) = self.block_port {
             let port_str = port.to_string();
-            args.extend_from_slice(&[
-                "-p".to_string(),
-                "udp".to_string(),
-                "--dport".to_string(),
-                port_str,
-            ]);
+            if !args
+                .windows(2)
+                .any(|w| w[0] == "--dport" && w[1] == port_str)
+            {
+                return None;
+            }
         }
+        let ip = args
+            .windows(2)
+            .find(|w| w[0] == "-s")
+            .map(|w| w[1].trim_end_matches("/32").trim_end_matches("/128"))?
+            .parse::<IpAddr>()
+            .ok()?;
+        let tagged = args
+            .windows(2)
+            .any(|w| w[0] == "--comment" && w[1].trim_matches('"') == RULE_COMMENT);
+        Some((ip, tagged))
+    }
 
-        args.extend_from_slice(&["-j".to_string(), "DROP".to_string()]);
-
-        debug!("执行 iptables 命令: iptables {}", args.join(" "));
-        let output = Command::new("iptables").args(&args).output();
+    /// 封禁 IP
+    ///
+    /// 只执行一条 `-A` 命令：是否已封禁由调用方（执行器）事先检查，规则是否生效由定期核查
+    /// （见 [`RuleAudit`](crate::rule_audit::RuleAudit)）批量确认，避免每次封禁多执行几次 iptables。
+    pub fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.ensure_supported(ip)?;
+        let command = Self::command_for(ip);
+        let args = self.rule_args("-A", &self.chain_name, ip);
+        debug!("执行 {} 命令: {} {}", command, command, args.join(" "));
+        let output = self.run(command, &args);
 
         match output {
             Ok(result) => {
@@ -131,26 +529,6 @@ impl IptablesManager {
                         .map(|p| format!("端口 {}", p))
                         .unwrap_or_else(|| "所有端口".to_string());
                     info!("成功封禁 IP: {} {}", ip, port_info);
-
-                    // 验证规则是否真的被添加
-                    if !self.is_blocked(ip) {
-                        warn!(
-                            "警告：封禁 IP {} 后，检查状态显示未封禁，可能规则未正确添加",
-                            ip
-                        );
-                        // 列出当前规则以便调试
-                        let list_output = Command::new("iptables")
-                            .args(["-L", &self.chain_name, "-n", "--line-numbers"])
-                            .output();
-                        if let Ok(list_result) = list_output {
-                            if list_result.status.success() {
-                                debug!(
-                                    "当前 iptables 规则:\n{}",
-                                    String::from_utf8_lossy(&list_result.stdout)
-                                );
-                            }
-                        }
-                    }
                     Ok(())
                 } else {
                     let error_msg = String::from_utf8_lossy(&result.stderr);
@@ -164,7 +542,7 @@ impl IptablesManager {
                 }
             }
             Err(e) => {
-                let msg = format!("执行 iptables 命令失败: {}", e);
+                let msg = format!("执行 {} 命令失败: {}", command, e);
                 error!("{}", msg);
                 Err(msg)
             }
@@ -177,83 +555,8 @@ impl IptablesManager {
             debug!("IP {} 未被封禁，无需解封", ip);
             return Ok(());
         }
-        // 先找到规则的行号
-        let output = Command::new("iptables")
-            .args(["-L", &self.chain_name, "--line-numbers", "-n"])
-            .output();
-
-        let line_numbers = match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let error_msg = String::from_utf8_lossy(&result.stderr);
-                    return Err(format!("获取 iptables 规则列表失败: {}", error_msg));
-                }
-                String::from_utf8_lossy(&result.stdout).to_string()
-            }
-            Err(e) => {
-                return Err(format!("执行 iptables 命令失败: {}", e));
-            }
-        };
-
-        // 查找匹配的规则行号
-        let target_ip = ip.to_string();
-        for line in line_numbers.lines() {
-            if line.contains(&target_ip) && line.contains("DROP") {
-                // 如果指定了端口，检查端口是否匹配
-                let port_matches = if let Some(port) = self.block_port {
-                    line.contains(&port.to_string())
-                } else {
-                    true
-                };
-
-                if port_matches {
-                    if let Some(line_num) = line.split_whitespace().next() {
-                        if let Ok(num) = line_num.parse::<u32>() {
-                            // 删除规则
-                            let delete_output = Command::new("iptables")
-                                .args(["-D", &self.chain_name, &num.to_string()])
-                                .output();
-
-                            match delete_output {
-                                Ok(result) => {
-                                    if result.status.success() {
-                                        info!("成功解封 IP: {}", ip);
-                                        return Ok(());
-                                    } else {
-                                        let error_msg = String::from_utf8_lossy(&result.stderr);
-                                        warn!("删除规则失败: {}", error_msg);
-                                    }
-                                }
-                                Err(e) => {
-                                    warn!("执行删除命令失败: {}", e);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        // 如果找不到规则，尝试直接删除（可能规则格式不同）
-        let ip_str = ip.to_string();
-        let mut delete_args: Vec<String> = vec![
-            "-D".to_string(),
-            self.chain_name.clone(),
-            "-s".to_string(),
-            ip_str,
-        ];
-        if let Some(port) = self.block_port {
-            let port_str = port.to_string();
-            delete_args.extend_from_slice(&[
-                "-p".to_string(),
-                "udp".to_string(),
-                "--dport".to_string(),
-                port_str,
-            ]);
-        }
-        delete_args.extend_from_slice(&["-j".to_string(), "DROP".to_string()]);
-
-        let output = Command::new("iptables").args(&delete_args).output();
+        let command = Self::command_for(ip);
+        let output = self.run(command, &self.rule_args("-D", &self.chain_name, ip));
 
         match output {
             Ok(result) => {
@@ -268,7 +571,7 @@ impl IptablesManager {
                 }
             }
             Err(e) => {
-                let msg = format!("执行 iptables 命令失败: {}", e);
+                let msg = format!("执行 {} 命令失败: {}", command, e);
                 warn!("{}", msg);
                 Err(msg)
             }
@@ -276,8 +579,242 @@ impl IptablesManager {
     }
 }
 
+/// 锁被占用或资源暂时不可用：iptables 的退出码 4（RESOURCE_PROBLEM），或旧版本只在 stderr 中说明
+fn is_transient(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    output.status.code() == Some(4)
+        || stderr.contains("Resource temporarily unavailable")
+        || stderr.contains("xtables lock")
+}
+
 impl Default for IptablesManager {
     fn default() -> Self {
         Self::new(None)
     }
 }
+
+impl FirewallBackend for IptablesManager {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        IptablesManager::block_ip(self, ip)
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        IptablesManager::unblock_ip(self, ip)
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        IptablesManager::is_blocked(self, ip)
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        IptablesManager::list_blocked(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::sync::Mutex;
+
+    /// 按顺序返回预设的退出码和 stderr（用完后都成功），记录执行的命令和退避等待
+    #[derive(Default)]
+    struct Script {
+        results: Mutex<VecDeque<(i32, &'static str)>>,
+        calls: Mutex<Vec<(String, Vec<String>)>>,
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl CommandRunner for Arc<Script> {
+        fn output(&self, command: &str, args: &[String]) -> std::io::Result<Output> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((command.to_string(), args.to_vec()));
+            let (code, stderr) = self.results.lock().unwrap().pop_front().unwrap_or((0, ""));
+            Ok(Output {
+                status: ExitStatus::from_raw(code << 8),
+                stdout: Vec::new(),
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        }
+
+        fn sleep(&self, delay: Duration) {
+            self.sleeps.lock().unwrap().push(delay);
+        }
+    }
+
+    fn scripted(results: &[(i32, &'static str)]) -> (IptablesManager, Arc<Script>) {
+        let script = Arc::new(Script::default());
+        script
+            .results
+            .lock()
+            .unwrap()
+            .extend(results.iter().copied());
+        let manager = IptablesManager {
+            runner: Box::new(script.clone()),
+            ..IptablesManager::new_with_port(None, Some(5060))
+        };
+        (manager, script)
+    }
+
+    fn millis(script: &Script) -> Vec<u128> {
+        script
+            .sleeps
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Duration::as_millis)
+            .collect()
+    }
+
+    #[test]
+    fn resource_problems_are_retried_with_doubling_backoff() {
+        let (manager, script) = scripted(&[(4, ""), (4, "")]);
+        let stats = Arc::new(Stats::default());
+        let manager = manager.with_stats(stats.clone());
+
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_ok());
+        assert_eq!(script.calls.lock().unwrap().len(), 3);
+        assert_eq!(millis(&script), [100, 200]);
+        assert_eq!(stats.firewall_retries.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn retries_stop_after_the_configured_count() {
+        let (manager, script) = scripted(&[(4, ""); 10]);
+        let manager = manager.with_retries(2);
+
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_err());
+        assert_eq!(script.calls.lock().unwrap().len(), 3);
+        assert_eq!(millis(&script), [100, 200]);
+
+        let (manager, script) = scripted(&[(4, "")]);
+        let manager = manager.with_retries(0);
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_err());
+        assert_eq!(script.calls.lock().unwrap().len(), 1);
+        assert!(script.sleeps.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn lock_messages_are_transient_but_other_failures_are_not() {
+        let (manager, script) =
+            scripted(&[(1, "Another app is currently holding the xtables lock")]);
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_ok());
+        assert_eq!(script.calls.lock().unwrap().len(), 2);
+
+        let (manager, script) = scripted(&[(1, "iptables: Bad rule"), (4, "")]);
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_err());
+        assert_eq!(script.calls.lock().unwrap().len(), 1);
+        assert!(script.sleeps.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ipv4_and_ipv6_rules_go_to_their_own_command() {
+        let (manager, script) = scripted(&[]);
+        let manager = manager
+            .with_lock_wait(7)
+            .with_target("REJECT --reject-with icmp-port-unreachable");
+
+        manager.block_ip(&"10.0.0.1".parse().unwrap()).unwrap();
+        manager.block_ip(&"2001:db8::1".parse().unwrap()).unwrap();
+
+        let calls = script.calls.lock().unwrap();
+        assert_eq!(calls[0].0, "iptables");
+        assert_eq!(
+            calls[0].1.join(" "),
+            "-w 7 -A UABLOCK -s 10.0.0.1 -p udp --dport 5060 -m comment --comment uablock \
+             -j REJECT --reject-with icmp-port-unreachable"
+        );
+        assert_eq!(calls[1].0, "ip6tables");
+        assert_eq!(
+            calls[1].1.join(" "),
+            "-w 7 -A UABLOCK -s 2001:db8::1 -p udp --dport 5060 -m comment --comment uablock \
+             -j REJECT --reject-with icmp6-port-unreachable"
+        );
+    }
+
+    #[test]
+    fn ipv6_is_refused_without_running_when_ip6tables_is_missing() {
+        // iptables：链和跳转已存在，迁移时列出 INPUT 和 UABLOCK；ip6tables：链不存在且创建失败
+        let (manager, script) = scripted(&[(0, ""), (0, ""), (0, ""), (0, ""), (1, ""), (1, "")]);
+        manager.setup().unwrap();
+        let before = script.calls.lock().unwrap().clone();
+        assert_eq!(before[5].0, "ip6tables");
+        assert_eq!(before[5].1.join(" "), "-w 5 -N UABLOCK");
+        assert!(before[6..].iter().all(|(command, _)| command == "iptables"));
+
+        assert!(manager.block_ip(&"2001:db8::1".parse().unwrap()).is_err());
+        assert!(!manager.is_blocked(&"2001:db8::1".parse().unwrap()));
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_ok());
+        let calls = script.calls.lock().unwrap();
+        assert_eq!(calls.len(), before.len() + 1);
+        assert_eq!(calls.last().unwrap().0, "iptables");
+    }
+
+    #[test]
+    fn iptables_rules_default_to_a_dedicated_chain() {
+        let manager = IptablesManager::new_with_port(None, Some(5060));
+        assert_eq!(manager.chain(), DEFAULT_CHAIN);
+        assert!(manager.is_dedicated_chain());
+        // 直接写入内置链的旧配置不创建专用链
+        let legacy = IptablesManager::new(Some("INPUT".to_string()));
+        assert!(!legacy.is_dedicated_chain());
+    }
+
+    #[test]
+    fn iptables_target_is_configurable_per_address_family() {
+        let manager = IptablesManager::new(None);
+        assert_eq!(manager.target(), "DROP");
+        assert_eq!(manager.target_args(&ip("203.0.113.5")), vec!["DROP"]);
+
+        let manager =
+            IptablesManager::new(None).with_target("REJECT --reject-with icmp-port-unreachable");
+        assert_eq!(manager.target(), "REJECT");
+        assert_eq!(
+            manager.target_args(&ip("203.0.113.5")),
+            vec!["REJECT", "--reject-with", "icmp-port-unreachable"]
+        );
+        assert_eq!(
+            manager.target_args(&ip("2001:db8::5")),
+            vec!["REJECT", "--reject-with", "icmp6-port-unreachable"]
+        );
+        // 空白的设置不改变动作
+        assert_eq!(IptablesManager::new(None).with_target(" ").target(), "DROP");
+    }
+
+    #[test]
+    fn iptables_rules_are_recognised_by_their_comment() {
+        assert_eq!(RULE_COMMENT, "uablock");
+        let manager = IptablesManager::new_with_port(None, Some(5060));
+        assert_eq!(
+            manager.parse_rule(
+                "-A UABLOCK -s 203.0.113.5/32 -p udp -m udp --dport 5060 -m comment --comment uablock -j DROP",
+                "DROP"
+            ),
+            Some((ip("203.0.113.5"), true))
+        );
+        // 旧版本的规则没有注释，启动时换成带注释的规则
+        assert_eq!(
+            manager.parse_rule(
+                "-A UABLOCK -s 2001:db8::5/128 -p udp -m udp --dport 5060 -j DROP",
+                "DROP"
+            ),
+            Some((ip("2001:db8::5"), false))
+        );
+        // 其他端口、其他动作和非规则行都不是本工具的封禁
+        for line in [
+            "-A UABLOCK -s 203.0.113.5/32 -p udp -m udp --dport 50600 -m comment --comment uablock -j DROP",
+            "-A UABLOCK -s 203.0.113.5/32 -p udp -m udp --dport 5060 -m comment --comment uablock -j ACCEPT",
+            "-N UABLOCK",
+        ] {
+            assert_eq!(manager.parse_rule(line, "DROP"), None, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::events::{EventBus, EventKind, QueueReceiver};
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// JSONRPC 请求超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 未指定 htable 时使用的表名
+const DEFAULT_HTABLE: &str = "ipban";
+
+/// 一个 Kamailio 实例
+struct KamailioInstance {
+    /// jsonrpcs 模块的 HTTP 地址，例如 `http://127.0.0.1:5071/RPC`
+    url: String,
+    /// 存放封禁 IP 的 htable 名称
+    htable: String,
+}
+
+/// 待同步到 Kamailio 的操作
+enum HtableUpdate {
+    Set { ip: IpAddr, reason: String },
+    Delete { ip: IpAddr },
+}
+
+/// 把封禁/解封镜像到 Kamailio 的 htable
+///
+/// Kamailio 在路由脚本中检查 htable 并直接丢弃请求，比 iptables 更省资源。
+/// 每次封禁调用 `htable.sets <table> <ip> <reason>`，解封调用 `htable.delete <table> <ip>`。
+pub struct KamailioMirror {
+    instances: Vec<KamailioInstance>,
+    agent: ureq::Agent,
+}
+
+impl KamailioMirror {
+    /// 从环境变量创建，未设置 `UABLOCK_KAMAILIO_RPC` 时返回 None
+    ///
+    /// `UABLOCK_KAMAILIO_RPC` 为逗号分隔的实例列表，每个实例可用 `#` 指定 htable，
+    /// 例如 `http://10.0.0.1:5071/RPC#ipban,http://10.0.0.2:5071/RPC#blocked`。
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var("UABLOCK_KAMAILIO_RPC").ok()?;
+        let instances: Vec<KamailioInstance> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once('#') {
+                Some((url, htable)) => KamailioInstance {
+                    url: url.to_string(),
+                    htable: htable.to_string(),
+                },
+                None => KamailioInstance {
+                    url: entry.to_string(),
+                    htable: DEFAULT_HTABLE.to_string(),
+                },
+            })
+            .collect();
+        if instances.is_empty() {
+            return None;
+        }
+        Some(Self {
+            instances,
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+        })
+    }
+
+    /// 订阅封禁/解封事件并启动同步线程
+    pub fn start(self, events: &EventBus) -> Result<(), String> {
+        let (tx, rx) = events.queue();
+        events.subscribe(move |event| {
+            let update = match event.kind {
+                EventKind::Ban => HtableUpdate::Set {
+                    ip: event.ip,
+                    reason: event.reason.clone(),
+                },
+                EventKind::Unban => HtableUpdate::Delete { ip: event.ip },
+                EventKind::Detection | EventKind::Alert => return true,
+            };
+            tx.send(update)
+        });
+
+        for instance in &self.instances {
+            info!(
+                "【Kamailio】封禁将同步到 {}（htable: {}）",
+                instance.url, instance.htable
+            );
+        }
+        std::thread::Builder::new()
+            .name("kamailio".to_string())
+            .spawn(move || self.run(rx))
+            .map_err(|e| format!("启动 Kamailio 同步线程失败: {}", e))?;
+        Ok(())
+    }
+
+    fn run(self, rx: QueueReceiver<HtableUpdate>) {
+        let mut id: u64 = 0;
+        for update in rx {
+            for instance in &self.instances {
+                id += 1;
+                let (method, params, ip) = match &update {
+                    HtableUpdate::Set { ip, reason } => (
+                        "htable.sets",
+                        json!([instance.htable, ip.to_string(), reason]),
+                        ip,
+                    ),
+                    HtableUpdate::Delete { ip } => (
+                        "htable.delete",
+                        json!([instance.htable, ip.to_string()]),
+                        ip,
+                    ),
+                };
+                match self.call(&instance.url, method, params, id) {
+                    Ok(()) => debug!("【Kamailio】{} {} -> {}", method, ip, instance.url),
+                    Err(e) => error!(
+                        "【Kamailio】{} {} 同步到 {} 失败: {}",
+                        method, ip, instance.url, e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// 发送一次 JSONRPC 调用
+    fn call(&self, url: &str, method: &str, params: Value, id: u64) -> Result<(), String> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": id,
+        });
+        let response: Value = self
+            .agent
+            .post(url)
+            .send_json(request)
+            .map_err(|e| e.to_string())?
+            .into_json()
+            .map_err(|e| format!("响应解析失败: {}", e))?;
+        if let Some(error) = response.get("error") {
+            // 删除不存在的键不算失败
+            if method == "htable.delete" {
+                warn!("【Kamailio】{} 返回错误（忽略）: {}", url, error);
+                return Ok(());
+            }
+            return Err(error.to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! SIP UA 封禁工具的检测流水线
+//!
+//! 除了作为独立守护进程（`uablock-rust` 可执行文件）运行，也可以把检测流水线嵌入到
+//! 自己的 SBC 管理进程中：
+//!
+//! - [`SipParser`]：从 UDP 负载解析 SIP REGISTER/INVITE 请求，识别畸形报文
+//! - [`Policy`]：白名单、UA 全局限速、检测规则、WASM 插件、灰名单和惩罚分，给出 [`Verdict`]
+//! - [`FirewallBackend`]：封禁/解封的执行方式，内置 `IptablesManager`（`iptables` 特性）、
+//!   [`IpsetManager`]、[`NftManager`] 和 [`FirewalldManager`]，可自行实现
+//! - [`Enforcer`]：统一的处置入口，负责统计、fail2ban 日志和事件发布
+//! - [`Pipeline`]：把以上组件串起来，逐个处理 [`CapturedPacket`]
+//! - [`Engine`]：检测任务，在 tokio 上接收数据包和外部信号并执行定时工作
+//! - [`PacketSource`]：数据包来源，内置 `PacketCapture`（libpcap，`pcap` 特性）、Linux 上的
+//!   `AfPacketSource`，以及不抓包、直接收取 UDP（或 PBX 以 HEP 转发的请求）的 [`UdpSource`]
+//! - [`EventBus`] / [`Event`]：检测、封禁、解封事件，供集成方订阅
+//!
+//! 抓包和各类外部集成（HTTP API、集群同步、PBX 接入等）同样以模块形式公开，
+//! 可按需使用。[`testing`] 模块提供内存中的数据包来源和防火墙，便于在没有 root 权限和网卡的环境中测试。
+
+#[cfg(target_os = "linux")]
+pub mod af_packet;
+#[cfg(feature = "api")]
+pub mod api;
+pub mod asterisk;
+pub mod authz;
+pub mod ban_check;
+pub mod ban_latency;
+pub mod banned_sources;
+pub mod bans;
+pub mod blocked_cache;
+#[cfg(feature = "signed-bundles")]
+pub mod bundle;
+pub mod campaigns;
+#[cfg(feature = "central")]
+pub mod central;
+#[cfg(any(feature = "redis-sync", feature = "gossip"))]
+pub mod cluster;
+pub mod config;
+pub mod conntrack;
+pub mod crowdsec;
+pub mod decision_cache;
+pub mod detection;
+pub mod enforcement;
+pub mod enforcement_queue;
+pub mod engine;
+pub mod events;
+pub mod fail2ban;
+pub mod failover;
+pub mod firewall;
+pub mod firewalld;
+pub mod freeswitch;
+pub mod geoip;
+#[cfg(feature = "gossip")]
+pub mod gossip;
+pub mod greylist;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ha;
+pub mod honeypot;
+pub mod ingest;
+pub mod ipset;
+#[cfg(feature = "iptables")]
+pub mod iptables_manager;
+pub mod kamailio;
+pub mod limits;
+pub mod local_net;
+#[cfg(feature = "lua-hooks")]
+pub mod lua_hooks;
+pub mod method_rate;
+pub mod nft;
+#[cfg(any(feature = "redis-sync", feature = "gossip", feature = "central"))]
+pub mod node;
+pub mod noise;
+pub mod notify;
+pub mod offences;
+pub mod packet_capture;
+pub mod parse_pool;
+pub mod pending;
+pub mod pf;
+pub mod pipeline;
+#[cfg(feature = "wasm-plugins")]
+pub mod plugins;
+pub mod policy;
+pub mod reason;
+pub mod reconcile;
+#[cfg(feature = "redis-sync")]
+pub mod redis_sync;
+pub mod registered;
+pub mod registrations;
+pub mod retransmission;
+pub mod rule_audit;
+pub mod rules;
+#[cfg(feature = "shared-state")]
+pub mod shared_state;
+pub mod sip_parser;
+pub mod snmp;
+pub mod sources;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod ssh;
+pub mod state_file;
+pub mod stats;
+pub mod strikes;
+pub mod tenants;
+pub mod testing;
+pub mod tls_meta;
+pub mod trace;
+pub mod ua_rate;
+pub mod udp_source;
+pub mod verification;
+#[cfg(feature = "api")]
+pub mod webhook;
+pub mod whitelist;
+pub mod whitelist_file;
+#[cfg(any(feature = "gossip", feature = "central"))]
+pub mod wire;
+
+#[cfg(target_os = "linux")]
+pub use af_packet::AfPacketSource;
+pub use detection::Detection;
+pub use enforcement::Enforcer;
+pub use engine::Engine;
+pub use events::{Event, EventBus, EventKind};
+pub use firewall::FirewallBackend;
+pub use firewalld::FirewalldManager;
+pub use ipset::IpsetManager;
+#[cfg(feature = "iptables")]
+pub use iptables_manager::IptablesManager;
+pub use nft::NftManager;
+#[cfg(feature = "pcap")]
+pub use packet_capture::PacketCapture;
+pub use packet_capture::{CapturedPacket, PacketSource};
+pub use pf::PfManager;
+pub use pipeline::{PacketOutcome, Pipeline, PipelineBuilder};
+pub use policy::{Policy, Verdict};
+pub use sip_parser::{SipParser, SipRequest};
+pub use ssh::SshFirewall;
+pub use stats::Stats;
+pub use tenants::Tenants;
+pub use udp_source::UdpSource;
+pub use whitelist::Whitelist;
@@ -0,0 +1,93 @@
+use log::info;
+
+/// 默认的来源状态表上限
+const DEFAULT_MAX_SOURCES: usize = 200_000;
+
+/// 默认的重传跟踪表上限
+const DEFAULT_MAX_TRANSACTIONS: usize = 100_000;
+
+/// 默认的事件订阅队列长度
+const DEFAULT_EVENT_QUEUE: usize = 10_000;
+
+/// 内存中各状态表和队列的上限
+///
+/// 在内存很小的设备上，大规模扫描可能让按来源、按事务的状态表和事件订阅者（通知、SNMP、集群同步等）
+/// 的队列无限增长直到被 OOM 杀掉。达到上限后：来源状态表淘汰最久未活动的来源，重传跟踪表不再记录
+/// 新事务（新请求一律按新事务处理），事件队列丢弃新事件；各表的当前大小和淘汰/丢弃计数见 `/stats`。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// 来源状态表（惩罚分、请求计数）的最大来源数，0 表示不限制
+    pub max_sources: usize,
+    /// 重传跟踪表的最大事务数，0 表示不限制
+    pub max_transactions: usize,
+    /// 每个事件订阅者队列的长度
+    pub event_queue: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_sources: DEFAULT_MAX_SOURCES,
+            max_transactions: DEFAULT_MAX_TRANSACTIONS,
+            event_queue: DEFAULT_EVENT_QUEUE,
+        }
+    }
+}
+
+impl Limits {
+    /// 从环境变量读取，未设置的项使用默认值
+    ///
+    /// - `UABLOCK_MAX_SOURCES`：来源状态表上限（默认 200000，0 表示不限制）
+    /// - `UABLOCK_MAX_TRANSACTIONS`：重传跟踪表上限（默认 100000，0 表示不限制）
+    /// - `UABLOCK_EVENT_QUEUE`：每个事件订阅者的队列长度（默认 10000）
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        let limits = Self {
+            max_sources: read("UABLOCK_MAX_SOURCES", defaults.max_sources, true)?,
+            max_transactions: read("UABLOCK_MAX_TRANSACTIONS", defaults.max_transactions, true)?,
+            event_queue: read("UABLOCK_EVENT_QUEUE", defaults.event_queue, false)?,
+        };
+        if limits != defaults {
+            info!(
+                "资源上限: 来源 {}，事务 {}，事件队列 {}",
+                describe(limits.max_sources),
+                describe(limits.max_transactions),
+                limits.event_queue
+            );
+        }
+        Ok(limits)
+    }
+}
+
+fn read(name: &str, default: usize, allow_zero: bool) -> Result<usize, String> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse::<usize>()
+            .ok()
+            .filter(|&limit| allow_zero || limit > 0)
+            .ok_or_else(|| format!("{} 无效: {}", name, value)),
+        _ => Ok(default),
+    }
+}
+
+fn describe(limit: usize) -> String {
+    if limit == 0 {
+        "不限制".to_string()
+    } else {
+        limit.to_string()
+    }
+}
+
+/// 本进程的常驻内存（字节），读取 `/proc/self/status`，无法读取时返回 None
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
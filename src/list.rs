@@ -0,0 +1,152 @@
+use chrono::{Local, TimeZone};
+use serde::Deserialize;
+use std::time::Duration;
+use uablock_rust::bans::BanRecord;
+use uablock_rust::state_file::unix_now;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时查询的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+/// `/bans` 响应
+#[derive(Debug, Deserialize)]
+struct BansReport {
+    #[serde(default)]
+    details: Vec<BanRecord>,
+}
+
+/// 查询参数
+#[derive(Debug)]
+struct ListOptions {
+    url: String,
+    token: String,
+    timeout: Duration,
+    json: bool,
+}
+
+impl ListOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            timeout: Duration::from_secs(10),
+            json: false,
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--json" {
+                options.json = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            match flag.as_str() {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--timeout" => {
+                    options.timeout = Duration::from_secs(
+                        value
+                            .parse()
+                            .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))?,
+                    )
+                }
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock list`：查询运行中的守护进程，列出当前封禁及其原因
+///
+/// 每行包括 IP、原因分类、原因代码、发起方、规则、触发封禁的 UA、封禁时间、封禁以来的命中次数、
+/// 最近命中时间、剩余时长和来源位置（守护进程启用 GeoIP 时）。`--json` 输出原始记录。
+pub fn run(args: &[String]) -> i32 {
+    let options = match ListOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let mut records = match fetch(&options) {
+        Ok(report) => report.details,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    records.sort_by_key(|record| std::cmp::Reverse(record.banned_at));
+
+    if options.json {
+        match serde_json::to_string_pretty(&records) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+        return 0;
+    }
+
+    let now = unix_now();
+    println!(
+        "{:<39} {:<17} {:<18} {:<12} {:<16} {:<19} {:>6} {:<19} {:>8}  {:<24} UA",
+        "IP", "CODE", "REASON", "ORIGIN", "RULE", "BANNED", "HITS", "LAST HIT", "TTL", "GEO"
+    );
+    for record in &records {
+        println!(
+            "{:<39} {:<17} {:<18} {:<12} {:<16} {:<19} {:>6} {:<19} {:>8}  {:<24} {}",
+            record.ip.to_string(),
+            record.reason.code.as_str(),
+            record.reason.reason,
+            record.reason.origin,
+            record.reason.rule.as_deref().unwrap_or("-"),
+            format_time(record.banned_at),
+            record.hits,
+            format_time(record.last_hit),
+            record
+                .remaining_secs(now)
+                .map_or("-".to_string(), format_duration),
+            record
+                .geo
+                .as_ref()
+                .map_or("-".to_string(), |geo| geo.summary()),
+            record.reason.user_agent.as_deref().unwrap_or("-"),
+        );
+    }
+    println!("共 {} 个封禁", records.len());
+    0
+}
+
+fn fetch(options: &ListOptions) -> Result<BansReport, String> {
+    let url = format!("{}/bans", options.url);
+    ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .build()
+        .get(&url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| format!("无法查询 {}: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))
+}
+
+fn format_time(timestamp: Option<u64>) -> String {
+    timestamp
+        .and_then(|t| Local.timestamp_opt(t as i64, 0).single())
+        .map_or("-".to_string(), |t| {
+            t.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 86400 => format!("{}d{}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h{}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
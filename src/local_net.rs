@@ -0,0 +1,376 @@
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// 邻居表（IPv4 ARP）
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// 一个 IP 子网
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn new(address: IpAddr, prefix: u8) -> Self {
+        let prefix = match address {
+            IpAddr::V4(_) => prefix.min(32),
+            IpAddr::V6(_) => prefix.min(128),
+        };
+        Self {
+            network: mask(address, prefix),
+            prefix,
+        }
+    }
+
+    /// 解析 `192.168.1.0/24`、`fd00::/8` 或单个 IP
+    pub fn parse(text: &str) -> Option<Self> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (text.parse().ok()?, None),
+        };
+        let full = if matches!(address, IpAddr::V4(_)) {
+            32
+        } else {
+            128
+        };
+        Some(Self::new(address, prefix.unwrap_or(full)))
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(*ip, self.prefix) == self.network
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let bits = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(v4) & bits))
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6) & bits))
+        }
+    }
+}
+
+/// 局域网设备识别：来源在抓包接口的本地子网内，或其 MAC 地址在已知列表中
+///
+/// 匹配的来源触发检测时只告警不封禁（见 [`Enforcer::alert`](crate::enforcement::Enforcer::alert)），
+/// 也不会被灰名单临时丢弃，避免 UA 配置错误的自家话机、网关被封禁。
+pub struct LocalNetworks {
+    subnets: Vec<Subnet>,
+    /// 小写、冒号分隔的 MAC 地址
+    macs: HashSet<String>,
+}
+
+impl LocalNetworks {
+    pub fn new(subnets: Vec<Subnet>, macs: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            subnets,
+            macs: macs
+                .into_iter()
+                .map(|mac| mac.trim().to_lowercase().replace('-', ":"))
+                .collect(),
+        }
+    }
+
+    /// 从环境变量创建，未设置 UABLOCK_LAN_ALERT_ONLY=1 时返回 None
+    ///
+    /// - 自动包含 `interface` 上配置的地址所在的子网（多个接口以逗号分隔）
+    /// - UABLOCK_LAN_SUBNETS：额外的本地子网，逗号分隔（如 `10.0.0.0/8,fd00::/8`）
+    /// - UABLOCK_LAN_MACS：已知设备的 MAC 地址，逗号分隔（按 ARP 表匹配，仅 IPv4）
+    pub fn from_env(interface: &str) -> Result<Option<Self>, String> {
+        if std::env::var("UABLOCK_LAN_ALERT_ONLY").as_deref() != Ok("1") {
+            return Ok(None);
+        }
+        let mut subnets = Vec::new();
+        for interface in interface.split(',').filter(|name| !name.is_empty()) {
+            match interface_subnets(interface) {
+                Ok(found) => subnets.extend(found),
+                Err(e) => warn!("无法读取接口 {} 的地址: {}", interface, e),
+            }
+        }
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        for text in list("UABLOCK_LAN_SUBNETS") {
+            subnets.push(
+                Subnet::parse(&text)
+                    .ok_or_else(|| format!("UABLOCK_LAN_SUBNETS 中的子网无效: {}", text))?,
+            );
+        }
+        let networks = Self::new(subnets, list("UABLOCK_LAN_MACS"));
+        info!(
+            "局域网设备只告警不封禁: 子网 [{}]，已知 MAC {} 个",
+            networks
+                .subnets
+                .iter()
+                .map(Subnet::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            networks.macs.len()
+        );
+        Ok(Some(networks))
+    }
+
+    /// 来源是否为局域网设备，返回匹配的依据（用于日志）
+    pub fn matches(&self, ip: &IpAddr) -> Option<String> {
+        if let Some(subnet) = self.subnets.iter().find(|subnet| subnet.contains(ip)) {
+            return Some(format!("子网 {}", subnet));
+        }
+        if self.macs.is_empty() {
+            return None;
+        }
+        let mac = neighbor_mac(ip)?;
+        self.macs.contains(&mac).then(|| format!("MAC {}", mac))
+    }
+}
+
+/// 可信来源：SIP 中继、办公网段等永远不会被封禁的地址
+///
+/// 与 [`LocalNetworks`] 不同，可信来源的数据包在 UA 匹配之前就被放行，不参与任何判定；
+/// 任何来源的封禁请求（检测、管理接口、集群同步、主备切换）都不会为可信来源下发防火墙规则。
+pub struct TrustedSources {
+    subnets: Vec<Subnet>,
+}
+
+impl TrustedSources {
+    pub fn new(subnets: Vec<Subnet>) -> Self {
+        Self { subnets }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_TRUSTED_SOURCES` 时返回 None
+    ///
+    /// `UABLOCK_TRUSTED_SOURCES`：可信的 IP 或子网，逗号分隔（如 `203.0.113.10,198.51.100.0/24`）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let value = match std::env::var("UABLOCK_TRUSTED_SOURCES") {
+            Ok(value) if !value.trim().is_empty() => value,
+            _ => return Ok(None),
+        };
+        let subnets = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|text| {
+                Subnet::parse(text)
+                    .ok_or_else(|| format!("UABLOCK_TRUSTED_SOURCES 中的地址无效: {}", text))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        info!(
+            "可信来源不参与判定，也不会被封禁: [{}]",
+            subnets
+                .iter()
+                .map(Subnet::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(Some(Self::new(subnets)))
+    }
+
+    /// 来源所在的可信子网
+    pub fn matches(&self, ip: &IpAddr) -> Option<Subnet> {
+        self.subnets
+            .iter()
+            .find(|subnet| subnet.contains(ip))
+            .copied()
+    }
+}
+
+/// 本机地址：所有接口上配置的地址（含回环地址）
+///
+/// 抓包接口也能看到本机发出的 SIP 流量（例如 PBX 向外注册），配置不当时会把本机当成扫描器判定
+/// 甚至封禁。本机地址发出的数据包不参与判定，任何来源的封禁请求也不会封禁本机地址。
+/// 接口地址可能在运行中变化（DHCP、新增接口），由后台线程定期重新读取。
+pub struct HostAddresses {
+    addresses: RwLock<HashSet<IpAddr>>,
+}
+
+impl HostAddresses {
+    pub fn new(addresses: impl IntoIterator<Item = IpAddr>) -> Self {
+        Self {
+            addresses: RwLock::new(addresses.into_iter().collect()),
+        }
+    }
+
+    /// 读取本机所有接口的地址
+    pub fn detect() -> Result<Self, String> {
+        let addresses = host_addresses()?;
+        info!(
+            "本机地址不参与判定和封禁: {}",
+            addresses
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(Self::new(addresses))
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.addresses.read().unwrap().contains(ip)
+    }
+
+    /// 重新读取接口地址，返回地址是否发生变化
+    pub fn refresh(&self) -> Result<bool, String> {
+        let current: HashSet<IpAddr> = host_addresses()?.into_iter().collect();
+        let mut addresses = self.addresses.write().unwrap();
+        if *addresses == current {
+            return Ok(false);
+        }
+        let added: Vec<String> = current
+            .difference(&addresses)
+            .map(IpAddr::to_string)
+            .collect();
+        let removed: Vec<String> = addresses
+            .difference(&current)
+            .map(IpAddr::to_string)
+            .collect();
+        info!(
+            "本机地址已变化: 新增 [{}]，移除 [{}]",
+            added.join(", "),
+            removed.join(", ")
+        );
+        *addresses = current;
+        Ok(true)
+    }
+
+    /// 启动定期重新读取接口地址的线程
+    pub fn start_refresh(self: &Arc<Self>, interval: Duration) -> Result<(), String> {
+        let addresses = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("host-addresses".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(addresses) = addresses.upgrade() else {
+                    break;
+                };
+                if let Err(e) = addresses.refresh() {
+                    warn!("无法读取本机地址: {}", e);
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动本机地址刷新线程失败: {}", e))
+    }
+}
+
+/// 从 ARP 表查找 IPv4 邻居的 MAC 地址
+fn neighbor_mac(ip: &IpAddr) -> Option<String> {
+    let IpAddr::V4(_) = ip else {
+        return None;
+    };
+    let table = std::fs::read_to_string(ARP_TABLE).ok()?;
+    parse_arp_table(&table, ip)
+}
+
+/// `/proc/net/arp`：`IP address  HW type  Flags  HW address  Mask  Device`
+fn parse_arp_table(table: &str, ip: &IpAddr) -> Option<String> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let address: IpAddr = fields.first()?.parse().ok()?;
+        (address == *ip && fields.get(2) != Some(&"0x0"))
+            .then(|| fields.get(3).map(|mac| mac.to_lowercase()))
+            .flatten()
+    })
+}
+
+/// 接口上配置的地址所在的子网
+fn interface_subnets(interface: &str) -> Result<Vec<Subnet>, String> {
+    Ok(interface_addresses()?
+        .into_iter()
+        .filter(|(name, _, _)| name == interface)
+        .filter_map(|(_, address, netmask)| {
+            let prefix = match netmask? {
+                IpAddr::V4(v4) => u32::from(v4).count_ones(),
+                IpAddr::V6(v6) => u128::from(v6).count_ones(),
+            };
+            Some(Subnet::new(address, prefix as u8))
+        })
+        .collect())
+}
+
+/// 本机所有接口上配置的地址（去重）
+fn host_addresses() -> Result<Vec<IpAddr>, String> {
+    let mut addresses: Vec<IpAddr> = interface_addresses()?
+        .into_iter()
+        .map(|(_, address, _)| address)
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+/// 各接口上配置的地址：(接口名称, 地址, 子网掩码)
+#[cfg(unix)]
+fn interface_addresses() -> Result<Vec<(String, IpAddr, Option<IpAddr>)>, String> {
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    let mut addresses = Vec::new();
+    let mut cursor = addrs;
+    while !cursor.is_null() {
+        let ifa = unsafe { &*cursor };
+        cursor = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let Some(address) = sockaddr_ip(ifa.ifa_addr) else {
+            continue;
+        };
+        let netmask = (!ifa.ifa_netmask.is_null())
+            .then(|| sockaddr_ip(ifa.ifa_netmask))
+            .flatten();
+        let name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+        addresses.push((name.to_string_lossy().into_owned(), address, netmask));
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(addresses)
+}
+
+#[cfg(not(unix))]
+fn interface_addresses() -> Result<Vec<(String, IpAddr, Option<IpAddr>)>, String> {
+    Err("当前平台不支持读取接口地址".to_string())
+}
+
+#[cfg(unix)]
+fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    // getifaddrs 返回的地址按 sa_family 对应的结构体分配
+    unsafe {
+        match i32::from((*addr).sa_family) {
+            libc::AF_INET => {
+                let sin = &*(addr as *const libc::sockaddr_in);
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.s_addr,
+                ))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(addr as *const libc::sockaddr_in6);
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+}
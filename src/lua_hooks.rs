@@ -0,0 +1,201 @@
+//! Lua 脚本钩子
+//!
+//! 脚本可以定义以下全局函数（都可省略）：
+//!
+//! - `pre_decision(req, action)`：策略给出判定后调用。`req` 包含 `source_ip`、`method`、
+//!   `user_agent`、`call_id`、`cseq`、`branch`、`score`（当前惩罚分），判定为封禁时还有 `reason`；
+//!   `action` 为 `"allow"`、`"ban"`、`"hold"` 或 `"probation"`。返回 `"allow"` 放行、
+//!   `"ban"`（可附带第二个返回值作为说明）封禁，返回 nil 保持原判定
+//! - `post_ban(ban)`：检测任务封禁来源后调用，`ban` 包含 `source_ip`、`method`、`user_agent`、
+//!   `reason`、`rule`。返回 `"unban"` 立即撤销本次封禁
+//!
+//! 脚本的全局变量在调用之间保留，可用来维护自己的状态。脚本中可以调用 `uablock.info(msg)`、
+//! `uablock.warn(msg)` 输出日志。
+
+use crate::detection::Detection;
+use crate::policy::Verdict;
+use crate::sip_parser::SipRequest;
+use log::{info, warn};
+use mlua::{Function, HookTriggers, Lua, Table};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// 每执行多少条 VM 指令检查一次预算
+const HOOK_INSTRUCTION_STEP: u32 = 1000;
+/// 单次调用的指令预算（以 HOOK_INSTRUCTION_STEP 为单位）
+const MAX_INSTRUCTION_STEPS: u32 = 1000;
+/// 脚本可使用的内存上限
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// 已加载的 Lua 脚本
+pub struct LuaHooks {
+    lua: Lua,
+    /// 本次调用已执行的指令数（单位 HOOK_INSTRUCTION_STEP）
+    steps: Arc<AtomicU32>,
+}
+
+impl LuaHooks {
+    /// 从 UABLOCK_LUA_SCRIPT 加载脚本，未设置时返回 None
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_LUA_SCRIPT") {
+            Ok(path) if !path.is_empty() => Self::load(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// 加载脚本文件
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("无法读取 Lua 脚本 {}: {}", path.display(), e))?;
+        Self::from_source(&source, &path.display().to_string())
+    }
+
+    /// 从源码加载脚本，`name` 用于错误信息
+    pub fn from_source(source: &str, name: &str) -> Result<Self, String> {
+        let lua = Lua::new();
+        lua.set_memory_limit(MEMORY_LIMIT)
+            .map_err(|e| format!("设置 Lua 内存上限失败: {}", e))?;
+        Self::register_api(&lua).map_err(|e| format!("初始化 Lua 环境失败: {}", e))?;
+
+        // 死循环的脚本不能卡住检测任务
+        let steps = Arc::new(AtomicU32::new(0));
+        let counter = steps.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTION_STEP),
+            move |_, _| {
+                if counter.fetch_add(1, Ordering::Relaxed) >= MAX_INSTRUCTION_STEPS {
+                    return Err(mlua::Error::RuntimeError("超出指令预算".to_string()));
+                }
+                Ok(())
+            },
+        );
+
+        lua.load(source)
+            .set_name(name)
+            .exec()
+            .map_err(|e| format!("加载 Lua 脚本 {} 失败: {}", name, e))?;
+        let hooks = Self { lua, steps };
+        info!(
+            "【脚本】已加载 {}（pre_decision: {}, post_ban: {}）",
+            name,
+            hooks.function("pre_decision").is_some(),
+            hooks.function("post_ban").is_some()
+        );
+        Ok(hooks)
+    }
+
+    fn register_api(lua: &Lua) -> mlua::Result<()> {
+        let api = lua.create_table()?;
+        api.set(
+            "info",
+            lua.create_function(|_, msg: String| {
+                info!("【脚本】{}", msg);
+                Ok(())
+            })?,
+        )?;
+        api.set(
+            "warn",
+            lua.create_function(|_, msg: String| {
+                warn!("【脚本】{}", msg);
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("uablock", api)
+    }
+
+    fn function(&self, name: &str) -> Option<Function<'_>> {
+        self.lua.globals().get::<_, Function>(name).ok()
+    }
+
+    /// 调用 `pre_decision`，返回覆盖后的判定；脚本未定义该函数、返回 nil 或出错时返回 None
+    pub fn pre_decision(
+        &self,
+        request: &SipRequest,
+        score: f64,
+        verdict: &Verdict,
+    ) -> Option<Verdict> {
+        let hook = self.function("pre_decision")?;
+        let action = match verdict {
+            Verdict::Allow => "allow",
+            Verdict::Hold => "hold",
+            Verdict::Probation => "probation",
+            Verdict::Unverified => "unverified",
+            Verdict::Detect(_) => "ban",
+        };
+        let result = self.request_table(request, score, verdict).and_then(|req| {
+            self.steps.store(0, Ordering::Relaxed);
+            hook.call::<_, (Option<String>, Option<String>)>((req, action))
+        });
+        match result {
+            Ok((None, _)) => None,
+            Ok((Some(decision), note)) => match decision.as_str() {
+                // 与原判定相同时保持原判定（保留原因代码）
+                "allow" if matches!(verdict, Verdict::Allow) => None,
+                "ban" if matches!(verdict, Verdict::Detect(_)) => None,
+                "allow" => Some(Verdict::Allow),
+                "ban" => {
+                    let mut detection = Detection::from_request(request, "SCRIPT");
+                    detection.rule = note;
+                    Some(Verdict::Detect(detection))
+                }
+                other => {
+                    warn!("【脚本】pre_decision 返回了未知的判定: {}", other);
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("【脚本】pre_decision 执行失败: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 调用 `post_ban`，返回 true 表示脚本要求撤销本次封禁
+    pub fn post_ban(&self, detection: &Detection) -> bool {
+        let Some(hook) = self.function("post_ban") else {
+            return false;
+        };
+        let result = self.ban_table(detection).and_then(|ban| {
+            self.steps.store(0, Ordering::Relaxed);
+            hook.call::<_, Option<String>>(ban)
+        });
+        match result {
+            Ok(decision) => decision.as_deref() == Some("unban"),
+            Err(e) => {
+                warn!("【脚本】post_ban 执行失败: {}", e);
+                false
+            }
+        }
+    }
+
+    fn request_table(
+        &self,
+        request: &SipRequest,
+        score: f64,
+        verdict: &Verdict,
+    ) -> mlua::Result<Table<'_>> {
+        let table = self.lua.create_table()?;
+        table.set("source_ip", request.source_ip.to_string())?;
+        table.set("method", request.method.as_str())?;
+        table.set("user_agent", request.user_agent.as_str())?;
+        table.set("call_id", request.call_id.as_deref())?;
+        table.set("cseq", request.cseq.as_deref())?;
+        table.set("branch", request.branch.as_deref())?;
+        table.set("score", score)?;
+        if let Verdict::Detect(detection) = verdict {
+            table.set("reason", detection.reason.as_str())?;
+        }
+        Ok(table)
+    }
+
+    fn ban_table(&self, detection: &Detection) -> mlua::Result<Table<'_>> {
+        let table = self.lua.create_table()?;
+        table.set("source_ip", detection.source_ip.to_string())?;
+        table.set("method", detection.method.as_str())?;
+        table.set("user_agent", detection.user_agent.as_str())?;
+        table.set("reason", detection.reason.as_str())?;
+        table.set("rule", detection.rule.as_deref())?;
+        Ok(table)
+    }
+}
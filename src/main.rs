@@ -1,201 +1,1567 @@
-mod iptables_manager;
-mod packet_capture;
-mod sip_parser;
-mod whitelist;
-
-use iptables_manager::IptablesManager;
-use log::{debug, error, info, warn};
-use packet_capture::PacketCapture;
-use sip_parser::SipParser;
+mod check;
+mod doctor;
+mod import;
+mod list;
+mod promote;
+mod replay;
+mod review;
+mod selftest;
+#[cfg(feature = "signed-bundles")]
+mod sign;
+mod signals;
+mod unban;
+
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use whitelist::Whitelist;
+use std::time::Duration;
+use tokio::sync::mpsc;
+#[cfg(feature = "api")]
+use uablock_rust::api;
+use uablock_rust::asterisk::{AsteriskAmi, AsteriskSecurityLog};
+use uablock_rust::authz::Authz;
+use uablock_rust::ban_check::BanCheck;
+use uablock_rust::ban_latency::LatencyAlert;
+use uablock_rust::bans::parse_duration;
+use uablock_rust::blocked_cache::BlockedCache;
+#[cfg(feature = "signed-bundles")]
+use uablock_rust::bundle::BundleVerifier;
+use uablock_rust::campaigns::Campaigns;
+#[cfg(feature = "central")]
+use uablock_rust::central;
+#[cfg(any(feature = "redis-sync", feature = "gossip"))]
+use uablock_rust::cluster;
+use uablock_rust::config::Config;
+use uablock_rust::conntrack::ConntrackFlush;
+use uablock_rust::crowdsec::CrowdSec;
+use uablock_rust::fail2ban::Fail2banLogger;
+use uablock_rust::failover::{FailureAction, GuardedFirewall};
+use uablock_rust::firewalld::FirewalldManager;
+use uablock_rust::freeswitch::FreeswitchEsl;
+use uablock_rust::geoip::GeoIp;
+#[cfg(feature = "gossip")]
+use uablock_rust::gossip;
+use uablock_rust::greylist::Greylist;
+#[cfg(feature = "grpc")]
+use uablock_rust::grpc;
+use uablock_rust::ha::HaRole;
+use uablock_rust::honeypot::Honeypot;
+use uablock_rust::ipset::IpsetManager;
+use uablock_rust::kamailio::KamailioMirror;
+use uablock_rust::limits::Limits;
+use uablock_rust::local_net::{HostAddresses, LocalNetworks, TrustedSources};
+#[cfg(feature = "lua-hooks")]
+use uablock_rust::lua_hooks::LuaHooks;
+use uablock_rust::method_rate::MethodRateLimiter;
+use uablock_rust::nft::NftManager;
+use uablock_rust::notify::Notifier;
+use uablock_rust::offences::Escalation;
+use uablock_rust::packet_capture::{self, CapturePorts};
+use uablock_rust::parse_pool::ParsePool;
+use uablock_rust::pending::PendingBans;
+use uablock_rust::pf::PfManager;
+#[cfg(feature = "wasm-plugins")]
+use uablock_rust::plugins::PluginHost;
+#[cfg(feature = "redis-sync")]
+use uablock_rust::redis_sync;
+use uablock_rust::registered::RegisteredEndpoints;
+use uablock_rust::rule_audit::RuleAudit;
+use uablock_rust::rules::RulesEngine;
+#[cfg(feature = "shared-state")]
+use uablock_rust::shared_state;
+use uablock_rust::snmp;
+use uablock_rust::sources::SourceTable;
+#[cfg(feature = "sqlite")]
+use uablock_rust::sqlite_store::SqliteBanStore;
+use uablock_rust::ssh::SshFirewall;
+use uablock_rust::state_file::StateFile;
+use uablock_rust::strikes::StrikeTracker;
+use uablock_rust::tenants::Tenants;
+#[cfg(feature = "pcap")]
+use uablock_rust::tls_meta::TlsCapture;
+use uablock_rust::trace::TraceRecorder;
+use uablock_rust::ua_rate::UaRateLimiter;
+use uablock_rust::verification::Verifier;
+#[cfg(feature = "api")]
+use uablock_rust::webhook;
+use uablock_rust::whitelist::{ListMode, MatchMode};
+use uablock_rust::whitelist_file::{read_patterns, WhitelistFile};
+#[cfg(target_os = "linux")]
+use uablock_rust::AfPacketSource;
+#[cfg(feature = "iptables")]
+use uablock_rust::IptablesManager;
+#[cfg(feature = "pcap")]
+use uablock_rust::PacketCapture;
+use uablock_rust::{
+    Enforcer, Engine, EventBus, FirewallBackend, PacketSource, Pipeline, Policy, SipParser, Stats,
+    UdpSource, Whitelist,
+};
+
+/// 抓包线程与检测任务之间的数据包队列长度
+const PACKET_QUEUE_CAPACITY: usize = 4096;
+
+/// 默认的处置队列容量
+const DEFAULT_ENFORCEMENT_QUEUE: usize = 1024;
+
+/// 默认的判定缓存有效期（秒）
+const DEFAULT_DECISION_CACHE_TTL_SECS: u64 = 10;
+
+/// 默认的已封禁来源日志间隔（秒）
+const DEFAULT_BANNED_LOG_INTERVAL_SECS: u64 = 60;
+
+/// 检查限时封禁是否到期的间隔
+const BAN_EXPIRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 重新读取本机接口地址的间隔
+const HOST_ADDRESS_REFRESH: Duration = Duration::from_secs(60);
+
+/// 默认的升级交接快照路径（/run 在重启后清空，不会恢复过期的快照）
+const DEFAULT_SNAPSHOT_FILE: &str = "/run/uablock-snapshot.json";
 
 fn main() {
-    // 初始化日志（默认使用 Debug 级别以便调试）
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Debug)
-        .init();
+    let mut args: Vec<String> = std::env::args().collect();
+    // `--config <配置文件>`：接口、端口、白名单、链名、日志级别和其他选项；守护进程和各子命令都读取
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(PathBuf::from(path))
+        }
+        Some(_) => {
+            eprintln!("--config 需要配置文件路径");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let config = match config_path.as_deref().map(load_config).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            // `check` 插件的退出码 3 表示 UNKNOWN
+            let code = if args.get(1).map(String::as_str) == Some("check") {
+                3
+            } else {
+                1
+            };
+            std::process::exit(code);
+        }
+    };
+    // 配置文件中的选项作为环境变量的默认值；此时还没有启动任何线程（子命令和 tokio 运行时都在下面），
+    // 修改环境变量不会与其他线程的读取并发
+    let env_defaults = config.env_defaults();
+    for (name, value) in &env_defaults {
+        std::env::set_var(name, value);
+    }
+
+    // `check` 子命令：作为 Nagios/Icinga 插件查询运行中的守护进程
+    if args.get(1).map(String::as_str) == Some("check") {
+        std::process::exit(check::run(&args[2..]));
+    }
+    // `doctor` 子命令：核对状态文件、防火墙规则和当前配置，可选自动修复
+    if args.get(1).map(String::as_str) == Some("doctor") {
+        std::process::exit(doctor::run(&args[2..], &config));
+    }
+    // `list` 子命令：列出运行中守护进程的封禁及其原因
+    if args.get(1).map(String::as_str) == Some("list") {
+        std::process::exit(list::run(&args[2..]));
+    }
+    // `import-registrations` 子命令：由 PBX 注册表生成白名单条目
+    if args.get(1).map(String::as_str) == Some("import-registrations") {
+        std::process::exit(import::run(&args[2..], &config));
+    }
+    // `promote` 子命令：备机升为主机
+    if args.get(1).map(String::as_str) == Some("promote") {
+        std::process::exit(promote::run(&args[2..]));
+    }
+    // `replay-trace` 子命令：用当前配置重新执行判定轨迹并对比结果
+    if args.get(1).map(String::as_str) == Some("replay-trace") {
+        std::process::exit(replay::run(&args[2..], &config));
+    }
+    // `review` 子命令：查看并审核待审核封禁
+    if args.get(1).map(String::as_str) == Some("review") {
+        std::process::exit(review::run(&args[2..]));
+    }
+    // `selftest` 子命令：发送伪造 UA 的 REGISTER，确认守护进程的判定和防火墙规则
+    if args.get(1).map(String::as_str) == Some("selftest") {
+        std::process::exit(selftest::run(&args[2..]));
+    }
+    // `sign-bundle` 子命令：生成密钥、为规则文件和配置文件签名、校验签名
+    if args.get(1).map(String::as_str) == Some("sign-bundle") {
+        #[cfg(feature = "signed-bundles")]
+        std::process::exit(sign::run(&args[2..]));
+        #[cfg(not(feature = "signed-bundles"))]
+        {
+            eprintln!("sign-bundle 子命令需要在编译时启用 signed-bundles 特性");
+            std::process::exit(2);
+        }
+    }
+    // `unban` 子命令：批量解封（`--all` 或 `--older-than 7d`）
+    if args.get(1).map(String::as_str) == Some("unban") {
+        std::process::exit(unban::run(&args[2..]));
+    }
+    // `--restore <快照>`：从升级交接快照恢复检测状态
+    let restore = match args.iter().position(|arg| arg == "--restore") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(PathBuf::from(path))
+        }
+        Some(_) => {
+            eprintln!("--restore 需要快照文件路径");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    // `--capture-filter <BPF 表达式>`：只抓取同时满足该表达式的数据包（优先于 UABLOCK_CAPTURE_FILTER）
+    let capture_filter = match args.iter().position(|arg| arg == "--capture-filter") {
+        Some(i) if i + 1 < args.len() => {
+            let filter = args.remove(i + 1);
+            args.remove(i);
+            Some(filter)
+        }
+        Some(_) => {
+            eprintln!("--capture-filter 需要 BPF 过滤表达式");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    // `--interface <接口>`（可重复）：在多个接口上同时抓包，与第一个参数中逗号分隔的接口列表等价
+    let mut interface_flags = Vec::new();
+    while let Some(i) = args.iter().position(|arg| arg == "--interface") {
+        if i + 1 >= args.len() {
+            eprintln!("--interface 需要网络接口名");
+            std::process::exit(1);
+        }
+        interface_flags.push(args.remove(i + 1));
+        args.remove(i);
+    }
+    if !interface_flags.is_empty() {
+        args.insert(1, interface_flags.join(","));
+    }
+
+    // 初始化日志：RUST_LOG 优先，其次为配置文件中的级别（默认使用 Debug 级别以便调试）
+    let mut logger = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none() {
+        logger.filter_level(config.level().unwrap_or(log::LevelFilter::Debug));
+    }
+    logger.init();
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("创建 tokio 运行时失败: {}", e);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(run(
+        args,
+        config,
+        config_path,
+        restore,
+        capture_filter,
+        env_defaults.len(),
+    ));
+}
 
+/// 守护进程主体：在 [`main`] 处理完命令行参数、配置文件和日志之后运行
+async fn run(
+    args: Vec<String>,
+    config: Config,
+    config_path: Option<PathBuf>,
+    restore: Option<PathBuf>,
+    capture_filter: Option<String>,
+    env_defaults: usize,
+) {
     info!("SIP UA 封禁工具启动");
+    if let Some(path) = &config_path {
+        info!(
+            "使用配置文件: {}（{} 个选项作为环境变量生效）",
+            path.display(),
+            env_defaults
+        );
+    }
+
+    // 数据包来源和封禁后端（可在编译时通过 pcap / iptables 特性裁剪）
+    let capture_mode =
+        std::env::var("UABLOCK_CAPTURE").unwrap_or_else(|_| default_capture().to_string());
+    let backend_mode =
+        std::env::var("UABLOCK_BACKEND").unwrap_or_else(|_| default_backend().to_string());
 
-    // 检查是否有 root 权限（iptables 需要 root 权限）
-    if !is_root() {
-        error!("此程序需要 root 权限才能使用 iptables");
+    // 检查是否有 root 权限（抓包和修改防火墙规则需要 root 权限，UDP 接收不需要）
+    if (!matches!(capture_mode.as_str(), "none" | "udp") || backend_mode != "none") && !is_root() {
+        error!("此程序需要 root 权限才能抓包和修改防火墙规则");
         eprintln!("请使用 sudo 运行此程序");
         std::process::exit(1);
     }
 
-    // 配置参数
-    let args: Vec<String> = std::env::args().collect();
-    let interface = args
-        .get(1)
-        .map(|s| s.clone())
-        .unwrap_or_else(|| "eth0".to_string());
+    // 配置参数：命令行参数优先于配置文件；可以是逗号分隔的多个接口，每个接口一个抓包线程
+    let interfaces =
+        match packet_capture::parse_interfaces(args.get(1).unwrap_or(&config.interface)) {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+    // 封禁效果验证、已注册终端、TLS 元数据等只在一个接口上工作的功能使用第一个接口
+    let interface = interfaces[0].clone();
 
     // 第二个参数是端口，默认 5060
-    let block_port: u16 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(5060);
+    let block_port: u16 = args
+        .get(2)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(config.port);
 
-    info!("使用网络接口: {}", interface);
+    info!("使用网络接口: {}", interfaces.join(", "));
     info!("封禁端口: {}", block_port);
 
+    // 受保护端口：抓包只接收发往这些端口的数据包（默认为封禁端口，UABLOCK_PORTS_FILE 可指定多个）
+    let capture_ports = match CapturePorts::from_env(block_port) {
+        Ok(ports) => Arc::new(ports),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // libpcap 和 AF_PACKET 抓包可以在运行中更换端口，UDP 套接字不行
+    let live_ports =
+        matches!(capture_mode.as_str(), "pcap" | "af_packet").then(|| capture_ports.clone());
+
     // 初始化组件
-    let mut capture = match PacketCapture::open(&interface, block_port) {
-        Ok(cap) => cap,
+    // 附加的抓包过滤表达式（可选），与受保护端口的过滤同时满足
+    let capture_filter = capture_filter
+        .or_else(|| std::env::var("UABLOCK_CAPTURE_FILTER").ok())
+        .filter(|filter| !filter.trim().is_empty());
+    if let Some(filter) = &capture_filter {
+        info!("附加抓包过滤: {}", filter);
+    }
+    let sources = match open_packet_sources(
+        &capture_mode,
+        &interfaces,
+        &capture_ports.ports(),
+        capture_filter.as_deref(),
+    ) {
+        Ok(sources) => sources,
         Err(e) => {
-            error!("无法打开网络接口: {}", e);
-            eprintln!("可用接口: {:?}", PacketCapture::list_interfaces());
+            error!("{}", e);
             std::process::exit(1);
         }
     };
 
-    let parser = SipParser::new();
-    let iptables = IptablesManager::new_with_port(None, Some(block_port));
+    // 运行时统计
+    let stats = Arc::new(Stats::default());
+
+    let iptables = match open_firewall(&backend_mode, block_port, &stats) {
+        Ok(firewall) => firewall,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // fail2ban 兼容的检测日志（可选）
+    let fail2ban = match std::env::var("UABLOCK_FAIL2BAN_LOG") {
+        Ok(path) if !path.is_empty() => match Fail2banLogger::open(Path::new(&path)) {
+            Ok(logger) => Some(logger),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
 
     // 初始化白名单（可以从配置文件或环境变量读取）
-    let whitelist = Arc::new(Mutex::new(initialize_whitelist()));
+    let whitelist = match initialize_whitelist(&config) {
+        Ok(whitelist) => Arc::new(Mutex::new(whitelist)),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // 用于跟踪 IP 的最后处理时间，定期清理
-    let last_processed: Arc<Mutex<std::collections::HashMap<String, Instant>>> =
-        Arc::new(Mutex::new(std::collections::HashMap::new()));
+    // 白名单文件（可选）：文件修改后或收到 SIGHUP 时重新读取
+    if let Some(path) = WhitelistFile::path_from_env() {
+        let file = Arc::new(WhitelistFile::new(&path, whitelist.clone()));
+        match WhitelistFile::reload_interval_from_env() {
+            Ok(Some(interval)) => {
+                if let Err(e) = file.start_watch(interval) {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        signals::on_reload(move || match file.reload() {
+            Ok(true) => info!("【SIGHUP】白名单已更新"),
+            Ok(false) => info!("【SIGHUP】白名单未变化"),
+            Err(e) => error!("【SIGHUP】{}，继续使用原来的名单", e),
+        });
+    }
 
-    info!("开始监控 SIP 流量...");
+    // 封禁后端连续失败时的降级策略，封禁后清除连接跟踪条目（可选），以及封禁 IP 的内存缓存
+    let iptables = match iptables
+        .map(|primary| guard_firewall(primary, block_port, stats.clone()))
+        .transpose()
+        .map(|firewall| firewall.map(ConntrackFlush::wrap_from_env))
+        .and_then(|firewall| firewall.map(BlockedCache::wrap_from_env).transpose())
+    {
+        Ok(firewall) => firewall,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // 主循环
-    loop {
-        match capture.next_packet() {
-            Ok(Some((source_ip, data))) => {
-                // 尝试解析为 SIP 请求
-                // 如果不是 SIP 请求，parse_udp_packet 会返回 None，不输出任何日志
-                if let Some(sip_request) = parser.parse_udp_packet(&data, source_ip) {
-                    // 只有解析到 SIP REGISTER 或 INVITE 请求才会到这里
-
-                    let ip_str = sip_request.source_ip.to_string();
-                    let whitelist_guard = whitelist.lock().unwrap();
-                    let is_allowed = whitelist_guard.is_allowed(&sip_request.user_agent);
-                    drop(whitelist_guard);
-
-                    if is_allowed {
-                        // UA 在白名单中，检查是否需要解封
-                        if iptables.is_blocked(&sip_request.source_ip) {
-                            info!(
-                                "【解封】User-Agent: '{}', IP: {}, 原因: UA 在白名单中",
-                                sip_request.user_agent, sip_request.source_ip
-                            );
-                            match iptables.unblock_ip(&sip_request.source_ip) {
-                                Ok(_) => {
-                                    info!(
-                                        "【解封成功】User-Agent: '{}', IP: {}",
-                                        sip_request.user_agent, sip_request.source_ip
-                                    );
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "【解封失败】User-Agent: '{}', IP: {}, 错误: {}",
-                                        sip_request.user_agent, sip_request.source_ip, e
-                                    );
-                                }
-                            }
-                        } else {
-                            debug!(
-                                "User-Agent '{}' 在白名单中，IP {} 未被封禁，无需操作",
-                                sip_request.user_agent, sip_request.source_ip
-                            );
-                        }
-                    } else {
-                        // UA 不在白名单中，检查是否需要封禁
-                        let is_blocked = iptables.is_blocked(&sip_request.source_ip);
-                        if !is_blocked {
-                            warn!(
-                                "【封禁】User-Agent: '{}', IP: {}, 原因: UA 不在白名单中",
-                                sip_request.user_agent, sip_request.source_ip
-                            );
-                            match iptables.block_ip(&sip_request.source_ip) {
-                                Ok(_) => {
-                                    info!(
-                                        "【封禁成功】User-Agent: '{}', IP: {}",
-                                        sip_request.user_agent, sip_request.source_ip
-                                    );
-                                    // 再次检查确认封禁是否生效
-                                    if iptables.is_blocked(&sip_request.source_ip) {
-                                        info!(
-                                            "【确认封禁】User-Agent: '{}', IP: {} 已被成功封禁",
-                                            sip_request.user_agent, sip_request.source_ip
-                                        );
-                                    } else {
-                                        warn!(
-                                            "【警告】User-Agent: '{}', IP: {} 封禁后检查状态为未封禁，可能规则未正确添加",
-                                            sip_request.user_agent, sip_request.source_ip
-                                        );
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "【封禁失败】User-Agent: '{}', IP: {}, 错误: {}",
-                                        sip_request.user_agent, sip_request.source_ip, e
-                                    );
-                                }
-                            }
-                        } else {
-                            debug!(
-                                "User-Agent '{}' 不在白名单中，IP {} 已被封禁，无需重复封禁",
-                                sip_request.user_agent, sip_request.source_ip
-                            );
-                        }
-                    }
-
-                    // 记录处理时间
-                    let mut last_processed_guard = last_processed.lock().unwrap();
-                    last_processed_guard.insert(ip_str, Instant::now());
-                }
-                // 如果不是 SIP 请求，静默忽略（不输出日志）
-            }
-            Ok(None) => {
-                // 超时或无效数据包，继续
-                // 每 1000 次超时输出一次日志，避免日志过多
-                use std::sync::atomic::{AtomicU64, Ordering};
-                static TIMEOUT_COUNT: AtomicU64 = AtomicU64::new(0);
-                let count = TIMEOUT_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
-                if count % 1000 == 0 {
-                    debug!("等待数据包中... (已等待 {} 次)", count);
+    // 资源上限：来源状态表、重传跟踪表和事件订阅者队列
+    let limits = match Limits::from_env() {
+        Ok(limits) => limits,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // 处置执行器：检测任务、HTTP API 和 gRPC 共用
+    // GeoIP：事件和封禁记录补充来源的国家、城市和 ASN（可选）
+    let mut events = EventBus::new().with_queue_capacity(limits.event_queue);
+    match GeoIp::from_env() {
+        Ok(Some((geoip, reload))) => {
+            let geoip = Arc::new(geoip);
+            if let Some(interval) = reload {
+                if let Err(e) = geoip.start_reload(interval.max(Duration::from_secs(60))) {
+                    error!("{}", e);
+                    std::process::exit(1);
                 }
             }
+            events = events.with_geoip(geoip);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let events = Arc::new(events);
+    #[allow(unused_mut)]
+    let mut enforcer = Enforcer::new(iptables, fail2ban, stats.clone(), events);
+
+    // 中央服务器/代理模式（可选）：代理只上报检测结果，由中央服务器决定封禁
+    #[cfg(feature = "central")]
+    let (central_agent, central_server) = match (
+        central::CentralAgent::from_env(),
+        central::CentralServer::from_env(),
+    ) {
+        (Ok(agent), Ok(server)) => (agent, server),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(feature = "central")]
+    if central_agent.is_some() {
+        enforcer.set_local_enforcement(false);
+    }
+    // 本机地址保护：本机发出的流量不参与判定，也不会被封禁（UABLOCK_SELF_PROTECT=0 关闭）
+    if std::env::var("UABLOCK_SELF_PROTECT").as_deref() != Ok("0") {
+        match HostAddresses::detect() {
+            Ok(addresses) => {
+                let addresses = Arc::new(addresses);
+                if let Err(e) = addresses.start_refresh(HOST_ADDRESS_REFRESH) {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+                enforcer.set_host_addresses(addresses);
+            }
+            Err(e) => warn!("无法读取本机地址，本机地址保护未启用: {}", e),
+        }
+    }
+    // 可信来源（可选）：SIP 中继、办公网段不参与判定，也不会被封禁
+    match TrustedSources::from_env() {
+        Ok(Some(trusted)) => enforcer.set_trusted_sources(trusted),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 封禁生效延迟告警：最近封禁的 P99 超过上限时告警（可选）
+    match LatencyAlert::from_env() {
+        Ok(Some(alert)) => enforcer.set_latency_alert(alert),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 主备角色：备机只维护封禁表，升为主机前不下发防火墙规则
+    match HaRole::from_env() {
+        Ok(HaRole::Standby) if enforcer.firewall().is_none() => {
+            error!("备机角色（UABLOCK_HA_ROLE=standby）需要启用内置封禁");
+            std::process::exit(1);
+        }
+        Ok(role) => enforcer.set_role(role),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 检测策略：白名单、惩罚计数、UA 限速、检测规则、WASM 插件和灰名单
+    let mut policy = match build_policy(whitelist.clone(), &limits) {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // 已注册终端：本机对 REGISTER 应答过 200 OK 的来源在有效期内不因 UA 封禁（可选）
+    let registered = match RegisteredEndpoints::from_env() {
+        Ok(Some(registered)) => {
+            let registered = Arc::new(registered);
+            if let Err(e) = registered.clone().start(&interface, &capture_ports.ports()) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            policy = policy.with_registered(registered.clone());
+            Some(registered)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // 扫描活动归并（可选）：把相关的检测归并为活动，定期报告
+    let campaigns = match Campaigns::from_env() {
+        Ok(campaigns) => campaigns.map(Arc::new),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // 待审核封禁（可选）：低置信度的检测先等待运维审核
+    let review = match PendingBans::from_env() {
+        Ok(review) => review.map(Arc::new),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // 按触发方法的处置方式（检测规则文件的 [policy.<方法>] 段）
+    enforcer.set_method_policies(policy.method_policies());
+    // 封禁时长：UABLOCK_BAN_TTL=1h 时检测触发的封禁 1 小时后自动解封，未设置时永久封禁
+    if let Ok(value) = std::env::var("UABLOCK_BAN_TTL") {
+        match parse_duration(&value).filter(|ttl| !ttl.is_zero()) {
+            Some(ttl) => {
+                enforcer.set_ban_ttl(ttl);
+                info!("检测触发的封禁 {} 秒后自动解封", ttl.as_secs());
+            }
+            None if value.is_empty() => {}
+            None => {
+                error!("UABLOCK_BAN_TTL 无效: {}", value);
+                std::process::exit(1);
+            }
+        }
+    }
+    // 封禁升级（可选）：按违规次数延长检测触发的封禁，如 10m,1h,permanent
+    match Escalation::from_env() {
+        Ok(Some(escalation)) => enforcer.set_escalation(escalation),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // SQLite 封禁记录（可选）：先恢复上次运行的封禁记录，之后每次封禁/解封同步写入
+    #[cfg(feature = "sqlite")]
+    match SqliteBanStore::from_env() {
+        Ok(Some(store)) => match store.load() {
+            Ok(records) => {
+                info!(
+                    "封禁数据库 {}: 恢复 {} 条封禁记录",
+                    store.path().display(),
+                    records.len()
+                );
+                enforcer.bans().restore(&records);
+                if let Err(e) = enforcer.bans().set_store(Arc::new(store)) {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if std::env::var("UABLOCK_BAN_DB").is_ok_and(|path| !path.is_empty()) {
+        error!("设置了 UABLOCK_BAN_DB，但编译时未启用 sqlite 特性");
+        std::process::exit(1);
+    }
+    let enforcer = Arc::new(enforcer);
+
+    // 处置队列：检测触发的封禁、解封和规则复核由专用线程执行，检测任务（tokio 工作线程）不调用防火墙命令
+    let queue_capacity = match std::env::var("UABLOCK_ENFORCEMENT_QUEUE") {
+        Ok(value) if !value.is_empty() => match value.parse::<usize>() {
+            Ok(0) => {
+                warn!(
+                    "UABLOCK_ENFORCEMENT_QUEUE=0 不再关闭处置队列，使用默认容量 {}",
+                    DEFAULT_ENFORCEMENT_QUEUE
+                );
+                DEFAULT_ENFORCEMENT_QUEUE
+            }
+            Ok(capacity) => capacity,
+            Err(_) => {
+                error!("UABLOCK_ENFORCEMENT_QUEUE 无效: {}", value);
+                std::process::exit(1);
+            }
+        },
+        _ => DEFAULT_ENFORCEMENT_QUEUE,
+    };
+    // 备机也启动，升为主机后直接使用
+    if enforcer.firewall().is_some() || enforcer.is_standby() {
+        if let Err(e) = enforcer.start_queue(queue_capacity) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        info!("处置队列已启用，容量 {}", queue_capacity);
+    }
+
+    // 到期解封：检测触发的限时封禁（UABLOCK_BAN_TTL 或规则文件的 ban_duration）到期后解除
+    if enforcer.firewall().is_some() || enforcer.is_standby() {
+        if let Err(e) = enforcer.start_expiry(BAN_EXPIRY_INTERVAL) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // 定期大赦：UABLOCK_AMNESTY_AGE=7d 时定期解封封禁超过 7 天的 IP
+    if let Ok(age) = std::env::var("UABLOCK_AMNESTY_AGE") {
+        let interval =
+            std::env::var("UABLOCK_AMNESTY_INTERVAL").unwrap_or_else(|_| "1h".to_string());
+        let (Some(age), Some(interval)) = (parse_duration(&age), parse_duration(&interval)) else {
+            error!("UABLOCK_AMNESTY_AGE / UABLOCK_AMNESTY_INTERVAL 无效");
+            std::process::exit(1);
+        };
+        if enforcer.firewall().is_some() || enforcer.is_standby() {
+            if let Err(e) = enforcer.start_amnesty(age, interval.max(Duration::from_secs(60))) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            info!(
+                "定期大赦已启用: 每 {} 秒解封封禁超过 {} 秒的 IP",
+                interval.as_secs(),
+                age.as_secs()
+            );
+        }
+    }
+    // 规则核查：定期确认最近的封禁规则确实存在（UABLOCK_RULE_AUDIT_INTERVAL=0 关闭）
+    if enforcer.firewall().is_some() || enforcer.is_standby() {
+        match RuleAudit::from_env() {
+            Ok(Some(audit)) => {
+                info!(
+                    "规则核查已启用: 每 {} 秒核查最近 {} 个封禁",
+                    audit.interval().as_secs(),
+                    audit.batch()
+                );
+                if let Err(e) = audit.start(&enforcer) {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "central")]
+    {
+        let started = central_agent
+            .map_or(Ok(()), |agent| agent.start(enforcer.clone()))
+            .and_then(|_| central_server.map_or(Ok(()), |server| server.start(enforcer.clone())));
+        if let Err(e) = started {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // 授权查询：PBX 在接受请求前查询（IP, UA）是否放行（可选）
+    if let Ok(listen) = std::env::var("UABLOCK_AUTHZ_LISTEN") {
+        let started = match listen.parse() {
+            Ok(addr) => {
+                let mut authz = Authz::new(enforcer.clone(), whitelist.clone());
+                if let Some(registered) = &registered {
+                    authz = authz.with_registered(registered.clone());
+                }
+                Arc::new(authz).start(addr)
+            }
+            Err(e) => Err(format!("UABLOCK_AUTHZ_LISTEN 地址无效 {}: {}", listen, e)),
+        };
+        if let Err(e) = started {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // HTTP API（可选）
+    #[cfg(feature = "api")]
+    if let Ok(listen) = std::env::var("UABLOCK_API_LISTEN") {
+        let token = std::env::var("UABLOCK_API_TOKEN").unwrap_or_default();
+        if token.is_empty() {
+            error!("启用 HTTP API 时必须设置 UABLOCK_API_TOKEN");
+            std::process::exit(1);
+        }
+        // 外部系统封禁命令入口（可选）
+        let webhook = match webhook::Webhook::from_env() {
+            Ok(webhook) => webhook.map(|w| w.start(enforcer.clone())),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let state = api::ApiState {
+            token: Arc::new(token),
+            feed_token: std::env::var("UABLOCK_FEED_TOKEN")
+                .ok()
+                .filter(|t| !t.is_empty())
+                .map(Arc::new),
+            enforcer: enforcer.clone(),
+            whitelist: whitelist.clone(),
+            webhook,
+            ports: live_ports.clone(),
+            campaigns: campaigns.clone(),
+            review: review.clone(),
+        };
+        let started = match listen.parse() {
+            Ok(addr) => api::spawn(addr, state).await,
+            Err(e) => Err(format!("UABLOCK_API_LISTEN 地址无效 {}: {}", listen, e)),
+        };
+        if let Err(e) = started {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // gRPC 控制接口（可选）
+    #[cfg(feature = "grpc")]
+    if let Ok(listen) = std::env::var("UABLOCK_GRPC_LISTEN") {
+        match listen.parse() {
+            Ok(addr) => grpc::spawn(addr, enforcer.clone()),
+            Err(e) => {
+                error!("UABLOCK_GRPC_LISTEN 地址无效 {}: {}", listen, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // 把封禁镜像到 Kamailio htable（可选）
+    if let Some(kamailio) = KamailioMirror::from_env() {
+        if let Err(e) = kamailio.start(enforcer.events()) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // CrowdSec 告警上报 / 决策同步（可选）
+    if let Some(crowdsec) = CrowdSec::from_env() {
+        if let Err(e) = crowdsec.start(enforcer.clone()) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // SNMP AgentX 子代理（可选）
+    match snmp::SnmpAgent::from_env(enforcer.clone()) {
+        Ok(Some(agent)) => {
+            if let Err(e) = agent.start() {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Consul/etcd 共享状态（可选）
+    #[cfg(feature = "shared-state")]
+    {
+        let started = shared_state::SharedState::from_env().and_then(|shared| match shared {
+            Some(shared) => shared.start(enforcer.clone(), whitelist.clone()),
+            None => Ok(()),
+        });
+        if let Err(e) = started {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // 集群封禁同步（可选）：Redis pub/sub 或点对点同步
+    #[cfg(any(feature = "redis-sync", feature = "gossip"))]
+    {
+        let cluster = Arc::new(cluster::ClusterSync::from_env(enforcer.clone()));
+        #[cfg(feature = "redis-sync")]
+        {
+            let started =
+                redis_sync::RedisSync::from_env(cluster.clone()).and_then(|sync| match sync {
+                    Some(sync) => sync.start(enforcer.events()),
+                    None => Ok(()),
+                });
+            if let Err(e) = started {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "gossip")]
+        {
+            let started = gossip::Gossip::from_env(cluster.clone()).and_then(|sync| match sync {
+                Some(sync) => sync.start(enforcer.events()),
+                None => Ok(()),
+            });
+            if let Err(e) = started {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut builder = Pipeline::builder(enforcer.clone())
+        .parser(SipParser::new())
+        .policy(policy)
+        .transaction_limit(limits.max_transactions);
+    // 蜜罐应答模式（可选，需显式开启）
+    if std::env::var("UABLOCK_HONEYPOT").as_deref() == Ok("1") {
+        match Honeypot::new(std::env::var("UABLOCK_HONEYPOT_BANNER").ok()) {
+            Ok(honeypot) => builder = builder.honeypot(honeypot),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // 判定缓存：最近已封禁的来源不再判定，UABLOCK_DECISION_CACHE_TTL=0 时关闭
+    let decision_ttl = match std::env::var("UABLOCK_DECISION_CACHE_TTL") {
+        Ok(value) if !value.is_empty() => match value.parse::<u64>() {
+            Ok(secs) => secs,
+            Err(_) => {
+                error!("UABLOCK_DECISION_CACHE_TTL 无效: {}", value);
+                std::process::exit(1);
+            }
+        },
+        _ => DEFAULT_DECISION_CACHE_TTL_SECS,
+    };
+    if decision_ttl > 0 {
+        builder = builder.decision_cache(Duration::from_secs(decision_ttl));
+    }
+    // 已封禁来源只计数不判定，每个来源每个间隔最多一行日志，UABLOCK_BANNED_LOG_INTERVAL=0 时关闭
+    let banned_log_interval = match std::env::var("UABLOCK_BANNED_LOG_INTERVAL") {
+        Ok(value) if !value.is_empty() => match value.parse::<u64>() {
+            Ok(secs) => secs,
+            Err(_) => {
+                error!("UABLOCK_BANNED_LOG_INTERVAL 无效: {}", value);
+                std::process::exit(1);
+            }
+        },
+        _ => DEFAULT_BANNED_LOG_INTERVAL_SECS,
+    };
+    if banned_log_interval > 0 {
+        builder = builder.suppress_banned(Duration::from_secs(banned_log_interval));
+    }
+    // 封禁效果验证：封禁后本机仍在应答时告警（可选）
+    match BanCheck::from_env() {
+        Ok(Some(check)) => {
+            if let Err(e) = Arc::new(check).start(
+                &interface,
+                block_port,
+                stats.clone(),
+                enforcer.events().clone(),
+            ) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 多租户：按目标地址选择白名单和通知地址（可选）
+    let tenants = match Tenants::from_env() {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // 事件通知：租户的事件发给该租户的目标，其余发给默认目标（可选）
+    match Notifier::from_env(&interface, tenants.as_ref()) {
+        Ok(Some(notifier)) => {
+            if let Err(e) = notifier.start(enforcer.events()) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(tenants) = tenants {
+        info!("已加载 {} 个租户", tenants.len());
+        builder = builder.tenants(tenants);
+    }
+    // 局域网设备只告警不封禁（可选）
+    match LocalNetworks::from_env(&interfaces.join(",")) {
+        Ok(Some(networks)) => builder = builder.local_networks(networks),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 白名单 UA 的二次验证（可选）
+    match Verifier::from_env() {
+        Ok(Some(verifier)) => builder = builder.verifier(verifier),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // Lua 脚本钩子（可选）
+    #[cfg(feature = "lua-hooks")]
+    match LuaHooks::from_env() {
+        Ok(Some(hooks)) => builder = builder.lua_hooks(hooks),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    // 判定轨迹（可选）：记录输入和判定结果，供 `replay-trace` 复查
+    match TraceRecorder::from_env() {
+        Ok(Some(recorder)) => builder = builder.trace(recorder),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(campaigns) = campaigns {
+        builder = builder.campaigns(campaigns);
+    }
+    if let Some(review) = review {
+        builder = builder.review(review);
+    }
+    // 检测状态持久化（可选）：恢复上次保存的惩罚分和 UA 速率窗口
+    if let Ok(path) = std::env::var("UABLOCK_STATE_FILE") {
+        if !path.is_empty() {
+            builder = builder.state_file(StateFile::new(Path::new(&path)));
+        }
+    }
+    let pipeline = builder.build();
+
+    // 外部系统（PBX）上报的安全信号
+    let (signal_tx, signal_rx) = mpsc::unbounded_channel();
+    let inputs = [
+        FreeswitchEsl::from_env(signal_tx.clone()).map(|input| input.start()),
+        AsteriskSecurityLog::from_env(signal_tx.clone()).map(|input| input.start()),
+        AsteriskAmi::from_env(signal_tx.clone()).map(|input| input.start()),
+        #[cfg(feature = "pcap")]
+        TlsCapture::from_env(&interface, signal_tx.clone()).map(|input| input.start()),
+    ];
+    for started in inputs.into_iter().flatten() {
+        if let Err(e) = started {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    drop(signal_tx);
+
+    // 抓包在专用线程中阻塞进行，数据包经由队列交给检测任务
+    let (packet_tx, packet_rx) = mpsc::channel(PACKET_QUEUE_CAPACITY);
+    // 不抓包时保留发送端，检测任务不会因数据包通道关闭而退出
+    let _idle_packet_tx = if sources.is_empty() {
+        info!("未启用抓包（UABLOCK_CAPTURE=none），只处理外部系统上报的信号");
+        Some(packet_tx)
+    } else {
+        // 解析线程池（可选）：抓包线程与检测任务之间并行解析，同一来源的数据包保持顺序
+        let packet_tx = match ParsePool::from_env() {
+            Ok(Some(pool)) => match pool.start(packet_tx) {
+                Ok(input) => {
+                    info!("解析线程池已启用: {} 个线程", pool.workers());
+                    input
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            Ok(None) => packet_tx,
             Err(e) => {
-                error!("抓包错误: {}", e);
-                std::thread::sleep(Duration::from_secs(1));
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        // 每个接口一个抓包线程，数据包汇入同一个队列
+        for (interface, source) in sources {
+            if let Err(e) = packet_capture::spawn_interface_reader(
+                &interface,
+                source,
+                packet_tx.clone(),
+                stats.clone(),
+                live_ports.clone(),
+            ) {
+                error!("{}", e);
+                std::process::exit(1);
             }
         }
+        None
+    };
+
+    // 收到 SIGHUP 时重新读取端口文件，抓包过滤器随之更新
+    if let Some(ports) = live_ports.filter(|_| std::env::var_os("UABLOCK_PORTS_FILE").is_some()) {
+        signals::on_reload(move || match ports.reload() {
+            Ok(true) => info!("【SIGHUP】受保护端口更新为 {:?}", ports.ports()),
+            Ok(false) => info!("【SIGHUP】受保护端口未变化"),
+            Err(e) => error!("【SIGHUP】{}", e),
+        });
+    }
 
-        // 定期清理过期的处理记录（每 1000 次循环检查一次）
-        static mut CLEANUP_COUNTER: u32 = 0;
-        unsafe {
-            CLEANUP_COUNTER += 1;
-            if CLEANUP_COUNTER % 1000 == 0 {
-                let mut last_processed_guard = last_processed.lock().unwrap();
-                let now = Instant::now();
-                last_processed_guard
-                    .retain(|_, time| now.duration_since(*time) < Duration::from_secs(3600));
+    // 升级交接：收到 SIGUSR2 时写入完整快照后退出
+    let snapshot_path = std::env::var("UABLOCK_SNAPSHOT_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SNAPSHOT_FILE));
+    let mut engine = Engine::new(pipeline).handover_on(&snapshot_path, signals::handover());
+    if let Some(path) = restore {
+        if let Some(state) = StateFile::new(&path).load_or_warn() {
+            engine.restore(&state);
+            // 快照只用一次，避免之后重启时重放过期的待解除规则
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("删除升级快照 {} 失败: {}", path.display(), e);
             }
+        } else {
+            warn!("升级快照 {} 不可用，从空状态启动", path.display());
         }
     }
+
+    // 启动核对：封禁记录（状态文件、封禁数据库）、防火墙规则和当前配置之间的差异；
+    // 已到期的封禁总是解除，防火墙中已有的规则记入封禁表
+    if engine.pipeline().enforcer().firewall().is_some() {
+        let enforcer = engine.pipeline().enforcer();
+        match engine.pipeline().reconcile() {
+            Ok(report) => {
+                report.log();
+                if !report.is_clean()
+                    && std::env::var("UABLOCK_RECONCILE_REPAIR").as_deref() == Ok("1")
+                {
+                    let repaired = report.repair(enforcer);
+                    info!("【核对】已自动修复 {} 处差异", repaired);
+                } else if !report.expired.is_empty() {
+                    let removed = report.remove_expired(enforcer);
+                    info!("【核对】已解除 {} 个到期的封禁", removed);
+                }
+                let adopted = report.adopt_foreign(enforcer);
+                if adopted > 0 {
+                    info!("【核对】{} 条外来规则已记入封禁表（原因 UNKNOWN）", adopted);
+                }
+            }
+            Err(e) => warn!("【核对】无法列出防火墙规则: {}", e),
+        }
+    }
+
+    info!("开始监控 SIP 流量...");
+    engine.run(packet_rx, signal_rx, signals::shutdown()).await;
+}
+
+/// 默认数据包来源：优先 libpcap，未编译时在 Linux 上使用 AF_PACKET
+fn default_capture() -> &'static str {
+    if cfg!(feature = "pcap") {
+        "pcap"
+    } else if cfg!(target_os = "linux") {
+        "af_packet"
+    } else {
+        "none"
+    }
+}
+
+/// 默认封禁后端：优先 iptables；未编译 iptables 后端，或系统中没有 iptables 命令（只安装了 nftables
+/// 的发行版）时使用 nftables；两者都没有而有 pfctl 时（FreeBSD、macOS）使用 pf
+fn default_backend() -> &'static str {
+    if cfg!(feature = "iptables") && command_exists("iptables") {
+        "iptables"
+    } else if !command_exists("nft") && command_exists("pfctl") {
+        "pf"
+    } else {
+        "nft"
+    }
+}
+
+/// PATH 中是否有该命令
+fn command_exists(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
 }
 
-/// 初始化白名单
-fn initialize_whitelist() -> Whitelist {
-    // 可以从环境变量或配置文件读取
-    let whitelist = if let Ok(whitelist_env) = std::env::var("SIP_UA_WHITELIST") {
-        let patterns = whitelist_env
+/// 接口名和在该接口上打开的数据包来源
+type NamedSource = (String, Box<dyn PacketSource>);
+
+/// 按 UABLOCK_CAPTURE 在每个接口上打开数据包来源，与接口名一起返回；UDP 接收只打开一个来源（名称为 udp），
+/// none 时返回空列表
+fn open_packet_sources(
+    mode: &str,
+    interfaces: &[String],
+    ports: &[u16],
+    filter: Option<&str>,
+) -> Result<Vec<NamedSource>, String> {
+    if filter.is_some() && mode != "pcap" {
+        return Err(format!(
+            "UABLOCK_CAPTURE_FILTER 只适用于 libpcap 抓包（UABLOCK_CAPTURE=pcap），当前为 {}",
+            mode
+        ));
+    }
+    let (interfaces, single) = match mode {
+        "pcap" | "af_packet" => (interfaces, false),
+        _ => (&interfaces[..1], true),
+    };
+    interfaces
+        .iter()
+        .filter_map(|interface| {
+            let name = if single { mode } else { interface };
+            open_packet_source(mode, interface, ports, filter)
+                .map(|source| source.map(|source| (name.to_string(), source)))
+                .transpose()
+        })
+        .collect()
+}
+
+/// 按 UABLOCK_CAPTURE 打开数据包来源；none 表示不抓包
+#[cfg_attr(not(feature = "pcap"), allow(unused_variables))]
+fn open_packet_source(
+    mode: &str,
+    interface: &str,
+    ports: &[u16],
+    filter: Option<&str>,
+) -> Result<Option<Box<dyn PacketSource>>, String> {
+    match mode {
+        #[cfg(feature = "pcap")]
+        "pcap" => match PacketCapture::open_filtered(interface, ports, filter) {
+            Ok(capture) => Ok(Some(Box::new(capture))),
+            Err(e) => Err(format!(
+                "无法打开网络接口: {}（可用接口: {:?}）",
+                e,
+                PacketCapture::list_interfaces()
+            )),
+        },
+        #[cfg(not(feature = "pcap"))]
+        "pcap" => Err(
+            "此构建未包含 libpcap 抓包（pcap 特性），请使用 UABLOCK_CAPTURE=af_packet、udp 或 none"
+                .to_string(),
+        ),
+        #[cfg(target_os = "linux")]
+        "af_packet" => AfPacketSource::open_ports(interface, ports)
+            .map(|source| Some(Box::new(source) as Box<dyn PacketSource>)),
+        "udp" if ports.len() > 1 => Err(
+            "UDP 接收模式只能监听一个端口，请勿在 UABLOCK_PORTS_FILE 中配置多个端口".to_string(),
+        ),
+        "udp" => UdpSource::from_env(ports[0]).map(|source| {
+            if let Ok(addr) = source.local_addr() {
+                info!("通过 UDP 套接字接收 SIP 流量: {}", addr);
+            }
+            Some(Box::new(source) as Box<dyn PacketSource>)
+        }),
+        "none" => Ok(None),
+        other => Err(format!(
+            "UABLOCK_CAPTURE 无效: {}（可选 pcap、af_packet、udp、none）",
+            other
+        )),
+    }
+}
+
+/// 按 UABLOCK_BACKEND 创建封禁后端；none 表示不执行封禁
+///
+/// iptables 后端的锁等待和重试由 UABLOCK_IPTABLES_WAIT（秒，默认 5）和
+/// UABLOCK_IPTABLES_RETRIES（默认 3）设置；规则所在的链由 UABLOCK_IPTABLES_CHAIN（默认 UABLOCK）
+/// 设置，专用链从 UABLOCK_IPTABLES_PARENT（默认 INPUT）跳转，打开时创建；封禁规则的动作由
+/// UABLOCK_IPTABLES_TARGET（默认 DROP）设置。ipset 后端的集合名由
+/// UABLOCK_IPSET_NAME（默认 uablock）、新建集合的容量由 UABLOCK_IPSET_MAXELEM 设置，匹配规则
+/// 插入 UABLOCK_IPTABLES_PARENT 的开头，集合和规则在打开时创建。firewalld 后端使用同样的集合名和
+/// 容量，富规则加在 UABLOCK_FIREWALLD_ZONE（默认为 firewalld 的默认区域）中。pf 后端的表和锚点由
+/// UABLOCK_PF_TABLE、UABLOCK_PF_ANCHOR（默认都为 uablock）设置。ssh 后端登录 UABLOCK_SSH_HOST
+/// （必填），端口和私钥由 UABLOCK_SSH_PORT、UABLOCK_SSH_KEY 设置，命令模板由
+/// UABLOCK_SSH_BLOCK_CMD、UABLOCK_SSH_UNBLOCK_CMD、UABLOCK_SSH_CHECK_CMD、UABLOCK_SSH_LIST_CMD 设置。
+#[cfg_attr(not(feature = "iptables"), allow(unused_variables))]
+fn open_firewall(
+    mode: &str,
+    port: u16,
+    stats: &Arc<Stats>,
+) -> Result<Option<Box<dyn FirewallBackend>>, String> {
+    match mode {
+        #[cfg(feature = "iptables")]
+        "iptables" => {
+            let env_u32 = |name: &str| -> Result<Option<u32>, String> {
+                match std::env::var(name) {
+                    Ok(text) if !text.is_empty() => text
+                        .parse()
+                        .map(Some)
+                        .map_err(|_| format!("{} 无效: {}", name, text)),
+                    _ => Ok(None),
+                }
+            };
+            let chain = std::env::var("UABLOCK_IPTABLES_CHAIN")
+                .ok()
+                .filter(|chain| !chain.is_empty());
+            let mut manager =
+                IptablesManager::new_with_port(chain, Some(port)).with_stats(stats.clone());
+            if let Some(secs) = env_u32("UABLOCK_IPTABLES_WAIT")? {
+                manager = manager.with_lock_wait(secs);
+            }
+            if let Some(retries) = env_u32("UABLOCK_IPTABLES_RETRIES")? {
+                manager = manager.with_retries(retries);
+            }
+            if let Ok(parent) = std::env::var("UABLOCK_IPTABLES_PARENT") {
+                if !parent.is_empty() {
+                    manager = manager.with_parent_chain(&parent);
+                }
+            }
+            if let Ok(target) = std::env::var("UABLOCK_IPTABLES_TARGET") {
+                manager = manager.with_target(&target);
+            }
+            manager.setup()?;
+            Ok(Some(Box::new(manager)))
+        }
+        #[cfg(not(feature = "iptables"))]
+        "iptables" => Err(
+            "此构建未包含 iptables 后端（iptables 特性），请使用 UABLOCK_BACKEND=ipset、nft、firewalld、pf、ssh 或 none"
+                .to_string(),
+        ),
+        "ipset" => {
+            let mut manager = IpsetManager::new(Some(port));
+            if let Ok(name) = std::env::var("UABLOCK_IPSET_NAME") {
+                if !name.is_empty() {
+                    manager = manager.with_set_name(&name);
+                }
+            }
+            if let Ok(parent) = std::env::var("UABLOCK_IPTABLES_PARENT") {
+                if !parent.is_empty() {
+                    manager = manager.with_chain(&parent);
+                }
+            }
+            if let Some(maxelem) = ipset_maxelem()? {
+                manager = manager.with_maxelem(maxelem);
+            }
+            manager.setup()?;
+            Ok(Some(Box::new(manager)))
+        }
+        "firewalld" => {
+            let mut manager = FirewalldManager::new(Some(port));
+            if let Ok(name) = std::env::var("UABLOCK_IPSET_NAME") {
+                if !name.is_empty() {
+                    manager = manager.with_set_name(&name);
+                }
+            }
+            if let Ok(zone) = std::env::var("UABLOCK_FIREWALLD_ZONE") {
+                if !zone.is_empty() {
+                    manager = manager.with_zone(&zone);
+                }
+            }
+            if let Some(maxelem) = ipset_maxelem()? {
+                manager = manager.with_maxelem(maxelem);
+            }
+            manager.setup()?;
+            Ok(Some(Box::new(manager)))
+        }
+        "pf" => {
+            let mut manager = PfManager::new(Some(port));
+            if let Ok(table) = std::env::var("UABLOCK_PF_TABLE") {
+                if !table.is_empty() {
+                    manager = manager.with_table(&table);
+                }
+            }
+            if let Ok(anchor) = std::env::var("UABLOCK_PF_ANCHOR") {
+                if !anchor.is_empty() {
+                    manager = manager.with_anchor(&anchor);
+                }
+            }
+            manager.setup()?;
+            Ok(Some(Box::new(manager)))
+        }
+        "ssh" => {
+            let host = std::env::var("UABLOCK_SSH_HOST")
+                .ok()
+                .filter(|host| !host.is_empty())
+                .ok_or("UABLOCK_BACKEND=ssh 时必须设置 UABLOCK_SSH_HOST")?;
+            let mut firewall = SshFirewall::new(&host, Some(port));
+            if let Ok(text) = std::env::var("UABLOCK_SSH_PORT") {
+                if !text.is_empty() {
+                    let ssh_port = text
+                        .parse()
+                        .map_err(|_| format!("UABLOCK_SSH_PORT 无效: {}", text))?;
+                    firewall = firewall.with_port(ssh_port);
+                }
+            }
+            let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+            if let Some(key) = env("UABLOCK_SSH_KEY") {
+                firewall = firewall.with_key(&key);
+            }
+            if let Some(template) = env("UABLOCK_SSH_BLOCK_CMD") {
+                firewall = firewall.with_block_command(&template);
+            }
+            if let Some(template) = env("UABLOCK_SSH_UNBLOCK_CMD") {
+                firewall = firewall.with_unblock_command(&template);
+            }
+            if let Some(template) = env("UABLOCK_SSH_CHECK_CMD") {
+                firewall = firewall.with_check_command(&template);
+            }
+            if let Some(command) = env("UABLOCK_SSH_LIST_CMD") {
+                firewall = firewall.with_list_command(&command);
+            }
+            firewall.setup()?;
+            Ok(Some(Box::new(firewall)))
+        }
+        "nft" => NftManager::new(Some(port))
+            .map(|manager| Some(Box::new(manager) as Box<dyn FirewallBackend>)),
+        "none" => {
+            info!("内置封禁已禁用（UABLOCK_BACKEND=none）");
+            Ok(None)
+        }
+        other => Err(format!(
+            "UABLOCK_BACKEND 无效: {}（可选 iptables、ipset、nft、firewalld、pf、ssh、none）",
+            other
+        )),
+    }
+}
+
+/// 新建 ipset 集合的容量（UABLOCK_IPSET_MAXELEM），未设置时返回 None
+fn ipset_maxelem() -> Result<Option<u32>, String> {
+    match std::env::var("UABLOCK_IPSET_MAXELEM") {
+        Ok(text) if !text.is_empty() => text
+            .parse()
+            .ok()
+            .filter(|&maxelem| maxelem > 0)
+            .map(Some)
+            .ok_or_else(|| format!("UABLOCK_IPSET_MAXELEM 无效: {}", text)),
+        _ => Ok(None),
+    }
+}
+
+/// 按环境变量为封禁后端加上降级策略
+///
+/// - UABLOCK_BACKEND_ON_FAILURE：`alert`（默认）、`fallback` 或 `exit`
+/// - UABLOCK_BACKEND_FALLBACK：备用后端（`iptables`、`ipset`、`nft`、`firewalld`、`pf` 或 `ssh`，`fallback` 时必填）
+/// - UABLOCK_BACKEND_FAILURE_THRESHOLD：连续失败多少次后视为不可用（默认 3）
+fn guard_firewall(
+    primary: Box<dyn FirewallBackend>,
+    port: u16,
+    stats: Arc<Stats>,
+) -> Result<Box<dyn FirewallBackend>, String> {
+    let action = std::env::var("UABLOCK_BACKEND_ON_FAILURE").unwrap_or_default();
+    let action = match action.as_str() {
+        "" => FailureAction::Alert,
+        text => FailureAction::parse(text).ok_or_else(|| {
+            format!(
+                "UABLOCK_BACKEND_ON_FAILURE 无效: {}（可选 alert、fallback、exit）",
+                text
+            )
+        })?,
+    };
+    let mut firewall = GuardedFirewall::new(primary, stats.clone()).with_action(action);
+    if let Ok(text) = std::env::var("UABLOCK_BACKEND_FAILURE_THRESHOLD") {
+        let threshold = text
+            .parse()
+            .map_err(|_| format!("UABLOCK_BACKEND_FAILURE_THRESHOLD 无效: {}", text))?;
+        firewall = firewall.with_threshold(threshold);
+    }
+    if action == FailureAction::Fallback {
+        let mode = std::env::var("UABLOCK_BACKEND_FALLBACK").unwrap_or_default();
+        let fallback = open_firewall(&mode, port, &stats)?
+            .ok_or("UABLOCK_BACKEND_ON_FAILURE=fallback 时必须设置 UABLOCK_BACKEND_FALLBACK")?;
+        info!("封禁后端不可用时切换到备用后端 {}", mode);
+        firewall = firewall.with_fallback(fallback);
+    }
+    Ok(Box::new(firewall))
+}
+
+/// 按环境变量构造检测策略；守护进程和 `replay-trace` 共用，重放时的配置与运行时一致
+///
+/// 策略包括白名单、惩罚计数（畸形报文等无法提取 UA 的异常行为）以及可选的 UA 全局限速、
+/// MESSAGE/SUBSCRIBE/NOTIFY 来源限速、声明式检测规则、WASM 检测插件、首次来源灰名单和
+/// 二进制垃圾数据惩罚。
+fn build_policy(whitelist: Arc<Mutex<Whitelist>>, limits: &Limits) -> Result<Policy, String> {
+    let strikes = StrikeTracker::from_env()
+        .with_sources(Arc::new(SourceTable::with_limit(limits.max_sources)));
+    let mut policy = Policy::new(whitelist).with_strikes(strikes);
+    if let Some(limiter) = UaRateLimiter::from_env() {
+        policy = policy.with_ua_limiter(limiter);
+    }
+    if let Some(limiter) = MethodRateLimiter::from_env()? {
+        policy = policy.with_method_limiter(limiter);
+    }
+    match std::env::var("UABLOCK_RULES_FILE") {
+        Ok(path) if !path.is_empty() => {
+            let path = Path::new(&path);
+            let rules = match read_signed(path)? {
+                Some(content) => RulesEngine::load_content(path, &content)?,
+                None => RulesEngine::load(path)?,
+            };
+            policy = policy.with_rules(rules);
+        }
+        _ => {}
+    }
+    #[cfg(feature = "wasm-plugins")]
+    if let Some(plugins) = PluginHost::from_env()? {
+        policy = policy.with_plugins(plugins);
+    }
+    if let Some(greylist) = Greylist::from_env() {
+        policy = policy.with_greylist(greylist);
+    }
+    match std::env::var("UABLOCK_NOISE_WEIGHT") {
+        Ok(weight) if !weight.is_empty() => {
+            let weight = weight
+                .parse::<f64>()
+                .map_err(|_| format!("UABLOCK_NOISE_WEIGHT 无效: {}", weight))?;
+            info!("二进制垃圾数据计入惩罚分: 每个数据包 {}", weight);
+            policy = policy.with_noise_weight(weight);
+        }
+        _ => {}
+    }
+    Ok(policy)
+}
+
+/// 按配置文件中的 `whitelist`/`blacklist` 初始化 UA 名单，守护进程和各子命令共用
+fn initialize_whitelist(config: &Config) -> Result<Whitelist, String> {
+    initialize_whitelist_with(config.whitelist.clone(), config.blacklist.clone())
+}
+
+/// 初始化 UA 名单：白名单文件 `UABLOCK_WHITELIST_FILE` 或环境变量 `SIP_UA_BLACKLIST`（黑名单模式）/
+/// `SIP_UA_WHITELIST` 优先，其次为配置文件中的模式；两种模式同时设置时报错。
+/// 匹配方式由 `UABLOCK_UA_MATCH` 设置
+fn initialize_whitelist_with(
+    configured: Option<Vec<String>>,
+    blacklist: Option<Vec<String>>,
+) -> Result<Whitelist, String> {
+    let split = |value: String| -> Vec<String> {
+        value
             .split(',')
             .map(|s| s.trim().to_string())
-            .collect();
-        Whitelist::new(patterns)
-    } else {
-        // 使用 Default trait 的默认白名单
-        Whitelist::default()
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+    let blacklist_env = std::env::var("SIP_UA_BLACKLIST").ok().map(split);
+    let whitelist_env = std::env::var("SIP_UA_WHITELIST").ok();
+    if let Some(path) = WhitelistFile::path_from_env() {
+        if whitelist_env.is_some() || blacklist_env.is_some() || blacklist.is_some() {
+            return Err(
+                "UABLOCK_WHITELIST_FILE 不能与 SIP_UA_WHITELIST、SIP_UA_BLACKLIST 或配置文件中的 blacklist 同时设置"
+                    .to_string(),
+            );
+        }
+        if configured.is_some() {
+            info!(
+                "使用白名单文件 {}，忽略配置文件中的 whitelist",
+                path.display()
+            );
+        }
+        let whitelist = Whitelist::new(read_patterns(&path)?);
+        return log_whitelist(whitelist.with_match_mode(MatchMode::from_env()?));
+    }
+    let whitelist = match (blacklist_env, whitelist_env) {
+        (Some(_), Some(_)) => {
+            return Err("SIP_UA_BLACKLIST 和 SIP_UA_WHITELIST 不能同时设置".to_string())
+        }
+        (Some(patterns), None) if patterns.is_empty() => {
+            return Err("黑名单不能为空（SIP_UA_BLACKLIST）".to_string())
+        }
+        (Some(patterns), None) => Whitelist::deny(patterns),
+        (None, Some(whitelist_env)) => {
+            let patterns = whitelist_env
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect();
+            Whitelist::new(patterns)
+        }
+        (None, None) => match (blacklist, configured) {
+            (Some(patterns), _) => Whitelist::deny(patterns),
+            (None, Some(patterns)) => Whitelist::new(patterns),
+            // 使用 Default trait 的默认白名单
+            (None, None) => Whitelist::default(),
+        },
+    };
+    log_whitelist(whitelist.with_match_mode(MatchMode::from_env()?))
+}
+
+fn log_whitelist(whitelist: Whitelist) -> Result<Whitelist, String> {
+    match whitelist.mode() {
+        ListMode::Allow => info!(
+            "白名单模式: {:?}（匹配方式 {}）",
+            whitelist.get_patterns(),
+            whitelist.match_mode().as_str()
+        ),
+        ListMode::Deny => info!(
+            "黑名单模式: 只封禁匹配 {:?} 的 UA，其余放行（匹配方式 {}）",
+            whitelist.get_patterns(),
+            whitelist.match_mode().as_str()
+        ),
+    }
+    Ok(whitelist)
+}
+
+/// 读取配置文件，设置了 `UABLOCK_BUNDLE_PUBKEY` 时只接受签名有效的内容
+fn load_config(path: &Path) -> Result<Config, String> {
+    match read_signed(path)? {
+        Some(content) => Config::load_content(path, &content),
+        None => Config::load(path),
+    }
+}
+
+/// 读取受签名保护的文件（规则文件、配置文件）
+///
+/// 设置了 `UABLOCK_BUNDLE_PUBKEY` 时校验同名的 `.sig` 签名，返回通过校验的内容，没有签名或签名不符时
+/// 返回错误；未设置时返回 None，由调用方照常读取。
+#[cfg(feature = "signed-bundles")]
+fn read_signed(path: &Path) -> Result<Option<String>, String> {
+    let Some(verifier) = BundleVerifier::from_env()? else {
+        return Ok(None);
     };
+    verifier
+        .verify_file(path)
+        .map(|bundle| Some(bundle.content))
+}
 
-    info!("白名单模式: {:?}", whitelist.get_patterns());
-    whitelist
+/// 未启用 signed-bundles 特性：设置了 `UABLOCK_BUNDLE_PUBKEY` 时报错，不会跳过签名校验
+#[cfg(not(feature = "signed-bundles"))]
+fn read_signed(path: &Path) -> Result<Option<String>, String> {
+    match std::env::var("UABLOCK_BUNDLE_PUBKEY") {
+        Ok(value) if !value.is_empty() => Err(format!(
+            "设置了 UABLOCK_BUNDLE_PUBKEY，但编译时未启用 signed-bundles 特性，无法校验 {} 的签名",
+            path.display()
+        )),
+        _ => Ok(None),
+    }
 }
 
 /// 检查是否有 root 权限
@@ -0,0 +1,102 @@
+use crate::detection::Detection;
+use crate::sip_parser::SipRequest;
+use log::info;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 按来源、按 SIP 方法的请求速率限制
+///
+/// REGISTER/INVITE 之外的方法同样会被滥用：MESSAGE 垃圾短信（SPIT）、SUBSCRIBE/NOTIFY 洪泛。
+/// 为这些方法分别设置每个来源在窗口内允许的请求数，超过时产生检测结果，
+/// 原因代码为 `<方法>_FLOOD`（如 `MESSAGE_FLOOD`）。每个窗口只在刚超过上限时检测一次。
+pub struct MethodRateLimiter {
+    window: Duration,
+    /// 方法（大写）→ 窗口内允许的请求数
+    limits: HashMap<String, u32>,
+    /// (来源, 方法) → (窗口开始时间, 请求数)
+    counters: HashMap<(IpAddr, String), (Instant, u32)>,
+}
+
+impl MethodRateLimiter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            limits: HashMap::new(),
+            counters: HashMap::new(),
+        }
+    }
+
+    /// 设置某个方法每个来源在窗口内允许的请求数
+    pub fn with_limit(mut self, method: &str, limit: u32) -> Self {
+        self.limits.insert(method.to_uppercase(), limit);
+        self
+    }
+
+    /// 从环境变量创建，没有设置任何方法的上限时返回 None
+    ///
+    /// - UABLOCK_MESSAGE_LIMIT：每个来源窗口内允许的 MESSAGE 请求数
+    /// - UABLOCK_SUBSCRIBE_LIMIT：每个来源窗口内允许的 SUBSCRIBE 请求数
+    /// - UABLOCK_NOTIFY_LIMIT：每个来源窗口内允许的 NOTIFY 请求数
+    /// - UABLOCK_METHOD_WINDOW：统计窗口（秒，默认 60）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let env_u64 = |name: &str| match std::env::var(name) {
+            Ok(value) if !value.is_empty() => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| format!("{} 无效: {}", name, value)),
+            _ => Ok(None),
+        };
+        let window = env_u64("UABLOCK_METHOD_WINDOW")?.unwrap_or(60);
+        let mut limiter = Self::new(Duration::from_secs(window));
+        for method in ["MESSAGE", "SUBSCRIBE", "NOTIFY"] {
+            if let Some(limit) = env_u64(&format!("UABLOCK_{}_LIMIT", method))? {
+                limiter = limiter.with_limit(method, limit as u32);
+            }
+        }
+        if limiter.limits.is_empty() {
+            return Ok(None);
+        }
+        let mut limits: Vec<String> = limiter
+            .limits
+            .iter()
+            .map(|(method, limit)| format!("{} {} 次", method, limit))
+            .collect();
+        limits.sort();
+        info!(
+            "按方法的来源限速已启用: 每个来源 {} 秒内最多 {}",
+            window,
+            limits.join("、")
+        );
+        Ok(Some(limiter))
+    }
+
+    /// 是否限制该方法
+    pub fn limits(&self, method: &str) -> bool {
+        self.limits.contains_key(method)
+    }
+
+    /// 记录一次请求，刚超过上限时返回检测结果
+    pub fn observe(&mut self, request: &SipRequest) -> Option<Detection> {
+        let limit = *self.limits.get(&request.method)?;
+        let now = Instant::now();
+        let (since, count) = self
+            .counters
+            .entry((request.source_ip, request.method.clone()))
+            .or_insert((now, 0));
+        if now.duration_since(*since) >= self.window {
+            *since = now;
+            *count = 0;
+        }
+        *count += 1;
+        (*count == limit.saturating_add(1))
+            .then(|| Detection::from_request(request, &format!("{}_FLOOD", request.method)))
+    }
+
+    /// 清理过期的计数窗口
+    pub fn cleanup(&mut self) {
+        let window = self.window;
+        self.counters
+            .retain(|_, (since, _)| since.elapsed() < window);
+    }
+}
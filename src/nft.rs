@@ -0,0 +1,335 @@
+use crate::firewall::FirewallBackend;
+use log::{debug, info};
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Output, Stdio};
+
+/// 本工具使用的 nftables 表（inet 族，同时处理 IPv4 和 IPv6）
+const TABLE: &str = "uablock";
+const SET_V4: &str = "banned4";
+const SET_V6: &str = "banned6";
+
+/// 执行 nft 命令的方式
+trait NftRunner: Send + Sync {
+    /// 执行 `nft <args>` 并等待它结束，`stdin` 为写入标准输入的内容
+    fn output(&self, args: &[&str], stdin: Option<&str>) -> std::io::Result<Output>;
+}
+
+/// 执行系统中的 nft 命令
+struct SystemNft;
+
+impl NftRunner for SystemNft {
+    fn output(&self, args: &[&str], stdin: Option<&str>) -> std::io::Result<Output> {
+        let Some(input) = stdin else {
+            return Command::new("nft").args(args).output();
+        };
+        let mut child = Command::new("nft")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("无法写入 nft 标准输入"))?
+            .write_all(input.as_bytes())?;
+        child.wait_with_output()
+    }
+}
+
+/// nftables 后端：封禁的 IP 放在独立表的集合中，由一条规则统一丢弃
+///
+/// 只依赖 `nft` 命令，不需要 iptables。重启后集合中的封禁保留，规则按当前端口重建。
+pub struct NftManager {
+    block_port: Option<u16>,
+    runner: Box<dyn NftRunner>,
+}
+
+impl NftManager {
+    /// 创建表、集合和规则（已存在时保留集合内容）
+    pub fn new(block_port: Option<u16>) -> Result<Self, String> {
+        let manager = Self {
+            block_port,
+            runner: Box::new(SystemNft),
+        };
+        manager.setup()?;
+        info!(
+            "nftables 后端已就绪（表 inet {}，端口 {}）",
+            TABLE,
+            block_port.map_or("全部".to_string(), |p| p.to_string())
+        );
+        Ok(manager)
+    }
+
+    fn setup(&self) -> Result<(), String> {
+        let port = self
+            .block_port
+            .map(|p| format!("udp dport {} ", p))
+            .unwrap_or_default();
+        let script = format!(
+            "add table inet {table}\n\
+             add set inet {table} {v4} {{ type ipv4_addr; }}\n\
+             add set inet {table} {v6} {{ type ipv6_addr; }}\n\
+             add chain inet {table} input {{ type filter hook input priority filter - 1; policy accept; }}\n\
+             flush chain inet {table} input\n\
+             add rule inet {table} input {port}ip saddr @{v4} drop\n\
+             add rule inet {table} input {port}ip6 saddr @{v6} drop\n",
+            table = TABLE,
+            v4 = SET_V4,
+            v6 = SET_V6,
+            port = port,
+        );
+
+        let output = self
+            .runner
+            .output(&["-f", "-"], Some(&script))
+            .map_err(|e| format!("执行 nft 命令失败: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "创建 nftables 规则失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_for(ip: &IpAddr) -> &'static str {
+        match ip {
+            IpAddr::V4(_) => SET_V4,
+            IpAddr::V6(_) => SET_V6,
+        }
+    }
+
+    /// 对集合执行 `nft <action> element`
+    fn element(&self, action: &str, ip: &IpAddr) -> Result<Output, String> {
+        let element = format!("{{ {} }}", ip);
+        let args = [
+            action,
+            "element",
+            "inet",
+            TABLE,
+            Self::set_for(ip),
+            &element,
+        ];
+        debug!("执行 nft 命令: nft {}", args.join(" "));
+        self.runner
+            .output(&args, None)
+            .map_err(|e| format!("执行 nft 命令失败: {}", e))
+    }
+
+    /// 解析 `nft -j list set` 输出中的集合元素
+    fn parse_elements(json: &str) -> Result<Vec<IpAddr>, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("解析 nft 输出失败: {}", e))?;
+        let mut ips = Vec::new();
+        let objects = value["nftables"].as_array().cloned().unwrap_or_default();
+        for object in objects {
+            let Some(elements) = object["set"]["elem"].as_array() else {
+                continue;
+            };
+            for element in elements {
+                // 普通元素是字符串；带超时等属性时为 {"elem": {"val": ...}}
+                let text = element.as_str().or_else(|| element["elem"]["val"].as_str());
+                if let Some(ip) = text.and_then(|t| t.parse().ok()) {
+                    ips.push(ip);
+                }
+            }
+        }
+        Ok(ips)
+    }
+}
+
+impl FirewallBackend for NftManager {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        let output = self.element("add", ip)?;
+        if !output.status.success() {
+            return Err(format!(
+                "nft 添加封禁失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("成功封禁 IP: {}", ip);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        if !self.is_blocked(ip) {
+            return Ok(());
+        }
+        let output = self.element("delete", ip)?;
+        if !output.status.success() {
+            return Err(format!(
+                "nft 解除封禁失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        info!("成功解封 IP: {}", ip);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        matches!(self.element("get", ip), Ok(output) if output.status.success())
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let mut ips = Vec::new();
+        for set in [SET_V4, SET_V6] {
+            let output = self
+                .runner
+                .output(&["-j", "list", "set", "inet", TABLE, set], None)
+                .map_err(|e| format!("执行 nft 命令失败: {}", e))?;
+            if !output.status.success() {
+                return Err(format!(
+                    "获取 nftables 集合失败: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            ips.extend(Self::parse_elements(&String::from_utf8_lossy(
+                &output.stdout,
+            ))?);
+        }
+        Ok(ips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::sync::{Arc, Mutex};
+
+    /// 按顺序返回预设的退出码和 stdout（用完后都成功且无输出），记录执行的参数和标准输入
+    #[derive(Default)]
+    struct Script {
+        results: Mutex<VecDeque<(i32, &'static str)>>,
+        calls: Mutex<Vec<(String, Option<String>)>>,
+    }
+
+    impl NftRunner for Arc<Script> {
+        fn output(&self, args: &[&str], stdin: Option<&str>) -> std::io::Result<Output> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((args.join(" "), stdin.map(str::to_string)));
+            let (code, stdout) = self.results.lock().unwrap().pop_front().unwrap_or((0, ""));
+            Ok(Output {
+                status: ExitStatus::from_raw(code << 8),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: if code == 0 {
+                    Vec::new()
+                } else {
+                    b"Error".to_vec()
+                },
+            })
+        }
+    }
+
+    fn scripted(
+        block_port: Option<u16>,
+        results: &[(i32, &'static str)],
+    ) -> (NftManager, Arc<Script>) {
+        let script = Arc::new(Script::default());
+        script
+            .results
+            .lock()
+            .unwrap()
+            .extend(results.iter().copied());
+        let manager = NftManager {
+            block_port,
+            runner: Box::new(script.clone()),
+        };
+        (manager, script)
+    }
+
+    fn args(script: &Script) -> Vec<String> {
+        script
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(args, _)| args.clone())
+            .collect()
+    }
+
+    #[test]
+    fn setup_feeds_the_ruleset_on_stdin() {
+        let (manager, script) = scripted(Some(5060), &[]);
+        manager.setup().unwrap();
+        let calls = script.calls.lock().unwrap();
+        assert_eq!(calls[0].0, "-f -");
+        let ruleset = calls[0].1.as_deref().unwrap();
+        assert!(
+            ruleset.contains("add rule inet uablock input udp dport 5060 ip saddr @banned4 drop")
+        );
+        assert!(
+            ruleset.contains("add rule inet uablock input udp dport 5060 ip6 saddr @banned6 drop")
+        );
+
+        let (manager, script) = scripted(None, &[(1, "")]);
+        assert!(manager.setup().is_err());
+        let calls = script.calls.lock().unwrap();
+        assert!(calls[0]
+            .1
+            .as_deref()
+            .unwrap()
+            .contains("input ip saddr @banned4 drop"));
+    }
+
+    #[test]
+    fn ipv4_and_ipv6_addresses_use_their_own_set() {
+        let (manager, script) = scripted(None, &[]);
+        manager.block_ip(&"10.0.0.1".parse().unwrap()).unwrap();
+        manager.block_ip(&"2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(
+            args(&script),
+            [
+                "add element inet uablock banned4 { 10.0.0.1 }",
+                "add element inet uablock banned6 { 2001:db8::1 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn unblock_deletes_only_present_elements() {
+        // 第一次 get 失败（不在集合中），第二次成功
+        let (manager, script) = scripted(None, &[(1, ""), (0, "")]);
+        manager.unblock_ip(&"10.0.0.1".parse().unwrap()).unwrap();
+        manager.unblock_ip(&"2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(
+            args(&script),
+            [
+                "get element inet uablock banned4 { 10.0.0.1 }",
+                "get element inet uablock banned6 { 2001:db8::1 }",
+                "delete element inet uablock banned6 { 2001:db8::1 }",
+            ]
+        );
+
+        let (manager, _) = scripted(None, &[(1, "")]);
+        assert!(manager.block_ip(&"10.0.0.1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn list_blocked_reads_both_sets() {
+        let v4 = r#"{"nftables": [{"set": {"name": "banned4", "elem": ["10.0.0.1", {"elem": {"val": "10.0.0.2", "timeout": 60}}]}}]}"#;
+        let v6 = r#"{"nftables": [{"set": {"name": "banned6", "elem": ["2001:db8::1"]}}]}"#;
+        let (manager, script) = scripted(None, &[(0, v4), (0, v6)]);
+        let ips = manager.list_blocked().unwrap();
+        assert_eq!(
+            ips,
+            ["10.0.0.1", "10.0.0.2", "2001:db8::1"].map(|ip| ip.parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(
+            args(&script),
+            [
+                "-j list set inet uablock banned4",
+                "-j list set inet uablock banned6",
+            ]
+        );
+
+        let (manager, _) = scripted(None, &[(1, "")]);
+        assert!(manager.list_blocked().is_err());
+    }
+}
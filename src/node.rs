@@ -0,0 +1,13 @@
+/// 本节点 ID：优先 `UABLOCK_NODE_ID`，其次主机名，最后使用进程号
+pub fn node_id() -> String {
+    if let Ok(id) = std::env::var("UABLOCK_NODE_ID") {
+        if !id.is_empty() {
+            return id;
+        }
+    }
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| format!("uablock-{}", std::process::id()))
+}
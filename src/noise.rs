@@ -0,0 +1,54 @@
+/// STUN 报文头中的 magic cookie（RFC 5389）
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+/// STUN 报文头长度
+const STUN_HEADER_LEN: usize = 20;
+/// RTP 固定报文头长度
+const RTP_HEADER_LEN: usize = 12;
+
+/// SIP 端口上收到的非 SIP 数据
+///
+/// 5060 端口除了 SIP 还会收到 NAT 探测用的 STUN、NAT 映射错误而错发过来的 RTP/RTCP，
+/// 以及扫描器发送的随机二进制数据。文本数据（包括 NAT 保活用的空行）不属于这里的分类。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// STUN 报文（前两位为 0，第 4–7 字节为 magic cookie）
+    Stun,
+    /// RTP/RTCP 报文（版本号为 2）
+    Rtp,
+    /// 无法识别的二进制数据
+    Binary,
+}
+
+impl NoiseKind {
+    /// 机器可读的分类代码
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoiseKind::Stun => "STUN",
+            NoiseKind::Rtp => "RTP",
+            NoiseKind::Binary => "BINARY_JUNK",
+        }
+    }
+}
+
+/// 按协议特征对非 SIP 数据分类；文本数据返回 None
+pub fn classify(data: &[u8]) -> Option<NoiseKind> {
+    if data.len() >= STUN_HEADER_LEN
+        && data[0] & 0xc0 == 0
+        && data[4..8] == STUN_MAGIC_COOKIE
+        && usize::from(u16::from_be_bytes([data[2], data[3]])) + STUN_HEADER_LEN == data.len()
+    {
+        return Some(NoiseKind::Stun);
+    }
+    let is_text = std::str::from_utf8(data).is_ok_and(|text| {
+        !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\r' | '\n' | '\t'))
+    });
+    if is_text {
+        return None;
+    }
+    if data.len() >= RTP_HEADER_LEN && data[0] >> 6 == 2 {
+        return Some(NoiseKind::Rtp);
+    }
+    Some(NoiseKind::Binary)
+}
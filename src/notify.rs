@@ -0,0 +1,359 @@
+use crate::events::{Event, EventBus, EventKind, QueueReceiver};
+use crate::tenants::Tenants;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// 通知请求超时
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 默认的 sendmail 路径
+const DEFAULT_SENDMAIL: &str = "/usr/sbin/sendmail";
+
+/// 一个通知目标
+///
+/// 配置中写作字符串：`https://...`（webhook，POST 事件 JSON）、`telegram:<bot token>@<chat id>`、
+/// `mailto:<地址>`（经本机 sendmail 发送）。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyTarget {
+    Webhook(String),
+    Telegram { bot_token: String, chat_id: String },
+    Email(String),
+}
+
+impl NotifyTarget {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return Ok(Self::Webhook(spec.to_string()));
+        }
+        if let Some(rest) = spec.strip_prefix("telegram:") {
+            return match rest.rsplit_once('@') {
+                Some((bot_token, chat_id)) if !bot_token.is_empty() && !chat_id.is_empty() => {
+                    Ok(Self::Telegram {
+                        bot_token: bot_token.to_string(),
+                        chat_id: chat_id.to_string(),
+                    })
+                }
+                _ => Err(format!(
+                    "Telegram 通知目标应为 telegram:<bot token>@<chat id>: {}",
+                    spec
+                )),
+            };
+        }
+        if let Some(address) = spec.strip_prefix("mailto:") {
+            if address.contains('@') && !address.contains(char::is_whitespace) {
+                return Ok(Self::Email(address.to_string()));
+            }
+        }
+        Err(format!("无法识别的通知目标: {}", spec))
+    }
+
+    /// 是否接收该类事件：webhook 接收全部事件，Telegram 和邮件只接收封禁和告警
+    pub fn wants(&self, kind: EventKind) -> bool {
+        match self {
+            Self::Webhook(_) => true,
+            Self::Telegram { .. } | Self::Email(_) => {
+                matches!(kind, EventKind::Ban | EventKind::Alert)
+            }
+        }
+    }
+
+    /// 用于日志的描述（不包含 bot token）
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Webhook(url) => url.clone(),
+            Self::Telegram { chat_id, .. } => format!("telegram:{}", chat_id),
+            Self::Email(address) => format!("mailto:{}", address),
+        }
+    }
+}
+
+/// 解析逗号分隔的通知目标
+pub fn parse_targets(list: &str) -> Result<Vec<NotifyTarget>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(NotifyTarget::parse)
+        .collect()
+}
+
+/// 按租户路由事件通知
+///
+/// 带租户名称的事件只发给该租户的通知目标（租户没有配置时发给默认目标），
+/// 不属于任何租户的事件发给默认目标（`UABLOCK_NOTIFY`）。
+pub struct Notifier {
+    /// 默认目标
+    default: Vec<NotifyTarget>,
+    /// 租户名称 → 目标
+    tenants: HashMap<String, Vec<NotifyTarget>>,
+    /// 出现在 Telegram/邮件正文中的实例标识（抓包接口）
+    label: String,
+    sendmail: String,
+    agent: ureq::Agent,
+}
+
+impl Notifier {
+    pub fn new(default: Vec<NotifyTarget>) -> Self {
+        Self {
+            default,
+            tenants: HashMap::new(),
+            label: String::new(),
+            sendmail: DEFAULT_SENDMAIL.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(NOTIFY_TIMEOUT).build(),
+        }
+    }
+
+    /// 该租户的事件改发给这些目标
+    pub fn with_tenant(mut self, name: &str, targets: Vec<NotifyTarget>) -> Self {
+        if !targets.is_empty() {
+            self.tenants.insert(name.to_string(), targets);
+        }
+        self
+    }
+
+    /// Telegram/邮件正文中标明的实例（每个抓包接口运行一个实例时用于区分接口）
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// 从环境变量和租户配置创建，没有任何通知目标时返回 None
+    ///
+    /// - `UABLOCK_NOTIFY`：默认通知目标，逗号分隔
+    /// - `UABLOCK_SENDMAIL`：发送邮件使用的 sendmail 程序（默认 `/usr/sbin/sendmail`）
+    pub fn from_env(label: &str, tenants: Option<&Tenants>) -> Result<Option<Self>, String> {
+        let default = parse_targets(&std::env::var("UABLOCK_NOTIFY").unwrap_or_default())
+            .map_err(|e| format!("UABLOCK_NOTIFY 无效: {}", e))?;
+        let mut notifier = Self::new(default).with_label(label);
+        if let Some(tenants) = tenants {
+            for (name, targets) in tenants.notify_targets() {
+                notifier = notifier.with_tenant(name, targets.to_vec());
+            }
+        }
+        if notifier.default.is_empty() && notifier.tenants.is_empty() {
+            return Ok(None);
+        }
+        if let Ok(sendmail) = std::env::var("UABLOCK_SENDMAIL") {
+            if !sendmail.is_empty() {
+                notifier.sendmail = sendmail;
+            }
+        }
+        info!(
+            "事件通知已启用: 默认 {} 个目标，{} 个租户有自己的目标",
+            notifier.default.len(),
+            notifier.tenants.len()
+        );
+        Ok(Some(notifier))
+    }
+
+    /// 事件应发往的目标
+    pub fn targets_for(&self, event: &Event) -> Vec<&NotifyTarget> {
+        event
+            .tenant
+            .as_ref()
+            .and_then(|tenant| self.tenants.get(tenant))
+            .unwrap_or(&self.default)
+            .iter()
+            .filter(|target| target.wants(event.kind))
+            .collect()
+    }
+
+    /// 订阅事件并启动推送线程
+    pub fn start(self, events: &EventBus) -> Result<(), String> {
+        let (tx, rx) = events.queue();
+        events.subscribe(move |event| tx.send(event.clone()));
+        std::thread::Builder::new()
+            .name("notify".to_string())
+            .spawn(move || self.run(rx))
+            .map_err(|e| format!("启动通知线程失败: {}", e))?;
+        Ok(())
+    }
+
+    fn run(self, rx: QueueReceiver<Event>) {
+        for event in rx {
+            for target in self.targets_for(&event) {
+                match self.send(target, &event) {
+                    Ok(()) => debug!(
+                        "【通知】{:?} {} 已通知 {}",
+                        event.kind,
+                        event.ip,
+                        target.describe()
+                    ),
+                    Err(e) => error!("【通知】通知 {} 失败: {}", target.describe(), e),
+                }
+            }
+        }
+    }
+
+    fn send(&self, target: &NotifyTarget, event: &Event) -> Result<(), String> {
+        match target {
+            NotifyTarget::Webhook(url) => self
+                .agent
+                .post(url)
+                .send_json(event)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            NotifyTarget::Telegram { bot_token, chat_id } => self
+                .agent
+                .post(&format!(
+                    "https://api.telegram.org/bot{}/sendMessage",
+                    bot_token
+                ))
+                .send_json(serde_json::json!({
+                    "chat_id": chat_id,
+                    "text": self.message(event),
+                }))
+                .map(|_| ())
+                // 错误信息中的 URL 包含 bot token，不写入日志
+                .map_err(|e| match e {
+                    ureq::Error::Status(code, _) => format!("HTTP {}", code),
+                    ureq::Error::Transport(transport) => transport.kind().to_string(),
+                }),
+            NotifyTarget::Email(address) => self.mail(address, event),
+        }
+    }
+
+    /// 经 sendmail 发送邮件（主题只用 ASCII，正文为 UTF-8）
+    fn mail(&self, address: &str, event: &Event) -> Result<(), String> {
+        let mut child = Command::new(&self.sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("无法执行 {}: {}", self.sendmail, e))?;
+        let mail = format!(
+            "To: {}\r\nSubject: uablock {:?} {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            address,
+            event.kind,
+            event.ip,
+            self.message(event)
+        );
+        child
+            .stdin
+            .take()
+            .ok_or("sendmail 标准输入不可用")?
+            .write_all(mail.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let status = child.wait().map_err(|e| e.to_string())?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{} 退出状态 {}", self.sendmail, status))
+        }
+    }
+
+    /// 给人看的事件描述
+    pub fn message(&self, event: &Event) -> String {
+        let action = match event.kind {
+            EventKind::Detection => "检测到",
+            EventKind::Ban => "已封禁",
+            EventKind::Unban => "已解封",
+            EventKind::Alert => "仅告警",
+        };
+        let mut text = String::from("【uablock】");
+        if !self.label.is_empty() {
+            text.push_str(&format!("[{}] ", self.label));
+        }
+        text.push_str(&format!("{} {}，原因 {}", action, event.ip, event.reason));
+        if let Some(tenant) = &event.tenant {
+            text.push_str(&format!("，租户 {}", tenant));
+        }
+        if let Some(port) = event.dest_port {
+            text.push_str(&format!("，端口 {}", port));
+        }
+        if let Some(geo) = &event.geo {
+            text.push_str(&format!("，位置 {}", geo.summary()));
+        }
+        if !event.user_agent.is_empty() {
+            text.push_str(&format!("，UA: {}", event.user_agent));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn notifications_are_routed_to_the_tenant_of_the_event() {
+        let tenants = Tenants::parse(
+            r#"
+        [[tenant]]
+        name = "acme"
+        destinations = ["192.0.2.1"]
+        whitelist = ["acme-pbx"]
+        notify = ["https://hooks.acme.example/uablock", "telegram:123456:ABC-def@-1001234"]
+
+        [[tenant]]
+        name = "globex"
+        destinations = ["192.0.2.20"]
+        whitelist = ["globex-phone"]
+        "#,
+        )
+        .unwrap();
+        assert!(Tenants::parse(
+            r#"
+        [[tenant]]
+        name = "bad"
+        destinations = ["192.0.2.1"]
+        whitelist = []
+        notify = ["telegram:no-chat-id"]
+        "#
+        )
+        .is_err());
+
+        let mut notifier = Notifier::new(parse_targets("mailto:noc@example.com").unwrap());
+        for (name, targets) in tenants.notify_targets() {
+            notifier = notifier.with_tenant(name, targets.to_vec());
+        }
+        let event = |kind, tenant: Option<&str>| Event {
+            tenant: tenant.map(str::to_string),
+            ..Event::new(
+                kind,
+                ip(SCANNER),
+                "friendly-scanner",
+                "UA_NOT_ALLOWED",
+                "engine",
+            )
+        };
+        let acme_webhook = NotifyTarget::Webhook("https://hooks.acme.example/uablock".to_string());
+        let acme_telegram = NotifyTarget::Telegram {
+            bot_token: "123456:ABC-def".to_string(),
+            chat_id: "-1001234".to_string(),
+        };
+        let noc = NotifyTarget::Email("noc@example.com".to_string());
+
+        // acme 的事件只发给 acme；Telegram 和邮件不接收检测事件
+        assert_eq!(
+            notifier.targets_for(&event(EventKind::Ban, Some("acme"))),
+            vec![&acme_webhook, &acme_telegram]
+        );
+        assert_eq!(
+            notifier.targets_for(&event(EventKind::Detection, Some("acme"))),
+            vec![&acme_webhook]
+        );
+        // 没有配置通知的租户和不属于任何租户的事件发给默认目标
+        assert_eq!(
+            notifier.targets_for(&event(EventKind::Ban, Some("globex"))),
+            vec![&noc]
+        );
+        assert_eq!(
+            notifier.targets_for(&event(EventKind::Alert, None)),
+            vec![&noc]
+        );
+        assert!(notifier
+            .targets_for(&event(EventKind::Unban, None))
+            .is_empty());
+    }
+}
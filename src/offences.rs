@@ -0,0 +1,146 @@
+use crate::bans::parse_duration;
+use crate::state_file::unix_now;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 默认的违规记录保留时长：超过该时长没有再次违规的来源重新从第一次违规算起
+const DEFAULT_WINDOW: Duration = Duration::from_secs(30 * 86400);
+
+/// 一个来源的违规记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffenceRecord {
+    pub ip: IpAddr,
+    /// 违规（被检测封禁）次数
+    pub count: u32,
+    /// 最近一次违规的时间（Unix 秒）
+    pub last_at: u64,
+}
+
+/// 重复违规的封禁时长升级
+///
+/// 每次检测触发新的封禁记一次违规，按违规次数依次使用 `steps` 中的封禁时长（None 表示永久），
+/// 超出步骤数的沿用最后一步；例如 `10m,1h,permanent` 表示第一次封禁 10 分钟，第二次 1 小时，
+/// 之后永久封禁。超过 `window` 没有再次违规的来源重新从第一次算起。
+pub struct Escalation {
+    steps: Vec<Option<Duration>>,
+    window: Duration,
+    offences: Mutex<HashMap<IpAddr, OffenceRecord>>,
+}
+
+impl Escalation {
+    pub fn new(steps: Vec<Option<Duration>>, window: Duration) -> Self {
+        Self {
+            steps,
+            window,
+            offences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_BAN_ESCALATION` 时返回 None
+    ///
+    /// - `UABLOCK_BAN_ESCALATION`：逗号分隔的各次违规的封禁时长，`permanent` 表示永久，
+    ///   如 `10m,1h,permanent`
+    /// - `UABLOCK_BAN_ESCALATION_WINDOW`：违规记录保留时长（默认 `30d`）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let steps = match std::env::var("UABLOCK_BAN_ESCALATION") {
+            Ok(value) if !value.is_empty() => {
+                parse_steps(&value).map_err(|e| format!("UABLOCK_BAN_ESCALATION 无效: {}", e))?
+            }
+            _ => return Ok(None),
+        };
+        let window = match std::env::var("UABLOCK_BAN_ESCALATION_WINDOW") {
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|window| !window.is_zero())
+                .ok_or_else(|| format!("UABLOCK_BAN_ESCALATION_WINDOW 无效: {}", value))?,
+            _ => DEFAULT_WINDOW,
+        };
+        info!(
+            "封禁升级已启用: {}，违规记录保留 {} 秒",
+            describe(&steps),
+            window.as_secs()
+        );
+        Ok(Some(Self::new(steps, window)))
+    }
+
+    /// 记一次违规，返回违规次数和本次的封禁时长（None 表示永久）
+    pub fn record(&self, ip: IpAddr) -> (u32, Option<Duration>) {
+        let now = unix_now();
+        let mut offences = self.offences.lock().unwrap();
+        let offence = offences.entry(ip).or_insert(OffenceRecord {
+            ip,
+            count: 0,
+            last_at: now,
+        });
+        if now.saturating_sub(offence.last_at) > self.window.as_secs() {
+            offence.count = 0;
+        }
+        offence.count += 1;
+        offence.last_at = now;
+        let step = (offence.count as usize).min(self.steps.len()) - 1;
+        (offence.count, self.steps[step])
+    }
+
+    /// 来源在保留时长内的违规次数
+    pub fn count(&self, ip: &IpAddr) -> u32 {
+        let now = unix_now();
+        self.offences
+            .lock()
+            .unwrap()
+            .get(ip)
+            .filter(|offence| now.saturating_sub(offence.last_at) <= self.window.as_secs())
+            .map_or(0, |offence| offence.count)
+    }
+
+    /// 清理超过保留时长的违规记录
+    pub fn cleanup(&self) {
+        let now = unix_now();
+        let window = self.window.as_secs();
+        self.offences
+            .lock()
+            .unwrap()
+            .retain(|_, offence| now.saturating_sub(offence.last_at) <= window);
+    }
+
+    /// 全部违规记录（用于保存状态）
+    pub fn records(&self) -> Vec<OffenceRecord> {
+        self.offences.lock().unwrap().values().cloned().collect()
+    }
+
+    /// 恢复保存的违规记录，已超过保留时长的忽略
+    pub fn restore(&self, records: &[OffenceRecord]) {
+        let now = unix_now();
+        let mut offences = self.offences.lock().unwrap();
+        for record in records {
+            if now.saturating_sub(record.last_at) <= self.window.as_secs() {
+                offences.insert(record.ip, record.clone());
+            }
+        }
+    }
+}
+
+/// 解析逗号分隔的封禁时长，`permanent` 表示永久
+pub fn parse_steps(value: &str) -> Result<Vec<Option<Duration>>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .map(|step| match step {
+            "permanent" => Ok(None),
+            _ => parse_duration(step)
+                .filter(|duration| !duration.is_zero())
+                .map(Some)
+                .ok_or_else(|| format!("{:?} 不是有效的时长", step)),
+        })
+        .collect()
+}
+
+fn describe(steps: &[Option<Duration>]) -> String {
+    steps
+        .iter()
+        .map(|step| step.map_or("永久".to_string(), |d| format!("{} 秒", d.as_secs())))
+        .collect::<Vec<_>>()
+        .join(" → ")
+}
@@ -1,128 +1,884 @@
-use log::{debug, error};
+use crate::sip_parser::ParsedPayload;
+use crate::stats::Stats;
+use log::{debug, error, info, warn};
+#[cfg(feature = "pcap")]
 use pcap::{Active, Capture, Device};
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// 刷新抓包丢包统计的间隔
+const DROPS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 捕获到的 UDP 数据包
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    /// 网络层真实源 IP
+    pub source_ip: IpAddr,
+    /// 目标 IP（本机被访问的地址）
+    pub dest_ip: IpAddr,
+    /// UDP 源端口
+    pub source_port: u16,
+    /// UDP 目标端口
+    pub dest_port: u16,
+    /// UDP 负载
+    pub payload: Vec<u8>,
+    /// 读取线程收到数据包的时刻（用于统计封禁生效延迟，构造的数据包为 None）
+    pub captured_at: Option<Instant>,
+    /// 解析线程预先完成的解析结果（见 [`ParsePool`](crate::parse_pool::ParsePool)），None 时由检测任务解析
+    pub parsed: Option<ParsedPayload>,
+}
+
+/// 数据包来源：检测任务的输入
+///
+/// 内置实现为基于 libpcap 的 `PacketCapture`（`pcap` 特性）、Linux 上不依赖 libpcap 的
+/// `AfPacketSource` 和基于普通 UDP 套接字的 [`UdpSource`](crate::udp_source::UdpSource)；测试中可使用 [`MemorySource`](crate::testing::MemorySource) 注入构造好的数据包。
+pub trait PacketSource: Send {
+    /// 获取下一个数据包；超时或不是可处理的 UDP 数据包时返回 `Ok(None)`
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String>;
+
+    /// 自开始读取以来丢弃的数据包总数（来源不支持统计时为 0）
+    fn dropped(&mut self) -> Result<u64, String> {
+        Ok(0)
+    }
+
+    /// 在运行中更换只接收的目标端口（不重新打开接口）
+    fn set_ports(&mut self, _ports: &[u16]) -> Result<(), String> {
+        Err("该数据包来源不支持在运行中更换端口".to_string())
+    }
+}
+
+impl<S: PacketSource + ?Sized> PacketSource for Box<S> {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+        (**self).next_packet()
+    }
+
+    fn dropped(&mut self) -> Result<u64, String> {
+        (**self).dropped()
+    }
+
+    fn set_ports(&mut self, ports: &[u16]) -> Result<(), String> {
+        (**self).set_ports(ports)
+    }
+}
+
+/// 受保护的 SIP 端口：抓包只接收发往这些端口的数据包
+///
+/// 可以在运行中经 HTTP API 修改，或在收到 SIGHUP 时从端口文件重新读取；读取线程在下一次读取前
+/// 把新的端口应用到正在使用的抓包句柄上（libpcap 重新编译 BPF 过滤器），不需要重启进程，
+/// 封禁表和检测状态都不受影响。
+pub struct CapturePorts {
+    ports: Mutex<Vec<u16>>,
+    /// 每次修改加一，读取线程据此判断是否需要重新应用
+    version: AtomicU64,
+    /// 端口文件（`UABLOCK_PORTS_FILE`）
+    file: Option<PathBuf>,
+}
+
+impl CapturePorts {
+    pub fn new(ports: Vec<u16>) -> Self {
+        Self {
+            ports: Mutex::new(normalize_ports(ports)),
+            version: AtomicU64::new(0),
+            file: None,
+        }
+    }
+
+    /// 从环境变量创建：设置了 `UABLOCK_PORTS_FILE` 时从该文件读取端口，否则只有 `default_port`
+    pub fn from_env(default_port: u16) -> Result<Self, String> {
+        match std::env::var("UABLOCK_PORTS_FILE") {
+            Ok(path) if !path.is_empty() => {
+                let path = PathBuf::from(path);
+                let ports = read_ports_file(&path)?;
+                info!("受保护端口 {:?}（来自 {}）", ports, path.display());
+                Ok(Self {
+                    file: Some(path),
+                    ..Self::new(ports)
+                })
+            }
+            _ => Ok(Self::new(vec![default_port])),
+        }
+    }
+
+    /// 当前的端口（升序）
+    pub fn ports(&self) -> Vec<u16> {
+        self.ports.lock().unwrap().clone()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// 更换端口，端口与当前相同时返回 false
+    pub fn set(&self, ports: Vec<u16>) -> Result<bool, String> {
+        let ports = normalize_ports(ports);
+        if ports.is_empty() {
+            return Err("受保护端口不能为空".to_string());
+        }
+        let mut current = self.ports.lock().unwrap();
+        if *current == ports {
+            return Ok(false);
+        }
+        *current = ports;
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// 重新读取端口文件（未配置端口文件时不做任何事），端口有变化时返回 true
+    pub fn reload(&self) -> Result<bool, String> {
+        match &self.file {
+            Some(path) => self.set(read_ports_file(path)?),
+            None => Ok(false),
+        }
+    }
+}
+
+/// 排序并去除重复和为 0 的端口
+fn normalize_ports(mut ports: Vec<u16>) -> Vec<u16> {
+    ports.retain(|&port| port != 0);
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// 解析端口列表：以逗号、空白或换行分隔，`#` 之后为注释
+pub fn parse_ports(text: &str) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for item in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if item.is_empty() {
+                continue;
+            }
+            match item.parse::<u16>() {
+                Ok(port) if port != 0 => ports.push(port),
+                _ => return Err(format!("端口无效: {}", item)),
+            }
+        }
+    }
+    if ports.is_empty() {
+        return Err("没有配置任何端口".to_string());
+    }
+    Ok(normalize_ports(ports))
+}
+
+/// 解析接口列表：以逗号分隔，去掉重复的接口
+pub fn parse_interfaces(text: &str) -> Result<Vec<String>, String> {
+    let mut interfaces: Vec<String> = Vec::new();
+    for item in text
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        if !interfaces.iter().any(|interface| interface == item) {
+            interfaces.push(item.to_string());
+        }
+    }
+    if interfaces.is_empty() {
+        return Err(format!("没有指定网络接口: {:?}", text));
+    }
+    Ok(interfaces)
+}
+
+fn read_ports_file(path: &Path) -> Result<Vec<u16>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("读取端口文件 {} 失败: {}", path.display(), e))?;
+    parse_ports(&text).map_err(|e| format!("端口文件 {} 无效: {}", path.display(), e))
+}
+
+/// 只接收发往这些端口的入站 UDP 数据包的 BPF 过滤表达式
+pub fn filter_expression(ports: &[u16]) -> String {
+    let ports: Vec<String> = ports
+        .iter()
+        .map(|port| format!("dst port {}", port))
+        .collect();
+    format!("udp and ({})", ports.join(" or "))
+}
+
+/// 受保护端口的过滤表达式，再与用户指定的表达式（`UABLOCK_CAPTURE_FILTER`）同时满足
+pub fn capture_filter(ports: &[u16], extra: Option<&str>) -> String {
+    match extra {
+        Some(extra) => format!("({}) and ({})", filter_expression(ports), extra),
+        None => filter_expression(ports),
+    }
+}
 
 /// 数据包捕获器
+#[cfg(feature = "pcap")]
 pub struct PacketCapture {
     capture: Option<Capture<Active>>,
+    /// 链路层头格式（`any` 伪接口为 Linux cooked 头）
+    link: LinkLayer,
+    /// 用户指定的附加过滤表达式
+    filter: Option<String>,
 }
 
+#[cfg(feature = "pcap")]
 impl PacketCapture {
     /// 打开网络接口进行抓包
     /// port: 目标端口，只捕获目标端口为该端口的入站流量
     pub fn open(interface: &str, port: u16) -> Result<Self, String> {
-        let mut cap = Capture::from_device(interface)
+        Self::open_ports(interface, &[port])
+    }
+
+    /// 打开网络接口进行抓包，只捕获目标端口为 `ports` 之一的入站流量
+    pub fn open_ports(interface: &str, ports: &[u16]) -> Result<Self, String> {
+        Self::open_filtered(interface, ports, None)
+    }
+
+    /// 同 [`PacketCapture::open_ports`]，并且只捕获同时满足 `filter`（BPF 表达式）的数据包；
+    /// 更换端口时附加的表达式保留
+    pub fn open_filtered(
+        interface: &str,
+        ports: &[u16],
+        filter: Option<&str>,
+    ) -> Result<Self, String> {
+        // any 伪接口不支持混杂模式
+        let cap = Capture::from_device(interface)
             .map_err(|e| format!("无法打开网络接口 {}: {}", interface, e))?
-            .promisc(true)
+            .promisc(interface != "any")
             .snaplen(65535)
             .timeout(1000)
             .open()
             .map_err(|e| format!("无法开始抓包: {}", e))?;
+        let link = LinkLayer::of(&cap);
+        debug!("接口 {} 的链路层: {:?}", interface, link);
 
         // 设置过滤器，只捕获目标端口为指定端口的 UDP 入站流量
         // dst port 确保只捕获入站流量（目标端口匹配）
-        let filter = format!("udp and dst port {}", port);
-        cap.filter(&filter, true)
-            .map_err(|e| format!("设置过滤器失败: {}", e))?;
+        let mut capture = Self {
+            capture: Some(cap),
+            link,
+            filter: filter.map(str::to_string),
+        };
+        capture.set_ports(ports)?;
+        Ok(capture)
+    }
 
-        Ok(Self { capture: Some(cap) })
+    /// 列出所有可用的网络接口
+    pub fn list_interfaces() -> Vec<String> {
+        match Device::list() {
+            Ok(devices) => devices.iter().map(|d| d.name.clone()).collect(),
+            Err(_) => vec![],
+        }
     }
+}
 
+#[cfg(feature = "pcap")]
+impl PacketSource for PacketCapture {
     /// 获取下一个数据包
-    pub fn next_packet(&mut self) -> Result<Option<(IpAddr, Vec<u8>)>, String> {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
         let cap = self.capture.as_mut().ok_or("捕获器未初始化")?;
 
         match cap.next_packet() {
-            Ok(packet) => {
-                // pcap 返回的数据可能包含以太网头（14字节），也可能直接从 IP 层开始
-                // 首先检查是否是 IP 数据包（IP 版本在第一个字节的高4位）
-                let data = &packet.data;
-
-                if data.len() < 20 {
-                    // 数据包太小，静默返回
-                    return Ok(None);
-                }
+            Ok(packet) => Ok(decode_frame(packet.data, self.link)),
+            Err(pcap::Error::TimeoutExpired) => {
+                // 超时是正常的，继续等待
+                Ok(None)
+            }
+            Err(e) => {
+                error!("抓包错误: {}", e);
+                Err(format!("抓包错误: {}", e))
+            }
+        }
+    }
+
+    /// 获取内核和网卡丢弃的数据包总数（自开始抓包以来）
+    fn dropped(&mut self) -> Result<u64, String> {
+        let cap = self.capture.as_mut().ok_or("捕获器未初始化")?;
+        let stat = cap
+            .stats()
+            .map_err(|e| format!("获取抓包统计失败: {}", e))?;
+        Ok(u64::from(stat.dropped) + u64::from(stat.if_dropped))
+    }
+
+    /// 重新编译 BPF 过滤器并应用到当前的抓包句柄
+    fn set_ports(&mut self, ports: &[u16]) -> Result<(), String> {
+        let cap = self.capture.as_mut().ok_or("捕获器未初始化")?;
+        let expression = capture_filter(ports, self.filter.as_deref());
+        cap.filter(&expression, true)
+            .map_err(|e| format!("设置过滤器 {:?} 失败: {}", expression, e))
+    }
+}
+
+#[cfg(feature = "pcap")]
+impl Drop for PacketCapture {
+    fn drop(&mut self) {
+        if self.capture.is_some() {
+            debug!("关闭数据包捕获器");
+        }
+    }
+}
+
+/// 在专用线程中阻塞读取数据包，经由通道交给异步检测任务
+///
+/// 通道满时读取线程阻塞等待（积压留在内核缓冲区）；接收端关闭后线程退出。
+/// 抓包丢包数定期写入 `stats.capture_drops`。
+pub fn spawn_reader<S: PacketSource + 'static>(
+    source: S,
+    tx: mpsc::Sender<CapturedPacket>,
+    stats: Arc<Stats>,
+) -> Result<(), String> {
+    spawn_reader_with_ports(source, tx, stats, None)
+}
+
+/// 同 [`spawn_reader`]，并在 `ports` 变化后把新的端口应用到数据包来源
+///
+/// 来源以 `ports` 当前的端口打开；之后每次读取前检查是否有变化，更换失败时记录错误并继续使用原来的端口。
+pub fn spawn_reader_with_ports<S: PacketSource + 'static>(
+    source: S,
+    tx: mpsc::Sender<CapturedPacket>,
+    stats: Arc<Stats>,
+    ports: Option<Arc<CapturePorts>>,
+) -> Result<(), String> {
+    spawn_named_reader("packet-capture".to_string(), source, tx, stats, ports)
+}
 
-                // 检查第一个字节，判断是否包含以太网头
-                // 以太网类型 0x0800 表示 IPv4，通常在字节 12-13（16位值）
-                // 如果前两个字节看起来像 MAC 地址（通常不会超过 0xFF），可能是以太网头
-                let ip_start_offset = if data.len() >= 14 {
-                    let ethertype = ((data[12] as u16) << 8) | (data[13] as u16);
-                    if ethertype == 0x0800 {
-                        // 包含以太网头，IP 头从第 14 字节开始
-                        14
-                    } else if (data[0] & 0xF0) == 0x40 {
-                        // 第一个字节的高4位是 0x4，表示 IPv4，没有以太网头
-                        0
-                    } else {
-                        // 尝试从第 14 字节开始（假设有以太网头）
-                        14
+/// 同 [`spawn_reader_with_ports`]，读取线程以接口命名（如 `cap-eth0`）
+///
+/// 多个接口同时抓包时可以在 `top -H` 等工具中区分各自的线程；Linux 的线程名最长 15 字节，
+/// 因此使用短前缀。
+pub fn spawn_interface_reader<S: PacketSource + 'static>(
+    interface: &str,
+    source: S,
+    tx: mpsc::Sender<CapturedPacket>,
+    stats: Arc<Stats>,
+    ports: Option<Arc<CapturePorts>>,
+) -> Result<(), String> {
+    spawn_named_reader(format!("cap-{}", interface), source, tx, stats, ports)
+}
+
+fn spawn_named_reader<S: PacketSource + 'static>(
+    name: String,
+    mut source: S,
+    tx: mpsc::Sender<CapturedPacket>,
+    stats: Arc<Stats>,
+    ports: Option<Arc<CapturePorts>>,
+) -> Result<(), String> {
+    let mut applied = ports.as_ref().map(|ports| ports.version());
+    std::thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            let mut timeouts: u64 = 0;
+            // 本来源已计入统计的丢包数（多个接口同时抓包时各自累加）
+            let mut reported_drops: u64 = 0;
+            let mut last_drops_refresh = Instant::now();
+            while !tx.is_closed() {
+                if let Some(ports) = &ports {
+                    let version = ports.version();
+                    if applied != Some(version) {
+                        let current = ports.ports();
+                        match source.set_ports(&current) {
+                            Ok(()) => info!("【抓包】过滤器已更新，受保护端口 {:?}", current),
+                            Err(e) => error!("【抓包】更换端口 {:?} 失败: {}", current, e),
+                        }
+                        applied = Some(version);
+                    }
+                }
+                if last_drops_refresh.elapsed() >= DROPS_REFRESH_INTERVAL {
+                    match source.dropped() {
+                        Ok(dropped) => {
+                            stats.capture_drops.fetch_add(
+                                dropped.saturating_sub(reported_drops),
+                                Ordering::Relaxed,
+                            );
+                            reported_drops = dropped;
+                        }
+                        Err(e) => warn!("{}", e),
                     }
-                } else if (data[0] & 0xF0) == 0x40 {
-                    // 数据包太小，但第一个字节看起来像 IPv4
-                    0
-                } else {
-                    // 尝试从第 0 字节开始
-                    0
-                };
-
-                if data.len() < ip_start_offset + 20 {
-                    // 数据包太小，静默返回
-                    return Ok(None);
+                    last_drops_refresh = Instant::now();
                 }
+                match source.next_packet() {
+                    Ok(Some(mut packet)) => {
+                        packet.captured_at.get_or_insert_with(Instant::now);
+                        if tx.blocking_send(packet).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        // 超时或无效数据包，继续
+                        // 每 1000 次超时输出一次日志，避免日志过多
+                        timeouts += 1;
+                        if timeouts.is_multiple_of(1000) {
+                            debug!("等待数据包中... (已等待 {} 次)", timeouts);
+                        }
+                    }
+                    Err(e) => {
+                        error!("抓包错误: {}", e);
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        })
+        .map(|_| ())
+        .map_err(|e| format!("启动抓包线程失败: {}", e))
+}
 
-                let ip_header = &data[ip_start_offset..];
+/// 数据包来源的链路层头格式
+///
+/// libpcap 抓包按句柄的链路层类型确定 IP 头的位置；AF_PACKET 等不知道链路层类型的来源使用
+/// [`LinkLayer::Auto`]。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkLayer {
+    /// 类型未知：以太网头或裸 IP 数据包，按内容判断
+    #[default]
+    Auto,
+    /// 以太网（`DLT_EN10MB`），14 字节的头，之后可能有 VLAN 标签
+    Ethernet,
+    /// 没有链路层头，直接是 IPv4/IPv6 数据包（`DLT_RAW`，tun 等接口）
+    Raw,
+    /// BSD 回环（`DLT_NULL`），4 字节的地址族，抓包主机的字节序
+    Null,
+    /// OpenBSD 回环（`DLT_LOOP`），4 字节的地址族，网络字节序
+    Loop,
+    /// Linux cooked capture（`any` 伪接口），16 字节的 SLL 头
+    LinuxSll,
+    /// Linux cooked capture v2（较新的 libpcap 在 `any` 上使用），20 字节的 SLL2 头
+    LinuxSll2,
+}
 
-                // 验证是否是 IPv4（版本号在第一个字节的高4位）
-                if (ip_header[0] & 0xF0) != 0x40 {
-                    // 不是 IPv4，静默返回
-                    return Ok(None);
-                }
+impl LinkLayer {
+    /// 按 libpcap 的链路层类型（`DLT_*`）选择头格式，未知类型按内容判断
+    pub fn from_linktype(linktype: i32) -> Self {
+        match linktype {
+            DLT_NULL => LinkLayer::Null,
+            DLT_EN10MB => LinkLayer::Ethernet,
+            DLT_RAW | DLT_RAW_OPENBSD | LINKTYPE_RAW | DLT_IPV4 | DLT_IPV6 => LinkLayer::Raw,
+            DLT_LOOP => LinkLayer::Loop,
+            DLT_LINUX_SLL => LinkLayer::LinuxSll,
+            DLT_LINUX_SLL2 => LinkLayer::LinuxSll2,
+            other => {
+                warn!("未知的链路层类型 {}，按内容判断 IP 头的位置", other);
+                LinkLayer::Auto
+            }
+        }
+    }
+
+    /// 抓包句柄的链路层头格式
+    #[cfg(feature = "pcap")]
+    pub fn of<T: pcap::Activated>(capture: &Capture<T>) -> Self {
+        Self::from_linktype(capture.get_datalink().0)
+    }
+}
 
-                // 源 IP 在 IP 头的字节 12-15（相对于 IP 头开始）
-                let src_ip_bytes = [ip_header[12], ip_header[13], ip_header[14], ip_header[15]];
-                let src_ip = IpAddr::from(src_ip_bytes);
+/// libpcap 的链路层类型
+const DLT_NULL: i32 = 0;
+const DLT_EN10MB: i32 = 1;
+/// 裸 IP 在大多数系统上为 12，OpenBSD 上为 14，保存的文件中为 101
+const DLT_RAW: i32 = 12;
+const DLT_RAW_OPENBSD: i32 = 14;
+const LINKTYPE_RAW: i32 = 101;
+const DLT_LOOP: i32 = 108;
+const DLT_LINUX_SLL: i32 = 113;
+const DLT_IPV4: i32 = 228;
+const DLT_IPV6: i32 = 229;
+const DLT_LINUX_SLL2: i32 = 276;
 
-                // IP 头长度在字节 0 的低 4 位（IHL），单位是 4 字节
-                let ip_header_len = (ip_header[0] & 0x0F) as usize * 4;
+/// 回环头中 IPv4/IPv6 的地址族：AF_INET 各系统都为 2，AF_INET6 在 Linux、NetBSD/OpenBSD、
+/// FreeBSD、macOS 上分别为 10、24、28、30
+const LOOPBACK_FAMILIES: [u32; 5] = [2, 10, 24, 28, 30];
 
-                // UDP 头在 IP 头之后，UDP 头是 8 字节
-                let udp_start = ip_start_offset + ip_header_len;
-                let udp_data_start = udp_start + 8;
+/// 从链路层帧（或裸 IPv4/IPv6 数据包）中解析出 UDP 数据包
+///
+/// 输入来自网络，完全不可信：长度不足、头长度字段非法或不是 IPv4/IPv6 上的 UDP 时返回 None。
+pub fn decode_packet(data: &[u8]) -> Option<CapturedPacket> {
+    decode_frame(data, LinkLayer::Auto)
+}
 
-                if data.len() > udp_data_start {
-                    // UDP 数据从 udp_data_start 开始
-                    let udp_data = data[udp_data_start..].to_vec();
-                    // 不输出日志，只在解析到 SIP 请求时才输出
-                    return Ok(Some((src_ip, udp_data)));
-                }
+/// 同 [`decode_packet`]，按指定的链路层头格式解析
+pub fn decode_frame(data: &[u8], link: LinkLayer) -> Option<CapturedPacket> {
+    let ip = decode_ip_frame(data, link)?;
 
-                Ok(None)
+    // 只处理 UDP（17）；IPv6 的协议号取自扩展头之后的最后一个“下一个头”
+    // pcap 的过滤器已保证这一点，AF_PACKET 等来源会收到所有 IP 数据包
+    if ip.protocol != 17 {
+        return None;
+    }
+
+    // UDP 头在 IP 头之后，UDP 头是 8 字节
+    let udp_start = ip.transport_start;
+    let udp_data_start = udp_start + 8;
+
+    if data.len() > udp_data_start {
+        // UDP 头：源端口（字节 0-1）、目标端口（字节 2-3）
+        let source_port = u16::from_be_bytes([data[udp_start], data[udp_start + 1]]);
+        let dest_port = u16::from_be_bytes([data[udp_start + 2], data[udp_start + 3]]);
+        // UDP 数据从 udp_data_start 开始
+        let udp_data = data[udp_data_start..].to_vec();
+        // 不输出日志，只在解析到 SIP 请求时才输出
+        return Some(CapturedPacket {
+            source_ip: ip.source,
+            dest_ip: ip.dest,
+            source_port,
+            dest_port,
+            payload: udp_data,
+            captured_at: None,
+            parsed: None,
+        });
+    }
+
+    None
+}
+
+/// IP 头中需要的字段
+pub(crate) struct IpHeader {
+    pub source: IpAddr,
+    pub dest: IpAddr,
+    /// 协议号：6 为 TCP，17 为 UDP
+    pub protocol: u8,
+    /// 传输层头在 `data` 中的起始位置
+    pub transport_start: usize,
+}
+
+/// 以太网类型
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+/// VLAN 标签：802.1Q、802.1ad（QinQ 外层）以及部分交换机使用的旧 QinQ 类型
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+const ETHERTYPE_QINQ_LEGACY: u16 = 0x9100;
+
+/// 最多跳过的 VLAN 标签层数
+const MAX_VLAN_TAGS: usize = 4;
+
+/// 最多跳过的 IPv6 扩展头个数，防止构造的长链消耗过多时间
+const MAX_IPV6_EXTENSIONS: usize = 8;
+
+/// 按指定的链路层头格式解析链路层帧（或裸 IPv4/IPv6 数据包）的 IP 头
+pub(crate) fn decode_ip_frame(data: &[u8], link: LinkLayer) -> Option<IpHeader> {
+    let ip_start_offset = match link {
+        LinkLayer::Auto => guess_ip_offset(data)?,
+        LinkLayer::Ethernet => {
+            let ethertype = data.get(12..14)?;
+            skip_vlan_tags(data, u16::from_be_bytes([ethertype[0], ethertype[1]]), 14)?
+        }
+        LinkLayer::Raw => 0,
+        // 保存的文件可能来自另一台主机，两种字节序都接受
+        LinkLayer::Null => {
+            let family: [u8; 4] = data.get(..4)?.try_into().ok()?;
+            [u32::from_le_bytes(family), u32::from_be_bytes(family)]
+                .iter()
+                .any(|family| LOOPBACK_FAMILIES.contains(family))
+                .then_some(4)?
+        }
+        LinkLayer::Loop => {
+            let family: [u8; 4] = data.get(..4)?.try_into().ok()?;
+            LOOPBACK_FAMILIES
+                .contains(&u32::from_be_bytes(family))
+                .then_some(4)?
+        }
+        // SLL 头：包类型、ARPHRD 类型、地址长度、8 字节地址，协议（以太网类型）在字节 14-15
+        LinkLayer::LinuxSll => cooked_ip_offset(data, 14, 16)?,
+        // SLL2 头：协议（以太网类型）在字节 0-1，之后是保留字段、接口索引、ARPHRD 类型等
+        LinkLayer::LinuxSll2 => cooked_ip_offset(data, 0, 20)?,
+    };
+
+    let ip_header = data.get(ip_start_offset..)?;
+    match ip_header.first()? >> 4 {
+        4 => decode_ipv4(ip_header, ip_start_offset),
+        6 => decode_ipv6(ip_header, ip_start_offset),
+        // 不是 IP，静默返回
+        _ => None,
+    }
+}
+
+/// Linux cooked 头之后的 IP 头位置；协议字段（跳过 VLAN 标签后）不是 IPv4/IPv6 时返回 None
+fn cooked_ip_offset(data: &[u8], protocol_at: usize, header_len: usize) -> Option<usize> {
+    let protocol = data.get(protocol_at..protocol_at + 2)?;
+    skip_vlan_tags(
+        data,
+        u16::from_be_bytes([protocol[0], protocol[1]]),
+        header_len,
+    )
+    .filter(|&offset| data.len() > offset)
+}
+
+/// 跳过（可能叠加的）VLAN 标签：`ethertype` 为链路层头中的以太网类型，`start` 为其后内容的位置
+///
+/// 返回 IP 头的位置；最内层的以太网类型不是 IPv4/IPv6，或标签超过 [`MAX_VLAN_TAGS`] 层时返回 None。
+fn skip_vlan_tags(data: &[u8], mut ethertype: u16, mut start: usize) -> Option<usize> {
+    for _ in 0..=MAX_VLAN_TAGS {
+        match ethertype {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => return Some(start),
+            ETHERTYPE_VLAN | ETHERTYPE_QINQ | ETHERTYPE_QINQ_LEGACY => {
+                // 每个标签 4 字节：优先级和 VLAN ID，之后是内层的以太网类型
+                let inner = data.get(start + 2..start + 4)?;
+                ethertype = u16::from_be_bytes([inner[0], inner[1]]);
+                start += 4;
             }
-            Err(pcap::Error::TimeoutExpired) => {
-                // 超时是正常的，继续等待
-                Ok(None)
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// 按内容判断 IP 头的位置：以太网头之后，或裸 IP 数据包的开头
+fn guess_ip_offset(data: &[u8]) -> Option<usize> {
+    // pcap 返回的数据可能包含以太网头（14字节），也可能直接从 IP 层开始
+    // 首先检查是否是 IP 数据包（IP 版本在第一个字节的高4位）
+    if data.len() < 20 {
+        // 数据包太小，静默返回
+        return None;
+    }
+
+    // 以太网类型在字节 12-13：0x0800 为 IPv4，0x86DD 为 IPv6，IP 头从第 14 字节开始；
+    // 否则按第一个字节的版本号判断是否是没有以太网头的裸 IP 数据包
+    let ethertype = data
+        .get(12..14)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    // 带 VLAN 标签的帧（trunk 端口）：跳过标签后按内层类型定位；裸 IPv4 数据包的源地址也可能
+    // 形如 0x8100，再核对一次版本号
+    if let Some(ethertype @ (ETHERTYPE_VLAN | ETHERTYPE_QINQ | ETHERTYPE_QINQ_LEGACY)) = ethertype {
+        let tagged = skip_vlan_tags(data, ethertype, 14)
+            .filter(|&offset| matches!(data.get(offset).map(|byte| byte >> 4), Some(4 | 6)));
+        if tagged.is_some() {
+            return tagged;
+        }
+    }
+    Some(match ethertype {
+        Some(ETHERTYPE_IPV4) => 14,
+        // 裸 IPv4 数据包的字节 12-13 是源地址，可能恰好等于 0x86DD，再核对一次版本号
+        Some(ETHERTYPE_IPV6) if data[14] >> 4 == 6 => 14,
+        _ if matches!(data[0] >> 4, 4 | 6) => 0,
+        // 尝试从第 14 字节开始（假设有以太网头）
+        Some(_) => 14,
+        None => 0,
+    })
+}
+
+/// 解析 IPv4 头，`offset` 为 IP 头在整个帧中的起始位置
+fn decode_ipv4(ip_header: &[u8], offset: usize) -> Option<IpHeader> {
+    if ip_header.len() < 20 {
+        // 数据包太小，静默返回
+        return None;
+    }
+
+    // 源 IP 在 IP 头的字节 12-15（相对于 IP 头开始）
+    let src_ip_bytes = [ip_header[12], ip_header[13], ip_header[14], ip_header[15]];
+    let src_ip = IpAddr::from(src_ip_bytes);
+    // 目标 IP 在 IP 头的字节 16-19
+    let dst_ip_bytes = [ip_header[16], ip_header[17], ip_header[18], ip_header[19]];
+    let dst_ip = IpAddr::from(dst_ip_bytes);
+
+    // IP 头长度在字节 0 的低 4 位（IHL），单位是 4 字节
+    let ip_header_len = (ip_header[0] & 0x0F) as usize * 4;
+    if ip_header_len < 20 {
+        // IHL 小于 5 的头部非法，否则会把 IP 头误当作传输层头
+        return None;
+    }
+    // 分片（字节 6-7：MF 标志和分片偏移）：首个分片只有残缺的 SIP 消息，之后的分片没有 UDP 头，都跳过
+    if u16::from_be_bytes([ip_header[6], ip_header[7]]) & 0x3FFF != 0 {
+        return None;
+    }
+
+    Some(IpHeader {
+        source: src_ip,
+        dest: dst_ip,
+        protocol: ip_header[9],
+        transport_start: offset + ip_header_len,
+    })
+}
+
+/// 解析 IPv6 头并跳过扩展头，`offset` 为 IP 头在整个帧中的起始位置
+///
+/// 逐跳选项、路由、目的选项和认证头按各自的长度字段跳过；分片的数据包不完整，
+/// ESP 加密了之后的内容，遇到这两种情况（以及“没有下一个头”）返回 None。
+fn decode_ipv6(ip_header: &[u8], offset: usize) -> Option<IpHeader> {
+    // 固定头 40 字节：下一个头在字节 6，源地址在字节 8-23，目标地址在字节 24-39
+    if ip_header.len() < 40 {
+        return None;
+    }
+    let source: [u8; 16] = ip_header[8..24].try_into().ok()?;
+    let dest: [u8; 16] = ip_header[24..40].try_into().ok()?;
+
+    let mut next_header = ip_header[6];
+    let mut position = 40;
+    for _ in 0..MAX_IPV6_EXTENSIONS {
+        let extension = ip_header.get(position..position + 8)?;
+        let length = match next_header {
+            // 逐跳选项、路由、目的选项：长度字段以 8 字节为单位，不含前 8 字节
+            0 | 43 | 60 => (usize::from(extension[1]) + 1) * 8,
+            // 分片：固定 8 字节；分片偏移（字节 2-3 的高 13 位）或 M 标志（最低位）不为 0 时是真正的分片，
+            // 首个分片只有残缺的 SIP 消息，之后的分片没有 UDP 头，都跳过
+            44 => {
+                if u16::from_be_bytes([extension[2], extension[3]]) & 0xFFF9 != 0 {
+                    return None;
+                }
+                8
             }
-            Err(e) => {
-                error!("抓包错误: {}", e);
-                Err(format!("抓包错误: {}", e))
+            // 认证头：长度字段以 4 字节为单位，不含前 8 字节
+            51 => (usize::from(extension[1]) + 2) * 4,
+            // ESP（50）和没有下一个头（59）：找不到传输层头
+            50 | 59 => return None,
+            _ => {
+                return Some(IpHeader {
+                    source: IpAddr::from(source),
+                    dest: IpAddr::from(dest),
+                    protocol: next_header,
+                    transport_start: offset + position,
+                })
+            }
+        };
+        next_header = extension[0];
+        position += length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{ipv4_datagram, sip_request};
+
+    #[test]
+    fn linux_cooked_frames_from_the_any_interface_are_decoded() {
+        let source = std::net::Ipv4Addr::new(203, 0, 113, 9);
+        let dest = std::net::Ipv4Addr::new(192, 0, 2, 1);
+        let payload = sip_request("REGISTER", "friendly-scanner", "sll-1", 1);
+        let datagram = ipv4_datagram(source, dest, 5060, 5060, payload.as_bytes());
+
+        // SLL：包类型（发往本机）、ARPHRD_ETHER、地址长度 6、MAC 补齐到 8 字节、协议 IPv4
+        let mut sll = vec![0, 0, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x00];
+        sll.extend_from_slice(&datagram);
+        // SLL2：协议 IPv4、保留、接口索引、ARPHRD_ETHER、包类型、地址长度、8 字节地址
+        let mut sll2 = vec![
+            0x08, 0x00, 0, 0, 0, 0, 0, 2, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0,
+        ];
+        sll2.extend_from_slice(&datagram);
+
+        assert_eq!(LinkLayer::from_linktype(113), LinkLayer::LinuxSll);
+        assert_eq!(LinkLayer::from_linktype(276), LinkLayer::LinuxSll2);
+        assert_eq!(LinkLayer::from_linktype(1), LinkLayer::Ethernet);
+        // 按以太网猜测时找不到 IP 头
+        assert!(decode_packet(&sll).is_none());
+        for (frame, link) in [(&sll, LinkLayer::LinuxSll), (&sll2, LinkLayer::LinuxSll2)] {
+            let packet = decode_frame(frame, link).unwrap();
+            assert_eq!(packet.source_ip, IpAddr::V4(source));
+            assert_eq!(packet.dest_port, 5060);
+            assert_eq!(packet.payload, payload.as_bytes());
+        }
+        // 协议不是 IP 时（如 ARP）忽略
+        sll[14..16].copy_from_slice(&[0x08, 0x06]);
+        assert!(decode_frame(&sll, LinkLayer::LinuxSll).is_none());
+        assert!(decode_frame(&sll2[..20], LinkLayer::LinuxSll2).is_none());
+    }
+
+    #[test]
+    fn vlan_tagged_frames_are_decoded_through_stacked_tags() {
+        let source = std::net::Ipv4Addr::new(203, 0, 113, 9);
+        let dest = std::net::Ipv4Addr::new(192, 0, 2, 1);
+        let payload = sip_request("REGISTER", "friendly-scanner", "vlan-1", 1);
+        let datagram = ipv4_datagram(source, dest, 5060, 5060, payload.as_bytes());
+        let ethernet = |tags: &[[u8; 4]]| {
+            let mut frame = vec![0x02; 12];
+            for tag in tags {
+                frame.extend_from_slice(tag);
             }
+            frame.extend_from_slice(&[0x08, 0x00]);
+            frame.extend_from_slice(&datagram);
+            frame
+        };
+
+        // 802.1Q（VLAN 100），以及 QinQ：外层 802.1ad（VLAN 10）+ 内层 802.1Q（VLAN 100）
+        let single = ethernet(&[[0x81, 0x00, 0x00, 0x64]]);
+        let stacked = ethernet(&[[0x88, 0xA8, 0x00, 0x0A], [0x81, 0x00, 0x00, 0x64]]);
+        for frame in [&single, &stacked] {
+            let packet = decode_packet(frame).unwrap();
+            assert_eq!(packet.source_ip, IpAddr::V4(source));
+            assert_eq!(packet.dest_port, 5060);
+            assert_eq!(packet.payload, payload.as_bytes());
         }
+        // Linux cooked 头中的协议也可能是 VLAN 标签
+        let mut sll = vec![
+            0, 0, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x81, 0x00, 0x00, 0x64,
+        ];
+        sll.extend_from_slice(&[0x08, 0x00]);
+        sll.extend_from_slice(&datagram);
+        assert_eq!(
+            decode_frame(&sll, LinkLayer::LinuxSll).unwrap().source_ip,
+            IpAddr::V4(source)
+        );
+
+        // 标签内不是 IP（如 ARP）时忽略；源地址形如 0x8100 的裸 IPv4 数据包不当作 VLAN 帧
+        let mut arp = single.clone();
+        arp[16..18].copy_from_slice(&[0x08, 0x06]);
+        assert!(decode_packet(&arp).is_none());
+        let raw = ipv4_datagram(
+            std::net::Ipv4Addr::new(129, 0, 0, 7),
+            dest,
+            5060,
+            5060,
+            payload.as_bytes(),
+        );
+        assert_eq!(
+            decode_packet(&raw).unwrap().source_ip,
+            IpAddr::from([129, 0, 0, 7])
+        );
     }
 
-    /// 列出所有可用的网络接口
-    pub fn list_interfaces() -> Vec<String> {
-        match Device::list() {
-            Ok(devices) => devices.iter().map(|d| d.name.clone()).collect(),
-            Err(_) => vec![],
+    #[test]
+    fn ip_header_offset_follows_the_capture_link_type() {
+        // 源地址 8.0.0.1 的裸 IPv4 数据包：字节 12-13 恰好是 0x0800，按内容猜测会当作以太网帧
+        let source = std::net::Ipv4Addr::new(8, 0, 0, 1);
+        let dest = std::net::Ipv4Addr::new(192, 0, 2, 1);
+        let payload = sip_request("REGISTER", "friendly-scanner", "dlt-1", 1);
+        let datagram = ipv4_datagram(source, dest, 5060, 5060, payload.as_bytes());
+        assert_ne!(
+            decode_packet(&datagram).map(|packet| packet.source_ip),
+            Some(IpAddr::V4(source))
+        );
+
+        let with_header = |header: &[u8]| [header, &datagram[..]].concat();
+        let mut ethernet = vec![0x02; 12];
+        ethernet.extend_from_slice(&[0x08, 0x00]);
+        let cases = [
+            (12, datagram.clone()),
+            (101, datagram.clone()),
+            (1, with_header(&ethernet)),
+            // DLT_NULL 为抓包主机的字节序，DLT_LOOP 为网络字节序
+            (0, with_header(&2u32.to_le_bytes())),
+            (0, with_header(&30u32.to_be_bytes())),
+            (108, with_header(&2u32.to_be_bytes())),
+        ];
+        for (linktype, frame) in cases {
+            let packet = decode_frame(&frame, LinkLayer::from_linktype(linktype)).unwrap();
+            assert_eq!(packet.source_ip, IpAddr::V4(source), "DLT {}", linktype);
+            assert_eq!(packet.payload, payload.as_bytes());
         }
+
+        // 以太网帧只按以太网类型定位，不再退回到裸 IP
+        ethernet[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert!(decode_frame(&with_header(&ethernet), LinkLayer::Ethernet).is_none());
+        // 回环头中的地址族不是 IP 时忽略
+        assert!(decode_frame(&with_header(&17u32.to_be_bytes()), LinkLayer::Loop).is_none());
+        assert_eq!(LinkLayer::from_linktype(147), LinkLayer::Auto);
     }
-}
 
-impl Drop for PacketCapture {
-    fn drop(&mut self) {
-        if self.capture.is_some() {
-            debug!("关闭数据包捕获器");
+    #[test]
+    fn capture_filter_narrows_the_protected_port_filter() {
+        assert_eq!(capture_filter(&[5060], None), filter_expression(&[5060]));
+        // 附加的表达式与端口过滤同时满足，更换端口时保留
+        assert_eq!(
+            capture_filter(&[5060, 5080], Some("dst host 203.0.113.5")),
+            "(udp and (dst port 5060 or dst port 5080)) and (dst host 203.0.113.5)"
+        );
+
+        let config = Config::parse("capture_filter = \"dst host 203.0.113.5\"").unwrap();
+        assert_eq!(
+            config.capture_filter.as_deref(),
+            Some("dst host 203.0.113.5")
+        );
+        if std::env::var_os("UABLOCK_CAPTURE_FILTER").is_none() {
+            assert!(config.env_defaults().contains(&(
+                "UABLOCK_CAPTURE_FILTER".to_string(),
+                "dst host 203.0.113.5".to_string()
+            )));
         }
+        let error = Config::parse("capture_filter = \" \"").unwrap_err();
+        assert!(error.contains("capture_filter"), "{}", error);
     }
 }
@@ -0,0 +1,92 @@
+use crate::packet_capture::CapturedPacket;
+use crate::sip_parser::SipParser;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+/// 每个解析线程的队列容量
+const WORKER_QUEUE_CAPACITY: usize = 1024;
+
+/// 解析线程池：在检测任务之外并行分类和解析数据包
+///
+/// SIP 洪泛时正则解析是检测任务的主要开销。启用后抓包线程先把数据包交给分发线程，按来源 IP
+/// 分给固定的解析线程，解析结果随数据包（[`CapturedPacket::parsed`]）交给检测任务。同一来源的
+/// 数据包总是由同一个线程按顺序解析，检测任务收到它们的顺序与抓包顺序一致；判定和处置仍由
+/// 检测任务逐个完成，同一 IP 的封禁/解封不会并发执行。
+pub struct ParsePool {
+    workers: usize,
+}
+
+impl ParsePool {
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+        }
+    }
+
+    /// `UABLOCK_PARSE_WORKERS` 指定的解析线程数，未设置或为 0 时返回 None（在检测任务中解析）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_PARSE_WORKERS") {
+            Ok(text) if !text.is_empty() => match text.parse::<usize>() {
+                Ok(0) => Ok(None),
+                Ok(workers) => Ok(Some(Self::new(workers))),
+                Err(_) => Err(format!("UABLOCK_PARSE_WORKERS 无效: {}", text)),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// 解析该来源数据包的线程
+    pub fn worker_for(&self, ip: &IpAddr) -> usize {
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+        (hasher.finish() % self.workers as u64) as usize
+    }
+
+    /// 启动分发线程和解析线程，返回交给抓包线程的发送端；解析后的数据包发往 `output`
+    ///
+    /// `output` 关闭后各线程依次退出，返回的发送端随之关闭，抓包线程也会退出。
+    pub fn start(
+        &self,
+        output: mpsc::Sender<CapturedPacket>,
+    ) -> Result<mpsc::Sender<CapturedPacket>, String> {
+        let mut queues = Vec::with_capacity(self.workers);
+        for index in 0..self.workers {
+            let (tx, mut rx) = mpsc::channel::<CapturedPacket>(WORKER_QUEUE_CAPACITY);
+            let output = output.clone();
+            std::thread::Builder::new()
+                .name(format!("sip-parse-{}", index))
+                .spawn(move || {
+                    let parser = SipParser::new();
+                    while let Some(mut packet) = rx.blocking_recv() {
+                        packet.parsed = Some(parser.parse(&packet.payload, packet.source_ip));
+                        if output.blocking_send(packet).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .map_err(|e| format!("启动解析线程失败: {}", e))?;
+            queues.push(tx);
+        }
+
+        let (input, mut rx) = mpsc::channel::<CapturedPacket>(WORKER_QUEUE_CAPACITY);
+        let pool = Self::new(self.workers);
+        std::thread::Builder::new()
+            .name("sip-parse-dispatch".to_string())
+            .spawn(move || {
+                while let Some(packet) = rx.blocking_recv() {
+                    let worker = pool.worker_for(&packet.source_ip);
+                    if queues[worker].blocking_send(packet).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(|e| format!("启动解析分发线程失败: {}", e))?;
+        Ok(input)
+    }
+}
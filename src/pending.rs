@@ -0,0 +1,267 @@
+use crate::bans::parse_duration;
+use crate::detection::Detection;
+use crate::reason::ReasonCode;
+use crate::state_file::unix_now;
+use crate::whitelist::{ListMode, Whitelist};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 默认直接封禁、不进入审核队列的原因：扫描器特征、黑名单 UA 和 PBX 报告的认证失败
+const DEFAULT_IMMEDIATE: &str = "SCANNER_SIGNATURE,UA_DENIED,AUTH_FAILURE";
+
+/// 驳回后该来源的检测只告警的时长
+const REJECT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// 一个等待审核的封禁
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingBan {
+    pub ip: IpAddr,
+    pub method: String,
+    pub user_agent: String,
+    pub reason: String,
+    pub code: ReasonCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// 进入队列和审核期满自动封禁的时间（Unix 秒）
+    pub queued_at: u64,
+    pub enforce_at: u64,
+    /// 进入队列以来的检测次数
+    pub hits: u64,
+    /// 首次检测，审核通过或期满时按它封禁
+    #[serde(skip)]
+    detection: Detection,
+}
+
+impl PendingBan {
+    pub fn detection(&self) -> &Detection {
+        &self.detection
+    }
+}
+
+/// 提交检测的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submission {
+    /// 高置信度的原因，照常立即封禁
+    Immediate,
+    /// 已进入（或已在）审核队列，暂不封禁
+    Queued,
+    /// 该来源已被驳回，只告警
+    Rejected,
+}
+
+/// 运维的审核操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewAction {
+    /// 立即封禁
+    Approve,
+    /// 误报，不封禁，24 小时内该来源的检测只告警
+    Reject,
+    /// 误报，并把该 UA 加入白名单
+    Whitelist,
+}
+
+impl ReviewAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Approve => "approve",
+            Self::Reject => "reject",
+            Self::Whitelist => "whitelist",
+        }
+    }
+}
+
+struct State {
+    pending: BTreeMap<IpAddr, PendingBan>,
+    /// 已驳回的来源 → 驳回失效时间（Unix 秒）
+    rejected: HashMap<IpAddr, u64>,
+}
+
+/// 待审核封禁队列
+///
+/// 低置信度的检测（如 UA 不在白名单中、速率超限）先进入队列，在审核期内由运维通过 API 或
+/// `uablock review` 批准、驳回或加入白名单；审核期满仍未处理的按原检测封禁。扫描器特征等
+/// 高置信度的原因不进入队列，照常立即封禁。
+pub struct PendingBans {
+    hold: Duration,
+    /// 直接封禁的原因代码或原因分类
+    immediate: Vec<String>,
+    state: Mutex<State>,
+}
+
+impl PendingBans {
+    pub fn new(hold: Duration, immediate: Vec<String>) -> Self {
+        Self {
+            hold,
+            immediate,
+            state: Mutex::new(State {
+                pending: BTreeMap::new(),
+                rejected: HashMap::new(),
+            }),
+        }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_REVIEW_HOLD` 时返回 None
+    ///
+    /// - `UABLOCK_REVIEW_HOLD`：审核期（支持 `d`/`h`/`m`/`s`，如 `30m`），期满未处理的自动封禁
+    /// - `UABLOCK_REVIEW_IMMEDIATE`：直接封禁的原因代码或原因分类，逗号分隔
+    ///   （默认 `SCANNER_SIGNATURE,UA_DENIED,AUTH_FAILURE`）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let hold = match std::env::var("UABLOCK_REVIEW_HOLD") {
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|hold| !hold.is_zero())
+                .ok_or_else(|| format!("UABLOCK_REVIEW_HOLD 无效: {}", value))?,
+            _ => return Ok(None),
+        };
+        let immediate: Vec<String> = std::env::var("UABLOCK_REVIEW_IMMEDIATE")
+            .unwrap_or_else(|_| DEFAULT_IMMEDIATE.to_string())
+            .split(',')
+            .map(|code| code.trim().to_ascii_uppercase())
+            .filter(|code| !code.is_empty())
+            .collect();
+        info!(
+            "待审核封禁已启用: 审核期 {} 秒，直接封禁 {:?}",
+            hold.as_secs(),
+            immediate
+        );
+        Ok(Some(Self::new(hold, immediate)))
+    }
+
+    pub fn hold(&self) -> Duration {
+        self.hold
+    }
+
+    /// 检测的原因代码或原因分类是否直接封禁
+    pub fn is_immediate(&self, detection: &Detection) -> bool {
+        self.immediate
+            .iter()
+            .any(|code| *code == detection.reason || code == detection.code.as_str())
+    }
+
+    /// 提交一次检测
+    pub fn submit(&self, detection: &Detection) -> Submission {
+        if self.is_immediate(detection) {
+            return Submission::Immediate;
+        }
+        let now = unix_now();
+        let ip = detection.source_ip;
+        let mut state = self.state.lock().unwrap();
+        if state.rejected.get(&ip).is_some_and(|&until| until > now) {
+            return Submission::Rejected;
+        }
+        if let Some(pending) = state.pending.get_mut(&ip) {
+            pending.hits += 1;
+            return Submission::Queued;
+        }
+        let enforce_at = now + self.hold.as_secs();
+        info!(
+            "【待审核】IP: {} ({}) 进入审核队列，UA: {:?}，{} 秒内未处理将自动封禁",
+            ip,
+            detection.reason,
+            detection.user_agent,
+            self.hold.as_secs()
+        );
+        state.pending.insert(
+            ip,
+            PendingBan {
+                ip,
+                method: detection.method.clone(),
+                user_agent: detection.user_agent.clone(),
+                reason: detection.reason.clone(),
+                code: detection.code,
+                rule: detection.rule.clone(),
+                queued_at: now,
+                enforce_at,
+                hits: 1,
+                // 审核造成的延迟不计入封禁生效延迟
+                detection: Detection {
+                    captured_at: None,
+                    detected_at: None,
+                    ..detection.clone()
+                },
+            },
+        );
+        Submission::Queued
+    }
+
+    /// 全部待审核的封禁（按进入队列的时间）
+    pub fn pending(&self) -> Vec<PendingBan> {
+        let mut pending: Vec<PendingBan> = self
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .values()
+            .cloned()
+            .collect();
+        pending.sort_by_key(|pending| pending.queued_at);
+        pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 取出审核期已满的封禁（由调用方执行），并清理已失效的驳回记录
+    pub fn due(&self) -> Vec<PendingBan> {
+        let now = unix_now();
+        let mut state = self.state.lock().unwrap();
+        state.rejected.retain(|_, until| *until > now);
+        let due: Vec<IpAddr> = state
+            .pending
+            .values()
+            .filter(|pending| pending.enforce_at <= now)
+            .map(|pending| pending.ip)
+            .collect();
+        due.iter()
+            .filter_map(|ip| state.pending.remove(ip))
+            .collect()
+    }
+
+    /// 处理一个待审核的封禁，返回被处理的条目；不在队列中时返回错误
+    ///
+    /// 批准时从队列取出，由调用方执行封禁；驳回和加入白名单时记录驳回，
+    /// 加入白名单时还把该 UA 加入白名单（黑名单模式下不支持）。
+    pub fn review(
+        &self,
+        ip: IpAddr,
+        action: ReviewAction,
+        whitelist: &Mutex<Whitelist>,
+    ) -> Result<PendingBan, String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.pending.contains_key(&ip) {
+            return Err(format!("{} 不在审核队列中", ip));
+        }
+        if action == ReviewAction::Whitelist {
+            let user_agent = state.pending[&ip].user_agent.trim().to_string();
+            if user_agent.is_empty() {
+                return Err(format!("{} 的请求没有 UA，无法加入白名单", ip));
+            }
+            let mut whitelist = whitelist.lock().unwrap();
+            if whitelist.mode() == ListMode::Deny {
+                return Err("黑名单模式下不能加入白名单，请使用 reject".to_string());
+            }
+            whitelist.add_pattern(user_agent);
+        }
+        let pending = state.pending.remove(&ip).expect("已确认在队列中");
+        if action != ReviewAction::Approve {
+            state.rejected.insert(ip, unix_now() + REJECT_TTL.as_secs());
+        }
+        info!(
+            "【待审核】IP: {} ({}) 审核结果: {}，UA: {:?}",
+            ip,
+            pending.reason,
+            action.as_str(),
+            pending.user_agent
+        );
+        Ok(pending)
+    }
+}
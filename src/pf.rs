@@ -0,0 +1,224 @@
+use crate::firewall::FirewallBackend;
+use log::{debug, info, warn};
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Output, Stdio};
+
+/// 默认的表名（IPv4 和 IPv6 地址放在同一个表中）
+pub const DEFAULT_TABLE: &str = "uablock";
+
+/// 默认的锚点：主规则集中需要有 `anchor "uablock"`（macOS 自带的 `anchor "com.apple/*"`
+/// 会包含 `com.apple/uablock`）
+pub const DEFAULT_ANCHOR: &str = "uablock";
+
+/// pf 后端（FreeBSD、OPNsense/pfSense、macOS）：封禁的 IP 放在锚点中的表里，由锚点中的一条
+/// `block drop in quick` 规则统一丢弃
+///
+/// [`PfManager::setup`] 把表和规则加载到锚点中，不改动主规则集（`/etc/pf.conf`）；主规则集没有引用
+/// 该锚点，或 pf 没有启用时只告警，封禁不会生效。pf 会按已有的状态放行后续数据包，封禁后执行
+/// `pfctl -k <ip>` 清除该来源的状态，失败只记录警告。
+pub struct PfManager {
+    table: String,
+    anchor: String,
+    block_port: Option<u16>,
+}
+
+impl PfManager {
+    pub fn new(block_port: Option<u16>) -> Self {
+        Self {
+            table: DEFAULT_TABLE.to_string(),
+            anchor: DEFAULT_ANCHOR.to_string(),
+            block_port,
+        }
+    }
+
+    /// 表名（默认 uablock）
+    pub fn with_table(mut self, table: &str) -> Self {
+        self.table = table.to_string();
+        self
+    }
+
+    /// 规则所在的锚点（默认 uablock）
+    pub fn with_anchor(mut self, anchor: &str) -> Self {
+        self.anchor = anchor.to_string();
+        self
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// 加载到锚点中的规则
+    pub fn ruleset(&self) -> String {
+        let (proto, port) = match self.block_port {
+            Some(port) => (" proto udp", format!(" port {}", port)),
+            None => ("", String::new()),
+        };
+        format!(
+            "table <{table}> persist\nblock drop in quick{proto} from <{table}> to any{port}\n",
+            table = self.table,
+            proto = proto,
+            port = port
+        )
+    }
+
+    /// 把表和规则加载到锚点中（替换锚点中原有的规则，表的内容保留），检查 pf 是否启用、锚点是否被引用
+    pub fn setup(&self) -> Result<(), String> {
+        let mut child = Command::new("pfctl")
+            .args(["-a", &self.anchor, "-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("执行 pfctl 命令失败: {}", e))?;
+        child
+            .stdin
+            .take()
+            .ok_or("无法写入 pfctl 标准输入")?
+            .write_all(self.ruleset().as_bytes())
+            .map_err(|e| format!("写入 pf 规则失败: {}", e))?;
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("执行 pfctl 命令失败: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "加载锚点 {} 的规则失败: {}",
+                self.anchor,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let info = self.check(&["-s", "info"], "获取 pf 状态")?;
+        if !info.contains("Status: Enabled") {
+            warn!("pf 未启用（pfctl -e），封禁不会生效");
+        }
+        let anchors = self.check(&["-s", "Anchors"], "获取 pf 锚点")?;
+        if !is_referenced(&anchors, &self.anchor) {
+            warn!(
+                "主规则集没有引用锚点 {}，请在 pf.conf 中加入 anchor \"{}\" 并重新加载，否则封禁不会生效",
+                self.anchor, self.anchor
+            );
+        }
+        info!(
+            "pf 后端已就绪（锚点 {}，表 {}，端口 {}）",
+            self.anchor,
+            self.table,
+            self.block_port
+                .map_or("全部".to_string(), |p| p.to_string())
+        );
+        Ok(())
+    }
+
+    fn pfctl(&self, args: &[&str]) -> Result<Output, String> {
+        debug!("执行 pfctl 命令: pfctl {}", args.join(" "));
+        Command::new("pfctl")
+            .args(args)
+            .output()
+            .map_err(|e| format!("执行 pfctl 命令失败: {}", e))
+    }
+
+    /// 对锚点中的表执行 `pfctl -T <command>`
+    fn table_command(&self, command: &str, ip: Option<&IpAddr>) -> Result<Output, String> {
+        let ip = ip.map(IpAddr::to_string);
+        let mut args = vec!["-a", &self.anchor, "-t", &self.table, "-T", command];
+        args.extend(ip.as_deref());
+        self.pfctl(&args)
+    }
+
+    /// 执行命令，成功时返回标准输出，失败时返回带说明的错误
+    fn check(&self, args: &[&str], action: &str) -> Result<String, String> {
+        output_text(self.pfctl(args)?, action)
+    }
+
+    /// 解析 `pfctl -t <表> -T show` 的输出（每行一个地址，网段忽略）
+    pub fn parse_table(text: &str) -> Vec<IpAddr> {
+        text.split_whitespace()
+            .filter_map(|entry| entry.parse().ok())
+            .collect()
+    }
+}
+
+/// `pfctl -s Anchors` 的输出（每行一个主规则集中的锚点）中是否有该锚点；锚点位于列出的锚点之下时
+/// （如 macOS 的 `com.apple/*` 求值的 `com.apple/uablock`）也视为被引用
+pub fn is_referenced(anchors: &str, anchor: &str) -> bool {
+    anchors.lines().map(str::trim).any(|line| {
+        let parent = line.trim_end_matches("/*");
+        !parent.is_empty() && (parent == anchor || anchor.starts_with(&format!("{}/", parent)))
+    })
+}
+
+fn output_text(output: Output, action: &str) -> Result<String, String> {
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!(
+            "{}失败: {}",
+            action,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+impl FirewallBackend for PfManager {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        output_text(self.table_command("add", Some(ip))?, "pf 添加封禁")?;
+        info!("成功封禁 IP: {}", ip);
+        if let Err(e) = self.check(&["-k", &ip.to_string()], "清除 pf 状态") {
+            warn!("{}", e);
+        }
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        if !self.is_blocked(ip) {
+            return Ok(());
+        }
+        output_text(self.table_command("delete", Some(ip))?, "pf 解除封禁")?;
+        info!("成功解封 IP: {}", ip);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        matches!(self.table_command("test", Some(ip)), Ok(output) if output.status.success())
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let text = output_text(
+            self.table_command("show", None)?,
+            &format!("获取 pf 表 {}", self.table),
+        )?;
+        Ok(Self::parse_table(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn pf_backend_loads_one_rule_for_its_table_into_an_anchor() {
+        let manager = PfManager::new(Some(5060));
+        assert_eq!(manager.table(), "uablock");
+        assert_eq!(
+            manager.ruleset(),
+            "table <uablock> persist\nblock drop in quick proto udp from <uablock> to any port 5060\n"
+        );
+        assert_eq!(
+            PfManager::new(None).with_table("sip").ruleset(),
+            "table <sip> persist\nblock drop in quick from <sip> to any\n"
+        );
+        assert_eq!(
+            PfManager::parse_table("   203.0.113.5\n   2001:db8::7\n   198.51.100.0/24\n"),
+            vec![ip("203.0.113.5"), ip("2001:db8::7")]
+        );
+        assert!(is_referenced("  uablock\n", "uablock"));
+        assert!(is_referenced("  com.apple\n", "com.apple/uablock"));
+        assert!(!is_referenced("  com.apple\n", "uablock"));
+        Config::parse("backend = \"pf\"").unwrap();
+    }
+}
@@ -0,0 +1,851 @@
+use crate::banned_sources::BannedSources;
+use crate::campaigns::Campaigns;
+use crate::decision_cache::DecisionCache;
+use crate::detection::Detection;
+use crate::enforcement::Enforcer;
+use crate::honeypot::Honeypot;
+use crate::ingest::ExternalSignal;
+use crate::limits;
+use crate::local_net::LocalNetworks;
+#[cfg(feature = "lua-hooks")]
+use crate::lua_hooks::LuaHooks;
+use crate::noise::{self, NoiseKind};
+use crate::packet_capture::CapturedPacket;
+use crate::pending::{PendingBans, Submission};
+use crate::policy::{Policy, Verdict};
+use crate::reconcile::{self, ReconcileReport};
+use crate::retransmission::RetransmissionTracker;
+use crate::sip_parser::{PacketClass, SipParser, SipRequest};
+use crate::state_file::{DetectionState, StateFile};
+use crate::stats::Stats;
+use crate::tenants::Tenants;
+use crate::trace::{TraceInput, TraceOutcome, TraceRecorder};
+use crate::verification::{Verifier, AUTH_SUCCESS};
+use log::{debug, error, info, warn};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 灰名单临时丢弃的原因代码
+const GREYLIST_HOLD: &str = "GREYLIST_HOLD";
+
+/// 周期性维护（重传统计输出、清理、保存状态）的间隔
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 一个数据包的处理结果
+#[derive(Debug, Clone)]
+pub enum PacketOutcome {
+    /// 不是 SIP REGISTER/INVITE 请求
+    Ignored,
+    /// 畸形 SIP 报文（已计入惩罚分）
+    Malformed,
+    /// STUN、RTP 或二进制垃圾数据
+    Noise(NoiseKind),
+    /// 同一事务的重传，不重复处理
+    Retransmission,
+    /// 来源最近已被判定封禁，只计数不处理
+    Cached,
+    /// 已按策略判定并处置的请求
+    Request { source_ip: IpAddr, verdict: Verdict },
+}
+
+/// 检测流水线：解析 → 去重 → 策略判定 → 处置
+///
+/// 流水线不负责抓包，调用方把数据包逐个交给 [`Pipeline::process`]，并在循环中调用
+/// [`Pipeline::tick`] 完成定时任务。处置统一经由 [`Enforcer`]，因此事件和统计与
+/// 管理接口触发的封禁保持一致。
+///
+/// ```no_run
+/// use std::sync::{Arc, Mutex};
+/// use uablock_rust::{Enforcer, EventBus, Pipeline, Policy, Stats, Whitelist};
+///
+/// let enforcer = Arc::new(Enforcer::new(
+///     None,
+///     None,
+///     Arc::new(Stats::default()),
+///     Arc::new(EventBus::new()),
+/// ));
+/// let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())));
+/// let mut pipeline = Pipeline::builder(enforcer).policy(policy).build();
+/// # let packets: Vec<uablock_rust::CapturedPacket> = Vec::new();
+/// for packet in &packets {
+///     pipeline.tick();
+///     pipeline.process(packet);
+/// }
+/// ```
+pub struct Pipeline {
+    parser: SipParser,
+    policy: Policy,
+    enforcer: Arc<Enforcer>,
+    retransmissions: RetransmissionTracker,
+    honeypot: Option<Honeypot>,
+    state_file: Option<StateFile>,
+    decisions: Option<DecisionCache>,
+    banned: Option<BannedSources>,
+    tenants: Option<Tenants>,
+    verifier: Option<Verifier>,
+    local_networks: Option<LocalNetworks>,
+    trace: Option<TraceRecorder>,
+    campaigns: Option<Arc<Campaigns>>,
+    review: Option<Arc<PendingBans>>,
+    #[cfg(feature = "lua-hooks")]
+    hooks: Option<LuaHooks>,
+    last_maintenance: Instant,
+    /// 上次维护时来源状态表的淘汰数和重传跟踪表的未记录数
+    reported_overflow: (u64, u64),
+    /// 正在处理的数据包的抓包时刻，检测结果据此统计封禁生效延迟
+    captured_at: Option<Instant>,
+}
+
+/// [`Pipeline`] 构造器
+pub struct PipelineBuilder {
+    enforcer: Arc<Enforcer>,
+    parser: Option<SipParser>,
+    policy: Option<Policy>,
+    honeypot: Option<Honeypot>,
+    state_file: Option<StateFile>,
+    decision_ttl: Option<Duration>,
+    banned_log_interval: Option<Duration>,
+    tenants: Option<Tenants>,
+    verifier: Option<Verifier>,
+    local_networks: Option<LocalNetworks>,
+    trace: Option<TraceRecorder>,
+    campaigns: Option<Arc<Campaigns>>,
+    review: Option<Arc<PendingBans>>,
+    transaction_limit: usize,
+    #[cfg(feature = "lua-hooks")]
+    hooks: Option<LuaHooks>,
+}
+
+impl PipelineBuilder {
+    /// 自定义 SIP 解析器
+    pub fn parser(mut self, parser: SipParser) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+
+    /// 检测策略（未设置时使用默认白名单）
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// 对扫描器伪造应答（研究用途）
+    pub fn honeypot(mut self, honeypot: Honeypot) -> Self {
+        self.honeypot = Some(honeypot);
+        self
+    }
+
+    /// 持久化检测状态：构造时恢复，定期和退出时保存
+    pub fn state_file(mut self, state_file: StateFile) -> Self {
+        self.state_file = Some(state_file);
+        self
+    }
+
+    /// 按来源 IP 缓存最近的判定 `ttl` 时长，见 [`DecisionCache`]
+    pub fn decision_cache(mut self, ttl: Duration) -> Self {
+        self.decision_ttl = Some(ttl);
+        self
+    }
+
+    /// 封禁表中的来源只计数不判定，每个来源每 `interval` 最多输出一行日志，见 [`BannedSources`]
+    ///
+    /// 白名单 UA 的请求仍按正常流程处理（解封）；灰名单临时丢弃中的来源不受影响。
+    pub fn suppress_banned(mut self, interval: Duration) -> Self {
+        self.banned_log_interval = Some(interval);
+        self
+    }
+
+    /// 多租户：按目标地址选择租户的白名单，并在检测和事件上标记租户，见 [`Tenants`]
+    pub fn tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// 白名单 UA 的二次验证，见 [`Verifier`]
+    pub fn verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// 局域网设备只告警不封禁，见 [`LocalNetworks`]
+    pub fn local_networks(mut self, networks: LocalNetworks) -> Self {
+        self.local_networks = Some(networks);
+        self
+    }
+
+    /// 记录每个输入及其判定结果，见 [`TraceRecorder`]
+    pub fn trace(mut self, recorder: TraceRecorder) -> Self {
+        self.trace = Some(recorder);
+        self
+    }
+
+    /// 把 SIP 请求触发的检测归并为扫描活动并定期报告，见 [`Campaigns`]
+    pub fn campaigns(mut self, campaigns: Arc<Campaigns>) -> Self {
+        self.campaigns = Some(campaigns);
+        self
+    }
+
+    /// 低置信度的检测先进入审核队列，审核期满未处理才封禁，见 [`PendingBans`]
+    pub fn review(mut self, review: Arc<PendingBans>) -> Self {
+        self.review = Some(review);
+        self
+    }
+
+    /// 重传跟踪表最多跟踪 `limit` 个事务（0 表示不限制），见 [`RetransmissionTracker::with_limit`]
+    pub fn transaction_limit(mut self, limit: usize) -> Self {
+        self.transaction_limit = limit;
+        self
+    }
+
+    /// Lua 脚本钩子：判定前覆盖判定、封禁后撤销封禁
+    #[cfg(feature = "lua-hooks")]
+    pub fn lua_hooks(mut self, hooks: LuaHooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    pub fn build(self) -> Pipeline {
+        let mut policy = self
+            .policy
+            .unwrap_or_else(|| Policy::new(Default::default()));
+        if let Some(state) = self.state_file.as_ref().and_then(|f| f.load_or_warn()) {
+            policy.restore(&state);
+            self.enforcer.bans().restore(&state.bans);
+            if let Some(escalation) = self.enforcer.escalation() {
+                escalation.restore(&state.offences);
+            }
+        }
+        Pipeline {
+            parser: self.parser.unwrap_or_default(),
+            policy,
+            enforcer: self.enforcer,
+            retransmissions: RetransmissionTracker::with_limit(self.transaction_limit),
+            honeypot: self.honeypot,
+            state_file: self.state_file,
+            decisions: self.decision_ttl.map(DecisionCache::new),
+            banned: self.banned_log_interval.map(BannedSources::new),
+            tenants: self.tenants,
+            verifier: self.verifier,
+            local_networks: self.local_networks,
+            trace: self.trace,
+            campaigns: self.campaigns,
+            review: self.review,
+            #[cfg(feature = "lua-hooks")]
+            hooks: self.hooks,
+            last_maintenance: Instant::now(),
+            reported_overflow: (0, 0),
+            captured_at: None,
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn builder(enforcer: Arc<Enforcer>) -> PipelineBuilder {
+        PipelineBuilder {
+            enforcer,
+            parser: None,
+            policy: None,
+            honeypot: None,
+            state_file: None,
+            decision_ttl: None,
+            banned_log_interval: None,
+            tenants: None,
+            verifier: None,
+            local_networks: None,
+            trace: None,
+            campaigns: None,
+            review: None,
+            transaction_limit: 0,
+            #[cfg(feature = "lua-hooks")]
+            hooks: None,
+        }
+    }
+
+    pub fn enforcer(&self) -> &Arc<Enforcer> {
+        &self.enforcer
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// 处理一个捕获到的数据包
+    pub fn process(&mut self, packet: &CapturedPacket) -> PacketOutcome {
+        self.captured_at = packet.captured_at;
+        let outcome = self.process_packet(packet);
+        self.captured_at = None;
+        if let Some(trace) = self.trace.as_mut() {
+            if trace.wants(&packet.source_ip) {
+                trace.record(
+                    TraceInput::packet(packet),
+                    TraceOutcome::packet(&outcome, self.policy.score(&packet.source_ip)),
+                );
+            }
+        }
+        outcome
+    }
+
+    fn process_packet(&mut self, packet: &CapturedPacket) -> PacketOutcome {
+        let stats = self.enforcer.stats().clone();
+        Stats::incr(&stats.packets);
+        stats.port(packet.dest_port, |port| port.packets += 1);
+
+        // 本机发出的流量（例如 PBX 向外注册）不参与判定
+        if self.enforcer.is_host_address(&packet.source_ip) {
+            return PacketOutcome::Ignored;
+        }
+        // 可信来源（SIP 中继、办公网段）在 UA 匹配之前放行
+        if self.enforcer.is_trusted(&packet.source_ip) {
+            Stats::incr(&stats.trusted_packets);
+            return PacketOutcome::Ignored;
+        }
+
+        // 最近已封禁的来源（典型的是封禁后仍在洪泛的扫描器）不再解析和判定
+        if self
+            .decisions
+            .as_ref()
+            .is_some_and(|cache| cache.is_banned(&packet.source_ip))
+        {
+            Stats::incr(&stats.decision_cache_hits);
+            self.enforcer.bans().hit(&packet.source_ip);
+            return PacketOutcome::Cached;
+        }
+
+        // 按目标地址选择租户，未命中时使用全局白名单
+        let tenant = self
+            .tenants
+            .as_ref()
+            .and_then(|tenants| tenants.lookup(packet.dest_ip, packet.dest_port));
+        let tenant_name = tenant.map(|tenant| tenant.name.clone());
+        // 封禁表中的来源（灰名单临时丢弃除外）
+        let banned = self.banned.is_some()
+            && self.enforcer.bans().matches(&packet.source_ip, |record| {
+                record.reason.reason != GREYLIST_HOLD
+            });
+
+        // 畸形 SIP 报文累计惩罚分，达到阈值后封禁
+        // 解析线程已解析的数据包直接使用其结果
+        let class = match &packet.parsed {
+            Some(parsed) => parsed.class,
+            None => self.parser.classify(&packet.payload),
+        };
+        if let PacketClass::Malformed(reason) = class {
+            if banned && Self::suppress(&mut self.banned, &self.enforcer, packet.source_ip) {
+                return PacketOutcome::Cached;
+            }
+            Stats::incr(&stats.malformed);
+            stats.port(packet.dest_port, |port| port.malformed += 1);
+            debug!(
+                "收到畸形 SIP 报文，来源 IP: {}，原因: {}",
+                packet.source_ip,
+                reason.as_str()
+            );
+            // 能解析出请求时先按 UA 和事务判断：白名单 UA 的报文和同一请求的重传不累计惩罚分
+            let request = match &packet.parsed {
+                Some(parsed) => parsed.request.clone(),
+                None => self.parser.parse_request(&packet.payload, packet.source_ip),
+            };
+            if let Some(request) = request {
+                let allowed = match tenant {
+                    Some(tenant) => tenant.is_allowed(&request.user_agent),
+                    None => self.policy.is_allowed(&request.user_agent),
+                };
+                if allowed {
+                    return PacketOutcome::Malformed;
+                }
+                if !self.retransmissions.observe(&request) {
+                    Stats::incr(&stats.retransmissions);
+                    return PacketOutcome::Retransmission;
+                }
+            }
+            if let Some(detection) = self.policy.malformed(packet.source_ip, reason) {
+                self.report(
+                    &detection
+                        .with_tenant(tenant_name.as_deref())
+                        .with_dest_port(packet.dest_port),
+                );
+            }
+            return PacketOutcome::Malformed;
+        }
+
+        // STUN、错发的 RTP 和扫描器的二进制垃圾数据：分类计数，可选对持续发送垃圾数据的来源累计惩罚分
+        if let Some(kind) = noise::classify(&packet.payload) {
+            if banned && Self::suppress(&mut self.banned, &self.enforcer, packet.source_ip) {
+                return PacketOutcome::Cached;
+            }
+            Stats::incr(stats.noise(kind));
+            stats.port(packet.dest_port, |port| port.noise += 1);
+            debug!(
+                "收到非 SIP 数据，来源 IP: {}，分类: {}",
+                packet.source_ip,
+                kind.as_str()
+            );
+            if let Some(detection) = self.policy.noise(packet.source_ip, kind) {
+                self.report(
+                    &detection
+                        .with_tenant(tenant_name.as_deref())
+                        .with_dest_port(packet.dest_port),
+                );
+            }
+            return PacketOutcome::Noise(kind);
+        }
+
+        let request = match &packet.parsed {
+            Some(parsed) => parsed.request.clone(),
+            None => self.parser.parse_request(&packet.payload, packet.source_ip),
+        };
+
+        // 蜜罐模式：对扫描器（非白名单 UA）的 OPTIONS/REGISTER 伪造应答
+        if let Some(honeypot) = self.honeypot.as_mut() {
+            if let Some(request) = &request {
+                let allowed = match tenant {
+                    Some(tenant) => tenant.is_allowed(&request.user_agent),
+                    None => self.policy.is_allowed(&request.user_agent),
+                };
+                if !allowed {
+                    honeypot.handle(packet, request);
+                }
+            }
+        }
+
+        // MESSAGE 垃圾短信（SPIT）、SUBSCRIBE/NOTIFY 洪泛：按来源和方法限速，白名单 UA 不受限
+        if let Some(request) = request
+            .clone()
+            .filter(|request| self.policy.limits_method(&request.method))
+            .map(|request| request.with_ports(packet.source_port, packet.dest_port))
+        {
+            Stats::incr(&stats.sip_requests);
+            stats.port(packet.dest_port, |port| port.sip_requests += 1);
+            let allowed = match tenant {
+                Some(tenant) => tenant.is_allowed(&request.user_agent),
+                None => self.policy.is_allowed(&request.user_agent),
+            };
+            if allowed {
+                return PacketOutcome::Ignored;
+            }
+            if banned && Self::suppress(&mut self.banned, &self.enforcer, packet.source_ip) {
+                return PacketOutcome::Cached;
+            }
+            if !self.retransmissions.observe(&request) {
+                Stats::incr(&stats.retransmissions);
+                return PacketOutcome::Retransmission;
+            }
+            let Some(detection) = self.policy.method_request(&request) else {
+                return PacketOutcome::Ignored;
+            };
+            let detection = detection
+                .with_tenant(tenant_name.as_deref())
+                .with_dest_port(packet.dest_port);
+            self.observe_campaign(&detection, &request);
+            self.report(&detection);
+            return PacketOutcome::Request {
+                source_ip: request.source_ip,
+                verdict: Verdict::Detect(detection),
+            };
+        }
+
+        // 不是 SIP REGISTER/INVITE 请求时静默忽略
+        let Some(request) = request
+            .and_then(SipParser::checked_request)
+            .map(|request| request.with_ports(packet.source_port, packet.dest_port))
+        else {
+            return PacketOutcome::Ignored;
+        };
+        Stats::incr(&stats.sip_requests);
+        stats.port(packet.dest_port, |port| port.sip_requests += 1);
+
+        // 已封禁的来源只计数；白名单 UA 仍需判定，以便解封
+        if banned {
+            let allowed = match tenant {
+                Some(tenant) => tenant.is_allowed(&request.user_agent),
+                None => self.policy.is_allowed(&request.user_agent),
+            };
+            if !allowed && Self::suppress(&mut self.banned, &self.enforcer, packet.source_ip) {
+                return PacketOutcome::Cached;
+            }
+        }
+
+        // 同一事务的重传不重复计数和处理
+        if !self.retransmissions.observe(&request) {
+            Stats::incr(&stats.retransmissions);
+            return PacketOutcome::Retransmission;
+        }
+
+        let verdict = self.policy.evaluate_for(&request, tenant);
+        // 白名单 UA 的二次验证：未验证的来源不解封，请求过多时封禁
+        let verdict = match (verdict, self.verifier.as_mut()) {
+            (Verdict::Allow, Some(verifier)) => {
+                if verifier.check(request.source_ip, &packet.payload) {
+                    Verdict::Allow
+                } else if verifier.observe_unverified(request.source_ip) {
+                    Verdict::Detect(Detection::from_request(&request, "UA_UNVERIFIED"))
+                } else {
+                    Verdict::Unverified
+                }
+            }
+            (verdict, _) => verdict,
+        };
+        // Lua 脚本可以覆盖策略的判定
+        #[cfg(feature = "lua-hooks")]
+        let verdict = match self.hooks.as_ref().and_then(|hooks| {
+            hooks.pre_decision(&request, self.policy.score(&request.source_ip), &verdict)
+        }) {
+            Some(overridden) => {
+                info!(
+                    "【脚本】User-Agent: '{}', IP: {} 的判定由 {:?} 改为 {:?}",
+                    request.user_agent, request.source_ip, verdict, overridden
+                );
+                overridden
+            }
+            None => verdict,
+        };
+        let verdict = match verdict {
+            Verdict::Detect(detection) => {
+                Verdict::Detect(detection.with_tenant(tenant_name.as_deref()))
+            }
+            verdict => verdict,
+        };
+        match &verdict {
+            Verdict::Allow => {
+                // UA 在白名单中，检查是否需要解封（最近已检查过时跳过）
+                match self.decisions.as_mut() {
+                    Some(cache) if cache.is_allowed(&request.source_ip, &request.user_agent) => {
+                        Stats::incr(&stats.decision_cache_hits);
+                    }
+                    cache => {
+                        self.enforcer
+                            .unblock_if_needed(request.source_ip, &request.user_agent);
+                        if let Some(cache) = cache {
+                            cache.allow(request.source_ip, &request.user_agent);
+                        }
+                    }
+                }
+            }
+            Verdict::Hold => {
+                info!(
+                    "【灰名单】User-Agent: '{}', IP: {} 首次出现，临时丢弃",
+                    request.user_agent, request.source_ip
+                );
+                if let Some(note) = self.lan_match(&request.source_ip) {
+                    debug!(
+                        "【灰名单】IP: {} 为局域网设备（{}），不临时丢弃",
+                        request.source_ip, note
+                    );
+                } else {
+                    self.enforcer.hold(
+                        Detection::from_request(&request, GREYLIST_HOLD)
+                            .with_tenant(tenant_name.as_deref()),
+                    );
+                }
+            }
+            Verdict::Probation => {
+                debug!(
+                    "【灰名单】User-Agent: '{}', IP: {} 处于观察期",
+                    request.user_agent, request.source_ip
+                );
+            }
+            Verdict::Unverified => {
+                debug!(
+                    "【验证】User-Agent: '{}', IP: {} 尚未通过二次验证，不解封",
+                    request.user_agent, request.source_ip
+                );
+            }
+            Verdict::Detect(detection) => {
+                self.observe_campaign(detection, &request);
+                self.report(detection)
+            }
+        }
+        PacketOutcome::Request {
+            source_ip: request.source_ip,
+            verdict,
+        }
+    }
+
+    /// 处理外部系统上报的信号（计入惩罚分），返回触发的检测
+    pub fn process_signal(&mut self, signal: &ExternalSignal) -> Option<Detection> {
+        let detection = self.process_external(signal);
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(
+                TraceInput::signal(signal),
+                TraceOutcome::signal(detection.as_ref(), self.policy.score(&signal.source_ip)),
+            );
+        }
+        detection
+    }
+
+    fn process_external(&mut self, signal: &ExternalSignal) -> Option<Detection> {
+        debug!(
+            "【外部信号】来源: {}, IP: {}, 原因: {}, 权重: {}",
+            signal.origin, signal.source_ip, signal.reason, signal.weight
+        );
+        // 认证成功只用于二次验证，不计入惩罚分
+        if signal.reason == AUTH_SUCCESS {
+            if let Some(verifier) = self.verifier.as_mut() {
+                verifier.auth_succeeded(signal.source_ip);
+            }
+            return None;
+        }
+        let detection = self.policy.signal(signal)?;
+        self.report(&detection);
+        Some(detection)
+    }
+
+    /// 定时任务：解除到期的灰名单临时规则，并每分钟执行一次维护
+    ///
+    /// 返回 true 表示本次执行了周期性维护。
+    pub fn tick(&mut self) -> bool {
+        if let Some(cache) = self.decisions.as_mut() {
+            cache.cleanup();
+        }
+        if let Some(banned) = self.banned.as_mut() {
+            banned.cleanup();
+        }
+        for ip in self.policy.due_releases() {
+            self.enforcer.release(ip);
+        }
+
+        if let Some(review) = self.review.clone() {
+            for pending in review.due() {
+                info!(
+                    "【待审核】IP: {} 审核期满未处理（期间检测 {} 次），执行封禁",
+                    pending.ip, pending.hits
+                );
+                self.enforce(pending.detection());
+            }
+        }
+
+        self.update_usage();
+
+        if self.last_maintenance.elapsed() < MAINTENANCE_INTERVAL {
+            return false;
+        }
+        self.report_overflow();
+        // 输出各来源的重传率，并重置统计窗口
+        for (ip, stats) in self.retransmissions.top_retransmitters(10) {
+            info!(
+                "【重传统计】IP: {}, 请求数: {}, 重传数: {}, 重传率: {:.1}%",
+                ip,
+                stats.total,
+                stats.retransmissions,
+                stats.retransmission_rate() * 100.0
+            );
+        }
+        self.retransmissions.cleanup();
+        if let Some(escalation) = self.enforcer.escalation() {
+            escalation.cleanup();
+        }
+        for ip in self.enforcer.expire_bans() {
+            if let Some(cache) = self.decisions.as_mut() {
+                cache.forget(&ip);
+            }
+        }
+        for expired in self.policy.expired_rules() {
+            info!(
+                "【规则】规则 '{}' 已于 {} 过期，不再生效，请从规则文件中删除",
+                expired.rule, expired.expires
+            );
+        }
+        self.policy.cleanup();
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.cleanup();
+        }
+        self.enforcer.bans().sync_store();
+        self.save_state();
+        if let Some(honeypot) = self.honeypot.as_mut() {
+            honeypot.cleanup();
+        }
+        if let Some(campaigns) = &self.campaigns {
+            campaigns.report_if_due();
+        }
+        self.last_maintenance = Instant::now();
+        true
+    }
+
+    /// 更新各状态表、事件队列和内存用量的统计
+    fn update_usage(&self) {
+        let stats = self.enforcer.stats();
+        let events = self.enforcer.events();
+        let sources = self.policy.sources();
+        Stats::set(&stats.source_entries, sources.len() as u64);
+        Stats::set(&stats.sources_evicted, sources.evicted());
+        Stats::set(
+            &stats.transaction_entries,
+            self.retransmissions.len() as u64,
+        );
+        Stats::set(
+            &stats.transactions_untracked,
+            self.retransmissions.untracked(),
+        );
+        Stats::set(&stats.event_queue_pending, events.queued() as u64);
+        Stats::set(&stats.events_dropped, events.dropped());
+        if let Some(memory) = limits::resident_memory() {
+            Stats::set(&stats.resident_memory, memory);
+        }
+    }
+
+    /// 上次维护以来状态表已满时输出一条警告
+    fn report_overflow(&mut self) {
+        let overflow = (
+            self.policy.sources().evicted(),
+            self.retransmissions.untracked(),
+        );
+        let (evicted, untracked) = (
+            overflow.0 - self.reported_overflow.0,
+            overflow.1 - self.reported_overflow.1,
+        );
+        if evicted > 0 {
+            warn!(
+                "【资源】来源状态表已满（{} 个来源），淘汰了 {} 个最久未活动的来源，可调大 UABLOCK_MAX_SOURCES",
+                self.policy.sources().len(),
+                evicted
+            );
+        }
+        if untracked > 0 {
+            warn!(
+                "【资源】重传跟踪表已满（{} 个事务），{} 个事务未记录，可调大 UABLOCK_MAX_TRANSACTIONS",
+                self.retransmissions.len(),
+                untracked
+            );
+        }
+        self.reported_overflow = overflow;
+    }
+
+    /// 导出检测状态
+    pub fn snapshot(&self) -> DetectionState {
+        DetectionState {
+            bans: self.enforcer.bans().records(),
+            offences: self
+                .enforcer
+                .escalation()
+                .map(|escalation| escalation.records())
+                .unwrap_or_default(),
+            ..self.policy.snapshot()
+        }
+    }
+
+    /// 恢复检测状态
+    pub fn restore(&mut self, state: &DetectionState) {
+        self.policy.restore(state);
+        self.enforcer.bans().restore(&state.bans);
+        if let Some(escalation) = self.enforcer.escalation() {
+            escalation.restore(&state.offences);
+        }
+    }
+
+    /// 核对封禁记录、防火墙规则和当前配置（见 [`reconcile::examine`]）
+    ///
+    /// 没有配置状态文件或封禁数据库时无从判断防火墙中的规则是否由本工具添加，不报告外来规则，
+    /// 直接把它们记入封禁表（见 [`ReconcileReport::adopt_foreign`]）。
+    pub fn reconcile(&self) -> Result<ReconcileReport, String> {
+        let mut report = reconcile::examine(
+            &self.enforcer,
+            &self.policy.whitelist().lock().unwrap(),
+            self.local_networks.as_ref(),
+        )?;
+        if self.state_file.is_none() && !self.enforcer.bans().has_store() {
+            report.adopt_foreign(&self.enforcer);
+            report.foreign.clear();
+        }
+        Ok(report)
+    }
+
+    /// 保存检测状态（未配置状态文件时不做任何事）
+    pub fn save_state(&self) {
+        if let Some(state_file) = &self.state_file {
+            if let Err(e) = state_file.save(self.snapshot()) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// 跳过已封禁来源的数据包：计数，每个间隔输出一行日志并复核防火墙规则
+    ///
+    /// 规则已被外部删除时清除封禁记录并返回 false，数据包按正常流程处理；启用处置队列时复核由执行线程完成，
+    /// 清除记录后该来源之后的数据包按正常流程处理。
+    fn suppress(banned: &mut Option<BannedSources>, enforcer: &Enforcer, ip: IpAddr) -> bool {
+        let Some(banned) = banned.as_mut() else {
+            return false;
+        };
+        if let Some(hits) = banned.hit(ip) {
+            // 备机没有规则，封禁表即为准
+            if !enforcer.is_standby() && !enforcer.verify_rule(ip) {
+                banned.forget(&ip);
+                return false;
+            }
+            info!(
+                "【已封禁】IP: {} 已封禁但仍在发送数据包（{} 秒内 {} 个），跳过判定",
+                ip,
+                banned.interval().as_secs(),
+                hits
+            );
+        }
+        Stats::incr(&enforcer.stats().banned_hits);
+        enforcer.bans().hit(&ip);
+        true
+    }
+
+    /// 把 SIP 请求触发的检测计入扫描活动
+    fn observe_campaign(&self, detection: &Detection, request: &SipRequest) {
+        if let Some(campaigns) = &self.campaigns {
+            let id = campaigns.observe(detection, request.to_user.as_deref());
+            debug!("【攻击活动】IP: {} 归入活动 #{}", detection.source_ip, id);
+        }
+    }
+
+    /// 来源是否为局域网设备，返回匹配的依据
+    fn lan_match(&self, ip: &IpAddr) -> Option<String> {
+        self.local_networks.as_ref()?.matches(ip)
+    }
+
+    /// 上报一次检测：局域网设备只告警，低置信度的检测等待审核，其余封禁
+    fn report(&mut self, detection: &Detection) {
+        let detection = &detection.clone().stamped(self.captured_at);
+        // 局域网设备只告警
+        if let Some(note) = self.lan_match(&detection.source_ip) {
+            self.enforcer
+                .alert(detection, &format!("局域网设备，{}", note));
+            return;
+        }
+        // 低置信度的检测等待审核；已驳回的来源只告警
+        if let Some(review) = &self.review {
+            match review.submit(detection) {
+                Submission::Immediate => {}
+                Submission::Queued => return,
+                Submission::Rejected => {
+                    self.enforcer.alert(detection, "已审核为误报");
+                    return;
+                }
+            }
+        }
+        self.enforce(detection);
+    }
+
+    /// 封禁检测到的来源
+    fn enforce(&mut self, detection: &Detection) {
+        // 灰名单临时丢弃中的来源被正式封禁时，不能再按期解除
+        self.policy.cancel_release(&detection.source_ip);
+        let banned = self.enforcer.handle_detection(detection);
+        self.policy.sources().update(detection.source_ip, |source| {
+            source.detections += 1;
+            if banned {
+                source.bans += 1;
+            }
+        });
+        // 新封禁后交给 Lua 脚本，脚本可以要求撤销
+        #[cfg(feature = "lua-hooks")]
+        if banned && self.hooks.as_ref().is_some_and(|h| h.post_ban(detection)) {
+            if let Err(e) = self.enforcer.unban(detection.source_ip, "SCRIPT", "Lua") {
+                error!("【脚本】撤销封禁 {} 失败: {}", detection.source_ip, e);
+            }
+            return;
+        }
+        // 封禁已生效（或已排队）时，有效期内该来源的后续数据包不再处理；封禁失败时没有封禁记录，不缓存，下次重试。
+        // 这里只查封禁表，不在检测任务中调用防火墙命令
+        if let Some(cache) = self.decisions.as_mut() {
+            if banned || self.enforcer.bans().get(&detection.source_ip).is_some() {
+                cache.ban(detection.source_ip);
+            }
+        }
+    }
+}
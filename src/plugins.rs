@@ -0,0 +1,314 @@
+//! WASM 检测插件
+//!
+//! 插件是导出以下函数的 WebAssembly 模块（接口版本 1）：
+//!
+//! - `memory`：线性内存
+//! - `uablock_abi_version() -> i32`：返回 1
+//! - `uablock_alloc(len: i32) -> i32`：分配 `len` 字节，返回偏移
+//! - `uablock_inspect(ptr: i32, len: i32) -> i64`：输入为 [`PluginInput`] 的 JSON，
+//!   返回 `(结果偏移 << 32) | 结果长度`，结果为 [`PluginOutput`] 的 JSON；返回 0 表示不做判定
+//!
+//! 宿主每次调用前通过 `uablock_alloc` 申请输入缓冲区，插件可以在 `uablock_inspect` 开始时
+//! 重置自己的分配器。插件不能导入任何宿主函数（没有文件、网络和时钟），每个插件有独立的
+//! 指令预算和内存上限。
+
+use crate::sip_parser::SipRequest;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use wasmtime::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+/// 宿主支持的插件接口版本
+pub const ABI_VERSION: i32 = 1;
+
+/// 默认每次调用的指令预算
+const DEFAULT_FUEL: u64 = 1_000_000;
+/// 默认线性内存上限（MB）
+const DEFAULT_MEMORY_MB: usize = 16;
+/// 连续失败（陷入、超出预算、结果无效）达到该次数后停用插件
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
+/// 传给插件的请求和上下文
+#[derive(Debug, Serialize)]
+pub struct PluginInput<'a> {
+    pub source_ip: String,
+    pub method: &'a str,
+    pub user_agent: &'a str,
+    pub call_id: Option<&'a str>,
+    pub cseq: Option<&'a str>,
+    pub branch: Option<&'a str>,
+    /// 来源当前的惩罚分
+    pub score: f64,
+}
+
+impl<'a> PluginInput<'a> {
+    pub fn new(request: &'a SipRequest, score: f64) -> Self {
+        Self {
+            source_ip: request.source_ip.to_string(),
+            method: &request.method,
+            user_agent: &request.user_agent,
+            call_id: request.call_id.as_deref(),
+            cseq: request.cseq.as_deref(),
+            branch: request.branch.as_deref(),
+            score,
+        }
+    }
+}
+
+/// 插件的判定
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginDecision {
+    /// 不做判定，交给后续检测
+    #[default]
+    None,
+    /// 放行（跳过 UA 白名单检查）
+    Allow,
+    /// 累加 `score` 惩罚分
+    Strike,
+    /// 立即封禁
+    Ban,
+}
+
+/// 插件返回的结果
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PluginOutput {
+    #[serde(default)]
+    pub decision: PluginDecision,
+    /// `strike` 时累加的惩罚分
+    #[serde(default)]
+    pub score: f64,
+    /// 可选的说明，写入日志
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// 一个插件对请求给出的判定
+#[derive(Debug, Clone)]
+pub struct PluginHit {
+    pub plugin: String,
+    pub output: PluginOutput,
+}
+
+/// 单个插件的资源限制（插件旁同名的 `.toml` 文件，可省略）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginLimits {
+    /// 每次调用的指令预算
+    pub fuel: u64,
+    /// 线性内存上限（MB）
+    pub memory_mb: usize,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            memory_mb: DEFAULT_MEMORY_MB,
+        }
+    }
+}
+
+/// 已加载的插件实例
+struct Plugin {
+    name: String,
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    inspect: TypedFunc<(i32, i32), i64>,
+    fuel: u64,
+    failures: u32,
+}
+
+impl Plugin {
+    fn load(engine: &Engine, path: &Path, limits: PluginLimits) -> Result<Self, String> {
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let module = Module::from_file(engine, path)
+            .map_err(|e| format!("加载插件 {} 失败: {}", path.display(), e))?;
+        let mut store = Store::new(
+            engine,
+            StoreLimitsBuilder::new()
+                .memory_size(limits.memory_mb * 1024 * 1024)
+                .instances(1)
+                .build(),
+        );
+        store.limiter(|limits| limits);
+        // 实例化（含 start 函数）也消耗预算
+        store
+            .set_fuel(limits.fuel)
+            .map_err(|e| format!("插件 {} 设置指令预算失败: {}", name, e))?;
+        // 不提供任何宿主函数，导入了宿主函数的模块无法实例化
+        let instance: Instance = Linker::new(engine)
+            .instantiate(&mut store, &module)
+            .map_err(|e| format!("实例化插件 {} 失败: {}", name, e))?;
+
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, "uablock_abi_version")
+            .and_then(|f| f.call(&mut store, ()))
+            .map_err(|e| format!("插件 {} 缺少 uablock_abi_version: {}", name, e))?;
+        if version != ABI_VERSION {
+            return Err(format!(
+                "插件 {} 的接口版本 {} 不受支持（需要 {}）",
+                name, version, ABI_VERSION
+            ));
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| format!("插件 {} 没有导出 memory", name))?;
+        let alloc = instance
+            .get_typed_func(&mut store, "uablock_alloc")
+            .map_err(|e| format!("插件 {} 缺少 uablock_alloc: {}", name, e))?;
+        let inspect = instance
+            .get_typed_func(&mut store, "uablock_inspect")
+            .map_err(|e| format!("插件 {} 缺少 uablock_inspect: {}", name, e))?;
+
+        Ok(Self {
+            name,
+            store,
+            memory,
+            alloc,
+            inspect,
+            fuel: limits.fuel,
+            failures: 0,
+        })
+    }
+
+    fn call(&mut self, input: &[u8]) -> Result<Option<PluginOutput>, String> {
+        self.store
+            .set_fuel(self.fuel)
+            .map_err(|e| format!("设置指令预算失败: {}", e))?;
+        let len = i32::try_from(input.len()).map_err(|_| "输入过大".to_string())?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|e| format!("uablock_alloc 失败: {}", e))?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)
+            .map_err(|e| format!("写入输入失败: {}", e))?;
+        let packed = self
+            .inspect
+            .call(&mut self.store, (ptr, len))
+            .map_err(|e| format!("uablock_inspect 失败: {}", e))? as u64;
+        if packed == 0 {
+            return Ok(None);
+        }
+
+        let start = (packed >> 32) as usize;
+        let end = start + (packed & 0xFFFF_FFFF) as usize;
+        let output = self
+            .memory
+            .data(&self.store)
+            .get(start..end)
+            .ok_or("结果超出线性内存范围")?;
+        serde_json::from_slice(output)
+            .map(Some)
+            .map_err(|e| format!("结果不是有效的 JSON: {}", e))
+    }
+}
+
+/// 插件宿主：按文件名顺序加载目录中的插件，依次对每个请求求值
+pub struct PluginHost {
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    /// 从 UABLOCK_PLUGIN_DIR 加载插件，未设置时返回 None
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_PLUGIN_DIR") {
+            Ok(dir) if !dir.is_empty() => Self::load_dir(Path::new(&dir)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// 加载目录中的所有 `.wasm` / `.wat` 插件；任何一个无效都返回错误
+    pub fn load_dir(dir: &Path) -> Result<Self, String> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| format!("无法读取插件目录 {}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("wasm" | "wat")
+                )
+            })
+            .collect();
+        paths.sort();
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| format!("初始化 WASM 引擎失败: {}", e))?;
+
+        let mut plugins = Vec::with_capacity(paths.len());
+        for path in paths {
+            let limits = Self::load_limits(&path)?;
+            let plugin = Plugin::load(&engine, &path, limits.clone())?;
+            info!(
+                "【插件】已加载 {}（指令预算 {}，内存上限 {} MB）",
+                plugin.name, limits.fuel, limits.memory_mb
+            );
+            plugins.push(plugin);
+        }
+        Ok(Self { plugins })
+    }
+
+    fn load_limits(path: &Path) -> Result<PluginLimits, String> {
+        let config = path.with_extension("toml");
+        if !config.exists() {
+            return Ok(PluginLimits::default());
+        }
+        let content = std::fs::read_to_string(&config)
+            .map_err(|e| format!("无法读取插件配置 {}: {}", config.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("插件配置 {} 无效: {}", config.display(), e))
+    }
+
+    /// 已加载（未被停用）的插件数
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// 依次调用每个插件，返回给出判定的结果
+    ///
+    /// 插件出错时视为不做判定；连续出错的插件会被停用。
+    pub fn inspect(&mut self, input: &PluginInput) -> Vec<PluginHit> {
+        let Ok(input) = serde_json::to_vec(input) else {
+            return Vec::new();
+        };
+        let mut hits = Vec::new();
+        self.plugins.retain_mut(|plugin| {
+            match plugin.call(&input) {
+                Ok(output) => {
+                    plugin.failures = 0;
+                    if let Some(output) = output.filter(|o| o.decision != PluginDecision::None) {
+                        hits.push(PluginHit {
+                            plugin: plugin.name.clone(),
+                            output,
+                        });
+                    }
+                }
+                Err(e) => {
+                    plugin.failures += 1;
+                    warn!("【插件】{} 执行失败: {}", plugin.name, e);
+                    if plugin.failures >= MAX_CONSECUTIVE_FAILURES {
+                        warn!(
+                            "【插件】{} 连续失败 {} 次，已停用",
+                            plugin.name, plugin.failures
+                        );
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        hits
+    }
+}
@@ -0,0 +1,501 @@
+use crate::detection::Detection;
+use crate::greylist::{Greylist, GreylistDecision};
+use crate::ingest::ExternalSignal;
+use crate::method_rate::MethodRateLimiter;
+use crate::noise::NoiseKind;
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins::{PluginDecision, PluginHost, PluginInput};
+use crate::registered::RegisteredEndpoints;
+use crate::rules::{ExpiredRule, MethodPolicy, RuleAction, RuleContext, RulesEngine};
+use crate::sip_parser::{MalformedReason, SipRequest};
+use crate::sources::SourceTable;
+use crate::state_file::DetectionState;
+use crate::strikes::StrikeTracker;
+use crate::tenants::Tenant;
+use crate::ua_rate::UaRateLimiter;
+use crate::whitelist::Whitelist;
+#[cfg(feature = "wasm-plugins")]
+use log::info;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// 每个畸形报文的惩罚分
+const MALFORMED_STRIKE_WEIGHT: f64 = 1.0;
+
+/// 策略对一个 SIP 请求的判定
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    /// UA 在白名单中，来源如已被封禁应解封
+    Allow,
+    /// 灰名单：首次出现的来源，应临时丢弃
+    Hold,
+    /// 灰名单观察期内，不做处置
+    Probation,
+    /// UA 在白名单中但来源尚未通过二次验证：不解封，也不封禁
+    Unverified,
+    /// 需要处置的检测结果
+    Detect(Detection),
+}
+
+/// 检测策略：白名单、UA 全局限速、检测规则、WASM 插件、灰名单和惩罚分
+///
+/// 策略只负责判定，不直接操作防火墙；处置由 [`Pipeline`](crate::pipeline::Pipeline)
+/// 交给 [`Enforcer`](crate::enforcement::Enforcer) 执行。除白名单外各检测器均为可选。
+pub struct Policy {
+    whitelist: Arc<Mutex<Whitelist>>,
+    strikes: StrikeTracker,
+    ua_limiter: Option<UaRateLimiter>,
+    method_limiter: Option<MethodRateLimiter>,
+    rules: Option<RulesEngine>,
+    greylist: Option<Greylist>,
+    /// 注册成功的来源，有效期内不因 UA 封禁
+    registered: Option<Arc<RegisteredEndpoints>>,
+    #[cfg(feature = "wasm-plugins")]
+    plugins: Option<PluginHost>,
+    /// 每个二进制垃圾数据包的惩罚分，未设置时只计数
+    noise_weight: Option<f64>,
+    /// 从状态恢复、但灰名单未启用时无人负责的临时规则
+    orphan_releases: Vec<IpAddr>,
+}
+
+impl Policy {
+    /// 只使用白名单和默认惩罚分阈值的策略
+    pub fn new(whitelist: Arc<Mutex<Whitelist>>) -> Self {
+        Self {
+            whitelist,
+            strikes: StrikeTracker::from_env(),
+            ua_limiter: None,
+            method_limiter: None,
+            rules: None,
+            greylist: None,
+            registered: None,
+            #[cfg(feature = "wasm-plugins")]
+            plugins: None,
+            noise_weight: None,
+            orphan_releases: Vec::new(),
+        }
+    }
+
+    /// 替换惩罚计数器（阈值、半衰期）
+    pub fn with_strikes(mut self, strikes: StrikeTracker) -> Self {
+        self.strikes = strikes;
+        self
+    }
+
+    /// 启用 UA 全局限速
+    pub fn with_ua_limiter(mut self, limiter: UaRateLimiter) -> Self {
+        self.ua_limiter = Some(limiter);
+        self
+    }
+
+    /// 启用 MESSAGE/SUBSCRIBE/NOTIFY 等方法的来源限速
+    pub fn with_method_limiter(mut self, limiter: MethodRateLimiter) -> Self {
+        self.method_limiter = Some(limiter);
+        self
+    }
+
+    /// 启用声明式检测规则
+    pub fn with_rules(mut self, rules: RulesEngine) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// 启用首次来源灰名单
+    pub fn with_greylist(mut self, greylist: Greylist) -> Self {
+        self.greylist = Some(greylist);
+        self
+    }
+
+    /// 注册成功的来源在有效期内不因 UA 不在白名单中被封禁
+    pub fn with_registered(mut self, registered: Arc<RegisteredEndpoints>) -> Self {
+        self.registered = Some(registered);
+        self
+    }
+
+    /// 启用 WASM 检测插件
+    #[cfg(feature = "wasm-plugins")]
+    pub fn with_plugins(mut self, plugins: PluginHost) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// 二进制垃圾数据计入惩罚分（STUN 和 RTP 不计）
+    pub fn with_noise_weight(mut self, weight: f64) -> Self {
+        self.noise_weight = Some(weight);
+        self
+    }
+
+    /// 共享的白名单（可在运行时修改）
+    pub fn whitelist(&self) -> &Arc<Mutex<Whitelist>> {
+        &self.whitelist
+    }
+
+    /// 各检测器共享的来源状态表
+    pub fn sources(&self) -> &Arc<SourceTable> {
+        self.strikes.sources()
+    }
+
+    /// 来源当前的惩罚分
+    pub fn score(&self, ip: &IpAddr) -> f64 {
+        self.strikes.score(ip)
+    }
+
+    /// UA 是否被放行（在白名单中，或黑名单模式下不在黑名单中）
+    pub fn is_allowed(&self, user_agent: &str) -> bool {
+        self.whitelist.lock().unwrap().is_allowed(user_agent)
+    }
+
+    /// 判定一个（非重传的）SIP 请求
+    pub fn evaluate(&mut self, request: &SipRequest) -> Verdict {
+        self.evaluate_for(request, None)
+    }
+
+    /// 判定一个（非重传的）SIP 请求，命中租户时使用该租户的白名单
+    pub fn evaluate_for(&mut self, request: &SipRequest, tenant: Option<&Tenant>) -> Verdict {
+        // UA 全局限速：处于临时拒绝期的 UA 直接封禁，不再检查白名单
+        if let Some(limiter) = self.ua_limiter.as_mut() {
+            limiter.observe(&request.user_agent, request.source_ip);
+            if limiter.is_denied(&request.user_agent) {
+                return Verdict::Detect(Detection::from_request(request, "UA_RATE_EXCEEDED"));
+            }
+        }
+
+        // 声明式检测规则；allow 规则相当于白名单
+        let mut rule_allowed = false;
+        if let Some(engine) = self.rules.as_mut() {
+            let ctx = RuleContext {
+                source_ip: request.source_ip,
+                dest_port: request.dest_port,
+                method: &request.method,
+                user_agent: &request.user_agent,
+                country: None,
+                score: self.strikes.score(&request.source_ip),
+            };
+            let mut ban_rule = None;
+            for hit in engine.evaluate(&ctx) {
+                match hit.action {
+                    RuleAction::Ban => ban_rule = Some((hit.rule, hit.code)),
+                    RuleAction::Strike => {
+                        if self
+                            .strikes
+                            .add(request.source_ip, hit.strike_weight, &hit.rule)
+                        {
+                            ban_rule = Some((hit.rule, hit.code));
+                        }
+                    }
+                    RuleAction::Log => warn!(
+                        "【规则】User-Agent: '{}', IP: {}, 命中规则: {}",
+                        request.user_agent, request.source_ip, hit.rule
+                    ),
+                    RuleAction::Allow => rule_allowed = true,
+                }
+            }
+            if let Some((rule, code)) = ban_rule {
+                return Verdict::Detect(
+                    Detection::from_request(request, "RULE_MATCH")
+                        .with_rule(&rule)
+                        .with_code(code),
+                );
+            }
+        }
+
+        // WASM 检测插件：封禁优先于放行
+        #[cfg(feature = "wasm-plugins")]
+        let plugin_allowed = match self.evaluate_plugins(request) {
+            Some(Verdict::Detect(detection)) => return Verdict::Detect(detection),
+            Some(_) => true,
+            None => false,
+        };
+        #[cfg(not(feature = "wasm-plugins"))]
+        let plugin_allowed = false;
+
+        let (allowed, mode) = match tenant {
+            Some(tenant) => (tenant.is_allowed(&request.user_agent), tenant.mode()),
+            None => {
+                let whitelist = self.whitelist.lock().unwrap();
+                (whitelist.is_allowed(&request.user_agent), whitelist.mode())
+            }
+        };
+        let registered = !allowed
+            && self
+                .registered
+                .as_ref()
+                .is_some_and(|r| r.is_registered(&request.source_ip));
+        if registered {
+            debug!(
+                "【已注册终端】User-Agent: '{}' 不在白名单中，IP: {} 已注册成功，不因 UA 封禁",
+                request.user_agent, request.source_ip
+            );
+        }
+        if !plugin_allowed && !rule_allowed && !allowed && !registered {
+            return Verdict::Detect(Detection::from_request(request, mode.reason()));
+        }
+
+        // 灰名单：首次出现的来源先临时丢弃，观察期内不解封
+        let decision = match self.greylist.as_mut() {
+            Some(greylist) => {
+                greylist.check(request.source_ip, &request.user_agent, &request.method)
+            }
+            None => GreylistDecision::Allowed,
+        };
+        match decision {
+            GreylistDecision::Allowed => Verdict::Allow,
+            GreylistDecision::Hold => Verdict::Hold,
+            GreylistDecision::Probation => Verdict::Probation,
+            GreylistDecision::Violation => {
+                Verdict::Detect(Detection::from_request(request, "GREYLIST_VIOLATION"))
+            }
+        }
+    }
+
+    /// 调用 WASM 插件：返回 Detect 表示应封禁，Allow 表示有插件放行，None 表示没有判定
+    #[cfg(feature = "wasm-plugins")]
+    fn evaluate_plugins(&mut self, request: &SipRequest) -> Option<Verdict> {
+        let plugins = self.plugins.as_mut()?;
+        let input = PluginInput::new(request, self.strikes.score(&request.source_ip));
+        let mut verdict = None;
+        for hit in plugins.inspect(&input) {
+            let reason = hit.output.reason.as_deref().unwrap_or("-");
+            let ban = match hit.output.decision {
+                PluginDecision::Ban => true,
+                PluginDecision::Strike => self.strikes.add(
+                    request.source_ip,
+                    hit.output.score,
+                    &format!("plugin:{}", hit.plugin),
+                ),
+                PluginDecision::Allow => {
+                    info!(
+                        "【插件】{} 放行 User-Agent: '{}', IP: {}, 说明: {}",
+                        hit.plugin, request.user_agent, request.source_ip, reason
+                    );
+                    verdict.get_or_insert(Verdict::Allow);
+                    false
+                }
+                PluginDecision::None => false,
+            };
+            if ban {
+                warn!(
+                    "【插件】{} 判定封禁 User-Agent: '{}', IP: {}, 说明: {}",
+                    hit.plugin, request.user_agent, request.source_ip, reason
+                );
+                return Some(Verdict::Detect(
+                    Detection::from_request(request, "PLUGIN").with_rule(&hit.plugin),
+                ));
+            }
+        }
+        verdict
+    }
+
+    /// 是否对该方法的请求限速
+    pub fn limits_method(&self, method: &str) -> bool {
+        self.method_limiter
+            .as_ref()
+            .is_some_and(|limiter| limiter.limits(method))
+    }
+
+    /// 记录一个受限速方法的（非重传）请求，来源超过上限时返回检测结果
+    pub fn method_request(&mut self, request: &SipRequest) -> Option<Detection> {
+        self.method_limiter.as_mut()?.observe(request)
+    }
+
+    /// 畸形报文累计惩罚分，达到阈值时返回检测结果
+    pub fn malformed(&mut self, source_ip: IpAddr, reason: MalformedReason) -> Option<Detection> {
+        self.strikes
+            .add(source_ip, MALFORMED_STRIKE_WEIGHT, reason.as_str())
+            .then(|| Detection::from_source(source_ip, "MALFORMED_PACKET"))
+    }
+
+    /// 二进制垃圾数据累计惩罚分，达到阈值时返回检测结果
+    pub fn noise(&mut self, source_ip: IpAddr, kind: NoiseKind) -> Option<Detection> {
+        let weight = self.noise_weight.filter(|_| kind == NoiseKind::Binary)?;
+        self.strikes
+            .add(source_ip, weight, kind.as_str())
+            .then(|| Detection::from_source(source_ip, kind.as_str()))
+    }
+
+    /// 外部系统上报的信号累计惩罚分，达到阈值时返回检测结果
+    pub fn signal(&mut self, signal: &ExternalSignal) -> Option<Detection> {
+        self.strikes
+            .add(signal.source_ip, signal.weight, &signal.reason)
+            .then(|| Detection::from_signal(signal))
+    }
+
+    /// 来源即将被正式封禁：取消其灰名单临时规则的到期解除
+    pub fn cancel_release(&mut self, ip: &IpAddr) {
+        self.orphan_releases.retain(|pending| pending != ip);
+        if let Some(greylist) = self.greylist.as_mut() {
+            greylist.cancel_release(ip);
+        }
+    }
+
+    /// 取出到期应解除的灰名单临时规则
+    pub fn due_releases(&mut self) -> Vec<IpAddr> {
+        let mut due = std::mem::take(&mut self.orphan_releases);
+        if let Some(greylist) = self.greylist.as_mut() {
+            due.extend(greylist.due_releases());
+        }
+        due
+    }
+
+    /// 检测规则文件中按触发方法配置的处置方式
+    pub fn method_policies(&self) -> HashMap<String, MethodPolicy> {
+        self.rules
+            .as_ref()
+            .map(|r| r.method_policies().clone())
+            .unwrap_or_default()
+    }
+
+    /// 已过期的检测规则
+    pub fn expired_rules(&self) -> Vec<ExpiredRule> {
+        self.rules.as_ref().map(|r| r.expired()).unwrap_or_default()
+    }
+
+    /// 清理过期的计数窗口和状态
+    pub fn cleanup(&mut self) {
+        self.strikes.cleanup();
+        if let Some(greylist) = self.greylist.as_mut() {
+            greylist.cleanup();
+        }
+        if let Some(engine) = self.rules.as_mut() {
+            engine.cleanup();
+        }
+        if let Some(limiter) = self.ua_limiter.as_mut() {
+            limiter.cleanup();
+        }
+        if let Some(limiter) = self.method_limiter.as_mut() {
+            limiter.cleanup();
+        }
+    }
+
+    /// 导出惩罚分、UA 速率窗口、规则计数窗口和灰名单状态，用于持久化
+    pub fn snapshot(&self) -> DetectionState {
+        let (ua_windows, ua_denied) = self
+            .ua_limiter
+            .as_ref()
+            .map(|l| l.snapshot())
+            .unwrap_or_default();
+        let (greylist, releases) = self
+            .greylist
+            .as_ref()
+            .map(|g| g.snapshot())
+            .unwrap_or_default();
+        DetectionState {
+            strikes: self.strikes.snapshot(),
+            ua_windows,
+            ua_denied,
+            greylist,
+            releases,
+            rule_counters: self
+                .rules
+                .as_ref()
+                .map(|r| r.snapshot())
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// 从持久化的状态恢复
+    pub fn restore(&mut self, state: &DetectionState) {
+        let elapsed = state.elapsed_secs();
+        self.strikes.restore(&state.strikes, elapsed);
+        if let Some(limiter) = self.ua_limiter.as_mut() {
+            limiter.restore(&state.ua_windows, &state.ua_denied, elapsed);
+        }
+        if let Some(engine) = self.rules.as_mut() {
+            engine.restore(&state.rule_counters, elapsed);
+        }
+        match self.greylist.as_mut() {
+            Some(greylist) => greylist.restore(&state.greylist, &state.releases, elapsed),
+            // 灰名单已关闭：之前添加的临时规则在下一次定时任务中立即解除
+            None => self
+                .orphan_releases
+                .extend(state.releases.iter().map(|r| r.ip)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sip_parser::SipParser;
+    use crate::testing::{sip_request, udp_packet};
+
+    const SCANNER: &str = "203.0.113.9";
+    const PHONE: &str = "198.51.100.7";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ordered_rules_match_sources_and_ports() {
+        let rules = |first_match: bool| {
+            RulesEngine::parse(&format!(
+                r#"
+            first_match = {}
+
+            [[rule]]
+            name = "office-phones"
+            sources = ["198.51.100.0/24"]
+            user_agent = "^LegacyPhone"
+            action = "allow"
+
+            [[rule]]
+            name = "trunk-port"
+            ports = [5080]
+            action = "block"
+
+            [[rule]]
+            name = "trace-invites"
+            methods = ["INVITE"]
+            action = "log-only"
+            "#,
+                first_match
+            ))
+            .unwrap()
+        };
+        let evaluate = |policy: &mut Policy, source: &str, port: u16| {
+            let mut packet = udp_packet(
+                ip(source),
+                sip_request("REGISTER", "LegacyPhone/1.0", "o1", 1),
+            );
+            packet.dest_port = port;
+            let request = SipParser::new()
+                .parse_udp_packet(&packet.payload, packet.source_ip)
+                .unwrap()
+                .with_ports(packet.source_port, packet.dest_port);
+            match policy.evaluate(&request) {
+                Verdict::Detect(detection) => Some((detection.reason, detection.rule)),
+                _ => None,
+            }
+        };
+
+        // 按顺序求值：办公网段的话机先被放行，其余来源访问 5080 端口被封禁
+        let whitelist = Arc::new(Mutex::new(Whitelist::default()));
+        let mut ordered = Policy::new(whitelist.clone()).with_rules(rules(true));
+        assert_eq!(evaluate(&mut ordered, PHONE, 5080), None);
+        assert_eq!(
+            evaluate(&mut ordered, SCANNER, 5080),
+            Some(("RULE_MATCH".to_string(), Some("trunk-port".to_string())))
+        );
+        assert_eq!(
+            evaluate(&mut ordered, SCANNER, 5060),
+            Some(("UA_NOT_ALLOWED".to_string(), None))
+        );
+
+        // 默认模式下所有规则都求值，封禁优先
+        let mut unordered = Policy::new(whitelist).with_rules(rules(false));
+        assert_eq!(
+            evaluate(&mut unordered, PHONE, 5080),
+            Some(("RULE_MATCH".to_string(), Some("trunk-port".to_string())))
+        );
+        assert_eq!(evaluate(&mut unordered, PHONE, 5060), None);
+
+        let invalid = RulesEngine::parse(
+            "[[rule]]\nname = \"bad\"\nsources = [\"10.0.0.0/99x\"]\naction = \"block\"",
+        );
+        assert!(invalid.is_err_and(|e| e.contains("sources")));
+    }
+}
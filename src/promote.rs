@@ -0,0 +1,87 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时请求的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+/// `POST /ha/promote` 响应
+#[derive(Debug, Deserialize)]
+struct PromoteReport {
+    #[serde(default)]
+    programmed: usize,
+}
+
+/// 升主参数
+#[derive(Debug)]
+struct PromoteOptions {
+    url: String,
+    token: String,
+    timeout: Duration,
+}
+
+impl PromoteOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            timeout: Duration::from_secs(60),
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            match flag.as_str() {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--timeout" => {
+                    options.timeout = Duration::from_secs(
+                        value
+                            .parse()
+                            .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))?,
+                    )
+                }
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock promote`：让以备机角色运行的守护进程升为主机
+///
+/// 守护进程按封禁表下发防火墙规则，之后照常封禁。本机已是主机时退出码为 1。
+pub fn run(args: &[String]) -> i32 {
+    let options = match PromoteOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    match request(&options) {
+        Ok(report) => {
+            println!("已升为主机，下发 {} 条封禁规则", report.programmed);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn request(options: &PromoteOptions) -> Result<PromoteReport, String> {
+    let url = format!("{}/ha/promote", options.url);
+    ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .build()
+        .post(&url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| format!("无法请求 {}: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 结构化的原因分类
+///
+/// 检测、事件和封禁记录中的 `reason` 是具体的原因代码（如 `MESSAGE_FLOOD`、`TLS_FINGERPRINT`），
+/// 种类多且插件、外部系统可以自定义。`ReasonCode` 把它们归为固定的几类，随事件、API 响应和
+/// 审计日志一起输出，下游自动化按类别分支即可，不需要跟踪每个具体代码。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReasonCode {
+    /// UA 不在白名单中（或白名单 UA 未通过二次验证）
+    UaNotAllowed,
+    /// 请求速率超限：UA 全局限速、按方法限速、灰名单观察期、TLS 新建连接、未完成的认证挑战
+    RateExceeded,
+    /// 命中扫描器特征：检测规则、TLS 指纹黑名单
+    ScannerSignature,
+    /// 按来源地理位置拒绝
+    GeoDeny,
+    /// PBX 报告认证失败或 ACL 拒绝
+    AuthFailure,
+    /// 畸形 SIP 报文或二进制垃圾数据
+    MalformedTraffic,
+    /// 灰名单首次出现，临时丢弃
+    Greylist,
+    /// 检测插件或 Lua 脚本的判定
+    Custom,
+    /// 外部情报（如 CrowdSec）
+    External,
+    /// 运维手动操作：API、gRPC、webhook
+    Manual,
+    /// UA 在白名单中而解封
+    Allowed,
+    /// 限时封禁到期或定期赦免而解封
+    Expired,
+    /// 程序自身的告警和核对（封禁无效、封禁延迟过高、规则缺失、启动核对）
+    System,
+    /// 无法归类（例如本次运行之前就存在的封禁）
+    #[default]
+    Unknown,
+}
+
+impl ReasonCode {
+    /// 把具体的原因代码归类
+    pub fn classify(reason: &str) -> Self {
+        match reason {
+            "UA_NOT_ALLOWED" | "UA_DENIED" | "UA_UNVERIFIED" => Self::UaNotAllowed,
+            "UA_RATE_EXCEEDED" | "GREYLIST_VIOLATION" | "TLS_SYN_RATE" | "AUTH_CHALLENGE" => {
+                Self::RateExceeded
+            }
+            reason if reason.ends_with("_FLOOD") => Self::RateExceeded,
+            "RULE_MATCH" | "TLS_FINGERPRINT" => Self::ScannerSignature,
+            reason if reason.starts_with("GEO_") => Self::GeoDeny,
+            "AUTH_FAILURE" | "ACL_DENIED" => Self::AuthFailure,
+            "MALFORMED_PACKET" | "BINARY_JUNK" => Self::MalformedTraffic,
+            "GREYLIST_HOLD" => Self::Greylist,
+            "PLUGIN" | "SCRIPT" => Self::Custom,
+            "CROWDSEC" => Self::External,
+            "MANUAL" | "WEBHOOK" => Self::Manual,
+            "UA_ALLOWED" => Self::Allowed,
+            "EXPIRED" | "AMNESTY" => Self::Expired,
+            "BAN_INEFFECTIVE" | "BAN_LATENCY_HIGH" | "RULE_MISSING" | "RECONCILE" => Self::System,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// 机器可读的代码
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UaNotAllowed => "UA_NOT_ALLOWED",
+            Self::RateExceeded => "RATE_EXCEEDED",
+            Self::ScannerSignature => "SCANNER_SIGNATURE",
+            Self::GeoDeny => "GEO_DENY",
+            Self::AuthFailure => "AUTH_FAILURE",
+            Self::MalformedTraffic => "MALFORMED_TRAFFIC",
+            Self::Greylist => "GREYLIST",
+            Self::Custom => "CUSTOM",
+            Self::External => "EXTERNAL",
+            Self::Manual => "MANUAL",
+            Self::Allowed => "ALLOWED",
+            Self::Expired => "EXPIRED",
+            Self::System => "SYSTEM",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::bans::BanRecord;
+use crate::enforcement::Enforcer;
+use crate::local_net::LocalNetworks;
+use crate::state_file::unix_now;
+use crate::whitelist::{ListMode, Whitelist};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// 按当前配置不应封禁却已封禁的 IP
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExemptBan {
+    pub ip: IpAddr,
+    /// 不应封禁的依据（本机地址、可信来源、局域网设备、UA 在白名单中）
+    pub note: String,
+}
+
+/// 封禁记录、防火墙规则与当前配置之间的差异
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconcileReport {
+    /// 有封禁记录（状态文件）但防火墙中没有规则
+    pub missing: Vec<BanRecord>,
+    /// 防火墙中有规则但没有封禁记录（手工添加或其他程序添加的规则）
+    pub foreign: Vec<IpAddr>,
+    /// 已封禁但按当前配置不应封禁
+    pub exempt: Vec<ExemptBan>,
+    /// 封禁记录已到期但防火墙中仍有规则（例如在停机期间到期）
+    pub expired: Vec<BanRecord>,
+}
+
+impl ReconcileReport {
+    /// 是否没有任何差异
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.foreign.is_empty()
+            && self.exempt.is_empty()
+            && self.expired.is_empty()
+    }
+
+    /// 把每一项差异写入日志
+    pub fn log(&self) {
+        if self.is_clean() {
+            info!("【核对】封禁记录、防火墙规则和当前配置一致");
+            return;
+        }
+        for record in &self.missing {
+            warn!(
+                "【核对】{} 有封禁记录（原因 {}）但防火墙中没有规则",
+                record.ip, record.reason.reason
+            );
+        }
+        for ip in &self.foreign {
+            warn!("【核对】防火墙中 {} 的规则没有封禁记录", ip);
+        }
+        for ban in &self.exempt {
+            warn!(
+                "【核对】{} 已封禁，但按当前配置不应封禁: {}",
+                ban.ip, ban.note
+            );
+        }
+        for record in &self.expired {
+            warn!(
+                "【核对】{} 的封禁（原因 {}）已到期，但防火墙中仍有规则",
+                record.ip, record.reason.reason
+            );
+        }
+    }
+
+    /// 解除已到期的封禁（无论封禁来自检测、管理接口还是集群同步），返回解除的条数
+    pub fn remove_expired(&self, enforcer: &Enforcer) -> usize {
+        let mut removed = 0;
+        for record in &self.expired {
+            match enforcer.unban(record.ip, "EXPIRED", "reconcile") {
+                Ok(true) => removed += 1,
+                Ok(false) => {}
+                Err(e) => error!("【核对】解除到期封禁 IP: {} 失败: {}", record.ip, e),
+            }
+        }
+        removed
+    }
+
+    /// 把外来规则记入封禁表（原因 `UNKNOWN`），之后与本工具的封禁一样统计命中、参与大赦，返回导入的条数
+    ///
+    /// 只导入记录，不改动规则。
+    pub fn adopt_foreign(&self, enforcer: &Enforcer) -> usize {
+        let records: Vec<BanRecord> = self
+            .foreign
+            .iter()
+            .map(|ip| BanRecord::unknown(*ip))
+            .collect();
+        enforcer.bans().restore(&records);
+        records.len()
+    }
+
+    /// 自动修复：补上缺失的封禁、解封不应封禁的 IP、解除已到期的封禁，返回修复的条数
+    ///
+    /// 外来规则可能是管理员手工添加的，只报告不删除。
+    pub fn repair(&self, enforcer: &Enforcer) -> usize {
+        let mut repaired = self.remove_expired(enforcer);
+        for record in &self.missing {
+            match enforcer.ban(record.ip, record.reason.clone()) {
+                Ok(_) => repaired += 1,
+                Err(e) => error!("【核对】补封 IP: {} 失败: {}", record.ip, e),
+            }
+        }
+        for ban in &self.exempt {
+            match enforcer.unban(ban.ip, "RECONCILE", "doctor") {
+                Ok(_) => repaired += 1,
+                Err(e) => error!("【核对】解封 IP: {} 失败: {}", ban.ip, e),
+            }
+        }
+        repaired
+    }
+}
+
+/// 核对执行器的封禁记录（启动时从状态文件或封禁数据库恢复）、防火墙中的规则和当前配置
+///
+/// 按当前配置不应封禁的 IP 包括本机地址、可信来源、局域网设备（`local_networks`）和触发封禁的 UA
+/// 已加入白名单的 IP；这些 IP 即使封禁记录缺失规则也不会列为需要补封。
+pub fn examine(
+    enforcer: &Enforcer,
+    whitelist: &Whitelist,
+    local_networks: Option<&LocalNetworks>,
+) -> Result<ReconcileReport, String> {
+    let blocked = enforcer.list_bans()?;
+    let records = enforcer.bans().records();
+    let exemption = |ip: &IpAddr, record: Option<&BanRecord>| -> Option<String> {
+        if enforcer.is_host_address(ip) {
+            return Some("本机地址".to_string());
+        }
+        if let Some(subnet) = enforcer.trusted_source(ip) {
+            return Some(format!("可信来源 {}", subnet));
+        }
+        if let Some(note) = local_networks.and_then(|networks| networks.matches(ip)) {
+            return Some(format!("局域网设备，{}", note));
+        }
+        let user_agent = record?.reason.user_agent.as_deref()?;
+        whitelist
+            .is_allowed(user_agent)
+            .then(|| match whitelist.mode() {
+                ListMode::Allow => format!("UA '{}' 在白名单中", user_agent),
+                ListMode::Deny => format!("UA '{}' 不在黑名单中", user_agent),
+            })
+    };
+
+    let present: HashSet<&IpAddr> = blocked.iter().collect();
+    let recorded: HashMap<IpAddr, &BanRecord> =
+        records.iter().map(|record| (record.ip, record)).collect();
+    let mut report = ReconcileReport::default();
+    let now = unix_now();
+    for record in &records {
+        // 已到期的封禁本就应当解除，规则仍在时列为待解除
+        if record.remaining_secs(now) == Some(0) {
+            if present.contains(&record.ip) {
+                report.expired.push(record.clone());
+            }
+            continue;
+        }
+        if !present.contains(&record.ip) && exemption(&record.ip, Some(record)).is_none() {
+            report.missing.push(record.clone());
+        }
+    }
+    for ip in &blocked {
+        let record = recorded.get(ip).copied();
+        if record.is_some_and(|record| record.remaining_secs(now) == Some(0)) {
+            continue;
+        }
+        if let Some(note) = exemption(ip, record) {
+            report.exempt.push(ExemptBan { ip: *ip, note });
+        } else if record.is_none() {
+            report.foreign.push(*ip);
+        }
+    }
+    report.foreign.sort();
+    report.exempt.sort_by_key(|ban| ban.ip);
+    Ok(report)
+}
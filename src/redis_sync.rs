@@ -0,0 +1,145 @@
+use crate::cluster::{ClusterMessage, ClusterSync};
+use crate::events::{EventBus, QueueReceiver};
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 连接断开后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 订阅连接的读超时，同时也是检查对端封禁到期的周期
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 基于 Redis pub/sub 的集群封禁同步
+///
+/// 本地的封禁/解封事件发布到频道，同时订阅该频道应用其他节点的封禁。
+pub struct RedisSync {
+    client: redis::Client,
+    channel: String,
+    cluster: Arc<ClusterSync>,
+}
+
+impl RedisSync {
+    /// 从环境变量创建，未设置 `UABLOCK_REDIS_URL` 时返回 None
+    ///
+    /// - `UABLOCK_REDIS_URL`：Redis 地址，例如 `redis://10.0.0.1:6379/`
+    /// - `UABLOCK_REDIS_CHANNEL`：频道名（默认 `uablock:bans`）
+    pub fn from_env(cluster: Arc<ClusterSync>) -> Result<Option<Self>, String> {
+        let url = match std::env::var("UABLOCK_REDIS_URL") {
+            Ok(url) if !url.is_empty() => url,
+            _ => return Ok(None),
+        };
+        let channel =
+            std::env::var("UABLOCK_REDIS_CHANNEL").unwrap_or_else(|_| "uablock:bans".to_string());
+        let client = redis::Client::open(url.as_str())
+            .map_err(|e| format!("Redis 地址无效 {}: {}", url, e))?;
+        Ok(Some(Self {
+            client,
+            channel,
+            cluster,
+        }))
+    }
+
+    /// 订阅本地事件并启动发布/订阅线程
+    pub fn start(self, events: &EventBus) -> Result<(), String> {
+        let (tx, rx) = events.queue();
+        let cluster = self.cluster.clone();
+        events.subscribe(move |event| match cluster.outgoing(event) {
+            Some(message) => tx.send(message),
+            None => true,
+        });
+
+        info!(
+            "【集群同步】节点 {} 通过 Redis 频道 {} 同步封禁",
+            self.cluster.node(),
+            self.channel
+        );
+
+        let client = self.client.clone();
+        let channel = self.channel.clone();
+        std::thread::Builder::new()
+            .name("redis-pub".to_string())
+            .spawn(move || publish_loop(client, &channel, rx))
+            .map_err(|e| format!("启动 Redis 发布线程失败: {}", e))?;
+        std::thread::Builder::new()
+            .name("redis-sub".to_string())
+            .spawn(move || self.subscribe_loop())
+            .map_err(|e| format!("启动 Redis 订阅线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 订阅频道并应用对端消息，断线后自动重连
+    fn subscribe_loop(self) {
+        loop {
+            if let Err(e) = self.subscribe_once() {
+                warn!(
+                    "【集群同步】Redis 订阅中断: {}，{} 秒后重连",
+                    e,
+                    RECONNECT_DELAY.as_secs()
+                );
+            }
+            self.cluster.expire();
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn subscribe_once(&self) -> redis::RedisResult<()> {
+        let mut connection = self.client.get_connection()?;
+        let mut pubsub = connection.as_pubsub();
+        pubsub.subscribe(&self.channel)?;
+        pubsub.set_read_timeout(Some(POLL_INTERVAL))?;
+        debug!("【集群同步】已订阅 Redis 频道 {}", self.channel);
+
+        loop {
+            match pubsub.get_message() {
+                Ok(message) => {
+                    let payload: String = message.get_payload()?;
+                    match serde_json::from_str::<ClusterMessage>(&payload) {
+                        Ok(message) => self.cluster.apply(&message),
+                        Err(e) => warn!("【集群同步】忽略无法解析的消息: {}", e),
+                    }
+                }
+                Err(e) if e.is_timeout() => {}
+                Err(e) => return Err(e),
+            }
+            self.cluster.expire();
+        }
+    }
+}
+
+/// 把本地事件发布到频道，连接失败时丢弃消息并在下一条消息时重连
+fn publish_loop(client: redis::Client, channel: &str, rx: QueueReceiver<ClusterMessage>) {
+    let mut connection: Option<redis::Connection> = None;
+    for message in rx {
+        let payload = match serde_json::to_string(&message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("【集群同步】序列化消息失败: {}", e);
+                continue;
+            }
+        };
+        if connection.is_none() {
+            connection = match client.get_connection() {
+                Ok(connection) => Some(connection),
+                Err(e) => {
+                    error!(
+                        "【集群同步】连接 Redis 失败，丢弃 IP {} 的同步消息: {}",
+                        message.ip, e
+                    );
+                    continue;
+                }
+            };
+        }
+        if let Some(conn) = connection.as_mut() {
+            let result: redis::RedisResult<()> =
+                redis::cmd("PUBLISH").arg(channel).arg(&payload).query(conn);
+            match result {
+                Ok(()) => debug!("【集群同步】已发布: {}", payload),
+                Err(e) => {
+                    error!("【集群同步】发布失败 IP {}: {}", message.ip, e);
+                    connection = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::bans::parse_duration;
+use log::{debug, info};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 本机对 REGISTER 应答 200 OK 的判断
+///
+/// 只看状态行和 CSeq 头部；抓包长度有限时报文可能被截断，因此按宽松的 UTF-8 解码。
+pub fn is_registration_ok(payload: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(payload);
+    let mut lines = text.split("\r\n");
+    if !lines
+        .next()
+        .is_some_and(|line| line.starts_with("SIP/2.0 200 "))
+    {
+        return false;
+    }
+    lines.take_while(|line| !line.is_empty()).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("CSeq")
+                && value.split_whitespace().nth(1) == Some("REGISTER")
+        })
+    })
+}
+
+/// 已注册终端：本机对其 REGISTER 应答过 200 OK 的来源
+///
+/// 能注册成功说明来源持有正确的账号密码，是自家的终端。有效期内这些来源不会仅因 UA 不在白名单中
+/// 被封禁（例如话机发出的辅助请求带着另一个 UA），速率限制、检测规则等其他检测照常生效。
+/// 应答由本机发出，需要在抓包接口上另外观察本机从 SIP 端口发出的数据包。
+pub struct RegisteredEndpoints {
+    ttl: Duration,
+    /// 来源 -> 最近一次注册成功的时间
+    endpoints: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl RegisteredEndpoints {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_REGISTERED_TTL` 时返回 None
+    ///
+    /// `UABLOCK_REGISTERED_TTL`：注册成功后免于 UA 封禁的时长（支持 `d`/`h`/`m`/`s`，如 `12h`）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_REGISTERED_TTL") {
+            Ok(value) if !value.is_empty() => {
+                let ttl = parse_duration(&value)
+                    .filter(|ttl| !ttl.is_zero())
+                    .ok_or_else(|| format!("UABLOCK_REGISTERED_TTL 无效: {}", value))?;
+                info!(
+                    "已注册终端豁免已启用: 注册成功的来源 {} 秒内不因 UA 封禁",
+                    ttl.as_secs()
+                );
+                Ok(Some(Self::new(ttl)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// 记录一次注册成功
+    pub fn record(&self, ip: IpAddr) {
+        let previous = self.endpoints.lock().unwrap().insert(ip, Instant::now());
+        if previous.is_none() {
+            debug!("【已注册终端】IP: {} 注册成功", ip);
+        }
+    }
+
+    /// 来源是否在有效期内注册成功过
+    pub fn is_registered(&self, ip: &IpAddr) -> bool {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(ip)
+            .is_some_and(|at| at.elapsed() < self.ttl)
+    }
+
+    /// 观察一个本机发出的数据包，是对 REGISTER 的 200 OK 时记录目标地址
+    pub fn observe_response(&self, dest_ip: IpAddr, payload: &[u8]) -> bool {
+        let ok = is_registration_ok(payload);
+        if ok {
+            self.record(dest_ip);
+        }
+        ok
+    }
+
+    /// 清理过期的记录
+    pub fn cleanup(&self) {
+        let ttl = self.ttl;
+        self.endpoints
+            .lock()
+            .unwrap()
+            .retain(|_, at| at.elapsed() < ttl);
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 在抓包接口上观察本机从 SIP 端口发出的应答，在后台线程中记录注册成功的来源
+    #[cfg(feature = "pcap")]
+    pub fn start(self: std::sync::Arc<Self>, interface: &str, ports: &[u16]) -> Result<(), String> {
+        use crate::packet_capture::{decode_frame, LinkLayer};
+
+        let mut capture = pcap::Capture::from_device(interface)
+            .map_err(|e| format!("无法打开网络接口 {}: {}", interface, e))?
+            .promisc(false)
+            .snaplen(2048)
+            .timeout(1000)
+            .open()
+            .map_err(|e| format!("无法开始抓包: {}", e))?;
+        let link = LinkLayer::of(&capture);
+        let filter = ports
+            .iter()
+            .map(|port| format!("src port {}", port))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        capture
+            .filter(&format!("udp and ({})", filter), true)
+            .map_err(|e| format!("设置过滤器失败: {}", e))?;
+
+        std::thread::Builder::new()
+            .name("registered".to_string())
+            .spawn(move || {
+                let mut last_cleanup = Instant::now();
+                loop {
+                    if last_cleanup.elapsed() >= Duration::from_secs(60) {
+                        self.cleanup();
+                        last_cleanup = Instant::now();
+                    }
+                    let packet = match capture.next_packet() {
+                        Ok(packet) => decode_frame(packet.data, link),
+                        Err(pcap::Error::TimeoutExpired) => None,
+                        Err(e) => {
+                            log::error!("【已注册终端】抓包错误: {}", e);
+                            return;
+                        }
+                    };
+                    if let Some(packet) = packet {
+                        self.observe_response(packet.dest_ip, &packet.payload);
+                    }
+                }
+            })
+            .map_err(|e| format!("启动已注册终端线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 未启用 `pcap` 特性时无法观察本机发出的应答
+    #[cfg(not(feature = "pcap"))]
+    pub fn start(
+        self: std::sync::Arc<Self>,
+        _interface: &str,
+        _ports: &[u16],
+    ) -> Result<(), String> {
+        Err("已注册终端豁免（UABLOCK_REGISTERED_TTL）需要 pcap 特性".to_string())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::whitelist::Whitelist;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+/// 白名单模式的最短长度；白名单双向模糊匹配，过短的模式会放行无关的 UA
+const MIN_PATTERN_LEN: usize = 4;
+
+/// PBX 注册表中的一个终端
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub user_agent: String,
+    pub ip: IpAddr,
+}
+
+/// 解析 FreeSWITCH `sofia status profile <profile> reg` 的输出
+///
+/// 每个注册以 `Call-ID:` 开始，取其中的 `Agent:` 和 `IP:` 两行。
+pub fn parse_sofia_status(text: &str) -> Vec<Registration> {
+    let mut registrations = Vec::new();
+    let mut user_agent = None;
+    let mut ip = None;
+    let mut flush = |user_agent: &mut Option<String>, ip: &mut Option<IpAddr>| {
+        if let (Some(user_agent), Some(ip)) = (user_agent.take(), ip.take()) {
+            registrations.push(Registration { user_agent, ip });
+        }
+    };
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "Call-ID" => flush(&mut user_agent, &mut ip),
+            "Agent" => user_agent = Some(value.to_string()),
+            "IP" => ip = value.parse().ok(),
+            _ => {}
+        }
+    }
+    flush(&mut user_agent, &mut ip);
+    registrations
+}
+
+/// 解析 Asterisk `database show registrar` 的输出（PJSIP 联系人）
+///
+/// 每行形如 `/registrar/contact/<id> : {"user_agent": "...", "via_addr": "...", "uri": "..."}`，
+/// 优先使用 `via_addr`，没有时取联系人 URI 中的主机。
+pub fn parse_astdb(text: &str) -> Vec<Registration> {
+    text.lines()
+        .filter(|line| line.starts_with("/registrar/contact/"))
+        .filter_map(|line| {
+            let contact: Value = serde_json::from_str(&line[line.find('{')?..]).ok()?;
+            let field = |name: &str| contact.get(name).and_then(Value::as_str).unwrap_or("");
+            let ip = field("via_addr")
+                .parse()
+                .ok()
+                .or_else(|| uri_host(field("uri")))?;
+            Some(Registration {
+                user_agent: field("user_agent").to_string(),
+                ip,
+            })
+        })
+        .collect()
+}
+
+/// SIP URI 中的主机（只接受 IP 地址）
+fn uri_host(uri: &str) -> Option<IpAddr> {
+    let rest = uri.split_once('@').map_or(uri, |(_, host)| host);
+    let rest = rest.trim_start_matches("sip:").trim_start_matches("sips:");
+    let host = match rest.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => rest.split([':', ';', '>']).next()?,
+    };
+    host.parse().ok()
+}
+
+/// 由 User-Agent 生成白名单模式：去掉版本号，转为小写
+///
+/// `Yealink SIP-T46S 66.86.0.15` → `yealink sip-t46s`，`MicroSIP/3.21.3` → `microsip`。
+pub fn ua_pattern(user_agent: &str) -> Option<String> {
+    let words: Vec<&str> = user_agent
+        .split_whitespace()
+        .map(|word| word.split('/').next().unwrap_or(word))
+        .filter(|word| !(word.contains('.') && word.chars().any(|c| c.is_ascii_digit())))
+        .filter(|word| !word.is_empty())
+        .collect();
+    Some(words.join(" ").to_lowercase()).filter(|pattern| !pattern.is_empty())
+}
+
+/// 由注册表生成的白名单条目
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportedEntries {
+    /// 现有白名单尚未覆盖的 UA 模式
+    pub user_agents: BTreeSet<String>,
+    /// 已注册终端的 IP
+    pub ips: BTreeSet<IpAddr>,
+    /// 过短、没有加入的 UA 模式
+    pub skipped: BTreeSet<String>,
+}
+
+impl ImportedEntries {
+    /// 汇总注册表，跳过现有白名单已覆盖的 UA
+    pub fn collect(registrations: &[Registration], whitelist: &Whitelist) -> Self {
+        let mut entries = Self::default();
+        for registration in registrations {
+            entries.ips.insert(registration.ip);
+            if whitelist.is_allowed(&registration.user_agent) {
+                continue;
+            }
+            match ua_pattern(&registration.user_agent) {
+                Some(pattern) if pattern.len() >= MIN_PATTERN_LEN => {
+                    entries.user_agents.insert(pattern);
+                }
+                Some(pattern) => {
+                    entries.skipped.insert(pattern);
+                }
+                None => {}
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn registration_tables_become_whitelist_entries() {
+        let sofia = "\
+Registrations:
+=================================================================================================
+Call-ID:        a1b2c3@192.0.2.10
+User:           1000@pbx.example.com
+Contact:        \"1000\" <sip:1000@192.0.2.10:5060>
+Agent:          Yealink SIP-T46S 66.86.0.15
+Status:         Registered(UDP)(unknown) EXP(2025-01-01 12:00:00) EXPSECS(3599)
+IP:             192.0.2.10
+Port:           5060
+
+Call-ID:        d4e5f6@198.51.100.7
+User:           1001@pbx.example.com
+Agent:          MicroSIP/3.21.3
+IP:             198.51.100.7
+
+Total items returned: 2
+";
+        let registrations = parse_sofia_status(sofia);
+        assert_eq!(registrations.len(), 2);
+        assert_eq!(registrations[0].user_agent, "Yealink SIP-T46S 66.86.0.15");
+        assert_eq!(registrations[0].ip, ip("192.0.2.10"));
+
+        let astdb = r#"/registrar/contact/1002;@9a1e      : {"user_agent":"Grandstream GXP2170 1.0.11.3","via_addr":"","uri":"sip:1002@[2001:db8::5]:5060;ob"}
+/registrar/contact/1003;@7b2c      : {"user_agent":"Zoiper rv2.10.18.2","via_addr":"192.0.2.20","uri":"sip:1003@10.0.0.5:5060"}
+/registrar/aor/1002                : {"contact":"1002;@9a1e"}
+2 results found."#;
+        let contacts = parse_astdb(astdb);
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[0].ip, ip("2001:db8::5"));
+        assert_eq!(contacts[1].ip, ip("192.0.2.20"));
+
+        assert_eq!(
+            ua_pattern("Yealink SIP-T46S 66.86.0.15").as_deref(),
+            Some("yealink sip-t46s")
+        );
+        assert_eq!(ua_pattern("Zoiper rv2.10.18.2").as_deref(), Some("zoiper"));
+
+        let all: Vec<_> = registrations.into_iter().chain(contacts).collect();
+        let entries = ImportedEntries::collect(&all, &Whitelist::default());
+        // MicroSIP 已在默认白名单中
+        assert_eq!(
+            entries.user_agents.iter().collect::<Vec<_>>(),
+            vec!["grandstream gxp2170", "yealink sip-t46s", "zoiper"]
+        );
+        assert_eq!(entries.ips.len(), 4);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{build_policy, initialize_whitelist};
+use chrono::{Local, TimeZone};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use uablock_rust::config::Config;
+use uablock_rust::limits::Limits;
+use uablock_rust::local_net::TrustedSources;
+use uablock_rust::tenants::Tenants;
+use uablock_rust::testing::MemoryFirewall;
+use uablock_rust::trace::{read_trace, replay, TraceOutcome};
+use uablock_rust::{Enforcer, EventBus, Pipeline, SipParser, Stats};
+
+/// `uablock replay-trace <轨迹文件> [--all]`：用当前配置重新执行判定轨迹，逐条对比判定结果
+///
+/// 配置取自与守护进程相同的 `--config` 配置文件和环境变量（白名单、惩罚分、UA 限速、检测规则、插件、灰名单、租户）；
+/// 封禁只作用于内存防火墙，不会修改本机规则。默认只输出判定不同的输入，`--all` 输出全部。
+/// 全部相同时退出码为 0，有差异时为 1。
+pub fn run(args: &[String], config: &Config) -> i32 {
+    let mut path = None;
+    let mut all = false;
+    for arg in args {
+        match arg.as_str() {
+            "--all" => all = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => {
+                eprintln!("未知参数: {}", arg);
+                return 2;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("用法: uablock replay-trace <轨迹文件> [--all]");
+        return 2;
+    };
+    let entries = match read_trace(Path::new(&path)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let mut pipeline = match build_pipeline(config) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let outcomes = replay(&mut pipeline, &entries);
+    let mut changed = 0;
+    for (entry, outcome) in entries.iter().zip(&outcomes) {
+        let same = entry.outcome.same_decision(outcome);
+        if !same {
+            changed += 1;
+        }
+        if all || !same {
+            println!(
+                "{} {} {:<39} {:<32} -> {}",
+                if same { " " } else { "!" },
+                format_time(entry.at),
+                entry.input.source_ip().to_string(),
+                describe(&entry.outcome),
+                describe(outcome)
+            );
+        }
+    }
+    println!("共 {} 条输入，{} 条判定不同", entries.len(), changed);
+    i32::from(changed > 0)
+}
+
+/// 与守护进程相同配置的流水线，封禁作用于内存防火墙
+fn build_pipeline(config: &Config) -> Result<Pipeline, String> {
+    let whitelist = Arc::new(Mutex::new(initialize_whitelist(config)?));
+    let limits = Limits::from_env()?;
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    if let Some(trusted) = TrustedSources::from_env()? {
+        enforcer.set_trusted_sources(trusted);
+    }
+    let mut builder = Pipeline::builder(Arc::new(enforcer))
+        .parser(SipParser::new())
+        .policy(build_policy(whitelist, &limits)?)
+        .transaction_limit(limits.max_transactions);
+    if let Some(tenants) = Tenants::from_env()? {
+        builder = builder.tenants(tenants);
+    }
+    Ok(builder.build())
+}
+
+/// 判定、原因代码和规则
+fn describe(outcome: &TraceOutcome) -> String {
+    let mut text = outcome.verdict.clone();
+    if let Some(reason) = &outcome.reason {
+        text.push_str(&format!(" {}", reason));
+    }
+    if let Some(rule) = &outcome.rule {
+        text.push_str(&format!(" ({})", rule));
+    }
+    text
+}
+
+fn format_time(timestamp: u64) -> String {
+    Local
+        .timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map_or("-".to_string(), |t| {
+            t.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+}
@@ -0,0 +1,181 @@
+use crate::sip_parser::SipRequest;
+use log::debug;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// SIP 事务超时时间（64 * T1 = 32 秒），超过该时间的相同请求视为新事务
+const TRANSACTION_WINDOW: Duration = Duration::from_secs(32);
+
+/// 事务标识：源 IP + Call-ID + CSeq + Via branch
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TransactionKey {
+    source_ip: IpAddr,
+    call_id: String,
+    cseq: String,
+    branch: String,
+}
+
+/// 单个来源的重传统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceStats {
+    /// 收到的请求总数（含重传）
+    pub total: u64,
+    /// 其中被识别为重传的数量
+    pub retransmissions: u64,
+}
+
+impl SourceStats {
+    /// 重传率（0.0 ~ 1.0）
+    pub fn retransmission_rate(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.retransmissions as f64 / self.total as f64
+        }
+    }
+}
+
+/// 重传感知的事务跟踪器
+///
+/// UDP 上的 SIP 请求在未收到响应时会按定时器重传，同一事务的重传
+/// 不应重复计入速率和惩罚计数，因此这里按 Call-ID/CSeq/branch 去重。
+///
+/// 用 [`RetransmissionTracker::with_limit`] 创建时事务表和来源统计的大小有上限：表满时先清理
+/// 过期事务，仍然已满则不再记录新事务（之后的重传会被当作新事务），也不再统计新来源。
+pub struct RetransmissionTracker {
+    transactions: HashMap<TransactionKey, Instant>,
+    sources: HashMap<IpAddr, SourceStats>,
+    /// 事务表和来源统计的最大条目数，0 表示不限制
+    limit: usize,
+    untracked: u64,
+    /// 上次清理过期事务的时间（表满时最多每秒清理一次）
+    expired_at: Instant,
+}
+
+impl RetransmissionTracker {
+    pub fn new() -> Self {
+        Self::with_limit(0)
+    }
+
+    /// 最多跟踪 `limit` 个事务（0 表示不限制）
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            sources: HashMap::new(),
+            limit,
+            untracked: 0,
+            expired_at: Instant::now(),
+        }
+    }
+
+    /// 记录一个请求，返回 true 表示这是一个新事务，false 表示是重传
+    ///
+    /// 缺少 Call-ID 或 CSeq 的请求无法识别事务，总是按新事务处理
+    pub fn observe(&mut self, request: &SipRequest) -> bool {
+        let new = self.observe_transaction(request);
+        let full = self.limit > 0 && self.sources.len() >= self.limit;
+        if !full || self.sources.contains_key(&request.source_ip) {
+            let stats = self.sources.entry(request.source_ip).or_default();
+            stats.total += 1;
+            if !new {
+                stats.retransmissions += 1;
+            }
+        }
+        new
+    }
+
+    fn observe_transaction(&mut self, request: &SipRequest) -> bool {
+        let (call_id, cseq) = match (&request.call_id, &request.cseq) {
+            (Some(call_id), Some(cseq)) => (call_id.clone(), cseq.clone()),
+            _ => return true,
+        };
+
+        let key = TransactionKey {
+            source_ip: request.source_ip,
+            call_id,
+            cseq,
+            branch: request.branch.clone().unwrap_or_default(),
+        };
+
+        let now = Instant::now();
+        match self.transactions.get(&key) {
+            Some(first_seen) if now.duration_since(*first_seen) < TRANSACTION_WINDOW => {
+                debug!(
+                    "识别到重传: IP {}, Call-ID {}, CSeq {}",
+                    request.source_ip, key.call_id, key.cseq
+                );
+                false
+            }
+            Some(_) => {
+                self.transactions.insert(key, now);
+                true
+            }
+            None => {
+                if self.limit > 0
+                    && self.transactions.len() >= self.limit
+                    && now.duration_since(self.expired_at) >= Duration::from_secs(1)
+                {
+                    self.expire(now);
+                }
+                if self.limit > 0 && self.transactions.len() >= self.limit {
+                    self.untracked += 1;
+                } else {
+                    self.transactions.insert(key, now);
+                }
+                true
+            }
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.expired_at = now;
+        self.transactions
+            .retain(|_, first_seen| now.duration_since(*first_seen) < TRANSACTION_WINDOW);
+    }
+
+    /// 正在跟踪的事务数
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// 因表满未记录的事务数（累计）
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+
+    /// 获取指定来源的统计
+    #[allow(dead_code)]
+    pub fn source_stats(&self, ip: &IpAddr) -> Option<SourceStats> {
+        self.sources.get(ip).copied()
+    }
+
+    /// 按重传数量降序返回前 n 个有重传的来源
+    pub fn top_retransmitters(&self, n: usize) -> Vec<(IpAddr, SourceStats)> {
+        let mut list: Vec<(IpAddr, SourceStats)> = self
+            .sources
+            .iter()
+            .filter(|(_, stats)| stats.retransmissions > 0)
+            .map(|(ip, stats)| (*ip, *stats))
+            .collect();
+        list.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.retransmissions));
+        list.truncate(n);
+        list
+    }
+
+    /// 清理过期事务，并重置来源统计（统计按清理周期计算）
+    pub fn cleanup(&mut self) {
+        self.expire(Instant::now());
+        self.sources.clear();
+    }
+}
+
+impl Default for RetransmissionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use uablock_rust::state_file::unix_now;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时请求的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+const USAGE: &str = "用法: uablock-rust review [--json] [--url <地址>] [--token <token>]
+       uablock-rust review approve|reject|whitelist <IP> [--url <地址>] [--token <token>]";
+
+/// `/review` 响应中的一个待审核封禁
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingReport {
+    ip: IpAddr,
+    method: String,
+    user_agent: String,
+    reason: String,
+    code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rule: Option<String>,
+    queued_at: u64,
+    enforce_at: u64,
+    hits: u64,
+}
+
+/// 审核参数
+#[derive(Debug)]
+struct ReviewOptions {
+    url: String,
+    token: String,
+    timeout: Duration,
+    json: bool,
+    /// 审核操作和目标 IP，未指定时列出队列
+    action: Option<(String, IpAddr)>,
+}
+
+impl ReviewOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            timeout: Duration::from_secs(10),
+            json: false,
+            action: None,
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--json" {
+                options.json = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            match flag.as_str() {
+                "approve" | "reject" | "whitelist" if options.action.is_none() => {
+                    let ip = value.parse().map_err(|_| format!("无效的 IP: {}", value))?;
+                    options.action = Some((flag.clone(), ip));
+                }
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--timeout" => {
+                    options.timeout = Duration::from_secs(
+                        value
+                            .parse()
+                            .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))?,
+                    )
+                }
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock review`：查看运行中守护进程的待审核封禁，并批准、驳回或把 UA 加入白名单
+///
+/// 不带操作时列出队列（IP、原因、UA、检测次数、距自动封禁的剩余时间），`--json` 输出原始记录；
+/// `approve <IP>` 立即封禁，`reject <IP>` 驳回（24 小时内该来源只告警），`whitelist <IP>`
+/// 驳回并把该来源的 UA 加入白名单。
+pub fn run(args: &[String]) -> i32 {
+    let options = match ReviewOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let result = match &options.action {
+        Some((action, ip)) => review(&options, action, *ip),
+        None => list(&options),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn list(options: &ReviewOptions) -> Result<(), String> {
+    let pending: Vec<PendingReport> = request(options, "GET", "/review")?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))?;
+    if options.json {
+        let json = serde_json::to_string_pretty(&pending).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let now = unix_now();
+    println!(
+        "{:<39} {:<17} {:<18} {:<8} {:<19} {:>6} {:>8}  UA",
+        "IP", "CODE", "REASON", "METHOD", "QUEUED", "HITS", "BAN IN"
+    );
+    for pending in &pending {
+        println!(
+            "{:<39} {:<17} {:<18} {:<8} {:<19} {:>6} {:>7}s  {}",
+            pending.ip.to_string(),
+            pending.code,
+            pending.reason,
+            pending.method,
+            format_time(pending.queued_at),
+            pending.hits,
+            pending.enforce_at.saturating_sub(now),
+            pending.user_agent,
+        );
+    }
+    println!("共 {} 个待审核封禁", pending.len());
+    Ok(())
+}
+
+fn review(options: &ReviewOptions, action: &str, ip: IpAddr) -> Result<(), String> {
+    let pending: PendingReport = request(options, "POST", &format!("/review/{}/{}", ip, action))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))?;
+    let result = match action {
+        "approve" => "已封禁".to_string(),
+        "reject" => "已驳回".to_string(),
+        _ => format!("已驳回，UA {:?} 已加入白名单", pending.user_agent),
+    };
+    println!("{} ({}): {}", pending.ip, pending.reason, result);
+    Ok(())
+}
+
+fn request(options: &ReviewOptions, method: &str, path: &str) -> Result<ureq::Response, String> {
+    let url = format!("{}{}", options.url, path);
+    ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .build()
+        .request(method, &url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| match e {
+            ureq::Error::Status(_, response) => {
+                let status = response.status();
+                let body = response.into_string().unwrap_or_default();
+                format!("{} 返回 {}: {}", url, status, body.trim())
+            }
+            e => format!("无法请求 {}: {}", url, e),
+        })
+}
+
+fn format_time(timestamp: u64) -> String {
+    Local
+        .timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map_or("-".to_string(), |t| {
+            t.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+}
@@ -0,0 +1,159 @@
+use crate::bans::parse_duration;
+use crate::enforcement::Enforcer;
+use crate::events::{Event, EventKind};
+use log::{debug, error, info, warn};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 规则核查告警的原因代码
+pub const RULE_MISSING: &str = "RULE_MISSING";
+
+/// 默认核查间隔
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// 默认每次核查的最近封禁数
+const DEFAULT_BATCH: usize = 200;
+
+/// 日志中最多列出的缺失 IP 数
+const LOG_LIMIT: usize = 10;
+
+/// 封禁规则的定期核查
+///
+/// 封禁时不再逐条复查规则是否生效（每次封禁都要多执行几次 iptables 命令），改为定期取一批最近的
+/// 封禁，与一次性列出的防火墙规则（`iptables -S` / nft 集合）对照。有封禁记录但没有规则时汇总成
+/// 一条告警，同样的缺失不重复告警。
+pub struct RuleAudit {
+    interval: Duration,
+    batch: usize,
+    /// 上次核查时缺失规则的 IP
+    reported: Mutex<HashSet<IpAddr>>,
+}
+
+impl RuleAudit {
+    pub fn new(interval: Duration, batch: usize) -> Self {
+        Self {
+            interval,
+            batch,
+            reported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// 从环境变量创建，`UABLOCK_RULE_AUDIT_INTERVAL=0` 时返回 None
+    ///
+    /// - `UABLOCK_RULE_AUDIT_INTERVAL`：核查间隔（支持 `d`/`h`/`m`/`s`，默认 5m）
+    /// - `UABLOCK_RULE_AUDIT_BATCH`：每次核查的最近封禁数（默认 200）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let interval = match std::env::var("UABLOCK_RULE_AUDIT_INTERVAL") {
+            Ok(value) if value == "0" => return Ok(None),
+            Ok(value) if !value.is_empty() => parse_duration(&value)
+                .filter(|interval| !interval.is_zero())
+                .ok_or_else(|| format!("UABLOCK_RULE_AUDIT_INTERVAL 无效: {}", value))?,
+            _ => DEFAULT_INTERVAL,
+        };
+        let batch = match std::env::var("UABLOCK_RULE_AUDIT_BATCH") {
+            Ok(value) if !value.is_empty() => value
+                .parse::<usize>()
+                .ok()
+                .filter(|&batch| batch > 0)
+                .ok_or_else(|| format!("UABLOCK_RULE_AUDIT_BATCH 无效: {}", value))?,
+            _ => DEFAULT_BATCH,
+        };
+        Ok(Some(Self::new(interval, batch)))
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// 核查一批最近的封禁，返回有记录但没有规则的 IP
+    ///
+    /// 出现新的缺失时输出一条 `【规则核查】` 错误日志并发布一个 Alert 事件（IP 为其中最近封禁的一个）。
+    /// 未启用内置封禁或本机为备机时不核查。
+    pub fn audit(&self, enforcer: &Enforcer) -> Result<Vec<IpAddr>, String> {
+        let Some(firewall) = enforcer.firewall() else {
+            return Ok(Vec::new());
+        };
+        let mut recent: Vec<_> = enforcer
+            .bans()
+            .records()
+            .into_iter()
+            .filter(|record| record.banned_at.is_some())
+            .collect();
+        recent.sort_by_key(|record| std::cmp::Reverse(record.banned_at));
+        recent.truncate(self.batch);
+        if recent.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let blocked: HashSet<IpAddr> = firewall.list_blocked()?.into_iter().collect();
+        // 列出规则期间被解封的 IP 已没有记录，不算缺失
+        let missing: Vec<IpAddr> = recent
+            .iter()
+            .map(|record| record.ip)
+            .filter(|ip| !blocked.contains(ip) && enforcer.bans().get(ip).is_some())
+            .collect();
+        debug!(
+            "【规则核查】核查最近 {} 个封禁，{} 个缺失规则",
+            recent.len(),
+            missing.len()
+        );
+
+        let mut reported = self.reported.lock().unwrap();
+        let current: HashSet<IpAddr> = missing.iter().copied().collect();
+        if missing.is_empty() {
+            if !reported.is_empty() {
+                info!("【规则核查】最近的封禁规则均已生效");
+            }
+        } else if !current.is_subset(&reported) {
+            let listed: Vec<String> = missing
+                .iter()
+                .take(LOG_LIMIT)
+                .map(|ip| ip.to_string())
+                .collect();
+            error!(
+                "【规则核查】最近 {} 个封禁中有 {} 个没有防火墙规则: {}{}",
+                recent.len(),
+                missing.len(),
+                listed.join(", "),
+                if missing.len() > LOG_LIMIT {
+                    " 等"
+                } else {
+                    ""
+                }
+            );
+            enforcer.events().publish(Event::new(
+                EventKind::Alert,
+                missing[0],
+                "",
+                RULE_MISSING,
+                "rule-audit",
+            ));
+        }
+        *reported = current;
+        Ok(missing)
+    }
+
+    /// 在后台线程中定期核查，执行器被释放后退出
+    pub fn start(self, enforcer: &Arc<Enforcer>) -> Result<(), String> {
+        let enforcer = Arc::downgrade(enforcer);
+        std::thread::Builder::new()
+            .name("rule-audit".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(self.interval);
+                let Some(enforcer) = enforcer.upgrade() else {
+                    break;
+                };
+                if let Err(e) = self.audit(&enforcer) {
+                    warn!("【规则核查】无法列出防火墙规则: {}", e);
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动规则核查线程失败: {}", e))
+    }
+}
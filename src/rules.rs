@@ -0,0 +1,735 @@
+use crate::bans::parse_duration;
+use crate::local_net::Subnet;
+use crate::reason::ReasonCode;
+use crate::state_file::RuleCounterRecord;
+use chrono::{Local, NaiveDate};
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// 规则命中后的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// 立即封禁来源
+    #[serde(alias = "block")]
+    Ban,
+    /// 为来源累加惩罚分
+    Strike,
+    /// 只记录日志
+    #[serde(alias = "log-only")]
+    Log,
+    /// 放行（视为在 UA 白名单中），用于临时白名单
+    Allow,
+}
+
+/// 规则文件中的单条规则定义
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    name: String,
+    /// 匹配的 SIP 方法，为空表示任意方法
+    #[serde(default)]
+    methods: Vec<String>,
+    /// User-Agent 正则表达式
+    user_agent: Option<String>,
+    /// 来源国家代码（需要 GeoIP 数据）
+    #[serde(default)]
+    countries: Vec<String>,
+    /// 来源地址（单个 IP 或 CIDR 网段）
+    #[serde(default)]
+    sources: Vec<String>,
+    /// 目标端口（被访问的本机端口）
+    #[serde(default)]
+    ports: Vec<u16>,
+    /// 来源当前惩罚分下限
+    min_score: Option<f64>,
+    /// 计数窗口（秒）
+    #[serde(default = "default_window")]
+    window: u64,
+    /// 窗口内命中次数达到该值时执行动作
+    #[serde(default = "default_threshold")]
+    threshold: u32,
+    action: RuleAction,
+    /// action = "strike" 时累加的惩罚分
+    #[serde(default = "default_strike_weight")]
+    strike_weight: f64,
+    /// 失效日期（YYYY-MM-DD，本地时间当天 0 点起不再生效）
+    expires: Option<String>,
+    /// 封禁的原因分类，省略时按条件推断
+    code: Option<ReasonCode>,
+}
+
+fn default_window() -> u64 {
+    60
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+fn default_strike_weight() -> f64 {
+    1.0
+}
+
+/// 按触发方法处置检测的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MethodAction {
+    /// 封禁来源
+    Ban,
+    /// 只告警，不封禁
+    Alert,
+}
+
+/// 规则文件 `[policy.<方法>]` 段的定义
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MethodPolicyDefinition {
+    #[serde(default = "default_method_action")]
+    action: MethodAction,
+    /// 封禁时长（支持 `d`/`h`/`m`/`s`），省略表示不会自动解封
+    ban_duration: Option<String>,
+}
+
+fn default_method_action() -> MethodAction {
+    MethodAction::Ban
+}
+
+/// 某个 SIP 方法触发的检测的处置方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodPolicy {
+    pub action: MethodAction,
+    /// 封禁时长，None 表示不会自动解封
+    pub ban_duration: Option<Duration>,
+}
+
+/// 规则文件
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    /// 规则包版本（如 `2026.10.1`），加载时写入日志
+    version: Option<String>,
+    /// 按文件中的顺序求值，第一条达到阈值的 ban/allow 规则决定结果，之后的规则不再求值
+    #[serde(default)]
+    first_match: bool,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleDefinition>,
+    /// 按触发方法的处置方式，如 `[policy.INVITE]`
+    #[serde(default)]
+    policy: HashMap<String, MethodPolicyDefinition>,
+}
+
+/// 编译后的规则
+struct Rule {
+    name: String,
+    methods: Vec<String>,
+    user_agent: Option<Regex>,
+    countries: Vec<String>,
+    sources: Vec<Subnet>,
+    ports: Vec<u16>,
+    min_score: Option<f64>,
+    window: Duration,
+    threshold: u32,
+    action: RuleAction,
+    strike_weight: f64,
+    expires: Option<NaiveDate>,
+    code: ReasonCode,
+    /// 每个来源在当前窗口内的命中计数
+    counters: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl Rule {
+    fn compile(def: RuleDefinition) -> Result<Self, String> {
+        if def.name.is_empty() || def.name.chars().any(char::is_whitespace) {
+            return Err(format!("规则名称 '{}' 不能为空或包含空白字符", def.name));
+        }
+        if def.threshold == 0 {
+            return Err(format!("规则 '{}' 的 threshold 必须大于 0", def.name));
+        }
+        let user_agent = match &def.user_agent {
+            Some(pattern) => Some(
+                Regex::new(pattern)
+                    .map_err(|e| format!("规则 '{}' 的 user_agent 正则无效: {}", def.name, e))?,
+            ),
+            None => None,
+        };
+        let sources = def
+            .sources
+            .iter()
+            .map(|source| {
+                Subnet::parse(source).ok_or_else(|| {
+                    format!(
+                        "规则 '{}' 的 sources 中 '{}' 不是有效的 IP 或网段",
+                        def.name, source
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let expires = match &def.expires {
+            Some(date) => Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                format!(
+                    "规则 '{}' 的 expires 应为 YYYY-MM-DD 格式的日期: {}",
+                    def.name, date
+                )
+            })?),
+            None => None,
+        };
+        // 限定国家的规则视为地理位置拒绝，需要累计多次的视为速率超限，其余视为扫描器特征
+        let code = def.code.unwrap_or(if !def.countries.is_empty() {
+            ReasonCode::GeoDeny
+        } else if def.threshold > 1 {
+            ReasonCode::RateExceeded
+        } else {
+            ReasonCode::ScannerSignature
+        });
+        Ok(Self {
+            name: def.name,
+            methods: def.methods.iter().map(|m| m.to_uppercase()).collect(),
+            user_agent,
+            countries: def.countries.iter().map(|c| c.to_uppercase()).collect(),
+            sources,
+            ports: def.ports,
+            min_score: def.min_score,
+            window: Duration::from_secs(def.window),
+            threshold: def.threshold,
+            action: def.action,
+            strike_weight: def.strike_weight,
+            expires,
+            code,
+            counters: HashMap::new(),
+        })
+    }
+
+    /// 规则在 today 是否已过期
+    fn is_expired(&self, today: NaiveDate) -> bool {
+        self.expires.is_some_and(|expires| today >= expires)
+    }
+
+    /// 检查静态条件（方法、UA、国家、来源地址、目标端口、惩罚分）
+    fn matches(&self, ctx: &RuleContext) -> bool {
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m == ctx.method) {
+            return false;
+        }
+        if !self.sources.is_empty()
+            && !self
+                .sources
+                .iter()
+                .any(|subnet| subnet.contains(&ctx.source_ip))
+        {
+            return false;
+        }
+        if !self.ports.is_empty() {
+            match ctx.dest_port {
+                Some(port) if self.ports.contains(&port) => {}
+                _ => return false,
+            }
+        }
+        if let Some(regex) = &self.user_agent {
+            if !regex.is_match(ctx.user_agent) {
+                return false;
+            }
+        }
+        if !self.countries.is_empty() {
+            match ctx.country {
+                Some(country) if self.countries.iter().any(|c| c == country) => {}
+                _ => return false,
+            }
+        }
+        if let Some(min_score) = self.min_score {
+            if ctx.score < min_score {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 规则求值所需的上下文
+pub struct RuleContext<'a> {
+    pub source_ip: IpAddr,
+    /// 目标端口（未知时为 None，限定端口的规则不匹配）
+    pub dest_port: Option<u16>,
+    pub method: &'a str,
+    pub user_agent: &'a str,
+    /// 来源国家代码（无 GeoIP 数据时为 None）
+    pub country: Option<&'a str>,
+    /// 来源当前惩罚分
+    pub score: f64,
+}
+
+/// 规则触发结果
+#[derive(Debug, Clone)]
+pub struct RuleHit {
+    pub rule: String,
+    pub action: RuleAction,
+    pub strike_weight: f64,
+    /// 规则的原因分类
+    pub code: ReasonCode,
+}
+
+/// 已过期的规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiredRule {
+    pub rule: String,
+    pub expires: NaiveDate,
+}
+
+/// 声明式检测规则引擎
+///
+/// 规则从 TOML 文件加载，每条规则由条件（方法/UA/国家/来源地址/目标端口/惩罚分）、
+/// 计数窗口、阈值和动作组成，运维无需重新编译即可增加检测。
+/// 设置了 `expires` 的规则从该日期起被忽略（例如只放行两周的临时软电话）。
+///
+/// 默认所有规则都求值，命中 ban 的规则优先于 allow；规则文件设置 `first_match = true` 时
+/// 按顺序求值，第一条达到阈值的 ban/allow 规则决定结果（类似防火墙规则表），
+/// 可以先放行某个网段的某类终端，再封禁其余来源。
+pub struct RulesEngine {
+    rules: Vec<Rule>,
+    first_match: bool,
+    version: Option<String>,
+    /// SIP 方法（大写）→ 该方法触发的检测的处置方式
+    methods: HashMap<String, MethodPolicy>,
+}
+
+impl RulesEngine {
+    /// 从 TOML 规则文件加载
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取规则文件 {} 失败: {}", path.display(), e))?;
+        Self::load_content(path, &content)
+    }
+
+    /// 解析已读取（例如已校验签名）的规则文件内容，`path` 只用于日志和错误信息
+    pub fn load_content(path: &Path, content: &str) -> Result<Self, String> {
+        let engine =
+            Self::parse(content).map_err(|e| format!("规则文件 {} 无效: {}", path.display(), e))?;
+        info!(
+            "从 {} 加载了 {} 条检测规则（版本: {}）",
+            path.display(),
+            engine.rules.len(),
+            engine.version.as_deref().unwrap_or("未标注")
+        );
+        for (method, policy) in &engine.methods {
+            info!(
+                "{} 触发的检测: {:?}，封禁时长: {}",
+                method,
+                policy.action,
+                policy
+                    .ban_duration
+                    .map_or("不限".to_string(), |d| format!("{} 秒", d.as_secs()))
+            );
+        }
+        for expired in engine.expired() {
+            warn!(
+                "规则 '{}' 已于 {} 过期，不会生效",
+                expired.rule, expired.expires
+            );
+        }
+        Ok(engine)
+    }
+
+    /// 解析 TOML 格式的规则
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: RulesFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(Rule::compile)
+            .collect::<Result<Vec<_>, _>>()?;
+        let methods =
+            file.policy
+                .into_iter()
+                .map(|(method, def)| {
+                    let ban_duration = match &def.ban_duration {
+                        Some(value) => {
+                            Some(parse_duration(value).filter(|d| !d.is_zero()).ok_or_else(
+                                || format!("[policy.{}] 的 ban_duration 无效: {}", method, value),
+                            )?)
+                        }
+                        None => None,
+                    };
+                    Ok((
+                        method.to_uppercase(),
+                        MethodPolicy {
+                            action: def.action,
+                            ban_duration,
+                        },
+                    ))
+                })
+                .collect::<Result<HashMap<_, _>, String>>()?;
+        Ok(Self {
+            rules,
+            first_match: file.first_match,
+            version: file.version,
+            methods,
+        })
+    }
+
+    /// 对一次请求求值，返回所有达到阈值的规则
+    ///
+    /// `first_match` 模式下到第一条达到阈值的 ban/allow 规则为止。
+    pub fn evaluate(&mut self, ctx: &RuleContext) -> Vec<RuleHit> {
+        let now = Instant::now();
+        let today = Local::now().date_naive();
+        let mut hits = Vec::new();
+        for rule in &mut self.rules {
+            if rule.is_expired(today) || !rule.matches(ctx) {
+                continue;
+            }
+            let window = rule.window;
+            let counter = rule.counters.entry(ctx.source_ip).or_insert((now, 0));
+            if now.duration_since(counter.0) >= window {
+                *counter = (now, 0);
+            }
+            counter.1 += 1;
+            if counter.1 >= rule.threshold {
+                rule.counters.remove(&ctx.source_ip);
+                hits.push(RuleHit {
+                    rule: rule.name.clone(),
+                    action: rule.action,
+                    strike_weight: rule.strike_weight,
+                    code: rule.code,
+                });
+                if self.first_match && matches!(rule.action, RuleAction::Ban | RuleAction::Allow) {
+                    break;
+                }
+            }
+        }
+        hits
+    }
+
+    /// 规则文件中标注的版本
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// 规则文件 `[policy.<方法>]` 段配置的处置方式（方法名为大写）
+    pub fn method_policies(&self) -> &HashMap<String, MethodPolicy> {
+        &self.methods
+    }
+
+    /// 已过期（不再生效）的规则
+    pub fn expired(&self) -> Vec<ExpiredRule> {
+        let today = Local::now().date_naive();
+        self.rules
+            .iter()
+            .filter(|rule| rule.is_expired(today))
+            .filter_map(|rule| {
+                rule.expires.map(|expires| ExpiredRule {
+                    rule: rule.name.clone(),
+                    expires,
+                })
+            })
+            .collect()
+    }
+
+    /// 导出各规则的计数窗口，用于持久化
+    pub fn snapshot(&self) -> Vec<RuleCounterRecord> {
+        let now = Instant::now();
+        self.rules
+            .iter()
+            .flat_map(|rule| {
+                rule.counters
+                    .iter()
+                    .map(move |(ip, (started, count))| RuleCounterRecord {
+                        rule: rule.name.clone(),
+                        ip: *ip,
+                        count: *count,
+                        age_secs: now.duration_since(*started).as_secs_f64(),
+                    })
+            })
+            .collect()
+    }
+
+    /// 按规则名称恢复计数窗口，扣除停机时长（elapsed_secs）后已结束的窗口被丢弃
+    pub fn restore(&mut self, records: &[RuleCounterRecord], elapsed_secs: f64) {
+        let now = Instant::now();
+        for record in records {
+            let Some(rule) = self.rules.iter_mut().find(|r| r.name == record.rule) else {
+                continue;
+            };
+            let Ok(age) = Duration::try_from_secs_f64((record.age_secs + elapsed_secs).max(0.0))
+            else {
+                warn!(
+                    "忽略无效的规则计数记录: {} {}（age_secs={}）",
+                    record.rule, record.ip, record.age_secs
+                );
+                continue;
+            };
+            if age >= rule.window {
+                continue;
+            }
+            rule.counters.insert(
+                record.ip,
+                (now.checked_sub(age).unwrap_or(now), record.count),
+            );
+        }
+    }
+
+    /// 清理过期的计数窗口
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        for rule in &mut self.rules {
+            let window = rule.window;
+            rule.counters
+                .retain(|_, (started, _)| now.duration_since(*started) < window);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ctx<'a>(method: &'a str, user_agent: &'a str) -> RuleContext<'a> {
+        RuleContext {
+            source_ip: SCANNER.parse().unwrap(),
+            dest_port: Some(5060),
+            method,
+            user_agent,
+            country: None,
+            score: 0.0,
+        }
+    }
+
+    fn names(hits: &[RuleHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.rule.as_str()).collect()
+    }
+
+    #[test]
+    fn every_condition_must_match() {
+        let mut engine = RulesEngine::parse(
+            r#"
+            [[rule]]
+            name = "scanner-invite"
+            methods = ["invite"]
+            user_agent = "(?i)sipvicious"
+            sources = ["203.0.113.0/24"]
+            ports = [5060]
+            countries = ["nl"]
+            min_score = 1.5
+            action = "ban"
+            "#,
+        )
+        .unwrap();
+        let matching = RuleContext {
+            country: Some("NL"),
+            score: 2.0,
+            ..ctx("INVITE", "SIPVicious/0.3")
+        };
+        assert_eq!(names(&engine.evaluate(&matching)), ["scanner-invite"]);
+
+        let misses = [
+            RuleContext {
+                method: "REGISTER",
+                ..matching
+            },
+            RuleContext {
+                user_agent: "MicroSIP/3.21.3",
+                ..matching
+            },
+            RuleContext {
+                source_ip: "198.51.100.7".parse().unwrap(),
+                ..matching
+            },
+            RuleContext {
+                dest_port: Some(5080),
+                ..matching
+            },
+            RuleContext {
+                dest_port: None,
+                ..matching
+            },
+            RuleContext {
+                country: Some("DE"),
+                ..matching
+            },
+            RuleContext {
+                country: None,
+                ..matching
+            },
+            RuleContext {
+                score: 1.0,
+                ..matching
+            },
+        ];
+        for ctx in &misses {
+            assert!(
+                engine.evaluate(ctx).is_empty(),
+                "{} {}",
+                ctx.method,
+                ctx.user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn rules_fire_when_the_threshold_is_reached_within_the_window() {
+        let mut engine = RulesEngine::parse(
+            r#"
+            [[rule]]
+            name = "options-flood"
+            methods = ["OPTIONS"]
+            threshold = 3
+            action = "strike"
+            strike_weight = 0.5
+            "#,
+        )
+        .unwrap();
+        let options = ctx("OPTIONS", "friendly-scanner");
+        assert!(engine.evaluate(&options).is_empty());
+        assert!(engine.evaluate(&options).is_empty());
+        let hits = engine.evaluate(&options);
+        assert_eq!(names(&hits), ["options-flood"]);
+        assert_eq!(hits[0].action, RuleAction::Strike);
+        assert_eq!(hits[0].strike_weight, 0.5);
+        assert_eq!(hits[0].code, ReasonCode::RateExceeded);
+        // 触发后重新计数
+        assert!(engine.evaluate(&options).is_empty());
+        assert_eq!(engine.snapshot()[0].count, 1);
+    }
+
+    #[test]
+    fn all_rules_are_evaluated_unless_first_match_is_set() {
+        let rules = r#"
+            [[rule]]
+            name = "log-everything"
+            action = "log"
+
+            [[rule]]
+            name = "allow-office"
+            sources = ["203.0.113.0/24"]
+            action = "allow"
+
+            [[rule]]
+            name = "ban-scanners"
+            user_agent = "friendly-scanner"
+            action = "ban"
+            "#;
+        let request = ctx("REGISTER", "friendly-scanner");
+        let mut engine = RulesEngine::parse(rules).unwrap();
+        assert_eq!(
+            names(&engine.evaluate(&request)),
+            ["log-everything", "allow-office", "ban-scanners"]
+        );
+
+        // 按顺序求值：第一条 ban/allow 决定结果，之前的 log 仍然记录
+        let mut engine = RulesEngine::parse(&format!("first_match = true\n{}", rules)).unwrap();
+        assert_eq!(
+            names(&engine.evaluate(&request)),
+            ["log-everything", "allow-office"]
+        );
+        let outside = RuleContext {
+            source_ip: "198.51.100.7".parse().unwrap(),
+            ..request
+        };
+        assert_eq!(
+            names(&engine.evaluate(&outside)),
+            ["log-everything", "ban-scanners"]
+        );
+    }
+
+    #[test]
+    fn expired_rules_are_ignored() {
+        let mut engine = RulesEngine::parse(
+            r#"
+            [[rule]]
+            name = "temporary-softphone"
+            user_agent = "Zoiper"
+            action = "allow"
+            expires = "2000-01-01"
+            "#,
+        )
+        .unwrap();
+        assert!(engine.evaluate(&ctx("REGISTER", "Zoiper 5")).is_empty());
+        assert_eq!(
+            engine.expired(),
+            vec![ExpiredRule {
+                rule: "temporary-softphone".to_string(),
+                expires: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reason_codes_are_inferred_from_the_conditions() {
+        let mut engine = RulesEngine::parse(
+            r#"
+            [[rule]]
+            name = "geo"
+            countries = ["CN"]
+            action = "ban"
+
+            [[rule]]
+            name = "signature"
+            action = "ban"
+
+            [[rule]]
+            name = "explicit"
+            action = "ban"
+            code = "MANUAL"
+            "#,
+        )
+        .unwrap();
+        let request = RuleContext {
+            country: Some("CN"),
+            ..ctx("REGISTER", "sipcli")
+        };
+        let codes: Vec<_> = engine
+            .evaluate(&request)
+            .into_iter()
+            .map(|hit| hit.code)
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                ReasonCode::GeoDeny,
+                ReasonCode::ScannerSignature,
+                ReasonCode::Manual
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for (rule, error) in [
+            ("name = \"bad name\"\naction = \"ban\"", "bad name"),
+            (
+                "name = \"zero\"\nthreshold = 0\naction = \"ban\"",
+                "threshold",
+            ),
+            (
+                "name = \"regex\"\nuser_agent = \"(\"\naction = \"ban\"",
+                "user_agent",
+            ),
+            (
+                "name = \"net\"\nsources = [\"10.0.0.x/8\"]\naction = \"ban\"",
+                "sources",
+            ),
+            (
+                "name = \"date\"\nexpires = \"01/02/2026\"\naction = \"ban\"",
+                "expires",
+            ),
+        ] {
+            let result = RulesEngine::parse(&format!("[[rule]]\n{}", rule));
+            let message = result.err().expect(rule);
+            assert!(message.contains(error), "{}: {}", rule, message);
+        }
+        assert!(RulesEngine::parse("[policy.INVITE]\nban_duration = \"0\"").is_err());
+        let engine = RulesEngine::parse("[policy.invite]\naction = \"alert\"").unwrap();
+        assert_eq!(
+            engine.method_policies()["INVITE"],
+            MethodPolicy {
+                action: MethodAction::Alert,
+                ban_duration: None
+            }
+        );
+    }
+}
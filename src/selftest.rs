@@ -0,0 +1,314 @@
+use crate::{default_backend, open_firewall};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uablock_rust::bans::BanRecord;
+use uablock_rust::testing::ipv4_datagram;
+use uablock_rust::Stats;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时请求的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+/// 默认的伪造来源（RFC 2544 基准测试网段，不会是真实终端）
+const DEFAULT_SOURCE: Ipv4Addr = Ipv4Addr::new(198, 18, 0, 254);
+
+/// 查询封禁表的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// `/bans` 响应
+#[derive(Debug, Deserialize)]
+struct BansReport {
+    #[serde(default)]
+    details: Vec<BanRecord>,
+}
+
+/// 期望的判定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Ban,
+    Allow,
+}
+
+/// 自检参数
+#[derive(Debug)]
+struct SelftestOptions {
+    url: String,
+    token: String,
+    target: Ipv4Addr,
+    port: u16,
+    source: Ipv4Addr,
+    user_agent: String,
+    interface: Option<String>,
+    expect: Expect,
+    wait: Duration,
+    dry_run: bool,
+}
+
+impl SelftestOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            target: Ipv4Addr::LOCALHOST,
+            port: 5060,
+            source: DEFAULT_SOURCE,
+            user_agent: "uablock-selftest".to_string(),
+            interface: None,
+            expect: Expect::Ban,
+            wait: Duration::from_secs(5),
+            dry_run: false,
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--dry-run" {
+                options.dry_run = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            let invalid = || format!("参数 {} 的取值无效: {}", flag, value);
+            match flag.as_str() {
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--target" => options.target = value.parse().map_err(|_| invalid())?,
+                "--port" => options.port = value.parse().map_err(|_| invalid())?,
+                "--source" => options.source = value.parse().map_err(|_| invalid())?,
+                "--ua" => options.user_agent = value.clone(),
+                "--interface" => options.interface = Some(value.clone()),
+                "--expect" => {
+                    options.expect = match value.as_str() {
+                        "ban" => Expect::Ban,
+                        "allow" => Expect::Allow,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--wait" => {
+                    options.wait = Duration::from_secs(value.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock selftest`：端到端自检
+///
+/// 构造一个带指定 UA（`--ua`，默认 `uablock-selftest`）的 REGISTER，以伪造的来源地址（`--source`，
+/// 默认 198.18.0.254）经原始套接字发往 `--target:--port`（默认 127.0.0.1:5060，`--interface` 指定出口
+/// 接口），然后经 HTTP API 等待运行中的守护进程给出判定（`--expect ban|allow`，默认 ban，最多等
+/// `--wait` 秒）。期望封禁时还在本机防火墙中确认规则已生效（`--dry-run` 时跳过，适用于
+/// `UABLOCK_BACKEND=none` 或备机），最后经 API 解封测试地址。需要 root 或 CAP_NET_RAW；
+/// 自检失败时退出码为 1。
+pub fn run(args: &[String]) -> i32 {
+    let options = match SelftestOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    match selftest(&options) {
+        Ok(()) => {
+            println!("自检通过");
+            0
+        }
+        Err(e) => {
+            eprintln!("自检失败: {}", e);
+            1
+        }
+    }
+}
+
+fn selftest(options: &SelftestOptions) -> Result<(), String> {
+    let source = IpAddr::V4(options.source);
+    if find_ban(options, source)?.is_some() {
+        return Err(format!(
+            "{} 已在封禁表中，请先解封或用 --source 换一个地址",
+            source
+        ));
+    }
+
+    let payload = register(options);
+    let packet = ipv4_datagram(
+        options.source,
+        options.target,
+        5060,
+        options.port,
+        payload.as_bytes(),
+    );
+    send_raw(&packet, options.target, options.interface.as_deref())?;
+    println!(
+        "已从 {} 向 {}:{} 发送 REGISTER（User-Agent: {}）",
+        source, options.target, options.port, options.user_agent
+    );
+
+    let deadline = Instant::now() + options.wait;
+    let record = loop {
+        if let Some(record) = find_ban(options, source)? {
+            break Some(record);
+        }
+        if Instant::now() >= deadline {
+            break None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let Some(record) = record else {
+        return match options.expect {
+            Expect::Allow => {
+                println!("判定: 放行（{} 秒内未封禁）", options.wait.as_secs());
+                Ok(())
+            }
+            Expect::Ban => Err(format!(
+                "{} 秒内未封禁 {}，请确认抓包接口能看到发往 {}:{} 的数据包",
+                options.wait.as_secs(),
+                source,
+                options.target,
+                options.port
+            )),
+        };
+    };
+    println!(
+        "判定: 封禁（{}，{}）",
+        record.reason.reason, record.reason.origin
+    );
+    let verified = match options.expect {
+        Expect::Allow => Err("期望放行，但测试地址被封禁".to_string()),
+        Expect::Ban if options.dry_run => Ok(()),
+        Expect::Ban => verify_rule(options, source),
+    };
+    // 无论结果如何都解封测试地址
+    unban(options, source)?;
+    println!("已解封测试地址 {}", source);
+    verified
+}
+
+/// 在本机防火墙中确认测试地址的规则已生效
+fn verify_rule(options: &SelftestOptions, source: IpAddr) -> Result<(), String> {
+    let backend =
+        std::env::var("UABLOCK_BACKEND").unwrap_or_else(|_| default_backend().to_string());
+    let firewall = open_firewall(&backend, options.port, &Arc::new(Stats::default()))?
+        .ok_or("未启用内置封禁（UABLOCK_BACKEND=none），无法检查规则，请使用 --dry-run")?;
+    if !firewall.is_blocked(&source) {
+        return Err(format!("封禁表中有 {}，但防火墙中没有规则", source));
+    }
+    println!("防火墙规则已生效（{}）", backend);
+    Ok(())
+}
+
+/// 带测试 UA 的 REGISTER 请求
+fn register(options: &SelftestOptions) -> String {
+    let call_id = format!("uablock-selftest-{}", std::process::id());
+    format!(
+        "REGISTER sip:{target} SIP/2.0\r\n\
+         Via: SIP/2.0/UDP {source}:5060;branch=z9hG4bK-{call_id}\r\n\
+         From: <sip:selftest@{target}>;tag=selftest\r\n\
+         To: <sip:selftest@{target}>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 REGISTER\r\n\
+         User-Agent: {user_agent}\r\n\
+         Max-Forwards: 70\r\n\
+         Content-Length: 0\r\n\
+         \r\n",
+        target = options.target,
+        source = options.source,
+        user_agent = options.user_agent,
+    )
+}
+
+/// 经原始套接字发出构造好的 IPv4 数据报（IP 头由调用方提供）
+#[cfg(target_os = "linux")]
+fn send_raw(packet: &[u8], target: Ipv4Addr, interface: Option<&str>) -> Result<(), String> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW) };
+    if fd < 0 {
+        return Err(format!(
+            "无法创建原始套接字（需要 root 或 CAP_NET_RAW）: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    if let Some(interface) = interface {
+        let set = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                interface.as_ptr() as *const libc::c_void,
+                interface.len() as libc::socklen_t,
+            )
+        };
+        if set < 0 {
+            return Err(format!(
+                "无法绑定网络接口 {}: {}",
+                interface,
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_addr = libc::in_addr {
+        s_addr: u32::from(target).to_be(),
+    };
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(format!(
+            "发送测试数据包失败: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_raw(_packet: &[u8], _target: Ipv4Addr, _interface: Option<&str>) -> Result<(), String> {
+    Err("selftest 子命令只支持 Linux".to_string())
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build()
+}
+
+/// 封禁表中测试地址的记录
+fn find_ban(options: &SelftestOptions, source: IpAddr) -> Result<Option<BanRecord>, String> {
+    let url = format!("{}/bans", options.url);
+    let report: BansReport = agent()
+        .get(&url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map_err(|e| format!("无法请求 {}: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))?;
+    Ok(report
+        .details
+        .into_iter()
+        .find(|record| record.ip == source))
+}
+
+fn unban(options: &SelftestOptions, source: IpAddr) -> Result<(), String> {
+    let url = format!("{}/bans/{}", options.url, source);
+    agent()
+        .delete(&url)
+        .set("Authorization", &format!("Bearer {}", options.token))
+        .call()
+        .map(|_| ())
+        .map_err(|e| format!("无法解封测试地址 {}: {}", source, e))
+}
@@ -0,0 +1,547 @@
+use crate::bans::BanReason;
+use crate::enforcement::Enforcer;
+use crate::events::{EventKind, QueueReceiver};
+use crate::whitelist::Whitelist;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::IpAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 普通请求超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 等待变化的最长时间，超时后重新读取一次全量
+const WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 存储不可用时的重试间隔
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// 写入线程检查本地白名单变化的周期
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 从共享存储应用的封禁使用的事件来源
+const SHARED_ORIGIN: &str = "shared-state";
+
+/// 共享存储中的一条封禁
+#[derive(Debug, Serialize, Deserialize)]
+struct BanRecord {
+    reason: String,
+    origin: String,
+    banned_at: u64,
+}
+
+/// 键值存储中的一个条目
+struct Entry {
+    key: String,
+    value: Vec<u8>,
+}
+
+/// 一次全量读取的结果
+struct Snapshot {
+    entries: Vec<Entry>,
+    /// 存储的版本号（Consul 的 index、etcd 的 revision），用于等待之后的变化
+    revision: u64,
+}
+
+/// 键值存储后端
+trait KvStore: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// 读取前缀下的所有键
+    fn list(&self, prefix: &str) -> Result<Snapshot, String>;
+    /// 阻塞直到 revision 之后有变化或超时
+    fn wait(&self, prefix: &str, revision: u64) -> Result<(), String>;
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), String>;
+    fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+/// Consul KV（HTTP API，阻塞查询）
+struct Consul {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+    watch_agent: ureq::Agent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulEntry {
+    key: String,
+    value: Option<String>,
+}
+
+impl Consul {
+    fn request(&self, agent: &ureq::Agent, method: &str, key: &str) -> ureq::Request {
+        let request = agent.request(method, &format!("{}/v1/kv/{}", self.url, key));
+        match &self.token {
+            Some(token) => request.set("X-Consul-Token", token),
+            None => request,
+        }
+    }
+
+    /// 读取前缀；`index` 不为 0 时使用阻塞查询
+    fn query(&self, prefix: &str, index: u64) -> Result<Snapshot, String> {
+        let mut request = self
+            .request(&self.watch_agent, "GET", prefix)
+            .query("recurse", "true");
+        if index > 0 {
+            request = request
+                .query("index", &index.to_string())
+                .query("wait", &format!("{}s", WATCH_TIMEOUT.as_secs()));
+        }
+        let (response, found) = match request.call() {
+            Ok(response) => (response, true),
+            // 前缀下没有任何键
+            Err(ureq::Error::Status(404, response)) => (response, false),
+            Err(e) => return Err(e.to_string()),
+        };
+        let revision = response
+            .header("X-Consul-Index")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let entries: Vec<ConsulEntry> = if found {
+            response
+                .into_json()
+                .map_err(|e| format!("响应解析失败: {}", e))?
+        } else {
+            Vec::new()
+        };
+        let entries = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let value = BASE64.decode(entry.value?).ok()?;
+                Some(Entry {
+                    key: entry.key,
+                    value,
+                })
+            })
+            .collect();
+        Ok(Snapshot { entries, revision })
+    }
+}
+
+impl KvStore for Consul {
+    fn name(&self) -> &'static str {
+        "Consul"
+    }
+
+    fn list(&self, prefix: &str) -> Result<Snapshot, String> {
+        self.query(prefix, 0)
+    }
+
+    fn wait(&self, prefix: &str, revision: u64) -> Result<(), String> {
+        self.query(prefix, revision.max(1)).map(|_| ())
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), String> {
+        self.request(&self.agent, "PUT", key)
+            .send_bytes(value)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.request(&self.agent, "DELETE", key)
+            .call()
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// etcd v3（gRPC gateway 的 JSON 接口）
+struct Etcd {
+    url: String,
+    agent: ureq::Agent,
+    watch_agent: ureq::Agent,
+}
+
+impl Etcd {
+    fn call(&self, path: &str, body: Value) -> Result<Value, String> {
+        self.agent
+            .post(&format!("{}{}", self.url, path))
+            .send_json(body)
+            .map_err(|e| e.to_string())?
+            .into_json()
+            .map_err(|e| format!("响应解析失败: {}", e))
+    }
+}
+
+/// 前缀查询的 range_end：最后一个字节加一
+fn range_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+/// etcd 的 int64 字段在 JSON 中编码为字符串
+fn json_u64(value: &Value) -> u64 {
+    match value {
+        Value::String(s) => s.parse().unwrap_or(0),
+        other => other.as_u64().unwrap_or(0),
+    }
+}
+
+impl KvStore for Etcd {
+    fn name(&self) -> &'static str {
+        "etcd"
+    }
+
+    fn list(&self, prefix: &str) -> Result<Snapshot, String> {
+        let response = self.call(
+            "/v3/kv/range",
+            json!({
+                "key": BASE64.encode(prefix),
+                "range_end": BASE64.encode(range_end(prefix)),
+            }),
+        )?;
+        let revision = json_u64(&response["header"]["revision"]);
+        let entries = response["kvs"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|kv| {
+                let key = BASE64.decode(kv["key"].as_str()?).ok()?;
+                let value = BASE64
+                    .decode(kv["value"].as_str().unwrap_or_default())
+                    .ok()?;
+                Some(Entry {
+                    key: String::from_utf8(key).ok()?,
+                    value,
+                })
+            })
+            .collect();
+        Ok(Snapshot { entries, revision })
+    }
+
+    /// 建立 watch 流，收到第一批事件或读超时后返回
+    fn wait(&self, prefix: &str, revision: u64) -> Result<(), String> {
+        let response = self
+            .watch_agent
+            .post(&format!("{}/v3/watch", self.url))
+            .send_json(json!({
+                "create_request": {
+                    "key": BASE64.encode(prefix),
+                    "range_end": BASE64.encode(range_end(prefix)),
+                    "start_revision": (revision + 1).to_string(),
+                }
+            }))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(response.into_reader());
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    let message: Value = serde_json::from_str(&line)
+                        .map_err(|e| format!("watch 响应解析失败: {}", e))?;
+                    if let Some(error) = message.get("error") {
+                        return Err(error.to_string());
+                    }
+                    let has_events = message["result"]["events"]
+                        .as_array()
+                        .is_some_and(|events| !events.is_empty());
+                    if has_events {
+                        return Ok(());
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), String> {
+        self.call(
+            "/v3/kv/put",
+            json!({"key": BASE64.encode(key), "value": BASE64.encode(value)}),
+        )
+        .map(|_| ())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.call("/v3/kv/deleterange", json!({"key": BASE64.encode(key)}))
+            .map(|_| ())
+    }
+}
+
+/// 写入共享存储的本地变化
+enum Update {
+    Ban { ip: IpAddr, record: BanRecord },
+    Unban { ip: IpAddr },
+}
+
+/// 把封禁表和白名单保存在 Consul 或 etcd 中，所有节点监听变化并收敛到同一份状态
+///
+/// 键布局：`<prefix>/bans/<ip>` 保存一条封禁（JSON），`<prefix>/whitelist` 保存白名单
+/// （JSON 字符串数组）。本地的封禁/解封和白名单修改写入存储；存储中出现的封禁在本地执行，
+/// 被删除的封禁在本地解除。节点重启或新增副本时先读取全量，因此会自动恢复到共享状态。
+pub struct SharedState {
+    store: Box<dyn KvStore>,
+    prefix: String,
+    /// 上次与存储一致的白名单；首次同步前为 None，避免用本地默认值覆盖存储
+    synced_whitelist: Mutex<Option<Vec<String>>>,
+}
+
+impl SharedState {
+    /// 从环境变量创建，未设置 `UABLOCK_CONSUL_URL` 和 `UABLOCK_ETCD_URL` 时返回 None
+    ///
+    /// - `UABLOCK_CONSUL_URL`：Consul 地址，例如 `http://127.0.0.1:8500`
+    /// - `UABLOCK_CONSUL_TOKEN`：Consul ACL token（可选）
+    /// - `UABLOCK_ETCD_URL`：etcd 地址，例如 `http://127.0.0.1:2379`
+    /// - `UABLOCK_KV_PREFIX`：键前缀（默认 `uablock`）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let consul = std::env::var("UABLOCK_CONSUL_URL")
+            .ok()
+            .filter(|u| !u.is_empty());
+        let etcd = std::env::var("UABLOCK_ETCD_URL")
+            .ok()
+            .filter(|u| !u.is_empty());
+        let watch_agent = ureq::AgentBuilder::new()
+            .timeout_connect(REQUEST_TIMEOUT)
+            .timeout_read(WATCH_TIMEOUT + REQUEST_TIMEOUT)
+            .build();
+        let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+        let store: Box<dyn KvStore> = match (consul, etcd) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                return Err("UABLOCK_CONSUL_URL 和 UABLOCK_ETCD_URL 只能设置一个".to_string())
+            }
+            (Some(url), None) => Box::new(Consul {
+                url: url.trim_end_matches('/').to_string(),
+                token: std::env::var("UABLOCK_CONSUL_TOKEN")
+                    .ok()
+                    .filter(|t| !t.is_empty()),
+                agent,
+                watch_agent,
+            }),
+            (None, Some(url)) => Box::new(Etcd {
+                url: url.trim_end_matches('/').to_string(),
+                agent,
+                // etcd 的 watch 流在空闲时不会返回数据，读超时即视为本轮等待结束
+                watch_agent: ureq::AgentBuilder::new()
+                    .timeout_connect(REQUEST_TIMEOUT)
+                    .timeout_read(WATCH_TIMEOUT)
+                    .build(),
+            }),
+        };
+        let prefix = std::env::var("UABLOCK_KV_PREFIX").unwrap_or_else(|_| "uablock".to_string());
+        Ok(Some(Self {
+            store,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            synced_whitelist: Mutex::new(None),
+        }))
+    }
+
+    /// 所有键共同的前缀（带 `/`，避免匹配到其他以相同字符开头的键）
+    fn key_prefix(&self) -> String {
+        format!("{}/", self.prefix)
+    }
+
+    fn bans_prefix(&self) -> String {
+        format!("{}/bans/", self.prefix)
+    }
+
+    fn whitelist_key(&self) -> String {
+        format!("{}/whitelist", self.prefix)
+    }
+
+    /// 订阅本地封禁事件，启动写入线程和监听线程
+    pub fn start(
+        self,
+        enforcer: Arc<Enforcer>,
+        whitelist: Arc<Mutex<Whitelist>>,
+    ) -> Result<(), String> {
+        let (tx, rx) = enforcer.events().queue();
+        enforcer.events().subscribe(move |event| {
+            if event.origin == SHARED_ORIGIN {
+                return true;
+            }
+            let update = match event.kind {
+                EventKind::Ban => Update::Ban {
+                    ip: event.ip,
+                    record: BanRecord {
+                        reason: event.reason.clone(),
+                        origin: event.origin.clone(),
+                        banned_at: event.timestamp,
+                    },
+                },
+                EventKind::Unban => Update::Unban { ip: event.ip },
+                EventKind::Detection | EventKind::Alert => return true,
+            };
+            tx.send(update)
+        });
+
+        info!(
+            "【共享状态】封禁表和白名单保存在 {}（前缀: {}）",
+            self.store.name(),
+            self.prefix
+        );
+        let this = Arc::new(self);
+        let writer = this.clone();
+        let local_whitelist = whitelist.clone();
+        std::thread::Builder::new()
+            .name("shared-state-write".to_string())
+            .spawn(move || writer.write_loop(rx, &local_whitelist))
+            .map_err(|e| format!("启动共享状态写入线程失败: {}", e))?;
+        std::thread::Builder::new()
+            .name("shared-state-watch".to_string())
+            .spawn(move || this.watch_loop(&enforcer, &whitelist))
+            .map_err(|e| format!("启动共享状态监听线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 把本地封禁/解封和白名单修改写入存储
+    fn write_loop(&self, rx: QueueReceiver<Update>, whitelist: &Mutex<Whitelist>) {
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(Update::Ban { ip, record }) => {
+                    let key = format!("{}{}", self.bans_prefix(), ip);
+                    let result = serde_json::to_vec(&record)
+                        .map_err(|e| e.to_string())
+                        .and_then(|value| self.store.put(&key, &value));
+                    match result {
+                        Ok(()) => debug!("【共享状态】已写入封禁 {}", ip),
+                        Err(e) => error!("【共享状态】写入封禁 {} 失败: {}", ip, e),
+                    }
+                }
+                Ok(Update::Unban { ip }) => {
+                    let key = format!("{}{}", self.bans_prefix(), ip);
+                    match self.store.delete(&key) {
+                        Ok(()) => debug!("【共享状态】已删除封禁 {}", ip),
+                        Err(e) => error!("【共享状态】删除封禁 {} 失败: {}", ip, e),
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            // 本地白名单（例如通过 API）被修改时写入存储
+            let local = whitelist.lock().unwrap().get_patterns().to_vec();
+            let mut synced = self.synced_whitelist.lock().unwrap();
+            if synced.as_ref().is_none_or(|synced| *synced == local) {
+                continue;
+            }
+            let result = serde_json::to_vec(&local)
+                .map_err(|e| e.to_string())
+                .and_then(|value| self.store.put(&self.whitelist_key(), &value));
+            match result {
+                Ok(()) => {
+                    info!("【共享状态】已写入白名单: {:?}", local);
+                    *synced = Some(local);
+                }
+                Err(e) => error!("【共享状态】写入白名单失败: {}", e),
+            }
+        }
+    }
+
+    /// 读取全量并应用，然后等待下一次变化
+    fn watch_loop(&self, enforcer: &Enforcer, whitelist: &Mutex<Whitelist>) {
+        // 上次应用时存储中的封禁（IP -> 原因）
+        let mut applied: HashMap<IpAddr, String> = HashMap::new();
+        let prefix = self.key_prefix();
+        loop {
+            let revision = match self.store.list(&prefix) {
+                Ok(snapshot) => {
+                    self.apply(snapshot.entries, &mut applied, enforcer, whitelist);
+                    snapshot.revision
+                }
+                Err(e) => {
+                    warn!("【共享状态】读取 {} 失败: {}", self.store.name(), e);
+                    std::thread::sleep(RETRY_DELAY);
+                    continue;
+                }
+            };
+            if let Err(e) = self.store.wait(&prefix, revision) {
+                warn!("【共享状态】监听 {} 失败: {}", self.store.name(), e);
+                std::thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+
+    fn apply(
+        &self,
+        entries: Vec<Entry>,
+        applied: &mut HashMap<IpAddr, String>,
+        enforcer: &Enforcer,
+        whitelist: &Mutex<Whitelist>,
+    ) {
+        let bans_prefix = self.bans_prefix();
+        let whitelist_key = self.whitelist_key();
+        let mut bans: HashMap<IpAddr, String> = HashMap::new();
+        let mut patterns: Option<Vec<String>> = None;
+        for entry in entries {
+            if entry.key == whitelist_key {
+                match serde_json::from_slice(&entry.value) {
+                    Ok(value) => patterns = Some(value),
+                    Err(e) => warn!("【共享状态】白名单格式无效: {}", e),
+                }
+            } else if let Some(ip) = entry.key.strip_prefix(&bans_prefix) {
+                let Ok(ip) = ip.parse() else {
+                    warn!("【共享状态】忽略无效的键: {}", entry.key);
+                    continue;
+                };
+                let reason = serde_json::from_slice::<BanRecord>(&entry.value)
+                    .map(|record| record.reason)
+                    .unwrap_or_else(|_| "MANUAL".to_string());
+                bans.insert(ip, reason);
+            }
+        }
+
+        for (ip, reason) in &bans {
+            if applied.contains_key(ip) {
+                continue;
+            }
+            match enforcer.ban(*ip, BanReason::new(reason, SHARED_ORIGIN)) {
+                Ok(true) => info!(
+                    "【共享状态】按共享封禁表封禁 IP: {}（原因: {}）",
+                    ip, reason
+                ),
+                Ok(false) => {}
+                Err(e) => error!("【共享状态】封禁 IP {} 失败: {}", ip, e),
+            }
+        }
+        for (ip, reason) in applied.iter() {
+            if bans.contains_key(ip) {
+                continue;
+            }
+            match enforcer.unban(*ip, reason, SHARED_ORIGIN) {
+                Ok(true) => info!("【共享状态】封禁已从共享封禁表移除，解封 IP: {}", ip),
+                Ok(false) => {}
+                Err(e) => error!("【共享状态】解封 IP {} 失败: {}", ip, e),
+            }
+        }
+        *applied = bans;
+
+        let mut synced = self.synced_whitelist.lock().unwrap();
+        match patterns {
+            Some(patterns) if !patterns.is_empty() => {
+                if synced.as_ref() != Some(&patterns) {
+                    info!("【共享状态】应用共享白名单: {:?}", patterns);
+                    whitelist.lock().unwrap().set_patterns(patterns.clone());
+                    *synced = Some(patterns);
+                }
+            }
+            // 存储中还没有白名单时，由写入线程用本地白名单初始化
+            _ => {
+                if synced.is_none() {
+                    *synced = Some(Vec::new());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Read;
+use std::path::Path;
+use uablock_rust::bundle::{keypair, sign, signature_path, BundleVerifier};
+
+const USAGE: &str = "用法: uablock-rust sign-bundle keygen <私钥文件>
+       uablock-rust sign-bundle sign <私钥文件> <文件>...
+       uablock-rust sign-bundle verify [--pubkey <公钥>] <文件>...";
+
+/// `uablock sign-bundle`：规则包签名工具
+///
+/// - `keygen <私钥文件>`：生成 ed25519 密钥，私钥写入文件（权限 0600，不覆盖已有文件），公钥输出到
+///   标准输出，配置到各 SBC 的 `UABLOCK_BUNDLE_PUBKEY`
+/// - `sign <私钥文件> <文件>...`：为每个文件生成同名的 `.sig` 签名
+/// - `verify [--pubkey <公钥>] <文件>...`：用 `--pubkey`（默认 `UABLOCK_BUNDLE_PUBKEY`）校验签名，
+///   与守护进程加载时的校验相同
+///
+/// 参数错误时退出码为 2，签名或校验失败时为 1。
+pub fn run(args: &[String]) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("keygen") if args.len() == 2 => keygen(Path::new(&args[1])),
+        Some("sign") if args.len() >= 3 => sign_files(Path::new(&args[1]), &args[2..]),
+        Some("verify") => match args.get(1).map(String::as_str) {
+            Some("--pubkey") if args.len() >= 4 => BundleVerifier::parse(&args[2])
+                .map_err(|e| format!("--pubkey {}", e))
+                .and_then(|verifier| verify_files(&verifier, &args[3..])),
+            Some("--pubkey") | None => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+            Some(_) => match BundleVerifier::from_env() {
+                Ok(Some(verifier)) => verify_files(&verifier, &args[1..]),
+                Ok(None) => Err("请用 --pubkey 或 UABLOCK_BUNDLE_PUBKEY 指定公钥".to_string()),
+                Err(e) => Err(e),
+            },
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn keygen(path: &Path) -> Result<(), String> {
+    let mut seed = [0u8; 32];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut seed))
+        .map_err(|e| format!("无法读取随机数: {}", e))?;
+    let (private_key, public_key) = keypair(seed);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("无法创建私钥文件 {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", private_key).as_bytes())
+        .map_err(|e| format!("无法写入私钥文件 {}: {}", path.display(), e))?;
+    eprintln!("私钥已写入 {}，请妥善保管", path.display());
+    println!("{}", public_key);
+    Ok(())
+}
+
+fn sign_files(key_path: &Path, files: &[String]) -> Result<(), String> {
+    let private_key = std::fs::read_to_string(key_path)
+        .map_err(|e| format!("无法读取私钥文件 {}: {}", key_path.display(), e))?;
+    for file in files {
+        let path = Path::new(file);
+        let content =
+            std::fs::read(path).map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+        let signature = sign(&private_key, &content)
+            .map_err(|e| format!("私钥文件 {} 无效: {}", key_path.display(), e))?;
+        let signature_path = signature_path(path);
+        std::fs::write(&signature_path, format!("{}\n", signature))
+            .map_err(|e| format!("无法写入 {}: {}", signature_path.display(), e))?;
+        println!("已签名 {} → {}", path.display(), signature_path.display());
+    }
+    Ok(())
+}
+
+fn verify_files(verifier: &BundleVerifier, files: &[String]) -> Result<(), String> {
+    for file in files {
+        let bundle = verifier.verify_file(Path::new(file))?;
+        println!("{}: 签名有效（SHA-256 {}）", file, bundle.sha256);
+    }
+    Ok(())
+}
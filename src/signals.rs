@@ -0,0 +1,58 @@
+use log::warn;
+
+/// 等待 SIGTERM / SIGINT，检测任务据此优雅退出
+pub async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("无法注册 SIGTERM 处理: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("无法注册 SIGINT 处理: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// 等待 SIGUSR2：升级交接，检测任务保存完整快照后退出
+pub async fn handover() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::user_defined2()) {
+            Ok(mut usr2) => {
+                usr2.recv().await;
+                return;
+            }
+            Err(e) => warn!("无法注册 SIGUSR2 处理: {}", e),
+        }
+    }
+    std::future::pending::<()>().await;
+}
+
+/// 每次收到 SIGHUP 时调用 `on_reload`（重新读取配置文件），在当前 tokio 运行时中执行
+#[cfg_attr(not(unix), allow(unused_variables, unused_mut))]
+pub fn on_reload(mut on_reload: impl FnMut() + Send + 'static) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        on_reload();
+                    }
+                });
+            }
+            Err(e) => warn!("无法注册 SIGHUP 处理: {}", e),
+        }
+    }
+}
@@ -7,12 +7,107 @@ pub struct SipRequest {
     pub source_ip: IpAddr,
     pub user_agent: String,
     pub method: String,
+    /// Call-ID 头，用于识别同一事务的重传
+    pub call_id: Option<String>,
+    /// CSeq 头（序号 + 方法）
+    pub cseq: Option<String>,
+    /// 顶层 Via 头中的 branch 参数
+    pub branch: Option<String>,
+    /// UDP 源端口（只解析负载时为 None）
+    pub source_port: Option<u16>,
+    /// UDP 目标端口，即被访问的本机端口（只解析负载时为 None）
+    pub dest_port: Option<u16>,
+    /// To 头中的用户部分（被叫或被注册的分机号）
+    pub to_user: Option<String>,
+}
+
+impl SipRequest {
+    /// 记录数据包的 UDP 端口
+    pub fn with_ports(mut self, source_port: u16, dest_port: u16) -> Self {
+        self.source_port = Some(source_port);
+        self.dest_port = Some(dest_port);
+        self
+    }
+}
+
+/// 已知的 SIP 请求方法
+const SIP_METHODS: [&str; 13] = [
+    "INVITE",
+    "REGISTER",
+    "OPTIONS",
+    "ACK",
+    "BYE",
+    "CANCEL",
+    "PRACK",
+    "UPDATE",
+    "INFO",
+    "REFER",
+    "MESSAGE",
+    "SUBSCRIBE",
+    "NOTIFY",
+];
+
+/// SIP 请求必须携带的头部（完整名称, 紧凑形式）
+const MANDATORY_HEADERS: [(&str, Option<&str>); 5] = [
+    ("via", Some("v")),
+    ("from", Some("f")),
+    ("to", Some("t")),
+    ("call-id", Some("i")),
+    ("cseq", None),
+];
+
+/// 负载的解析结果：分类，以及其中的 SIP 请求（任意方法）
+#[derive(Debug, Clone)]
+pub struct ParsedPayload {
+    pub class: PacketClass,
+    /// 不是 SIP 请求时为 None
+    pub request: Option<SipRequest>,
+}
+
+/// 数据包分类结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketClass {
+    /// 格式正确的 SIP 消息
+    Sip,
+    /// 看起来是 SIP，但残缺或违反协议
+    Malformed(MalformedReason),
+    /// 不是 SIP 消息
+    NotSip,
+}
+
+/// SIP 报文畸形原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalformedReason {
+    /// 以 SIP 方法开头，但不是合法 UTF-8 文本
+    InvalidEncoding,
+    /// 请求行不是 `METHOD URI SIP/2.0` 格式
+    BadRequestLine,
+    /// 缺少头部结束标记
+    Truncated,
+    /// 缺少必需头部（Via/From/To/Call-ID/CSeq）
+    MissingHeader,
+}
+
+impl MalformedReason {
+    /// 机器可读的原因代码
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MalformedReason::InvalidEncoding => "INVALID_ENCODING",
+            MalformedReason::BadRequestLine => "BAD_REQUEST_LINE",
+            MalformedReason::Truncated => "TRUNCATED",
+            MalformedReason::MissingHeader => "MISSING_HEADER",
+        }
+    }
 }
 
 /// 解析 SIP 数据包，提取 User-Agent 和源 IP
 pub struct SipParser {
     user_agent_regex: Regex,
     method_regex: Regex,
+    call_id_regex: Regex,
+    cseq_regex: Regex,
+    branch_regex: Regex,
+    to_user_regex: Regex,
 }
 
 impl SipParser {
@@ -22,6 +117,15 @@ impl SipParser {
             user_agent_regex: Regex::new(r"(?i)(?:user-agent|User-Agent):\s*([^\r\n]+)").unwrap(),
             // 匹配 SIP 方法（如 INVITE, REGISTER, OPTIONS 等）
             method_regex: Regex::new(r"^(INVITE|REGISTER|OPTIONS|ACK|BYE|CANCEL|PRACK|UPDATE|INFO|REFER|MESSAGE|SUBSCRIBE|NOTIFY)\s").unwrap(),
+            // 匹配 Call-ID 字段（支持紧凑形式 i:）
+            call_id_regex: Regex::new(r"(?im)^(?:call-id|i)[ \t]*:[ \t]*([^\r\n]+)").unwrap(),
+            // 匹配 CSeq 字段
+            cseq_regex: Regex::new(r"(?im)^cseq[ \t]*:[ \t]*([^\r\n]+)").unwrap(),
+            // 匹配第一个 Via 字段中的 branch 参数（支持紧凑形式 v:）
+            branch_regex: Regex::new(r"(?im)^(?:via|v)[ \t]*:[^\r\n]*?;[ \t]*branch=([^;,\s]+)")
+                .unwrap(),
+            // 匹配 To 字段 URI 中的用户部分（支持紧凑形式 t:）
+            to_user_regex: Regex::new(r"(?im)^(?:to|t)[ \t]*:[^\r\n]*?sips?:([^@;>\s]+)@").unwrap(),
         }
     }
 
@@ -29,8 +133,40 @@ impl SipParser {
     /// source_ip 是从网络层捕获的真实源 IP，不可伪装
     /// 只解析 SIP 内容，不信任数据包中的任何 IP 信息
     pub fn parse_udp_packet(&self, data: &[u8], source_ip: IpAddr) -> Option<SipRequest> {
+        Self::checked_request(self.parse_request(data, source_ip)?)
+    }
+
+    /// 只保留需要检查 User-Agent 的 REGISTER 和 INVITE 请求，并输出日志
+    pub fn checked_request(sip_request: SipRequest) -> Option<SipRequest> {
         use log::info;
 
+        // 只处理 REGISTER 和 INVITE 请求
+        if sip_request.method != "REGISTER" && sip_request.method != "INVITE" {
+            // 其他 SIP 方法不处理，静默返回
+            return None;
+        }
+
+        // 是 SIP REGISTER 或 INVITE 请求，输出日志
+        info!(
+            "收到 SIP {} 请求，来源 IP: {}（网络层真实IP），User-Agent: {}",
+            sip_request.method, sip_request.source_ip, sip_request.user_agent
+        );
+
+        Some(sip_request)
+    }
+
+    /// 分类并解析负载（解析线程池在检测任务之外调用）
+    ///
+    /// 畸形报文也尽量解析出请求，用于重传去重和白名单检查。
+    pub fn parse(&self, data: &[u8], source_ip: IpAddr) -> ParsedPayload {
+        ParsedPayload {
+            class: self.classify(data),
+            request: self.parse_request(data, source_ip),
+        }
+    }
+
+    /// 解析任意方法的 SIP 请求，不做方法过滤，也不输出日志
+    pub fn parse_request(&self, data: &[u8], source_ip: IpAddr) -> Option<SipRequest> {
         // 尝试将数据解析为 UTF-8 字符串
         let text = match std::str::from_utf8(data) {
             Ok(t) => t,
@@ -63,26 +199,90 @@ impl SipParser {
             .map(|m| m.as_str().trim().to_string())
             .unwrap_or_else(|| "Unknown".to_string());
 
-        // 只处理 REGISTER 和 INVITE 请求
-        if method != "REGISTER" && method != "INVITE" {
-            // 其他 SIP 方法不处理，静默返回
-            return None;
-        }
+        // 提取事务标识（Call-ID / CSeq / Via branch），用于重传去重
+        let call_id = Self::capture_header(&self.call_id_regex, text);
+        let cseq = Self::capture_header(&self.cseq_regex, text);
+        let branch = Self::capture_header(&self.branch_regex, text);
+        let to_user = Self::capture_header(&self.to_user_regex, text);
 
-        // 创建 SipRequest 结构
-        let sip_request = SipRequest {
+        Some(SipRequest {
             source_ip, // 使用从网络层捕获的真实源 IP，不信任数据包内容
             user_agent,
-            method: method.clone(),
+            method,
+            call_id,
+            cseq,
+            branch,
+            source_port: None,
+            dest_port: None,
+            to_user,
+        })
+    }
+
+    /// 对 UDP 负载进行分类：正常 SIP、畸形 SIP 或非 SIP
+    ///
+    /// 只对以 SIP 方法开头的请求做完整性检查；SIP 响应视为正常，
+    /// 其他内容（包括 CRLF 心跳）视为非 SIP。
+    pub fn classify(&self, data: &[u8]) -> PacketClass {
+        if data.starts_with(b"SIP/2.0 ") {
+            return PacketClass::Sip;
+        }
+        let looks_like_request = SIP_METHODS.iter().any(|method| {
+            data.len() > method.len()
+                && data.starts_with(method.as_bytes())
+                && data[method.len()] == b' '
+        });
+        if !looks_like_request {
+            return PacketClass::NotSip;
+        }
+
+        let text = match std::str::from_utf8(data) {
+            Ok(t) => t,
+            Err(_) => return PacketClass::Malformed(MalformedReason::InvalidEncoding),
         };
 
-        // 是 SIP REGISTER 或 INVITE 请求，输出日志
-        info!(
-            "收到 SIP {} 请求，来源 IP: {}（网络层真实IP），User-Agent: {}",
-            sip_request.method, sip_request.source_ip, sip_request.user_agent
+        let request_line = text.lines().next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let well_formed_line = matches!(
+            (parts.next(), parts.next(), parts.next(), parts.next()),
+            (Some(_), Some(uri), Some("SIP/2.0"), None) if !uri.is_empty()
         );
+        if !well_formed_line {
+            return PacketClass::Malformed(MalformedReason::BadRequestLine);
+        }
 
-        Some(sip_request)
+        let head = match text.split_once("\r\n\r\n") {
+            Some((head, _)) => head,
+            None => return PacketClass::Malformed(MalformedReason::Truncated),
+        };
+
+        // 消息体短于 Content-Length 不算畸形：超过 MTU 的请求（例如带大 SDP 的 INVITE）在路径上被截断
+        // 或分片时就是这样，合法话机也会发出
+        let mut seen = [false; MANDATORY_HEADERS.len()];
+        for line in head.split("\r\n").skip(1) {
+            let name = match line.split_once(':') {
+                Some((name, _)) => name.trim().to_ascii_lowercase(),
+                None => continue,
+            };
+            for (i, (full, compact)) in MANDATORY_HEADERS.iter().enumerate() {
+                if name == *full || Some(name.as_str()) == *compact {
+                    seen[i] = true;
+                }
+            }
+        }
+        if seen.iter().any(|found| !found) {
+            return PacketClass::Malformed(MalformedReason::MissingHeader);
+        }
+
+        PacketClass::Sip
+    }
+
+    /// 提取正则第一个捕获组并去除首尾空白
+    fn capture_header(regex: &Regex, text: &str) -> Option<String> {
+        regex
+            .captures(text)
+            .and_then(|cap| cap.get(1))
+            .map(|m| m.as_str().trim().to_string())
+            .filter(|s| !s.is_empty())
     }
 }
 
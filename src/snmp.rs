@@ -0,0 +1,752 @@
+use crate::enforcement::Enforcer;
+use crate::events::{Event, EventKind, QueueReceiver};
+use log::{debug, error, info, warn};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 默认的 AgentX master 地址（net-snmp 的 Unix socket）
+const DEFAULT_MASTER: &str = "/var/agentx/master";
+
+/// 默认根 OID：NET-SNMP-MIB::netSnmpPlaypen 下的实验分支，正式部署可换成自己的企业号
+const DEFAULT_ROOT_OID: &str = "1.3.6.1.4.1.8072.9999.9999.1";
+
+/// 断线后的重连间隔
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 当前封禁数、后端状态和包速率的刷新周期
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 与 master 通信的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// sysUpTime.0 和 snmpTrapOID.0，Notify 的前两个变量绑定
+const SYS_UPTIME_OID: &[u32] = &[1, 3, 6, 1, 2, 1, 1, 3, 0];
+const SNMP_TRAP_OID: &[u32] = &[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+/// AgentX 头部标志：负载使用网络字节序
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+/// AgentX 头部标志：请求带有非默认 context
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+
+/// AgentX PDU 类型（RFC 2741）
+mod pdu {
+    pub const OPEN: u8 = 1;
+    pub const CLOSE: u8 = 2;
+    pub const REGISTER: u8 = 3;
+    pub const GET: u8 = 5;
+    pub const GET_NEXT: u8 = 6;
+    pub const GET_BULK: u8 = 7;
+    pub const TEST_SET: u8 = 8;
+    pub const NOTIFY: u8 = 12;
+    pub const RESPONSE: u8 = 18;
+}
+
+/// SNMP 错误码 notWritable
+const ERROR_NOT_WRITABLE: u16 = 17;
+
+/// 封禁原因的严重级别（1 低、2 中、3 高），用于决定是否发送 trap
+fn severity(reason: &str) -> u8 {
+    match reason {
+        // 针对账号的攻击
+        "AUTH_FAILURE" | "ACL_DENIED" | "DIGEST" => 3,
+        // 噪声较大、误判代价低的检测
+        "MALFORMED_PACKET" | "GREYLIST_VIOLATION" | "AUTH_CHALLENGE" => 1,
+        _ => 2,
+    }
+}
+
+/// 变量绑定的值
+#[derive(Debug, Clone)]
+enum Value {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    Oid(Vec<u32>),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn type_code(&self) -> u16 {
+        match self {
+            Value::Integer(_) => 2,
+            Value::OctetString(_) => 4,
+            Value::Oid(_) => 6,
+            Value::Gauge32(_) => 66,
+            Value::TimeTicks(_) => 67,
+            Value::Counter64(_) => 70,
+            Value::NoSuchObject => 128,
+            Value::NoSuchInstance => 129,
+            Value::EndOfMibView => 130,
+        }
+    }
+}
+
+/// 定期刷新的数据（查询防火墙较慢，不在每次 GET 时执行）
+#[derive(Debug, Default, Clone, Copy)]
+struct Cached {
+    active_bans: u32,
+    /// 1 正常、2 未启用、3 异常
+    backend_status: i32,
+    /// 最近一个刷新周期的包速率（个/秒）
+    packet_rate: u32,
+}
+
+/// 与 master 的连接（Unix socket 或 TCP）
+enum AgentxStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl AgentxStream {
+    fn connect(master: &str) -> io::Result<Self> {
+        let stream = if master.starts_with('/') {
+            let stream = UnixStream::connect(master)?;
+            stream.set_read_timeout(Some(IO_TIMEOUT))?;
+            AgentxStream::Unix(stream)
+        } else {
+            let stream = TcpStream::connect(master)?;
+            stream.set_read_timeout(Some(IO_TIMEOUT))?;
+            AgentxStream::Tcp(stream)
+        };
+        Ok(stream)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            AgentxStream::Unix(s) => AgentxStream::Unix(s.try_clone()?),
+            AgentxStream::Tcp(s) => AgentxStream::Tcp(s.try_clone()?),
+        })
+    }
+
+    /// 会话建立后取消读超时，阻塞等待 master 的请求
+    fn clear_read_timeout(&self) -> io::Result<()> {
+        match self {
+            AgentxStream::Unix(s) => s.set_read_timeout(None),
+            AgentxStream::Tcp(s) => s.set_read_timeout(None),
+        }
+    }
+}
+
+impl Read for AgentxStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AgentxStream::Unix(s) => s.read(buf),
+            AgentxStream::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for AgentxStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AgentxStream::Unix(s) => s.write(buf),
+            AgentxStream::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            AgentxStream::Unix(s) => s.flush(),
+            AgentxStream::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+/// 收到的 PDU
+struct Pdu {
+    kind: u8,
+    flags: u8,
+    session_id: u32,
+    transaction_id: u32,
+    packet_id: u32,
+    payload: Vec<u8>,
+}
+
+impl Pdu {
+    fn read(stream: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0u8; 20];
+        stream.read_exact(&mut header)?;
+        let flags = header[2];
+        let field = |offset: usize| {
+            PduReader {
+                data: &header,
+                pos: offset,
+                big_endian: flags & FLAG_NETWORK_BYTE_ORDER != 0,
+            }
+            .u32()
+            .unwrap_or(0)
+        };
+        let length = field(16) as usize;
+        if length > 1 << 20 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "PDU 过大"));
+        }
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload)?;
+        Ok(Self {
+            kind: header[1],
+            flags,
+            session_id: field(4),
+            transaction_id: field(8),
+            packet_id: field(12),
+            payload,
+        })
+    }
+
+    fn reader(&self) -> PduReader<'_> {
+        PduReader {
+            data: &self.payload,
+            pos: 0,
+            big_endian: self.flags & FLAG_NETWORK_BYTE_ORDER != 0,
+        }
+    }
+}
+
+/// PDU 负载解析（字节序由头部标志决定）
+#[derive(Clone, Copy)]
+struct PduReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl PduReader<'_> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let slice = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b: [u8; 2] = self.bytes(2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let b: [u8; 4] = self.bytes(4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    /// 读取 OID，返回 (OID, include 标志)
+    fn oid(&mut self) -> Option<(Vec<u32>, bool)> {
+        let n_subid = self.u8()? as usize;
+        let prefix = self.u8()?;
+        let include = self.u8()? != 0;
+        self.u8()?;
+        let mut oid = Vec::with_capacity(n_subid + 5);
+        if prefix != 0 {
+            oid.extend_from_slice(&[1, 3, 6, 1, u32::from(prefix)]);
+        }
+        for _ in 0..n_subid {
+            oid.push(self.u32()?);
+        }
+        Some((oid, include))
+    }
+
+    fn octets(&mut self) -> Option<Vec<u8>> {
+        let length = self.u32()? as usize;
+        let value = self.bytes(length)?.to_vec();
+        self.bytes((4 - length % 4) % 4)?;
+        Some(value)
+    }
+
+    /// 跳过非默认 context
+    fn skip_context(&mut self, flags: u8) -> Option<()> {
+        if flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+            self.octets()?;
+        }
+        Some(())
+    }
+}
+
+/// PDU 负载构造（始终使用网络字节序）
+#[derive(Default)]
+struct PduWriter {
+    buf: Vec<u8>,
+}
+
+impl PduWriter {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn oid(&mut self, oid: &[u32], include: bool) {
+        self.u8(oid.len() as u8);
+        self.u8(0);
+        self.u8(u8::from(include));
+        self.u8(0);
+        for subid in oid {
+            self.u32(*subid);
+        }
+    }
+
+    fn octets(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value);
+        self.buf
+            .extend(std::iter::repeat_n(0, (4 - value.len() % 4) % 4));
+    }
+
+    fn varbind(&mut self, oid: &[u32], value: &Value) {
+        self.u16(value.type_code());
+        self.u16(0);
+        self.oid(oid, false);
+        match value {
+            Value::Integer(v) => self.u32(*v as u32),
+            Value::OctetString(v) => self.octets(v),
+            Value::Oid(v) => self.oid(v, false),
+            Value::Gauge32(v) | Value::TimeTicks(v) => self.u32(*v),
+            Value::Counter64(v) => self.buf.extend_from_slice(&v.to_be_bytes()),
+            Value::NoSuchObject | Value::NoSuchInstance | Value::EndOfMibView => {}
+        }
+    }
+
+    /// 加上 20 字节头部
+    fn into_pdu(self, kind: u8, session_id: u32, transaction_id: u32, packet_id: u32) -> Vec<u8> {
+        let mut out = Vec::with_capacity(20 + self.buf.len());
+        out.extend_from_slice(&[1, kind, FLAG_NETWORK_BYTE_ORDER, 0]);
+        out.extend_from_slice(&session_id.to_be_bytes());
+        out.extend_from_slice(&transaction_id.to_be_bytes());
+        out.extend_from_slice(&packet_id.to_be_bytes());
+        out.extend_from_slice(&(self.buf.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.buf);
+        out
+    }
+}
+
+/// 当前会话（供发送 Notify 使用）
+struct Session {
+    stream: AgentxStream,
+    session_id: u32,
+}
+
+/// SNMP AgentX 子代理
+///
+/// 连接本机 snmpd（需在 snmpd.conf 中开启 `master agentx`），在根 OID 下注册只读标量：
+///
+/// | OID（相对根） | 名称 | 类型 |
+/// |---|---|---|
+/// | `.1.1.0` | 当前封禁数 | Gauge32 |
+/// | `.1.2.0` | 后端状态（1 正常、2 未启用、3 异常） | INTEGER |
+/// | `.1.3.0` | 包速率（个/秒） | Gauge32 |
+/// | `.1.4.0` ~ `.1.9.0` | 数据包、SIP 请求、检测、封禁、解封、抓包丢包累计数 | Counter64 |
+///
+/// 严重级别达到阈值的封禁通过 master 发送 `.2.0.1` 通知，附带 IP、原因、严重级别和来源（`.3.1` ~ `.3.4`）。
+pub struct SnmpAgent {
+    master: String,
+    root: Vec<u32>,
+    trap_severity: u8,
+    started: Instant,
+    cached: Mutex<Cached>,
+    session: Mutex<Option<Session>>,
+    enforcer: Arc<Enforcer>,
+}
+
+impl SnmpAgent {
+    /// 从环境变量创建，未设置 `UABLOCK_SNMP_AGENTX` 时返回 None
+    ///
+    /// - `UABLOCK_SNMP_AGENTX`：master 地址，Unix socket 路径或 `host:port`（为空时使用 `/var/agentx/master`）
+    /// - `UABLOCK_SNMP_ROOT_OID`：根 OID（默认位于 netSnmpPlaypen 实验分支）
+    /// - `UABLOCK_SNMP_TRAP_SEVERITY`：发送 trap 的最低严重级别（1~3，默认 2；大于 3 时不发送）
+    pub fn from_env(enforcer: Arc<Enforcer>) -> Result<Option<Self>, String> {
+        let master = match std::env::var("UABLOCK_SNMP_AGENTX") {
+            Ok(master) if master.is_empty() => DEFAULT_MASTER.to_string(),
+            Ok(master) => master,
+            Err(_) => return Ok(None),
+        };
+        let root_oid =
+            std::env::var("UABLOCK_SNMP_ROOT_OID").unwrap_or_else(|_| DEFAULT_ROOT_OID.to_string());
+        let root = root_oid
+            .trim_start_matches('.')
+            .split('.')
+            .map(|s| s.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("UABLOCK_SNMP_ROOT_OID 无效: {}", root_oid))?;
+        if root.len() < 2 || root.len() > 100 {
+            return Err(format!("UABLOCK_SNMP_ROOT_OID 无效: {}", root_oid));
+        }
+        let trap_severity = std::env::var("UABLOCK_SNMP_TRAP_SEVERITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        Ok(Some(Self {
+            master,
+            root,
+            trap_severity,
+            started: Instant::now(),
+            cached: Mutex::new(Cached::default()),
+            session: Mutex::new(None),
+            enforcer,
+        }))
+    }
+
+    /// 启动会话、数据刷新和 trap 发送线程
+    pub fn start(self) -> Result<(), String> {
+        let (tx, rx) = self.enforcer.events().queue();
+        let threshold = self.trap_severity;
+        self.enforcer.events().subscribe(move |event| {
+            if event.kind != EventKind::Ban || severity(&event.reason) < threshold {
+                return true;
+            }
+            tx.send(event.clone())
+        });
+
+        info!(
+            "【SNMP】AgentX 子代理连接 {}，根 OID: {}",
+            self.master,
+            format_oid(&self.root)
+        );
+        let this = Arc::new(self);
+        let refresher = this.clone();
+        std::thread::Builder::new()
+            .name("snmp-refresh".to_string())
+            .spawn(move || refresher.refresh_loop())
+            .map_err(|e| format!("启动 SNMP 刷新线程失败: {}", e))?;
+        let notifier = this.clone();
+        std::thread::Builder::new()
+            .name("snmp-trap".to_string())
+            .spawn(move || notifier.notify_loop(rx))
+            .map_err(|e| format!("启动 SNMP trap 线程失败: {}", e))?;
+        std::thread::Builder::new()
+            .name("snmp-agentx".to_string())
+            .spawn(move || loop {
+                if let Err(e) = this.session() {
+                    warn!(
+                        "【SNMP】AgentX 连接中断: {}，{} 秒后重连",
+                        e,
+                        RECONNECT_DELAY.as_secs()
+                    );
+                }
+                *this.session.lock().unwrap() = None;
+                std::thread::sleep(RECONNECT_DELAY);
+            })
+            .map_err(|e| format!("启动 SNMP 会话线程失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 系统运行时间（百分之一秒）
+    fn uptime(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 10) as u32
+    }
+
+    /// 一次会话：Open、Register，然后处理 master 的请求
+    fn session(&self) -> Result<(), String> {
+        let mut stream = AgentxStream::connect(&self.master).map_err(|e| e.to_string())?;
+
+        let mut open = PduWriter::default();
+        open.u8(0);
+        open.u8(0);
+        open.u8(0);
+        open.u8(0);
+        open.oid(&[], false);
+        open.octets(b"uablock-rust");
+        stream
+            .write_all(&open.into_pdu(pdu::OPEN, 0, 0, 1))
+            .map_err(|e| e.to_string())?;
+        let response = Pdu::read(&mut stream).map_err(|e| e.to_string())?;
+        check_response(&response)?;
+        let session_id = response.session_id;
+
+        let mut register = PduWriter::default();
+        register.u8(0);
+        register.u8(127);
+        register.u8(0);
+        register.u8(0);
+        register.oid(&self.root, false);
+        stream
+            .write_all(&register.into_pdu(pdu::REGISTER, session_id, 0, 2))
+            .map_err(|e| e.to_string())?;
+        check_response(&Pdu::read(&mut stream).map_err(|e| e.to_string())?)?;
+        info!("【SNMP】已在 {} 注册子树", self.master);
+
+        stream.clear_read_timeout().map_err(|e| e.to_string())?;
+        *self.session.lock().unwrap() = Some(Session {
+            stream: stream.try_clone().map_err(|e| e.to_string())?,
+            session_id,
+        });
+
+        loop {
+            let request = Pdu::read(&mut stream).map_err(|e| e.to_string())?;
+            let reply = match request.kind {
+                pdu::GET | pdu::GET_NEXT | pdu::GET_BULK => self.handle_get(&request),
+                pdu::TEST_SET => self.response(&request, ERROR_NOT_WRITABLE, 1, &[]),
+                pdu::RESPONSE => continue,
+                pdu::CLOSE => return Err("master 关闭了会话".to_string()),
+                // CommitSet/UndoSet/CleanupSet 等只需确认
+                _ => self.response(&request, 0, 0, &[]),
+            };
+            // 与 trap 线程共用连接，写入时加锁
+            let mut session = self.session.lock().unwrap();
+            let writer = session
+                .as_mut()
+                .map(|s| &mut s.stream)
+                .unwrap_or(&mut stream);
+            writer.write_all(&reply).map_err(|e| e.to_string())?;
+        }
+    }
+
+    fn response(
+        &self,
+        request: &Pdu,
+        error: u16,
+        index: u16,
+        varbinds: &[(Vec<u32>, Value)],
+    ) -> Vec<u8> {
+        let mut writer = PduWriter::default();
+        writer.u32(self.uptime());
+        writer.u16(error);
+        writer.u16(index);
+        for (oid, value) in varbinds {
+            writer.varbind(oid, value);
+        }
+        writer.into_pdu(
+            pdu::RESPONSE,
+            request.session_id,
+            request.transaction_id,
+            request.packet_id,
+        )
+    }
+
+    /// 处理 Get / GetNext / GetBulk
+    fn handle_get(&self, request: &Pdu) -> Vec<u8> {
+        let table = self.table();
+        let mut reader = request.reader();
+        let mut varbinds = Vec::new();
+        if reader.skip_context(request.flags).is_none() {
+            return self.response(request, 0, 0, &[]);
+        }
+        let (non_repeaters, max_repetitions) = if request.kind == pdu::GET_BULK {
+            (
+                reader.u16().unwrap_or(0) as usize,
+                reader.u16().unwrap_or(0) as usize,
+            )
+        } else {
+            (0, 0)
+        };
+
+        let mut ranges = Vec::new();
+        while !reader.is_empty() {
+            let (Some((start, include)), Some((end, _))) = (reader.oid(), reader.oid()) else {
+                break;
+            };
+            ranges.push((start, include, end));
+        }
+
+        match request.kind {
+            pdu::GET => {
+                for (start, _, _) in &ranges {
+                    varbinds.push((start.clone(), self.get(&table, start)));
+                }
+            }
+            pdu::GET_NEXT => {
+                for (start, include, end) in &ranges {
+                    varbinds.push(next(&table, start, *include, end));
+                }
+            }
+            _ => {
+                for (start, include, end) in ranges.iter().take(non_repeaters) {
+                    varbinds.push(next(&table, start, *include, end));
+                }
+                let repeaters: Vec<_> = ranges.iter().skip(non_repeaters).collect();
+                let mut cursors: Vec<(Vec<u32>, bool)> = repeaters
+                    .iter()
+                    .map(|(start, include, _)| (start.clone(), *include))
+                    .collect();
+                for _ in 0..max_repetitions {
+                    for ((_, _, end), cursor) in repeaters.iter().zip(cursors.iter_mut()) {
+                        let (oid, value) = next(&table, &cursor.0, cursor.1, end);
+                        *cursor = (oid.clone(), false);
+                        varbinds.push((oid, value));
+                    }
+                }
+            }
+        }
+        self.response(request, 0, 0, &varbinds)
+    }
+
+    /// 精确查找；根 OID 下不存在的实例返回 noSuchInstance
+    fn get(&self, table: &[(Vec<u32>, Value)], oid: &[u32]) -> Value {
+        if let Some((_, value)) = table.iter().find(|(o, _)| o == oid) {
+            return value.clone();
+        }
+        let is_known_object = table
+            .iter()
+            .any(|(o, _)| oid.starts_with(&o[..o.len() - 1]));
+        if is_known_object {
+            Value::NoSuchInstance
+        } else {
+            Value::NoSuchObject
+        }
+    }
+
+    /// 按 OID 排序的标量表
+    fn table(&self) -> Vec<(Vec<u32>, Value)> {
+        let cached = *self.cached.lock().unwrap();
+        let stats = self.enforcer.stats();
+        let counter =
+            |c: &std::sync::atomic::AtomicU64| Value::Counter64(c.load(Ordering::Relaxed));
+        let values = [
+            Value::Gauge32(cached.active_bans),
+            Value::Integer(cached.backend_status),
+            Value::Gauge32(cached.packet_rate),
+            counter(&stats.packets),
+            counter(&stats.sip_requests),
+            counter(&stats.detections),
+            counter(&stats.bans),
+            counter(&stats.unbans),
+            counter(&stats.capture_drops),
+        ];
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (self.oid(&[1, i as u32 + 1, 0]), value))
+            .collect()
+    }
+
+    fn oid(&self, suffix: &[u32]) -> Vec<u32> {
+        let mut oid = self.root.clone();
+        oid.extend_from_slice(suffix);
+        oid
+    }
+
+    /// 定期刷新当前封禁数、后端状态和包速率
+    fn refresh_loop(&self) {
+        let stats = self.enforcer.stats();
+        let mut last_packets = stats.packets.load(Ordering::Relaxed);
+        let mut last_refresh = Instant::now();
+        loop {
+            let (active_bans, backend_status) = if self.enforcer.firewall().is_none() {
+                (0, 2)
+            } else {
+                match self.enforcer.list_bans() {
+                    Ok(bans) => (bans.len() as u32, 1),
+                    Err(e) => {
+                        debug!("【SNMP】查询封禁列表失败: {}", e);
+                        (0, 3)
+                    }
+                }
+            };
+            let packets = stats.packets.load(Ordering::Relaxed);
+            let elapsed = last_refresh.elapsed().as_secs_f64().max(1.0);
+            let packet_rate = (packets.saturating_sub(last_packets) as f64 / elapsed) as u32;
+            last_packets = packets;
+            last_refresh = Instant::now();
+            *self.cached.lock().unwrap() = Cached {
+                active_bans,
+                backend_status,
+                packet_rate,
+            };
+            std::thread::sleep(REFRESH_INTERVAL);
+        }
+    }
+
+    /// 通过 master 发送封禁通知
+    fn notify_loop(&self, rx: QueueReceiver<Event>) {
+        let mut packet_id: u32 = 1000;
+        for event in rx {
+            let mut session = self.session.lock().unwrap();
+            let Some(session) = session.as_mut() else {
+                debug!("【SNMP】未连接 master，丢弃 IP {} 的封禁通知", event.ip);
+                continue;
+            };
+            packet_id = packet_id.wrapping_add(1);
+            let mut writer = PduWriter::default();
+            writer.varbind(SYS_UPTIME_OID, &Value::TimeTicks(self.uptime()));
+            writer.varbind(SNMP_TRAP_OID, &Value::Oid(self.oid(&[2, 0, 1])));
+            writer.varbind(
+                &self.oid(&[3, 1, 0]),
+                &Value::OctetString(event.ip.to_string().into_bytes()),
+            );
+            writer.varbind(
+                &self.oid(&[3, 2, 0]),
+                &Value::OctetString(event.reason.clone().into_bytes()),
+            );
+            writer.varbind(
+                &self.oid(&[3, 3, 0]),
+                &Value::Integer(i32::from(severity(&event.reason))),
+            );
+            writer.varbind(
+                &self.oid(&[3, 4, 0]),
+                &Value::OctetString(event.origin.clone().into_bytes()),
+            );
+            let notify = writer.into_pdu(pdu::NOTIFY, session.session_id, 0, packet_id);
+            match session.stream.write_all(&notify) {
+                Ok(()) => debug!("【SNMP】已发送封禁通知 IP: {}", event.ip),
+                Err(e) => error!("【SNMP】发送封禁通知失败: {}", e),
+            }
+        }
+    }
+}
+
+/// GetNext 语义：范围内第一个大于（include 时大于等于）start 的 OID
+fn next(
+    table: &[(Vec<u32>, Value)],
+    start: &[u32],
+    include: bool,
+    end: &[u32],
+) -> (Vec<u32>, Value) {
+    table
+        .iter()
+        .find(|(oid, _)| {
+            let after = if include {
+                oid.as_slice() >= start
+            } else {
+                oid.as_slice() > start
+            };
+            after && (end.is_empty() || oid.as_slice() < end)
+        })
+        .cloned()
+        .unwrap_or_else(|| (start.to_vec(), Value::EndOfMibView))
+}
+
+/// 检查 master 的 Response 是否成功
+fn check_response(response: &Pdu) -> Result<(), String> {
+    if response.kind != pdu::RESPONSE {
+        return Err(format!("期望 Response，收到 PDU 类型 {}", response.kind));
+    }
+    let mut reader = response.reader();
+    reader.u32();
+    match reader.u16() {
+        Some(0) => Ok(()),
+        Some(error) => Err(format!("master 返回错误 {}", error)),
+        None => Err("Response 格式无效".to_string()),
+    }
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter()
+        .map(|subid| subid.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
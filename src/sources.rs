@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// 分片数（2 的幂）
+const SHARDS: usize = 16;
+
+/// 分片已满时一次淘汰的比例（1/8）：扫描分片的开销分摊到之后的多次插入上
+const EVICT_DIVISOR: usize = 8;
+
+/// 单个来源的状态
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SourceState {
+    /// 最近一次发出（非重传）SIP 请求的时间
+    pub last_seen: Option<Instant>,
+    /// 处理过的 SIP 请求数（不含重传）
+    pub requests: u64,
+    /// 检测次数
+    pub detections: u64,
+    /// 被封禁的次数（屡次被封禁的来源信誉更差）
+    pub bans: u32,
+    /// 惩罚分（上次更新时的值，未衰减）和更新时间，由 [`StrikeTracker`](crate::strikes::StrikeTracker) 维护
+    pub strikes: Option<(f64, Instant)>,
+}
+
+impl SourceState {
+    /// 既没有近期请求也没有惩罚分，可以删除
+    fn is_idle(&self) -> bool {
+        self.last_seen.is_none() && self.strikes.is_none()
+    }
+
+    /// 最近一次活动（请求或惩罚分更新）的时间
+    fn last_active(&self) -> Option<Instant> {
+        self.last_seen.max(self.strikes.map(|(_, updated)| updated))
+    }
+}
+
+/// 按来源 IP 的状态表，供各检测器共享
+///
+/// 按 IP 的哈希分成多个分片，每个分片一把锁：不同来源的更新互不阻塞，
+/// 多个线程同时处理数据包时不会在一把全局锁上排队。单个来源的读-改-写在
+/// [`SourceTable::update`] 中完成，持有的只是该来源所在分片的锁。
+///
+/// 用 [`SourceTable::with_limit`] 创建时表的大小有上限：分片已满时加入新来源会一次淘汰该分片中
+/// 最久未活动的 1/8 来源。
+pub struct SourceTable {
+    shards: Box<[Mutex<HashMap<IpAddr, SourceState>>]>,
+    /// 每个分片的最大来源数，0 表示不限制
+    shard_limit: usize,
+    evicted: AtomicU64,
+}
+
+impl Default for SourceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SourceTable {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            shard_limit: 0,
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    /// 最多保存约 `limit` 个来源（按分片平均分配），0 表示不限制
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            shard_limit: limit.div_ceil(SHARDS),
+            ..Self::new()
+        }
+    }
+
+    fn shard(&self, ip: &IpAddr) -> &Mutex<HashMap<IpAddr, SourceState>> {
+        let key = match ip {
+            IpAddr::V4(v4) => u64::from(u32::from(*v4)),
+            IpAddr::V6(v6) => {
+                let bits = u128::from(*v6);
+                (bits as u64) ^ ((bits >> 64) as u64)
+            }
+        };
+        // 乘法哈希取高位，相邻地址也能均匀分布
+        let index = key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - SHARDS.trailing_zeros());
+        &self.shards[index as usize]
+    }
+
+    /// 来源的当前状态
+    pub fn get(&self, ip: &IpAddr) -> Option<SourceState> {
+        self.shard(ip).lock().unwrap().get(ip).copied()
+    }
+
+    /// 原子地读取并修改来源的状态（不存在时从默认值开始）
+    ///
+    /// 分片已满时先淘汰该分片中最久未活动的一批来源（见 [`EVICT_DIVISOR`]），
+    /// 之后的插入在分片再次填满前不需要扫描。
+    pub fn update<R>(&self, ip: IpAddr, update: impl FnOnce(&mut SourceState) -> R) -> R {
+        let mut shard = self.shard(&ip).lock().unwrap();
+        if self.shard_limit > 0 && shard.len() >= self.shard_limit && !shard.contains_key(&ip) {
+            let count = (shard.len() / EVICT_DIVISOR).max(1);
+            let mut ages: Vec<(Option<Instant>, IpAddr)> = shard
+                .iter()
+                .map(|(ip, state)| (state.last_active(), *ip))
+                .collect();
+            ages.select_nth_unstable(count - 1);
+            for (_, oldest) in &ages[..count] {
+                shard.remove(oldest);
+            }
+            self.evicted.fetch_add(count as u64, Ordering::Relaxed);
+        }
+        update(shard.entry(ip).or_default())
+    }
+
+    /// 逐个分片修改所有来源的状态，之后既没有近期请求也没有惩罚分的来源被删除
+    pub fn retain(&self, mut update: impl FnMut(&IpAddr, &mut SourceState)) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().retain(|ip, state| {
+                update(ip, state);
+                !state.is_idle()
+            });
+        }
+    }
+
+    /// 逐个分片收集所有来源的信息
+    pub fn collect<T>(&self, mut select: impl FnMut(&IpAddr, &SourceState) -> Option<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            items.extend(shard.iter().filter_map(|(ip, state)| select(ip, state)));
+        }
+        items
+    }
+
+    /// 表中的来源数
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 因表满被淘汰的来源数（累计）
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn full_shards_evict_their_least_recently_active_sources_in_batches() {
+        let table = SourceTable::with_limit(SHARDS * 16);
+        let start = Instant::now();
+        let ips: Vec<IpAddr> = (0..2000u32)
+            .map(|i| IpAddr::from(std::net::Ipv4Addr::from(0xC000_0200 + i)))
+            .collect();
+        for (i, ip) in ips.iter().enumerate() {
+            table.update(*ip, |state| {
+                state.last_seen = Some(start + Duration::from_millis(i as u64))
+            });
+        }
+        assert!(table.len() <= SHARDS * 16);
+        assert_eq!(table.evicted(), (ips.len() - table.len()) as u64);
+
+        // 每个分片中保留下来的来源都比被淘汰的来源活动得更晚
+        for shard in table.shards.iter() {
+            let shard = shard.lock().unwrap();
+            let kept = ips.iter().position(|ip| shard.contains_key(ip)).unwrap();
+            for ip in &ips[kept..] {
+                if std::ptr::eq(table.shard(ip), table.shard(&ips[kept])) {
+                    assert!(
+                        shard.contains_key(ip),
+                        "{} 被淘汰，但更早的 {} 仍在",
+                        ip,
+                        ips[kept]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn existing_sources_are_updated_without_eviction() {
+        let table = SourceTable::with_limit(SHARDS);
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        for _ in 0..10 {
+            table.update(ip, |state| state.requests += 1);
+        }
+        assert_eq!(table.get(&ip).unwrap().requests, 10);
+        assert_eq!(table.evicted(), 0);
+    }
+}
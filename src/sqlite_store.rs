@@ -0,0 +1,173 @@
+use crate::bans::{BanReason, BanRecord, BanStore};
+use crate::reason::ReasonCode;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS bans (
+    ip         TEXT PRIMARY KEY,
+    reason     TEXT NOT NULL,
+    code       TEXT NOT NULL,
+    origin     TEXT NOT NULL,
+    user_agent TEXT,
+    method     TEXT,
+    rule       TEXT,
+    tenant     TEXT,
+    banned_at  INTEGER,
+    expires_at INTEGER,
+    hits       INTEGER NOT NULL DEFAULT 0,
+    last_hit   INTEGER
+)";
+
+const UPSERT: &str = "
+INSERT OR REPLACE INTO bans
+    (ip, reason, code, origin, user_agent, method, rule, tenant, banned_at, expires_at, hits, last_hit)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)";
+
+/// SQLite 封禁记录存储（`sqlite` 特性）
+///
+/// 每个封禁 IP 一行，记录原因、UA、方法、封禁时间、到期时间和命中次数。封禁和解封时立即写入，
+/// 命中次数随每分钟的定时任务同步；启动时先从数据库恢复封禁记录，再与防火墙规则核对，
+/// 不依赖状态文件的定期保存，进程被强制结束也不会丢失最近的封禁原因和到期时间。
+pub struct SqliteBanStore {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBanStore {
+    /// 打开（不存在时创建）数据库
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("无法打开封禁数据库 {}: {}", path.display(), e))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| format!("无法初始化封禁数据库 {}: {}", path.display(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// `UABLOCK_BAN_DB` 指定的数据库，未设置时返回 None
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_BAN_DB") {
+            Ok(path) if !path.is_empty() => Self::open(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 读取全部记录（按 IP 排序）
+    pub fn load(&self) -> Result<Vec<BanRecord>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare("SELECT * FROM bans")
+            .map_err(|e| self.error("读取", e))?;
+        let records = statement
+            .query_map([], read_record)
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| self.error("读取", e))?;
+        let mut records: Vec<BanRecord> = records.into_iter().flatten().collect();
+        records.sort_by_key(|record| record.ip);
+        Ok(records)
+    }
+
+    /// 读取一个 IP 的记录
+    pub fn get(&self, ip: &IpAddr) -> Result<Option<BanRecord>, String> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT * FROM bans WHERE ip = ?1",
+                [ip.to_string()],
+                read_record,
+            )
+            .optional()
+            .map(Option::flatten)
+            .map_err(|e| self.error("读取", e))
+    }
+
+    fn error(&self, action: &str, e: rusqlite::Error) -> String {
+        format!("{}封禁数据库 {} 失败: {}", action, self.path.display(), e)
+    }
+}
+
+impl BanStore for SqliteBanStore {
+    fn upsert(&self, record: &BanRecord) -> Result<(), String> {
+        write_record(&self.conn.lock().unwrap(), record).map_err(|e| self.error("写入", e))
+    }
+
+    fn remove(&self, ip: &IpAddr) -> Result<(), String> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM bans WHERE ip = ?1", [ip.to_string()])
+            .map(|_| ())
+            .map_err(|e| self.error("写入", e))
+    }
+
+    fn replace_all(&self, records: &[BanRecord]) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction().map_err(|e| self.error("写入", e))?;
+        transaction
+            .execute("DELETE FROM bans", [])
+            .map_err(|e| self.error("写入", e))?;
+        for record in records {
+            write_record(&transaction, record).map_err(|e| self.error("写入", e))?;
+        }
+        transaction.commit().map_err(|e| self.error("写入", e))
+    }
+}
+
+fn write_record(conn: &Connection, record: &BanRecord) -> rusqlite::Result<()> {
+    let reason = &record.reason;
+    conn.execute(
+        UPSERT,
+        params![
+            record.ip.to_string(),
+            reason.reason,
+            reason.code.as_str(),
+            reason.origin,
+            reason.user_agent,
+            reason.method,
+            reason.rule,
+            reason.tenant,
+            record.banned_at,
+            reason.expires_at,
+            record.hits,
+            record.last_hit,
+        ],
+    )
+    .map(|_| ())
+}
+
+/// 读取一行记录，IP 无法解析的行（例如被手工改坏）忽略
+fn read_record(row: &Row) -> rusqlite::Result<Option<BanRecord>> {
+    let Ok(ip) = row.get::<_, String>("ip")?.parse::<IpAddr>() else {
+        return Ok(None);
+    };
+    let reason: String = row.get("reason")?;
+    let code: String = row.get("code")?;
+    Ok(Some(BanRecord {
+        ip,
+        reason: BanReason {
+            code: serde_json::from_value(serde_json::Value::String(code))
+                .unwrap_or_else(|_| ReasonCode::classify(&reason)),
+            reason,
+            origin: row.get("origin")?,
+            user_agent: row.get("user_agent")?,
+            method: row.get("method")?,
+            rule: row.get("rule")?,
+            expires_at: row.get("expires_at")?,
+            tenant: row.get("tenant")?,
+        },
+        banned_at: row.get("banned_at")?,
+        hits: row.get("hits")?,
+        last_hit: row.get("last_hit")?,
+        geo: None,
+    }))
+}
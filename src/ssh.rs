@@ -0,0 +1,269 @@
+use crate::firewall::FirewallBackend;
+use log::{debug, info};
+use std::net::IpAddr;
+use std::process::{Command, Output};
+
+/// 连接远端的超时秒数
+const CONNECT_TIMEOUT_SECS: u32 = 10;
+
+/// 复用连接在最后一个命令结束后保持的秒数
+const CONTROL_PERSIST_SECS: u32 = 60;
+
+/// 远程封禁：经 SSH 在另一台主机（如边界路由器）上执行封禁/解封命令
+///
+/// 抓包的主机只接收镜像流量、防火墙在另一台设备上时使用。封禁、解封、检查和列出各是一条命令模板，
+/// 模板中的 `{ip}` 替换为来源地址，`{port}` 替换为受保护的端口，`{iptables}` 按地址族替换为
+/// `iptables` 或 `ip6tables`；默认模板在远端 `FORWARD` 链中增删带 `uablock` 注释的 DROP 规则。
+/// 检查命令以退出码表示是否已封禁（0 为已封禁），列出命令的输出中每行第一个可解析的地址为一个封禁。
+///
+/// ssh 以 `BatchMode` 运行（只能用密钥登录，不会等待输入密码），并复用连接
+/// （`ControlMaster`），连续封禁时不必每次重新握手。
+pub struct SshFirewall {
+    host: String,
+    port: Option<u16>,
+    key: Option<String>,
+    block_port: Option<u16>,
+    block_command: String,
+    unblock_command: String,
+    check_command: String,
+    list_command: String,
+}
+
+impl SshFirewall {
+    /// `host` 为 ssh 的目标（`host` 或 `user@host`）
+    pub fn new(host: &str, block_port: Option<u16>) -> Self {
+        Self {
+            host: host.to_string(),
+            port: None,
+            key: None,
+            block_port,
+            block_command: default_rule("-I", block_port),
+            unblock_command: default_rule("-D", block_port),
+            check_command: default_rule("-C", block_port),
+            // 没有封禁时 grep 的退出码为 1，不算失败
+            list_command: "{ iptables -w -S FORWARD; ip6tables -w -S FORWARD; } | grep -- '--comment uablock' || true"
+                .to_string(),
+        }
+    }
+
+    /// ssh 端口（默认由 ssh 配置决定）
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// 登录使用的私钥文件
+    pub fn with_key(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    /// 封禁命令模板
+    pub fn with_block_command(mut self, template: &str) -> Self {
+        self.block_command = template.to_string();
+        self
+    }
+
+    /// 解封命令模板
+    pub fn with_unblock_command(mut self, template: &str) -> Self {
+        self.unblock_command = template.to_string();
+        self
+    }
+
+    /// 检查命令模板（退出码 0 表示已封禁）
+    pub fn with_check_command(mut self, template: &str) -> Self {
+        self.check_command = template.to_string();
+        self
+    }
+
+    /// 列出封禁的命令（不替换 `{ip}`）
+    pub fn with_list_command(mut self, command: &str) -> Self {
+        self.list_command = command.to_string();
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// 按地址替换模板中的占位符
+    pub fn render(&self, template: &str, ip: &IpAddr) -> String {
+        let iptables = match ip {
+            IpAddr::V4(_) => "iptables",
+            IpAddr::V6(_) => "ip6tables",
+        };
+        template
+            .replace("{ip}", &ip.to_string())
+            .replace(
+                "{port}",
+                &self.block_port.map(|p| p.to_string()).unwrap_or_default(),
+            )
+            .replace("{iptables}", iptables)
+    }
+
+    /// 在远端执行命令的 ssh 参数
+    pub fn ssh_args(&self, command: &str) -> Vec<String> {
+        let control_path = std::env::temp_dir().join("uablock-ssh-%C");
+        let mut args: Vec<String> = [
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            &format!("ConnectTimeout={}", CONNECT_TIMEOUT_SECS),
+            "-o",
+            "ControlMaster=auto",
+            "-o",
+            &format!("ControlPath={}", control_path.display()),
+            "-o",
+            &format!("ControlPersist={}", CONTROL_PERSIST_SECS),
+        ]
+        .map(str::to_string)
+        .to_vec();
+        if let Some(port) = self.port {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(key) = &self.key {
+            args.extend(["-i".to_string(), key.clone()]);
+        }
+        args.extend([self.host.clone(), "--".to_string(), command.to_string()]);
+        args
+    }
+
+    fn remote(&self, command: &str) -> Result<Output, String> {
+        debug!("在 {} 上执行: {}", self.host, command);
+        Command::new("ssh")
+            .args(self.ssh_args(command))
+            .output()
+            .map_err(|e| format!("执行 ssh 命令失败: {}", e))
+    }
+
+    /// 执行命令，成功时返回标准输出，失败时返回带说明的错误
+    fn check(&self, command: &str, action: &str) -> Result<String, String> {
+        let output = self.remote(command)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(format!(
+                "{}失败（{}）: {}",
+                action,
+                self.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// 确认可以登录远端
+    pub fn setup(&self) -> Result<(), String> {
+        self.check("true", "连接远端")?;
+        info!(
+            "远程封禁已就绪（{}，端口 {}）",
+            self.host,
+            self.block_port
+                .map_or("全部".to_string(), |p| p.to_string())
+        );
+        Ok(())
+    }
+
+    /// 解析列出命令的输出：每行第一个可解析的地址（去掉 `/32`、`/128` 前缀长度）
+    pub fn parse_list(text: &str) -> Vec<IpAddr> {
+        text.lines()
+            .filter_map(|line| {
+                line.split_whitespace().find_map(|token| {
+                    token
+                        .trim_end_matches("/32")
+                        .trim_end_matches("/128")
+                        .parse()
+                        .ok()
+                })
+            })
+            .collect()
+    }
+}
+
+/// 默认的规则模板：远端 FORWARD 链中带 uablock 注释的 DROP 规则
+fn default_rule(op: &str, block_port: Option<u16>) -> String {
+    let port = if block_port.is_some() {
+        " -p udp --dport {port}"
+    } else {
+        ""
+    };
+    format!(
+        "{{iptables}} -w {} FORWARD -s {{ip}}{} -m comment --comment uablock -j DROP",
+        op, port
+    )
+}
+
+impl FirewallBackend for SshFirewall {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.check(&self.render(&self.block_command, ip), "远程封禁")?;
+        info!("成功封禁 IP: {}（{}）", ip, self.host);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        if !self.is_blocked(ip) {
+            return Ok(());
+        }
+        self.check(&self.render(&self.unblock_command, ip), "远程解封")?;
+        info!("成功解封 IP: {}（{}）", ip, self.host);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        matches!(
+            self.remote(&self.render(&self.check_command, ip)),
+            Ok(output) if output.status.success()
+        )
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        let text = self.check(&self.list_command, "远程列出封禁")?;
+        Ok(Self::parse_list(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ssh_backend_renders_command_templates_per_address_family() {
+        let firewall = SshFirewall::new("root@edge.example.net", Some(5060))
+            .with_port(2222)
+            .with_key("/etc/uablock/id_ed25519");
+        assert_eq!(
+            firewall.render(
+                "{iptables} -w -I FORWARD -s {ip} -p udp --dport {port} -j DROP",
+                &ip("2001:db8::5")
+            ),
+            "ip6tables -w -I FORWARD -s 2001:db8::5 -p udp --dport 5060 -j DROP"
+        );
+        let args = firewall.ssh_args("ipset add sipblock 203.0.113.5");
+        assert!(args.contains(&"BatchMode=yes".to_string()));
+        assert_eq!(
+            args[args.len() - 7..],
+            [
+                "-p",
+                "2222",
+                "-i",
+                "/etc/uablock/id_ed25519",
+                "root@edge.example.net",
+                "--",
+                "ipset add sipblock 203.0.113.5"
+            ]
+        );
+        assert_eq!(
+            SshFirewall::parse_list(
+                "-A FORWARD -s 203.0.113.5/32 -p udp -m udp --dport 5060 -m comment --comment uablock -j DROP\n\
+             -A FORWARD -s 2001:db8::5/128 -m comment --comment uablock -j DROP\n\
+             198.51.100.7\n"
+            ),
+            vec![ip("203.0.113.5"), ip("2001:db8::5"), ip("198.51.100.7")]
+        );
+        Config::parse("backend = \"ssh\"").unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use crate::bans::BanRecord;
+use crate::offences::OffenceRecord;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 惩罚分记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrikeRecord {
+    pub ip: IpAddr,
+    pub score: f64,
+    /// 距上次更新的秒数（保存时刻）
+    pub age_secs: f64,
+}
+
+/// UA 速率窗口记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UaWindowRecord {
+    pub user_agent: String,
+    pub requests: u32,
+    pub sources: Vec<IpAddr>,
+    /// 窗口已开始的秒数（保存时刻）
+    pub age_secs: f64,
+}
+
+/// UA 临时拒绝记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UaDenyRecord {
+    pub user_agent: String,
+    /// 剩余拒绝秒数（保存时刻）
+    pub remaining_secs: f64,
+}
+
+/// 灰名单 (IP, UA) 状态记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GreylistRecord {
+    pub ip: IpAddr,
+    pub user_agent: String,
+    /// 是否已通过观察期
+    pub allowed: bool,
+    /// 进入当前状态的秒数（保存时刻）
+    pub age_secs: f64,
+    /// 临时丢弃剩余秒数（保存时刻，观察期内）
+    #[serde(default)]
+    pub hold_remaining_secs: f64,
+    /// 观察期内的请求数
+    #[serde(default)]
+    pub requests: u32,
+}
+
+/// 待解除的灰名单临时规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseRecord {
+    pub ip: IpAddr,
+    /// 距解除的秒数（保存时刻）
+    pub remaining_secs: f64,
+}
+
+/// 检测规则的计数窗口记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleCounterRecord {
+    pub rule: String,
+    pub ip: IpAddr,
+    pub count: u32,
+    /// 窗口已开始的秒数（保存时刻）
+    pub age_secs: f64,
+}
+
+/// 来源最近活动记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceRecord {
+    pub ip: IpAddr,
+    /// 距最近一次请求的秒数（保存时刻）
+    pub idle_secs: f64,
+}
+
+/// 需要跨重启保留的检测状态
+///
+/// 时间均以保存时刻为基准记录相对值，恢复时再扣除停机时长，
+/// 使衰减和过期在重启前后保持连续。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DetectionState {
+    /// 保存时间（Unix 时间戳，秒）
+    pub saved_at: u64,
+    #[serde(default)]
+    pub strikes: Vec<StrikeRecord>,
+    #[serde(default)]
+    pub ua_windows: Vec<UaWindowRecord>,
+    #[serde(default)]
+    pub ua_denied: Vec<UaDenyRecord>,
+    #[serde(default)]
+    pub greylist: Vec<GreylistRecord>,
+    /// 待解除的灰名单临时规则；恢复时已到期的立即解除，不会遗留
+    #[serde(default)]
+    pub releases: Vec<ReleaseRecord>,
+    #[serde(default)]
+    pub rule_counters: Vec<RuleCounterRecord>,
+    /// 检测任务跟踪的来源（只在升级快照中保存）
+    #[serde(default)]
+    pub sources: Vec<SourceRecord>,
+    /// 封禁记录（原因、封禁时间、命中次数），启动时与防火墙规则核对
+    #[serde(default)]
+    pub bans: Vec<BanRecord>,
+    /// 违规次数记录（启用封禁升级时），重启后继续按次数升级
+    #[serde(default)]
+    pub offences: Vec<OffenceRecord>,
+}
+
+impl DetectionState {
+    /// 自保存以来经过的秒数
+    pub fn elapsed_secs(&self) -> f64 {
+        unix_now().saturating_sub(self.saved_at) as f64
+    }
+}
+
+/// 检测状态文件（JSON 格式）
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// 读取状态文件，文件不存在时返回 None
+    pub fn load(&self) -> Result<Option<DetectionState>, String> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("读取状态文件 {} 失败: {}", self.path.display(), e)),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("解析状态文件 {} 失败: {}", self.path.display(), e))
+    }
+
+    /// 保存状态（先写临时文件再重命名，避免写入中断导致文件损坏）
+    pub fn save(&self, mut state: DetectionState) -> Result<(), String> {
+        state.saved_at = unix_now();
+        let content =
+            serde_json::to_string(&state).map_err(|e| format!("序列化检测状态失败: {}", e))?;
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)
+            .map_err(|e| format!("写入状态文件 {} 失败: {}", tmp_path.display(), e))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("替换状态文件 {} 失败: {}", self.path.display(), e))
+    }
+
+    /// 读取状态文件，失败时记录警告并返回 None
+    pub fn load_or_warn(&self) -> Option<DetectionState> {
+        match self.load() {
+            Ok(Some(state)) => {
+                info!(
+                    "从 {} 恢复检测状态（{} 秒前保存）",
+                    self.path.display(),
+                    state.elapsed_secs()
+                );
+                Some(state)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("{}，忽略已保存的检测状态", e);
+                None
+            }
+        }
+    }
+}
+
+/// 当前 Unix 时间戳（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,227 @@
+use crate::ban_latency::{BanLatency, BanLatencySnapshot};
+use crate::noise::NoiseKind;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// 最多分别统计的目标端口数，防止扫描大量端口时撑大内存
+const MAX_PORTS: usize = 256;
+
+/// 运行时统计计数器（可在多个线程间共享）
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// 捕获到的 UDP 数据包数
+    pub packets: AtomicU64,
+    /// 解析到的 SIP REGISTER/INVITE 请求数（含重传）
+    pub sip_requests: AtomicU64,
+    /// 识别为重传的请求数
+    pub retransmissions: AtomicU64,
+    /// 畸形 SIP 报文数
+    pub malformed: AtomicU64,
+    /// 检测次数
+    pub detections: AtomicU64,
+    /// 成功封禁次数
+    pub bans: AtomicU64,
+    /// 成功解封次数
+    pub unbans: AtomicU64,
+    /// 内核/网卡丢弃的数据包数（libpcap 累计值）
+    pub capture_drops: AtomicU64,
+    /// 处置队列已满而丢弃的处置数
+    pub enforcement_drops: AtomicU64,
+    /// 与同一 IP 待执行处置合并的次数
+    pub enforcement_coalesced: AtomicU64,
+    /// 处置队列中待执行的处置数
+    pub enforcement_pending: AtomicU64,
+    /// 命中判定缓存、跳过判定或防火墙检查的数据包数
+    pub decision_cache_hits: AtomicU64,
+    /// 只告警、未处置的检测数（局域网设备）
+    pub alerts: AtomicU64,
+    /// 来自可信来源、未参与判定的数据包数
+    pub trusted_packets: AtomicU64,
+    /// 封禁后本机仍在应答、判定为无效的封禁数
+    pub ineffective_bans: AtomicU64,
+    /// 封禁后端执行封禁/解封失败的次数
+    pub firewall_failures: AtomicU64,
+    /// 封禁后端命令因锁冲突等临时错误重试的次数
+    pub firewall_retries: AtomicU64,
+    /// 封禁后端连续失败、处于降级状态时为 1
+    pub firewall_degraded: AtomicU64,
+    /// 已封禁来源被跳过判定、只计数的数据包数
+    pub banned_hits: AtomicU64,
+    /// SIP 端口上收到的 STUN 报文数
+    pub noise_stun: AtomicU64,
+    /// SIP 端口上收到的 RTP/RTCP 报文数
+    pub noise_rtp: AtomicU64,
+    /// SIP 端口上收到的无法识别的二进制数据包数
+    pub noise_binary: AtomicU64,
+    /// 来源状态表中的来源数
+    pub source_entries: AtomicU64,
+    /// 来源状态表已满而淘汰的来源数
+    pub sources_evicted: AtomicU64,
+    /// 重传跟踪表中的事务数
+    pub transaction_entries: AtomicU64,
+    /// 重传跟踪表已满而未记录的事务数
+    pub transactions_untracked: AtomicU64,
+    /// 事件订阅者队列中尚未处理的条目数
+    pub event_queue_pending: AtomicU64,
+    /// 事件订阅者队列已满而丢弃的条目数
+    pub events_dropped: AtomicU64,
+    /// 本进程的常驻内存（字节）
+    pub resident_memory: AtomicU64,
+    /// 从抓包到封禁规则生效的分段延迟
+    pub ban_latency: BanLatency,
+    /// 按目标端口（被访问的本机端口）分别统计
+    ports: Mutex<BTreeMap<u16, PortStats>>,
+}
+
+/// 单个目标端口的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortStats {
+    /// 捕获到的 UDP 数据包数
+    pub packets: u64,
+    /// 解析到的 SIP REGISTER/INVITE 请求数（含重传）
+    pub sip_requests: u64,
+    /// 畸形 SIP 报文数
+    pub malformed: u64,
+    /// 非 SIP 的二进制数据包数（STUN、RTP、垃圾数据）
+    #[serde(default)]
+    pub noise: u64,
+    /// 检测次数
+    pub detections: u64,
+    /// 成功封禁次数
+    pub bans: u64,
+}
+
+/// 统计快照（用于序列化输出）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    pub packets: u64,
+    pub sip_requests: u64,
+    pub retransmissions: u64,
+    pub malformed: u64,
+    pub detections: u64,
+    pub bans: u64,
+    pub unbans: u64,
+    pub capture_drops: u64,
+    #[serde(default)]
+    pub enforcement_drops: u64,
+    #[serde(default)]
+    pub enforcement_coalesced: u64,
+    #[serde(default)]
+    pub enforcement_pending: u64,
+    #[serde(default)]
+    pub decision_cache_hits: u64,
+    #[serde(default)]
+    pub alerts: u64,
+    #[serde(default)]
+    pub trusted_packets: u64,
+    #[serde(default)]
+    pub ineffective_bans: u64,
+    #[serde(default)]
+    pub firewall_failures: u64,
+    #[serde(default)]
+    pub firewall_retries: u64,
+    #[serde(default)]
+    pub firewall_degraded: u64,
+    #[serde(default)]
+    pub banned_hits: u64,
+    #[serde(default)]
+    pub noise_stun: u64,
+    #[serde(default)]
+    pub noise_rtp: u64,
+    #[serde(default)]
+    pub noise_binary: u64,
+    #[serde(default)]
+    pub source_entries: u64,
+    #[serde(default)]
+    pub sources_evicted: u64,
+    #[serde(default)]
+    pub transaction_entries: u64,
+    #[serde(default)]
+    pub transactions_untracked: u64,
+    #[serde(default)]
+    pub event_queue_pending: u64,
+    #[serde(default)]
+    pub events_dropped: u64,
+    #[serde(default)]
+    pub resident_memory: u64,
+    /// 封禁生效延迟直方图
+    #[serde(default)]
+    pub ban_latency: BanLatencySnapshot,
+    /// 按目标端口的统计
+    #[serde(default)]
+    pub ports: BTreeMap<u16, PortStats>,
+}
+
+impl Stats {
+    /// 计数器加一
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 设置计数器的当前值（用于来自外部的累计值）
+    pub fn set(counter: &AtomicU64, value: u64) {
+        counter.store(value, Ordering::Relaxed);
+    }
+
+    /// 某类非 SIP 数据的计数器
+    pub fn noise(&self, kind: NoiseKind) -> &AtomicU64 {
+        match kind {
+            NoiseKind::Stun => &self.noise_stun,
+            NoiseKind::Rtp => &self.noise_rtp,
+            NoiseKind::Binary => &self.noise_binary,
+        }
+    }
+
+    /// 更新某个目标端口的统计（超过 [`MAX_PORTS`] 个端口后不再统计新端口）
+    pub fn port(&self, port: u16, update: impl FnOnce(&mut PortStats)) {
+        let mut ports = self.ports.lock().unwrap();
+        if ports.len() >= MAX_PORTS && !ports.contains_key(&port) {
+            return;
+        }
+        update(ports.entry(port).or_default());
+    }
+
+    /// 各目标端口的统计
+    pub fn ports(&self) -> BTreeMap<u16, PortStats> {
+        self.ports.lock().unwrap().clone()
+    }
+
+    /// 获取当前统计快照
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            packets: self.packets.load(Ordering::Relaxed),
+            sip_requests: self.sip_requests.load(Ordering::Relaxed),
+            retransmissions: self.retransmissions.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            detections: self.detections.load(Ordering::Relaxed),
+            bans: self.bans.load(Ordering::Relaxed),
+            unbans: self.unbans.load(Ordering::Relaxed),
+            capture_drops: self.capture_drops.load(Ordering::Relaxed),
+            enforcement_drops: self.enforcement_drops.load(Ordering::Relaxed),
+            enforcement_coalesced: self.enforcement_coalesced.load(Ordering::Relaxed),
+            enforcement_pending: self.enforcement_pending.load(Ordering::Relaxed),
+            decision_cache_hits: self.decision_cache_hits.load(Ordering::Relaxed),
+            alerts: self.alerts.load(Ordering::Relaxed),
+            trusted_packets: self.trusted_packets.load(Ordering::Relaxed),
+            ineffective_bans: self.ineffective_bans.load(Ordering::Relaxed),
+            firewall_failures: self.firewall_failures.load(Ordering::Relaxed),
+            firewall_retries: self.firewall_retries.load(Ordering::Relaxed),
+            firewall_degraded: self.firewall_degraded.load(Ordering::Relaxed),
+            banned_hits: self.banned_hits.load(Ordering::Relaxed),
+            noise_stun: self.noise_stun.load(Ordering::Relaxed),
+            noise_rtp: self.noise_rtp.load(Ordering::Relaxed),
+            noise_binary: self.noise_binary.load(Ordering::Relaxed),
+            source_entries: self.source_entries.load(Ordering::Relaxed),
+            sources_evicted: self.sources_evicted.load(Ordering::Relaxed),
+            transaction_entries: self.transaction_entries.load(Ordering::Relaxed),
+            transactions_untracked: self.transactions_untracked.load(Ordering::Relaxed),
+            event_queue_pending: self.event_queue_pending.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            resident_memory: self.resident_memory.load(Ordering::Relaxed),
+            ban_latency: self.ban_latency.snapshot(),
+            ports: self.ports(),
+        }
+    }
+}
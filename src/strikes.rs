@@ -0,0 +1,133 @@
+use crate::sources::SourceTable;
+use crate::state_file::StrikeRecord;
+use log::{debug, warn};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 惩罚计数器（按来源 IP 累计，随时间指数衰减）
+///
+/// 各检测器按权重累加惩罚分，分数达到阈值时应当封禁该来源。
+/// 分数按半衰期衰减，偶发的异常不会无限累积。分数保存在共享的 [`SourceTable`] 中。
+pub struct StrikeTracker {
+    sources: Arc<SourceTable>,
+    threshold: f64,
+    half_life: Duration,
+}
+
+impl StrikeTracker {
+    pub fn new(threshold: f64, half_life: Duration) -> Self {
+        Self {
+            sources: Arc::new(SourceTable::new()),
+            threshold,
+            half_life,
+        }
+    }
+
+    /// 从环境变量创建（UABLOCK_STRIKE_THRESHOLD / UABLOCK_STRIKE_HALF_LIFE，单位秒）
+    pub fn from_env() -> Self {
+        let threshold = std::env::var("UABLOCK_STRIKE_THRESHOLD")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5.0);
+        let half_life = std::env::var("UABLOCK_STRIKE_HALF_LIFE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300);
+        Self::new(threshold, Duration::from_secs(half_life))
+    }
+
+    /// 把惩罚分保存到与其他检测器共享的来源状态表
+    pub fn with_sources(mut self, sources: Arc<SourceTable>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// 保存惩罚分的来源状态表
+    pub fn sources(&self) -> &Arc<SourceTable> {
+        &self.sources
+    }
+
+    /// 为来源累加惩罚分，返回 true 表示达到封禁阈值
+    ///
+    /// 达到阈值后该来源的分数被清零，避免重复触发
+    pub fn add(&mut self, ip: IpAddr, weight: f64, reason: &str) -> bool {
+        let now = Instant::now();
+        let (half_life, threshold) = (self.half_life, self.threshold);
+        self.sources.update(ip, |state| {
+            let score = state.strikes.map_or(0.0, |(score, last_update)| {
+                decayed(score, now.duration_since(last_update), half_life)
+            }) + weight;
+            debug!(
+                "IP {} 累加惩罚分 {:.1}（{}），当前 {:.2} / {:.1}",
+                ip, weight, reason, score, threshold
+            );
+            if score >= threshold {
+                state.strikes = None;
+                return true;
+            }
+            state.strikes = Some((score, now));
+            false
+        })
+    }
+
+    /// 获取来源当前（已衰减的）分数
+    pub fn score(&self, ip: &IpAddr) -> f64 {
+        self.sources
+            .get(ip)
+            .and_then(|state| state.strikes)
+            .map(|(score, last_update)| decayed(score, last_update.elapsed(), self.half_life))
+            .unwrap_or(0.0)
+    }
+
+    /// 导出当前惩罚分，用于持久化
+    pub fn snapshot(&self) -> Vec<StrikeRecord> {
+        self.sources.collect(|ip, state| {
+            state.strikes.map(|(score, last_update)| StrikeRecord {
+                ip: *ip,
+                score,
+                age_secs: last_update.elapsed().as_secs_f64(),
+            })
+        })
+    }
+
+    /// 恢复惩罚分，并补算停机期间（elapsed_secs）的衰减
+    pub fn restore(&mut self, records: &[StrikeRecord], elapsed_secs: f64) {
+        let now = Instant::now();
+        for record in records {
+            let Ok(age) = Duration::try_from_secs_f64((record.age_secs + elapsed_secs).max(0.0))
+            else {
+                warn!(
+                    "忽略无效的惩罚分记录: {}（age_secs={}）",
+                    record.ip, record.age_secs
+                );
+                continue;
+            };
+            let score = decayed(record.score, age, self.half_life);
+            if score >= 0.01 {
+                self.sources
+                    .update(record.ip, |state| state.strikes = Some((score, now)));
+            }
+        }
+    }
+
+    /// 清理已衰减到可忽略的条目
+    pub fn cleanup(&mut self) {
+        let half_life = self.half_life;
+        self.sources.retain(|_, state| {
+            if state.strikes.is_some_and(|(score, last_update)| {
+                decayed(score, last_update.elapsed(), half_life) < 0.01
+            }) {
+                state.strikes = None;
+            }
+        });
+    }
+}
+
+/// 按半衰期计算衰减后的分数
+fn decayed(score: f64, elapsed: Duration, half_life: Duration) -> f64 {
+    if half_life.is_zero() {
+        return score;
+    }
+    score * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+}
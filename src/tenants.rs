@@ -0,0 +1,177 @@
+use crate::notify::NotifyTarget;
+use crate::whitelist::{ListMode, MatchMode, Whitelist};
+use log::info;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// 配置文件格式
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantsFile {
+    #[serde(default)]
+    tenant: Vec<TenantConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantConfig {
+    name: String,
+    /// `IP` 或 `IP:端口`（IPv6 带端口时写作 `[::1]:5060`）
+    destinations: Vec<String>,
+    whitelist: Vec<String>,
+    /// 白名单的匹配方式，省略时使用全局的 `UABLOCK_UA_MATCH`
+    #[serde(default)]
+    match_mode: Option<MatchMode>,
+    /// 接收该租户事件的通知目标（webhook 地址、`telegram:`、`mailto:`）
+    #[serde(default)]
+    notify: Vec<String>,
+}
+
+/// 租户匹配的目标地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Destination {
+    ip: IpAddr,
+    /// None 表示该 IP 上的所有端口
+    port: Option<u16>,
+}
+
+impl Destination {
+    fn parse(text: &str) -> Option<Self> {
+        if let Ok(addr) = text.parse::<SocketAddr>() {
+            return Some(Self {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            });
+        }
+        text.parse().ok().map(|ip| Self { ip, port: None })
+    }
+}
+
+/// 一个租户：按被访问的目标地址选出，拥有自己的白名单和通知目标
+pub struct Tenant {
+    pub name: String,
+    destinations: Vec<Destination>,
+    whitelist: Whitelist,
+    notify: Vec<NotifyTarget>,
+}
+
+impl Tenant {
+    /// UA 是否在该租户的白名单中
+    pub fn is_allowed(&self, user_agent: &str) -> bool {
+        self.whitelist.is_allowed(user_agent)
+    }
+
+    /// 租户白名单的名单模式
+    pub fn mode(&self) -> ListMode {
+        self.whitelist.mode()
+    }
+}
+
+/// 租户配置：同一抓包接口上托管多个客户的 PBX 时，按数据包的目标 IP/端口选择租户
+///
+/// 命中租户的请求使用该租户的白名单判定，产生的检测和封禁事件带上租户名称；
+/// 未命中任何租户的流量使用全局白名单。惩罚分、限速、规则等其他检测仍然按来源 IP 全局生效，
+/// 封禁也对所有租户生效（扫描器通常同时扫描所有地址）。
+pub struct Tenants {
+    tenants: Vec<Tenant>,
+}
+
+impl Tenants {
+    /// 从 UABLOCK_TENANTS 指定的 TOML 文件加载，未设置时返回 None
+    ///
+    /// 未设置 `match_mode` 的租户使用 `UABLOCK_UA_MATCH` 的匹配方式。
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("UABLOCK_TENANTS") {
+            Ok(path) if !path.is_empty() => {
+                Self::load(Path::new(&path), MatchMode::from_env()?).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn load(path: &Path, match_mode: MatchMode) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("无法读取租户配置 {}: {}", path.display(), e))?;
+        Self::parse_with(&content, match_mode)
+            .map_err(|e| format!("租户配置 {} 无效: {}", path.display(), e))
+    }
+
+    /// 解析租户配置，未设置 `match_mode` 的租户使用默认的匹配方式
+    pub fn parse(content: &str) -> Result<Self, String> {
+        Self::parse_with(content, MatchMode::default())
+    }
+
+    /// 解析租户配置，未设置 `match_mode` 的租户使用 `match_mode`
+    pub fn parse_with(content: &str, match_mode: MatchMode) -> Result<Self, String> {
+        let file: TenantsFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let mut tenants = Vec::with_capacity(file.tenant.len());
+        for config in file.tenant {
+            let destinations = config
+                .destinations
+                .iter()
+                .map(|text| {
+                    Destination::parse(text)
+                        .ok_or_else(|| format!("租户 {} 的目标地址无效: {}", config.name, text))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if destinations.is_empty() {
+                return Err(format!("租户 {} 没有配置目标地址", config.name));
+            }
+            let notify = config
+                .notify
+                .iter()
+                .map(|spec| NotifyTarget::parse(spec))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("租户 {} 的通知目标无效: {}", config.name, e))?;
+            info!(
+                "【租户】{}：目标 {:?}，白名单 {:?}，通知 {} 个目标",
+                config.name,
+                config.destinations,
+                config.whitelist,
+                notify.len()
+            );
+            tenants.push(Tenant {
+                name: config.name,
+                destinations,
+                whitelist: Whitelist::new(config.whitelist)
+                    .with_match_mode(config.match_mode.unwrap_or(match_mode)),
+                notify,
+            });
+        }
+        Ok(Self { tenants })
+    }
+
+    /// 按目标地址查找租户：`IP:端口` 精确匹配优先于只写 IP 的配置
+    pub fn lookup(&self, ip: IpAddr, port: u16) -> Option<&Tenant> {
+        let exact = Destination {
+            ip,
+            port: Some(port),
+        };
+        let any_port = Destination { ip, port: None };
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.destinations.contains(&exact))
+            .or_else(|| {
+                self.tenants
+                    .iter()
+                    .find(|tenant| tenant.destinations.contains(&any_port))
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    /// 配置了通知目标的租户及其目标
+    pub fn notify_targets(&self) -> impl Iterator<Item = (&str, &[NotifyTarget])> {
+        self.tenants
+            .iter()
+            .filter(|tenant| !tenant.notify.is_empty())
+            .map(|tenant| (tenant.name.as_str(), tenant.notify.as_slice()))
+    }
+}
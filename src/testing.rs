@@ -0,0 +1,210 @@
+//! 用于测试的内存实现
+//!
+//! 不需要 root 权限和真实网卡即可驱动完整的检测、封禁、解封流程：
+//! [`MemorySource`] 提供构造好的数据包，[`MemoryFirewall`] 记录封禁状态。
+
+use crate::firewall::FirewallBackend;
+use crate::packet_capture::{CapturedPacket, PacketSource};
+use std::collections::{BTreeSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+
+/// 内存防火墙：克隆出的句柄共享同一份封禁表，交给 [`Enforcer`](crate::enforcement::Enforcer)
+/// 后仍可在测试中检查状态
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFirewall {
+    blocked: Arc<Mutex<BTreeSet<IpAddr>>>,
+    /// 设置后所有封禁/解封操作返回该错误
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl MemoryFirewall {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 当前封禁的 IP（有序）
+    pub fn blocked(&self) -> Vec<IpAddr> {
+        self.blocked.lock().unwrap().iter().copied().collect()
+    }
+
+    /// 模拟后端故障：之后的封禁/解封都返回 `error`，传入 None 恢复
+    pub fn fail_with(&self, error: Option<&str>) {
+        *self.failure.lock().unwrap() = error.map(str::to_string);
+    }
+
+    fn check_failure(&self) -> Result<(), String> {
+        match self.failure.lock().unwrap().as_ref() {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FirewallBackend for MemoryFirewall {
+    fn block_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.check_failure()?;
+        self.blocked.lock().unwrap().insert(*ip);
+        Ok(())
+    }
+
+    fn unblock_ip(&self, ip: &IpAddr) -> Result<(), String> {
+        self.check_failure()?;
+        self.blocked.lock().unwrap().remove(ip);
+        Ok(())
+    }
+
+    fn is_blocked(&self, ip: &IpAddr) -> bool {
+        self.blocked.lock().unwrap().contains(ip)
+    }
+
+    fn list_blocked(&self) -> Result<Vec<IpAddr>, String> {
+        Ok(self.blocked())
+    }
+}
+
+/// 内存数据包来源：按加入顺序返回数据包，取完后返回 `Ok(None)`（相当于抓包超时）
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    packets: VecDeque<CapturedPacket>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入一个数据包
+    pub fn push(&mut self, packet: CapturedPacket) -> &mut Self {
+        self.packets.push_back(packet);
+        self
+    }
+
+    /// 加入一个发往本机 5060 端口的 UDP 数据包
+    pub fn push_payload(&mut self, source_ip: IpAddr, payload: impl Into<Vec<u8>>) -> &mut Self {
+        self.push(udp_packet(source_ip, payload))
+    }
+
+    /// 剩余的数据包数
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+impl PacketSource for MemorySource {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+        Ok(self.packets.pop_front())
+    }
+}
+
+/// 构造一个发往本机 5060 端口的 UDP 数据包
+pub fn udp_packet(source_ip: IpAddr, payload: impl Into<Vec<u8>>) -> CapturedPacket {
+    CapturedPacket {
+        source_ip,
+        dest_ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+        source_port: 5060,
+        dest_port: 5060,
+        payload: payload.into(),
+        captured_at: None,
+        parsed: None,
+    }
+}
+
+/// 构造一个格式完整的 SIP 请求（包含全部必需头部）
+pub fn sip_request(method: &str, user_agent: &str, call_id: &str, cseq: u32) -> String {
+    format!(
+        "{method} sip:100@192.0.2.1 SIP/2.0\r\n\
+         Via: SIP/2.0/UDP 198.51.100.7:5060;branch=z9hG4bK-{call_id}-{cseq}\r\n\
+         From: <sip:100@192.0.2.1>;tag=1\r\n\
+         To: <sip:100@192.0.2.1>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: {cseq} {method}\r\n\
+         User-Agent: {user_agent}\r\n\
+         Content-Length: 0\r\n\
+         \r\n"
+    )
+}
+
+/// 构造一个完整的 IPv4/UDP 数据报（含 IP 头和 UDP 校验和），可经原始套接字发出
+pub fn ipv4_datagram(
+    source: Ipv4Addr,
+    dest: Ipv4Addr,
+    source_port: u16,
+    dest_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let total_len = 20 + udp_len;
+    let mut packet = Vec::with_capacity(usize::from(total_len));
+    // IP 头：版本 4、头长 5（20 字节）、TTL 64、协议 17（UDP），不分片
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&dest.octets());
+    let header_checksum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+    let udp_start = packet.len();
+    packet.extend_from_slice(&source_port.to_be_bytes());
+    packet.extend_from_slice(&dest_port.to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    // UDP 校验和覆盖伪头部（源/目标地址、协议号、UDP 长度）
+    let pseudo: u32 = [source.octets(), dest.octets()]
+        .iter()
+        .flat_map(|octets| octets.chunks(2))
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]])))
+        .sum::<u32>()
+        + 17
+        + u32::from(udp_len);
+    let udp_checksum = match checksum(&packet[udp_start..], pseudo) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[udp_start + 6..udp_start + 8].copy_from_slice(&udp_checksum.to_be_bytes());
+    packet
+}
+
+/// 互联网校验和（RFC 1071），`initial` 为已累加的部分
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .fold(initial, |sum, word| sum + word);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// 构造 HEPv3 数据包（IPv4、UDP、SIP）
+pub fn hep_packet(source: &str, dest_port: u16, payload: &str) -> Vec<u8> {
+    let chunk = |kind: u16, value: &[u8]| {
+        let mut bytes = vec![0, 0];
+        bytes.extend(kind.to_be_bytes());
+        bytes.extend((value.len() as u16 + 6).to_be_bytes());
+        bytes.extend(value);
+        bytes
+    };
+    let source: std::net::Ipv4Addr = source.parse().unwrap();
+    let mut body = Vec::new();
+    body.extend(chunk(0x0001, &[2]));
+    body.extend(chunk(0x0002, &[17]));
+    body.extend(chunk(0x0003, &source.octets()));
+    body.extend(chunk(0x0004, &[192, 0, 2, 1]));
+    body.extend(chunk(0x0007, &5062u16.to_be_bytes()));
+    body.extend(chunk(0x0008, &dest_port.to_be_bytes()));
+    body.extend(chunk(0x000b, &[1]));
+    body.extend(chunk(0x000f, payload.as_bytes()));
+
+    let mut packet = b"HEP3".to_vec();
+    packet.extend((body.len() as u16 + 6).to_be_bytes());
+    packet.extend(body);
+    packet
+}
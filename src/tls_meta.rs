@@ -0,0 +1,419 @@
+use crate::ingest::ExternalSignal;
+#[cfg(feature = "pcap")]
+use crate::ingest::SignalSender;
+use crate::packet_capture::{decode_ip_frame, LinkLayer};
+use log::debug;
+#[cfg(feature = "pcap")]
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// SYN 计数窗口
+const SYN_WINDOW: Duration = Duration::from_secs(60);
+
+/// 清理过期计数的间隔
+#[cfg(feature = "pcap")]
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// TCP 头中需要的字段
+#[derive(Debug, Clone)]
+pub struct TcpSegment {
+    pub source_ip: IpAddr,
+    pub dest_port: u16,
+    /// 只有 SYN 置位（新连接的第一个包）
+    pub syn: bool,
+    pub payload: Vec<u8>,
+}
+
+/// 从链路层帧（或裸 IPv4/IPv6 数据包）中解析出 TCP 报文段，不是 TCP 时返回 None
+pub fn decode_tcp(data: &[u8]) -> Option<TcpSegment> {
+    decode_tcp_frame(data, LinkLayer::Auto)
+}
+
+/// 同 [`decode_tcp`]，按指定的链路层头格式解析
+pub fn decode_tcp_frame(data: &[u8], link: LinkLayer) -> Option<TcpSegment> {
+    let ip = decode_ip_frame(data, link)?;
+    if ip.protocol != 6 {
+        return None;
+    }
+    let tcp = data.get(ip.transport_start..)?;
+    if tcp.len() < 20 {
+        return None;
+    }
+    // 数据偏移在字节 12 的高 4 位，单位是 4 字节
+    let header_len = usize::from(tcp[12] >> 4) * 4;
+    if header_len < 20 {
+        return None;
+    }
+    let flags = tcp[13];
+    Some(TcpSegment {
+        source_ip: ip.source,
+        dest_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+        // SYN（0x02）置位且 ACK（0x10）未置位
+        syn: flags & 0x12 == 0x02,
+        payload: tcp.get(header_len..).unwrap_or_default().to_vec(),
+    })
+}
+
+/// TLS 扩展中的 GREASE 值（RFC 8701），计算 JA3 时忽略
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// 按大端读取的游标，越界时返回 None
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// 长度前缀为 1 或 2 字节的数据块
+    fn block(&mut self, prefix: usize) -> Option<Reader<'a>> {
+        let len = match prefix {
+            1 => usize::from(self.u8()?),
+            _ => usize::from(self.u16()?),
+        };
+        self.take(len).map(|data| Reader { data })
+    }
+
+    fn u16_list(mut self) -> Vec<u16> {
+        let mut values = Vec::new();
+        while let Some(value) = self.u16() {
+            if !is_grease(value) {
+                values.push(value);
+            }
+        }
+        values
+    }
+}
+
+/// 由 ClientHello 计算 JA3 指纹，返回 (JA3 字符串, MD5)
+///
+/// JA3 字符串为 `版本,密码套件,扩展,椭圆曲线,点格式`，各列表以 `-` 连接，忽略 GREASE 值。
+/// `payload` 应以 TLS 记录头开始；不是 ClientHello 或被截断时返回 None
+/// （ClientHello 跨多个 TCP 报文段时不做重组）。
+pub fn ja3(payload: &[u8]) -> Option<(String, String)> {
+    let mut record = Reader { data: payload };
+    // 记录类型 22（握手）
+    if record.u8()? != 0x16 {
+        return None;
+    }
+    record.u16()?;
+    let mut handshake = record.block(2)?;
+    // 握手类型 1（ClientHello）
+    if handshake.u8()? != 0x01 {
+        return None;
+    }
+    handshake.take(3)?;
+    let version = handshake.u16()?;
+    handshake.take(32)?;
+    handshake.block(1)?;
+    let ciphers = handshake.block(2)?.u16_list();
+    handshake.block(1)?;
+
+    let mut extensions = Vec::new();
+    let mut groups = Vec::new();
+    let mut point_formats = Vec::new();
+    if let Some(mut list) = handshake.block(2) {
+        while let Some(kind) = list.u16() {
+            let mut data = list.block(2)?;
+            if is_grease(kind) {
+                continue;
+            }
+            extensions.push(kind);
+            match kind {
+                // supported_groups
+                10 => groups = data.block(2)?.u16_list(),
+                // ec_point_formats
+                11 => point_formats = data.block(1)?.data.iter().map(|&f| u16::from(f)).collect(),
+                _ => {}
+            }
+        }
+    }
+
+    let join = |values: &[u16]| {
+        values
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join("-")
+    };
+    let text = format!(
+        "{},{},{},{},{}",
+        version,
+        join(&ciphers),
+        join(&extensions),
+        join(&groups),
+        join(&point_formats)
+    );
+    let hash = format!("{:x}", md5::compute(text.as_bytes()));
+    Some((text, hash))
+}
+
+/// SIPS（TLS）连接的元数据检测
+///
+/// 无法解密 SIPS，但扫描器的连接行为仍然可见：
+///
+/// - 每个来源每分钟的新连接（SYN）数超过上限时，上报 `TLS_SYN_RATE` 信号
+/// - ClientHello 的 JA3 指纹在黑名单中时，上报 `TLS_FINGERPRINT` 信号
+///
+/// 信号计入来源的惩罚分，与抓包检测和 PBX 上报合并处置。
+pub struct TlsMonitor {
+    syn_limit: u32,
+    weight: f64,
+    blocklist: HashSet<String>,
+    /// 来源当前窗口的 (开始时间, SYN 数)
+    syns: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl TlsMonitor {
+    pub fn new(syn_limit: u32, weight: f64, blocklist: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            syn_limit,
+            weight,
+            blocklist: blocklist
+                .into_iter()
+                .map(|hash| hash.trim().to_lowercase())
+                .collect(),
+            syns: HashMap::new(),
+        }
+    }
+
+    /// 处理一个 TCP 报文段，需要计入惩罚分时返回信号
+    pub fn observe(&mut self, segment: &TcpSegment) -> Option<ExternalSignal> {
+        if segment.syn {
+            let now = Instant::now();
+            let (since, count) = self.syns.entry(segment.source_ip).or_insert((now, 0));
+            if now.duration_since(*since) >= SYN_WINDOW {
+                *since = now;
+                *count = 0;
+            }
+            *count += 1;
+            // 每个窗口只在刚超过上限时上报一次
+            if *count == self.syn_limit + 1 {
+                return Some(self.signal(segment.source_ip, "TLS_SYN_RATE"));
+            }
+            return None;
+        }
+
+        let (text, hash) = ja3(&segment.payload)?;
+        debug!(
+            "【TLS】IP: {}, 端口: {}, JA3: {} ({})",
+            segment.source_ip, segment.dest_port, hash, text
+        );
+        self.blocklist
+            .contains(&hash)
+            .then(|| self.signal(segment.source_ip, "TLS_FINGERPRINT"))
+    }
+
+    /// 清理过期的 SYN 计数窗口
+    pub fn cleanup(&mut self) {
+        self.syns
+            .retain(|_, (since, _)| since.elapsed() < SYN_WINDOW);
+    }
+
+    fn signal(&self, source_ip: IpAddr, reason: &str) -> ExternalSignal {
+        ExternalSignal {
+            source_ip,
+            user_agent: String::new(),
+            reason: reason.to_string(),
+            weight: self.weight,
+            origin: "tls".to_string(),
+        }
+    }
+}
+
+/// 在抓包接口上观察 SIPS 端口的 TCP 连接
+#[cfg(feature = "pcap")]
+pub struct TlsCapture {
+    interface: String,
+    port: u16,
+    monitor: TlsMonitor,
+    sender: SignalSender,
+}
+
+#[cfg(feature = "pcap")]
+impl TlsCapture {
+    /// 从环境变量创建，未设置 `UABLOCK_TLS_PORT` 时返回 None
+    ///
+    /// - `UABLOCK_TLS_PORT`：SIPS 端口，例如 `5061`
+    /// - `UABLOCK_TLS_SYN_LIMIT`：每个来源每分钟允许的新连接数（默认 30）
+    /// - `UABLOCK_TLS_JA3_BLOCKLIST`：扫描器的 JA3 指纹（MD5），逗号分隔
+    /// - `UABLOCK_TLS_WEIGHT`：每次信号计入的惩罚分（默认 1.0）
+    pub fn from_env(interface: &str, sender: SignalSender) -> Option<Self> {
+        let port = std::env::var("UABLOCK_TLS_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())?;
+        let syn_limit = std::env::var("UABLOCK_TLS_SYN_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let weight = std::env::var("UABLOCK_TLS_WEIGHT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1.0);
+        let blocklist: Vec<String> = std::env::var("UABLOCK_TLS_JA3_BLOCKLIST")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|hash| !hash.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+        Some(Self {
+            interface: interface.to_string(),
+            port,
+            monitor: TlsMonitor::new(syn_limit, weight, blocklist),
+            sender,
+        })
+    }
+
+    /// 在后台线程中抓包
+    pub fn start(self) -> Result<(), String> {
+        let mut capture = pcap::Capture::from_device(self.interface.as_str())
+            .map_err(|e| format!("无法打开网络接口 {}: {}", self.interface, e))?
+            .promisc(self.interface != "any")
+            .snaplen(2048)
+            .timeout(1000)
+            .open()
+            .map_err(|e| format!("无法开始抓包: {}", e))?;
+        let link = LinkLayer::of(&capture);
+        // 只需要新连接的 SYN 和携带数据的报文段（ClientHello 在其中）
+        let filter = format!(
+            "tcp and dst port {} and (tcp[tcpflags] & tcp-syn != 0 or tcp[tcpflags] & tcp-push != 0)",
+            self.port
+        );
+        capture
+            .filter(&filter, true)
+            .map_err(|e| format!("设置过滤器失败: {}", e))?;
+        info!(
+            "【TLS】观察 {} 上目标端口 {} 的连接元数据",
+            self.interface, self.port
+        );
+
+        let TlsCapture {
+            mut monitor,
+            sender,
+            ..
+        } = self;
+        std::thread::Builder::new()
+            .name("tls-capture".to_string())
+            .spawn(move || {
+                let mut last_cleanup = Instant::now();
+                loop {
+                    if last_cleanup.elapsed() >= CLEANUP_INTERVAL {
+                        monitor.cleanup();
+                        last_cleanup = Instant::now();
+                    }
+                    let segment = match capture.next_packet() {
+                        Ok(packet) => decode_tcp_frame(packet.data, link),
+                        Err(pcap::Error::TimeoutExpired) => None,
+                        Err(e) => {
+                            warn!("【TLS】抓包错误: {}", e);
+                            std::thread::sleep(Duration::from_secs(1));
+                            None
+                        }
+                    };
+                    let Some(signal) = segment.and_then(|segment| monitor.observe(&segment)) else {
+                        continue;
+                    };
+                    if sender.send(signal).is_err() {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| format!("启动 TLS 抓包线程失败: {}", e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    /// 裸 IPv4 + TCP 报文段（无选项）
+    fn tcp_segment(source: &str, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let IpAddr::V4(source) = ip(source) else {
+            panic!("IPv4 only");
+        };
+        let mut data = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0];
+        data.extend(source.octets());
+        data.extend([203, 0, 113, 1]);
+        data.extend(40000u16.to_be_bytes());
+        data.extend(5061u16.to_be_bytes());
+        data.extend([0; 8]);
+        data.extend([5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        data.extend(payload);
+        data
+    }
+
+    /// 带 2 字节长度前缀的数据块
+    fn with_len(body: &[u8]) -> Vec<u8> {
+        let mut data = (body.len() as u16).to_be_bytes().to_vec();
+        data.extend(body);
+        data
+    }
+
+    fn client_hello() -> Vec<u8> {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend([0; 32]);
+        hello.push(0);
+        // 密码套件，含一个 GREASE 值
+        hello.extend(with_len(&[0x0a, 0x0a, 0x13, 0x01, 0xc0, 0x2f]));
+        hello.extend([1, 0]);
+        let mut extensions = vec![0x0a, 0x0a, 0, 0, 0, 0, 0, 0];
+        extensions.extend([0, 10]);
+        extensions.extend(with_len(&with_len(&[0, 29, 0, 23])));
+        extensions.extend([0, 11, 0, 2, 1, 0]);
+        hello.extend(with_len(&extensions));
+
+        let mut handshake = vec![1, 0];
+        handshake.extend(with_len(&hello));
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend(with_len(&handshake));
+        record
+    }
+
+    #[test]
+    fn tls_metadata_scores_connection_floods_and_known_fingerprints() {
+        let hello = decode_tcp(&tcp_segment(SCANNER, 0x18, &client_hello())).unwrap();
+        assert_eq!(hello.source_ip, ip(SCANNER));
+        assert_eq!(hello.dest_port, 5061);
+        assert!(!hello.syn);
+        let (text, hash) = ja3(&hello.payload).unwrap();
+        assert_eq!(text, "771,4865-49199,0-10-11,29-23,0");
+        assert_eq!(ja3(b"INVITE sip:100@example.com SIP/2.0\r\n"), None);
+
+        let mut monitor = TlsMonitor::new(2, 1.0, vec![hash.to_uppercase()]);
+        let syn = decode_tcp(&tcp_segment(SCANNER, 0x02, &[])).unwrap();
+        assert!(syn.syn);
+        let mut reasons: Vec<String> = (0..4)
+            .filter_map(|_| monitor.observe(&syn))
+            .map(|signal| signal.reason)
+            .collect();
+        reasons.extend(monitor.observe(&hello).map(|signal| signal.reason));
+        // 超过上限只上报一次
+        assert_eq!(reasons, vec!["TLS_SYN_RATE", "TLS_FINGERPRINT"]);
+    }
+}
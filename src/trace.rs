@@ -0,0 +1,315 @@
+use crate::detection::Detection;
+use crate::ingest::ExternalSignal;
+use crate::packet_capture::CapturedPacket;
+use crate::pipeline::{PacketOutcome, Pipeline};
+use crate::policy::Verdict;
+use crate::state_file::unix_now;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// 判定轨迹中的一个输入
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "input", rename_all = "snake_case")]
+pub enum TraceInput {
+    /// 捕获到的数据包；负载是合法 UTF-8 时记录在 `payload`，否则以十六进制记录在 `payload_hex`
+    Packet {
+        source_ip: IpAddr,
+        dest_ip: IpAddr,
+        source_port: u16,
+        dest_port: u16,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        payload: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload_hex: Option<String>,
+    },
+    /// 外部系统上报的信号
+    Signal {
+        source_ip: IpAddr,
+        #[serde(default)]
+        user_agent: String,
+        reason: String,
+        weight: f64,
+        origin: String,
+    },
+}
+
+impl TraceInput {
+    pub fn packet(packet: &CapturedPacket) -> Self {
+        let (payload, payload_hex) = match std::str::from_utf8(&packet.payload) {
+            Ok(text) => (text.to_string(), None),
+            Err(_) => (
+                String::new(),
+                Some(
+                    packet
+                        .payload
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect(),
+                ),
+            ),
+        };
+        Self::Packet {
+            source_ip: packet.source_ip,
+            dest_ip: packet.dest_ip,
+            source_port: packet.source_port,
+            dest_port: packet.dest_port,
+            payload,
+            payload_hex,
+        }
+    }
+
+    pub fn signal(signal: &ExternalSignal) -> Self {
+        Self::Signal {
+            source_ip: signal.source_ip,
+            user_agent: signal.user_agent.clone(),
+            reason: signal.reason.clone(),
+            weight: signal.weight,
+            origin: signal.origin.clone(),
+        }
+    }
+
+    pub fn source_ip(&self) -> IpAddr {
+        match self {
+            Self::Packet { source_ip, .. } | Self::Signal { source_ip, .. } => *source_ip,
+        }
+    }
+}
+
+/// 一个输入的判定结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceOutcome {
+    /// `ignored`、`malformed`、`noise`（`reason` 为分类代码）、`retransmission`、`cached`、`allow`、`hold`、`probation`、
+    /// `unverified`、`detect`，外部信号未触发检测时为 `signal`
+    pub verdict: String,
+    /// 检测的原因代码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// 命中的检测规则、插件或脚本说明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// 判定后来源的惩罚分
+    #[serde(default)]
+    pub score: f64,
+}
+
+impl TraceOutcome {
+    fn new(verdict: &str, detection: Option<&Detection>, score: f64) -> Self {
+        Self {
+            verdict: verdict.to_string(),
+            reason: detection.map(|d| d.reason.clone()),
+            rule: detection.and_then(|d| d.rule.clone()),
+            score,
+        }
+    }
+
+    pub fn packet(outcome: &PacketOutcome, score: f64) -> Self {
+        match outcome {
+            PacketOutcome::Ignored => Self::new("ignored", None, score),
+            PacketOutcome::Malformed => Self::new("malformed", None, score),
+            PacketOutcome::Noise(kind) => Self {
+                reason: Some(kind.as_str().to_string()),
+                ..Self::new("noise", None, score)
+            },
+            PacketOutcome::Retransmission => Self::new("retransmission", None, score),
+            PacketOutcome::Cached => Self::new("cached", None, score),
+            PacketOutcome::Request { verdict, .. } => match verdict {
+                Verdict::Allow => Self::new("allow", None, score),
+                Verdict::Hold => Self::new("hold", None, score),
+                Verdict::Probation => Self::new("probation", None, score),
+                Verdict::Unverified => Self::new("unverified", None, score),
+                Verdict::Detect(detection) => Self::new("detect", Some(detection), score),
+            },
+        }
+    }
+
+    pub fn signal(detection: Option<&Detection>, score: f64) -> Self {
+        match detection {
+            Some(detection) => Self::new("detect", Some(detection), score),
+            None => Self::new("signal", None, score),
+        }
+    }
+
+    /// 判定是否相同（惩罚分随时间衰减，不参与比较）
+    pub fn same_decision(&self, other: &Self) -> bool {
+        self.verdict == other.verdict && self.reason == other.reason && self.rule == other.rule
+    }
+}
+
+/// 判定轨迹的一行（JSON Lines）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// 记录时间（Unix 秒）
+    pub at: u64,
+    #[serde(flatten)]
+    pub input: TraceInput,
+    pub outcome: TraceOutcome,
+}
+
+/// 判定轨迹记录器：把每个输入及其判定结果追加写入 JSON Lines 文件
+///
+/// 用于事后复查误封：`uablock replay-trace` 用当前配置重新执行轨迹中的输入，
+/// 逐条对比判定结果。可以只记录指定来源，避免扫描洪泛时轨迹过大。
+pub struct TraceRecorder {
+    writer: LineWriter<File>,
+    sources: Option<HashSet<IpAddr>>,
+}
+
+impl TraceRecorder {
+    /// 以追加方式打开轨迹文件
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("无法打开判定轨迹文件 {}: {}", path.display(), e))?;
+        Ok(Self {
+            writer: LineWriter::new(file),
+            sources: None,
+        })
+    }
+
+    /// 从环境变量创建，未设置 `UABLOCK_TRACE_FILE` 时返回 None
+    ///
+    /// - `UABLOCK_TRACE_FILE`：轨迹文件路径
+    /// - `UABLOCK_TRACE_SOURCES`：只记录这些来源 IP（逗号分隔，默认全部记录）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let path = match std::env::var("UABLOCK_TRACE_FILE") {
+            Ok(path) if !path.is_empty() => path,
+            _ => return Ok(None),
+        };
+        let mut recorder = Self::create(Path::new(&path))?;
+        if let Ok(list) = std::env::var("UABLOCK_TRACE_SOURCES") {
+            let sources = list
+                .split(',')
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty())
+                .map(|ip| {
+                    ip.parse()
+                        .map_err(|_| format!("UABLOCK_TRACE_SOURCES 中的 IP 无效: {}", ip))
+                })
+                .collect::<Result<HashSet<IpAddr>, String>>()?;
+            if !sources.is_empty() {
+                recorder = recorder.with_sources(sources);
+            }
+        }
+        info!("判定轨迹记录到 {}", path);
+        Ok(Some(recorder))
+    }
+
+    /// 只记录这些来源
+    pub fn with_sources(mut self, sources: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.sources = Some(sources.into_iter().collect());
+        self
+    }
+
+    /// 是否记录该来源的输入
+    pub fn wants(&self, ip: &IpAddr) -> bool {
+        self.sources
+            .as_ref()
+            .is_none_or(|sources| sources.contains(ip))
+    }
+
+    /// 追加一行；写入失败只记录警告，不影响检测
+    pub fn record(&mut self, input: TraceInput, outcome: TraceOutcome) {
+        if !self.wants(&input.source_ip()) {
+            return;
+        }
+        let entry = TraceEntry {
+            at: unix_now(),
+            input,
+            outcome,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(self.writer, "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("写入判定轨迹失败: {}", e);
+        }
+    }
+}
+
+/// 读取判定轨迹文件
+pub fn read_trace(path: &Path) -> Result<Vec<TraceEntry>, String> {
+    let file =
+        File::open(path).map_err(|e| format!("无法打开判定轨迹文件 {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| format!("{} 第 {} 行无效: {}", path.display(), index + 1, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// 在流水线上按顺序重新执行轨迹中的输入，返回每个输入的新判定结果
+///
+/// 输入之间没有原来的时间间隔，按时间窗口计数的检测器（UA 限速、惩罚分衰减）看到的是
+/// 压缩后的时间线。原来命中判定缓存的数据包没有经过判定，不重新执行，沿用原结果。
+pub fn replay(pipeline: &mut Pipeline, entries: &[TraceEntry]) -> Vec<TraceOutcome> {
+    entries
+        .iter()
+        .map(|entry| match &entry.input {
+            _ if entry.outcome.verdict == "cached" => entry.outcome.clone(),
+            TraceInput::Packet {
+                source_ip,
+                dest_ip,
+                source_port,
+                dest_port,
+                payload,
+                payload_hex,
+            } => {
+                let payload = match payload_hex {
+                    Some(hex) => decode_hex(hex),
+                    None => payload.clone().into_bytes(),
+                };
+                let outcome = pipeline.process(&CapturedPacket {
+                    source_ip: *source_ip,
+                    dest_ip: *dest_ip,
+                    source_port: *source_port,
+                    dest_port: *dest_port,
+                    payload,
+                    captured_at: None,
+                    parsed: None,
+                });
+                TraceOutcome::packet(&outcome, pipeline.policy().score(source_ip))
+            }
+            TraceInput::Signal {
+                source_ip,
+                user_agent,
+                reason,
+                weight,
+                origin,
+            } => {
+                let detection = pipeline.process_signal(&ExternalSignal {
+                    source_ip: *source_ip,
+                    user_agent: user_agent.clone(),
+                    reason: reason.clone(),
+                    weight: *weight,
+                    origin: origin.clone(),
+                });
+                TraceOutcome::signal(detection.as_ref(), pipeline.policy().score(source_ip))
+            }
+        })
+        .collect()
+}
+
+/// 十六进制解码，忽略无效的字节
+fn decode_hex(hex: &str) -> Vec<u8> {
+    hex.as_bytes()
+        .chunks(2)
+        .filter_map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
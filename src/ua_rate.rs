@@ -0,0 +1,314 @@
+use crate::state_file::{UaDenyRecord, UaWindowRecord};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// 单个 UA 在当前窗口内的统计
+struct UaWindow {
+    started: Instant,
+    requests: u32,
+    sources: HashSet<IpAddr>,
+}
+
+/// 按 User-Agent 的全局速率限制
+///
+/// 分布式扫描会使用大量 IP 但相同的 UA，单 IP 计数无法发现。
+/// 这里跨所有来源统计每个 UA 的请求数，窗口内请求数和来源数都超过阈值时，
+/// 将该 UA 临时加入拒绝列表，期间无论是否在白名单中都会被封禁。
+pub struct UaRateLimiter {
+    window: Duration,
+    threshold: u32,
+    min_sources: usize,
+    deny_duration: Duration,
+    windows: HashMap<String, UaWindow>,
+    denied: HashMap<String, Instant>,
+}
+
+impl UaRateLimiter {
+    pub fn new(
+        window: Duration,
+        threshold: u32,
+        min_sources: usize,
+        deny_duration: Duration,
+    ) -> Self {
+        Self {
+            window,
+            threshold,
+            min_sources,
+            deny_duration,
+            windows: HashMap::new(),
+            denied: HashMap::new(),
+        }
+    }
+
+    /// 从环境变量创建，未设置 UABLOCK_UA_RATE_THRESHOLD 时返回 None（功能关闭）
+    ///
+    /// - UABLOCK_UA_RATE_THRESHOLD：窗口内请求数阈值
+    /// - UABLOCK_UA_RATE_WINDOW：统计窗口（秒，默认 60）
+    /// - UABLOCK_UA_RATE_MIN_SOURCES：窗口内最少来源 IP 数（默认 10）
+    /// - UABLOCK_UA_DENY_DURATION：拒绝持续时间（秒，默认 3600）
+    pub fn from_env() -> Option<Self> {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let threshold = env_u64("UABLOCK_UA_RATE_THRESHOLD", 0) as u32;
+        if threshold == 0 {
+            return None;
+        }
+        let limiter = Self::new(
+            Duration::from_secs(env_u64("UABLOCK_UA_RATE_WINDOW", 60)),
+            threshold,
+            env_u64("UABLOCK_UA_RATE_MIN_SOURCES", 10) as usize,
+            Duration::from_secs(env_u64("UABLOCK_UA_DENY_DURATION", 3600)),
+        );
+        info!(
+            "UA 全局速率限制已启用: {} 秒内超过 {} 次请求且来自至少 {} 个 IP 时拒绝 {} 秒",
+            limiter.window.as_secs(),
+            limiter.threshold,
+            limiter.min_sources,
+            limiter.deny_duration.as_secs()
+        );
+        Some(limiter)
+    }
+
+    /// 记录一次请求，返回 true 表示该 UA 刚刚被加入拒绝列表
+    pub fn observe(&mut self, user_agent: &str, source_ip: IpAddr) -> bool {
+        self.observe_at(user_agent, source_ip, Instant::now())
+    }
+
+    /// 同 [`observe`](Self::observe)，以 `now` 为当前时间
+    fn observe_at(&mut self, user_agent: &str, source_ip: IpAddr, now: Instant) -> bool {
+        let key = user_agent.to_lowercase();
+        if self.is_denied_at(&key, now) {
+            return false;
+        }
+
+        let window = self.windows.entry(key.clone()).or_insert_with(|| UaWindow {
+            started: now,
+            requests: 0,
+            sources: HashSet::new(),
+        });
+        if now.duration_since(window.started) >= self.window {
+            window.started = now;
+            window.requests = 0;
+            window.sources.clear();
+        }
+        window.requests += 1;
+        window.sources.insert(source_ip);
+
+        if window.requests > self.threshold && window.sources.len() >= self.min_sources {
+            warn!(
+                "【UA 限速】User-Agent: '{}' 在 {} 秒内来自 {} 个 IP 共 {} 次请求，加入拒绝列表 {} 秒",
+                user_agent,
+                self.window.as_secs(),
+                window.sources.len(),
+                window.requests,
+                self.deny_duration.as_secs()
+            );
+            self.windows.remove(&key);
+            self.denied.insert(key, now + self.deny_duration);
+            return true;
+        }
+        false
+    }
+
+    /// 检查 UA 当前是否在临时拒绝列表中
+    pub fn is_denied(&self, user_agent: &str) -> bool {
+        self.is_denied_at(user_agent, Instant::now())
+    }
+
+    fn is_denied_at(&self, user_agent: &str, now: Instant) -> bool {
+        self.denied
+            .get(&user_agent.to_lowercase())
+            .is_some_and(|expires| now < *expires)
+    }
+
+    /// 导出当前窗口和拒绝列表，用于持久化
+    pub fn snapshot(&self) -> (Vec<UaWindowRecord>, Vec<UaDenyRecord>) {
+        let now = Instant::now();
+        let windows = self
+            .windows
+            .iter()
+            .map(|(ua, w)| UaWindowRecord {
+                user_agent: ua.clone(),
+                requests: w.requests,
+                sources: w.sources.iter().copied().collect(),
+                age_secs: now.duration_since(w.started).as_secs_f64(),
+            })
+            .collect();
+        let denied = self
+            .denied
+            .iter()
+            .filter(|(_, expires)| **expires > now)
+            .map(|(ua, expires)| UaDenyRecord {
+                user_agent: ua.clone(),
+                remaining_secs: expires.duration_since(now).as_secs_f64(),
+            })
+            .collect();
+        (windows, denied)
+    }
+
+    /// 恢复窗口和拒绝列表，扣除停机时长（elapsed_secs）后已过期的记录被丢弃
+    pub fn restore(
+        &mut self,
+        windows: &[UaWindowRecord],
+        denied: &[UaDenyRecord],
+        elapsed_secs: f64,
+    ) {
+        let now = Instant::now();
+        for record in windows {
+            let Ok(age) = Duration::try_from_secs_f64((record.age_secs + elapsed_secs).max(0.0))
+            else {
+                warn!(
+                    "忽略无效的 UA 速率窗口记录: {}（age_secs={}）",
+                    record.user_agent, record.age_secs
+                );
+                continue;
+            };
+            if age >= self.window {
+                continue;
+            }
+            self.windows.insert(
+                record.user_agent.clone(),
+                UaWindow {
+                    started: now.checked_sub(age).unwrap_or(now),
+                    requests: record.requests,
+                    sources: record.sources.iter().copied().collect(),
+                },
+            );
+        }
+        for record in denied {
+            let remaining = record.remaining_secs - elapsed_secs;
+            if remaining <= 0.0 {
+                continue;
+            }
+            match Duration::try_from_secs_f64(remaining)
+                .ok()
+                .and_then(|remaining| now.checked_add(remaining))
+            {
+                Some(expires) => {
+                    self.denied.insert(record.user_agent.clone(), expires);
+                }
+                None => warn!(
+                    "忽略无效的 UA 拒绝记录: {}（remaining_secs={}）",
+                    record.user_agent, record.remaining_secs
+                ),
+            }
+        }
+    }
+
+    /// 清理过期的窗口和拒绝记录
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        let window = self.window;
+        self.windows
+            .retain(|_, w| now.duration_since(w.started) < window);
+        self.denied.retain(|ua, expires| {
+            let keep = now < *expires;
+            if !keep {
+                info!("【UA 限速】User-Agent: '{}' 拒绝期满，移出拒绝列表", ua);
+            }
+            keep
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const DENY: Duration = Duration::from_secs(600);
+
+    fn source(n: u8) -> IpAddr {
+        IpAddr::from([203, 0, 113, n])
+    }
+
+    #[test]
+    fn denies_once_both_request_and_source_thresholds_are_exceeded() {
+        let mut limiter = UaRateLimiter::new(WINDOW, 3, 2, DENY);
+        let start = Instant::now();
+        // 请求数等于阈值时不拒绝，超过阈值才拒绝
+        for n in 1..=3 {
+            assert!(!limiter.observe_at("sipvicious", source(n % 2), start));
+        }
+        assert!(limiter.observe_at("sipvicious", source(3), start));
+        assert!(limiter.is_denied_at("SIPVicious", start));
+        // 已在拒绝列表中时不再重复报告
+        assert!(!limiter.observe_at("sipvicious", source(4), start));
+
+        // 单个来源的大量请求不算分布式扫描
+        for _ in 0..10 {
+            assert!(!limiter.observe_at("friendly-scanner", source(1), start));
+        }
+    }
+
+    #[test]
+    fn window_resets_exactly_when_it_ends() {
+        let mut limiter = UaRateLimiter::new(WINDOW, 3, 1, DENY);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(!limiter.observe_at("sipcli", source(1), start));
+        }
+        // 窗口结束前的最后一刻仍计入同一窗口
+        let last = start + WINDOW - Duration::from_nanos(1);
+        assert!(limiter.observe_at("sipcli", source(1), last));
+
+        let mut limiter = UaRateLimiter::new(WINDOW, 3, 1, DENY);
+        for _ in 0..3 {
+            assert!(!limiter.observe_at("sipcli", source(1), start));
+        }
+        // 窗口结束时重新计数
+        for _ in 0..3 {
+            assert!(!limiter.observe_at("sipcli", source(1), start + WINDOW));
+        }
+        assert!(limiter.observe_at("sipcli", source(1), start + WINDOW));
+    }
+
+    #[test]
+    fn deny_lasts_exactly_the_deny_duration() {
+        let mut limiter = UaRateLimiter::new(WINDOW, 0, 1, DENY);
+        let start = Instant::now();
+        assert!(limiter.observe_at("sipcli", source(1), start));
+        assert!(limiter.is_denied_at("sipcli", start + DENY - Duration::from_nanos(1)));
+        assert!(!limiter.is_denied_at("sipcli", start + DENY));
+        // 拒绝期满后重新开始统计
+        assert!(limiter.observe_at("sipcli", source(1), start + DENY));
+    }
+
+    #[test]
+    fn restore_drops_windows_and_denials_that_expired_while_stopped() {
+        let mut limiter = UaRateLimiter::new(WINDOW, 3, 1, DENY);
+        let window = |user_agent: &str, age_secs: f64| UaWindowRecord {
+            user_agent: user_agent.to_string(),
+            requests: 3,
+            sources: vec![source(1)],
+            age_secs,
+        };
+        let denied = |user_agent: &str, remaining_secs: f64| UaDenyRecord {
+            user_agent: user_agent.to_string(),
+            remaining_secs,
+        };
+        limiter.restore(
+            &[window("fresh", 10.0), window("stale", 55.0)],
+            &[denied("kept", 100.0), denied("lapsed", 5.0)],
+            10.0,
+        );
+        let (windows, _) = limiter.snapshot();
+        assert_eq!(
+            windows
+                .iter()
+                .map(|w| w.user_agent.as_str())
+                .collect::<Vec<_>>(),
+            ["fresh"]
+        );
+        assert!(limiter.is_denied("kept"));
+        assert!(!limiter.is_denied("lapsed"));
+        // 恢复的窗口继续计数
+        assert!(limiter.observe("fresh", source(2)));
+    }
+}
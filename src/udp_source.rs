@@ -0,0 +1,211 @@
+use crate::packet_capture::{CapturedPacket, PacketSource};
+use log::debug;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// 单个数据报的最大长度
+const BUFFER_SIZE: usize = 65536;
+/// 默认允许转发 HEP 的地址（本机 PBX）
+const DEFAULT_RELAYS: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(Ipv6Addr::LOCALHOST),
+];
+
+/// HEP 块类型（vendor 0）
+const HEP_SRC_IPV4: u16 = 0x0003;
+const HEP_DST_IPV4: u16 = 0x0004;
+const HEP_SRC_IPV6: u16 = 0x0005;
+const HEP_DST_IPV6: u16 = 0x0006;
+const HEP_SRC_PORT: u16 = 0x0007;
+const HEP_DST_PORT: u16 = 0x0008;
+const HEP_PROTO_TYPE: u16 = 0x000b;
+const HEP_PAYLOAD: u16 = 0x000f;
+/// HEP 协议类型中的 SIP
+const HEP_PROTO_SIP: u8 = 1;
+
+/// 基于普通 UDP 套接字的数据包来源，不抓包、不需要 root
+///
+/// 两种用法可以同时存在：
+///
+/// - 监听模式：直接绑定 SIP 端口（后面没有 PBX），数据报的对端地址即为来源
+/// - 转发模式：PBX 把收到的请求以 HEPv3 复制过来（Kamailio `siptrace`、OpenSIPS `proto_hep`、
+///   Asterisk `res_hep` 等），来源取自 HEP 头。只接受来自 `relays` 的 HEP，
+///   否则任何人都能伪造来源让本工具封禁任意 IP
+pub struct UdpSource {
+    socket: UdpSocket,
+    port: u16,
+    relays: Vec<IpAddr>,
+    buffer: Vec<u8>,
+}
+
+impl UdpSource {
+    /// 从环境变量创建
+    ///
+    /// `UABLOCK_UDP_LISTEN` 为监听地址（默认 `0.0.0.0:<port>`），`UABLOCK_HEP_RELAYS` 为逗号分隔的
+    /// 可信转发地址（默认本机回环地址）。
+    pub fn from_env(port: u16) -> Result<Self, String> {
+        let listen = match std::env::var("UABLOCK_UDP_LISTEN") {
+            Ok(value) if !value.is_empty() => value
+                .parse()
+                .map_err(|_| format!("UABLOCK_UDP_LISTEN 无效: {}", value))?,
+            _ => SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+        };
+        let relays = match std::env::var("UABLOCK_HEP_RELAYS") {
+            Ok(value) if !value.is_empty() => value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    entry
+                        .parse()
+                        .map_err(|_| format!("UABLOCK_HEP_RELAYS 中的地址无效: {}", entry))
+                })
+                .collect::<Result<Vec<IpAddr>, String>>()?,
+            _ => DEFAULT_RELAYS.to_vec(),
+        };
+        Self::bind(listen, port, relays)
+    }
+
+    /// 绑定监听地址
+    /// port: SIP 端口，HEP 转发的消息只处理目标端口为该端口的请求
+    pub fn bind(listen: SocketAddr, port: u16, relays: Vec<IpAddr>) -> Result<Self, String> {
+        let socket =
+            UdpSocket::bind(listen).map_err(|e| format!("无法绑定 UDP {}: {}", listen, e))?;
+        // 与抓包一样 1 秒超时，使读取线程能定期检查通道是否关闭
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .map_err(|e| format!("设置读取超时失败: {}", e))?;
+        Ok(Self {
+            socket,
+            port,
+            relays,
+            buffer: vec![0; BUFFER_SIZE],
+        })
+    }
+
+    /// 实际绑定的地址（监听端口为 0 时由系统分配）
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket
+            .local_addr()
+            .map_err(|e| format!("获取监听地址失败: {}", e))
+    }
+}
+
+impl PacketSource for UdpSource {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+        let (len, peer) = match self.socket.recv_from(&mut self.buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                return match e.kind() {
+                    // 超时是正常的，继续等待
+                    io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted => Ok(None),
+                    _ => Err(format!("接收 UDP 数据失败: {}", e)),
+                };
+            }
+        };
+        let data = &self.buffer[..len];
+
+        if data.starts_with(b"HEP3") {
+            if !self.relays.contains(&peer.ip()) {
+                debug!("忽略来自非转发地址 {} 的 HEP 数据", peer);
+                return Ok(None);
+            }
+            return Ok(decode_hep(data).filter(|packet| packet.dest_port == self.port));
+        }
+
+        let local = self.socket.local_addr().map_err(|e| e.to_string())?;
+        Ok(Some(CapturedPacket {
+            source_ip: peer.ip(),
+            dest_ip: local.ip(),
+            source_port: peer.port(),
+            dest_port: local.port(),
+            payload: data.to_vec(),
+            captured_at: None,
+            parsed: None,
+        }))
+    }
+}
+
+/// 解析 HEPv3 数据包，取出原始来源和 SIP 负载
+///
+/// 输入来自网络：长度字段不一致、缺少地址/端口/负载或不是 SIP 时返回 None。
+pub fn decode_hep(data: &[u8]) -> Option<CapturedPacket> {
+    if data.len() < 6 || &data[..4] != b"HEP3" {
+        return None;
+    }
+    let total = usize::from(u16::from_be_bytes([data[4], data[5]]));
+    let data = data.get(..total)?;
+
+    let mut source_ip = None;
+    let mut dest_ip = None;
+    let mut source_port = None;
+    let mut dest_port = None;
+    let mut payload = None;
+    let mut offset = 6;
+    while offset + 6 <= data.len() {
+        let vendor = u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let kind = u16::from_be_bytes([data[offset + 2], data[offset + 3]]);
+        let len = usize::from(u16::from_be_bytes([data[offset + 4], data[offset + 5]]));
+        if len < 6 {
+            return None;
+        }
+        let value = data.get(offset + 6..offset + len)?;
+        offset += len;
+        // 厂商自定义块不影响解析
+        if vendor != 0 {
+            continue;
+        }
+        match (kind, value.len()) {
+            (HEP_SRC_IPV4, 4) | (HEP_SRC_IPV6, 16) => source_ip = hep_address(value),
+            (HEP_DST_IPV4, 4) | (HEP_DST_IPV6, 16) => dest_ip = hep_address(value),
+            (HEP_SRC_PORT, 2) => source_port = Some(u16::from_be_bytes([value[0], value[1]])),
+            (HEP_DST_PORT, 2) => dest_port = Some(u16::from_be_bytes([value[0], value[1]])),
+            (HEP_PROTO_TYPE, 1) if value[0] != HEP_PROTO_SIP => return None,
+            (HEP_PAYLOAD, _) => payload = Some(value.to_vec()),
+            _ => {}
+        }
+    }
+
+    Some(CapturedPacket {
+        source_ip: source_ip?,
+        dest_ip: dest_ip?,
+        source_port: source_port?,
+        dest_port: dest_port?,
+        payload: payload?,
+        captured_at: None,
+        parsed: None,
+    })
+}
+
+fn hep_address(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => <[u8; 4]>::try_from(value).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(value).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{hep_packet, sip_request};
+
+    const SCANNER: &str = "203.0.113.9";
+
+    #[test]
+    fn udp_source_ignores_hep_from_untrusted_peers() {
+        let mut source = UdpSource::bind("127.0.0.1:0".parse().unwrap(), 5060, Vec::new()).unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let scan = sip_request("INVITE", "friendly-scanner", "u3", 1);
+        sender
+            .send_to(
+                &hep_packet(SCANNER, 5060, &scan),
+                source.local_addr().unwrap(),
+            )
+            .unwrap();
+        assert!(source.next_packet().unwrap().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::Duration;
+use uablock_rust::bans::parse_duration;
+
+/// 未指定 `--url` 且未设置 `UABLOCK_API_LISTEN` 时请求的地址
+const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+/// `DELETE /bans` 响应
+#[derive(Debug, Deserialize)]
+struct UnbanReport {
+    #[serde(default)]
+    unbanned: Vec<IpAddr>,
+}
+
+/// 解封参数
+#[derive(Debug)]
+struct UnbanOptions {
+    url: String,
+    token: String,
+    timeout: Duration,
+    all: bool,
+    older_than: Option<String>,
+}
+
+impl UnbanOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            url: std::env::var("UABLOCK_API_LISTEN")
+                .map(|listen| format!("http://{}", listen))
+                .unwrap_or_else(|_| DEFAULT_URL.to_string()),
+            token: std::env::var("UABLOCK_API_TOKEN").unwrap_or_default(),
+            timeout: Duration::from_secs(60),
+            all: false,
+            older_than: None,
+        };
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--all" {
+                options.all = true;
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("参数 {} 缺少取值", flag))?;
+            match flag.as_str() {
+                "--older-than" => {
+                    parse_duration(value)
+                        .ok_or_else(|| format!("参数 {} 的取值无效: {}", flag, value))?;
+                    options.older_than = Some(value.clone());
+                }
+                "--url" => options.url = value.trim_end_matches('/').to_string(),
+                "--token" => options.token = value.clone(),
+                "--timeout" => {
+                    options.timeout = Duration::from_secs(
+                        value
+                            .parse()
+                            .map_err(|_| format!("参数 {} 的取值无效: {}", flag, value))?,
+                    )
+                }
+                _ => return Err(format!("未知参数: {}", flag)),
+            }
+        }
+        if options.all == options.older_than.is_some() {
+            return Err("用法: uablock unban --all | --older-than <时长，如 7d>".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// `uablock unban`：让运行中的守护进程批量解封
+///
+/// `--all` 解封全部，`--older-than 7d` 只解封封禁时长达到 7 天的 IP
+/// （本次运行之前就存在的封禁按守护进程启动时间计算）。
+pub fn run(args: &[String]) -> i32 {
+    let options = match UnbanOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    match request(&options) {
+        Ok(report) => {
+            for ip in &report.unbanned {
+                println!("{}", ip);
+            }
+            println!("共解封 {} 个 IP", report.unbanned.len());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn request(options: &UnbanOptions) -> Result<UnbanReport, String> {
+    let url = format!("{}/bans", options.url);
+    let request = ureq::AgentBuilder::new()
+        .timeout(options.timeout)
+        .build()
+        .delete(&url)
+        .set("Authorization", &format!("Bearer {}", options.token));
+    let request = match &options.older_than {
+        Some(age) => request.query("older_than", age),
+        None => request.query("all", "true"),
+    };
+    request
+        .call()
+        .map_err(|e| format!("无法请求 {}: {}", url, e))?
+        .into_json()
+        .map_err(|e| format!("响应解析失败: {}", e))
+}
@@ -0,0 +1,353 @@
+use log::{debug, info, warn};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// 未验证来源请求计数的窗口
+const UNVERIFIED_WINDOW: Duration = Duration::from_secs(60);
+
+/// 反向解析失败或不匹配后，多久之后才重新解析
+const RDNS_RETRY: Duration = Duration::from_secs(3600);
+
+/// 认证成功信号的原因代码（由 PBX 接入模块上报）
+pub const AUTH_SUCCESS: &str = "AUTH_SUCCESS";
+
+/// 请求头的指纹：按出现顺序排列的小写头部名称的 FNV-1a 哈希（16 位十六进制）
+///
+/// 同一型号终端的头部顺序是固定的，冒用白名单 UA 的扫描器通常与之不同。
+/// 不是 SIP 请求时返回 None。
+pub fn fingerprint(payload: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(payload).ok()?;
+    let mut lines = text.split("\r\n");
+    lines.next().filter(|line| line.ends_with("SIP/2.0"))?;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let Some((name, _)) = line.split_once(':') else {
+            continue;
+        };
+        for byte in name.trim().to_ascii_lowercase().bytes().chain([b'\n']) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    Some(format!("{:016x}", hash))
+}
+
+/// 后台反向解析：PTR 记录再正向解析，确认包含原 IP（防止伪造 PTR）
+struct ReverseDns {
+    requests: Sender<IpAddr>,
+    results: Receiver<(IpAddr, Option<String>)>,
+    /// 已提交、尚未返回的解析
+    pending: HashSet<IpAddr>,
+    /// 解析失败或不匹配的来源，到期后才重试
+    failed: HashMap<IpAddr, Instant>,
+}
+
+impl ReverseDns {
+    fn start() -> Result<Self, String> {
+        let (requests, rx) = mpsc::channel::<IpAddr>();
+        let (tx, results) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("verify-rdns".to_string())
+            .spawn(move || {
+                for ip in rx {
+                    if tx.send((ip, Self::confirmed_name(ip))).is_err() {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| format!("启动反向解析线程失败: {}", e))?;
+        Ok(Self {
+            requests,
+            results,
+            pending: HashSet::new(),
+            failed: HashMap::new(),
+        })
+    }
+
+    /// 正反向一致的主机名
+    fn confirmed_name(ip: IpAddr) -> Option<String> {
+        let name = dns_lookup::lookup_addr(&ip).ok()?;
+        let addresses = dns_lookup::lookup_host(&name).ok()?;
+        if addresses.contains(&ip) {
+            Some(name)
+        } else {
+            debug!("【验证】IP {} 的 PTR {} 正向解析不包含该 IP", ip, name);
+            None
+        }
+    }
+
+    fn request(&mut self, ip: IpAddr) {
+        if self.pending.contains(&ip)
+            || self
+                .failed
+                .get(&ip)
+                .is_some_and(|at| at.elapsed() < RDNS_RETRY)
+        {
+            return;
+        }
+        if self.requests.send(ip).is_ok() {
+            self.pending.insert(ip);
+        }
+    }
+}
+
+/// 白名单 UA 的二次验证
+///
+/// UA 可以随意伪造，只凭 UA 放行会让冒充 `freeswitch` 的扫描器永远不被封禁，
+/// 甚至解封已被封禁的自己。启用后，UA 在白名单中但来源尚未通过验证时：
+///
+/// - 不会因此解封该来源
+/// - 每分钟请求数超过上限时按 `UA_UNVERIFIED` 处置
+///
+/// 来源满足任一已启用的验证方式后，在有效期内按正常白名单处理：
+///
+/// - `auth`：PBX 报告该来源认证成功（[`AUTH_SUCCESS`] 信号）
+/// - `rdns`：反向解析得到的主机名（正反向一致）匹配配置的正则
+/// - `fingerprint`：请求头指纹（见 [`fingerprint`]）在配置的列表中
+pub struct Verifier {
+    auth: bool,
+    rdns_pattern: Option<Regex>,
+    rdns: Option<ReverseDns>,
+    fingerprints: HashSet<String>,
+    ttl: Duration,
+    limit: u32,
+    /// 已验证的来源 -> 验证时间
+    verified: HashMap<IpAddr, Instant>,
+    /// 未验证来源当前窗口的 (开始时间, 请求数)
+    unverified: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl Verifier {
+    /// `ttl` 为验证结果的有效期，`limit` 为未验证来源每分钟允许的请求数
+    pub fn new(ttl: Duration, limit: u32) -> Self {
+        Self {
+            auth: false,
+            rdns_pattern: None,
+            rdns: None,
+            fingerprints: HashSet::new(),
+            ttl,
+            limit,
+            verified: HashMap::new(),
+            unverified: HashMap::new(),
+        }
+    }
+
+    /// 从环境变量创建，未设置 UABLOCK_VERIFY 时返回 None
+    ///
+    /// - UABLOCK_VERIFY：启用的验证方式，逗号分隔：`auth`、`rdns`、`fingerprint`
+    /// - UABLOCK_VERIFY_RDNS：主机名需要匹配的正则（启用 rdns 时必填）
+    /// - UABLOCK_VERIFY_FINGERPRINTS：允许的请求头指纹，逗号分隔（启用 fingerprint 时必填）
+    /// - UABLOCK_VERIFY_TTL：验证结果的有效期（秒，默认 86400）
+    /// - UABLOCK_VERIFY_LIMIT：未验证来源每分钟允许的请求数（默认 30）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let methods = match std::env::var("UABLOCK_VERIFY") {
+            Ok(methods) if !methods.is_empty() => methods,
+            _ => return Ok(None),
+        };
+        let env_u64 = |name: &str, default: u64| match std::env::var(name) {
+            Ok(value) if !value.is_empty() => value
+                .parse()
+                .map_err(|_| format!("{} 无效: {}", name, value)),
+            _ => Ok(default),
+        };
+        let mut verifier = Self::new(
+            Duration::from_secs(env_u64("UABLOCK_VERIFY_TTL", 86400)?),
+            env_u64("UABLOCK_VERIFY_LIMIT", 30)? as u32,
+        );
+        for method in methods.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            verifier = match method {
+                "auth" => verifier.with_auth(),
+                "rdns" => {
+                    let pattern = std::env::var("UABLOCK_VERIFY_RDNS")
+                        .ok()
+                        .filter(|p| !p.is_empty())
+                        .ok_or("启用 rdns 验证时必须设置 UABLOCK_VERIFY_RDNS")?;
+                    let pattern = Regex::new(&pattern)
+                        .map_err(|e| format!("UABLOCK_VERIFY_RDNS 无效: {}", e))?;
+                    verifier.with_rdns(pattern)?
+                }
+                "fingerprint" => {
+                    let fingerprints: Vec<String> = std::env::var("UABLOCK_VERIFY_FINGERPRINTS")
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|f| f.trim().to_lowercase())
+                        .filter(|f| !f.is_empty())
+                        .collect();
+                    if fingerprints.is_empty() {
+                        return Err(
+                            "启用 fingerprint 验证时必须设置 UABLOCK_VERIFY_FINGERPRINTS"
+                                .to_string(),
+                        );
+                    }
+                    verifier.with_fingerprints(fingerprints)
+                }
+                other => return Err(format!("UABLOCK_VERIFY 中的验证方式无效: {}", other)),
+            };
+        }
+        info!(
+            "白名单 UA 二次验证已启用: {}，有效期 {} 秒，未验证来源每分钟最多 {} 次请求",
+            methods,
+            verifier.ttl.as_secs(),
+            verifier.limit
+        );
+        Ok(Some(verifier))
+    }
+
+    /// PBX 报告认证成功的来源视为已验证
+    pub fn with_auth(mut self) -> Self {
+        self.auth = true;
+        self
+    }
+
+    /// 反向解析的主机名匹配 `pattern` 的来源视为已验证
+    pub fn with_rdns(mut self, pattern: Regex) -> Result<Self, String> {
+        self.rdns = Some(ReverseDns::start()?);
+        self.rdns_pattern = Some(pattern);
+        Ok(self)
+    }
+
+    /// 请求头指纹在列表中的来源视为已验证
+    pub fn with_fingerprints(mut self, fingerprints: impl IntoIterator<Item = String>) -> Self {
+        self.fingerprints.extend(fingerprints);
+        self
+    }
+
+    /// 白名单 UA 的请求：来源是否已通过验证
+    ///
+    /// 未验证时按请求头指纹验证，并提交反向解析（结果在之后的请求中生效）。
+    pub fn check(&mut self, ip: IpAddr, payload: &[u8]) -> bool {
+        self.collect_rdns();
+        if self.is_verified(&ip) {
+            return true;
+        }
+        let fingerprint = fingerprint(payload);
+        if let Some(fingerprint) = fingerprint.as_ref() {
+            if self.fingerprints.contains(fingerprint) {
+                self.mark_verified(ip, "fingerprint");
+                return true;
+            }
+        }
+        if let Some(rdns) = self.rdns.as_mut() {
+            rdns.request(ip);
+        }
+        debug!(
+            "【验证】IP {} 尚未通过验证，请求头指纹: {}",
+            ip,
+            fingerprint.as_deref().unwrap_or("-")
+        );
+        false
+    }
+
+    /// 计入一次未验证来源的请求，返回 true 表示本窗口内请求数超过上限
+    pub fn observe_unverified(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let (since, requests) = self.unverified.entry(ip).or_insert((now, 0));
+        if now.duration_since(*since) >= UNVERIFIED_WINDOW {
+            *since = now;
+            *requests = 0;
+        }
+        *requests += 1;
+        *requests > self.limit
+    }
+
+    /// PBX 报告来源认证成功（未启用 auth 验证时忽略）
+    pub fn auth_succeeded(&mut self, ip: IpAddr) {
+        if self.auth {
+            self.mark_verified(ip, "auth");
+        }
+    }
+
+    /// 来源通过了某种验证
+    pub fn mark_verified(&mut self, ip: IpAddr, method: &str) {
+        self.unverified.remove(&ip);
+        if self.verified.insert(ip, Instant::now()).is_none() {
+            info!("【验证】IP {} 通过 {} 验证", ip, method);
+        }
+    }
+
+    pub fn is_verified(&self, ip: &IpAddr) -> bool {
+        self.verified
+            .get(ip)
+            .is_some_and(|at| at.elapsed() < self.ttl)
+    }
+
+    /// 清理过期的验证结果和计数窗口
+    pub fn cleanup(&mut self) {
+        let ttl = self.ttl;
+        self.verified.retain(|_, at| at.elapsed() < ttl);
+        self.unverified
+            .retain(|_, (since, _)| since.elapsed() < UNVERIFIED_WINDOW);
+        if let Some(rdns) = self.rdns.as_mut() {
+            rdns.failed.retain(|_, at| at.elapsed() < RDNS_RETRY);
+        }
+    }
+
+    /// 取回已完成的反向解析结果
+    fn collect_rdns(&mut self) {
+        let Some(rdns) = self.rdns.as_mut() else {
+            return;
+        };
+        let mut confirmed = Vec::new();
+        loop {
+            match rdns.results.try_recv() {
+                Ok((ip, name)) => {
+                    rdns.pending.remove(&ip);
+                    match name {
+                        Some(name)
+                            if self
+                                .rdns_pattern
+                                .as_ref()
+                                .is_some_and(|pattern| pattern.is_match(&name)) =>
+                        {
+                            confirmed.push((ip, name));
+                        }
+                        name => {
+                            debug!("【验证】IP {} 的主机名 {:?} 不匹配", ip, name);
+                            rdns.failed.insert(ip, Instant::now());
+                        }
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    warn!("【验证】反向解析线程已退出");
+                    self.rdns = None;
+                    break;
+                }
+            }
+        }
+        for (ip, name) in confirmed {
+            self.mark_verified(ip, &format!("rdns（{}）", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHONE: &str = "198.51.100.7";
+    use crate::testing::sip_request;
+
+    const SCANNER: &str = "203.0.113.9";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn header_fingerprint_verifies_known_devices() {
+        let payload = sip_request("REGISTER", "MicroSIP/3.21.3", "f1", 1);
+        let known = fingerprint(payload.as_bytes()).unwrap();
+        assert_eq!(known.len(), 16);
+        let reordered = payload.replacen("Via:", "X-Scanner: 1\r\nVia:", 1);
+        assert_ne!(fingerprint(reordered.as_bytes()).unwrap(), known);
+
+        let mut verifier = Verifier::new(Duration::from_secs(3600), 30).with_fingerprints([known]);
+        assert!(!verifier.check(ip(SCANNER), reordered.as_bytes()));
+        assert!(verifier.check(ip(PHONE), payload.as_bytes()));
+        assert!(verifier.is_verified(&ip(PHONE)));
+    }
+}
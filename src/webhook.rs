@@ -0,0 +1,527 @@
+use crate::bans::BanReason;
+use crate::enforcement::Enforcer;
+use crate::reason::ReasonCode;
+use crate::state_file::unix_now;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 请求时间戳允许的最大偏差（秒），超出视为重放
+const MAX_CLOCK_SKEW: u64 = 300;
+
+/// 最近请求的 (时间戳, 签名) 最多保留的条数，超出时拒绝新请求直到旧记录过期
+const MAX_SEEN: usize = 10_000;
+
+/// 检查限时封禁到期的周期
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 通过 webhook 执行的封禁/解封使用的原因代码和事件来源
+const WEBHOOK_REASON: &str = "WEBHOOK";
+const WEBHOOK_ORIGIN: &str = "webhook";
+
+/// webhook 命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookAction {
+    Ban,
+    Unban,
+}
+
+/// webhook 请求体
+#[derive(Debug, Deserialize)]
+pub struct WebhookCommand {
+    pub action: WebhookAction,
+    pub ip: IpAddr,
+    /// 封禁时长（秒），不设置时永久封禁
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+    /// 调用方名称，例如 `billing`（仅用于审计）
+    #[serde(default)]
+    pub requested_by: String,
+    /// 备注，例如工单号（仅用于审计）
+    #[serde(default)]
+    pub note: String,
+}
+
+impl WebhookCommand {
+    /// 限时封禁的到期时间（Unix 秒），时长超出可表示的范围时返回错误
+    pub fn expires_at(&self, now: u64) -> Result<Option<u64>, String> {
+        self.duration_secs
+            .map(|duration| {
+                now.checked_add(duration)
+                    .filter(|&expires_at| i64::try_from(expires_at).is_ok())
+                    .ok_or_else(|| format!("duration_secs 超出范围: {}", duration))
+            })
+            .transpose()
+    }
+}
+
+/// 审计日志的一行
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: u64,
+    remote: &'a str,
+    action: WebhookAction,
+    ip: IpAddr,
+    /// 原因分类：命令为 MANUAL，到期解封为 EXPIRED
+    code: ReasonCode,
+    duration_secs: Option<u64>,
+    requested_by: &'a str,
+    note: &'a str,
+    result: &'a str,
+}
+
+/// 外部系统（如计费系统）推送封禁/解封命令的入口
+///
+/// 请求必须携带 `X-Uablock-Timestamp`（Unix 秒）和 `X-Uablock-Signature: sha256=<hex>`，
+/// 签名为 HMAC-SHA256(secret, `<timestamp>.<body>`)。命令通过统一执行器生效（与 API、检测相同的路径），
+/// 每条命令都写入审计日志。时间戳允许的偏差内同一 (时间戳, 签名) 只接受一次，防止重放。
+/// 限时封禁的到期时间可持久化，重启后继续生效。
+pub struct Webhook {
+    secret: Vec<u8>,
+    audit: Option<Mutex<File>>,
+    /// 限时封禁的到期时间持久化文件
+    state_path: Option<PathBuf>,
+    /// 限时封禁：IP -> 到期时间（Unix 秒）
+    expiries: Mutex<HashMap<IpAddr, u64>>,
+    /// 时间戳仍在允许偏差内的已接受请求：(时间戳, 签名)
+    seen: Mutex<BTreeSet<(u64, Vec<u8>)>>,
+}
+
+impl Webhook {
+    /// 从环境变量创建，未设置 `UABLOCK_WEBHOOK_SECRET` 时返回 None
+    ///
+    /// - `UABLOCK_WEBHOOK_SECRET`：签名密钥
+    /// - `UABLOCK_WEBHOOK_AUDIT_LOG`：审计日志路径（JSON Lines，可选）
+    /// - `UABLOCK_WEBHOOK_STATE`：限时封禁到期时间的持久化文件（可选）
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let secret = match std::env::var("UABLOCK_WEBHOOK_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => return Ok(None),
+        };
+        let audit = match std::env::var("UABLOCK_WEBHOOK_AUDIT_LOG") {
+            Ok(path) if !path.is_empty() => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| format!("无法打开 webhook 审计日志 {}: {}", path, e))?;
+                Some(Mutex::new(file))
+            }
+            _ => None,
+        };
+        let state_path = std::env::var("UABLOCK_WEBHOOK_STATE")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        Self::new(secret.into_bytes(), audit, state_path).map(Some)
+    }
+
+    /// 创建入口并从状态文件恢复限时封禁的到期时间
+    fn new(
+        secret: Vec<u8>,
+        audit: Option<Mutex<File>>,
+        state_path: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        let expiries = match &state_path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(content) => serde_json::from_str(&content)
+                    .map_err(|e| format!("解析 webhook 状态文件 {} 失败: {}", path.display(), e))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+                Err(e) => {
+                    return Err(format!(
+                        "读取 webhook 状态文件 {} 失败: {}",
+                        path.display(),
+                        e
+                    ))
+                }
+            },
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            secret,
+            audit,
+            state_path,
+            expiries: Mutex::new(expiries),
+            seen: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// 启动限时封禁到期检查任务（需在 tokio 运行时中调用）
+    pub fn start(self, enforcer: Arc<Enforcer>) -> Arc<Self> {
+        let this = Arc::new(self);
+        let pending = this.expiries.lock().unwrap().len();
+        if pending > 0 {
+            info!("【Webhook】恢复 {} 条限时封禁", pending);
+        }
+        let expiry = this.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+            loop {
+                interval.tick().await;
+                let expiry = expiry.clone();
+                let enforcer = enforcer.clone();
+                // 解封会调用 iptables，放到阻塞线程池执行
+                if let Err(e) = tokio::task::spawn_blocking(move || expiry.expire(&enforcer)).await
+                {
+                    error!("【Webhook】到期检查异常: {}", e);
+                }
+            }
+        });
+        this
+    }
+
+    /// 校验时间戳和签名，并拒绝重放的请求
+    pub fn verify(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), String> {
+        let timestamp = timestamp.ok_or("缺少 X-Uablock-Timestamp")?;
+        let sent_at: u64 = timestamp.parse().map_err(|_| "时间戳无效")?;
+        let now = unix_now();
+        if now.abs_diff(sent_at) > MAX_CLOCK_SKEW {
+            return Err("时间戳超出允许范围".to_string());
+        }
+        let signature = signature
+            .and_then(|s| s.strip_prefix("sha256="))
+            .and_then(decode_hex)
+            .ok_or("缺少或无效的 X-Uablock-Signature")?;
+        let mut mac = HmacSha256::new_from_slice(&self.secret).map_err(|e| e.to_string())?;
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.verify_slice(&signature)
+            .map_err(|_| "签名校验失败".to_string())?;
+
+        // 早于允许偏差的时间戳已会被上面拒绝，对应的记录不必再保留
+        let mut seen = self.seen.lock().unwrap();
+        *seen = seen.split_off(&(now.saturating_sub(MAX_CLOCK_SKEW), Vec::new()));
+        if seen.contains(&(sent_at, signature.clone())) {
+            return Err("重复的请求（重放）".to_string());
+        }
+        if seen.len() >= MAX_SEEN {
+            return Err("请求过于频繁".to_string());
+        }
+        seen.insert((sent_at, signature));
+        Ok(())
+    }
+
+    /// 执行一条命令并写入审计日志；返回防火墙规则是否发生变化
+    pub fn apply(
+        &self,
+        enforcer: &Enforcer,
+        command: &WebhookCommand,
+        remote: &str,
+    ) -> Result<bool, String> {
+        let expires_at = command.expires_at(unix_now())?;
+        let result = match command.action {
+            WebhookAction::Ban => enforcer.ban(
+                command.ip,
+                BanReason::new(WEBHOOK_REASON, WEBHOOK_ORIGIN)
+                    .with_rule(&command.note)
+                    .expires_at(expires_at.unwrap_or(0)),
+            ),
+            WebhookAction::Unban => enforcer.unban(command.ip, WEBHOOK_REASON, WEBHOOK_ORIGIN),
+        };
+        if result.is_ok() {
+            let mut expiries = self.expiries.lock().unwrap();
+            match (command.action, expires_at) {
+                (WebhookAction::Ban, Some(expires_at)) => {
+                    expiries.insert(command.ip, expires_at);
+                }
+                _ => {
+                    expiries.remove(&command.ip);
+                }
+            }
+            self.save(&expiries);
+        }
+
+        let outcome = match &result {
+            Ok(true) => "applied".to_string(),
+            Ok(false) => "unchanged".to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+        info!(
+            "【Webhook】{} 请求 {:?} IP: {}（时长: {:?} 秒，备注: {}，来自 {}）-> {}",
+            command.requested_by,
+            command.action,
+            command.ip,
+            command.duration_secs,
+            command.note,
+            remote,
+            outcome
+        );
+        self.audit(&AuditRecord {
+            timestamp: unix_now(),
+            remote,
+            action: command.action,
+            ip: command.ip,
+            code: ReasonCode::classify(WEBHOOK_REASON),
+            duration_secs: command.duration_secs,
+            requested_by: &command.requested_by,
+            note: &command.note,
+            result: &outcome,
+        });
+        result
+    }
+
+    /// 解除到期的限时封禁
+    fn expire(&self, enforcer: &Enforcer) {
+        let now = unix_now();
+        let mut expiries = self.expiries.lock().unwrap();
+        let due: Vec<IpAddr> = expiries
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        for ip in due {
+            let outcome = match enforcer.unban(ip, "EXPIRED", WEBHOOK_ORIGIN) {
+                Ok(changed) => {
+                    expiries.remove(&ip);
+                    info!("【Webhook】IP: {} 限时封禁到期，已解封", ip);
+                    if changed {
+                        "applied".to_string()
+                    } else {
+                        "unchanged".to_string()
+                    }
+                }
+                // 保留记录，下次重试
+                Err(e) => {
+                    error!("【Webhook】IP {} 限时封禁到期解封失败: {}", ip, e);
+                    format!("error: {}", e)
+                }
+            };
+            self.audit(&AuditRecord {
+                timestamp: now,
+                remote: "-",
+                action: WebhookAction::Unban,
+                ip,
+                code: ReasonCode::Expired,
+                duration_secs: None,
+                requested_by: "expiry",
+                note: "",
+                result: &outcome,
+            });
+        }
+        self.save(&expiries);
+    }
+
+    fn save(&self, expiries: &HashMap<IpAddr, u64>) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+        let result = serde_json::to_string(expiries)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                let tmp_path = path.with_extension("tmp");
+                std::fs::write(&tmp_path, content).map_err(|e| e.to_string())?;
+                std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("【Webhook】保存状态文件 {} 失败: {}", path.display(), e);
+        }
+    }
+
+    fn audit(&self, record: &AuditRecord) {
+        let Some(file) = &self.audit else {
+            return;
+        };
+        let result = serde_json::to_string(record)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(file.lock().unwrap(), "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("【Webhook】写入审计日志失败: {}", e);
+        }
+    }
+}
+
+/// 解码十六进制字符串
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryFirewall;
+    use crate::{EventBus, Stats};
+
+    const SECRET: &[u8] = b"billing-secret";
+
+    fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256={}", hex)
+    }
+
+    fn enforcer() -> Enforcer {
+        Enforcer::new(
+            Some(Box::new(MemoryFirewall::new())),
+            None,
+            Arc::new(Stats::default()),
+            Arc::new(EventBus::new()),
+        )
+    }
+
+    fn ban(ip: &str, duration_secs: Option<u64>) -> WebhookCommand {
+        WebhookCommand {
+            action: WebhookAction::Ban,
+            ip: ip.parse().unwrap(),
+            duration_secs,
+            requested_by: "billing".to_string(),
+            note: "fraud #1234".to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_only_correctly_signed_requests_once() {
+        let webhook = Webhook::new(SECRET.to_vec(), None, None).unwrap();
+        let body = br#"{"action": "ban", "ip": "203.0.113.9"}"#;
+        let timestamp = unix_now().to_string();
+        let signature = sign(SECRET, &timestamp, body);
+
+        assert!(webhook.verify(None, Some(&signature), body).is_err());
+        assert!(webhook.verify(Some(&timestamp), None, body).is_err());
+        assert!(webhook
+            .verify(Some(&timestamp), Some("sha256=zz"), body)
+            .is_err());
+        let forged = sign(b"other-secret", &timestamp, body);
+        assert!(webhook
+            .verify(Some(&timestamp), Some(&forged), body)
+            .is_err());
+        // 篡改请求体后签名不再匹配
+        let tampered = br#"{"action": "ban", "ip": "198.51.100.7"}"#;
+        assert!(webhook
+            .verify(Some(&timestamp), Some(&signature), tampered)
+            .is_err());
+
+        webhook
+            .verify(Some(&timestamp), Some(&signature), body)
+            .unwrap();
+        // 同一请求重放被拒绝，新的时间戳重新签名后可以通过
+        let error = webhook
+            .verify(Some(&timestamp), Some(&signature), body)
+            .unwrap_err();
+        assert!(error.contains("重放"), "{}", error);
+        let next = (unix_now() + 1).to_string();
+        webhook
+            .verify(Some(&next), Some(&sign(SECRET, &next, body)), body)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_the_allowed_skew() {
+        let webhook = Webhook::new(SECRET.to_vec(), None, None).unwrap();
+        let body = br#"{"action": "unban", "ip": "203.0.113.9"}"#;
+        let now = unix_now();
+        for sent_at in [now - MAX_CLOCK_SKEW - 10, now + MAX_CLOCK_SKEW + 10] {
+            let timestamp = sent_at.to_string();
+            let error = webhook
+                .verify(
+                    Some(&timestamp),
+                    Some(&sign(SECRET, &timestamp, body)),
+                    body,
+                )
+                .unwrap_err();
+            assert!(error.contains("时间戳"), "{}", error);
+        }
+        for sent_at in [now - MAX_CLOCK_SKEW + 10, now + MAX_CLOCK_SKEW - 10] {
+            let timestamp = sent_at.to_string();
+            webhook
+                .verify(
+                    Some(&timestamp),
+                    Some(&sign(SECRET, &timestamp, body)),
+                    body,
+                )
+                .unwrap();
+        }
+        assert!(webhook
+            .verify(Some("soon"), Some("sha256=00"), body)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow_the_expiry() {
+        let webhook = Webhook::new(SECRET.to_vec(), None, None).unwrap();
+        let enforcer = enforcer();
+        let command = ban("203.0.113.9", Some(u64::MAX));
+        assert!(command.expires_at(unix_now()).is_err());
+        assert!(webhook.apply(&enforcer, &command, "127.0.0.1").is_err());
+        assert!(enforcer.bans().get(&command.ip).is_none());
+        assert_eq!(
+            ban("203.0.113.9", Some(60)).expires_at(1_000).unwrap(),
+            Some(1_060)
+        );
+        assert_eq!(ban("203.0.113.9", None).expires_at(1_000).unwrap(), None);
+    }
+
+    #[test]
+    fn lifts_timed_bans_when_they_expire() {
+        let webhook = Webhook::new(SECRET.to_vec(), None, None).unwrap();
+        let enforcer = enforcer();
+        let timed = ban("203.0.113.9", Some(0));
+        let permanent = ban("203.0.113.10", None);
+        assert!(webhook.apply(&enforcer, &timed, "127.0.0.1").unwrap());
+        assert!(webhook.apply(&enforcer, &permanent, "127.0.0.1").unwrap());
+
+        webhook.expire(&enforcer);
+        assert!(enforcer.bans().get(&timed.ip).is_none());
+        assert!(enforcer.bans().get(&permanent.ip).is_some());
+        assert!(webhook.expiries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn timed_bans_survive_a_restart_through_the_state_file() {
+        let path =
+            std::env::temp_dir().join(format!("uablock-webhook-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let webhook = Webhook::new(SECRET.to_vec(), None, Some(path.clone())).unwrap();
+        let enforcer = enforcer();
+        let command = ban("203.0.113.9", Some(3600));
+        webhook.apply(&enforcer, &command, "127.0.0.1").unwrap();
+        let expires_at = webhook.expiries.lock().unwrap()[&command.ip];
+
+        let restored = Webhook::new(SECRET.to_vec(), None, Some(path.clone())).unwrap();
+        assert_eq!(
+            *restored.expiries.lock().unwrap(),
+            HashMap::from([(command.ip, expires_at)])
+        );
+        // 解封后状态文件中的记录同时删除
+        let unban = WebhookCommand {
+            action: WebhookAction::Unban,
+            ..ban("203.0.113.9", None)
+        };
+        restored.apply(&enforcer, &unban, "127.0.0.1").unwrap();
+        let restored = Webhook::new(SECRET.to_vec(), None, Some(path.clone())).unwrap();
+        assert!(restored.expiries.lock().unwrap().is_empty());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Webhook::new(SECRET.to_vec(), None, Some(path.clone())).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,38 +1,244 @@
 use log::debug;
+use serde::{Deserialize, Serialize};
 
-/// 白名单管理器，支持模糊匹配
+/// 名单模式：启动时选择，运行中不能切换
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListMode {
+    /// 白名单：只放行匹配的 UA
+    #[default]
+    Allow,
+    /// 黑名单：只封禁匹配的 UA，其余放行
+    Deny,
+}
+
+impl ListMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        }
+    }
+
+    /// UA 未被放行时的原因代码
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Allow => "UA_NOT_ALLOWED",
+            Self::Deny => "UA_DENIED",
+        }
+    }
+}
+
+/// 模式与 UA 的匹配方式（均不区分大小写）
+///
+/// 全局默认值由 `UABLOCK_UA_MATCH` 设置，单个模式可以用 `exact:`、`prefix:`、`substring:`、
+/// `glob:`、`fuzzy:` 前缀覆盖，如 `prefix:Yealink SIP-T4`。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// 双向包含：UA 包含模式，或模式包含 UA（兼容旧版本；很短的 UA 如 `ms` 也会命中 `microsip`）。
+    /// 黑名单模式下按 `substring` 处理
+    #[default]
+    Fuzzy,
+    /// UA 包含模式
+    Substring,
+    /// UA 以模式开头
+    Prefix,
+    /// UA 与模式完全相同
+    Exact,
+    /// 通配符：`*` 匹配任意个字符，`?` 匹配一个字符，需匹配整个 UA
+    Glob,
+}
+
+impl MatchMode {
+    const ALL: [Self; 5] = [
+        Self::Fuzzy,
+        Self::Substring,
+        Self::Prefix,
+        Self::Exact,
+        Self::Glob,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fuzzy => "fuzzy",
+            Self::Substring => "substring",
+            Self::Prefix => "prefix",
+            Self::Exact => "exact",
+            Self::Glob => "glob",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(text.trim()))
+    }
+
+    /// 从 `UABLOCK_UA_MATCH` 读取全局匹配方式，未设置时为 `fuzzy`
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("UABLOCK_UA_MATCH") {
+            Ok(value) if !value.is_empty() => Self::parse(&value).ok_or_else(|| {
+                format!(
+                    "UABLOCK_UA_MATCH 无效: {}（可选 fuzzy、substring、prefix、exact、glob）",
+                    value
+                )
+            }),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// 拆出模式的匹配方式前缀，没有前缀时返回 None
+    pub fn split_pattern(pattern: &str) -> (Option<Self>, &str) {
+        pattern
+            .split_once(':')
+            .and_then(|(prefix, rest)| {
+                Self::ALL
+                    .into_iter()
+                    .find(|mode| mode.as_str() == prefix)
+                    .map(|mode| (Some(mode), rest))
+            })
+            .unwrap_or((None, pattern))
+    }
+
+    /// 已转为小写的模式和 UA 是否匹配
+    fn matches(&self, pattern: &str, user_agent: &str) -> bool {
+        match self {
+            Self::Fuzzy => user_agent.contains(pattern) || pattern.contains(user_agent),
+            Self::Substring => user_agent.contains(pattern),
+            Self::Prefix => user_agent.starts_with(pattern),
+            Self::Exact => user_agent == pattern,
+            Self::Glob => glob_match(
+                &pattern.chars().collect::<Vec<_>>(),
+                &user_agent.chars().collect::<Vec<_>>(),
+            ),
+        }
+    }
+}
+
+/// 通配符匹配：`*` 匹配任意个字符，`?` 匹配一个字符
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 `*` 的位置及其当前匹配到的文本位置，失配时回溯
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// 白名单管理器，支持多种匹配方式（见 [`MatchMode`]）；也可以作为黑名单使用，见 [`ListMode`]
 pub struct Whitelist {
     patterns: Vec<String>,
+    mode: ListMode,
+    match_mode: MatchMode,
 }
 
 impl Whitelist {
     pub fn new(patterns: Vec<String>) -> Self {
-        Self { patterns }
+        Self {
+            patterns,
+            mode: ListMode::Allow,
+            match_mode: MatchMode::default(),
+        }
+    }
+
+    /// 黑名单：匹配任一模式的 UA 被封禁，其余放行
+    pub fn deny(patterns: Vec<String>) -> Self {
+        Self {
+            patterns,
+            mode: ListMode::Deny,
+            match_mode: MatchMode::default(),
+        }
+    }
+
+    /// 没有匹配方式前缀的模式使用的匹配方式
+    pub fn with_match_mode(mut self, match_mode: MatchMode) -> Self {
+        self.match_mode = match_mode;
+        self
+    }
+
+    pub fn mode(&self) -> ListMode {
+        self.mode
     }
 
-    /// 检查 User-Agent 是否在白名单中（支持模糊匹配）
+    pub fn match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
+
+    /// 检查 User-Agent 是否被放行
+    ///
+    /// 白名单模式下匹配任一模式即放行；黑名单模式下不匹配任何模式即放行。
     pub fn is_allowed(&self, user_agent: &str) -> bool {
-        let ua_lower = user_agent.to_lowercase();
+        match self.mode {
+            ListMode::Allow => self.matches(user_agent),
+            ListMode::Deny => !self.denies(user_agent),
+        }
+    }
 
-        for pattern in &self.patterns {
-            let pattern_lower = pattern.to_lowercase();
+    /// 黑名单匹配
+    ///
+    /// 与白名单不同，`fuzzy` 按 `substring` 处理：模式包含 UA 不算匹配，否则很短的 UA（如 `a`）
+    /// 会被任何模式命中。
+    fn denies(&self, user_agent: &str) -> bool {
+        let matched = self.find(user_agent, MatchMode::Substring);
+        if let Some(pattern) = matched {
+            debug!("User-Agent '{}' 匹配黑名单模式 '{}'", user_agent, pattern);
+        }
+        matched.is_some()
+    }
 
-            // 支持模糊匹配：如果 pattern 包含在 user_agent 中，或者 user_agent 包含在 pattern 中
-            if ua_lower.contains(&pattern_lower) || pattern_lower.contains(&ua_lower) {
-                debug!("User-Agent '{}' 匹配白名单模式 '{}'", user_agent, pattern);
-                return true;
-            }
+    /// 白名单匹配
+    fn matches(&self, user_agent: &str) -> bool {
+        let matched = self.find(user_agent, MatchMode::Fuzzy);
+        if let Some(pattern) = matched {
+            debug!("User-Agent '{}' 匹配白名单模式 '{}'", user_agent, pattern);
         }
+        matched.is_some()
+    }
 
-        false
+    /// 第一个匹配 UA 的模式，`fuzzy` 按 `fuzzy_as` 处理
+    fn find(&self, user_agent: &str, fuzzy_as: MatchMode) -> Option<&String> {
+        let ua_lower = user_agent.to_lowercase();
+        self.patterns.iter().find(|pattern| {
+            let (mode, body) = MatchMode::split_pattern(pattern);
+            let mode = match mode.unwrap_or(self.match_mode) {
+                MatchMode::Fuzzy => fuzzy_as,
+                mode => mode,
+            };
+            mode.matches(&body.to_lowercase(), &ua_lower)
+        })
     }
 
-    /// 添加白名单模式
+    /// 添加模式
     #[allow(dead_code)]
     pub fn add_pattern(&mut self, pattern: String) {
         self.patterns.push(pattern);
     }
 
+    /// 替换全部模式（不改变名单模式）
+    #[allow(dead_code)]
+    pub fn set_patterns(&mut self, patterns: Vec<String>) {
+        self.patterns = patterns;
+    }
+
     /// 获取所有模式
     #[allow(dead_code)]
     pub fn get_patterns(&self) -> &[String] {
@@ -51,3 +257,64 @@ impl Default for Whitelist {
         ])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenants::Tenants;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn whitelist_match_modes_control_strictness() {
+        let list =
+            |mode: MatchMode| Whitelist::new(vec!["microsip".to_string()]).with_match_mode(mode);
+        // 旧版本的双向包含：很短的 UA 也能命中
+        assert!(list(MatchMode::Fuzzy).is_allowed("micro"));
+        assert!(!list(MatchMode::Substring).is_allowed("ms"));
+        assert!(list(MatchMode::Substring).is_allowed("Softphone MicroSIP/3.21"));
+        assert!(list(MatchMode::Prefix).is_allowed("MicroSIP/3.21.3"));
+        assert!(!list(MatchMode::Prefix).is_allowed("Softphone MicroSIP/3.21"));
+        assert!(list(MatchMode::Exact).is_allowed("MICROSIP"));
+        assert!(!list(MatchMode::Exact).is_allowed("MicroSIP/3.21.3"));
+
+        // 单个模式的前缀覆盖全局匹配方式
+        let whitelist = Whitelist::new(vec![
+            "glob:yealink sip-t4?s *".to_string(),
+            "exact:Zoiper".to_string(),
+            "freeswitch".to_string(),
+        ])
+        .with_match_mode(MatchMode::Prefix);
+        assert!(whitelist.is_allowed("Yealink SIP-T46S 66.86.0.15"));
+        assert!(!whitelist.is_allowed("Yealink SIP-T46U 66.86.0.15"));
+        assert!(whitelist.is_allowed("zoiper"));
+        assert!(!whitelist.is_allowed("Zoiper rv2.10"));
+        assert!(whitelist.is_allowed("FreeSWITCH-mod_sofia/1.10"));
+        assert!(!whitelist.is_allowed("mod_sofia FreeSWITCH"));
+        assert!(!whitelist.is_allowed(""));
+
+        // 黑名单下 fuzzy 按 substring 处理
+        let denylist = Whitelist::deny(vec!["friendly-scanner".to_string()]);
+        assert!(denylist.is_allowed("friendly"));
+        assert!(!denylist.is_allowed("friendly-scanner 1.0"));
+
+        assert_eq!(MatchMode::parse("GLOB"), Some(MatchMode::Glob));
+        assert_eq!(MatchMode::parse("regex"), None);
+        let tenants = Tenants::parse(
+            r#"
+        [[tenant]]
+        name = "acme"
+        destinations = ["192.0.2.10"]
+        whitelist = ["yealink"]
+        match_mode = "prefix"
+        "#,
+        )
+        .unwrap();
+        let tenant = tenants.lookup(ip("192.0.2.10"), 5060).unwrap();
+        assert!(tenant.is_allowed("Yealink SIP-T46S"));
+        assert!(!tenant.is_allowed("yea"));
+    }
+}
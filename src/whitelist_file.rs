@@ -0,0 +1,122 @@
+use crate::bans::parse_duration;
+use crate::whitelist::Whitelist;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// 默认的文件修改检查间隔
+const DEFAULT_RELOAD: Duration = Duration::from_secs(5);
+
+/// 白名单文件：每行一个模式，`#` 之后为注释，空行忽略
+///
+/// 文件修改后（定期检查修改时间）或收到 SIGHUP 时重新读取，在同一把锁内整体替换模式列表，
+/// 抓包和判定不中断，也不会看到只更新了一半的名单。新内容无效（无法读取或没有任何模式）时
+/// 保留原来的名单。通过 HTTP API 或审核队列对名单的修改不会写回文件，下次重新读取时被覆盖。
+pub struct WhitelistFile {
+    path: PathBuf,
+    whitelist: Arc<Mutex<Whitelist>>,
+    /// 上次读取时文件的修改时间
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl WhitelistFile {
+    pub fn new(path: &Path, whitelist: Arc<Mutex<Whitelist>>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            whitelist,
+            modified: Mutex::new(modified(path)),
+        }
+    }
+
+    /// `UABLOCK_WHITELIST_FILE` 指定的文件，未设置时返回 None
+    pub fn path_from_env() -> Option<PathBuf> {
+        std::env::var("UABLOCK_WHITELIST_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// 检查文件修改的间隔（`UABLOCK_WHITELIST_RELOAD`，默认 5s），`0` 表示只在收到 SIGHUP 时重新读取
+    pub fn reload_interval_from_env() -> Result<Option<Duration>, String> {
+        match std::env::var("UABLOCK_WHITELIST_RELOAD") {
+            Ok(value) if value.trim() == "0" => Ok(None),
+            Ok(value) if !value.is_empty() => parse_duration(value.trim())
+                .filter(|interval| !interval.is_zero())
+                .map(Some)
+                .ok_or_else(|| format!("UABLOCK_WHITELIST_RELOAD 无效: {}", value)),
+            _ => Ok(Some(DEFAULT_RELOAD)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 重新读取文件，返回名单是否发生变化
+    pub fn reload(&self) -> Result<bool, String> {
+        *self.modified.lock().unwrap() = modified(&self.path);
+        let patterns = read_patterns(&self.path)?;
+        let mut whitelist = self.whitelist.lock().unwrap();
+        if whitelist.get_patterns() == patterns.as_slice() {
+            return Ok(false);
+        }
+        info!(
+            "【白名单文件】{} 已重新读取: {:?}",
+            self.path.display(),
+            patterns
+        );
+        whitelist.set_patterns(patterns);
+        Ok(true)
+    }
+
+    /// 文件的修改时间变化时重新读取
+    pub fn reload_if_modified(&self) -> Result<bool, String> {
+        if modified(&self.path) == *self.modified.lock().unwrap() {
+            return Ok(false);
+        }
+        self.reload()
+    }
+
+    /// 启动按间隔检查文件修改的线程
+    pub fn start_watch(self: &Arc<Self>, interval: Duration) -> Result<(), String> {
+        let file = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("whitelist-file".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(file) = file.upgrade() else {
+                    break;
+                };
+                if let Err(e) = file.reload_if_modified() {
+                    warn!("【白名单文件】{}，继续使用原来的名单", e);
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| format!("启动白名单文件检查线程失败: {}", e))
+    }
+}
+
+/// 读取白名单文件中的模式，文件中没有任何模式时返回错误
+pub fn read_patterns(path: &Path) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("无法读取白名单文件 {}: {}", path.display(), e))?;
+    let patterns = parse_patterns(&text);
+    if patterns.is_empty() {
+        return Err(format!("白名单文件 {} 中没有任何模式", path.display()));
+    }
+    Ok(patterns)
+}
+
+/// 解析白名单文件：每行一个模式，`#` 之后为注释
+pub fn parse_patterns(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,90 @@
+use crate::state_file::unix_now;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 标签长度
+const TAG_LEN: usize = 32;
+
+/// 单帧最大长度（摘要可能较大）
+const MAX_FRAME: usize = 8 * 1024 * 1024;
+
+/// 允许的发送时间偏差（秒），超出的帧视为重放并丢弃
+const MAX_CLOCK_SKEW: u64 = 300;
+
+/// 帧内容：发送时间 + 负载
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    sent_at: u64,
+    payload: T,
+}
+
+/// 编码一帧：4 字节大端长度 + JSON + 32 字节 HMAC-SHA256 标签
+pub fn encode<T: Serialize>(payload: &T, key: &[u8]) -> Result<Vec<u8>, String> {
+    let envelope = Envelope {
+        sent_at: unix_now(),
+        payload,
+    };
+    let body = serde_json::to_vec(&envelope).map_err(|e| format!("序列化帧失败: {}", e))?;
+    if body.len() > MAX_FRAME {
+        return Err(format!("帧过大: {} 字节", body.len()));
+    }
+    let mut mac = HmacSha256::new_from_slice(key).map_err(|e| e.to_string())?;
+    mac.update(&body);
+    let tag = mac.finalize().into_bytes();
+
+    let mut encoded = Vec::with_capacity(4 + body.len() + TAG_LEN);
+    encoded.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    encoded.extend_from_slice(&body);
+    encoded.extend_from_slice(&tag);
+    Ok(encoded)
+}
+
+/// 读取并校验一帧（认证失败或发送时间偏差过大时返回错误）
+pub fn read<T: DeserializeOwned>(reader: &mut impl Read, key: &[u8]) -> Result<T, String> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(format!("帧过大: {} 字节", len));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    let mut tag = [0u8; TAG_LEN];
+    reader.read_exact(&mut tag).map_err(|e| e.to_string())?;
+
+    let mut mac = HmacSha256::new_from_slice(key).map_err(|e| e.to_string())?;
+    mac.update(&body);
+    mac.verify_slice(&tag)
+        .map_err(|_| "认证失败（密钥不一致？）".to_string())?;
+
+    let envelope: Envelope<T> =
+        serde_json::from_slice(&body).map_err(|e| format!("解析失败: {}", e))?;
+    if unix_now().abs_diff(envelope.sent_at) > MAX_CLOCK_SKEW {
+        return Err(format!(
+            "发送时间 {} 超出允许偏差，可能是重放",
+            envelope.sent_at
+        ));
+    }
+    Ok(envelope.payload)
+}
+
+/// 连接 `host:port`（支持主机名），并设置读写超时
+pub fn connect(addr: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let resolved = addr
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("无法解析地址")?;
+    let stream = TcpStream::connect_timeout(&resolved, timeout).map_err(|e| e.to_string())?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    Ok(stream)
+}
@@ -0,0 +1,208 @@
+//! 检测任务（Engine）的状态与运行循环
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uablock_rust::ingest::ExternalSignal;
+use uablock_rust::strikes::StrikeTracker;
+use uablock_rust::testing::{sip_request, udp_packet, MemoryFirewall};
+use uablock_rust::{Enforcer, Engine, EventBus, Pipeline, Policy, Stats, Whitelist};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn engine(firewall: &MemoryFirewall) -> Engine {
+    let enforcer = Arc::new(Enforcer::new(
+        Some(Box::new(firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    ));
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())))
+        .with_strikes(StrikeTracker::new(2.5, Duration::from_secs(300)));
+    Engine::new(Pipeline::builder(enforcer).policy(policy).build())
+}
+
+fn signal(source: &str) -> ExternalSignal {
+    ExternalSignal {
+        source_ip: ip(source),
+        user_agent: String::new(),
+        reason: "AUTH_FAILURE".to_string(),
+        weight: 1.0,
+        origin: "test".to_string(),
+    }
+}
+
+#[test]
+fn tracks_sources_of_sip_requests_only() {
+    let mut engine = engine(&MemoryFirewall::new());
+    engine.handle_packet(&udp_packet(
+        ip("198.51.100.7"),
+        sip_request("REGISTER", "MicroSIP/3.21.3", "a", 1),
+    ));
+    engine.handle_packet(&udp_packet(ip("198.51.100.8"), "\r\n\r\n"));
+
+    assert!(engine.last_seen(&ip("198.51.100.7")).is_some());
+    assert!(engine.last_seen(&ip("198.51.100.8")).is_none());
+    assert_eq!(engine.tracked_sources(), 1);
+}
+
+#[test]
+fn prunes_sources_idle_for_an_hour() {
+    let mut engine = engine(&MemoryFirewall::new());
+    engine.handle_packet(&udp_packet(
+        ip("198.51.100.7"),
+        sip_request("REGISTER", "MicroSIP/3.21.3", "a", 1),
+    ));
+
+    engine.prune(Instant::now() + Duration::from_secs(60));
+    assert_eq!(engine.tracked_sources(), 1);
+    engine.prune(Instant::now() + Duration::from_secs(3601));
+    assert_eq!(engine.tracked_sources(), 0);
+}
+
+#[test]
+fn signals_accumulate_strikes_until_ban() {
+    let firewall = MemoryFirewall::new();
+    let mut engine = engine(&firewall);
+    for _ in 0..3 {
+        engine.handle_signal(&signal("203.0.113.9"));
+    }
+    assert_eq!(firewall.blocked(), vec![ip("203.0.113.9")]);
+}
+
+#[test]
+fn source_table_is_shared_by_requests_and_strikes() {
+    let mut engine = engine(&MemoryFirewall::new());
+    let scanner = ip("203.0.113.9");
+    engine.handle_packet(&udp_packet(
+        scanner,
+        sip_request("OPTIONS", "MicroSIP/3.21.3", "a", 1),
+    ));
+    engine.handle_packet(&udp_packet(
+        scanner,
+        sip_request("REGISTER", "MicroSIP/3.21.3", "b", 1),
+    ));
+    engine.handle_signal(&signal("203.0.113.9"));
+    let sources = engine.pipeline().policy().sources().clone();
+    let state = sources.get(&scanner).unwrap();
+    assert_eq!(state.requests, 1);
+    assert_eq!(state.strikes.map(|(score, _)| score), Some(1.0));
+
+    engine.handle_signal(&signal("203.0.113.9"));
+    engine.handle_signal(&signal("203.0.113.9"));
+    let state = sources.get(&scanner).unwrap();
+    assert_eq!((state.detections, state.bans), (1, 1));
+    assert_eq!(state.strikes, None);
+
+    // 多个线程同时更新不同来源
+    let threads: Vec<_> = (0..4u8)
+        .map(|n| {
+            let sources = sources.clone();
+            std::thread::spawn(move || {
+                for host in 0..=255u8 {
+                    sources.update(IpAddr::from([10, 0, n, host]), |s| s.requests += 1);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(sources.len(), 1 + 4 * 256);
+}
+
+#[test]
+fn first_tick_does_not_run_maintenance() {
+    let mut engine = engine(&MemoryFirewall::new());
+    assert!(!engine.tick());
+}
+
+#[tokio::test]
+async fn run_processes_queued_input_until_shutdown() {
+    let firewall = MemoryFirewall::new();
+    let (packet_tx, packet_rx) = tokio::sync::mpsc::channel(16);
+    let (signal_tx, signal_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let task = tokio::spawn(engine(&firewall).run(packet_rx, signal_rx, async {
+        let _ = stop_rx.await;
+    }));
+
+    packet_tx
+        .send(udp_packet(
+            ip("203.0.113.9"),
+            sip_request("INVITE", "friendly-scanner", "b", 1),
+        ))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        signal_tx.send(signal("203.0.113.10")).unwrap();
+    }
+    for _ in 0..100 {
+        if firewall.blocked().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        firewall.blocked(),
+        vec![ip("203.0.113.9"), ip("203.0.113.10")]
+    );
+
+    stop_tx.send(()).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn run_stops_when_packet_source_closes() {
+    let (packet_tx, packet_rx) = tokio::sync::mpsc::channel(1);
+    let (_signal_tx, signal_rx) = tokio::sync::mpsc::unbounded_channel();
+    drop(packet_tx);
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        engine(&MemoryFirewall::new()).run(packet_rx, signal_rx, std::future::pending()),
+    )
+    .await
+    .expect("数据包通道关闭后应退出");
+}
+
+#[tokio::test]
+async fn handover_snapshot_restores_strikes_and_sources() {
+    let path = std::env::temp_dir().join(format!("uablock-handover-{}.json", std::process::id()));
+    let (packet_tx, packet_rx) = tokio::sync::mpsc::channel(16);
+    let (signal_tx, signal_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (handover_tx, handover_rx) = tokio::sync::oneshot::channel::<()>();
+    let task = tokio::spawn(
+        engine(&MemoryFirewall::new())
+            .handover_on(&path, async {
+                let _ = handover_rx.await;
+            })
+            .run(packet_rx, signal_rx, std::future::pending()),
+    );
+
+    packet_tx
+        .send(udp_packet(
+            ip("198.51.100.7"),
+            sip_request("REGISTER", "MicroSIP/3.21.3", "c", 1),
+        ))
+        .await
+        .unwrap();
+    signal_tx.send(signal("203.0.113.9")).unwrap();
+    // 交接前等待输入被消费
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handover_tx.send(()).unwrap();
+    task.await.unwrap();
+
+    let state = uablock_rust::state_file::StateFile::new(&path)
+        .load()
+        .unwrap()
+        .expect("交接时应写入快照");
+    std::fs::remove_file(&path).unwrap();
+
+    let mut upgraded = engine(&MemoryFirewall::new());
+    upgraded.restore(&state);
+    assert!(upgraded.last_seen(&ip("198.51.100.7")).is_some());
+    let score = upgraded.pipeline().policy().score(&ip("203.0.113.9"));
+    assert!((0.9..=1.0).contains(&score), "恢复后的惩罚分: {}", score);
+}
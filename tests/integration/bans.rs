@@ -0,0 +1,72 @@
+use crate::harness::Testbed;
+use std::net::Ipv4Addr;
+use uablock_rust::testing::sip_request;
+
+const SCANNER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 10);
+const PHONE: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 11);
+/// 用作同步点：检测按到达顺序处理，标记来源被封禁说明之前的数据包都已处理完
+const MARKER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 99);
+
+fn register(user_agent: &str, call_id: &str) -> Vec<u8> {
+    sip_request("REGISTER", user_agent, call_id, 1).into_bytes()
+}
+
+/// 等待此前注入的数据包全部处理完
+fn settle(testbed: &Testbed) {
+    testbed.inject(MARKER, &register("friendly-scanner", "marker"));
+    testbed.wait_until("标记来源被封禁", || testbed.is_banned(MARKER));
+}
+
+#[test]
+fn scanner_rule_appears_and_disappears_on_whitelisted_request() {
+    let testbed = Testbed::start();
+
+    testbed.inject(SCANNER, &register("friendly-scanner", "scan-1"));
+    testbed.wait_until("扫描器被封禁", || testbed.is_banned(SCANNER));
+
+    // 封禁只针对 SIP 端口的入站流量，抓包仍能看到被封禁来源的请求
+    testbed.inject(SCANNER, &register("MicroSIP/3.21.3", "scan-2"));
+    testbed.wait_until("白名单 UA 解封", || !testbed.is_banned(SCANNER));
+}
+
+#[test]
+fn whitelisted_phone_is_never_banned() {
+    let testbed = Testbed::start();
+
+    testbed.inject(PHONE, &register("MicroSIP/3.21.3", "phone-1"));
+    testbed.inject(
+        PHONE,
+        &sip_request("INVITE", "MicroSIP/3.21.3", "phone-2", 1).into_bytes(),
+    );
+    settle(&testbed);
+    testbed.wait_for_log(&format!("来源 IP: {}", PHONE));
+    assert!(!testbed.is_banned(PHONE));
+}
+
+#[test]
+fn malformed_flood_is_banned() {
+    let testbed = Testbed::start();
+
+    // 默认阈值 5 分，惩罚分随时间衰减，多发几个
+    for _ in 0..7 {
+        testbed.inject(
+            SCANNER,
+            b"REGISTER sip:100@10.199.0.2 SIP/2.0\r\nVia: x\r\n",
+        );
+    }
+    testbed.wait_until("畸形报文来源被封禁", || testbed.is_banned(SCANNER));
+}
+
+#[test]
+fn non_sip_traffic_is_ignored() {
+    let testbed = Testbed::start();
+
+    testbed.inject(PHONE, b"\r\n\r\n");
+    testbed.inject(PHONE, b"\x00\x01binary");
+    testbed.inject(
+        PHONE,
+        &sip_request("OPTIONS", "friendly-scanner", "ping", 1).into_bytes(),
+    );
+    settle(&testbed);
+    assert!(!testbed.is_banned(PHONE));
+}
@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::Ipv4Addr;
+use std::os::fd::AsRawFd;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uablock_rust::testing::ipv4_datagram;
+
+/// 攻击方 veth 地址
+const ATTACKER_ADDR: &str = "10.199.0.1/24";
+/// 被保护主机 veth 地址
+const HOST_ADDR: Ipv4Addr = Ipv4Addr::new(10, 199, 0, 2);
+const HOST_PREFIX: &str = "10.199.0.2/24";
+pub const SIP_PORT: u16 = 5060;
+
+/// 等待守护进程启动、规则出现/消失的超时
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// 一对通过 veth 相连的网络命名空间，以及在被保护主机中运行的守护进程
+///
+/// 析构时停止守护进程并删除命名空间（veth 和 iptables 规则随之删除）。
+pub struct Testbed {
+    attacker: String,
+    host: String,
+    daemon: Option<Daemon>,
+}
+
+impl Testbed {
+    /// 创建命名空间和 veth，并在被保护主机中启动守护进程
+    pub fn start() -> Self {
+        assert!(
+            unsafe { libc::geteuid() } == 0,
+            "netns 集成测试需要 root 权限"
+        );
+        let id = format!(
+            "{}x{}",
+            std::process::id() % 100_000,
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut testbed = Self {
+            attacker: format!("uablock-a{}", id),
+            host: format!("uablock-h{}", id),
+            daemon: None,
+        };
+        let attacker_if = format!("ua{}a", id);
+        let host_if = format!("ua{}h", id);
+
+        ip(&["netns", "add", &testbed.attacker]);
+        ip(&["netns", "add", &testbed.host]);
+        ip(&[
+            "link",
+            "add",
+            &attacker_if,
+            "netns",
+            &testbed.attacker,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            &host_if,
+            "netns",
+            &testbed.host,
+        ]);
+        for (ns, iface, addr) in [
+            (&testbed.attacker, &attacker_if, ATTACKER_ADDR),
+            (&testbed.host, &host_if, HOST_PREFIX),
+        ] {
+            ip(&["-n", ns, "addr", "add", addr, "dev", iface]);
+            ip(&["-n", ns, "link", "set", iface, "up"]);
+            ip(&["-n", ns, "link", "set", "lo", "up"]);
+        }
+
+        testbed.daemon = Some(Daemon::start(&testbed.host, &host_if));
+        testbed
+    }
+
+    /// 从攻击方命名空间发送一个 UDP 数据包到被保护主机的 SIP 端口，源 IP 可任意伪造
+    pub fn inject(&self, source: Ipv4Addr, payload: &[u8]) {
+        let netns = File::open(format!("/var/run/netns/{}", self.attacker))
+            .expect("无法打开攻击方命名空间");
+        let datagram = ipv4_datagram(source, HOST_ADDR, SIP_PORT, SIP_PORT, payload);
+        // setns 只影响调用线程，在独立线程中切换命名空间
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| unsafe {
+                    assert_eq!(
+                        libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET),
+                        0,
+                        "切换到攻击方命名空间失败"
+                    );
+                    let fd = libc::socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW);
+                    assert!(fd >= 0, "创建原始套接字失败");
+                    let dest = libc::sockaddr_in {
+                        sin_family: libc::AF_INET as libc::sa_family_t,
+                        sin_port: 0,
+                        sin_addr: libc::in_addr {
+                            s_addr: u32::from_ne_bytes(HOST_ADDR.octets()),
+                        },
+                        sin_zero: [0; 8],
+                    };
+                    let sent = libc::sendto(
+                        fd,
+                        datagram.as_ptr() as *const libc::c_void,
+                        datagram.len(),
+                        0,
+                        &dest as *const libc::sockaddr_in as *const libc::sockaddr,
+                        std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    );
+                    libc::close(fd);
+                    assert_eq!(sent, datagram.len() as isize, "发送数据包失败");
+                })
+                .join()
+                .unwrap();
+        });
+    }
+
+    /// 被保护主机中是否存在封禁该 IP 的规则
+    pub fn is_banned(&self, source: Ipv4Addr) -> bool {
+        let output = Command::new("ip")
+            .args(["netns", "exec", &self.host, "iptables", "-S", "UABLOCK"])
+            .output()
+            .expect("无法执行 iptables");
+        assert!(output.status.success(), "iptables -S 执行失败");
+        let rule = format!("-s {}/32", source);
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.contains(&rule) && line.ends_with("-j DROP"))
+    }
+
+    /// 等待条件成立，超时则附带守护进程日志失败
+    pub fn wait_until(&self, what: &str, condition: impl Fn() -> bool) {
+        let started = Instant::now();
+        while started.elapsed() < WAIT_TIMEOUT {
+            if condition() {
+                return;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        panic!("等待超时: {}\n守护进程日志:\n{}", what, self.log());
+    }
+
+    /// 等待守护进程输出包含 `needle` 的日志
+    pub fn wait_for_log(&self, needle: &str) {
+        self.wait_until(&format!("日志包含 '{}'", needle), || {
+            self.log().contains(needle)
+        });
+    }
+
+    fn log(&self) -> String {
+        self.daemon
+            .as_ref()
+            .map(|daemon| daemon.log.lock().unwrap().join("\n"))
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Testbed {
+    fn drop(&mut self) {
+        drop(self.daemon.take());
+        for ns in [&self.attacker, &self.host] {
+            let _ = Command::new("ip").args(["netns", "del", ns]).status();
+        }
+    }
+}
+
+/// 在被保护主机命名空间中运行的 uablock-rust
+struct Daemon {
+    child: Child,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Daemon {
+    fn start(netns: &str, iface: &str) -> Self {
+        let mut command = Command::new("ip");
+        command
+            .args(["netns", "exec", netns, env!("CARGO_BIN_EXE_uablock-rust")])
+            .args([iface, &SIP_PORT.to_string()])
+            .env("RUST_LOG", "debug")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        // 不继承运行测试的环境中的配置
+        for (key, _) in std::env::vars() {
+            if key.starts_with("UABLOCK_") || key == "SIP_UA_WHITELIST" {
+                command.env_remove(key);
+            }
+        }
+        let mut child = command.spawn().expect("无法启动 uablock-rust");
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let stderr = child.stderr.take().unwrap();
+        let sink = log.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                sink.lock().unwrap().push(line);
+            }
+        });
+
+        let daemon = Self { child, log };
+        let started = Instant::now();
+        while started.elapsed() < WAIT_TIMEOUT {
+            if daemon
+                .log
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|l| l.contains("开始监控 SIP 流量"))
+            {
+                return daemon;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        panic!(
+            "uablock-rust 未能启动:\n{}",
+            daemon.log.lock().unwrap().join("\n")
+        );
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        // `ip netns exec` 直接 exec 目标程序，子进程就是守护进程本身
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let started = Instant::now();
+        while started.elapsed() < WAIT_TIMEOUT {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn ip(args: &[&str]) {
+    let status = Command::new("ip")
+        .args(args)
+        .status()
+        .expect("无法执行 ip 命令（需要 iproute2）");
+    assert!(status.success(), "ip {} 执行失败", args.join(" "));
+}
@@ -0,0 +1,14 @@
+//! 基于网络命名空间的集成测试
+//!
+//! 每个测试创建一对网络命名空间（攻击方 / 被保护主机），用 veth 连接；在被保护主机的命名空间中
+//! 运行 `uablock-rust`，从攻击方用原始套接字注入构造的 SIP 数据包（可伪造源 IP），
+//! 然后检查被保护主机命名空间中的 iptables 规则。测试之间互不影响，也不会改动宿主机的防火墙。
+//!
+//! 需要 root 权限以及 iproute2、iptables、libpcap：
+//!
+//! ```bash
+//! sudo -E cargo test --features netns-tests --test integration
+//! ```
+
+mod bans;
+mod harness;
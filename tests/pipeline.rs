@@ -0,0 +1,2474 @@
+//! 检测 → 封禁 → 解封流程的端到端测试（内存数据包来源和防火墙，无需 root）
+
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uablock_rust::authz::{Authz, Decision};
+use uablock_rust::ban_check::{BanCheck, BAN_INEFFECTIVE};
+use uablock_rust::ban_latency::{LatencyAlert, BAN_LATENCY_HIGH};
+use uablock_rust::bans::{parse_duration, BanReason};
+use uablock_rust::campaigns::Campaigns;
+use uablock_rust::config::Config;
+use uablock_rust::enforcement_queue::{Action, EnforcementQueue};
+use uablock_rust::failover::{FailureAction, GuardedFirewall};
+use uablock_rust::greylist::Greylist;
+use uablock_rust::ha::HaRole;
+use uablock_rust::ingest::ExternalSignal;
+use uablock_rust::local_net::{HostAddresses, LocalNetworks, Subnet};
+use uablock_rust::method_rate::MethodRateLimiter;
+use uablock_rust::noise::NoiseKind;
+use uablock_rust::packet_capture::{
+    decode_packet, filter_expression, parse_ports, spawn_interface_reader, spawn_reader,
+    spawn_reader_with_ports, CapturePorts,
+};
+use uablock_rust::reason::ReasonCode;
+use uablock_rust::registered::RegisteredEndpoints;
+use uablock_rust::rule_audit::{RuleAudit, RULE_MISSING};
+use uablock_rust::rules::RulesEngine;
+use uablock_rust::sources::SourceTable;
+use uablock_rust::state_file::{
+    GreylistRecord, ReleaseRecord, RuleCounterRecord, StateFile, StrikeRecord, UaDenyRecord,
+    UaWindowRecord,
+};
+use uablock_rust::strikes::StrikeTracker;
+use uablock_rust::testing::{
+    hep_packet, ipv4_datagram, sip_request, udp_packet, MemoryFirewall, MemorySource,
+};
+use uablock_rust::trace::{read_trace, replay, TraceRecorder};
+use uablock_rust::ua_rate::UaRateLimiter;
+use uablock_rust::verification::{Verifier, AUTH_SUCCESS};
+use uablock_rust::whitelist::ListMode;
+use uablock_rust::{
+    CapturedPacket, Detection, Enforcer, Event, EventBus, EventKind, FirewallBackend,
+    PacketOutcome, PacketSource, Pipeline, Policy, Stats, Tenants, UdpSource, Verdict, Whitelist,
+};
+
+const SCANNER: &str = "203.0.113.9";
+const PHONE: &str = "198.51.100.7";
+
+struct Harness {
+    pipeline: Pipeline,
+    firewall: MemoryFirewall,
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl Harness {
+    /// 默认白名单；惩罚分随时间衰减，阈值 2.5 即第三个畸形报文触发封禁
+    fn new() -> Self {
+        let firewall = MemoryFirewall::new();
+        let bus = Arc::new(EventBus::new());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        bus.subscribe(move |event| {
+            sink.lock().unwrap().push(event.clone());
+            true
+        });
+        let enforcer = Arc::new(Enforcer::new(
+            Some(Box::new(firewall.clone())),
+            None,
+            Arc::new(Stats::default()),
+            bus,
+        ));
+        let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())))
+            .with_strikes(StrikeTracker::new(2.5, Duration::from_secs(300)));
+        Self {
+            pipeline: Pipeline::builder(enforcer).policy(policy).build(),
+            firewall,
+            events,
+        }
+    }
+
+    fn send(&mut self, source: &str, payload: impl Into<Vec<u8>>) -> PacketOutcome {
+        self.pipeline.process(&udp_packet(ip(source), payload))
+    }
+
+    fn register(&mut self, source: &str, user_agent: &str, call_id: &str) -> PacketOutcome {
+        self.send(source, sip_request("REGISTER", user_agent, call_id, 1))
+    }
+
+    fn event_kinds(&self) -> Vec<(EventKind, IpAddr)> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e.kind, e.ip))
+            .collect()
+    }
+
+    fn stat(&self, counter: fn(&Stats) -> &std::sync::atomic::AtomicU64) -> u64 {
+        counter(self.pipeline.enforcer().stats()).load(Ordering::Relaxed)
+    }
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn bans_source_with_unknown_user_agent() {
+    let mut h = Harness::new();
+    let outcome = h.register(SCANNER, "friendly-scanner", "a1");
+
+    assert!(matches!(
+        outcome,
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. } if d.reason == "UA_NOT_ALLOWED"
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(
+        h.event_kinds(),
+        vec![
+            (EventKind::Detection, ip(SCANNER)),
+            (EventKind::Ban, ip(SCANNER))
+        ]
+    );
+    assert_eq!(h.stat(|s| &s.bans), 1);
+}
+
+#[test]
+fn allows_whitelisted_user_agent() {
+    let mut h = Harness::new();
+    let outcome = h.register(PHONE, "MicroSIP/3.21.3", "b1");
+
+    assert!(matches!(
+        outcome,
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    assert!(h.firewall.blocked().is_empty());
+    assert!(h.event_kinds().is_empty());
+}
+
+#[test]
+fn unbans_source_once_whitelisted_user_agent_is_seen() {
+    let mut h = Harness::new();
+    h.register(PHONE, "sipcli/v1.8", "c1");
+    assert_eq!(h.firewall.blocked(), vec![ip(PHONE)]);
+
+    h.register(PHONE, "MicroSIP/3.21.3", "c2");
+    assert!(h.firewall.blocked().is_empty());
+    assert_eq!(h.event_kinds().last(), Some(&(EventKind::Unban, ip(PHONE))));
+    assert_eq!(h.stat(|s| &s.unbans), 1);
+}
+
+#[test]
+fn does_not_ban_already_banned_source_twice() {
+    let mut h = Harness::new();
+    h.register(SCANNER, "friendly-scanner", "d1");
+    h.register(SCANNER, "friendly-scanner", "d2");
+
+    assert_eq!(h.stat(|s| &s.detections), 2);
+    assert_eq!(h.stat(|s| &s.bans), 1);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn banned_sources_are_counted_without_reevaluation() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())));
+    h.pipeline = Pipeline::builder(enforcer)
+        .policy(policy)
+        .suppress_banned(Duration::from_secs(60))
+        .build();
+
+    h.register(SCANNER, "friendly-scanner", "s1");
+    for call_id in ["s2", "s3", "s4"] {
+        assert!(matches!(
+            h.register(SCANNER, "friendly-scanner", call_id),
+            PacketOutcome::Cached
+        ));
+    }
+    assert!(matches!(
+        h.send(SCANNER, "INVITE sip:100@192.0.2.1\r\n\r\n"),
+        PacketOutcome::Cached
+    ));
+    assert_eq!(h.stat(|s| &s.detections), 1);
+    assert_eq!(h.stat(|s| &s.banned_hits), 4);
+    assert_eq!(
+        h.pipeline.enforcer().bans().get(&ip(SCANNER)).unwrap().hits,
+        4
+    );
+
+    // 白名单 UA 仍然解封
+    h.register(SCANNER, "MicroSIP/3.21.3", "s5");
+    assert!(h.firewall.blocked().is_empty());
+
+    // 规则被外部删除后恢复正常判定
+    h.register(SCANNER, "friendly-scanner", "s6");
+    h.firewall.unblock_ip(&ip(SCANNER)).unwrap();
+    h.register(SCANNER, "friendly-scanner", "s7");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn queued_rule_checks_restore_processing_after_external_removal() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    enforcer.start_queue(16).unwrap();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())));
+    h.pipeline = Pipeline::builder(enforcer.clone())
+        .policy(policy)
+        .suppress_banned(Duration::from_secs(60))
+        .build();
+    let wait = |done: &dyn Fn() -> bool| {
+        for _ in 0..100 {
+            if done() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    h.register(SCANNER, "friendly-scanner", "q1");
+    wait(&|| !h.firewall.blocked().is_empty());
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 规则被外部删除：检测任务不查询防火墙，复核交给执行线程，之后恢复正常判定
+    h.firewall.unblock_ip(&ip(SCANNER)).unwrap();
+    assert!(matches!(
+        h.register(SCANNER, "friendly-scanner", "q2"),
+        PacketOutcome::Cached
+    ));
+    wait(&|| enforcer.bans().get(&ip(SCANNER)).is_none());
+    assert!(enforcer.bans().get(&ip(SCANNER)).is_none());
+    h.register(SCANNER, "friendly-scanner", "q3");
+    wait(&|| !h.firewall.blocked().is_empty());
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn message_floods_are_banned_per_source_and_method() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_method_limiter(
+        MethodRateLimiter::new(Duration::from_secs(60)).with_limit("MESSAGE", 3),
+    );
+    h.pipeline = Pipeline::builder(enforcer).policy(policy).build();
+
+    // 未限速的方法和白名单 UA 不计数
+    for call_id in ["n1", "n2", "n3", "n4", "n5"] {
+        h.send(SCANNER, sip_request("NOTIFY", "spitter", call_id, 1));
+        h.send(PHONE, sip_request("MESSAGE", "MicroSIP/3.21.3", call_id, 1));
+    }
+    assert!(h.firewall.blocked().is_empty());
+
+    for call_id in ["m1", "m2", "m3"] {
+        assert!(matches!(
+            h.send(SCANNER, sip_request("MESSAGE", "spitter", call_id, 1)),
+            PacketOutcome::Ignored
+        ));
+    }
+    // 重传不计数
+    h.send(SCANNER, sip_request("MESSAGE", "spitter", "m3", 1));
+    assert!(h.firewall.blocked().is_empty());
+
+    let outcome = h.send(SCANNER, sip_request("MESSAGE", "spitter", "m4", 1));
+    assert!(matches!(
+        outcome,
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. } if d.reason == "MESSAGE_FLOOD"
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(h.stat(|s| &s.retransmissions), 1);
+}
+
+#[test]
+fn binary_noise_is_classified_and_sustained_junk_is_banned() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())))
+        .with_strikes(StrikeTracker::new(2.5, Duration::from_secs(300)))
+        .with_noise_weight(1.0);
+    h.pipeline = Pipeline::builder(enforcer).policy(policy).build();
+
+    let mut stun = vec![0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42];
+    stun.extend([7; 12]);
+    let mut rtp = vec![0x80, 0x00, 0x12, 0x34, 0, 0, 0, 160, 0xde, 0xad, 0xbe, 0xef];
+    rtp.extend([0xff; 160]);
+    for _ in 0..3 {
+        assert!(matches!(
+            h.send(PHONE, stun.clone()),
+            PacketOutcome::Noise(NoiseKind::Stun)
+        ));
+        assert!(matches!(
+            h.send(PHONE, rtp.clone()),
+            PacketOutcome::Noise(NoiseKind::Rtp)
+        ));
+    }
+    // NAT 保活的空行是文本，不属于非 SIP 数据
+    assert!(matches!(h.send(PHONE, "\r\n\r\n"), PacketOutcome::Ignored));
+    assert!(h.firewall.blocked().is_empty());
+
+    for _ in 0..3 {
+        assert!(matches!(
+            h.send(SCANNER, vec![0x17, 0x03, 0x00, 0xff, 0x10]),
+            PacketOutcome::Noise(NoiseKind::Binary)
+        ));
+    }
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(h.stat(|s| &s.noise_stun), 3);
+    assert_eq!(h.stat(|s| &s.noise_rtp), 3);
+    assert_eq!(h.stat(|s| &s.noise_binary), 3);
+    assert_eq!(h.pipeline.enforcer().stats().ports()[&5060].noise, 9);
+}
+
+#[test]
+fn ignores_retransmissions_of_the_same_transaction() {
+    let mut h = Harness::new();
+    let request = sip_request("REGISTER", "friendly-scanner", "e1", 1);
+    h.send(SCANNER, request.clone());
+    let outcome = h.send(SCANNER, request);
+
+    assert!(matches!(outcome, PacketOutcome::Retransmission));
+    assert_eq!(h.stat(|s| &s.detections), 1);
+    assert_eq!(h.stat(|s| &s.retransmissions), 1);
+}
+
+#[test]
+fn bans_after_repeated_malformed_packets() {
+    let mut h = Harness::new();
+    for _ in 0..2 {
+        assert!(matches!(
+            h.send(SCANNER, "REGISTER sip:100@192.0.2.1 SIP/2.0\r\nVia: x\r\n"),
+            PacketOutcome::Malformed
+        ));
+    }
+    assert!(h.firewall.blocked().is_empty());
+
+    h.send(SCANNER, "INVITE sip:100@192.0.2.1\r\n\r\n");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(h.stat(|s| &s.malformed), 3);
+}
+
+#[test]
+fn fragmented_and_truncated_invites_from_phones_are_not_banned() {
+    let mut h = Harness::new();
+    let sdp = "a=rtpmap:0 PCMU/8000\r\n".repeat(100);
+    let invite = |user_agent: &str, call_id: &str| {
+        format!(
+            "INVITE sip:200@192.0.2.1 SIP/2.0\r\n\
+             Via: SIP/2.0/UDP 198.51.100.7:5060;branch=z9hG4bK-{call_id}\r\n\
+             From: <sip:100@192.0.2.1>;tag=1\r\n\
+             To: <sip:200@192.0.2.1>\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: 1 INVITE\r\n\
+             User-Agent: {user_agent}\r\n\
+             Content-Type: application/sdp\r\n\
+             Content-Length: {}\r\n\
+             \r\n{sdp}",
+            sdp.len()
+        )
+    };
+    let phone_invite = invite("MicroSIP/3.21.3", "big-1");
+    assert!(phone_invite.len() > 1500);
+
+    // 超过 MTU 的 INVITE 被分片：首个分片（MF 置位）和之后的分片（偏移不为 0）都不交给检测
+    let datagram = ipv4_datagram(
+        PHONE.parse().unwrap(),
+        "192.0.2.1".parse().unwrap(),
+        5060,
+        5060,
+        phone_invite.as_bytes(),
+    );
+    let frame = |flags_offset: [u8; 2], ip: &[u8]| {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(ip);
+        frame[20..22].copy_from_slice(&flags_offset);
+        frame
+    };
+    assert!(decode_packet(&frame([0x00, 0x00], &datagram)).is_some());
+    assert!(decode_packet(&frame([0x20, 0x00], &datagram[..1500])).is_none());
+    assert!(decode_packet(&frame([0x00, 0xb9], &datagram[1480..])).is_none());
+
+    // 消息体短于 Content-Length（在路径上被截断）不算畸形，白名单话机照常放行，重传照常去重
+    let truncated = &phone_invite.as_bytes()[..1400];
+    assert!(matches!(
+        h.send(PHONE, truncated),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    for _ in 0..4 {
+        assert!(matches!(
+            h.send(PHONE, truncated),
+            PacketOutcome::Retransmission
+        ));
+    }
+    // 头部残缺的报文计为畸形，但白名单 UA 不累计惩罚分
+    let cut = |invite: String| invite[..invite.find("Content-Type").unwrap()].to_string();
+    for _ in 0..5 {
+        assert!(matches!(
+            h.send(PHONE, cut(phone_invite.clone())),
+            PacketOutcome::Malformed
+        ));
+    }
+    assert!(h.firewall.blocked().is_empty());
+    assert_eq!(h.stat(|s| &s.malformed), 5);
+
+    // 其他 UA 的畸形报文先做重传去重：同一请求的重传只计一次惩罚分
+    let scanner_invite = cut(invite("friendly-scanner", "cut-1"));
+    assert!(matches!(
+        h.send(SCANNER, scanner_invite.clone()),
+        PacketOutcome::Malformed
+    ));
+    for _ in 0..5 {
+        assert!(matches!(
+            h.send(SCANNER, scanner_invite.clone()),
+            PacketOutcome::Retransmission
+        ));
+    }
+    assert!(h.firewall.blocked().is_empty());
+    h.send(SCANNER, cut(invite("friendly-scanner", "cut-2")));
+    h.send(SCANNER, cut(invite("friendly-scanner", "cut-3")));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn ignores_non_sip_traffic() {
+    let mut h = Harness::new();
+    assert!(matches!(
+        h.send(SCANNER, "\r\n\r\n"),
+        PacketOutcome::Ignored
+    ));
+    assert!(matches!(
+        h.send(SCANNER, sip_request("OPTIONS", "friendly-scanner", "f1", 1)),
+        PacketOutcome::Ignored
+    ));
+    assert!(h.firewall.blocked().is_empty());
+}
+
+#[test]
+fn failed_block_is_not_counted_as_ban() {
+    let mut h = Harness::new();
+    h.firewall.fail_with(Some("backend unavailable"));
+    h.register(SCANNER, "friendly-scanner", "g1");
+
+    assert!(h.firewall.blocked().is_empty());
+    assert_eq!(h.stat(|s| &s.detections), 1);
+    assert_eq!(h.stat(|s| &s.bans), 0);
+
+    // 后端恢复后，下一次检测正常封禁
+    h.firewall.fail_with(None);
+    h.register(SCANNER, "friendly-scanner", "g2");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn failing_backend_degrades_and_switches_to_fallback() {
+    let primary = MemoryFirewall::new();
+    let fallback = MemoryFirewall::new();
+    let stats = Arc::new(Stats::default());
+    let firewall = GuardedFirewall::new(Box::new(primary.clone()), stats.clone())
+        .with_action(FailureAction::Fallback)
+        .with_fallback(Box::new(fallback.clone()))
+        .with_threshold(2);
+    let degraded = || stats.firewall_degraded.load(Ordering::Relaxed);
+
+    primary.block_ip(&ip(PHONE)).unwrap();
+    primary.fail_with(Some("xtables lock"));
+    assert!(firewall.block_ip(&ip(SCANNER)).is_err());
+    assert_eq!(degraded(), 0);
+    // 第二次失败达到阈值，本次封禁改由备用后端执行
+    firewall.block_ip(&ip(SCANNER)).unwrap();
+    assert!(firewall.is_on_fallback());
+    assert_eq!(fallback.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(degraded(), 1);
+    assert_eq!(stats.firewall_failures.load(Ordering::Relaxed), 2);
+
+    // 两个后端的封禁合并列出；切换前的封禁仍从原后端解除
+    let mut blocked = firewall.list_blocked().unwrap();
+    blocked.sort();
+    assert_eq!(blocked, vec![ip(PHONE), ip(SCANNER)]);
+    primary.fail_with(None);
+    firewall.unblock_ip(&ip(PHONE)).unwrap();
+    assert!(primary.blocked().is_empty());
+}
+
+#[test]
+fn alert_only_backend_recovers_after_a_success() {
+    let primary = MemoryFirewall::new();
+    let stats = Arc::new(Stats::default());
+    let firewall = GuardedFirewall::new(Box::new(primary.clone()), stats.clone()).with_threshold(1);
+
+    primary.fail_with(Some("read-only file system"));
+    assert!(firewall.block_ip(&ip(SCANNER)).is_err());
+    assert_eq!(stats.firewall_degraded.load(Ordering::Relaxed), 1);
+    primary.fail_with(None);
+    firewall.block_ip(&ip(SCANNER)).unwrap();
+    assert_eq!(stats.firewall_degraded.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn manual_ban_and_unban_go_through_the_same_enforcer() {
+    let h = Harness::new();
+    let enforcer = h.pipeline.enforcer();
+
+    assert_eq!(
+        enforcer.ban(ip(SCANNER), BanReason::new("MANUAL", "API")),
+        Ok(true)
+    );
+    assert_eq!(
+        enforcer.ban(ip(SCANNER), BanReason::new("MANUAL", "API")),
+        Ok(false)
+    );
+    assert_eq!(enforcer.list_bans(), Ok(vec![ip(SCANNER)]));
+    assert_eq!(enforcer.unban(ip(SCANNER), "MANUAL", "API"), Ok(true));
+    assert_eq!(enforcer.unban(ip(SCANNER), "MANUAL", "API"), Ok(false));
+    assert_eq!(
+        h.event_kinds(),
+        vec![
+            (EventKind::Ban, ip(SCANNER)),
+            (EventKind::Unban, ip(SCANNER))
+        ]
+    );
+}
+
+#[test]
+fn batch_unban_releases_only_bans_older_than_the_cutoff() {
+    let h = Harness::new();
+    let enforcer = h.pipeline.enforcer();
+    enforcer
+        .ban(ip(SCANNER), BanReason::new("MANUAL", "API"))
+        .unwrap();
+    enforcer
+        .ban(ip(PHONE), BanReason::new("MANUAL", "API"))
+        .unwrap();
+
+    assert_eq!(parse_duration("7d"), Some(Duration::from_secs(7 * 86400)));
+    assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("7"), None);
+
+    // 刚封禁的 IP 不会被大赦
+    let unbanned = enforcer
+        .unban_older_than(parse_duration("7d"), "AMNESTY", "amnesty")
+        .unwrap();
+    assert!(unbanned.is_empty());
+    assert_eq!(h.firewall.blocked().len(), 2);
+
+    let mut unbanned = enforcer.unban_older_than(None, "MANUAL", "API").unwrap();
+    unbanned.sort();
+    let mut expected = vec![ip(SCANNER), ip(PHONE)];
+    expected.sort();
+    assert_eq!(unbanned, expected);
+    assert!(h.firewall.blocked().is_empty());
+}
+
+#[test]
+fn whitelist_changes_apply_immediately() {
+    let mut h = Harness::new();
+    h.register(PHONE, "Acme-Phone/2.0", "h1");
+    assert_eq!(h.firewall.blocked(), vec![ip(PHONE)]);
+
+    h.pipeline
+        .policy()
+        .whitelist()
+        .lock()
+        .unwrap()
+        .add_pattern("acme-phone".to_string());
+    h.register(PHONE, "Acme-Phone/2.0", "h2");
+    assert!(h.firewall.blocked().is_empty());
+}
+
+#[test]
+fn decision_traces_replay_against_a_changed_config() {
+    let path = std::env::temp_dir().join(format!("uablock-trace-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let enforcer = Arc::new(Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    ));
+    let mut pipeline = Pipeline::builder(enforcer)
+        .trace(TraceRecorder::create(&path).unwrap())
+        .build();
+    pipeline.process(&udp_packet(
+        ip(PHONE),
+        sip_request("REGISTER", "MicroSIP/3.21.3", "t1", 1),
+    ));
+    pipeline.process(&udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "Acme-Phone/2.0", "t2", 1),
+    ));
+    pipeline.process(&udp_packet(ip(SCANNER), vec![0xff, 0x00, 0x0d, 0x0a]));
+    drop(pipeline);
+
+    let entries = read_trace(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].outcome.verdict, "allow");
+    assert_eq!(entries[1].outcome.verdict, "detect");
+    assert_eq!(entries[1].outcome.reason.as_deref(), Some("UA_NOT_ALLOWED"));
+
+    // 相同配置下重放结果一致
+    let mut h = Harness::new();
+    let outcomes = replay(&mut h.pipeline, &entries);
+    assert!(entries
+        .iter()
+        .zip(&outcomes)
+        .all(|(entry, outcome)| entry.outcome.same_decision(outcome)));
+
+    // 白名单加入该 UA 后，原来的封禁不再发生
+    let mut h = Harness::new();
+    h.pipeline
+        .policy()
+        .whitelist()
+        .lock()
+        .unwrap()
+        .add_pattern("acme-phone".to_string());
+    let outcomes = replay(&mut h.pipeline, &entries);
+    assert_eq!(outcomes[1].verdict, "allow");
+    assert!(h.firewall.blocked().is_empty());
+}
+
+#[test]
+fn memory_source_drives_the_pipeline() {
+    let mut h = Harness::new();
+    let mut source = MemorySource::new();
+    source
+        .push_payload(
+            ip(PHONE),
+            sip_request("REGISTER", "MicroSIP/3.21.3", "i1", 1),
+        )
+        .push_payload(
+            ip(SCANNER),
+            sip_request("INVITE", "friendly-scanner", "i2", 1),
+        )
+        .push_payload(ip(SCANNER), "\r\n\r\n");
+
+    while let Some(packet) = source.next_packet().unwrap() {
+        h.pipeline.process(&packet);
+    }
+    assert_eq!(h.stat(|s| &s.packets), 3);
+    assert_eq!(h.stat(|s| &s.sip_requests), 2);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn reader_thread_forwards_packets_and_stops_when_receiver_closes() {
+    let mut source = MemorySource::new();
+    source
+        .push_payload(ip(PHONE), "first")
+        .push_payload(ip(SCANNER), "second");
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    spawn_reader(source, tx.clone(), Arc::new(Stats::default())).unwrap();
+
+    let first = rx.blocking_recv().unwrap();
+    let second = rx.blocking_recv().unwrap();
+    assert_eq!(first.payload, b"first");
+    assert_eq!(second.source_ip, ip(SCANNER));
+
+    drop(rx);
+    // 接收端关闭后，读取线程持有的发送端随线程退出而释放
+    for _ in 0..100 {
+        if tx.strong_count() == 1 {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("读取线程未退出");
+}
+
+/// 记录端口更换的数据包来源（不产生数据包）
+struct PortRecorder(Arc<Mutex<Vec<Vec<u16>>>>);
+
+impl PacketSource for PortRecorder {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+        std::thread::sleep(Duration::from_millis(5));
+        Ok(None)
+    }
+
+    fn set_ports(&mut self, ports: &[u16]) -> Result<(), String> {
+        self.0.lock().unwrap().push(ports.to_vec());
+        Ok(())
+    }
+}
+
+#[test]
+fn capture_ports_are_applied_to_the_running_reader() {
+    assert_eq!(
+        parse_ports("5060, 5080\n# TLS\n5061 5060").unwrap(),
+        vec![5060, 5061, 5080]
+    );
+    assert!(parse_ports("5060,abc").is_err());
+    assert!(parse_ports("# 空").is_err());
+    assert_eq!(
+        filter_expression(&[5060, 5080]),
+        "udp and (dst port 5060 or dst port 5080)"
+    );
+
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let ports = Arc::new(CapturePorts::new(vec![5060]));
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    spawn_reader_with_ports(
+        PortRecorder(applied.clone()),
+        tx,
+        Arc::new(Stats::default()),
+        Some(ports.clone()),
+    )
+    .unwrap();
+
+    // 启动时的端口与来源打开时相同，不重复应用
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(applied.lock().unwrap().is_empty());
+    assert!(!ports.set(vec![5060]).unwrap());
+    assert!(ports.set(vec![5080, 5060, 5080]).unwrap());
+    assert!(ports.set(Vec::new()).is_err());
+    for _ in 0..100 {
+        if !applied.lock().unwrap().is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*applied.lock().unwrap(), vec![vec![5060, 5080]]);
+    assert_eq!(ports.reload(), Ok(false));
+    drop(rx);
+}
+
+#[test]
+fn udp_source_accepts_direct_requests_and_hep_from_relays() {
+    let mut h = Harness::new();
+    let mut source =
+        UdpSource::bind("127.0.0.1:0".parse().unwrap(), 5060, vec![ip("127.0.0.1")]).unwrap();
+    let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = source.local_addr().unwrap();
+
+    // 直接发来的请求以对端地址为来源
+    let request = sip_request("REGISTER", "MicroSIP/3.21.3", "u1", 1);
+    sender.send_to(request.as_bytes(), target).unwrap();
+    let direct = source.next_packet().unwrap().unwrap();
+    assert_eq!(direct.source_ip, ip("127.0.0.1"));
+    assert_eq!(direct.payload, request.as_bytes());
+
+    // 转发的请求以 HEP 头中的原始来源为准，目标端口不符的忽略
+    let scan = sip_request("INVITE", "friendly-scanner", "u2", 1);
+    sender
+        .send_to(&hep_packet(SCANNER, 5080, &scan), target)
+        .unwrap();
+    assert!(source.next_packet().unwrap().is_none());
+    sender
+        .send_to(&hep_packet(SCANNER, 5060, &scan), target)
+        .unwrap();
+    let relayed = source.next_packet().unwrap().unwrap();
+    assert_eq!(relayed.source_ip, ip(SCANNER));
+    assert_eq!(relayed.source_port, 5062);
+
+    h.pipeline.process(&relayed);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn queued_enforcement_bans_in_the_background() {
+    let h = Harness::new();
+    let enforcer = h.pipeline.enforcer();
+    enforcer.start_queue(16).unwrap();
+
+    let detection = Detection::from_source(ip(SCANNER), "UA_NOT_ALLOWED");
+    assert!(enforcer.handle_detection(&detection));
+    for _ in 0..100 {
+        if !h.firewall.blocked().is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    assert_eq!(h.stat(|s| &s.bans), 1);
+}
+
+#[test]
+fn decision_cache_skips_recently_banned_sources() {
+    let mut h = Harness::new();
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .decision_cache(Duration::from_secs(60))
+        .build();
+
+    h.register(SCANNER, "friendly-scanner", "c1");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    for i in 2..5 {
+        let outcome = h.register(SCANNER, "friendly-scanner", &format!("c{}", i));
+        assert!(matches!(outcome, PacketOutcome::Cached));
+    }
+    assert_eq!(h.stat(|s| &s.packets), 4);
+    assert_eq!(h.stat(|s| &s.detections), 1);
+    assert_eq!(h.stat(|s| &s.decision_cache_hits), 3);
+
+    // 白名单来源在有效期内不再重复检查解封
+    h.register(PHONE, "MicroSIP/3.21.3", "c5");
+    h.register(PHONE, "MicroSIP/3.21.3", "c6");
+    assert_eq!(h.stat(|s| &s.decision_cache_hits), 4);
+}
+
+#[test]
+fn decision_cache_does_not_hide_failed_bans() {
+    let mut h = Harness::new();
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .decision_cache(Duration::from_secs(60))
+        .build();
+
+    h.firewall
+        .fail_with(Some("iptables: Resource temporarily unavailable"));
+    h.register(SCANNER, "friendly-scanner", "f1");
+    h.firewall.fail_with(None);
+    h.register(SCANNER, "friendly-scanner", "f2");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn ban_records_explain_why_each_ip_is_banned() {
+    let mut h = Harness::new();
+    h.register(SCANNER, "friendly-scanner", "r1");
+    h.register(SCANNER, "friendly-scanner", "r2");
+    let enforcer = h.pipeline.enforcer().clone();
+    enforcer
+        .ban(
+            ip(PHONE),
+            BanReason::new("MANUAL", "API").expires_at(u64::MAX),
+        )
+        .unwrap();
+
+    let records = enforcer.ban_records().unwrap();
+    assert_eq!(records.len(), 2);
+    let scanner = records.iter().find(|r| r.ip == ip(SCANNER)).unwrap();
+    assert_eq!(scanner.reason.reason, "UA_NOT_ALLOWED");
+    assert_eq!(scanner.reason.origin, "engine");
+    assert_eq!(
+        scanner.reason.user_agent.as_deref(),
+        Some("friendly-scanner")
+    );
+    assert_eq!(scanner.reason.method.as_deref(), Some("REGISTER"));
+    assert_eq!(scanner.hits, 1);
+    assert_eq!(scanner.remaining_secs(0), None);
+    let manual = records.iter().find(|r| r.ip == ip(PHONE)).unwrap();
+    assert_eq!(manual.reason.reason, "MANUAL");
+    assert!(manual.remaining_secs(0).is_some());
+
+    // 解封后记录随之移除
+    enforcer.unban(ip(SCANNER), "MANUAL", "API").unwrap();
+    assert!(enforcer.bans().get(&ip(SCANNER)).is_none());
+}
+
+#[test]
+fn tenants_use_their_own_whitelist_and_tag_events() {
+    let mut h = Harness::new();
+    let tenants = Tenants::parse(
+        r#"
+        [[tenant]]
+        name = "acme"
+        destinations = ["192.0.2.1"]
+        whitelist = ["acme-pbx"]
+
+        [[tenant]]
+        name = "globex"
+        destinations = ["192.0.2.1:5080"]
+        whitelist = ["globex-phone"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        tenants
+            .lookup(ip("192.0.2.1"), 5080)
+            .map(|t| t.name.as_str()),
+        Some("globex")
+    );
+    assert!(tenants.lookup(ip("192.0.2.2"), 5060).is_none());
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .tenants(tenants)
+        .build();
+
+    // 全局白名单中的 UA 不在 acme 的白名单中，acme 自己的 UA 放行
+    h.register(PHONE, "MicroSIP/3.21.3", "t1");
+    h.register(SCANNER, "acme-pbx", "t2");
+    assert_eq!(h.firewall.blocked(), vec![ip(PHONE)]);
+
+    let record = h.pipeline.enforcer().bans().get(&ip(PHONE)).unwrap();
+    assert_eq!(record.reason.tenant.as_deref(), Some("acme"));
+    let events = h.events.lock().unwrap();
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|e| e.tenant.as_deref() == Some("acme")));
+}
+
+#[test]
+fn host_addresses_are_never_evaluated_or_banned() {
+    let firewall = MemoryFirewall::new();
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_host_addresses(Arc::new(HostAddresses::new([ip(PHONE)])));
+    let enforcer = Arc::new(enforcer);
+    let mut pipeline = Pipeline::builder(enforcer.clone()).build();
+
+    let outcome = pipeline.process(&udp_packet(
+        ip(PHONE),
+        sip_request("REGISTER", "friendly-scanner", "h1", 1),
+    ));
+    assert!(matches!(outcome, PacketOutcome::Ignored));
+    pipeline.process_signal(&ExternalSignal {
+        source_ip: ip(PHONE),
+        user_agent: String::new(),
+        reason: "AUTH_FAILURE".to_string(),
+        weight: 100.0,
+        origin: "asterisk".to_string(),
+    });
+    assert!(enforcer
+        .ban(ip(PHONE), BanReason::new("MANUAL", "API"))
+        .is_err());
+    assert!(firewall.blocked().is_empty());
+
+    // 其他来源照常处置
+    pipeline.process(&udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "h2", 1),
+    ));
+    assert_eq!(firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 回环地址总在本机地址中
+    let detected = HostAddresses::detect().unwrap();
+    assert!(detected.contains(&ip("127.0.0.1")));
+    assert!(!detected.refresh().unwrap());
+}
+
+#[test]
+fn reconciliation_reports_missing_foreign_and_exempt_bans() {
+    let path = std::env::temp_dir().join(format!("uablock-state-{}.json", std::process::id()));
+    let firewall = MemoryFirewall::new();
+    let pipeline_with_state = || {
+        let enforcer = Arc::new(Enforcer::new(
+            Some(Box::new(firewall.clone())),
+            None,
+            Arc::new(Stats::default()),
+            Arc::new(EventBus::new()),
+        ));
+        Pipeline::builder(enforcer)
+            .state_file(StateFile::new(&path))
+            .build()
+    };
+
+    let mut pipeline = pipeline_with_state();
+    pipeline.process(&udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "r1", 1),
+    ));
+    let mut reason = BanReason::new("MANUAL", "API");
+    reason.user_agent = Some("MicroSIP/3.21.3".to_string());
+    pipeline.enforcer().ban(ip(PHONE), reason).unwrap();
+    pipeline.save_state();
+    drop(pipeline);
+
+    // 停机期间规则被外部删除，又有人手工添加了一条规则
+    firewall.unblock_ip(&ip(SCANNER)).unwrap();
+    firewall.block_ip(&ip("192.0.2.50")).unwrap();
+
+    let pipeline = pipeline_with_state();
+    let report = pipeline.reconcile().unwrap();
+    assert_eq!(
+        report.missing.iter().map(|r| r.ip).collect::<Vec<_>>(),
+        vec![ip(SCANNER)]
+    );
+    assert_eq!(report.missing[0].reason.reason, "UA_NOT_ALLOWED");
+    assert_eq!(report.foreign, vec![ip("192.0.2.50")]);
+    assert_eq!(report.exempt.len(), 1);
+    assert_eq!(report.exempt[0].ip, ip(PHONE));
+
+    // 修复：补封缺失的规则，解封白名单 UA 的来源，外来规则保留
+    assert_eq!(report.repair(pipeline.enforcer()), 2);
+    let mut blocked = firewall.blocked();
+    blocked.sort();
+    assert_eq!(blocked, vec![ip("192.0.2.50"), ip(SCANNER)]);
+    let report = pipeline.reconcile().unwrap();
+    assert!(report.missing.is_empty() && report.exempt.is_empty());
+    assert_eq!(report.foreign, vec![ip("192.0.2.50")]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn ban_latency_is_measured_per_stage_and_alerts_on_p99() {
+    let bus = Arc::new(EventBus::new());
+    let alerts = Arc::new(Mutex::new(Vec::new()));
+    let sink = alerts.clone();
+    bus.subscribe(move |event| {
+        if event.kind == EventKind::Alert {
+            sink.lock().unwrap().push(event.reason.clone());
+        }
+        true
+    });
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        bus,
+    );
+    enforcer.set_latency_alert(LatencyAlert::new(Duration::from_millis(20)));
+    let mut pipeline = Pipeline::builder(Arc::new(enforcer)).build();
+
+    // 数据包在队列中等了 50 毫秒才被处理
+    for i in 0..10 {
+        let mut packet = udp_packet(
+            ip(&format!("203.0.113.{}", i + 10)),
+            sip_request("REGISTER", "friendly-scanner", &format!("l{}", i), 1),
+        );
+        packet.captured_at = Some(std::time::Instant::now() - Duration::from_millis(50));
+        pipeline.process(&packet);
+        assert_eq!(alerts.lock().unwrap().is_empty(), i < 9);
+    }
+    assert_eq!(*alerts.lock().unwrap(), vec![BAN_LATENCY_HIGH.to_string()]);
+
+    // 外部信号没有抓包时刻，只计入判定到生效一段
+    pipeline.process_signal(&ExternalSignal {
+        source_ip: ip(SCANNER),
+        user_agent: String::new(),
+        reason: "AUTH_FAILURE".to_string(),
+        weight: 100.0,
+        origin: "asterisk".to_string(),
+    });
+    let latency = pipeline.enforcer().stats().snapshot().ban_latency;
+    assert_eq!(latency.capture_to_block.count, 10);
+    assert_eq!(latency.capture_to_detect.count, 10);
+    assert_eq!(latency.detect_to_block.count, 11);
+    assert_eq!(latency.capture_to_block.quantile_ms(0.99), Some(100));
+    assert!(latency.capture_to_block.sum_ms >= 500.0);
+    assert!(latency.detect_to_block.quantile_ms(0.5) < Some(50));
+    // 持续超限不重复告警
+    assert_eq!(alerts.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn expired_allow_rules_are_ignored_and_reported() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let rules = RulesEngine::parse(
+        r#"
+        [[rule]]
+        name = "contractor-softphone"
+        user_agent = "^Zoiper"
+        action = "allow"
+        expires = "2999-01-01"
+
+        [[rule]]
+        name = "old-contractor"
+        user_agent = "^Linphone"
+        action = "allow"
+        expires = "2000-01-01"
+        "#,
+    )
+    .unwrap();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_rules(rules);
+    h.pipeline = Pipeline::builder(enforcer).policy(policy).build();
+
+    assert!(matches!(
+        h.register(PHONE, "Zoiper 5.6", "x1"),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    assert!(matches!(
+        h.register(SCANNER, "Linphone-weird", "x2"),
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. } if d.reason == "UA_NOT_ALLOWED"
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(
+        h.pipeline
+            .policy()
+            .expired_rules()
+            .iter()
+            .map(|r| (r.rule.as_str(), r.expires.to_string()))
+            .collect::<Vec<_>>(),
+        vec![("old-contractor", "2000-01-01".to_string())]
+    );
+
+    let invalid = RulesEngine::parse(
+        r#"
+        [[rule]]
+        name = "bad-date"
+        action = "allow"
+        expires = "01/07/2025"
+        "#,
+    );
+    assert!(invalid.is_err_and(|e| e.contains("expires")));
+}
+
+#[test]
+fn reason_codes_are_carried_through_events_and_ban_records() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let rules = RulesEngine::parse(
+        r#"
+        [[rule]]
+        name = "register-burst"
+        methods = ["REGISTER"]
+        user_agent = "^MicroSIP"
+        threshold = 2
+        action = "ban"
+
+        [[rule]]
+        name = "blocked-country"
+        user_agent = "^geo"
+        action = "ban"
+        code = "GEO_DENY"
+        "#,
+    )
+    .unwrap();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_rules(rules);
+    h.pipeline = Pipeline::builder(enforcer.clone()).policy(policy).build();
+
+    h.register(SCANNER, "friendly-scanner", "r1");
+    h.register("203.0.113.10", "MicroSIP/3.21.3", "r2");
+    h.register("203.0.113.10", "MicroSIP/3.21.3", "r3");
+    h.register("203.0.113.11", "geo-1", "r4");
+    enforcer
+        .ban(ip("203.0.113.12"), BanReason::new("MANUAL", "API"))
+        .unwrap();
+    enforcer.unban(ip(SCANNER), "MANUAL", "API").unwrap();
+
+    let codes: Vec<(EventKind, IpAddr, ReasonCode)> = h
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.kind != EventKind::Detection)
+        .map(|e| (e.kind, e.ip, e.code))
+        .collect();
+    assert_eq!(
+        codes,
+        vec![
+            (EventKind::Ban, ip(SCANNER), ReasonCode::UaNotAllowed),
+            (EventKind::Ban, ip("203.0.113.10"), ReasonCode::RateExceeded),
+            (EventKind::Ban, ip("203.0.113.11"), ReasonCode::GeoDeny),
+            (EventKind::Ban, ip("203.0.113.12"), ReasonCode::Manual),
+            (EventKind::Unban, ip(SCANNER), ReasonCode::Manual),
+        ]
+    );
+    let records = enforcer.bans().records();
+    assert_eq!(
+        records
+            .iter()
+            .map(|r| (r.reason.reason.as_str(), r.reason.code))
+            .collect::<Vec<_>>(),
+        vec![
+            ("RULE_MATCH", ReasonCode::RateExceeded),
+            ("RULE_MATCH", ReasonCode::GeoDeny),
+            ("MANUAL", ReasonCode::Manual),
+        ]
+    );
+    let json = serde_json::to_value(&records[1]).unwrap();
+    assert_eq!(json["code"], "GEO_DENY");
+
+    // 旧版本状态文件中的记录没有 code，恢复时按原因代码推断
+    let mut legacy = json.clone();
+    legacy.as_object_mut().unwrap().remove("code");
+    legacy["ip"] = "203.0.113.20".into();
+    legacy["reason"] = "MESSAGE_FLOOD".into();
+    let legacy = serde_json::from_value(legacy).unwrap();
+    enforcer.bans().restore(&[legacy]);
+    assert_eq!(
+        enforcer
+            .bans()
+            .get(&ip("203.0.113.20"))
+            .unwrap()
+            .reason
+            .code,
+        ReasonCode::RateExceeded
+    );
+    assert_eq!(ReasonCode::classify("GREYLIST_HOLD"), ReasonCode::Greylist);
+    assert_eq!(ReasonCode::classify("SOMETHING_NEW"), ReasonCode::Unknown);
+}
+
+#[test]
+fn enforcement_is_configured_per_triggering_method() {
+    let mut h = Harness::new();
+    let rules = RulesEngine::parse(
+        r#"
+        [policy.INVITE]
+        action = "alert"
+
+        [policy.REGISTER]
+        ban_duration = "30d"
+        "#,
+    )
+    .unwrap();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_rules(rules);
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(h.firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_method_policies(policy.method_policies());
+    let enforcer = Arc::new(enforcer);
+    h.pipeline = Pipeline::builder(enforcer.clone()).policy(policy).build();
+
+    // 未知 UA 的 INVITE 只告警
+    h.send(PHONE, sip_request("INVITE", "odd-gateway", "m1", 1));
+    assert!(h.firewall.blocked().is_empty());
+    assert_eq!(enforcer.stats().alerts.load(Ordering::Relaxed), 1);
+
+    // 未知 UA 的 REGISTER 封禁 30 天
+    h.register(SCANNER, "friendly-scanner", "m2");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    let expires_at = enforcer.bans().get(&ip(SCANNER)).unwrap().reason.expires_at;
+    let expected = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 30 * 86400;
+    assert!(expires_at.is_some_and(|t| t.abs_diff(expected) <= 2));
+
+    // 到期的检测封禁在定时任务中解除；手动封禁不受影响
+    enforcer
+        .ban(
+            ip("203.0.113.30"),
+            BanReason::new("UA_NOT_ALLOWED", "engine").expires_at(1),
+        )
+        .unwrap();
+    enforcer
+        .ban(
+            ip("203.0.113.31"),
+            BanReason::new("MANUAL", "API").expires_at(1),
+        )
+        .unwrap();
+    assert_eq!(enforcer.expire_bans(), vec![ip("203.0.113.30")]);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER), ip("203.0.113.31")]);
+
+    assert!(RulesEngine::parse("[policy.REGISTER]\nban_duration = \"forever\"").is_err());
+    assert!(RulesEngine::parse("[policy.REGISTER]\naction = \"tarpit\"").is_err());
+}
+
+#[test]
+fn registered_endpoints_are_exempt_from_ua_bans() {
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let registered = Arc::new(RegisteredEndpoints::new(Duration::from_secs(3600)));
+    let policy =
+        Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_registered(registered.clone());
+    h.pipeline = Pipeline::builder(enforcer).policy(policy).build();
+
+    let response = |status: &str, cseq: &str| {
+        format!(
+            "SIP/2.0 {}\r\nVia: SIP/2.0/UDP 198.51.100.7:5060;branch=z9hG4bK1\r\n\
+             Call-ID: r1\r\nCSeq: {}\r\nContent-Length: 0\r\n\r\n",
+            status, cseq
+        )
+    };
+    // 认证挑战和其他方法的 200 OK 不算注册成功
+    assert!(!registered.observe_response(
+        ip(PHONE),
+        response("401 Unauthorized", "1 REGISTER").as_bytes()
+    ));
+    assert!(!registered.observe_response(ip(PHONE), response("200 OK", "5 INVITE").as_bytes()));
+    assert!(registered.is_empty());
+    assert!(registered.observe_response(ip(PHONE), response("200 OK", "2 REGISTER").as_bytes()));
+    assert!(registered.is_registered(&ip(PHONE)));
+
+    // 已注册的话机发出的辅助请求即使 UA 不在白名单中也不封禁
+    assert!(matches!(
+        h.send(PHONE, sip_request("INVITE", "odd-helper/1.0", "r2", 1)),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    h.register(SCANNER, "odd-helper/1.0", "r3");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 有效期过后恢复按 UA 判定
+    let expired = RegisteredEndpoints::new(Duration::ZERO);
+    expired.record(ip(PHONE));
+    assert!(!expired.is_registered(&ip(PHONE)));
+}
+
+#[test]
+fn standby_learns_bans_and_programs_them_on_promotion() {
+    let firewall = MemoryFirewall::new();
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_role(HaRole::Standby);
+    let enforcer = Arc::new(enforcer);
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default())));
+    let mut pipeline = Pipeline::builder(enforcer.clone()).policy(policy).build();
+    assert!(enforcer.firewall().is_none());
+
+    // 备机检测和同步来的封禁只记入封禁表
+    pipeline.process(&udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "h1", 1),
+    ));
+    let peer = ip("192.0.2.44");
+    assert!(enforcer
+        .ban(peer, BanReason::new("MANUAL", "redis"))
+        .unwrap());
+    assert!(firewall.blocked().is_empty());
+    let mut listed = enforcer.list_bans().unwrap();
+    listed.sort();
+    assert_eq!(listed, vec![ip("192.0.2.44"), ip(SCANNER)]);
+
+    // 同步来的解封同样只修改封禁表
+    let removed = ip("192.0.2.45");
+    enforcer
+        .ban(removed, BanReason::new("MANUAL", "redis"))
+        .unwrap();
+    assert!(enforcer.unban(removed, "MANUAL", "redis").unwrap());
+    assert!(enforcer.bans().get(&removed).is_none());
+
+    // 升为主机后按封禁表下发规则，之后的检测直接封禁
+    assert_eq!(enforcer.promote().unwrap(), 2);
+    assert_eq!(enforcer.role(), HaRole::Active);
+    let mut blocked = firewall.blocked();
+    blocked.sort();
+    assert_eq!(blocked, vec![ip("192.0.2.44"), ip(SCANNER)]);
+    assert!(enforcer.promote().is_err());
+    pipeline.process(&udp_packet(
+        ip("192.0.2.46"),
+        sip_request("REGISTER", "sipvicious", "h2", 1),
+    ));
+    assert!(firewall.blocked().contains(&ip("192.0.2.46")));
+}
+
+#[test]
+fn rule_audit_raises_a_single_alert_for_missing_rules() {
+    let mut h = Harness::new();
+    h.register(SCANNER, "friendly-scanner", "a1");
+    h.register("192.0.2.50", "sipvicious", "a2");
+    let enforcer = h.pipeline.enforcer().clone();
+    let audit = RuleAudit::new(Duration::from_secs(300), 10);
+    assert!(audit.audit(&enforcer).unwrap().is_empty());
+
+    // 规则被外部删除，封禁记录还在
+    h.firewall.unblock_ip(&ip(SCANNER)).unwrap();
+    h.firewall.unblock_ip(&ip("192.0.2.50")).unwrap();
+    let mut missing = audit.audit(&enforcer).unwrap();
+    missing.sort();
+    assert_eq!(missing, vec![ip("192.0.2.50"), ip(SCANNER)]);
+    // 同样的缺失不重复告警
+    audit.audit(&enforcer).unwrap();
+    let alerts: Vec<_> = h
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.kind == EventKind::Alert)
+        .map(|e| (e.reason.clone(), e.code))
+        .collect();
+    assert_eq!(alerts, vec![(RULE_MISSING.to_string(), ReasonCode::System)]);
+
+    // 只核查最近的封禁
+    let narrow = RuleAudit::new(Duration::from_secs(300), 1);
+    assert_eq!(narrow.audit(&enforcer).unwrap().len(), 1);
+}
+
+#[test]
+fn authorization_queries_answer_from_ban_table_and_whitelist() {
+    let mut h = Harness::new();
+    h.register(SCANNER, "friendly-scanner", "z1");
+    let authz = Authz::new(
+        h.pipeline.enforcer().clone(),
+        Arc::new(Mutex::new(Whitelist::default())),
+    );
+
+    // 已封禁的来源无论 UA 都拒绝，原因取自封禁记录
+    assert_eq!(
+        authz.decide(ip(SCANNER), "MicroSIP/3.21.3"),
+        Decision::Deny {
+            reason: "UA_NOT_ALLOWED".to_string(),
+            code: ReasonCode::UaNotAllowed,
+        }
+    );
+    assert_eq!(authz.answer(b"198.51.100.7 MicroSIP/3.21.3\r\n"), "ALLOW");
+    assert_eq!(
+        authz.answer(b"198.51.100.7 sipvicious"),
+        "DENY UA_NOT_ALLOWED UA_NOT_ALLOWED"
+    );
+    assert!(authz
+        .answer(b"not-an-ip friendly-scanner")
+        .starts_with("ERROR"));
+    // 查询不触发检测和封禁
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 已注册终端放行
+    let registered = Arc::new(RegisteredEndpoints::new(Duration::from_secs(3600)));
+    registered.record(ip(PHONE));
+    let authz = authz.with_registered(registered);
+    assert_eq!(authz.decide(ip(PHONE), "odd-helper/1.0"), Decision::Allow);
+}
+
+#[test]
+fn related_detections_are_grouped_into_campaigns() {
+    let campaigns = Arc::new(Campaigns::new(Duration::from_secs(3600), Duration::ZERO));
+    let enforcer = Arc::new(Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    ));
+    let mut pipeline = Pipeline::builder(enforcer)
+        .policy(Policy::new(Arc::new(Mutex::new(Whitelist::default()))))
+        .campaigns(campaigns.clone())
+        .build();
+    let mut send = |source: &str, user_agent: &str, extension: u32| {
+        let payload = sip_request("REGISTER", user_agent, &format!("c-{}", extension), 1)
+            .replace("To: <sip:100@", &format!("To: <sip:{}@", extension));
+        pipeline.process(&udp_packet(ip(source), payload));
+    };
+    // 相邻 IP 和同一 UA 归入同一活动，无关的扫描器单独成为活动
+    send("203.0.113.9", "sipcli", 100);
+    send("203.0.113.10", "sipcli/1.8", 101);
+    send("198.18.0.5", "sipcli", 199);
+    send("192.0.2.77", "friendly-scanner", 100);
+
+    let all = campaigns.campaigns();
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[0].ips.len(), 3);
+    assert_eq!(all[0].target_range().as_deref(), Some("100-199"));
+    assert_eq!(all[1].ips.len(), 1);
+
+    // 报告只列出涉及多个 IP 的活动
+    let reported = campaigns.report_if_due().unwrap();
+    assert_eq!(reported.len(), 1);
+    let summary = reported[0].summary();
+    assert!(
+        summary.starts_with("活动 #1: 3 个 IP, UA sipcli/sipcli/1.8, 目标分机 100-199"),
+        "{}",
+        summary
+    );
+}
+
+#[test]
+fn selftest_datagrams_decode_and_drive_a_ban() {
+    let source = std::net::Ipv4Addr::new(198, 18, 0, 254);
+    let dest = std::net::Ipv4Addr::new(192, 0, 2, 1);
+    let payload = sip_request("REGISTER", "uablock-selftest", "selftest-1", 1);
+    let datagram = ipv4_datagram(source, dest, 5060, 5080, payload.as_bytes());
+    // IP 头校验和正确时，对整个头部求和的结果为 0xffff
+    let header_sum = datagram[..20]
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]])))
+        .sum::<u32>();
+    assert_eq!((header_sum & 0xffff) + (header_sum >> 16), 0xffff);
+
+    let packet = decode_packet(&datagram).unwrap();
+    assert_eq!(packet.source_ip, IpAddr::V4(source));
+    assert_eq!(packet.dest_ip, IpAddr::V4(dest));
+    assert_eq!(packet.dest_port, 5080);
+    assert_eq!(packet.payload, payload.as_bytes());
+
+    let mut harness = Harness::new();
+    harness.pipeline.process(&packet);
+    assert_eq!(harness.firewall.blocked(), vec![IpAddr::V4(source)]);
+}
+
+#[test]
+fn deny_list_mode_bans_only_matching_user_agents() {
+    let whitelist = Whitelist::deny(vec!["friendly-scanner".to_string(), "sipcli".to_string()]);
+    assert_eq!(whitelist.mode(), ListMode::Deny);
+    // 黑名单只按“UA 包含模式”匹配，很短的 UA 不会被任何模式命中
+    assert!(whitelist.is_allowed("a"));
+    assert!(whitelist.is_allowed("Yealink SIP-T46S"));
+    assert!(!whitelist.is_allowed("SIPCLI/1.0"));
+
+    let whitelist = Arc::new(Mutex::new(whitelist));
+    let mut h = Harness::new();
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .policy(Policy::new(whitelist.clone()))
+        .build();
+    let outcome = h.register(SCANNER, "friendly-scanner", "d1");
+    assert!(matches!(
+        outcome,
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. }
+            if d.reason == "UA_DENIED" && d.code == ReasonCode::UaNotAllowed
+    ));
+    assert!(matches!(
+        h.register(PHONE, "SomeUnlistedPhone/2.0", "d2"),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 授权查询给出同样的原因
+    let authz = Authz::new(h.pipeline.enforcer().clone(), whitelist);
+    assert_eq!(
+        authz.answer(b"192.0.2.50 sipcli"),
+        "DENY UA_DENIED UA_NOT_ALLOWED"
+    );
+    assert_eq!(authz.answer(b"192.0.2.50 Linphone"), "ALLOW");
+
+    // 配置文件中两种名单不能同时设置，黑名单不能为空
+    let config = Config::parse(r#"blacklist = ["sipvicious"]"#).unwrap();
+    assert_eq!(config.blacklist, Some(vec!["sipvicious".to_string()]));
+    assert!(Config::parse("whitelist = [\"a\"]\nblacklist = [\"b\"]").is_err());
+    assert!(Config::parse("blacklist = []").is_err());
+}
+
+#[test]
+fn state_tables_and_event_queues_stay_within_limits() {
+    // 来源状态表已满时淘汰最久未活动的来源
+    let sources = SourceTable::with_limit(16);
+    for i in 0..100 {
+        sources.update(ip(&format!("192.0.2.{}", i)), |state| state.requests += 1);
+    }
+    assert!(sources.len() <= 16);
+    assert_eq!(sources.evicted(), 100 - sources.len() as u64);
+
+    // 事件队列已满时丢弃新条目，接收端退出后发送端取消订阅
+    let bus = EventBus::new().with_queue_capacity(2);
+    let (tx, rx) = bus.queue();
+    for n in 0..5 {
+        assert!(tx.send(n));
+    }
+    assert_eq!((bus.queued(), bus.dropped()), (2, 3));
+    assert_eq!(rx.recv(), Some(0));
+    assert_eq!(bus.queued(), 1);
+    drop(rx);
+    assert!(!tx.send(5));
+    assert_eq!(bus.queued(), 0);
+
+    // 重传跟踪表已满后不再记录新事务，其重传按新事务处理；用量在定时任务中更新到统计
+    let enforcer = Arc::new(Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(bus),
+    ));
+    let mut pipeline = Pipeline::builder(enforcer)
+        .policy(Policy::new(Arc::new(Mutex::new(Whitelist::default()))))
+        .transaction_limit(2)
+        .build();
+    let mut send = |call_id: &str| {
+        pipeline.process(&udp_packet(
+            ip(PHONE),
+            sip_request("REGISTER", "MicroSIP/3.21.3", call_id, 1),
+        ))
+    };
+    send("l1");
+    assert!(matches!(send("l1"), PacketOutcome::Retransmission));
+    send("l2");
+    send("l3");
+    assert!(!matches!(send("l3"), PacketOutcome::Retransmission));
+
+    pipeline.tick();
+    let stats = pipeline.enforcer().stats().snapshot();
+    assert_eq!(stats.transaction_entries, 2);
+    assert_eq!(stats.transactions_untracked, 2);
+    assert_eq!(stats.events_dropped, 3);
+    assert_eq!(stats.event_queue_pending, 0);
+}
+
+#[test]
+fn low_confidence_bans_wait_for_review() {
+    use uablock_rust::pending::{PendingBans, ReviewAction, Submission};
+
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    let whitelist = Arc::new(Mutex::new(Whitelist::default()));
+    let rules = RulesEngine::parse(
+        r#"
+        [[rule]]
+        name = "sipvicious"
+        user_agent = "^sipvicious"
+        action = "ban"
+        "#,
+    )
+    .unwrap();
+    let review = Arc::new(PendingBans::new(
+        Duration::from_secs(3600),
+        vec!["SCANNER_SIGNATURE".to_string()],
+    ));
+    h.pipeline = Pipeline::builder(enforcer.clone())
+        .policy(Policy::new(whitelist.clone()).with_rules(rules))
+        .review(review.clone())
+        .build();
+
+    // UA 不在白名单中：进入审核队列，不封禁；扫描器特征照常立即封禁
+    h.register(SCANNER, "friendly-scanner", "r1");
+    h.register(SCANNER, "friendly-scanner", "r2");
+    h.register("203.0.113.10", "sipvicious-0.3", "r3");
+    assert_eq!(h.firewall.blocked(), vec![ip("203.0.113.10")]);
+    let pending = review.pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!((pending[0].ip, pending[0].hits), (ip(SCANNER), 2));
+    assert_eq!(pending[0].code, ReasonCode::UaNotAllowed);
+
+    // 驳回后该来源只告警
+    review
+        .review(ip(SCANNER), ReviewAction::Reject, &whitelist)
+        .unwrap();
+    h.register(SCANNER, "friendly-scanner", "r4");
+    assert!(review.is_empty());
+    assert!(h.event_kinds().contains(&(EventKind::Alert, ip(SCANNER))));
+    assert!(!h.firewall.is_blocked(&ip(SCANNER)));
+
+    // 加入白名单：该 UA 之后直接放行
+    h.register(PHONE, "Zoiper 5.6", "r5");
+    let pending = review
+        .review(ip(PHONE), ReviewAction::Whitelist, &whitelist)
+        .unwrap();
+    assert_eq!(pending.user_agent, "Zoiper 5.6");
+    assert!(matches!(
+        h.register(PHONE, "Zoiper 5.6", "r6"),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    assert!(review
+        .review(ip(PHONE), ReviewAction::Approve, &whitelist)
+        .is_err());
+
+    // 批准：按排队时的检测封禁
+    h.register("203.0.113.11", "friendly-scanner", "r7");
+    let pending = review
+        .review(ip("203.0.113.11"), ReviewAction::Approve, &whitelist)
+        .unwrap();
+    assert!(enforcer.handle_detection(pending.detection()));
+    assert!(h.firewall.is_blocked(&ip("203.0.113.11")));
+
+    // 审核期满未处理：定时任务自动封禁
+    let expiring = Arc::new(PendingBans::new(Duration::ZERO, Vec::new()));
+    h.pipeline = Pipeline::builder(enforcer)
+        .policy(Policy::new(whitelist.clone()))
+        .review(expiring.clone())
+        .build();
+    h.register("203.0.113.12", "friendly-scanner", "r8");
+    assert!(!h.firewall.is_blocked(&ip("203.0.113.12")));
+    h.pipeline.tick();
+    assert!(h.firewall.is_blocked(&ip("203.0.113.12")));
+    assert!(expiring.is_empty());
+    assert_eq!(
+        expiring.submit(&Detection::from_source(ip(SCANNER), "RULE_MATCH")),
+        Submission::Queued
+    );
+}
+
+#[test]
+fn trusted_sources_are_never_evaluated_or_blocked() {
+    use uablock_rust::local_net::TrustedSources;
+
+    let firewall = MemoryFirewall::new();
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_trusted_sources(TrustedSources::new(vec![
+        Subnet::parse("198.51.100.0/24").unwrap(),
+        Subnet::parse("2001:db8::1").unwrap(),
+    ]));
+    let enforcer = Arc::new(enforcer);
+    let whitelist = Arc::new(Mutex::new(Whitelist::default()));
+    let mut pipeline = Pipeline::builder(enforcer.clone())
+        .policy(Policy::new(whitelist.clone()))
+        .build();
+
+    // 中继的 UA 不在白名单中也不判定
+    let outcome = pipeline.process(&udp_packet(
+        ip(PHONE),
+        sip_request("REGISTER", "friendly-scanner", "t1", 1),
+    ));
+    assert!(matches!(outcome, PacketOutcome::Ignored));
+    pipeline.process(&udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "t2", 1),
+    ));
+    assert_eq!(firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(enforcer.stats().snapshot().trusted_packets, 1);
+
+    // 管理接口、外部信号和授权查询同样不会封禁或拒绝可信来源
+    let error = enforcer
+        .ban(ip("2001:db8::1"), BanReason::new("MANUAL", "API"))
+        .unwrap_err();
+    assert!(error.contains("可信来源"));
+    assert!(!enforcer.handle_detection(&Detection::from_source(ip(PHONE), "AUTH_FAILURE")));
+    assert_eq!(firewall.blocked(), vec![ip(SCANNER)]);
+    let authz = Authz::new(enforcer.clone(), whitelist);
+    assert_eq!(authz.decide(ip(PHONE), "friendly-scanner"), Decision::Allow);
+}
+
+#[test]
+fn whitelist_file_is_reloaded_in_place() {
+    use uablock_rust::whitelist_file::{parse_patterns, WhitelistFile};
+
+    assert_eq!(
+        parse_patterns("# 话机\nYealink  # T4 系列\n\n  exact:Zoiper \n"),
+        vec!["Yealink", "exact:Zoiper"]
+    );
+
+    let path = std::env::temp_dir().join(format!("uablock-whitelist-{}.txt", std::process::id()));
+    std::fs::write(&path, "microsip\n").unwrap();
+    let whitelist = Arc::new(Mutex::new(Whitelist::new(vec!["microsip".to_string()])));
+    let file = WhitelistFile::new(&path, whitelist.clone());
+    let mut pipeline = Pipeline::builder(Arc::new(Enforcer::new(
+        Some(Box::new(MemoryFirewall::new())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    )))
+    .policy(Policy::new(whitelist.clone()))
+    .build();
+    let mut register = |user_agent: &str, call_id: &str| {
+        pipeline.process(&udp_packet(
+            ip(PHONE),
+            sip_request("REGISTER", user_agent, call_id, 1),
+        ))
+    };
+    assert!(matches!(
+        register("Yealink SIP-T46S", "w1"),
+        PacketOutcome::Request {
+            verdict: Verdict::Detect(_),
+            ..
+        }
+    ));
+    assert_eq!(file.reload_if_modified(), Ok(false));
+
+    // 修改后重新读取，同一个名单对象立即生效
+    std::fs::write(&path, "microsip\nyealink\n").unwrap();
+    assert_eq!(file.reload(), Ok(true));
+    assert_eq!(file.reload(), Ok(false));
+    assert!(matches!(
+        register("Yealink SIP-T46S", "w2"),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+
+    // 新内容无效时保留原来的名单
+    std::fs::write(&path, "# 全部注释掉\n").unwrap();
+    assert!(file.reload().is_err());
+    assert_eq!(
+        whitelist.lock().unwrap().get_patterns(),
+        &["microsip".to_string(), "yealink".to_string()]
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(file.reload_if_modified().is_err());
+}
+
+#[test]
+fn detection_bans_expire_after_the_configured_ttl() {
+    let mut h = Harness::new();
+    let rules = RulesEngine::parse("[policy.INVITE]\nban_duration = \"30d\"").unwrap();
+    let policy = Policy::new(Arc::new(Mutex::new(Whitelist::default()))).with_rules(rules);
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(h.firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_method_policies(policy.method_policies());
+    enforcer.set_ban_ttl(Duration::from_secs(3600));
+    let enforcer = Arc::new(enforcer);
+    h.pipeline = Pipeline::builder(enforcer.clone()).policy(policy).build();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // 未设置方法时长的检测使用默认封禁时长，方法设置的时长优先
+    h.register(SCANNER, "friendly-scanner", "m1");
+    let expires_at = enforcer.bans().get(&ip(SCANNER)).unwrap().reason.expires_at;
+    assert!(expires_at.is_some_and(|t| t.abs_diff(now + 3600) <= 2));
+    h.send(
+        "203.0.113.40",
+        sip_request("INVITE", "friendly-scanner", "m2", 1),
+    );
+    let expires_at = enforcer
+        .bans()
+        .get(&ip("203.0.113.40"))
+        .unwrap()
+        .reason
+        .expires_at;
+    assert!(expires_at.is_some_and(|t| t.abs_diff(now + 30 * 86400) <= 2));
+
+    // 后台线程解封到期的检测封禁，手动封禁不受影响
+    enforcer
+        .ban(
+            ip("203.0.113.41"),
+            BanReason::new("UA_NOT_ALLOWED", "engine").expires_at(1),
+        )
+        .unwrap();
+    enforcer
+        .ban(ip("203.0.113.42"), BanReason::new("MANUAL", "API"))
+        .unwrap();
+    enforcer.start_expiry(Duration::from_millis(10)).unwrap();
+    for _ in 0..100 {
+        if !h.firewall.blocked().contains(&ip("203.0.113.41")) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+        h.firewall.blocked(),
+        vec![ip(SCANNER), ip("203.0.113.40"), ip("203.0.113.42")]
+    );
+    assert!(enforcer.bans().get(&ip("203.0.113.41")).is_none());
+    assert_eq!(enforcer.stats().unbans.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn repeat_offenders_get_escalating_bans() {
+    use uablock_rust::offences::{parse_steps, Escalation};
+
+    let state_path =
+        std::env::temp_dir().join(format!("uablock-offences-{}.json", std::process::id()));
+    let mut h = Harness::new();
+    let mut enforcer = Enforcer::new(
+        Some(Box::new(h.firewall.clone())),
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    enforcer.set_ban_ttl(Duration::from_secs(60));
+    enforcer.set_escalation(Escalation::new(
+        parse_steps("10m,1h,permanent").unwrap(),
+        Duration::from_secs(86400),
+    ));
+    let enforcer = Arc::new(enforcer);
+    h.pipeline = Pipeline::builder(enforcer.clone())
+        .state_file(StateFile::new(&state_path))
+        .build();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let expires_at = || enforcer.bans().get(&ip(SCANNER)).unwrap().reason.expires_at;
+
+    // 第一次违规封禁 10 分钟（优先于默认封禁时长），第二次 1 小时，之后永久
+    h.register(SCANNER, "friendly-scanner", "m1");
+    assert!(expires_at().is_some_and(|t| t.abs_diff(now + 600) <= 2));
+    enforcer.unban(ip(SCANNER), "EXPIRED", "expiry").unwrap();
+    h.register(SCANNER, "friendly-scanner", "m2");
+    assert!(expires_at().is_some_and(|t| t.abs_diff(now + 3600) <= 2));
+    enforcer.unban(ip(SCANNER), "EXPIRED", "expiry").unwrap();
+    h.register(SCANNER, "friendly-scanner", "m3");
+    assert_eq!(expires_at(), None);
+
+    // 重复检测已封禁的来源不计违规
+    h.register(SCANNER, "friendly-scanner", "m4");
+    let escalation = enforcer.escalation().unwrap();
+    assert_eq!(escalation.count(&ip(SCANNER)), 3);
+    assert_eq!(escalation.count(&ip(PHONE)), 0);
+
+    // 违规次数随状态文件跨重启保留
+    h.pipeline.save_state();
+    let mut restarted = Enforcer::new(
+        None,
+        None,
+        Arc::new(Stats::default()),
+        Arc::new(EventBus::new()),
+    );
+    restarted.set_escalation(Escalation::new(
+        parse_steps("10m,1h,permanent").unwrap(),
+        Duration::from_secs(86400),
+    ));
+    let restarted = Arc::new(restarted);
+    let _pipeline = Pipeline::builder(restarted.clone())
+        .state_file(StateFile::new(&state_path))
+        .build();
+    assert_eq!(restarted.escalation().unwrap().count(&ip(SCANNER)), 3);
+    std::fs::remove_file(&state_path).unwrap();
+
+    assert!(parse_steps("10m,forever").is_err());
+    assert!(parse_steps("0s").is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn ban_records_are_kept_in_sqlite() {
+    use uablock_rust::sqlite_store::SqliteBanStore;
+
+    let path = std::env::temp_dir().join(format!("uablock-bans-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut h = Harness::new();
+    let enforcer = h.pipeline.enforcer().clone();
+    enforcer
+        .bans()
+        .set_store(Arc::new(SqliteBanStore::open(&path).unwrap()))
+        .unwrap();
+
+    // 封禁和解封立即写入数据库
+    h.register(SCANNER, "friendly-scanner", "m1");
+    enforcer
+        .ban(
+            ip("203.0.113.50"),
+            BanReason::new("MANUAL", "API").expires_at(4_000_000_000),
+        )
+        .unwrap();
+    enforcer.unban(ip("203.0.113.50"), "MANUAL", "API").unwrap();
+    enforcer
+        .ban(
+            ip("203.0.113.51"),
+            BanReason::new("MANUAL", "API").expires_at(4_000_000_000),
+        )
+        .unwrap();
+    let store = SqliteBanStore::open(&path).unwrap();
+    let records = store.load().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records, enforcer.bans().records());
+    let scanner = store.get(&ip(SCANNER)).unwrap().unwrap();
+    assert_eq!(scanner.reason.reason, "UA_NOT_ALLOWED");
+    assert_eq!(scanner.reason.code, ReasonCode::UaNotAllowed);
+    assert_eq!(
+        scanner.reason.user_agent.as_deref(),
+        Some("friendly-scanner")
+    );
+    assert_eq!(
+        store
+            .get(&ip("203.0.113.51"))
+            .unwrap()
+            .unwrap()
+            .reason
+            .expires_at,
+        Some(4_000_000_000)
+    );
+
+    // 命中次数在定时同步时写入
+    enforcer.bans().hit(&ip(SCANNER));
+    enforcer.bans().sync_store();
+    assert_eq!(store.get(&ip(SCANNER)).unwrap().unwrap().hits, 1);
+
+    // 重启后从数据库恢复的记录与之前一致
+    let restarted = Harness::new();
+    restarted
+        .pipeline
+        .enforcer()
+        .bans()
+        .restore(&store.load().unwrap());
+    assert_eq!(
+        restarted.pipeline.enforcer().bans().records(),
+        enforcer.bans().records()
+    );
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn startup_reconciliation_expires_stale_rules_and_adopts_existing_ones() {
+    let path = std::env::temp_dir().join(format!("uablock-startup-{}.json", std::process::id()));
+    let firewall = MemoryFirewall::new();
+    let pipeline_with_state = |state_file: bool| {
+        let enforcer = Arc::new(Enforcer::new(
+            Some(Box::new(firewall.clone())),
+            None,
+            Arc::new(Stats::default()),
+            Arc::new(EventBus::new()),
+        ));
+        let builder = Pipeline::builder(enforcer);
+        if state_file {
+            builder.state_file(StateFile::new(&path)).build()
+        } else {
+            builder.build()
+        }
+    };
+
+    // 上次运行的 webhook 限时封禁在停机期间到期，另有一条手工添加的规则
+    let pipeline = pipeline_with_state(true);
+    pipeline
+        .enforcer()
+        .ban(
+            ip("203.0.113.60"),
+            BanReason::new("WEBHOOK", "webhook").expires_at(1),
+        )
+        .unwrap();
+    pipeline
+        .enforcer()
+        .ban(ip(SCANNER), BanReason::new("MANUAL", "API"))
+        .unwrap();
+    pipeline.save_state();
+    drop(pipeline);
+    firewall.block_ip(&ip("192.0.2.60")).unwrap();
+
+    let pipeline = pipeline_with_state(true);
+    let enforcer = pipeline.enforcer();
+    let report = pipeline.reconcile().unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(
+        report.expired.iter().map(|r| r.ip).collect::<Vec<_>>(),
+        vec![ip("203.0.113.60")]
+    );
+    assert!(report.missing.is_empty() && report.exempt.is_empty());
+    assert_eq!(report.foreign, vec![ip("192.0.2.60")]);
+
+    // 到期的规则被删除，外来规则记入封禁表但不改动
+    assert_eq!(report.remove_expired(enforcer), 1);
+    assert_eq!(report.adopt_foreign(enforcer), 1);
+    let mut blocked = firewall.blocked();
+    blocked.sort();
+    assert_eq!(blocked, vec![ip("192.0.2.60"), ip(SCANNER)]);
+    let adopted = enforcer.bans().get(&ip("192.0.2.60")).unwrap();
+    assert_eq!(adopted.reason.reason, "UNKNOWN");
+    assert_eq!(adopted.banned_at, None);
+    assert!(pipeline.reconcile().unwrap().is_clean());
+    std::fs::remove_file(&path).unwrap();
+
+    // 没有状态文件时外来规则不报告，直接记入封禁表
+    let pipeline = pipeline_with_state(false);
+    let report = pipeline.reconcile().unwrap();
+    assert!(report.is_clean());
+    assert_eq!(pipeline.enforcer().bans().records().len(), 2);
+}
+
+#[test]
+fn ipv6_packets_are_decoded_past_extension_headers() {
+    let source: std::net::Ipv6Addr = "2001:db8::bad".parse().unwrap();
+    let dest: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let payload = sip_request("REGISTER", "friendly-scanner", "v6-1", 1);
+    // 以太网头（类型 0x86DD）+ IPv6 固定头 + 一个 8 字节的扩展头（下一个头为 UDP）+ UDP 头
+    let frame = |next_header: u8, extension: [u8; 8]| {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x86, 0xDD, 0x60, 0, 0, 0, 0, 0, next_header, 64]);
+        frame.extend_from_slice(&source.octets());
+        frame.extend_from_slice(&dest.octets());
+        frame.extend_from_slice(&extension);
+        frame.extend_from_slice(&40000u16.to_be_bytes());
+        frame.extend_from_slice(&5060u16.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    };
+    // 逐跳选项（0）：长度字段为 0 表示 8 字节
+    let packet = decode_packet(&frame(0, [17, 0, 1, 4, 0, 0, 0, 0])).unwrap();
+    assert_eq!(packet.source_ip, IpAddr::V6(source));
+    assert_eq!(packet.dest_ip, IpAddr::V6(dest));
+    assert_eq!((packet.source_port, packet.dest_port), (40000, 5060));
+    assert_eq!(packet.payload, payload.as_bytes());
+
+    // 分片（44）：不分片的分片头（偏移和 M 标志都为 0）照常解析；首个分片（M 置位）只有残缺的
+    // SIP 消息，之后的分片（偏移不为 0）没有 UDP 头，都跳过
+    assert!(decode_packet(&frame(44, [17, 0, 0, 0, 0, 0, 0, 1])).is_some());
+    assert!(decode_packet(&frame(44, [17, 0, 0, 1, 0, 0, 0, 1])).is_none());
+    assert!(decode_packet(&frame(44, [17, 0, 0, 0xb8, 0, 0, 0, 1])).is_none());
+    // ESP（50）之后的内容是加密的
+    assert!(decode_packet(&frame(50, [17, 0, 0, 0, 0, 0, 0, 0])).is_none());
+
+    let mut harness = Harness::new();
+    harness.pipeline.process(&packet);
+    assert_eq!(harness.firewall.blocked(), vec![IpAddr::V6(source)]);
+}
+
+#[test]
+fn greylist_holds_go_through_the_enforcement_queue() {
+    let stats = Arc::new(Stats::default());
+    let queue = EnforcementQueue::new(16, stats.clone());
+    let hold = Action::Hold(Detection::from_source(ip(SCANNER), "GREYLIST_HOLD"));
+    let ban = Action::Ban(Detection::from_source(ip(SCANNER), "UA_NOT_ALLOWED"));
+
+    assert!(queue.push(hold.clone()));
+    assert!(!queue.push(hold));
+    // 检测触发的封禁取代排队中的临时丢弃
+    assert!(queue.push(ban));
+    // 到期解除只撤销临时丢弃，排队中的封禁不受影响
+    assert!(!queue.push(Action::Release(ip(SCANNER))));
+    assert!(queue.push(Action::Hold(Detection::from_source(
+        ip(PHONE),
+        "GREYLIST_HOLD"
+    ))));
+    assert!(queue.push(Action::Release(ip(PHONE))));
+    assert!(queue.push(Action::Release(ip("203.0.113.10"))));
+    let order: Vec<_> = std::iter::from_fn(|| queue.pop(Duration::ZERO)).collect();
+    assert_eq!(order.len(), 3);
+    assert!(matches!(order[0], Action::Release(released) if released == ip(PHONE)));
+    assert!(matches!(order[1], Action::Release(released) if released == ip("203.0.113.10")));
+    assert!(matches!(&order[2], Action::Ban(detection) if detection.source_ip == ip(SCANNER)));
+
+    // 执行线程完成临时丢弃和到期解除
+    let h = Harness::new();
+    let enforcer = h.pipeline.enforcer();
+    enforcer.start_queue(16).unwrap();
+    enforcer.hold(Detection::from_source(ip(SCANNER), "GREYLIST_HOLD"));
+    // 封禁表在规则添加之后、解除之后更新
+    let wait = |held: bool| {
+        for _ in 0..100 {
+            if enforcer.bans().get(&ip(SCANNER)).is_some() == held {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+    wait(true);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    enforcer.release(ip(SCANNER));
+    wait(false);
+    assert!(h.firewall.blocked().is_empty());
+}
+
+#[test]
+fn parse_pool_keeps_per_source_order_and_the_pipeline_uses_its_result() {
+    use tokio::sync::mpsc;
+    use uablock_rust::parse_pool::ParsePool;
+    use uablock_rust::sip_parser::PacketClass;
+
+    let pool = ParsePool::new(4);
+    assert_eq!(pool.worker_for(&ip(SCANNER)), pool.worker_for(&ip(SCANNER)));
+    let (output, mut parsed) = mpsc::channel(256);
+    let input = pool.start(output).unwrap();
+    let sources = [SCANNER, PHONE, "192.0.2.44"];
+    for seq in 0..20 {
+        for source in sources {
+            let call_id = format!("{}-{}", source, seq);
+            let payload = sip_request("REGISTER", "friendly-scanner", &call_id, 1);
+            input
+                .blocking_send(udp_packet(ip(source), payload))
+                .unwrap();
+        }
+    }
+    drop(input);
+
+    let mut received: Vec<CapturedPacket> = Vec::new();
+    while let Some(packet) = parsed.blocking_recv() {
+        received.push(packet);
+    }
+    assert_eq!(received.len(), 60);
+    for source in sources {
+        let call_ids: Vec<_> = received
+            .iter()
+            .filter(|packet| packet.source_ip == ip(source))
+            .map(|packet| {
+                let parsed = packet.parsed.as_ref().expect("解析结果随数据包传递");
+                assert!(matches!(parsed.class, PacketClass::Sip));
+                parsed.request.as_ref().unwrap().call_id.clone().unwrap()
+            })
+            .collect();
+        let expected: Vec<_> = (0..20).map(|seq| format!("{}-{}", source, seq)).collect();
+        assert_eq!(call_ids, expected);
+    }
+
+    // 预先解析的数据包与检测任务中解析的判定结果相同
+    let mut h = Harness::new();
+    let packet = received
+        .into_iter()
+        .find(|packet| packet.source_ip == ip(SCANNER))
+        .unwrap();
+    assert!(matches!(
+        h.pipeline.process(&packet),
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. } if d.reason == "UA_NOT_ALLOWED"
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn captures_from_several_interfaces_into_one_pipeline() {
+    use uablock_rust::packet_capture::parse_interfaces;
+
+    assert_eq!(
+        parse_interfaces(" eth0, eth1,,eth0").unwrap(),
+        vec!["eth0".to_string(), "eth1".to_string()]
+    );
+    assert!(parse_interfaces(" , ").is_err());
+
+    // 每个接口一个读取线程，数据包汇入同一个队列
+    let mut eth0 = MemorySource::new();
+    eth0.push_payload(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "e0", 1),
+    );
+    let mut eth1 = MemorySource::new();
+    eth1.push_payload(
+        ip(PHONE),
+        sip_request("REGISTER", "MicroSIP/3.21.3", "e1", 1),
+    );
+    let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+    let stats = Arc::new(Stats::default());
+    for (interface, source) in [("eth0", eth0), ("eth1", eth1)] {
+        spawn_interface_reader(interface, source, tx.clone(), stats.clone(), None).unwrap();
+    }
+    drop(tx);
+
+    let mut h = Harness::new();
+    let mut sources = Vec::new();
+    for _ in 0..2 {
+        let packet = rx.blocking_recv().unwrap();
+        sources.push(packet.source_ip);
+        h.pipeline.process(&packet);
+    }
+    sources.sort();
+    assert_eq!(sources, vec![ip(PHONE), ip(SCANNER)]);
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+
+    // 读取线程以接口命名；来源报告线程名后返回错误，接收端关闭后线程退出
+    struct ThreadName(std::sync::mpsc::Sender<Option<String>>);
+    impl PacketSource for ThreadName {
+        fn next_packet(&mut self) -> Result<Option<CapturedPacket>, String> {
+            let _ = self
+                .0
+                .send(std::thread::current().name().map(str::to_string));
+            Err("EOF".to_string())
+        }
+    }
+    let (name_tx, name_rx) = std::sync::mpsc::channel();
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    spawn_interface_reader("eth1", ThreadName(name_tx), tx, stats, None).unwrap();
+    let name = name_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    drop(rx);
+    assert_eq!(name.as_deref(), Some("cap-eth1"));
+}
+
+#[test]
+fn restore_skips_records_with_out_of_range_durations() {
+    let scanner: IpAddr = SCANNER.parse().unwrap();
+    let phone: IpAddr = PHONE.parse().unwrap();
+
+    // 损坏或手工编辑的快照中过大的时长只跳过该记录，不会让恢复 panic
+    let mut strikes = StrikeTracker::new(5.0, Duration::from_secs(300));
+    strikes.restore(
+        &[
+            StrikeRecord {
+                ip: scanner,
+                score: 3.0,
+                age_secs: 1e20,
+            },
+            StrikeRecord {
+                ip: phone,
+                score: 3.0,
+                age_secs: 0.0,
+            },
+        ],
+        0.0,
+    );
+    assert_eq!(strikes.score(&scanner), 0.0);
+    assert!(strikes.score(&phone) > 2.9);
+
+    let mut limiter =
+        UaRateLimiter::new(Duration::from_secs(60), 100, 10, Duration::from_secs(600));
+    limiter.restore(
+        &[UaWindowRecord {
+            user_agent: "sipvicious".to_string(),
+            requests: 50,
+            sources: vec![scanner],
+            age_secs: 1e20,
+        }],
+        &[
+            UaDenyRecord {
+                user_agent: "friendly-scanner".to_string(),
+                remaining_secs: 1e20,
+            },
+            UaDenyRecord {
+                user_agent: "sipcli".to_string(),
+                remaining_secs: 300.0,
+            },
+        ],
+        0.0,
+    );
+    let (windows, denied) = limiter.snapshot();
+    assert!(windows.is_empty());
+    assert!(!limiter.is_denied("friendly-scanner"));
+    assert!(limiter.is_denied("sipcli"));
+    assert_eq!(denied.len(), 1);
+}
+
+#[test]
+fn restore_skips_greylist_and_rule_records_with_out_of_range_durations() {
+    let scanner: IpAddr = SCANNER.parse().unwrap();
+    let phone: IpAddr = PHONE.parse().unwrap();
+
+    let mut greylist = Greylist::new(
+        Duration::from_secs(30),
+        Duration::from_secs(600),
+        30,
+        Duration::from_secs(86400),
+    );
+    greylist.restore(
+        &[
+            GreylistRecord {
+                ip: scanner,
+                user_agent: "sipvicious".to_string(),
+                allowed: true,
+                age_secs: 1e20,
+                hold_remaining_secs: 0.0,
+                requests: 0,
+            },
+            GreylistRecord {
+                ip: scanner,
+                user_agent: "friendly-scanner".to_string(),
+                allowed: false,
+                age_secs: 10.0,
+                hold_remaining_secs: 1e20,
+                requests: 1,
+            },
+            GreylistRecord {
+                ip: phone,
+                user_agent: "MicroSIP/3.21.3".to_string(),
+                allowed: true,
+                age_secs: 10.0,
+                hold_remaining_secs: 0.0,
+                requests: 0,
+            },
+        ],
+        &[
+            ReleaseRecord {
+                ip: scanner,
+                remaining_secs: 1e20,
+            },
+            ReleaseRecord {
+                ip: phone,
+                remaining_secs: 5.0,
+            },
+        ],
+        0.0,
+    );
+    let (pairs, releases) = greylist.snapshot();
+    assert_eq!(pairs.len(), 1);
+    assert_eq!(pairs[0].ip, phone);
+    assert_eq!(releases.len(), 1);
+    assert_eq!(releases[0].ip, phone);
+
+    let mut rules = RulesEngine::parse(
+        r#"
+        [[rule]]
+        name = "register-burst"
+        methods = ["REGISTER"]
+        threshold = 5
+        action = "ban"
+        "#,
+    )
+    .unwrap();
+    rules.restore(
+        &[
+            RuleCounterRecord {
+                rule: "register-burst".to_string(),
+                ip: scanner,
+                count: 4,
+                age_secs: 1e20,
+            },
+            RuleCounterRecord {
+                rule: "register-burst".to_string(),
+                ip: phone,
+                count: 2,
+                age_secs: 1.0,
+            },
+        ],
+        0.0,
+    );
+    let counters = rules.snapshot();
+    assert_eq!(counters.len(), 1);
+    assert_eq!((counters[0].ip, counters[0].count), (phone, 2));
+}
+
+#[test]
+fn counts_traffic_and_bans_per_destination_port() {
+    let mut h = Harness::new();
+    let mut packet = udp_packet(
+        ip(SCANNER),
+        sip_request("REGISTER", "friendly-scanner", "p1", 1),
+    );
+    packet.dest_port = 5080;
+    h.pipeline.process(&packet);
+    h.register(PHONE, "MicroSIP/3.21.3", "p2");
+
+    let ports = h.pipeline.enforcer().stats().ports();
+    assert_eq!(ports[&5080].sip_requests, 1);
+    assert_eq!(ports[&5080].detections, 1);
+    assert_eq!(ports[&5080].bans, 1);
+    assert_eq!(ports[&5060].packets, 1);
+    assert_eq!(ports[&5060].bans, 0);
+
+    let events = h.events.lock().unwrap();
+    assert!(events.iter().all(|e| e.dest_port == Some(5080)));
+}
+
+#[test]
+fn spoofed_whitelisted_user_agent_needs_secondary_verification() {
+    let mut h = Harness::new();
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .verifier(Verifier::new(Duration::from_secs(3600), 2).with_auth())
+        .build();
+
+    // 已封禁的扫描器冒充白名单 UA 不能解封自己，请求过多时以 UA_UNVERIFIED 封禁
+    h.register(SCANNER, "friendly-scanner", "v1");
+    assert!(matches!(
+        h.register(SCANNER, "FreeSWITCH", "v2"),
+        PacketOutcome::Request {
+            verdict: Verdict::Unverified,
+            ..
+        }
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    h.register(PHONE, "MicroSIP/3.21.3", "v3");
+    h.register(PHONE, "MicroSIP/3.21.3", "v4");
+    assert!(matches!(
+        h.register(PHONE, "MicroSIP/3.21.3", "v5"),
+        PacketOutcome::Request { verdict: Verdict::Detect(ref d), .. } if d.reason == "UA_UNVERIFIED"
+    ));
+
+    // PBX 报告认证成功后按正常白名单处理
+    h.pipeline.process_signal(&ExternalSignal {
+        source_ip: ip(PHONE),
+        user_agent: String::new(),
+        reason: AUTH_SUCCESS.to_string(),
+        weight: 0.0,
+        origin: "asterisk".to_string(),
+    });
+    assert!(matches!(
+        h.register(PHONE, "MicroSIP/3.21.3", "v6"),
+        PacketOutcome::Request {
+            verdict: Verdict::Allow,
+            ..
+        }
+    ));
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+}
+
+#[test]
+fn lan_devices_are_alerted_instead_of_banned() {
+    let mut h = Harness::new();
+    let lan = Subnet::parse("198.51.100.0/24").unwrap();
+    assert!(lan.contains(&ip(PHONE)));
+    assert!(!lan.contains(&ip(SCANNER)));
+    assert!(!lan.contains(&ip("2001:db8::1")));
+    h.pipeline = Pipeline::builder(h.pipeline.enforcer().clone())
+        .local_networks(LocalNetworks::new(vec![lan], Vec::new()))
+        .build();
+
+    // 局域网内 UA 配置错误的设备只告警
+    h.register(PHONE, "misconfigured-ata", "l1");
+    h.register(SCANNER, "friendly-scanner", "l2");
+    assert_eq!(h.firewall.blocked(), vec![ip(SCANNER)]);
+    assert_eq!(h.stat(|s| &s.alerts), 1);
+    assert_eq!(h.stat(|s| &s.detections), 2);
+    assert_eq!(
+        h.event_kinds(),
+        vec![
+            (EventKind::Alert, ip(PHONE)),
+            (EventKind::Detection, ip(SCANNER)),
+            (EventKind::Ban, ip(SCANNER)),
+        ]
+    );
+}
+
+#[test]
+fn ban_check_alerts_when_banned_sources_still_get_replies() {
+    let mut h = Harness::new();
+    let check = Arc::new(BanCheck::new(Duration::from_secs(60), 2));
+    let enforcer = h.pipeline.enforcer().clone();
+    check.subscribe(enforcer.events());
+
+    // 封禁后开始验证，解封后停止
+    h.register(SCANNER, "friendly-scanner", "b1");
+    enforcer.unban(ip(SCANNER), "MANUAL", "test").unwrap();
+    assert!(check.is_empty());
+    assert_eq!(check.observe(&ip(SCANNER)), None);
+
+    h.register(SCANNER, "friendly-scanner", "b2");
+    assert_eq!(check.observe(&ip(PHONE)), None);
+    assert_eq!(check.observe(&ip(SCANNER)), None);
+    let responses = check.observe(&ip(SCANNER)).unwrap();
+    assert_eq!(responses, 2);
+    assert!(check.is_empty());
+
+    check.report(ip(SCANNER), responses, enforcer.stats(), enforcer.events());
+    assert_eq!(h.stat(|s| &s.ineffective_bans), 1);
+    let last = h.events.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(last.kind, EventKind::Alert);
+    assert_eq!(last.reason, BAN_INEFFECTIVE);
+}